@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- Per-type author thresholds (minimum account age / follower count)
+CREATE TABLE notification_thresholds (
+    user_id UUID NOT NULL REFERENCES user_devices(id) ON DELETE CASCADE,
+    notification_type TEXT NOT NULL,
+    min_account_age_days INTEGER,
+    min_followers INTEGER,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, notification_type)
+);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_user_devices_deleted_at;
+ALTER TABLE user_devices DROP COLUMN IF EXISTS deleted_reason;
+ALTER TABLE user_devices DROP COLUMN IF EXISTS deleted_at;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+-- Soft-delete support for device registrations
+ALTER TABLE user_devices ADD COLUMN deleted_at TIMESTAMPTZ;
+ALTER TABLE user_devices ADD COLUMN deleted_reason TEXT;
+
+CREATE INDEX idx_user_devices_deleted_at ON user_devices(deleted_at) WHERE deleted_at IS NOT NULL;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- A/B experiments for notification copy. Exposure is controlled by the feature
+-- flag of the same name; variants are picked by weight within the exposed users.
+CREATE TABLE copy_experiments (
+    name TEXT PRIMARY KEY,
+    notification_type TEXT NOT NULL,
+    variants JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_copy_experiments_type ON copy_experiments(notification_type);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS notification_daily_stats;
+DROP TABLE IF EXISTS notification_opens;
@@ -0,0 +1,21 @@
+-- Add up migration script here
+-- Open events reported by the app, keyed by the notification id sent as apns-id
+CREATE TABLE notification_opens (
+    notification_id UUID PRIMARY KEY,
+    user_did TEXT NOT NULL,
+    notification_type TEXT NOT NULL,
+    experiment TEXT,
+    variant TEXT,
+    opened_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_notification_opens_type_opened_at ON notification_opens(notification_type, opened_at);
+
+-- Daily delivered/opened counters per type, used to compute open rates
+CREATE TABLE notification_daily_stats (
+    day DATE NOT NULL,
+    notification_type TEXT NOT NULL,
+    delivered BIGINT NOT NULL DEFAULT 0,
+    opened BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (day, notification_type)
+);
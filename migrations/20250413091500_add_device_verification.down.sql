@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_user_devices_unverified;
+ALTER TABLE user_devices DROP COLUMN IF EXISTS verification_expires_at;
+ALTER TABLE user_devices DROP COLUMN IF EXISTS verification_nonce;
+ALTER TABLE user_devices DROP COLUMN IF EXISTS verified_at;
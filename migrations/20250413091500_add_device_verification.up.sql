@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- Proof-of-possession for device tokens. Existing rows are treated as verified;
+-- new registrations are parked unverified when verification is enabled.
+ALTER TABLE user_devices ADD COLUMN verified_at TIMESTAMPTZ DEFAULT NOW();
+ALTER TABLE user_devices ADD COLUMN verification_nonce TEXT;
+ALTER TABLE user_devices ADD COLUMN verification_expires_at TIMESTAMPTZ;
+
+CREATE INDEX idx_user_devices_unverified ON user_devices(verification_expires_at) WHERE verified_at IS NULL;
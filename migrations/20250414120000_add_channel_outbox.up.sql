@@ -0,0 +1,10 @@
+-- Add up migration script here
+-- Items spilled from full pipeline channels, drained back in once there is room
+CREATE TABLE channel_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    channel TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_channel_outbox_channel_id ON channel_outbox(channel, id);
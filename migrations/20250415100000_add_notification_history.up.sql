@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- One row per notification delivered to a device
+CREATE TABLE notification_history (
+    id BIGSERIAL PRIMARY KEY,
+    notification_id UUID NOT NULL UNIQUE,
+    user_did TEXT NOT NULL,
+    device_token TEXT NOT NULL,
+    notification_type TEXT NOT NULL,
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    data JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_notification_history_user_did_created_at ON notification_history(user_did, created_at);
@@ -0,0 +1,53 @@
+-- Add down migration script here
+DROP POLICY IF EXISTS tenant_isolation ON notification_opens;
+ALTER TABLE notification_opens NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE notification_opens DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON notification_history;
+ALTER TABLE notification_history NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE notification_history DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON relationship_audit_log;
+ALTER TABLE relationship_audit_log NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE relationship_audit_log DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON user_blocks_encrypted;
+ALTER TABLE user_blocks_encrypted NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE user_blocks_encrypted DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON user_mutes_encrypted;
+ALTER TABLE user_mutes_encrypted NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE user_mutes_encrypted DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON user_blocks_hashed;
+ALTER TABLE user_blocks_hashed NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE user_blocks_hashed DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON user_mutes_hashed;
+ALTER TABLE user_mutes_hashed NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE user_mutes_hashed DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON user_blocks;
+ALTER TABLE user_blocks NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE user_blocks DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON user_mutes;
+ALTER TABLE user_mutes NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE user_mutes DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON notification_thresholds;
+ALTER TABLE notification_thresholds NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE notification_thresholds DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON notification_preferences;
+ALTER TABLE notification_preferences NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE notification_preferences DISABLE ROW LEVEL SECURITY;
+
+DROP POLICY IF EXISTS tenant_isolation ON user_devices;
+ALTER TABLE user_devices NO FORCE ROW LEVEL SECURITY;
+ALTER TABLE user_devices DISABLE ROW LEVEL SECURITY;
+
+DROP INDEX IF EXISTS idx_user_devices_tenant_id;
+ALTER TABLE user_devices DROP COLUMN IF EXISTS tenant_id;
+DROP FUNCTION IF EXISTS current_tenant_id();
+DROP TABLE IF EXISTS tenants;
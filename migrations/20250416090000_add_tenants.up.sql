@@ -0,0 +1,84 @@
+-- Add up migration script here
+-- Multi-tenant mode: each app served by this deployment is a tenant. Only user_devices
+-- carries the tenant id; every other per-user table is scoped through it by RLS.
+CREATE TABLE tenants (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    api_key_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Existing data belongs to the implicit default tenant, which has no API key
+INSERT INTO tenants (id, name, api_key_hash) VALUES ('default', 'Default', '');
+
+-- API requests set app.tenant_id for their transaction. Background workers leave it
+-- unset and see every tenant. Note that superusers and BYPASSRLS roles skip RLS.
+CREATE FUNCTION current_tenant_id() RETURNS TEXT AS $$
+    SELECT NULLIF(current_setting('app.tenant_id', true), '')
+$$ LANGUAGE SQL STABLE;
+
+ALTER TABLE user_devices
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+CREATE INDEX idx_user_devices_tenant_id ON user_devices(tenant_id);
+
+ALTER TABLE user_devices ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_devices FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_devices
+    USING (current_tenant_id() IS NULL OR tenant_id = current_tenant_id());
+
+-- Tables keyed by device id
+ALTER TABLE notification_preferences ENABLE ROW LEVEL SECURITY;
+ALTER TABLE notification_preferences FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON notification_preferences
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+ALTER TABLE notification_thresholds ENABLE ROW LEVEL SECURITY;
+ALTER TABLE notification_thresholds FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON notification_thresholds
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+-- Tables keyed by DID
+ALTER TABLE user_mutes ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_mutes FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_mutes
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+ALTER TABLE user_blocks ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_blocks FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_blocks
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+ALTER TABLE user_mutes_hashed ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_mutes_hashed FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_mutes_hashed
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+ALTER TABLE user_blocks_hashed ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_blocks_hashed FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_blocks_hashed
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+ALTER TABLE user_mutes_encrypted ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_mutes_encrypted FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_mutes_encrypted
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+ALTER TABLE user_blocks_encrypted ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_blocks_encrypted FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_blocks_encrypted
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+ALTER TABLE relationship_audit_log ENABLE ROW LEVEL SECURITY;
+ALTER TABLE relationship_audit_log FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON relationship_audit_log
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+ALTER TABLE notification_history ENABLE ROW LEVEL SECURITY;
+ALTER TABLE notification_history FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON notification_history
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+ALTER TABLE notification_opens ENABLE ROW LEVEL SECURITY;
+ALTER TABLE notification_opens FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON notification_opens
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS pending_digests;
+DROP TABLE IF EXISTS tenant_usage;
+ALTER TABLE tenants DROP COLUMN IF EXISTS daily_quota;
@@ -0,0 +1,22 @@
+-- Add up migration script here
+-- NULL means unlimited
+ALTER TABLE tenants ADD COLUMN daily_quota BIGINT CHECK (daily_quota >= 0);
+
+-- Notifications sent (and deferred to digests) per tenant per day
+CREATE TABLE tenant_usage (
+    tenant_id TEXT NOT NULL REFERENCES tenants(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    sent BIGINT NOT NULL DEFAULT 0,
+    digested BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (tenant_id, day)
+);
+
+-- Notifications held back while a tenant is over quota, rolled up per device and type
+CREATE TABLE pending_digests (
+    device_token TEXT NOT NULL,
+    user_did TEXT NOT NULL,
+    notification_type TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (device_token, notification_type)
+);
@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE notification_preferences
+    ADD COLUMN priority_from_mutuals BOOLEAN NOT NULL DEFAULT FALSE;
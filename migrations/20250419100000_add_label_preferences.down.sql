@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS label_preferences;
+ALTER TABLE post_cache DROP COLUMN IF EXISTS labels;
@@ -0,0 +1,15 @@
+-- Add up migration script here
+ALTER TABLE post_cache ADD COLUMN labels TEXT[] NOT NULL DEFAULT '{}';
+
+-- How a user wants notifications about labeled content handled
+CREATE TABLE label_preferences (
+    user_id UUID NOT NULL REFERENCES user_devices(id) ON DELETE CASCADE,
+    label TEXT NOT NULL,
+    visibility TEXT NOT NULL CHECK (visibility IN ('show', 'mask', 'hide')),
+    PRIMARY KEY (user_id, label)
+);
+
+ALTER TABLE label_preferences ENABLE ROW LEVEL SECURITY;
+ALTER TABLE label_preferences FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON label_preferences
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
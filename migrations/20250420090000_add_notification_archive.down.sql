@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_notification_history_unarchived;
+ALTER TABLE notification_history DROP COLUMN IF EXISTS archived_at;
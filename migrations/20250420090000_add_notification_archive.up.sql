@@ -0,0 +1,6 @@
+-- Add up migration script here
+-- Set once a row has been uploaded to the archive; only archived rows are pruned
+ALTER TABLE notification_history ADD COLUMN archived_at TIMESTAMPTZ;
+
+CREATE INDEX idx_notification_history_unarchived ON notification_history(created_at)
+    WHERE archived_at IS NULL;
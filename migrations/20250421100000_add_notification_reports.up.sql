@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- One moderation report per notification; the notification id is the idempotency key
+CREATE TABLE notification_reports (
+    notification_id UUID PRIMARY KEY,
+    user_did TEXT NOT NULL,
+    reason_type TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
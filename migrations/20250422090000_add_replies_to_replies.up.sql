@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- `replies` now covers direct replies to the user's posts only
+ALTER TABLE notification_preferences
+    ADD COLUMN replies_to_replies BOOLEAN NOT NULL DEFAULT TRUE;
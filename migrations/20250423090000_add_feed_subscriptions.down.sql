@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS pending_feed_digests;
+DROP TABLE IF EXISTS feed_poll_state;
+DROP TABLE IF EXISTS feed_subscriptions;
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS feed_posts;
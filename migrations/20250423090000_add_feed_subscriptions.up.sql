@@ -0,0 +1,41 @@
+-- Add up migration script here
+ALTER TABLE notification_preferences ADD COLUMN feed_posts BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Custom feeds a device wants to hear about; digest subscribers get a periodic rollup
+CREATE TABLE feed_subscriptions (
+    user_id UUID NOT NULL REFERENCES user_devices(id) ON DELETE CASCADE,
+    feed_uri TEXT NOT NULL,
+    digest BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, feed_uri)
+);
+
+CREATE INDEX idx_feed_subscriptions_feed_uri ON feed_subscriptions(feed_uri);
+
+-- Poll state shared by every subscriber of a feed
+CREATE TABLE feed_poll_state (
+    feed_uri TEXT PRIMARY KEY,
+    service_did TEXT NOT NULL,
+    display_name TEXT NOT NULL,
+    seen_uris TEXT[] NOT NULL DEFAULT '{}',
+    polled_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- New feed posts held back for digest subscribers
+CREATE TABLE pending_feed_digests (
+    user_id UUID NOT NULL REFERENCES user_devices(id) ON DELETE CASCADE,
+    feed_uri TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, feed_uri)
+);
+
+ALTER TABLE feed_subscriptions ENABLE ROW LEVEL SECURITY;
+ALTER TABLE feed_subscriptions FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON feed_subscriptions
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+ALTER TABLE pending_feed_digests ENABLE ROW LEVEL SECURITY;
+ALTER TABLE pending_feed_digests FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON pending_feed_digests
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- Devices whose pushes have failed continuously; they only receive spaced probes
+CREATE TABLE device_quarantine (
+    device_token TEXT PRIMARY KEY,
+    probes INTEGER NOT NULL DEFAULT 0,
+    next_probe_at TIMESTAMPTZ NOT NULL,
+    quarantined_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
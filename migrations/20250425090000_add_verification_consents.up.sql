@@ -0,0 +1,18 @@
+-- Add up migration script here
+-- Users who opted in to having their delivered notifications checked against the
+-- AppView. The app password is encrypted with the server secret.
+CREATE TABLE verification_consents (
+    user_did TEXT PRIMARY KEY,
+    app_password_encrypted BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_verified_at TIMESTAMPTZ,
+    last_matched INTEGER,
+    last_missed INTEGER,
+    last_extra INTEGER,
+    last_mismatched INTEGER
+);
+
+ALTER TABLE verification_consents ENABLE ROW LEVEL SECURITY;
+ALTER TABLE verification_consents FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON verification_consents
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- Each account's app.bsky.notification.declaration: who may subscribe to its activity
+-- (all, followers, mutuals, none). No row means the lexicon default, followers.
+CREATE TABLE activity_declarations (
+    did TEXT PRIMARY KEY,
+    allow_subscriptions TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_notification_history_device_token_id;
+ALTER TABLE notification_history DROP COLUMN IF EXISTS status;
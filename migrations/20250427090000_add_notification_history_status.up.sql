@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- Failed sends are kept too, so the in-app notification center matches what was attempted
+ALTER TABLE notification_history ADD COLUMN status TEXT NOT NULL DEFAULT 'delivered';
+
+CREATE INDEX idx_notification_history_device_token_id ON notification_history(device_token, id);
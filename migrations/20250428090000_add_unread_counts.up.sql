@@ -0,0 +1,14 @@
+-- Add up migration script here
+-- Unread notifications per device, sent along in each payload so the app can badge
+-- its tabs. Mentions covers what the app's Mentions tab shows (mentions, replies, quotes).
+CREATE TABLE unread_counts (
+    user_id UUID PRIMARY KEY REFERENCES user_devices(id) ON DELETE CASCADE,
+    mentions INTEGER NOT NULL DEFAULT 0,
+    total INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE unread_counts ENABLE ROW LEVEL SECURITY;
+ALTER TABLE unread_counts FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON unread_counts
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
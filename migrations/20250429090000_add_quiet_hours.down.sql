@@ -0,0 +1,8 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS quiet_hours_pending;
+DROP FUNCTION IF EXISTS in_quiet_hours(TIME, TIME, TEXT);
+ALTER TABLE notification_preferences
+    DROP COLUMN IF EXISTS quiet_hours_start,
+    DROP COLUMN IF EXISTS quiet_hours_end,
+    DROP COLUMN IF EXISTS quiet_hours_timezone,
+    DROP COLUMN IF EXISTS quiet_hours_mode;
@@ -0,0 +1,31 @@
+-- Add up migration script here
+-- Per-device quiet hours in the device's own timezone. NULL start/end means off.
+ALTER TABLE notification_preferences
+    ADD COLUMN quiet_hours_start TIME,
+    ADD COLUMN quiet_hours_end TIME,
+    ADD COLUMN quiet_hours_timezone TEXT NOT NULL DEFAULT 'UTC',
+    ADD COLUMN quiet_hours_mode TEXT NOT NULL DEFAULT 'queue' CHECK (quiet_hours_mode IN ('suppress', 'queue'));
+
+-- Whether it is currently inside a quiet-hours window; windows may cross midnight
+CREATE FUNCTION in_quiet_hours(start_time TIME, end_time TIME, tz TEXT) RETURNS BOOLEAN AS $$
+    SELECT start_time IS NOT NULL AND end_time IS NOT NULL AND CASE
+        WHEN start_time <= end_time THEN local_time >= start_time AND local_time < end_time
+        ELSE local_time >= start_time OR local_time < end_time
+    END
+    FROM (SELECT (NOW() AT TIME ZONE tz)::TIME AS local_time) now_local
+$$ LANGUAGE SQL STABLE;
+
+-- Notifications held back during a device's quiet hours, rolled up per type and
+-- summarized once the window closes
+CREATE TABLE quiet_hours_pending (
+    user_id UUID NOT NULL REFERENCES user_devices(id) ON DELETE CASCADE,
+    notification_type TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, notification_type)
+);
+
+ALTER TABLE quiet_hours_pending ENABLE ROW LEVEL SECURITY;
+ALTER TABLE quiet_hours_pending FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON quiet_hours_pending
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
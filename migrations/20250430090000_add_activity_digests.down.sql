@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS activity_digest_pending;
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS digest_low_priority;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+-- Opt-in: likes and reposts arrive as a periodic rollup instead of one push each
+ALTER TABLE notification_preferences ADD COLUMN digest_low_priority BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Likes and reposts waiting for the next activity digest, rolled up per device and type
+CREATE TABLE activity_digest_pending (
+    user_id UUID NOT NULL REFERENCES user_devices(id) ON DELETE CASCADE,
+    notification_type TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, notification_type)
+);
+
+ALTER TABLE activity_digest_pending ENABLE ROW LEVEL SECURITY;
+ALTER TABLE activity_digest_pending FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON activity_digest_pending
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
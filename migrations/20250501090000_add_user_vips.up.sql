@@ -0,0 +1,14 @@
+-- Add up migration script here
+-- Accounts whose notifications always get through: they skip quiet hours, digests and
+-- load shedding, and arrive time-sensitive
+CREATE TABLE user_vips (
+    user_did TEXT NOT NULL,
+    vip_did TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_did, vip_did)
+);
+
+ALTER TABLE user_vips ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_vips FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_vips
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
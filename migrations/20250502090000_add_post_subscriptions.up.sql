@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- Authors a user wants a push for whenever they post
+CREATE TABLE post_subscriptions (
+    user_did TEXT NOT NULL,
+    subject_did TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_did, subject_did)
+);
+
+CREATE INDEX idx_post_subscriptions_subject ON post_subscriptions(subject_did);
+
+ALTER TABLE post_subscriptions ENABLE ROW LEVEL SECURITY;
+ALTER TABLE post_subscriptions FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON post_subscriptions
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS dm_consents;
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS dm_redact_body;
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS dms;
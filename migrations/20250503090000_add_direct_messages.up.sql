@@ -0,0 +1,18 @@
+-- Add up migration script here
+ALTER TABLE notification_preferences ADD COLUMN dms BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE notification_preferences ADD COLUMN dm_redact_body BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Users who connected their chat account so their direct messages can be pushed. The
+-- app password needs direct message access and is encrypted with the server secret.
+-- log_cursor is the position in the user's chat log already notified.
+CREATE TABLE dm_consents (
+    user_did TEXT PRIMARY KEY,
+    app_password_encrypted BYTEA NOT NULL,
+    log_cursor TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE dm_consents ENABLE ROW LEVEL SECURITY;
+ALTER TABLE dm_consents FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON dm_consents
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Opt-in: a push when a post that already mentioned the user is edited
+ALTER TABLE notification_preferences ADD COLUMN post_edits BOOLEAN NOT NULL DEFAULT FALSE;
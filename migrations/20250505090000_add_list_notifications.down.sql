@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS starter_packs;
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS list_additions;
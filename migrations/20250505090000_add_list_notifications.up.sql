@@ -0,0 +1,14 @@
+-- Add up migration script here
+-- Pushes when someone adds the user to a list or starter pack
+ALTER TABLE notification_preferences ADD COLUMN list_additions BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- app.bsky.graph.starterpack records seen on the firehose, so additions to a pack's
+-- list can link to the pack
+CREATE TABLE starter_packs (
+    uri TEXT PRIMARY KEY,
+    list_uri TEXT NOT NULL,
+    name TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_starter_packs_list_uri ON starter_packs(list_uri);
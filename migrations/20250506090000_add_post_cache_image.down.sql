@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE post_cache DROP COLUMN IF EXISTS image_url;
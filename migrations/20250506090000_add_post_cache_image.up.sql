@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Thumbnail of the post's first image, attached to rich notifications
+ALTER TABLE post_cache ADD COLUMN image_url TEXT;
@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- Windows of firehose events the consumer never processed because its cursor could
+-- not be resumed; reconciled against the AppView afterwards
+CREATE TABLE firehose_gaps (
+    id BIGSERIAL PRIMARY KEY,
+    reason TEXT NOT NULL,
+    cursor TEXT NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    ended_at TIMESTAMPTZ NOT NULL,
+    reconciled_at TIMESTAMPTZ,
+    -- Notifications the AppView has for verification users that we never sent
+    missed INTEGER
+);
+
+CREATE INDEX idx_firehose_gaps_pending ON firehose_gaps(id) WHERE reconciled_at IS NULL;
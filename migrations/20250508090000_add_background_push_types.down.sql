@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS background_types;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Notification types delivered as silent background pushes instead of alerts
+ALTER TABLE notification_preferences ADD COLUMN background_types TEXT[] NOT NULL DEFAULT '{}';
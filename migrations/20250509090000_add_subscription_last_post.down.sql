@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS comeback_posts;
+ALTER TABLE post_subscriptions DROP COLUMN IF EXISTS last_post_at;
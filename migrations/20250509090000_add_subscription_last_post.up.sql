@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- When the subject last posted, for "back after a break" notifications
+ALTER TABLE post_subscriptions ADD COLUMN last_post_at TIMESTAMPTZ;
+
+ALTER TABLE notification_preferences ADD COLUMN comeback_posts BOOLEAN NOT NULL DEFAULT FALSE;
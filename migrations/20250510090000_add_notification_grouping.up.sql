@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- How the app on this device wants its notifications grouped: app, thread, author or none
+ALTER TABLE notification_preferences ADD COLUMN grouping TEXT NOT NULL DEFAULT 'app';
@@ -0,0 +1,23 @@
+-- Add up migration script here
+-- What APNs answered for every send, for debugging pushes that never arrived
+CREATE TABLE delivery_log (
+    id BIGSERIAL PRIMARY KEY,
+    notification_id UUID,
+    user_did TEXT NOT NULL,
+    device_token TEXT NOT NULL,
+    notification_type TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    -- NULL when no response came back
+    status_code INTEGER,
+    apns_id TEXT,
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_delivery_log_user_did_created_at ON delivery_log(user_did, created_at);
+CREATE INDEX idx_delivery_log_created_at ON delivery_log(created_at);
+
+ALTER TABLE delivery_log ENABLE ROW LEVEL SECURITY;
+ALTER TABLE delivery_log FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON delivery_log
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
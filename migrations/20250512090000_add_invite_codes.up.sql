@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- Codes that admit new accounts when REGISTRATION_MODE=invite
+CREATE TABLE invite_codes (
+    code TEXT PRIMARY KEY,
+    -- NULL means unlimited
+    max_uses INTEGER,
+    uses INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
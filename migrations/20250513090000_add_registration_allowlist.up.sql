@@ -0,0 +1,7 @@
+-- Add up migration script here
+-- Accounts allowed to register when REGISTRATION_MODE=allowlist
+CREATE TABLE registration_allowlist (
+    did TEXT PRIMARY KEY,
+    note TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
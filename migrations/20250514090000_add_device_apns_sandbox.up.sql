@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Set for tokens from development builds, which only the APNs sandbox accepts
+ALTER TABLE user_devices ADD COLUMN apns_sandbox BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE notification_preferences ADD COLUMN custom_notifications BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Highest custom data format the app on this device understands, see payload_keys.rs
+ALTER TABLE notification_preferences ADD COLUMN payload_version SMALLINT NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS preset_version;
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS preset;
+DROP TABLE IF EXISTS preference_presets;
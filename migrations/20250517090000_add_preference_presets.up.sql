@@ -0,0 +1,24 @@
+-- Add up migration script here
+-- Named preference bundles offered at onboarding, see presets.rs
+CREATE TABLE preference_presets (
+    name TEXT PRIMARY KEY,
+    label TEXT NOT NULL,
+    description TEXT,
+    -- Value for notification types the preset doesn't list
+    fallback BOOLEAN NOT NULL,
+    toggles JSONB NOT NULL DEFAULT '{}',
+    version INTEGER NOT NULL DEFAULT 1,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO preference_presets (name, label, description, fallback, toggles) VALUES
+    ('everything', 'Everything', 'Every kind of notification', TRUE, '{}'),
+    ('essentials', 'Essentials', 'Mentions, replies, quotes, new followers and messages', FALSE,
+        '{"mentions": true, "replies": true, "replies_to_replies": true, "quotes": true, "follows": true, "dms": true, "custom_notifications": true}'),
+    ('mentions_only', 'Mentions only', 'Only posts that mention you', FALSE, '{"mentions": true}');
+
+-- The preset a device's toggles came from, until they're changed another way
+ALTER TABLE notification_preferences
+    ADD COLUMN preset TEXT REFERENCES preference_presets(name) ON DELETE SET NULL,
+    ADD COLUMN preset_version INTEGER;
+CREATE INDEX idx_notification_preferences_preset ON notification_preferences(preset) WHERE preset IS NOT NULL;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE notification_preferences ADD COLUMN reply_context BOOLEAN NOT NULL DEFAULT FALSE;
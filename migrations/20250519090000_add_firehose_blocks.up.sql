@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- Blocks registered users made on their accounts, as seen on the firehose. A block's
+-- deletion only names its record key, so the key is kept to find the blocked account.
+CREATE TABLE firehose_blocks (
+    user_did TEXT NOT NULL,
+    rkey TEXT NOT NULL,
+    blocked_did TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_did, rkey)
+);
+
+ALTER TABLE firehose_blocks ENABLE ROW LEVEL SECURITY;
+ALTER TABLE firehose_blocks FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON firehose_blocks
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
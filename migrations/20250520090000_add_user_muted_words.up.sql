@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- Words and phrases a user muted; posts matching one don't notify them. targets holds
+-- "content" (post text, alt text and link cards, and tags) and/or "tag" (tags only).
+CREATE TABLE user_muted_words (
+    user_did TEXT NOT NULL,
+    value TEXT NOT NULL,
+    targets TEXT[] NOT NULL,
+    expires_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_did, value)
+);
+
+ALTER TABLE user_muted_words ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_muted_words FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_muted_words
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
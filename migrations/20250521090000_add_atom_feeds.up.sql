@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- Opt-in Atom feeds of a user's notification history, read from a secret URL. Only a
+-- hash of the URL's token is kept; rotating the token replaces it.
+CREATE TABLE atom_feeds (
+    user_did TEXT PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    -- Notification types included, every type when NULL
+    notification_types TEXT[],
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    rotated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+ALTER TABLE atom_feeds ENABLE ROW LEVEL SECURITY;
+ALTER TABLE atom_feeds FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON atom_feeds
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE notification_preferences DROP COLUMN IF EXISTS only_from_follows;
+DROP TABLE IF EXISTS user_follows;
@@ -0,0 +1,21 @@
+-- Add up migration script here
+-- Accounts registered users follow, for the "only people I follow" preference. Kept
+-- from the firehose, with the record key since a follow's deletion names only that,
+-- and imported from the user's repo when they turn the preference on.
+CREATE TABLE user_follows (
+    user_did TEXT NOT NULL,
+    rkey TEXT NOT NULL,
+    followed_did TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_did, rkey)
+);
+
+CREATE INDEX idx_user_follows_followed ON user_follows (user_did, followed_did);
+
+ALTER TABLE user_follows ENABLE ROW LEVEL SECURITY;
+ALTER TABLE user_follows FORCE ROW LEVEL SECURITY;
+CREATE POLICY tenant_isolation ON user_follows
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+-- Likes, reposts and replies only from accounts the user follows
+ALTER TABLE notification_preferences ADD COLUMN only_from_follows BOOLEAN NOT NULL DEFAULT FALSE;
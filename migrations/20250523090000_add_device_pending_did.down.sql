@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE user_devices DROP COLUMN IF EXISTS pending_did;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- A token registered by another DID stays with its current DID until the new one
+-- proves possession; the claimant waits here until then.
+ALTER TABLE user_devices ADD COLUMN pending_did TEXT;
@@ -0,0 +1,113 @@
+-- Add down migration script here
+DROP POLICY tenant_isolation ON user_devices;
+CREATE POLICY tenant_isolation ON user_devices
+    USING (current_tenant_id() IS NULL OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON notification_preferences;
+CREATE POLICY tenant_isolation ON notification_preferences
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON notification_thresholds;
+CREATE POLICY tenant_isolation ON notification_thresholds
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON label_preferences;
+CREATE POLICY tenant_isolation ON label_preferences
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON feed_subscriptions;
+CREATE POLICY tenant_isolation ON feed_subscriptions
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON pending_feed_digests;
+CREATE POLICY tenant_isolation ON pending_feed_digests
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON unread_counts;
+CREATE POLICY tenant_isolation ON unread_counts
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON quiet_hours_pending;
+CREATE POLICY tenant_isolation ON quiet_hours_pending
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON activity_digest_pending;
+CREATE POLICY tenant_isolation ON activity_digest_pending
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON user_mutes;
+CREATE POLICY tenant_isolation ON user_mutes
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_blocks;
+CREATE POLICY tenant_isolation ON user_blocks
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_mutes_hashed;
+CREATE POLICY tenant_isolation ON user_mutes_hashed
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_blocks_hashed;
+CREATE POLICY tenant_isolation ON user_blocks_hashed
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_mutes_encrypted;
+CREATE POLICY tenant_isolation ON user_mutes_encrypted
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_blocks_encrypted;
+CREATE POLICY tenant_isolation ON user_blocks_encrypted
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON relationship_audit_log;
+CREATE POLICY tenant_isolation ON relationship_audit_log
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON notification_history;
+CREATE POLICY tenant_isolation ON notification_history
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON notification_opens;
+CREATE POLICY tenant_isolation ON notification_opens
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON verification_consents;
+CREATE POLICY tenant_isolation ON verification_consents
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_vips;
+CREATE POLICY tenant_isolation ON user_vips
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON post_subscriptions;
+CREATE POLICY tenant_isolation ON post_subscriptions
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON dm_consents;
+CREATE POLICY tenant_isolation ON dm_consents
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON delivery_log;
+CREATE POLICY tenant_isolation ON delivery_log
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON firehose_blocks;
+CREATE POLICY tenant_isolation ON firehose_blocks
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_muted_words;
+CREATE POLICY tenant_isolation ON user_muted_words
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON atom_feeds;
+CREATE POLICY tenant_isolation ON atom_feeds
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_follows;
+CREATE POLICY tenant_isolation ON user_follows
+    USING (current_tenant_id() IS NULL OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+ALTER TABLE user_devices DROP CONSTRAINT user_devices_tenant_device_token_key;
+ALTER TABLE user_devices ADD CONSTRAINT user_devices_device_token_key UNIQUE (device_token);
+
+DROP FUNCTION IF EXISTS all_tenants();
@@ -0,0 +1,123 @@
+-- Add up migration script here
+-- Tenant isolation fails closed: a session that sets neither app.tenant_id nor
+-- app.all_tenants sees no rows. The service's own connections opt in to every tenant
+-- (the pipeline, sender, maintenance and admin API work across tenants), and tenant
+-- transactions opt back out.
+CREATE FUNCTION all_tenants() RETURNS BOOLEAN AS $$
+    SELECT COALESCE(current_setting('app.all_tenants', true), '') = 'on'
+$$ LANGUAGE SQL STABLE;
+
+DROP POLICY tenant_isolation ON user_devices;
+CREATE POLICY tenant_isolation ON user_devices
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+-- Tables keyed by device id
+DROP POLICY tenant_isolation ON notification_preferences;
+CREATE POLICY tenant_isolation ON notification_preferences
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON notification_thresholds;
+CREATE POLICY tenant_isolation ON notification_thresholds
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON label_preferences;
+CREATE POLICY tenant_isolation ON label_preferences
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON feed_subscriptions;
+CREATE POLICY tenant_isolation ON feed_subscriptions
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON pending_feed_digests;
+CREATE POLICY tenant_isolation ON pending_feed_digests
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON unread_counts;
+CREATE POLICY tenant_isolation ON unread_counts
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON quiet_hours_pending;
+CREATE POLICY tenant_isolation ON quiet_hours_pending
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+DROP POLICY tenant_isolation ON activity_digest_pending;
+CREATE POLICY tenant_isolation ON activity_digest_pending
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.id = user_id));
+
+-- Tables keyed by DID
+DROP POLICY tenant_isolation ON user_mutes;
+CREATE POLICY tenant_isolation ON user_mutes
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_blocks;
+CREATE POLICY tenant_isolation ON user_blocks
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_mutes_hashed;
+CREATE POLICY tenant_isolation ON user_mutes_hashed
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_blocks_hashed;
+CREATE POLICY tenant_isolation ON user_blocks_hashed
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_mutes_encrypted;
+CREATE POLICY tenant_isolation ON user_mutes_encrypted
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_blocks_encrypted;
+CREATE POLICY tenant_isolation ON user_blocks_encrypted
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON relationship_audit_log;
+CREATE POLICY tenant_isolation ON relationship_audit_log
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON notification_history;
+CREATE POLICY tenant_isolation ON notification_history
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON notification_opens;
+CREATE POLICY tenant_isolation ON notification_opens
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON verification_consents;
+CREATE POLICY tenant_isolation ON verification_consents
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_vips;
+CREATE POLICY tenant_isolation ON user_vips
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON post_subscriptions;
+CREATE POLICY tenant_isolation ON post_subscriptions
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON dm_consents;
+CREATE POLICY tenant_isolation ON dm_consents
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON delivery_log;
+CREATE POLICY tenant_isolation ON delivery_log
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON firehose_blocks;
+CREATE POLICY tenant_isolation ON firehose_blocks
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_muted_words;
+CREATE POLICY tenant_isolation ON user_muted_words
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON atom_feeds;
+CREATE POLICY tenant_isolation ON atom_feeds
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_follows;
+CREATE POLICY tenant_isolation ON user_follows
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+-- A token is unique within its tenant, so registering one can't collide with (or
+-- reveal) another tenant's device
+ALTER TABLE user_devices DROP CONSTRAINT user_devices_device_token_key;
+ALTER TABLE user_devices ADD CONSTRAINT user_devices_tenant_device_token_key UNIQUE (tenant_id, device_token);
@@ -0,0 +1,2 @@
+-- Add down migration script here
+-- Tokens stay normalized; the original spelling isn't kept
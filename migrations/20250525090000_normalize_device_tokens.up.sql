@@ -0,0 +1,13 @@
+-- Add up migration script here
+-- Store every device token in canonical form (hex only, lowercased), as registration
+-- does now. A token whose canonical form is already registered in its tenant is left
+-- for the admin duplicate merge, which picks the surviving row and its preferences.
+UPDATE user_devices d
+SET device_token = lower(regexp_replace(d.device_token, '[[:space:]<>]', '', 'g')), updated_at = NOW()
+WHERE d.device_token <> lower(regexp_replace(d.device_token, '[[:space:]<>]', '', 'g'))
+AND NOT EXISTS (
+    SELECT 1 FROM user_devices o
+    WHERE o.tenant_id = d.tenant_id AND o.id <> d.id
+    AND lower(regexp_replace(o.device_token, '[[:space:]<>]', '', 'g'))
+        = lower(regexp_replace(d.device_token, '[[:space:]<>]', '', 'g'))
+);
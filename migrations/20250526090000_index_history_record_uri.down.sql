@@ -0,0 +1,2 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_notification_history_record_uri;
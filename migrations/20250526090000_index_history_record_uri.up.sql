@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Retractions look up the notifications sent about a deleted record
+CREATE INDEX idx_notification_history_record_uri ON notification_history ((data->>'record_uri'), created_at);
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- Whether the app on this device wants the event's relay position in the payload
+ALTER TABLE notification_preferences ADD COLUMN payload_event_origin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,137 @@
+-- Add down migration script here
+DROP POLICY tenant_isolation ON notification_history;
+CREATE POLICY tenant_isolation ON notification_history
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON notification_opens;
+CREATE POLICY tenant_isolation ON notification_opens
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_mutes;
+CREATE POLICY tenant_isolation ON user_mutes
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_blocks;
+CREATE POLICY tenant_isolation ON user_blocks
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_mutes_hashed;
+CREATE POLICY tenant_isolation ON user_mutes_hashed
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_blocks_hashed;
+CREATE POLICY tenant_isolation ON user_blocks_hashed
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_mutes_encrypted;
+CREATE POLICY tenant_isolation ON user_mutes_encrypted
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_blocks_encrypted;
+CREATE POLICY tenant_isolation ON user_blocks_encrypted
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_vips;
+CREATE POLICY tenant_isolation ON user_vips
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON user_muted_words;
+CREATE POLICY tenant_isolation ON user_muted_words
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON atom_feeds;
+CREATE POLICY tenant_isolation ON atom_feeds
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON dm_consents;
+CREATE POLICY tenant_isolation ON dm_consents
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON verification_consents;
+CREATE POLICY tenant_isolation ON verification_consents
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON delivery_log;
+CREATE POLICY tenant_isolation ON delivery_log
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON post_subscriptions;
+CREATE POLICY tenant_isolation ON post_subscriptions
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP POLICY tenant_isolation ON relationship_audit_log;
+CREATE POLICY tenant_isolation ON relationship_audit_log
+    USING (all_tenants() OR EXISTS (SELECT 1 FROM user_devices d WHERE d.did = user_did));
+
+DROP INDEX IF EXISTS idx_notification_history_tenant_user_did;
+DROP INDEX IF EXISTS idx_delivery_log_tenant_user_did;
+
+-- Rows a DID has in several tenants collapse to one
+DELETE FROM user_mutes a USING user_mutes b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did AND a.muted_did = b.muted_did;
+ALTER TABLE user_mutes DROP CONSTRAINT user_mutes_user_did_muted_did_key;
+ALTER TABLE user_mutes ADD CONSTRAINT user_mutes_user_did_muted_did_key UNIQUE (user_did, muted_did);
+DELETE FROM user_blocks a USING user_blocks b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did AND a.blocked_did = b.blocked_did;
+ALTER TABLE user_blocks DROP CONSTRAINT user_blocks_user_did_blocked_did_key;
+ALTER TABLE user_blocks ADD CONSTRAINT user_blocks_user_did_blocked_did_key UNIQUE (user_did, blocked_did);
+DELETE FROM user_mutes_hashed a USING user_mutes_hashed b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did AND a.muted_did_hash = b.muted_did_hash;
+ALTER TABLE user_mutes_hashed DROP CONSTRAINT user_mutes_hashed_user_did_muted_did_hash_key;
+ALTER TABLE user_mutes_hashed ADD CONSTRAINT user_mutes_hashed_user_did_muted_did_hash_key UNIQUE (user_did, muted_did_hash);
+DELETE FROM user_blocks_hashed a USING user_blocks_hashed b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did AND a.blocked_did_hash = b.blocked_did_hash;
+ALTER TABLE user_blocks_hashed DROP CONSTRAINT user_blocks_hashed_user_did_blocked_did_hash_key;
+ALTER TABLE user_blocks_hashed ADD CONSTRAINT user_blocks_hashed_user_did_blocked_did_hash_key UNIQUE (user_did, blocked_did_hash);
+DELETE FROM user_mutes_encrypted a USING user_mutes_encrypted b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did AND a.muted_did_encrypted = b.muted_did_encrypted;
+ALTER TABLE user_mutes_encrypted DROP CONSTRAINT user_mutes_encrypted_user_did_muted_did_encrypted_key;
+ALTER TABLE user_mutes_encrypted ADD CONSTRAINT user_mutes_encrypted_user_did_muted_did_encrypted_key UNIQUE (user_did, muted_did_encrypted);
+DELETE FROM user_blocks_encrypted a USING user_blocks_encrypted b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did AND a.blocked_did_encrypted = b.blocked_did_encrypted;
+ALTER TABLE user_blocks_encrypted DROP CONSTRAINT user_blocks_encrypted_user_did_blocked_did_encrypted_key;
+ALTER TABLE user_blocks_encrypted ADD CONSTRAINT user_blocks_encrypted_user_did_blocked_did_encrypted_key UNIQUE (user_did, blocked_did_encrypted);
+DELETE FROM user_vips a USING user_vips b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did AND a.vip_did = b.vip_did;
+ALTER TABLE user_vips DROP CONSTRAINT user_vips_pkey;
+ALTER TABLE user_vips ADD CONSTRAINT user_vips_pkey PRIMARY KEY (user_did, vip_did);
+DELETE FROM user_muted_words a USING user_muted_words b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did AND a.value = b.value;
+ALTER TABLE user_muted_words DROP CONSTRAINT user_muted_words_pkey;
+ALTER TABLE user_muted_words ADD CONSTRAINT user_muted_words_pkey PRIMARY KEY (user_did, value);
+DELETE FROM atom_feeds a USING atom_feeds b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did;
+ALTER TABLE atom_feeds DROP CONSTRAINT atom_feeds_pkey;
+ALTER TABLE atom_feeds ADD CONSTRAINT atom_feeds_pkey PRIMARY KEY (user_did);
+DELETE FROM dm_consents a USING dm_consents b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did;
+ALTER TABLE dm_consents DROP CONSTRAINT dm_consents_pkey;
+ALTER TABLE dm_consents ADD CONSTRAINT dm_consents_pkey PRIMARY KEY (user_did);
+DELETE FROM verification_consents a USING verification_consents b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did;
+ALTER TABLE verification_consents DROP CONSTRAINT verification_consents_pkey;
+ALTER TABLE verification_consents ADD CONSTRAINT verification_consents_pkey PRIMARY KEY (user_did);
+DELETE FROM post_subscriptions a USING post_subscriptions b
+WHERE a.tenant_id > b.tenant_id AND a.user_did = b.user_did AND a.subject_did = b.subject_did;
+ALTER TABLE post_subscriptions DROP CONSTRAINT post_subscriptions_pkey;
+ALTER TABLE post_subscriptions ADD CONSTRAINT post_subscriptions_pkey PRIMARY KEY (user_did, subject_did);
+
+ALTER TABLE notification_history DROP COLUMN tenant_id;
+ALTER TABLE notification_opens DROP COLUMN tenant_id;
+ALTER TABLE user_mutes DROP COLUMN tenant_id;
+ALTER TABLE user_blocks DROP COLUMN tenant_id;
+ALTER TABLE user_mutes_hashed DROP COLUMN tenant_id;
+ALTER TABLE user_blocks_hashed DROP COLUMN tenant_id;
+ALTER TABLE user_mutes_encrypted DROP COLUMN tenant_id;
+ALTER TABLE user_blocks_encrypted DROP COLUMN tenant_id;
+ALTER TABLE user_vips DROP COLUMN tenant_id;
+ALTER TABLE user_muted_words DROP COLUMN tenant_id;
+ALTER TABLE atom_feeds DROP COLUMN tenant_id;
+ALTER TABLE dm_consents DROP COLUMN tenant_id;
+ALTER TABLE verification_consents DROP COLUMN tenant_id;
+ALTER TABLE delivery_log DROP COLUMN tenant_id;
+ALTER TABLE post_subscriptions DROP COLUMN tenant_id;
+ALTER TABLE relationship_audit_log DROP COLUMN tenant_id;
+
+DROP FUNCTION device_tenant(TEXT, TEXT);
+DROP FUNCTION did_registered_elsewhere(TEXT);
@@ -0,0 +1,211 @@
+-- Add up migration script here
+-- Per-user rows carry their own tenant. Scoping DID-keyed tables through user_devices
+-- let a DID registered with two apps read and overwrite the other app's rows, since
+-- both saw the same row. Each table now has a tenant_id, set from app.tenant_id by
+-- tenant transactions and explicitly by the pipeline, which is part of its unique
+-- keys and what its policy checks. firehose_blocks and user_follows stay shared: they
+-- mirror the account's own records on the network, which are the same in every app.
+
+-- The tenant of the device a notification was sent to, for the sender, which writes
+-- history and delivery log rows across tenants. A device re-registered after
+-- deletion keeps its live row first.
+CREATE FUNCTION device_tenant(did TEXT, token TEXT) RETURNS TEXT AS $$
+    SELECT COALESCE(
+        (SELECT d.tenant_id FROM user_devices d
+         WHERE d.did = device_tenant.did AND d.device_token = device_tenant.token
+         ORDER BY d.deleted_at IS NOT NULL, d.created_at DESC
+         LIMIT 1),
+        'default'
+    )
+$$ LANGUAGE sql STABLE;
+
+-- Whether a DID has devices with a tenant other than the transaction's, which a tenant
+-- transaction can't see for itself. An erasure leaves the rows every tenant shares
+-- while another still serves the DID.
+CREATE FUNCTION did_registered_elsewhere(did TEXT) RETURNS BOOLEAN AS $$
+    SELECT EXISTS (
+        SELECT 1 FROM user_devices d
+        WHERE d.did = did_registered_elsewhere.did AND d.tenant_id <> current_tenant_id()
+    )
+$$ LANGUAGE sql STABLE SET app.all_tenants = 'on';
+
+-- Existing rows go to the device they were recorded for where there is one, and
+-- otherwise to the tenant the DID registered with first
+CREATE TEMP TABLE did_tenants AS
+SELECT DISTINCT ON (did) did, tenant_id
+FROM user_devices
+ORDER BY did, deleted_at IS NOT NULL, created_at;
+
+ALTER TABLE notification_history
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE notification_history t SET tenant_id = d.tenant_id
+FROM user_devices d WHERE d.did = t.user_did AND d.device_token = t.device_token;
+UPDATE notification_history t SET tenant_id = dt.tenant_id
+FROM did_tenants dt
+WHERE dt.did = t.user_did
+AND NOT EXISTS (SELECT 1 FROM user_devices d WHERE d.did = t.user_did AND d.device_token = t.device_token);
+
+ALTER TABLE notification_opens
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE notification_opens t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE user_mutes
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE user_mutes t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE user_blocks
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE user_blocks t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE user_mutes_hashed
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE user_mutes_hashed t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE user_blocks_hashed
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE user_blocks_hashed t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE user_mutes_encrypted
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE user_mutes_encrypted t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE user_blocks_encrypted
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE user_blocks_encrypted t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE user_vips
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE user_vips t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE user_muted_words
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE user_muted_words t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE atom_feeds
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE atom_feeds t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE dm_consents
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE dm_consents t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE verification_consents
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE verification_consents t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE delivery_log
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE delivery_log t SET tenant_id = d.tenant_id
+FROM user_devices d WHERE d.did = t.user_did AND d.device_token = t.device_token;
+UPDATE delivery_log t SET tenant_id = dt.tenant_id
+FROM did_tenants dt
+WHERE dt.did = t.user_did
+AND NOT EXISTS (SELECT 1 FROM user_devices d WHERE d.did = t.user_did AND d.device_token = t.device_token);
+
+ALTER TABLE post_subscriptions
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE post_subscriptions t SET tenant_id = dt.tenant_id FROM did_tenants dt WHERE dt.did = t.user_did;
+
+ALTER TABLE relationship_audit_log
+    ADD COLUMN tenant_id TEXT NOT NULL DEFAULT COALESCE(current_tenant_id(), 'default') REFERENCES tenants(id);
+UPDATE relationship_audit_log t SET tenant_id = d.tenant_id
+FROM user_devices d WHERE d.did = t.user_did AND d.device_token = t.device_token;
+UPDATE relationship_audit_log t SET tenant_id = dt.tenant_id
+FROM did_tenants dt
+WHERE dt.did = t.user_did
+AND NOT EXISTS (SELECT 1 FROM user_devices d WHERE d.did = t.user_did AND d.device_token = t.device_token);
+
+-- Unique per tenant, so each app upserts its own row
+ALTER TABLE user_mutes DROP CONSTRAINT user_mutes_user_did_muted_did_key;
+ALTER TABLE user_mutes ADD CONSTRAINT user_mutes_user_did_muted_did_key UNIQUE (tenant_id, user_did, muted_did);
+ALTER TABLE user_blocks DROP CONSTRAINT user_blocks_user_did_blocked_did_key;
+ALTER TABLE user_blocks ADD CONSTRAINT user_blocks_user_did_blocked_did_key UNIQUE (tenant_id, user_did, blocked_did);
+ALTER TABLE user_mutes_hashed DROP CONSTRAINT user_mutes_hashed_user_did_muted_did_hash_key;
+ALTER TABLE user_mutes_hashed ADD CONSTRAINT user_mutes_hashed_user_did_muted_did_hash_key UNIQUE (tenant_id, user_did, muted_did_hash);
+ALTER TABLE user_blocks_hashed DROP CONSTRAINT user_blocks_hashed_user_did_blocked_did_hash_key;
+ALTER TABLE user_blocks_hashed ADD CONSTRAINT user_blocks_hashed_user_did_blocked_did_hash_key UNIQUE (tenant_id, user_did, blocked_did_hash);
+ALTER TABLE user_mutes_encrypted DROP CONSTRAINT user_mutes_encrypted_user_did_muted_did_encrypted_key;
+ALTER TABLE user_mutes_encrypted ADD CONSTRAINT user_mutes_encrypted_user_did_muted_did_encrypted_key UNIQUE (tenant_id, user_did, muted_did_encrypted);
+ALTER TABLE user_blocks_encrypted DROP CONSTRAINT user_blocks_encrypted_user_did_blocked_did_encrypted_key;
+ALTER TABLE user_blocks_encrypted ADD CONSTRAINT user_blocks_encrypted_user_did_blocked_did_encrypted_key UNIQUE (tenant_id, user_did, blocked_did_encrypted);
+ALTER TABLE user_vips DROP CONSTRAINT user_vips_pkey;
+ALTER TABLE user_vips ADD CONSTRAINT user_vips_pkey PRIMARY KEY (tenant_id, user_did, vip_did);
+ALTER TABLE user_muted_words DROP CONSTRAINT user_muted_words_pkey;
+ALTER TABLE user_muted_words ADD CONSTRAINT user_muted_words_pkey PRIMARY KEY (tenant_id, user_did, value);
+ALTER TABLE atom_feeds DROP CONSTRAINT atom_feeds_pkey;
+ALTER TABLE atom_feeds ADD CONSTRAINT atom_feeds_pkey PRIMARY KEY (tenant_id, user_did);
+ALTER TABLE dm_consents DROP CONSTRAINT dm_consents_pkey;
+ALTER TABLE dm_consents ADD CONSTRAINT dm_consents_pkey PRIMARY KEY (tenant_id, user_did);
+ALTER TABLE verification_consents DROP CONSTRAINT verification_consents_pkey;
+ALTER TABLE verification_consents ADD CONSTRAINT verification_consents_pkey PRIMARY KEY (tenant_id, user_did);
+ALTER TABLE post_subscriptions DROP CONSTRAINT post_subscriptions_pkey;
+ALTER TABLE post_subscriptions ADD CONSTRAINT post_subscriptions_pkey PRIMARY KEY (tenant_id, user_did, subject_did);
+
+CREATE INDEX idx_notification_history_tenant_user_did ON notification_history(tenant_id, user_did, created_at);
+CREATE INDEX idx_delivery_log_tenant_user_did ON delivery_log(tenant_id, user_did, created_at);
+
+DROP POLICY tenant_isolation ON notification_history;
+CREATE POLICY tenant_isolation ON notification_history
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON notification_opens;
+CREATE POLICY tenant_isolation ON notification_opens
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON user_mutes;
+CREATE POLICY tenant_isolation ON user_mutes
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON user_blocks;
+CREATE POLICY tenant_isolation ON user_blocks
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON user_mutes_hashed;
+CREATE POLICY tenant_isolation ON user_mutes_hashed
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON user_blocks_hashed;
+CREATE POLICY tenant_isolation ON user_blocks_hashed
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON user_mutes_encrypted;
+CREATE POLICY tenant_isolation ON user_mutes_encrypted
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON user_blocks_encrypted;
+CREATE POLICY tenant_isolation ON user_blocks_encrypted
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON user_vips;
+CREATE POLICY tenant_isolation ON user_vips
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON user_muted_words;
+CREATE POLICY tenant_isolation ON user_muted_words
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON atom_feeds;
+CREATE POLICY tenant_isolation ON atom_feeds
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON dm_consents;
+CREATE POLICY tenant_isolation ON dm_consents
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON verification_consents;
+CREATE POLICY tenant_isolation ON verification_consents
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON delivery_log;
+CREATE POLICY tenant_isolation ON delivery_log
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON post_subscriptions;
+CREATE POLICY tenant_isolation ON post_subscriptions
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP POLICY tenant_isolation ON relationship_audit_log;
+CREATE POLICY tenant_isolation ON relationship_audit_log
+    USING (all_tenants() OR tenant_id = current_tenant_id());
+
+DROP TABLE did_tenants;
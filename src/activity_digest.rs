@@ -0,0 +1,95 @@
+// activity_digest.rs
+// Opt-in rollups for low-priority activity. Users with digest_low_priority set get
+// their likes and reposts counted per device and type, and a scheduler task sends one
+// summary push per device every ACTIVITY_DIGEST_INTERVAL_MINUTES. Everything else
+// stays realtime.
+use anyhow::Result;
+use sqlx::{Pool, Postgres};
+use tracing::{info, warn};
+
+use crate::channel::PipelineSender;
+use crate::models::{NotificationPayload, NotificationType};
+
+pub fn is_digestible(notification_type: &NotificationType) -> bool {
+    matches!(notification_type, NotificationType::Like | NotificationType::Repost)
+}
+
+pub async fn defer(
+    pool: &Pool<Postgres>,
+    user_id: uuid::Uuid,
+    notification_type: &NotificationType,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO activity_digest_pending (user_id, notification_type, count)
+        VALUES ($1, $2, 1)
+        ON CONFLICT (user_id, notification_type)
+        DO UPDATE SET count = activity_digest_pending.count + 1, updated_at = NOW()
+        "#,
+        user_id,
+        notification_type.as_str()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Send one rollup per device for everything counted since the last flush
+pub async fn flush(
+    pool: &Pool<Postgres>,
+    notification_sender: &PipelineSender<NotificationPayload>,
+) -> Result<usize> {
+    let devices = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT q.user_id
+        FROM activity_digest_pending q
+        JOIN user_devices d ON d.id = q.user_id AND d.deleted_at IS NULL
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut sent = 0;
+    for user_id in devices {
+        // The counts are only deleted once the digest is queued
+        let mut tx = pool.begin().await?;
+        let rows = sqlx::query!(
+            r#"
+            DELETE FROM activity_digest_pending q
+            USING user_devices d
+            WHERE q.user_id = $1 AND d.id = q.user_id
+            RETURNING d.device_token, d.did, q.notification_type, q.count
+            "#,
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let Some(first) = rows.first() else {
+            continue;
+        };
+        let (device_token, user_did) = (first.device_token.clone(), first.did.clone());
+        let counts: Vec<_> = rows
+            .into_iter()
+            .filter_map(|row| Some((NotificationType::parse(&row.notification_type)?, row.count)))
+            .collect();
+        if counts.is_empty() {
+            tx.commit().await?;
+            continue;
+        }
+
+        let payload = crate::quota::digest_payload(user_did, device_token, counts, "Recent activity");
+        if let Err(e) = notification_sender.send(payload).await {
+            warn!("Failed to queue activity digest: {}", e);
+            tx.rollback().await?;
+            break;
+        }
+        tx.commit().await?;
+        sent += 1;
+    }
+
+    if sent > 0 {
+        info!("Sent {} activity digests", sent);
+    }
+    Ok(sent)
+}
@@ -0,0 +1,658 @@
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::api::ApiState;
+use crate::experiments::{CopyExperiment, CopyVariant};
+use crate::feature_flags::FeatureFlag;
+use crate::models::EventSource;
+
+#[derive(Deserialize)]
+struct FlagUpdateRequest {
+    enabled: bool,
+    rollout_percentage: i32,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExperimentUpdateRequest {
+    notification_type: String,
+    variants: Vec<CopyVariant>,
+}
+
+#[derive(Deserialize)]
+struct RestoreQuery {
+    device_token: String,
+}
+
+#[derive(Deserialize)]
+struct OpenRatesQuery {
+    #[serde(default = "default_open_rate_days")]
+    days: i32,
+}
+
+fn default_open_rate_days() -> i32 {
+    7
+}
+
+#[derive(Deserialize)]
+struct ReplayQuery {
+    did: String,
+    // RFC 3339 timestamp
+    since: String,
+}
+
+// Upper bound on notifications re-sent by one replay request
+const MAX_REPLAY_NOTIFICATIONS: i64 = 100;
+
+#[derive(Deserialize)]
+struct DeliveriesQuery {
+    did: String,
+    #[serde(default = "default_deliveries_limit")]
+    limit: i64,
+}
+
+fn default_deliveries_limit() -> i64 {
+    50
+}
+
+#[derive(Deserialize)]
+struct UsageQuery {
+    #[serde(default = "default_usage_days")]
+    days: i32,
+}
+
+fn default_usage_days() -> i32 {
+    30
+}
+
+#[derive(Deserialize)]
+struct QuotaUpdateRequest {
+    daily_quota: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct MintInviteRequest {
+    // Unlimited when absent
+    max_uses: Option<i32>,
+    // Never expires when absent
+    expires_in_days: Option<i32>,
+}
+
+// Rotated keys come with a new key id; the path defaults to APNS_KEY_PATH
+#[derive(Deserialize)]
+struct ReloadApnsKeyRequest {
+    key_id: String,
+    key_path: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct AllowDidRequest {
+    note: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PresetUpdateRequest {
+    label: String,
+    description: Option<String>,
+    fallback: bool,
+    #[serde(default)]
+    toggles: std::collections::HashMap<String, bool>,
+}
+
+#[derive(Deserialize, Default)]
+struct StartTraceRequest {
+    // Capped at user_trace::MAX_TRACE_TTL
+    ttl_minutes: Option<u64>,
+}
+
+fn default_trace_minutes() -> u64 {
+    60
+}
+
+#[derive(Deserialize)]
+struct DryRunQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+// Admin routes, nested under /admin by the API router
+pub fn create_admin_router(state: Arc<ApiState>) -> Router<Arc<ApiState>> {
+    Router::new()
+        .route("/devices/merge-duplicates", post(merge_duplicate_devices))
+        .route("/devices/restore", post(restore_device))
+        .route("/replay", post(replay_notifications))
+        .route("/backfill", get(list_backfills))
+        .route("/backfill", post(start_backfill))
+        .route("/backfill/:id", get(get_backfill))
+        .route("/deliveries", get(list_deliveries))
+        .route("/invites", get(list_invites))
+        .route("/invites", post(mint_invite))
+        .route("/invites/:code", delete(revoke_invite))
+        .route("/allowlist", get(list_allowlist))
+        .route("/allowlist/:did", put(allow_did))
+        .route("/allowlist/:did", delete(disallow_did))
+        .route("/tenants/usage", get(tenant_usage))
+        .route("/tenants/:id/quota", put(update_tenant_quota))
+        .route("/flags", get(list_flags))
+        .route("/flags/:name", put(update_flag))
+        .route("/experiments", get(list_experiments))
+        .route("/analytics/open-rates", get(open_rates))
+        .route("/experiments/:name", put(update_experiment))
+        .route("/presets", get(list_presets))
+        .route("/presets/:name", put(update_preset))
+        .route("/maintenance", get(maintenance_report))
+        .route("/maintenance/run", post(run_maintenance))
+        .route("/apns/reload-key", post(reload_apns_key))
+        .route("/trace", get(list_traces))
+        .route("/trace/:did", get(get_trace))
+        .route("/trace/:did", put(start_trace))
+        .route("/trace/:did", delete(stop_trace))
+        .route("/version", get(version))
+        .route_layer(middleware::from_fn_with_state(state, require_admin_token))
+        // Outside the token check so it opens in a browser; the page is static and
+        // only polls the public /metrics endpoint
+        .route("/dashboard", get(dashboard))
+}
+
+// Reject requests without the configured admin bearer token.
+// Admin routes are disabled entirely when ADMIN_API_TOKEN is not set.
+async fn require_admin_token(
+    State(state): State<Arc<ApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let expected = match &state.admin_api_token {
+        Some(token) => token,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match provided {
+        Some(token) if constant_time_eq::constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => {
+            warn!(path = %request.uri().path(), "Unauthorized admin request");
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+}
+
+async fn dashboard(State(state): State<Arc<ApiState>>) -> Response {
+    if state.admin_api_token.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    Html(include_str!("dashboard.html")).into_response()
+}
+
+async fn merge_duplicate_devices(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<DryRunQuery>,
+) -> impl IntoResponse {
+    match crate::db::merge_duplicate_devices(&state.db_pool, query.dry_run).await {
+        Ok(report) => {
+            info!(
+                groups = report.groups_found,
+                removed = report.devices_removed,
+                normalized = report.tokens_normalized,
+                dry_run = report.dry_run,
+                "Duplicate device merge finished"
+            );
+            Json(report).into_response()
+        }
+        Err(e) => {
+            error!("Error merging duplicate devices: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Internal server error: {}", e),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn restore_device(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<RestoreQuery>,
+) -> StatusCode {
+    let device_token = crate::db::normalize_device_token(&query.device_token);
+
+    match crate::db::restore_device(&state.db_pool, &device_token).await {
+        Ok(true) => {
+            info!("Restored soft-deleted device");
+            StatusCode::OK
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Error restoring device: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn list_flags(State(state): State<Arc<ApiState>>) -> Json<Vec<FeatureFlag>> {
+    Json(state.feature_flags.list().await)
+}
+
+async fn update_flag(
+    State(state): State<Arc<ApiState>>,
+    Path(name): Path<String>,
+    Json(req): Json<FlagUpdateRequest>,
+) -> StatusCode {
+    if !(0..=100).contains(&req.rollout_percentage) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let flag = FeatureFlag {
+        name,
+        enabled: req.enabled,
+        rollout_percentage: req.rollout_percentage,
+        description: req.description,
+    };
+
+    match state.feature_flags.upsert(flag).await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error updating feature flag: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn list_experiments(State(state): State<Arc<ApiState>>) -> Json<Vec<CopyExperiment>> {
+    Json(state.experiments.list().await)
+}
+
+async fn update_experiment(
+    State(state): State<Arc<ApiState>>,
+    Path(name): Path<String>,
+    Json(req): Json<ExperimentUpdateRequest>,
+) -> StatusCode {
+    if crate::models::NotificationType::parse(&req.notification_type).is_none()
+        || req.variants.is_empty()
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let experiment = CopyExperiment {
+        name,
+        notification_type: req.notification_type,
+        variants: req.variants,
+    };
+
+    match state.experiments.upsert(experiment).await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error updating copy experiment: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn list_presets(State(state): State<Arc<ApiState>>) -> Response {
+    match crate::presets::list(&state.db_pool).await {
+        Ok(presets) => Json(presets).into_response(),
+        Err(e) => {
+            error!("Error listing preference presets: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Create or edit a preset; devices on it pick up the new version
+async fn update_preset(
+    State(state): State<Arc<ApiState>>,
+    Path(name): Path<String>,
+    Json(req): Json<PresetUpdateRequest>,
+) -> Response {
+    let known = crate::default_preferences::PREFERENCE_COLUMNS;
+    if let Some(column) = req.toggles.keys().find(|column| !known.contains(&column.as_str())) {
+        return (StatusCode::BAD_REQUEST, format!("Unknown preference {}", column)).into_response();
+    }
+
+    match crate::presets::upsert(
+        &state.db_pool,
+        &name,
+        &req.label,
+        req.description.as_deref(),
+        req.fallback,
+        &req.toggles,
+    )
+    .await
+    {
+        Ok((preset, devices)) => {
+            info!(preset = %preset.name, version = preset.version, devices, "Updated preference preset");
+            Json(preset).into_response()
+        }
+        Err(e) => {
+            error!("Error updating preference preset: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn open_rates(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<OpenRatesQuery>,
+) -> Response {
+    match crate::db::get_open_rates(&state.db_pool, query.days.clamp(1, 365)).await {
+        Ok(rates) => Json(rates).into_response(),
+        Err(e) => {
+            error!("Error computing open rates: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Re-queue a user's delivered notifications since a point in time, e.g. after their
+// device was offline or broken. Each replay keeps its original notification id,
+// which doubles as the APNs collapse id, so devices that already have the alert
+// don't show it twice.
+async fn replay_notifications(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<ReplayQuery>,
+) -> Response {
+    let since = match chrono::DateTime::parse_from_rfc3339(&query.since) {
+        Ok(since) => since,
+        Err(_) => return (StatusCode::BAD_REQUEST, "since must be an RFC 3339 timestamp").into_response(),
+    };
+
+    let notifications = match crate::db::get_replayable_notifications(
+        &state.db_pool,
+        &query.did,
+        since.timestamp() as f64,
+        MAX_REPLAY_NOTIFICATIONS,
+    )
+    .await
+    {
+        Ok(notifications) => notifications,
+        Err(e) => {
+            error!("Error loading notification history for replay: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut queued = 0;
+    for mut notification in notifications {
+        // Keeps the replay out of delivery stats and tenant usage
+        notification
+            .data
+            .insert("event_source".to_string(), EventSource::Replay.as_str().to_string());
+        if let Err(e) = state.notification_sender.send(notification).await {
+            error!("Failed to queue replayed notification: {}", e);
+            break;
+        }
+        queued += 1;
+    }
+
+    info!(did = %query.did, queued, "Replayed notifications from history");
+    Json(serde_json::json!({ "queued": queued })).into_response()
+}
+
+// Rebuild the notifications some users missed from repo archives; see backfill.rs.
+// Returns the job's initial progress, with the id to poll.
+async fn start_backfill(
+    State(state): State<Arc<ApiState>>,
+    Json(req): Json<crate::backfill::BackfillRequest>,
+) -> Response {
+    match state.backfills.start(req) {
+        Ok(progress) => (StatusCode::ACCEPTED, Json(progress)).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn list_backfills(State(state): State<Arc<ApiState>>) -> Response {
+    Json(state.backfills.jobs()).into_response()
+}
+
+async fn get_backfill(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<uuid::Uuid>,
+) -> Response {
+    match state.backfills.job(id) {
+        Some(progress) => Json(progress).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+// What APNs answered for a user's recent sends, for "I never got the push" reports
+async fn list_deliveries(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Response {
+    match crate::db::get_delivery_log(&state.db_pool, &query.did, None, query.limit.clamp(1, 500)).await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => {
+            error!("Error loading delivery log: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn list_invites(State(state): State<Arc<ApiState>>) -> Response {
+    match crate::registration::list(&state.db_pool).await {
+        Ok(invites) => Json(invites).into_response(),
+        Err(e) => {
+            error!("Error listing invite codes: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn mint_invite(
+    State(state): State<Arc<ApiState>>,
+    Json(req): Json<MintInviteRequest>,
+) -> Response {
+    if req.max_uses.is_some_and(|uses| uses < 1) || req.expires_in_days.is_some_and(|days| days < 1) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    match crate::registration::mint(&state.db_pool, req.max_uses, req.expires_in_days).await {
+        Ok(invite) => {
+            info!(code = %invite.code, "Minted invite code");
+            Json(invite).into_response()
+        }
+        Err(e) => {
+            error!("Error minting invite code: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn revoke_invite(
+    State(state): State<Arc<ApiState>>,
+    Path(code): Path<String>,
+) -> StatusCode {
+    match crate::registration::revoke(&state.db_pool, &code).await {
+        Ok(true) => StatusCode::OK,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Error revoking invite code: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn list_allowlist(State(state): State<Arc<ApiState>>) -> Response {
+    match crate::registration::list_allowed(&state.db_pool).await {
+        Ok(allowed) => Json(allowed).into_response(),
+        Err(e) => {
+            error!("Error listing registration allowlist: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn allow_did(
+    State(state): State<Arc<ApiState>>,
+    Path(did): Path<String>,
+    req: Option<Json<AllowDidRequest>>,
+) -> StatusCode {
+    if !did.starts_with("did:") {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let req = req.map(|Json(req)| req).unwrap_or_default();
+    match crate::registration::allow(&state.db_pool, &did, req.note.as_deref()).await {
+        Ok(()) => {
+            info!(did = %did, "Added DID to registration allowlist");
+            StatusCode::OK
+        }
+        Err(e) => {
+            error!("Error updating registration allowlist: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn disallow_did(
+    State(state): State<Arc<ApiState>>,
+    Path(did): Path<String>,
+) -> StatusCode {
+    match crate::registration::disallow(&state.db_pool, &did).await {
+        Ok(true) => StatusCode::OK,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Error updating registration allowlist: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn tenant_usage(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<UsageQuery>,
+) -> Response {
+    match state.quota.usage(query.days.clamp(1, 366)).await {
+        Ok(usage) => Json(usage).into_response(),
+        Err(e) => {
+            error!("Error loading tenant usage: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn update_tenant_quota(
+    State(state): State<Arc<ApiState>>,
+    Path(id): Path<String>,
+    Json(req): Json<QuotaUpdateRequest>,
+) -> StatusCode {
+    if req.daily_quota.is_some_and(|quota| quota < 0) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    match state.quota.set_quota(&id, req.daily_quota).await {
+        Ok(true) => StatusCode::OK,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Error updating tenant quota: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Report of the last maintenance run on this replica, null if none has run yet
+async fn maintenance_report(State(state): State<Arc<ApiState>>) -> Response {
+    Json(state.maintenance.last_report().await).into_response()
+}
+
+// Run maintenance now instead of waiting for the schedule
+async fn run_maintenance(State(state): State<Arc<ApiState>>) -> Response {
+    info!("Running maintenance on admin request");
+    Json(state.maintenance.run_once().await).into_response()
+}
+
+// Swap in a rotated APNs signing key on this replica; the old key keeps working until
+// the new one has loaded
+async fn reload_apns_key(
+    State(state): State<Arc<ApiState>>,
+    Json(req): Json<ReloadApnsKeyRequest>,
+) -> Response {
+    match state.apns_client.reload_key(req.key_path.as_deref(), &req.key_id) {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => {
+            error!("Error reloading APNs key: {}", e);
+            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+    }
+}
+
+async fn list_traces(State(state): State<Arc<ApiState>>) -> Response {
+    Json(state.user_trace.active()).into_response()
+}
+
+// Follow a user through the pipeline on this replica for a while; see user_trace.rs
+async fn start_trace(
+    State(state): State<Arc<ApiState>>,
+    Path(did): Path<String>,
+    req: Option<Json<StartTraceRequest>>,
+) -> Response {
+    if !did.starts_with("did:") {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let req = req.map(|Json(req)| req).unwrap_or_default();
+    let ttl = std::time::Duration::from_secs(req.ttl_minutes.unwrap_or_else(default_trace_minutes).saturating_mul(60));
+    Json(state.user_trace.start(&did, ttl)).into_response()
+}
+
+// Decisions recorded for the user, oldest first; kept after the trace stops until
+// newer entries push them out
+async fn get_trace(
+    State(state): State<Arc<ApiState>>,
+    Path(did): Path<String>,
+) -> Response {
+    Json(state.user_trace.entries(&did)).into_response()
+}
+
+async fn stop_trace(
+    State(state): State<Arc<ApiState>>,
+    Path(did): Path<String>,
+) -> StatusCode {
+    if state.user_trace.stop(&did) {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+// Which build and configuration this replica runs
+async fn version(State(state): State<Arc<ApiState>>) -> Response {
+    let firehose_cursor = match crate::db::get_last_cursor(&state.db_pool).await {
+        Ok(cursor) => cursor.map(|cursor| crate::build_info::FirehosePosition {
+            cursor: cursor.cursor,
+            updated_at: cursor.updated_at,
+        }),
+        Err(e) => {
+            warn!("Failed to read firehose cursor for /admin/version: {}", e);
+            None
+        }
+    };
+    let metrics = tokio::runtime::Handle::current().metrics();
+    let runtime = crate::build_info::RuntimeInfo {
+        worker_threads: metrics.num_workers(),
+        alive_tasks: metrics.num_alive_tasks(),
+        db_pool_max_connections: state.db_pool.options().get_max_connections(),
+    };
+    Json(crate::build_info::VersionInfo::new(
+        state.started_at,
+        state.feature_flags.list().await,
+        runtime,
+        firehose_cursor,
+    ))
+    .into_response()
+}
@@ -0,0 +1,169 @@
+// aggregation.rs
+// Groups bursts of likes and reposts on one post. The first notification of a burst
+// goes out straight away and opens a window of AGGREGATION_WINDOW_MINUTES for that
+// device, type and post; anything arriving inside the window is held, and when the
+// window closes a single "@alice and 3 others liked your post" replaces the first on
+// the device through their shared collapse id. Windows are kept in memory, so a
+// restart drops whatever is held. 0 disables aggregation.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::channel::PipelineSender;
+use crate::models::{NotificationPayload, NotificationType};
+
+pub fn is_aggregatable(notification_type: &NotificationType) -> bool {
+    matches!(notification_type, NotificationType::Like | NotificationType::Repost)
+}
+
+struct Group {
+    opened_at: Instant,
+    // Handles of everyone in the burst, the first one sent included
+    actors: Vec<String>,
+    // The latest notification held back, sent with a combined title at the end
+    held: Option<NotificationPayload>,
+}
+
+#[derive(Clone)]
+pub struct Aggregator {
+    window: Duration,
+    // Keyed by "device_token|type|uri"
+    groups: Arc<Mutex<HashMap<String, Group>>>,
+}
+
+impl Aggregator {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self::new(Duration::from_secs(config.aggregation_window_minutes * 60))
+    }
+
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            groups: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Returns the notification if it should be sent now, or None if it was folded
+    // into an open window
+    pub async fn offer(&self, payload: NotificationPayload, actor_handle: &str) -> Option<NotificationPayload> {
+        if self.window.is_zero() || !is_aggregatable(&payload.notification_type) {
+            return Some(payload);
+        }
+        let Some(uri) = payload.data.get("uri") else {
+            return Some(payload);
+        };
+
+        let key = format!("{}|{}|{}", payload.device_token, payload.notification_type.as_str(), uri);
+        let mut groups = self.groups.lock().await;
+        match groups.get_mut(&key) {
+            Some(group) if group.opened_at.elapsed() < self.window => {
+                if !group.actors.iter().any(|actor| actor == actor_handle) {
+                    group.actors.push(actor_handle.to_string());
+                }
+                group.held = Some(payload);
+                crate::metrics::NOTIFICATIONS_AGGREGATED.inc();
+                None
+            }
+            _ => {
+                groups.insert(
+                    key,
+                    Group {
+                        opened_at: Instant::now(),
+                        actors: vec![actor_handle.to_string()],
+                        held: None,
+                    },
+                );
+                Some(payload)
+            }
+        }
+    }
+
+    // Close expired windows, returning one combined notification for each that held any
+    async fn take_expired(&self) -> Vec<NotificationPayload> {
+        let mut groups = self.groups.lock().await;
+        let expired: Vec<String> = groups
+            .iter()
+            .filter(|(_, group)| group.opened_at.elapsed() >= self.window)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|key| groups.remove(&key))
+            .filter_map(|group| {
+                let mut payload = group.held?;
+                let latest = group.actors.last()?;
+                payload.title = combined_title(&payload.notification_type, latest, group.actors.len() - 1);
+                payload
+                    .data
+                    .insert("aggregated_count".to_string(), group.actors.len().to_string());
+                Some(payload)
+            })
+            .collect()
+    }
+
+    pub async fn run(self, notification_sender: PipelineSender<NotificationPayload>) {
+        if self.window.is_zero() {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(Duration::from_secs(15));
+        loop {
+            interval.tick().await;
+            for payload in self.take_expired().await {
+                if let Err(e) = notification_sender.send(payload).await {
+                    warn!("Failed to queue aggregated notification: {}", e);
+                }
+            }
+        }
+    }
+}
+
+fn combined_title(notification_type: &NotificationType, latest: &str, others: usize) -> String {
+    let action = match notification_type {
+        NotificationType::Repost => "reposted your post",
+        _ => "liked your post",
+    };
+    match others {
+        0 => format!("@{} {}", latest, action),
+        1 => format!("@{} and 1 other {}", latest, action),
+        n => format!("@{} and {} others {}", latest, n, action),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn like(actor: &str) -> NotificationPayload {
+        NotificationPayload {
+            user_did: "did:plc:me".to_string(),
+            device_token: "token".to_string(),
+            notification_type: NotificationType::Like,
+            title: format!("@{} liked your post", actor),
+            body: "hello".to_string(),
+            data: [("uri".to_string(), "at://did:plc:me/app.bsky.feed.post/1".to_string())].into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn folds_a_burst_into_one_notification() {
+        let aggregator = Aggregator::new(Duration::from_millis(50));
+
+        assert!(aggregator.offer(like("alice"), "alice").await.is_some());
+        for actor in ["bob", "carol", "dave", "bob"] {
+            assert!(aggregator.offer(like(actor), actor).await.is_none());
+        }
+        assert!(aggregator.take_expired().await.is_empty());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let combined = aggregator.take_expired().await;
+        assert_eq!(combined.len(), 1);
+        assert_eq!(combined[0].title, "@dave and 3 others liked your post");
+        assert_eq!(combined[0].data["aggregated_count"], "4");
+
+        assert!(aggregator.offer(like("erin"), "erin").await.is_some());
+    }
+}
@@ -1,30 +1,39 @@
 use axum::{
     error_handling::HandleErrorLayer, // Add HandleErrorLayer
-    extract::{Json, Query, State},
+    extract::{Extension, Json, Path, Query, State},
     http::{header, StatusCode},
+    middleware,
     response::IntoResponse,
-    routing::{get, post, put},
+    routing::{delete, get, post, put},
     BoxError, // Add BoxError for error handler
     Router,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 use tower_http::cors::CorsLayer;
 // Remove unused import: tower_http::limit::RequestBodyLimitLayer
 use tower::timeout::TimeoutLayer;
 use tower::ServiceBuilder;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
-use crate::models::{NotificationPreference, UserDevice};
+use crate::fieldmask::{FieldMask, FieldsQuery};
+use crate::models::{Grouping, LabelVisibility, NotificationPreference, NotificationType, UserDevice};
+use crate::portability::default_true;
 use crate::relationship_manager::RelationshipManager;
+use crate::reporting::ReportOutcome;
+use crate::tenant::Tenant;
 
 // Request and response models
 #[derive(Deserialize)]
 struct RegisterRequest {
     did: String,
     device_token: String,
+    // Needed for an account's first device when REGISTRATION_MODE=invite
+    #[serde(default)]
+    invite_code: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -41,6 +50,81 @@ struct PreferencesRequest {
     follows: bool,
     reposts: bool,
     quotes: bool,
+    #[serde(default = "default_true")]
+    replies_to_replies: bool,
+    #[serde(default)]
+    priority_from_mutuals: bool,
+    #[serde(default = "default_true")]
+    feed_posts: bool,
+    #[serde(default)]
+    digest_low_priority: bool,
+    #[serde(default = "default_true")]
+    dms: bool,
+    #[serde(default)]
+    dm_redact_body: bool,
+    #[serde(default)]
+    post_edits: bool,
+    #[serde(default = "default_true")]
+    list_additions: bool,
+    #[serde(default)]
+    background_types: Vec<String>,
+    #[serde(default)]
+    comeback_posts: bool,
+    #[serde(default = "default_true")]
+    custom_notifications: bool,
+    #[serde(default)]
+    reply_context: bool,
+    #[serde(default)]
+    only_from_follows: bool,
+}
+
+// Per-type author thresholds
+#[derive(Deserialize, Serialize)]
+struct ThresholdEntry {
+    notification_type: String,
+    min_account_age_days: Option<i32>,
+    min_followers: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct ThresholdsRequest {
+    did: String,
+    device_token: String,
+    thresholds: Vec<ThresholdEntry>,
+}
+
+#[derive(Serialize)]
+struct ThresholdsResponse {
+    did: String,
+    thresholds: Vec<ThresholdEntry>,
+}
+
+// Per-label handling of moderated content; unlisted labels use the defaults
+#[derive(Deserialize)]
+struct LabelPreferencesRequest {
+    did: String,
+    device_token: String,
+    labels: HashMap<String, LabelVisibility>,
+}
+
+#[derive(Serialize)]
+struct LabelPreferencesResponse {
+    did: String,
+    labels: HashMap<String, LabelVisibility>,
+}
+
+// Custom feeds to announce new posts from; replaces the existing set
+#[derive(Deserialize)]
+struct FeedSubscriptionsRequest {
+    did: String,
+    device_token: String,
+    feeds: Vec<crate::feeds::FeedSubscription>,
+}
+
+#[derive(Serialize)]
+struct FeedSubscriptionsResponse {
+    did: String,
+    feeds: Vec<crate::feeds::FeedSubscription>,
 }
 
 // New model for relationship updates with authentication
@@ -52,10 +136,315 @@ struct RelationshipsRequest {
     blocks: Vec<String>,
 }
 
+// Export/import authenticate with a registered device token
+#[derive(Deserialize)]
+struct ExportQuery {
+    did: String,
+    device_token: String,
+}
+
+#[derive(Deserialize)]
+struct ImportRequest {
+    device_token: String,
+    settings: crate::portability::SignedSettings,
+}
+
+// Echo of the nonce delivered in the verification push
+#[derive(Deserialize)]
+struct ConfirmRegistrationRequest {
+    did: String,
+    device_token: String,
+    nonce: String,
+}
+
+// Sent by the app when a push is tapped
+#[derive(Deserialize)]
+struct NotificationOpenedRequest {
+    did: String,
+    device_token: String,
+    notification_id: uuid::Uuid,
+}
+
+#[derive(Deserialize)]
+struct DeviceQuery {
+    did: String,
+    device_token: String,
+}
+
+#[derive(Deserialize)]
+struct QuietHoursRequest {
+    did: String,
+    device_token: String,
+    #[serde(flatten)]
+    quiet_hours: crate::quiet_hours::QuietHours,
+}
+
+// Accounts whose notifications always get through; replaces the existing list
+#[derive(Deserialize)]
+struct VipsRequest {
+    did: String,
+    device_token: String,
+    vips: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct VipsResponse {
+    vips: Vec<String>,
+}
+
+// VIPs bypass every delivery safeguard, so the list is kept short
+const MAX_VIPS: usize = 50;
+
+// Turns the Atom feed of notification history on, or changes the types it includes
+#[derive(Deserialize)]
+struct AtomFeedRequest {
+    did: String,
+    device_token: String,
+    // Every type when absent
+    notification_types: Option<Vec<String>>,
+}
+
+// Rotating the Atom feed's URL or turning the feed off
+#[derive(Deserialize)]
+struct AtomFeedDeviceRequest {
+    did: String,
+    device_token: String,
+}
+
+#[derive(Serialize)]
+struct AtomFeedResponse {
+    // Set when a new secret URL was issued; it can't be retrieved later
+    path: Option<String>,
+}
+
+// The user's muted words, as the app has them; replaces the existing list
+#[derive(Deserialize)]
+struct MutedWordsRequest {
+    did: String,
+    device_token: String,
+    muted_words: Vec<crate::muted_words::MutedWord>,
+}
+
+#[derive(Serialize)]
+struct MutedWordsResponse {
+    muted_words: Vec<crate::muted_words::MutedWord>,
+}
+
+// Toggles the "notify me when they post" bell for one account
+#[derive(Deserialize)]
+struct PostSubscriptionRequest {
+    did: String,
+    device_token: String,
+    subject: String,
+    subscribed: bool,
+}
+
+#[derive(Serialize)]
+struct PostSubscriptionsResponse {
+    subjects: Vec<String>,
+}
+
+// Sent by the app when the user views their notifications
+#[derive(Deserialize)]
+struct NotificationsSeenRequest {
+    did: String,
+    device_token: String,
+    // Only the Mentions tab was viewed
+    #[serde(default)]
+    mentions_only: bool,
+}
+
+// Sent by the app when it opens, so the icon badge starts again from zero
+#[derive(Deserialize)]
+struct ClearBadgeRequest {
+    did: String,
+    device_token: String,
+}
+
+// Chosen by the app, so each client can group notifications its own way
+#[derive(Deserialize)]
+struct DeviceGroupingRequest {
+    did: String,
+    device_token: String,
+    grouping: String,
+}
+
+// The highest payload version the app understands, see payload_keys
+#[derive(Deserialize)]
+struct DevicePayloadVersionRequest {
+    did: String,
+    device_token: String,
+    version: i16,
+    // Include where the event came from (relay sequence, commit rev), for debugging
+    #[serde(default)]
+    event_origin: bool,
+}
+
+#[derive(Serialize)]
+struct DevicePayloadVersionResponse {
+    // The version this device will be sent
+    version: i16,
+    event_origin: bool,
+}
+
+// A sample push through the real APNs path, for checking token registration and
+// deep links from the app
+#[derive(Deserialize)]
+struct TestNotificationRequest {
+    did: String,
+    device_token: String,
+    // Defaults to a mention
+    #[serde(default)]
+    notification_type: Option<String>,
+    // Deep link to send; defaults to the user's own profile
+    #[serde(default)]
+    uri: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TestNotificationResponse {
+    notification_id: String,
+    // DeliveryOutcome::as_str
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ReportNotificationRequest {
+    did: String,
+    device_token: String,
+    notification_id: uuid::Uuid,
+    reason_type: String,
+    reason: Option<String>,
+}
+
+// Opt in to AppView verification; the app password is only used to read notifications
+#[derive(Deserialize)]
+struct VerificationConsentRequest {
+    did: String,
+    device_token: String,
+    app_password: String,
+}
+
+// Re-import the preferences set in the official app; needs a session token
+#[derive(Deserialize)]
+struct SyncPreferencesRequest {
+    did: String,
+    device_token: String,
+}
+
+#[derive(Deserialize)]
+struct ApplyPresetRequest {
+    did: String,
+    device_token: String,
+    preset: String,
+}
+
+#[derive(Serialize)]
+struct ApplyPresetResponse {
+    preset: String,
+    version: i32,
+    devices: usize,
+    preferences: HashMap<String, bool>,
+}
+
+#[derive(Deserialize)]
+struct RevokeVerificationRequest {
+    did: String,
+    device_token: String,
+}
+
+// Connects the user's chat account so direct messages are pushed; the app password
+// needs direct message access
+#[derive(Deserialize)]
+struct DmAccessRequest {
+    did: String,
+    device_token: String,
+    app_password: String,
+}
+
+#[derive(Deserialize)]
+struct RevokeDmAccessRequest {
+    did: String,
+    device_token: String,
+}
+
+#[derive(Deserialize)]
+struct EraseDataRequest {
+    did: String,
+    device_token: String,
+}
+
+// In-app notification center; `cursor` is the opaque value from the previous page
+#[derive(Deserialize)]
+struct NotificationsQuery {
+    did: String,
+    device_token: String,
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct DeliveriesQuery {
+    did: String,
+    device_token: String,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct NotificationsResponse {
+    // History entries, masked by `fields`
+    notifications: Vec<serde_json::Value>,
+    cursor: Option<String>,
+}
+
+// Fields of a history entry a client can ask for, and their short names
+const HISTORY_FIELDS: &[&str] = &[
+    "notification_id",
+    "notification_type",
+    "title",
+    "body",
+    "uri",
+    "status",
+    "created_at",
+];
+const HISTORY_FIELD_ALIASES: &[(&str, &str)] = &[
+    ("id", "notification_id"),
+    ("type", "notification_type"),
+    ("ts", "created_at"),
+];
+
 // API state
 pub struct ApiState {
     pub db_pool: Pool<Postgres>,
     pub relationship_manager: Arc<RelationshipManager>,
+    pub admin_api_token: Option<String>,
+    pub internal_api_token: Option<String>,
+    pub post_resolver: Arc<crate::post_resolver::PostResolver>,
+    pub did_resolver: Arc<crate::did_resolver::DidResolver>,
+    pub reporter: Arc<crate::reporting::Reporter>,
+    // Where the user's server-side notification preferences are read from
+    pub appview_service_did: String,
+    // Set when registrations must carry service auth addressed to this DID
+    pub service_did: Option<String>,
+    pub feature_flags: Arc<crate::feature_flags::FeatureFlags>,
+    pub experiments: Arc<crate::experiments::Experiments>,
+    pub apns_client: Arc<crate::apns::ApnsClient>,
+    pub apns_topic_validation: bool,
+    pub device_health: Arc<crate::device_health::DeviceHealth>,
+    pub notification_sender: crate::channel::PipelineSender<crate::models::NotificationPayload>,
+    pub multi_tenant: bool,
+    pub registration_mode: crate::registration::RegistrationMode,
+    pub default_preferences: HashMap<String, bool>,
+    pub quota: Arc<crate::quota::QuotaTracker>,
+    // Set when new registrations must prove possession of the token
+    pub device_verification_window_secs: Option<i64>,
+    pub rate_limiter: Arc<crate::rate_limit::RateLimiter>,
+    pub maintenance: Arc<crate::maintenance::Maintenance>,
+    pub user_trace: crate::user_trace::UserTrace,
+    pub backfills: crate::backfill::Backfills,
+    pub started_at: time::OffsetDateTime,
 }
 
 // Add error handler function for timeouts
@@ -76,11 +465,63 @@ async fn handle_timeout_error(error: BoxError) -> (StatusCode, String) {
 pub fn create_api_router(state: Arc<ApiState>) -> Router {
     Router::new()
         .route("/register", post(register_device))
+        .route("/register", delete(unregister_device))
+        .route("/register/confirm", post(confirm_registration))
         .route("/preferences", get(get_preferences))
         .route("/preferences", put(update_preferences))
+        .route("/preferences/thresholds", get(get_thresholds))
+        .route("/preferences/thresholds", put(update_thresholds))
+        .route("/preferences/labels", get(get_label_preferences))
+        .route("/preferences/labels", put(update_label_preferences))
+        .route("/preferences/feeds", get(get_feed_subscriptions))
+        .route("/preferences/feeds", put(update_feed_subscriptions))
+        .route("/preferences/export", get(export_settings))
+        .route("/preferences/export", post(import_settings))
+        .route("/preferences/sync", post(sync_server_preferences))
+        .route("/preferences/presets", get(list_presets))
+        .route("/preferences/preset", post(apply_preset))
+        .route("/preferences/quiet-hours", get(get_quiet_hours))
+        .route("/preferences/quiet-hours", put(update_quiet_hours))
+        .route("/preferences/atom-feed", get(get_atom_feed))
+        .route("/preferences/atom-feed", put(update_atom_feed))
+        .route("/preferences/atom-feed", delete(disable_atom_feed))
+        .route("/preferences/atom-feed/rotate", post(rotate_atom_feed))
+        .route("/relationships", put(update_relationships))
+        .route("/relationships/muted-words", get(get_muted_words))
+        .route("/relationships/muted-words", put(update_muted_words))
+        .route("/vips", get(get_vips))
+        .route("/vips", put(update_vips))
+        .route("/subscriptions/posts", get(get_post_subscriptions))
+        .route("/subscriptions/posts", put(update_post_subscription))
+        .route("/notifications", get(list_notifications))
+        .route("/notifications/deliveries", get(list_deliveries))
+        .route("/notifications/opened", post(notification_opened))
+        .route("/notifications/seen", post(notifications_seen))
+        .route("/badge/clear", post(clear_badge))
+        .route("/device/grouping", put(update_device_grouping))
+        .route("/device/payload-version", put(update_device_payload_version))
+        .route("/report", post(report_notification))
+        .route("/test-notification", post(send_test_notification))
+        .route("/verification", put(grant_verification_consent))
+        .route("/verification", delete(revoke_verification_consent))
+        .route("/dms", put(grant_dm_access))
+        .route("/dms", delete(revoke_dm_access))
+        .route("/privacy/export", get(export_privacy_data))
+        .route("/privacy/data", delete(erase_privacy_data))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            crate::tenant::resolve_tenant,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            crate::rate_limit::enforce_rate_limits,
+        ))
         .route("/health", get(health_check))
+        // Read by feed readers, which authenticate with the secret in the URL
+        .route("/feeds/:file", get(atom_feed))
         .route("/metrics", get(metrics_endpoint))
-        .route("/relationships", put(update_relationships))
+        .nest("/admin", crate::admin::create_admin_router(state.clone()))
+        .nest("/internal", crate::internal::create_internal_router(state.clone()))
         .with_state(state)
         // Properly structure middleware stack
         .layer(
@@ -97,6 +538,7 @@ pub fn create_api_router(state: Arc<ApiState>) -> Router {
 // Handler for the new relationships endpoint
 async fn update_relationships(
     State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
     Json(req): Json<RelationshipsRequest>,
 ) -> impl IntoResponse {
     info!(
@@ -120,11 +562,12 @@ async fn update_relationships(
 
     match state
         .relationship_manager
-        .update_relationships_batch(&req.did, &req.device_token, req.mutes, req.blocks)
+        .update_relationships_batch(&tenant, &req.did, &req.device_token, req.mutes, req.blocks)
         .await
     {
         Ok(_) => {
             info!("Successfully updated relationships for DID: {}", req.did);
+            publish_settings_change(&state, &req.did).await;
             StatusCode::OK.into_response()
         }
         Err(e) => {
@@ -148,15 +591,135 @@ async fn update_relationships(
     }
 }
 
+// Tell every replica to drop cached state for this DID. Best effort: the filter's
+// periodic refresh still picks the change up if the notification is lost.
+async fn publish_settings_change(state: &ApiState, did: &str) {
+    if let Err(e) = crate::cache_sync::publish(&state.db_pool, did).await {
+        warn!(did = %did, "Failed to publish cache invalidation: {}", e);
+    }
+}
+
+// A device or post subscription came or went, which the filter's registered-user and
+// subscriber sets also need to see
+async fn publish_registration_change(state: &ApiState, did: &str) {
+    if let Err(e) = crate::cache_sync::publish_scoped(&state.db_pool, crate::cache_sync::Scope::Registration, did).await {
+        warn!(did = %did, "Failed to publish cache invalidation: {}", e);
+    }
+}
+
+// Read the follows a user made before the firehose started recording them, once the
+// "only people I follow" or "priority from mutuals" preference is on
+fn import_follows(state: &Arc<ApiState>, did: &str) {
+    crate::follows::spawn_import(state.did_resolver.clone(), state.relationship_manager.clone(), did.to_string());
+}
+
+// Load a newly registered DID's relationships and handle in the background, so its
+// first notifications aren't filtered against cold caches. Preferences are read from
+// the database for each event and need no warming.
+fn warm_caches(state: &Arc<ApiState>, did: &str) {
+    let state = state.clone();
+    let did = did.to_string();
+    tokio::spawn(async move {
+        let timer = std::time::Instant::now();
+        if let Err(e) = state.relationship_manager.warm(&did).await {
+            warn!(did = %did, "Failed to warm relationship caches: {}", e);
+        }
+        if let Err(e) = state.did_resolver.get_handle(&did).await {
+            warn!(did = %did, "Failed to resolve handle while warming caches: {}", e);
+        }
+        crate::metrics::CACHE_WARM_TIME.observe(timer.elapsed().as_secs_f64());
+    });
+}
+
+// With APNS_TOPIC_VALIDATION set, check in the background that a token registered
+// without a verification push belongs to our topic, quarantining it if not
+fn validate_topic(state: &Arc<ApiState>, device_token: &str) {
+    if !state.apns_topic_validation {
+        return;
+    }
+
+    let state = state.clone();
+    let device_token = device_token.to_string();
+    tokio::spawn(async move {
+        match state.apns_client.check_topic(&device_token).await {
+            Ok(true) => state.device_health.record_success(&device_token).await,
+            Ok(false) => state.device_health.record_topic_mismatch(&device_token).await,
+            Err(e) => debug!("Could not check device token topic: {}", e),
+        }
+    });
+}
+
+fn bearer_token(headers: &axum::http::HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+// The user's own session, for calls made on their behalf, always comes in its own
+// header; Authorization is reserved for service auth
+const SESSION_TOKEN_HEADER: &str = "x-session-token";
+
+fn session_token(headers: &axum::http::HeaderMap) -> Option<&str> {
+    headers.get(SESSION_TOKEN_HEADER).and_then(|v| v.to_str().ok())
+}
+
+// Best effort: without a session, or if the AppView can't be reached, our own
+// defaults apply
+async fn fetch_server_preferences(
+    state: &ApiState,
+    did: &str,
+    headers: &axum::http::HeaderMap,
+) -> crate::server_preferences::ServerPreferences {
+    let Some(session_token) = session_token(headers) else {
+        return Default::default();
+    };
+    match crate::server_preferences::fetch(&state.did_resolver, &state.appview_service_did, did, session_token).await {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            warn!(did = %did, "Could not fetch server notification preferences: {}", e);
+            Default::default()
+        }
+    }
+}
+
 // API handlers
 async fn register_device(
     axum::extract::State(state): axum::extract::State<Arc<ApiState>>,
-    Json(req): Json<RegisterRequest>,
+    Extension(tenant): Extension<Tenant>,
+    headers: axum::http::HeaderMap,
+    Json(mut req): Json<RegisterRequest>,
 ) -> axum::response::Response {
     tracing::info!("Registering device for DID: {}", req.did);
 
-    // Start a transaction to prevent race conditions
-    let mut tx = match state.db_pool.begin().await {
+    // Store tokens in canonical form so re-registrations don't create duplicates
+    req.device_token = crate::db::normalize_device_token(&req.device_token);
+
+    // The user's PDS vouches for the DID, so nobody can register devices for someone else
+    if let Some(service_did) = &state.service_did {
+        let Some(token) = bearer_token(&headers) else {
+            return StatusCode::UNAUTHORIZED.into_response();
+        };
+        if let Err(e) = crate::service_auth::verify(
+            &state.did_resolver,
+            token,
+            &req.did,
+            service_did,
+            crate::service_auth::REGISTER_PUSH_LXM,
+        )
+        .await
+        {
+            warn!("Rejected service auth for DID {}: {}", req.did, e);
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    // Seeds a new device's preferences; fetched before the transaction so the row
+    // lock isn't held across a network call
+    let server_preferences = fetch_server_preferences(&state, &req.did, &headers).await;
+
+    // Start a transaction to prevent race conditions; new rows land in the caller's tenant
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
         Ok(tx) => tx,
         Err(e) => {
             tracing::error!("Error starting transaction: {}", e);
@@ -167,11 +730,27 @@ async fn register_device(
         }
     };
 
+    // Invite codes are redeemed in the transaction, so a failed registration doesn't
+    // use one up
+    match crate::registration::admit(&mut tx, state.registration_mode, &req.did, req.invite_code.as_deref()).await {
+        Ok(None) => {}
+        Ok(Some(reason)) => {
+            let _ = tx.rollback().await;
+            tracing::info!("Rejected registration for DID {}: {}", req.did, reason);
+            return (StatusCode::FORBIDDEN, reason).into_response();
+        }
+        Err(e) => {
+            let _ = tx.rollback().await;
+            tracing::error!("Error checking registration access: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
     // Check for existing token within the transaction
     let existing_token = sqlx::query_as!(
         UserDevice,
         r#"
-        SELECT id, did, device_token, created_at, updated_at
+        SELECT id, did, device_token, created_at, updated_at, tenant_id
         FROM user_devices
         WHERE device_token = $1
         FOR UPDATE
@@ -183,14 +762,75 @@ async fn register_device(
 
     match existing_token {
         Ok(Some(device)) => {
+            // Re-registering a soft-deleted token restores it with its preferences intact.
+            // A token claimed by another DID is restored only once the claim is confirmed.
+            let verifying = state.device_verification_window_secs.is_some();
+            match sqlx::query!(
+                r#"
+                UPDATE user_devices
+                SET deleted_at = NULL, deleted_reason = NULL, updated_at = NOW()
+                WHERE id = $1 AND deleted_at IS NOT NULL AND (did = $2 OR NOT $3)
+                "#,
+                device.id,
+                req.did,
+                verifying
+            )
+            .execute(&mut *tx)
+            .await
+            {
+                Ok(result) if result.rows_affected() > 0 => {
+                    tracing::info!("Restored soft-deleted device registration");
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    let _ = tx.rollback().await;
+                    tracing::error!("Error restoring device: {}", e);
+                    return axum::response::Response::builder()
+                        .status(500)
+                        .body(axum::body::Body::from(format!("Database error: {}", e)))
+                        .unwrap();
+                }
+            }
+
             if device.did == req.did {
+                // Re-send the nonce if this device never finished verification
+                let nonce = match begin_verification(&state, &mut tx, device.id, false).await {
+                    Ok(nonce) => nonce,
+                    Err(e) => {
+                        let _ = tx.rollback().await;
+                        tracing::error!("Error starting device verification: {}", e);
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    }
+                };
+
                 // Device already registered with this DID - return success
                 let _ = tx.commit().await;
+                publish_registration_change(&state, &req.did).await;
                 tracing::info!("Device already registered with same DID");
+                if let Some(nonce) = nonce {
+                    return verification_response(&state, &req.device_token, &nonce).await;
+                }
+                validate_topic(&state, &req.device_token);
                 return axum::response::Response::builder()
                     .status(200)
                     .body(axum::body::Body::empty())
                     .unwrap();
+            } else if verifying {
+                // The token stays with its current DID until the new one proves possession
+                let nonce = match begin_transfer(&state, &mut tx, device.id, &req.did).await {
+                    Ok(nonce) => nonce,
+                    Err(e) => {
+                        let _ = tx.rollback().await;
+                        tracing::error!("Error starting device verification: {}", e);
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                    }
+                };
+                if let Err(e) = tx.commit().await {
+                    tracing::error!("Error committing transaction: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                }
+                tracing::info!("Device claimed by DID {}, pending verification", req.did);
+                return verification_response(&state, &req.device_token, &nonce).await;
             } else {
                 // Update the DID
                 tracing::info!("Updating device from DID {} to {}", device.did, req.did);
@@ -217,7 +857,11 @@ async fn register_device(
                                 .unwrap();
                         }
 
+                        publish_registration_change(&state, &device.did).await;
+                        publish_settings_change(&state, &req.did).await;
+                        warm_caches(&state, &req.did);
                         tracing::info!("Device token updated successfully");
+                        validate_topic(&state, &req.device_token);
                         return axum::response::Response::builder()
                             .status(200)
                             .body(axum::body::Body::empty())
@@ -251,18 +895,30 @@ async fn register_device(
 
             match result {
                 Ok(row) => {
-                    // Create default preferences
-                    match sqlx::query!(
-                        r#"
-                        INSERT INTO notification_preferences (user_id)
-                        VALUES ($1)
-                        "#,
+                    // Create default preferences: the deployment's, with the server-side ones laid over them
+                    let preferences =
+                        crate::default_preferences::merge(&state.default_preferences, &server_preferences);
+                    let inserted = match sqlx::query!(
+                        "INSERT INTO notification_preferences (user_id) VALUES ($1)",
                         row.id
                     )
                     .execute(&mut *tx)
                     .await
                     {
+                        Ok(_) => crate::default_preferences::apply(&mut tx, row.id, &preferences).await,
+                        Err(e) => Err(e.into()),
+                    };
+                    match inserted {
                         Ok(_) => {
+                            let nonce = match begin_verification(&state, &mut tx, row.id, true).await {
+                                Ok(nonce) => nonce,
+                                Err(e) => {
+                                    let _ = tx.rollback().await;
+                                    tracing::error!("Error starting device verification: {}", e);
+                                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                                }
+                            };
+
                             // Commit transaction
                             if let Err(e) = tx.commit().await {
                                 tracing::error!("Error committing transaction: {}", e);
@@ -272,7 +928,18 @@ async fn register_device(
                                     .unwrap();
                             }
 
+                            publish_registration_change(&state, &req.did).await;
+                            warm_caches(&state, &req.did);
+                            if preferences.get("only_from_follows") == Some(&true)
+                                || preferences.get("priority_from_mutuals") == Some(&true)
+                            {
+                                import_follows(&state, &req.did);
+                            }
                             tracing::info!("Device registered successfully");
+                            if let Some(nonce) = nonce {
+                                return verification_response(&state, &req.device_token, &nonce).await;
+                            }
+                            validate_topic(&state, &req.device_token);
                             return axum::response::Response::builder()
                                 .status(201)
                                 .body(axum::body::Body::empty())
@@ -308,23 +975,197 @@ async fn register_device(
         }
     }
 }
-async fn get_preferences(
-    axum::extract::State(state): axum::extract::State<Arc<ApiState>>,
-    Query(query): Query<PreferencesQuery>,
-) -> Result<Json<PreferencesRequest>, axum::http::StatusCode> {
-    // Find user devices
-    let device = sqlx::query_as!(
-        UserDevice,
+// When device verification is enabled, park the device as unverified with a fresh
+// nonce. With `force` false only devices that are still unverified get a new nonce.
+async fn begin_verification(
+    state: &ApiState,
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    device_id: uuid::Uuid,
+    force: bool,
+) -> Result<Option<String>, sqlx::Error> {
+    let Some(window_secs) = state.device_verification_window_secs else {
+        return Ok(None);
+    };
+
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    let result = sqlx::query!(
         r#"
-        SELECT id, did, device_token, created_at, updated_at
-        FROM user_devices
-        WHERE did = $1
-        LIMIT 1
+        UPDATE user_devices
+        SET verified_at = NULL,
+            verification_nonce = $2,
+            verification_expires_at = NOW() + INTERVAL '1 second' * $3
+        WHERE id = $1 AND ($4 OR verified_at IS NULL)
         "#,
-        query.did,
+        device_id,
+        nonce,
+        window_secs as f64,
+        force
     )
-    .fetch_optional(&state.db_pool)
-    .await
+    .execute(&mut **tx)
+    .await?;
+
+    Ok((result.rows_affected() > 0).then_some(nonce))
+}
+
+// Record another DID's claim on a registered token with a fresh nonce. The device keeps
+// its current DID, verification and preferences until the claim is confirmed.
+async fn begin_transfer(
+    state: &ApiState,
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    device_id: uuid::Uuid,
+    pending_did: &str,
+) -> Result<String, sqlx::Error> {
+    let window_secs = state.device_verification_window_secs.unwrap_or_default();
+    let nonce = uuid::Uuid::new_v4().simple().to_string();
+    sqlx::query!(
+        r#"
+        UPDATE user_devices
+        SET pending_did = $2,
+            verification_nonce = $3,
+            verification_expires_at = NOW() + INTERVAL '1 second' * $4
+        WHERE id = $1
+        "#,
+        device_id,
+        pending_did,
+        nonce,
+        window_secs as f64
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(nonce)
+}
+
+// Send the verification push; the registration stays pending until confirmed
+async fn verification_response(
+    state: &ApiState,
+    device_token: &str,
+    nonce: &str,
+) -> axum::response::Response {
+    match state.apns_client.send_verification(device_token, nonce).await {
+        Ok(_) => StatusCode::ACCEPTED.into_response(),
+        // A build signed for another bundle; it would never receive anything
+        Err(e) if crate::apns::is_topic_mismatch(&e) => {
+            state.device_health.record_topic_mismatch(device_token).await;
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Device token does not belong to this app's APNs topic",
+            )
+                .into_response()
+        }
+        Err(e) => {
+            warn!("Failed to send verification push: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+async fn confirm_registration(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<ConfirmRegistrationRequest>,
+) -> StatusCode {
+    let device_token = crate::db::normalize_device_token(&req.device_token);
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let confirmed = match crate::db::confirm_device_verification(&mut *tx, &req.did, &device_token, &req.nonce).await {
+        Ok(confirmed) => tx.commit().await.map(|_| confirmed).map_err(Into::into),
+        Err(e) => Err(e),
+    };
+
+    match confirmed {
+        Ok(Some(previous_did)) => {
+            info!("Device verified for DID: {}", req.did);
+            publish_registration_change(&state, &req.did).await;
+            if previous_did != req.did {
+                info!("Device moved from DID {} to {}", previous_did, req.did);
+                publish_registration_change(&state, &previous_did).await;
+                warm_caches(&state, &req.did);
+            }
+            StatusCode::OK
+        }
+        Ok(None) => {
+            warn!("Invalid or expired verification nonce for DID: {}", req.did);
+            StatusCode::UNAUTHORIZED
+        }
+        Err(e) => {
+            error!("Error confirming device verification: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Unregister a device. The row is soft-deleted so an accidental unregistration
+// can be undone by registering the same token again within the retention window.
+async fn unregister_device(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(mut req): Json<RegisterRequest>,
+) -> StatusCode {
+    req.device_token = crate::db::normalize_device_token(&req.device_token);
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    let deleted = match crate::db::soft_delete_device(&mut *tx, &req.did, &req.device_token, "unregistered").await {
+        Ok(deleted) => tx.commit().await.map(|_| deleted).map_err(Into::into),
+        Err(e) => Err(e),
+    };
+
+    match deleted {
+        Ok(true) => {
+            info!("Unregistered device for DID: {}", req.did);
+            publish_registration_change(&state, &req.did).await;
+            StatusCode::OK
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Error unregistering device: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn get_preferences(
+    axum::extract::State(state): axum::extract::State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<PreferencesQuery>,
+    Query(fields): Query<FieldsQuery>,
+) -> Result<Json<serde_json::Value>, axum::http::StatusCode> {
+    let known: Vec<&str> = ["did", "background_types"]
+        .into_iter()
+        .chain(crate::default_preferences::PREFERENCE_COLUMNS.iter().copied())
+        .collect();
+    let mask = FieldMask::parse(fields.fields.as_deref(), &known, &[])
+        .map_err(|_| axum::http::StatusCode::BAD_REQUEST)?;
+
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // Find user devices
+    let device = sqlx::query_as!(
+        UserDevice,
+        r#"
+        SELECT id, did, device_token, created_at, updated_at, tenant_id
+        FROM user_devices
+        WHERE did = $1 AND deleted_at IS NULL
+        LIMIT 1
+        "#,
+        query.did,
+    )
+    .fetch_optional(&mut *tx)
+    .await
     .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let device = device.ok_or(axum::http::StatusCode::NOT_FOUND)?;
@@ -333,17 +1174,20 @@ async fn get_preferences(
     let prefs = sqlx::query_as!(
         NotificationPreference,
         r#"
-        SELECT user_id, mentions, replies, likes, follows, reposts, quotes
+        SELECT user_id, mentions, replies, likes, follows, reposts, quotes, replies_to_replies,
+            priority_from_mutuals, feed_posts, digest_low_priority, dms, dm_redact_body, post_edits,
+            list_additions, background_types, comeback_posts, grouping, custom_notifications,
+            payload_version, payload_event_origin, reply_context, only_from_follows
         FROM notification_preferences
         WHERE user_id = $1
         "#,
         device.id
     )
-    .fetch_one(&state.db_pool)
+    .fetch_one(&mut *tx)
     .await
     .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    Ok(Json(PreferencesRequest {
+    let preferences = PreferencesRequest {
         did: query.did,
         mentions: prefs.mentions,
         replies: prefs.replies,
@@ -351,36 +1195,82 @@ async fn get_preferences(
         follows: prefs.follows,
         reposts: prefs.reposts,
         quotes: prefs.quotes,
-    }))
+        replies_to_replies: prefs.replies_to_replies,
+        priority_from_mutuals: prefs.priority_from_mutuals,
+        feed_posts: prefs.feed_posts,
+        digest_low_priority: prefs.digest_low_priority,
+        dms: prefs.dms,
+        dm_redact_body: prefs.dm_redact_body,
+        post_edits: prefs.post_edits,
+        list_additions: prefs.list_additions,
+        background_types: prefs.background_types,
+        comeback_posts: prefs.comeback_posts,
+        custom_notifications: prefs.custom_notifications,
+        reply_context: prefs.reply_context,
+        only_from_follows: prefs.only_from_follows,
+    };
+    mask.apply(&preferences)
+        .map(Json)
+        .map_err(|_| axum::http::StatusCode::INTERNAL_SERVER_ERROR)
 }
 
 async fn update_preferences(
     axum::extract::State(state): axum::extract::State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
     Json(req): Json<PreferencesRequest>,
 ) -> axum::http::StatusCode {
+    if req.background_types.iter().any(|t| NotificationType::parse(t).is_none()) {
+        return axum::http::StatusCode::BAD_REQUEST;
+    }
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
     // Find ALL user devices for this DID (remove the LIMIT 1)
     let devices = sqlx::query_as!(
         UserDevice,
         r#"
-        SELECT id, did, device_token, created_at, updated_at
+        SELECT id, did, device_token, created_at, updated_at, tenant_id
         FROM user_devices
-        WHERE did = $1
+        WHERE did = $1 AND deleted_at IS NULL
         "#,
         req.did,
     )
-    .fetch_all(&state.db_pool)
+    .fetch_all(&mut *tx)
     .await;
 
-    match devices {
-        Ok(devices) if !devices.is_empty() => {
+    // Whether a preference reading the user's follows is being turned on, so the
+    // follows are imported
+    let was_following_only = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM notification_preferences p
+            JOIN user_devices d ON d.id = p.user_id
+            WHERE d.did = $1 AND d.deleted_at IS NULL AND (p.only_from_follows OR p.priority_from_mutuals)
+        ) AS "exists!"
+        "#,
+        req.did
+    )
+    .fetch_one(&mut *tx)
+    .await;
+
+    match (devices, was_following_only) {
+        (Ok(devices), Ok(was_following_only)) if !devices.is_empty() => {
             // Update preferences for ALL devices associated with this DID
             let mut success = true;
             for device in devices {
                 let result = sqlx::query!(
                     r#"
                     UPDATE notification_preferences
-                    SET mentions = $1, replies = $2, likes = $3, follows = $4, reposts = $5, quotes = $6
-                    WHERE user_id = $7
+                    SET mentions = $1, replies = $2, likes = $3, follows = $4, reposts = $5, quotes = $6,
+                        priority_from_mutuals = $7, replies_to_replies = $8, feed_posts = $9,
+                        digest_low_priority = $10, dms = $11, dm_redact_body = $12, post_edits = $13,
+                        list_additions = $14, background_types = $15, comeback_posts = $16,
+                        custom_notifications = $17, reply_context = $18, only_from_follows = $19,
+                        preset = NULL, preset_version = NULL
+                    WHERE user_id = $20
                     "#,
                     req.mentions,
                     req.replies,
@@ -388,9 +1278,22 @@ async fn update_preferences(
                     req.follows,
                     req.reposts,
                     req.quotes,
+                    req.priority_from_mutuals,
+                    req.replies_to_replies,
+                    req.feed_posts,
+                    req.digest_low_priority,
+                    req.dms,
+                    req.dm_redact_body,
+                    req.post_edits,
+                    req.list_additions,
+                    &req.background_types,
+                    req.comeback_posts,
+                    req.custom_notifications,
+                    req.reply_context,
+                    req.only_from_follows,
                     device.id
                 )
-                .execute(&state.db_pool)
+                .execute(&mut *tx)
                 .await;
                 
                 if result.is_err() {
@@ -398,34 +1301,1782 @@ async fn update_preferences(
                 }
             }
             
-            if success {
+            if success && tx.commit().await.is_ok() {
+                publish_settings_change(&state, &req.did).await;
+                if (req.only_from_follows || req.priority_from_mutuals) && !was_following_only {
+                    import_follows(&state, &req.did);
+                }
                 axum::http::StatusCode::OK
             } else {
                 axum::http::StatusCode::INTERNAL_SERVER_ERROR
             }
         },
-        Ok(_) => axum::http::StatusCode::NOT_FOUND,
-        Err(_) => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+        (Ok(_), Ok(_)) => axum::http::StatusCode::NOT_FOUND,
+        _ => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
     }
 }
 
-// Add health check handler
-async fn health_check(State(state): State<Arc<ApiState>>) -> impl IntoResponse {
-    // Check DB connection
-    match sqlx::query("SELECT 1").fetch_one(&state.db_pool).await {
-        Ok(_) => (StatusCode::OK, "Healthy"),
+// Quiet hours are per device, since each one has its own timezone
+async fn get_quiet_hours(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<DeviceQuery>,
+) -> Result<Json<crate::quiet_hours::QuietHours>, StatusCode> {
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let device = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized quiet hours request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let quiet_hours = crate::quiet_hours::get(&mut *tx, device.id).await.map_err(|e| {
+        error!("Error loading quiet hours: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(quiet_hours))
+}
+
+async fn update_quiet_hours(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<QuietHoursRequest>,
+) -> StatusCode {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    let device = match state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        Ok(device) => device,
         Err(e) => {
-            error!("Health check failed: {}", e);
-            (StatusCode::SERVICE_UNAVAILABLE, "Unhealthy: Database issue")
+            warn!("Unauthorized quiet hours update for DID {}: {}", req.did, e);
+            return StatusCode::UNAUTHORIZED;
+        }
+    };
+
+    // Both ends or neither; clearing them turns quiet hours off
+    let quiet_hours = req.quiet_hours;
+    let (start, end) = match (quiet_hours.start.as_deref(), quiet_hours.end.as_deref()) {
+        (Some(start), Some(end)) => match (
+            crate::quiet_hours::parse_time(start),
+            crate::quiet_hours::parse_time(end),
+        ) {
+            (Some(start), Some(end)) => (Some(start), Some(end)),
+            _ => return StatusCode::BAD_REQUEST,
+        },
+        (None, None) => (None, None),
+        _ => return StatusCode::BAD_REQUEST,
+    };
+
+    match crate::quiet_hours::is_known_timezone(&mut *tx, &quiet_hours.timezone).await {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::BAD_REQUEST,
+        Err(e) => {
+            error!("Error validating timezone: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
         }
     }
-}
 
-// Add metrics endpoint handler
-async fn metrics_endpoint() -> impl IntoResponse {
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "text/plain")],
-        crate::metrics::metrics_handler(),
+    let result = crate::quiet_hours::set(
+        &mut *tx,
+        device.id,
+        start,
+        end,
+        &quiet_hours.timezone,
+        quiet_hours.mode,
     )
+    .await;
+
+    match result {
+        Ok(()) if tx.commit().await.is_ok() => StatusCode::OK,
+        Ok(()) => StatusCode::INTERNAL_SERVER_ERROR,
+        Err(e) => {
+            error!("Error updating quiet hours: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn get_vips(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<DeviceQuery>,
+) -> Result<Json<VipsResponse>, StatusCode> {
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized VIP list request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let vips = RelationshipManager::load_vips(&mut *tx, &query.did).await.map_err(|e| {
+        error!("Error loading VIPs: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut vips: Vec<String> = vips.into_iter().collect();
+    vips.sort();
+    Ok(Json(VipsResponse { vips }))
+}
+
+async fn update_vips(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<VipsRequest>,
+) -> StatusCode {
+    if req.vips.len() > MAX_VIPS || req.vips.iter().any(|vip| !vip.starts_with("did:")) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized VIP list update for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    if let Err(e) = state.relationship_manager.set_vips(&mut tx, &req.did, &req.vips).await {
+        error!("Error updating VIPs: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            publish_settings_change(&state, &req.did).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn get_atom_feed(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<DeviceQuery>,
+) -> Result<Json<crate::atom_feed::FeedSettings>, StatusCode> {
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized Atom feed request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    match crate::atom_feed::settings(&mut tx, &query.did).await {
+        Ok(Some(settings)) => Ok(Json(settings)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Error loading Atom feed settings: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn update_atom_feed(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<AtomFeedRequest>,
+) -> Result<Json<AtomFeedResponse>, StatusCode> {
+    if req
+        .notification_types
+        .iter()
+        .flatten()
+        .any(|notification_type| NotificationType::parse(notification_type).is_none())
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized Atom feed update for DID {}: {}", req.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let token = crate::atom_feed::enable(&mut tx, &req.did, req.notification_types.as_deref())
+        .await
+        .map_err(|e| {
+            error!("Error updating Atom feed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tx.commit().await.map_err(|e| {
+        error!("Error committing transaction: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(AtomFeedResponse {
+        path: token.as_deref().map(crate::atom_feed::feed_path),
+    }))
+}
+
+// Issue a new secret URL; the old one stops working
+async fn rotate_atom_feed(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<AtomFeedDeviceRequest>,
+) -> Result<Json<AtomFeedResponse>, StatusCode> {
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized Atom feed rotation for DID {}: {}", req.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let token = crate::atom_feed::rotate(&mut tx, &req.did)
+        .await
+        .map_err(|e| {
+            error!("Error rotating Atom feed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    tx.commit().await.map_err(|e| {
+        error!("Error committing transaction: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(AtomFeedResponse {
+        path: Some(crate::atom_feed::feed_path(&token)),
+    }))
+}
+
+async fn disable_atom_feed(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<AtomFeedDeviceRequest>,
+) -> StatusCode {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized Atom feed removal for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let disabled = match crate::atom_feed::disable(&mut tx, &req.did).await {
+        Ok(disabled) => disabled,
+        Err(e) => {
+            error!("Error removing Atom feed: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    match tx.commit().await {
+        Ok(_) if disabled => StatusCode::OK,
+        Ok(_) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// GET /feeds/{token}.atom
+async fn atom_feed(State(state): State<Arc<ApiState>>, Path(file): Path<String>) -> axum::response::Response {
+    let Some(token) = file.strip_suffix(".atom") else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match crate::atom_feed::render(&state.db_pool, token).await {
+        Ok(Some(feed)) => ([(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")], feed).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Error rendering Atom feed: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn get_muted_words(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<DeviceQuery>,
+) -> Result<Json<MutedWordsResponse>, StatusCode> {
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized muted words request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let muted_words = RelationshipManager::load_muted_words(&mut *tx, &query.did).await.map_err(|e| {
+        error!("Error loading muted words: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(MutedWordsResponse { muted_words }))
+}
+
+async fn update_muted_words(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<MutedWordsRequest>,
+) -> impl IntoResponse {
+    let muted_words = match crate::muted_words::sanitize(req.muted_words) {
+        Ok(muted_words) => muted_words,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized muted words update for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    if let Err(e) = state
+        .relationship_manager
+        .set_muted_words(&mut tx, &req.did, &muted_words)
+        .await
+    {
+        error!("Error updating muted words: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            publish_settings_change(&state, &req.did).await;
+            StatusCode::OK.into_response()
+        }
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn get_post_subscriptions(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<DeviceQuery>,
+) -> Result<Json<PostSubscriptionsResponse>, StatusCode> {
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized post subscriptions request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let subjects = crate::post_subscriptions::get(&mut *tx, &query.did).await.map_err(|e| {
+        error!("Error loading post subscriptions: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(PostSubscriptionsResponse { subjects }))
+}
+
+async fn update_post_subscription(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<PostSubscriptionRequest>,
+) -> StatusCode {
+    if !req.subject.starts_with("did:") || req.subject == req.did {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized post subscription update for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    if req.subscribed {
+        match crate::post_subscriptions::get(&mut *tx, &req.did).await {
+            Ok(subjects)
+                if subjects.len() >= crate::post_subscriptions::MAX_SUBSCRIPTIONS
+                    && !subjects.contains(&req.subject) =>
+            {
+                return StatusCode::BAD_REQUEST;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error loading post subscriptions: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        }
+    }
+
+    if let Err(e) = crate::post_subscriptions::set(&mut *tx, &req.did, &req.subject, req.subscribed).await {
+        error!("Error updating post subscription: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            // The filter reloads its subscription map on the next event
+            publish_registration_change(&state, &req.did).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn get_thresholds(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<DeviceQuery>,
+) -> Result<Json<ThresholdsResponse>, StatusCode> {
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let device = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized thresholds request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let thresholds = crate::db::get_notification_thresholds(&mut *tx, device.id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(ThresholdsResponse {
+        did: query.did,
+        thresholds: thresholds
+            .into_iter()
+            .map(|t| ThresholdEntry {
+                notification_type: t.notification_type,
+                min_account_age_days: t.min_account_age_days,
+                min_followers: t.min_followers,
+            })
+            .collect(),
+    }))
+}
+
+async fn update_thresholds(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<ThresholdsRequest>,
+) -> StatusCode {
+    // Reject unknown types and negative values up front
+    for entry in &req.thresholds {
+        if NotificationType::parse(&entry.notification_type).is_none()
+            || entry.min_account_age_days.is_some_and(|v| v < 0)
+            || entry.min_followers.is_some_and(|v| v < 0)
+        {
+            return StatusCode::BAD_REQUEST;
+        }
+    }
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized thresholds update for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let devices = match crate::db::get_user_devices(&mut *tx, &req.did).await {
+        Ok(devices) => devices,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    // Replace thresholds for ALL devices associated with this DID
+    for device in &devices {
+        if let Err(e) = sqlx::query!(
+            "DELETE FROM notification_thresholds WHERE user_id = $1",
+            device.id
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            error!("Error clearing thresholds: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+
+        for entry in &req.thresholds {
+            if let Err(e) = sqlx::query!(
+                r#"
+                INSERT INTO notification_thresholds (user_id, notification_type, min_account_age_days, min_followers)
+                VALUES ($1, $2, $3, $4)
+                "#,
+                device.id,
+                entry.notification_type,
+                entry.min_account_age_days,
+                entry.min_followers
+            )
+            .execute(&mut *tx)
+            .await
+            {
+                error!("Error saving thresholds: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            publish_settings_change(&state, &req.did).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn get_label_preferences(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<DeviceQuery>,
+) -> Result<Json<LabelPreferencesResponse>, StatusCode> {
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let device = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized label preferences request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let labels = crate::db::get_label_preferences(&mut *tx, device.id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(LabelPreferencesResponse {
+        did: query.did,
+        labels,
+    }))
+}
+
+async fn update_label_preferences(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<LabelPreferencesRequest>,
+) -> StatusCode {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized label preferences update for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let devices = match crate::db::get_user_devices(&mut *tx, &req.did).await {
+        Ok(devices) => devices,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    // Replace label preferences for ALL devices associated with this DID
+    for device in &devices {
+        if let Err(e) = sqlx::query!("DELETE FROM label_preferences WHERE user_id = $1", device.id)
+            .execute(&mut *tx)
+            .await
+        {
+            error!("Error clearing label preferences: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+
+        for (label, visibility) in &req.labels {
+            if let Err(e) = sqlx::query!(
+                "INSERT INTO label_preferences (user_id, label, visibility) VALUES ($1, $2, $3)",
+                device.id,
+                label,
+                visibility.as_str()
+            )
+            .execute(&mut *tx)
+            .await
+            {
+                error!("Error saving label preferences: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            publish_settings_change(&state, &req.did).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn get_feed_subscriptions(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<DeviceQuery>,
+) -> Result<Json<FeedSubscriptionsResponse>, StatusCode> {
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let device = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized feed subscriptions request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let feeds = crate::db::get_feed_subscriptions(&mut *tx, device.id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(FeedSubscriptionsResponse {
+        did: query.did,
+        feeds,
+    }))
+}
+
+async fn update_feed_subscriptions(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<FeedSubscriptionsRequest>,
+) -> StatusCode {
+    if req.feeds.len() > crate::feeds::MAX_SUBSCRIPTIONS
+        || req.feeds.iter().any(|feed| !crate::feeds::is_feed_uri(&feed.feed_uri))
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized feed subscriptions update for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let devices = match crate::db::get_user_devices(&mut *tx, &req.did).await {
+        Ok(devices) => devices,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    // Replace subscriptions for ALL devices associated with this DID
+    for device in &devices {
+        if let Err(e) = sqlx::query!("DELETE FROM feed_subscriptions WHERE user_id = $1", device.id)
+            .execute(&mut *tx)
+            .await
+        {
+            error!("Error clearing feed subscriptions: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+
+        for feed in &req.feeds {
+            if let Err(e) = sqlx::query!(
+                r#"
+                INSERT INTO feed_subscriptions (user_id, feed_uri, digest)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (user_id, feed_uri) DO UPDATE SET digest = EXCLUDED.digest
+                "#,
+                device.id,
+                feed.feed_uri,
+                feed.digest
+            )
+            .execute(&mut *tx)
+            .await
+            {
+                error!("Error saving feed subscriptions: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            publish_settings_change(&state, &req.did).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn list_notifications(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<NotificationsQuery>,
+    Query(fields): Query<FieldsQuery>,
+) -> Result<Json<NotificationsResponse>, StatusCode> {
+    let mask = FieldMask::parse(fields.fields.as_deref(), HISTORY_FIELDS, HISTORY_FIELD_ALIASES)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let before_id = match query.cursor.as_deref() {
+        Some(cursor) => Some(cursor.parse::<i64>().map_err(|_| StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let device = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized notification history request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let notifications = crate::db::get_notification_history(
+        &mut *tx,
+        &query.did,
+        &device.device_token,
+        before_id,
+        limit,
+    )
+    .await
+    .map_err(|e| {
+        error!("Error loading notification history: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // A short page means there is nothing older
+    let cursor = if notifications.len() as i64 == limit {
+        notifications.last().map(|entry| entry.id.to_string())
+    } else {
+        None
+    };
+
+    let notifications = mask
+        .apply_all(&notifications)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(NotificationsResponse {
+        notifications,
+        cursor,
+    }))
+}
+
+// What APNs answered for this device's recent sends, so the app can show why a push
+// never arrived
+async fn list_deliveries(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Result<Json<Vec<crate::db::DeliveryLogEntry>>, StatusCode> {
+    let limit = query.limit.unwrap_or(50).clamp(1, 100);
+
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let device = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .map_err(|e| {
+            warn!("Unauthorized delivery log request for DID {}: {}", query.did, e);
+            StatusCode::UNAUTHORIZED
+        })?;
+
+    let entries = crate::db::get_delivery_log(&mut *tx, &query.did, Some(&device.device_token), limit)
+        .await
+        .map_err(|e| {
+            error!("Error loading delivery log: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(entries))
+}
+
+async fn sync_server_preferences(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: axum::http::HeaderMap,
+    Json(req): Json<SyncPreferencesRequest>,
+) -> StatusCode {
+    let Some(session_token) = session_token(&headers) else {
+        return StatusCode::UNAUTHORIZED;
+    };
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized preference sync for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let server_preferences = match crate::server_preferences::fetch(
+        &state.did_resolver,
+        &state.appview_service_did,
+        &req.did,
+        session_token,
+    )
+    .await
+    {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            warn!(did = %req.did, "Could not fetch server notification preferences: {}", e);
+            return StatusCode::BAD_GATEWAY;
+        }
+    };
+
+    let devices = match crate::db::get_user_devices(&mut *tx, &req.did).await {
+        Ok(devices) if !devices.is_empty() => devices,
+        Ok(_) => return StatusCode::NOT_FOUND,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    // Apply to ALL devices; types the server didn't report keep their local setting
+    for device in &devices {
+        if let Err(e) = sqlx::query!(
+            r#"
+            UPDATE notification_preferences
+            SET mentions = COALESCE($2, mentions), replies = COALESCE($3, replies),
+                likes = COALESCE($4, likes), follows = COALESCE($5, follows),
+                reposts = COALESCE($6, reposts), quotes = COALESCE($7, quotes),
+                preset = NULL, preset_version = NULL
+            WHERE user_id = $1
+            "#,
+            device.id,
+            server_preferences.mentions,
+            server_preferences.replies,
+            server_preferences.likes,
+            server_preferences.follows,
+            server_preferences.reposts,
+            server_preferences.quotes
+        )
+        .execute(&mut *tx)
+        .await
+        {
+            error!("Error applying server preferences: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => {
+            publish_settings_change(&state, &req.did).await;
+            StatusCode::OK
+        }
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Presets onboarding can offer, with the toggles each one sets
+async fn list_presets(State(state): State<Arc<ApiState>>) -> axum::response::Response {
+    match crate::presets::list(&state.db_pool).await {
+        Ok(presets) => Json(
+            presets
+                .into_iter()
+                .map(|preset| {
+                    serde_json::json!({
+                        "name": preset.name,
+                        "label": preset.label,
+                        "description": preset.description,
+                        "version": preset.version,
+                        "preferences": preset.resolve(),
+                    })
+                })
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(e) => {
+            error!("Error listing preference presets: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Put every device of the DID on a preset in one transaction
+async fn apply_preset(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<ApplyPresetRequest>,
+) -> axum::response::Response {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized preset request for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let preset = match crate::presets::get(&mut tx, &req.preset).await {
+        Ok(Some(preset)) => preset,
+        Ok(None) => return (StatusCode::BAD_REQUEST, "Unknown preset").into_response(),
+        Err(e) => {
+            error!("Error loading preference preset: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let devices = match crate::db::get_user_devices(&mut *tx, &req.did).await {
+        Ok(devices) if !devices.is_empty() => devices,
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    for device in &devices {
+        if let Err(e) = crate::presets::apply(&mut tx, device.id, &preset).await {
+            error!("Error applying preference preset: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    if let Err(e) = tx.commit().await {
+        error!("Error committing transaction: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    publish_settings_change(&state, &req.did).await;
+    info!(did = %req.did, preset = %preset.name, version = preset.version, "Applied preference preset");
+
+    Json(ApplyPresetResponse {
+        preferences: preset.resolve(),
+        preset: preset.name,
+        version: preset.version,
+        devices: devices.len(),
+    })
+    .into_response()
+}
+
+async fn grant_verification_consent(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<VerificationConsentRequest>,
+) -> StatusCode {
+    if req.app_password.is_empty() || req.app_password.len() > 256 {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized verification consent for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let server_secret = match crate::crypto::CryptoUtils::new() {
+        Ok(crypto) => crypto.server_secret,
+        Err(e) => {
+            error!("Error loading server secret: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = sqlx::query!(
+        r#"
+        INSERT INTO verification_consents (user_did, app_password_encrypted)
+        VALUES ($1, pgp_sym_encrypt($2, $3))
+        ON CONFLICT (tenant_id, user_did) DO UPDATE SET app_password_encrypted = EXCLUDED.app_password_encrypted
+        "#,
+        req.did,
+        req.app_password,
+        server_secret
+    )
+    .execute(&mut *tx)
+    .await
+    {
+        error!("Error saving verification consent: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    match tx.commit().await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn revoke_verification_consent(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<RevokeVerificationRequest>,
+) -> StatusCode {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized verification revocation for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    if let Err(e) = sqlx::query!("DELETE FROM verification_consents WHERE user_did = $1", req.did)
+        .execute(&mut *tx)
+        .await
+    {
+        error!("Error deleting verification consent: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    match tx.commit().await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn grant_dm_access(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<DmAccessRequest>,
+) -> StatusCode {
+    if req.app_password.is_empty() || req.app_password.len() > 256 {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized DM access grant for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let server_secret = match crate::crypto::CryptoUtils::new() {
+        Ok(crypto) => crypto.server_secret,
+        Err(e) => {
+            error!("Error loading server secret: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    // Clearing the cursor makes the next poll start from the present
+    if let Err(e) = sqlx::query!(
+        r#"
+        INSERT INTO dm_consents (user_did, app_password_encrypted)
+        VALUES ($1, pgp_sym_encrypt($2, $3))
+        ON CONFLICT (tenant_id, user_did)
+        DO UPDATE SET app_password_encrypted = EXCLUDED.app_password_encrypted, log_cursor = NULL
+        "#,
+        req.did,
+        req.app_password,
+        server_secret
+    )
+    .execute(&mut *tx)
+    .await
+    {
+        error!("Error saving DM access: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    match tx.commit().await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn revoke_dm_access(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<RevokeDmAccessRequest>,
+) -> StatusCode {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized DM access revocation for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    if let Err(e) = sqlx::query!("DELETE FROM dm_consents WHERE user_did = $1", req.did)
+        .execute(&mut *tx)
+        .await
+    {
+        error!("Error deleting DM access: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    match tx.commit().await {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            error!("Error committing transaction: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+// Everything stored for the DID, as JSON by table
+async fn export_privacy_data(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<ExportQuery>,
+) -> axum::response::Response {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+    {
+        warn!("Unauthorized data export for DID {}: {}", query.did, e);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match crate::privacy::export(&mut tx, &query.did).await {
+        Ok(export) => {
+            info!("Exported stored data for DID: {}", query.did);
+            Json(export).into_response()
+        }
+        Err(e) => {
+            error!("Error exporting data for DID {}: {}", query.did, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Delete everything stored for the DID, returning the rows deleted by table. The
+// device is unregistered along with the rest.
+async fn erase_privacy_data(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<EraseDataRequest>,
+) -> axum::response::Response {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized data erasure for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let device_token = crate::db::normalize_device_token(&req.device_token);
+    let deleted = match crate::privacy::erase(&mut tx, &req.did, crate::privacy::ErasedBy::Device(&device_token)).await {
+        Ok(deleted) => deleted,
+        Err(e) => {
+            error!("Error erasing data for DID {}: {}", req.did, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if let Err(e) = tx.commit().await {
+        error!("Error committing transaction: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    state.relationship_manager.invalidate_cache(&req.did).await;
+    info!("Erased stored data for DID: {}", req.did);
+    Json(serde_json::json!({ "deleted": deleted })).into_response()
+}
+
+// Add health check handler
+async fn health_check(State(state): State<Arc<ApiState>>) -> impl IntoResponse {
+    // Check DB connection
+    match sqlx::query("SELECT 1").fetch_one(&state.db_pool).await {
+        Ok(_) => (StatusCode::OK, "Healthy"),
+        Err(e) => {
+            error!("Health check failed: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, "Unhealthy: Database issue")
+        }
+    }
+}
+
+// Add metrics endpoint handler
+async fn metrics_endpoint(State(state): State<Arc<ApiState>>) -> impl IntoResponse {
+    crate::metrics::record_db_pool(&state.db_pool);
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain")],
+        crate::metrics::metrics_handler(),
+    )
+}
+
+async fn notification_opened(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<NotificationOpenedRequest>,
+) -> StatusCode {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized open acknowledgment for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    match crate::db::record_open(&mut tx, req.notification_id, &req.did, &req.device_token).await {
+        Ok(crate::db::OpenOutcome::Opened {
+            notification_type,
+            experiment,
+            variant,
+        }) => {
+            let is_mention = NotificationType::parse(&notification_type).is_some_and(|t| t.is_mention());
+            if let Err(e) = crate::db::decrement_unread_counts(&mut *tx, &req.did, is_mention).await {
+                error!("Failed to update unread counts: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            if let Err(e) = tx.commit().await {
+                error!("Error committing notification open: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            crate::metrics::NOTIFICATIONS_OPENED
+                .with_label_values(&[&notification_type])
+                .inc();
+            if let (Some(experiment), Some(variant)) = (experiment, variant) {
+                crate::metrics::EXPERIMENT_NOTIFICATIONS
+                    .with_label_values(&[&experiment, &variant, "opened"])
+                    .inc();
+            }
+            StatusCode::OK
+        }
+        // Already acknowledged; the app may retry
+        Ok(crate::db::OpenOutcome::AlreadyOpened) => StatusCode::OK,
+        Ok(crate::db::OpenOutcome::Unknown) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Error recording notification open: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn notifications_seen(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<NotificationsSeenRequest>,
+) -> StatusCode {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized seen acknowledgment for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let cleared = match crate::db::clear_unread_counts(&mut *tx, &req.did, req.mentions_only).await {
+        Ok(()) => tx.commit().await.map_err(Into::into),
+        Err(e) => Err(e),
+    };
+
+    match cleared {
+        Ok(()) => StatusCode::OK,
+        Err(e) => {
+            error!("Error clearing unread counts: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn clear_badge(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<ClearBadgeRequest>,
+) -> StatusCode {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Error starting transaction: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized badge clear for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    let cleared = match crate::db::clear_device_unread_counts(&mut *tx, &req.did, &req.device_token).await {
+        Ok(()) => tx.commit().await.map_err(Into::into),
+        Err(e) => Err(e),
+    };
+
+    match cleared {
+        Ok(()) => StatusCode::OK,
+        Err(e) => {
+            error!("Error clearing badge: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn update_device_grouping(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<DeviceGroupingRequest>,
+) -> StatusCode {
+    let Some(grouping) = Grouping::parse(&req.grouping) else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized grouping update for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    match crate::db::set_device_grouping(&mut *tx, &req.did, &req.device_token, grouping).await {
+        Ok(()) if tx.commit().await.is_ok() => StatusCode::OK,
+        Ok(()) => StatusCode::INTERNAL_SERVER_ERROR,
+        Err(e) => {
+            error!("Error updating device grouping: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+async fn update_device_payload_version(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<DevicePayloadVersionRequest>,
+) -> Result<Json<DevicePayloadVersionResponse>, StatusCode> {
+    let version = crate::payload_keys::negotiate(req.version).ok_or(StatusCode::BAD_REQUEST)?;
+
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized payload version update for DID {}: {}", req.did, e);
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    match crate::db::set_device_payload_version(
+        &mut *tx,
+        &req.did,
+        &req.device_token,
+        version,
+        req.event_origin,
+    )
+    .await
+    {
+        Ok(()) if tx.commit().await.is_ok() => Ok(Json(DevicePayloadVersionResponse {
+            version,
+            event_origin: req.event_origin,
+        })),
+        Ok(()) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        Err(e) => {
+            error!("Error updating device payload version: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+async fn send_test_notification(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<TestNotificationRequest>,
+) -> Result<Json<TestNotificationResponse>, StatusCode> {
+    let notification_type = match req.notification_type.as_deref() {
+        Some(value) => NotificationType::parse(value).ok_or(StatusCode::BAD_REQUEST)?,
+        None => NotificationType::Mention,
+    };
+
+    let mut tx = crate::tenant::begin(&state.db_pool, &tenant)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let device = match state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        Ok(device) => device,
+        Err(e) => {
+            warn!("Unauthorized test notification for DID {}: {}", req.did, e);
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    // Shaped like a real push to this device, so grouping and compact keys apply
+    let prefs = crate::db::get_notification_preferences(&mut *tx, device.id)
+        .await
+        .map_err(|e| {
+            error!("Error loading preferences for test notification: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    // Read only, so released before the send rather than committed
+    drop(tx);
+
+    let notification_id = uuid::Uuid::new_v4().to_string();
+    let mut data = HashMap::new();
+    data.insert("notification_id".to_string(), notification_id.clone());
+    data.insert("type".to_string(), format!("{:?}", notification_type));
+    data.insert("uri".to_string(), req.uri.unwrap_or_else(|| format!("at://{}", req.did)));
+    data.insert("author_did".to_string(), req.did.clone());
+    data.insert("test".to_string(), "true".to_string());
+    if prefs.grouping != Grouping::App.as_str() {
+        data.insert("grouping".to_string(), prefs.grouping.clone());
+    }
+    if prefs.payload_version >= crate::payload_keys::COMPACT_VERSION {
+        data.insert(crate::payload_keys::VERSION_KEY.to_string(), prefs.payload_version.to_string());
+    }
+
+    let payload = crate::models::NotificationPayload {
+        user_did: req.did.clone(),
+        device_token: device.device_token.clone(),
+        notification_type,
+        title: "Test notification".to_string(),
+        body: "Push notifications are working on this device".to_string(),
+        data,
+    };
+
+    let outcome = state.apns_client.send_notification(&payload).await;
+    info!(did = %req.did, outcome = outcome.as_str(), "Sent test notification");
+    let reason = match &outcome {
+        crate::apns::DeliveryOutcome::Rejected { reason } => Some(reason.clone()),
+        crate::apns::DeliveryOutcome::Retried { attempts } => Some(format!("still failing after {} attempts", attempts)),
+        crate::apns::DeliveryOutcome::Unavailable { attempts } => {
+            Some(format!("APNs unavailable after {} attempts", attempts))
+        }
+        _ => None,
+    };
+
+    Ok(Json(TestNotificationResponse {
+        notification_id,
+        outcome: outcome.as_str(),
+        reason,
+    }))
+}
+
+async fn export_settings(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(query): Query<ExportQuery>,
+) -> axum::response::Response {
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &query.did, &query.device_token)
+        .await
+        .is_err()
+    {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let signed = crate::portability::export_settings(&mut tx, &query.did)
+        .await
+        .and_then(crate::portability::SignedSettings::sign);
+
+    match signed {
+        Ok(signed) => Json(signed).into_response(),
+        Err(e) => {
+            error!("Error exporting settings: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn import_settings(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<ImportRequest>,
+) -> axum::response::Response {
+    let did = req.settings.document.did.clone();
+
+    match req.settings.verify() {
+        Ok(true) => {}
+        Ok(false) => {
+            warn!("Rejected settings import with invalid signature for DID: {}", did);
+            return (StatusCode::BAD_REQUEST, "Invalid signature").into_response();
+        }
+        Err(e) => {
+            error!("Error verifying settings import: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    // Only the owner of a registered device for this DID may import
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    if state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &did, &req.device_token)
+        .await
+        .is_err()
+    {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    drop(tx);
+
+    let following_only = req.settings.document.preferences.only_from_follows
+        || req.settings.document.preferences.priority_from_mutuals;
+    match crate::portability::import_settings(
+        &state.db_pool,
+        &tenant,
+        &state.relationship_manager,
+        &req.device_token,
+        req.settings.document,
+    )
+    .await
+    {
+        Ok(()) => {
+            info!("Imported settings for DID: {}", did);
+            publish_settings_change(&state, &did).await;
+            if following_only {
+                import_follows(&state, &did);
+            }
+            StatusCode::OK.into_response()
+        }
+        Err(e) => {
+            error!("Error importing settings: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Report the content behind a notification. The service files it with its own
+// account, so the device token is all the client needs to send.
+async fn report_notification(
+    State(state): State<Arc<ApiState>>,
+    Extension(tenant): Extension<Tenant>,
+    Json(req): Json<ReportNotificationRequest>,
+) -> axum::response::Response {
+    if !crate::reporting::is_valid_reason_type(&req.reason_type) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let mut tx = match crate::tenant::begin(&state.db_pool, &tenant).await {
+        Ok(tx) => tx,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if let Err(e) = state
+        .relationship_manager
+        .authenticate_device(&mut *tx, &req.did, &req.device_token)
+        .await
+    {
+        warn!("Unauthorized report for DID {}: {}", req.did, e);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    // Read only; the report goes out without holding it open
+    drop(tx);
+
+    let request = crate::reporting::ReportRequest {
+        tenant_id: &tenant.id,
+        user_did: &req.did,
+        notification_id: req.notification_id,
+        reason_type: &req.reason_type,
+        reason: req.reason.as_deref(),
+    };
+
+    match crate::reporting::forward_report(&state.db_pool, &state.reporter, request).await {
+        Ok(ReportOutcome::Forwarded(result)) => Json(result).into_response(),
+        Ok(ReportOutcome::AlreadyReported) => StatusCode::OK.into_response(),
+        Ok(ReportOutcome::NotFound) => StatusCode::NOT_FOUND.into_response(),
+        Ok(ReportOutcome::NotConfigured) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+        Ok(ReportOutcome::UpstreamFailed) => StatusCode::BAD_GATEWAY.into_response(),
+        Err(e) => {
+            error!("Error forwarding report: {}", e);
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
 }
@@ -1,66 +1,641 @@
-use a2::{Client, DefaultNotificationBuilder, NotificationBuilder, NotificationOptions, Priority};
-use anyhow::{Context, Result};
+use a2::{
+    Client, DefaultNotificationBuilder, InterruptionLevel, NotificationBuilder, NotificationOptions,
+    Payload, Priority, PushType,
+};
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use p256::pkcs8::DecodePrivateKey;
+use serde_json::json;
+use sha2::{Digest, Sha256};
 use sqlx::{Pool, Postgres};
-use std::{path::Path, time::Duration};
-use tokio::sync::mpsc;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+use std::{path::Path, sync::Arc, time::Duration};
 use tracing::{debug, error, info, warn};
 
-use crate::models::NotificationPayload;
+use crate::channel::PipelineReceiver;
+use crate::device_health::{DeviceHealth, SendMode};
+use crate::retry::RetryPolicy;
+use crate::models::{Grouping, NotificationPayload};
+use crate::token_cleanup::TokenCleanupQueue;
 
 pub struct ApnsClient {
-    client: Client,
-    topic: String,
+    // Swapped whole when the signing key is rotated; sends already under way finish
+    // on the routes they started with
+    routes: RwLock<Arc<Vec<Route>>>,
+    // Index of the route sends start from; moves on when a host can't be reached
+    active: AtomicUsize,
+    // Apple's sandbox, set in production for tokens from development builds, which
+    // only the sandbox accepts
+    sandbox: RwLock<Option<Arc<Route>>>,
+    // Devices last reached through the sandbox
+    sandbox_tokens: Mutex<HashSet<String>>,
+    // What the routes are rebuilt from
+    key_path: String,
+    team_id: String,
+    production: bool,
+    hosts: Vec<String>,
+    // apns-topic per push class, from APNS_TOPIC and APNS_TOPIC_OVERRIDES
+    topics: HashMap<PushClass, String>,
+    // Keyed by notification type; unlisted types are high priority alerts
+    push_settings: HashMap<String, PushSettings>,
+}
+
+// How one notification type is pushed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PushSettings {
+    pub background: bool,
+    // Priority 10 rather than 5
+    pub high_priority: bool,
+}
+
+impl Default for PushSettings {
+    fn default() -> Self {
+        Self {
+            background: false,
+            high_priority: true,
+        }
+    }
+}
+
+// "type=alert:10" or "type=background:5" pairs separated by commas. Apple rejects
+// background pushes at priority 10, so those don't parse.
+pub fn parse_push_settings(spec: &str) -> Option<HashMap<String, PushSettings>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (notification_type, settings) = entry.split_once('=')?;
+            let notification_type = crate::models::NotificationType::parse(notification_type.trim())?;
+            let (push_type, priority) = settings.trim().split_once(':')?;
+            let settings = match (push_type.trim(), priority.trim()) {
+                ("alert", "10") => PushSettings { background: false, high_priority: true },
+                ("alert", "5") => PushSettings { background: false, high_priority: false },
+                ("background", "5") => PushSettings { background: true, high_priority: false },
+                _ => return None,
+            };
+            Some((notification_type.as_str().to_string(), settings))
+        })
+        .collect()
+}
+
+// The kinds of push Apple routes by apns-push-type. Alert and background pushes go to
+// the app's bundle id; VoIP and complication pushes go to the bundle id with Apple's
+// suffix for them, unless APNS_TOPIC_OVERRIDES names another topic, e.g. a watch
+// app's bundle id for complications. Only alerts and background pushes are sent for
+// now: the others need tokens of their own, which devices don't register yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PushClass {
+    Alert,
+    Background,
+    Voip,
+    Complication,
+}
+
+impl PushClass {
+    const ALL: [PushClass; 4] = [PushClass::Alert, PushClass::Background, PushClass::Voip, PushClass::Complication];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "alert" => Some(PushClass::Alert),
+            "background" => Some(PushClass::Background),
+            "voip" => Some(PushClass::Voip),
+            "complication" => Some(PushClass::Complication),
+            _ => None,
+        }
+    }
+
+    fn push_type(&self) -> PushType {
+        match self {
+            PushClass::Alert => PushType::Alert,
+            PushClass::Background => PushType::Background,
+            PushClass::Voip => PushType::Voip,
+            PushClass::Complication => PushType::Complication,
+        }
+    }
+
+    fn topic_suffix(&self) -> &'static str {
+        match self {
+            PushClass::Alert | PushClass::Background => "",
+            PushClass::Voip => ".voip",
+            PushClass::Complication => ".complication",
+        }
+    }
+
+    // Whether a registered device token can receive this class. VoIP pushes need a
+    // PushKit token and complications a watch app's token, which registration doesn't
+    // collect yet.
+    pub fn uses_device_token(&self) -> bool {
+        matches!(self, PushClass::Alert | PushClass::Background)
+    }
+
+    // The class a payload asks for in its push_type data; alert when it names none
+    pub fn of(payload: &NotificationPayload) -> Self {
+        payload
+            .data
+            .get("push_type")
+            .and_then(|push_type| Self::parse(push_type))
+            .unwrap_or(PushClass::Alert)
+    }
+}
+
+// "class=topic" pairs separated by commas
+pub fn parse_topic_overrides(spec: &str) -> Option<HashMap<PushClass, String>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (class, topic) = entry.split_once('=')?;
+            let topic = topic.trim();
+            if topic.is_empty() {
+                return None;
+            }
+            Some((PushClass::parse(class.trim())?, topic.to_string()))
+        })
+        .collect()
+}
+
+// apns-topic for every push class
+fn push_topics(base: &str, overrides: &HashMap<PushClass, String>) -> HashMap<PushClass, String> {
+    PushClass::ALL
+        .iter()
+        .map(|class| {
+            let topic = overrides
+                .get(class)
+                .cloned()
+                .unwrap_or_else(|| format!("{}{}", base, class.topic_suffix()));
+            (*class, topic)
+        })
+        .collect()
+}
+
+// One APNs host. Apple's host for the environment goes through a2; any other host
+// (Apple's port 2197 alternate, a proxy) can't be set on a2, so requests to it are
+// made directly over HTTP/2 with our own provider token.
+struct Route {
+    host: String,
+    transport: Transport,
+}
+
+enum Transport {
+    A2(Client),
+    Direct {
+        http: reqwest::Client,
+        token: Arc<ProviderToken>,
+    },
+}
+
+// Apple rejects provider tokens older than an hour and throttles refreshing them more
+// often than every 20 minutes
+const PROVIDER_TOKEN_LIFETIME: Duration = Duration::from_secs(40 * 60);
+
+struct ProviderToken {
+    key: SigningKey,
+    key_id: String,
+    team_id: String,
+    current: Mutex<Option<(String, Instant)>>,
+}
+
+impl ProviderToken {
+    fn get(&self) -> String {
+        let mut current = self.current.lock().unwrap();
+        if let Some((token, issued_at)) = current.as_ref() {
+            if issued_at.elapsed() < PROVIDER_TOKEN_LIFETIME {
+                return token.clone();
+            }
+        }
+
+        let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "ES256", "kid": self.key_id }).to_string());
+        let claims = URL_SAFE_NO_PAD
+            .encode(json!({ "iss": self.team_id, "iat": chrono::Utc::now().timestamp() }).to_string());
+        let signing_input = format!("{}.{}", header, claims);
+        let signature: Signature = self.key.sign(signing_input.as_bytes());
+        let token = format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.to_bytes()));
+
+        *current = Some((token.clone(), Instant::now()));
+        token
+    }
+}
+
+impl Route {
+    async fn send(&self, payload: Payload<'_>) -> Result<a2::Response> {
+        let (http, token) = match &self.transport {
+            Transport::A2(client) => return Ok(client.send(payload).await?),
+            Transport::Direct { http, token } => (http, token),
+        };
+
+        let url = format!("https://{}/3/device/{}", self.host, payload.device_token);
+        let options = payload.options.clone();
+        let mut request = http
+            .post(url)
+            .header("authorization", format!("bearer {}", token.get()))
+            .header(
+                "apns-push-type",
+                match options.apns_push_type {
+                    Some(PushType::Background) => "background",
+                    Some(PushType::Voip) => "voip",
+                    Some(PushType::Complication) => "complication",
+                    _ => "alert",
+                },
+            )
+            .header(
+                "apns-priority",
+                match options.apns_priority {
+                    Some(Priority::Normal) => "5",
+                    _ => "10",
+                },
+            );
+        if let Some(topic) = options.apns_topic {
+            request = request.header("apns-topic", topic);
+        }
+        if let Some(collapse_id) = &options.apns_collapse_id {
+            request = request.header("apns-collapse-id", collapse_id.value);
+        }
+        if let Some(expiration) = options.apns_expiration {
+            request = request.header("apns-expiration", expiration.to_string());
+        }
+        if let Some(apns_id) = options.apns_id {
+            request = request.header("apns-id", apns_id);
+        }
+
+        let response = request.body(payload.to_json_string()?).send().await?;
+        let code = response.status().as_u16();
+        let apns_id = response
+            .headers()
+            .get("apns-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().await?;
+
+        // Same shape a2 gives, so outcomes and retries don't depend on the route
+        let response = a2::Response {
+            error: serde_json::from_slice(&body).ok(),
+            apns_id,
+            code,
+        };
+        if code == 200 {
+            Ok(response)
+        } else {
+            Err(a2::Error::ResponseError(response).into())
+        }
+    }
+}
+
+// What became of one send. The sender loop records it in history and metrics and
+// decides what happens to the device from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryOutcome {
+    Delivered,
+    // APNs refused this payload for good (bad topic, payload too large, ...)
+    Rejected { reason: String },
+    // Still failing to reach APNs after this many attempts
+    Retried { attempts: u32 },
+    // APNs still throttling or failing server-side after this many attempts
+    Unavailable { attempts: u32 },
+    // APNs says the token is no longer registered for the app
+    TokenInvalid,
+    // The token belongs to another app than APNS_TOPIC, typically a misconfigured build
+    TopicMismatch,
+}
+
+impl DeliveryOutcome {
+    // Stable name used for history status and metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryOutcome::Delivered => "delivered",
+            DeliveryOutcome::Rejected { .. } => "rejected",
+            DeliveryOutcome::Retried { .. } => "retries_exhausted",
+            DeliveryOutcome::Unavailable { .. } => "apns_unavailable",
+            DeliveryOutcome::TokenInvalid => "token_invalid",
+            DeliveryOutcome::TopicMismatch => "topic_mismatch",
+        }
+    }
+
+    fn from_error(error: &anyhow::Error, attempts: u32) -> Self {
+        match error.downcast_ref::<a2::Error>() {
+            Some(a2::Error::ResponseError(response)) if response.code == 410 => DeliveryOutcome::TokenInvalid,
+            _ if is_topic_mismatch(error) => DeliveryOutcome::TopicMismatch,
+            _ if is_retryable(error) => DeliveryOutcome::Retried { attempts },
+            _ if is_apns_unavailable(error) => DeliveryOutcome::Unavailable { attempts },
+            Some(a2::Error::ResponseError(response)) => DeliveryOutcome::Rejected {
+                reason: match &response.error {
+                    Some(body) => format!("{:?}", body.reason),
+                    None => format!("HTTP {}", response.code),
+                },
+            },
+            _ => DeliveryOutcome::Rejected {
+                reason: error.to_string(),
+            },
+        }
+    }
+}
+
+// What APNs answered for one send, kept in the delivery log for debugging pushes that
+// never arrived
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeliveryReceipt {
+    pub apns_id: Option<String>,
+    // None when no response came back
+    pub status: Option<u16>,
+    pub reason: Option<String>,
+    // Set when the send moved the device between production and the sandbox: whether
+    // it's in the sandbox now
+    pub sandbox: Option<bool>,
+}
+
+impl DeliveryReceipt {
+    fn from_error(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<a2::Error>() {
+            Some(a2::Error::ResponseError(response)) => DeliveryReceipt {
+                apns_id: response.apns_id.clone(),
+                status: Some(response.code),
+                reason: response.error.as_ref().map(|body| format!("{:?}", body.reason)),
+                sandbox: None,
+            },
+            _ => DeliveryReceipt {
+                reason: Some(error.to_string()),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+fn apple_host(production: bool) -> &'static str {
+    if production {
+        "api.push.apple.com"
+    } else {
+        "api.development.push.apple.com"
+    }
+}
+
+fn read_key(key_path: &str) -> Result<String> {
+    let key_path = Path::new(key_path);
+    std::fs::read_to_string(key_path).context(format!(
+        "Failed to read APNs key file: {}",
+        key_path.display()
+    ))
+}
+
+fn build_sandbox_route(key: &str, key_id: &str, team_id: &str, production: bool) -> Result<Option<Arc<Route>>> {
+    if !production {
+        return Ok(None);
+    }
+    let mut routes = build_routes(key, key_id, team_id, false, &[apple_host(false).to_string()])?;
+    Ok(routes.pop().map(Arc::new))
+}
+
+fn build_routes(key: &str, key_id: &str, team_id: &str, production: bool, hosts: &[String]) -> Result<Vec<Route>> {
+    let apple_host = apple_host(production);
+    let mut routes = Vec::new();
+    let mut direct = None;
+    for host in hosts {
+        let transport = if host == apple_host {
+            let config = a2::ClientConfig::new(if production {
+                a2::Endpoint::Production
+            } else {
+                a2::Endpoint::Sandbox
+            });
+            Transport::A2(a2::Client::token(key.as_bytes(), key_id, team_id, config)?)
+        } else {
+            let (http, token) = match &direct {
+                Some(direct) => direct,
+                None => direct.insert((
+                    reqwest::Client::builder()
+                        .http2_prior_knowledge()
+                        .timeout(Duration::from_secs(20))
+                        .build()?,
+                    Arc::new(ProviderToken {
+                        key: SigningKey::from_pkcs8_pem(key)
+                            .map_err(|e| anyhow!("Invalid APNs key: {}", e))?,
+                        key_id: key_id.to_string(),
+                        team_id: team_id.to_string(),
+                        current: Mutex::new(None),
+                    }),
+                )),
+            };
+            Transport::Direct {
+                http: http.clone(),
+                token: token.clone(),
+            }
+        };
+        routes.push(Route {
+            host: host.clone(),
+            transport,
+        });
+    }
+    Ok(routes)
 }
 
 impl ApnsClient {
-    pub fn new(key_path: &str, key_id: &str, team_id: &str, production: bool) -> Result<Self> {
-        let key_path = Path::new(key_path);
-        let _key = std::fs::read(key_path).context(format!(
-            "Failed to read APNs key file: {}",
-            key_path.display()
-        ))?;
-
-        let _endpoint = if production {
-            a2::Endpoint::Production
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        key_path: &str,
+        key_id: &str,
+        team_id: &str,
+        production: bool,
+        hosts: &[String],
+        topic: &str,
+        push_settings: HashMap<String, PushSettings>,
+        topic_overrides: HashMap<PushClass, String>,
+    ) -> Result<Self> {
+        let topics = push_topics(topic, &topic_overrides);
+
+        let hosts = if hosts.is_empty() {
+            vec![apple_host(production).to_string()]
         } else {
-            a2::Endpoint::Sandbox
+            hosts.to_vec()
         };
+        let key = read_key(key_path)?;
+        let routes = build_routes(&key, key_id, team_id, production, &hosts)?;
+        let sandbox = build_sandbox_route(&key, key_id, team_id, production)?;
+        info!(hosts = ?hosts, "APNs hosts configured");
+
+        Ok(Self {
+            routes: RwLock::new(Arc::new(routes)),
+            active: AtomicUsize::new(0),
+            sandbox: RwLock::new(sandbox),
+            sandbox_tokens: Mutex::new(HashSet::new()),
+            key_path: key_path.to_string(),
+            team_id: team_id.to_string(),
+            production,
+            hosts,
+            topics,
+            push_settings,
+        })
+    }
+
+    // Rotate the signing key without a restart. The new key is read from key_path, or
+    // the configured APNS_KEY_PATH, and only replaces the old one once every route has
+    // been rebuilt with it; on any error the old key stays in use.
+    pub fn reload_key(&self, key_path: Option<&str>, key_id: &str) -> Result<()> {
+        let key_path = key_path.unwrap_or(&self.key_path);
+        let key = read_key(key_path)?;
+        let routes = build_routes(&key, key_id, &self.team_id, self.production, &self.hosts)?;
+        let sandbox = build_sandbox_route(&key, key_id, &self.team_id, self.production)?;
+        *self.routes.write().unwrap() = Arc::new(routes);
+        *self.sandbox.write().unwrap() = sandbox;
+        info!(key_id, key_path, "Reloaded APNs signing key");
+        Ok(())
+    }
+
+    fn topic(&self, class: PushClass) -> &str {
+        &self.topics[&class]
+    }
 
-        // Use the topic from config
-        let topic =
-            std::env::var("APNS_TOPIC").context("APNS_TOPIC environment variable not set")?;
+    // Devices known to need the sandbox, loaded at startup
+    pub fn remember_sandbox_tokens(&self, tokens: impl IntoIterator<Item = String>) {
+        self.sandbox_tokens.lock().unwrap().extend(tokens);
+    }
 
-        let config = a2::ClientConfig::new(if production {
-            a2::Endpoint::Production
+    pub fn is_sandbox_token(&self, device_token: &str) -> bool {
+        self.sandbox_tokens.lock().unwrap().contains(device_token)
+    }
+
+    // In production a token APNs calls bad is tried once in the other environment, and
+    // whichever accepts it is used for that device from then on
+    async fn send_to_device(&self, payload: Payload<'_>) -> Result<a2::Response> {
+        let sandbox = self.sandbox.read().unwrap().clone();
+        let Some(sandbox) = sandbox else {
+            return self.send(payload).await;
+        };
+
+        let device_token = payload.device_token.to_string();
+        let in_sandbox = self.is_sandbox_token(&device_token);
+        let result = if in_sandbox {
+            timed_send(&sandbox, payload.clone()).await
         } else {
-            a2::Endpoint::Sandbox
-        });
+            self.send(payload.clone()).await
+        };
+        if !is_bad_device_token(&result) {
+            return result;
+        }
 
-        let client = a2::Client::token(std::fs::File::open(key_path)?, key_id, team_id, config)?;
+        let fallback = if in_sandbox {
+            self.send(payload).await
+        } else {
+            timed_send(&sandbox, payload).await
+        };
+        match fallback {
+            Ok(response) => {
+                let mut tokens = self.sandbox_tokens.lock().unwrap();
+                if in_sandbox {
+                    tokens.remove(&device_token);
+                } else {
+                    tokens.insert(device_token);
+                }
+                info!(sandbox = !in_sandbox, "Switched device to the other APNs environment");
+                Ok(response)
+            }
+            Err(_) => result,
+        }
+    }
+
+    // Sends through the active host. A host that can't be connected to hands over to
+    // the next one, which stays active until it fails in turn.
+    async fn send(&self, payload: Payload<'_>) -> Result<a2::Response> {
+        let routes = self.routes.read().unwrap().clone();
+        let start = self.active.load(Ordering::Relaxed);
+        let mut last_error = None;
+
+        for offset in 0..routes.len() {
+            let index = (start + offset) % routes.len();
+            let route = &routes[index];
+
+            match timed_send(route, payload.clone()).await {
+                Err(e) if is_connect_failure(&e) => {
+                    warn!(host = %route.host, "Could not connect to APNs host: {}", e);
+                    crate::metrics::APNS_HOST_FAILOVERS
+                        .with_label_values(&[&route.host])
+                        .inc();
+                    last_error = Some(e);
+                }
+                result => {
+                    if index != start
+                        && self
+                            .active
+                            .compare_exchange(start, index, Ordering::Relaxed, Ordering::Relaxed)
+                            .is_ok()
+                    {
+                        warn!(host = %route.host, "Switched APNs host");
+                    }
+                    return result;
+                }
+            }
+        }
 
-        Ok(Self { client, topic })
+        Err(last_error.unwrap_or_else(|| anyhow!("No APNs hosts configured")))
     }
 
-    pub async fn send_notification(&self, payload_data: &NotificationPayload) -> Result<()> {
-        let builder = DefaultNotificationBuilder::new()
-            .set_title(&payload_data.title)
-            .set_body(&payload_data.body)
-            .set_sound("default");
+    pub async fn send_notification(&self, payload_data: &NotificationPayload) -> DeliveryOutcome {
+        self.send_notification_with_attempts(payload_data, DEFAULT_SEND_ATTEMPTS)
+            .await
+            .0
+    }
 
+    pub async fn send_notification_with_attempts(
+        &self,
+        payload_data: &NotificationPayload,
+        max_attempts: u8,
+    ) -> (DeliveryOutcome, DeliveryReceipt) {
+        // Background pushes carry no alert, sound or badge; the app wakes briefly to
+        // refresh its data, and Apple requires them at normal priority. Recipients can
+        // ask for background delivery of a type, and VIPs always alert at priority 10.
+        // VoIP and complication pushes carry only their data and go at priority 10.
+        let class = PushClass::of(payload_data);
+        let settings = if payload_data.is_vip() || matches!(class, PushClass::Voip | PushClass::Complication) {
+            PushSettings::default()
+        } else if payload_data.is_background() {
+            PushSettings {
+                background: true,
+                high_priority: false,
+            }
+        } else {
+            self.push_settings
+                .get(payload_data.notification_type.as_str())
+                .copied()
+                .unwrap_or_default()
+        };
+        let class = match class {
+            PushClass::Alert | PushClass::Background if settings.background => PushClass::Background,
+            PushClass::Alert | PushClass::Background => PushClass::Alert,
+            class => class,
+        };
+        let thread_id = thread_id(payload_data);
+        let builder = match class {
+            PushClass::Background => DefaultNotificationBuilder::new().set_content_available(),
+            PushClass::Voip | PushClass::Complication => DefaultNotificationBuilder::new(),
+            PushClass::Alert => match &thread_id {
+                Some(thread_id) => alert_builder(payload_data).set_thread_id(thread_id),
+                None => alert_builder(payload_data),
+            },
+        };
+
+        let collapse_id = collapse_id(payload_data);
         let mut payload = builder.build(
             &payload_data.device_token,
             NotificationOptions {
-                apns_topic: Some(&self.topic),
-                apns_priority: Some(Priority::High),
-                apns_collapse_id: None,
+                apns_topic: Some(self.topic(class)),
+                apns_priority: Some(if settings.high_priority { Priority::High } else { Priority::Normal }),
+                apns_collapse_id: a2::CollapseId::new(&collapse_id).ok(),
                 apns_expiration: None,
-                apns_push_type: None,
-                apns_id: None,
+                apns_push_type: Some(class.push_type()),
+                apns_id: payload_data.data.get("notification_id").map(String::as_str),
             },
         );
 
-        for (key, value) in &payload_data.data {
-            payload.add_custom_data(key, value)?;
+        for (key, value) in crate::payload_keys::encode(&payload_data.data) {
+            if let Err(e) = payload.add_custom_data(key, &value) {
+                let reason = format!("Invalid custom data {}: {}", key, e);
+                let receipt = DeliveryReceipt {
+                    reason: Some(reason.clone()),
+                    ..Default::default()
+                };
+                return (DeliveryOutcome::Rejected { reason }, receipt);
+            }
         }
 
         debug!(
@@ -74,66 +649,285 @@ impl ApnsClient {
             notification_type = ?payload_data.notification_type,
             user_did = %payload_data.user_did,
             title = %payload_data.title,
+            event_source = ?payload_data.data.get("event_source"),
+            event_seq = ?payload_data.data.get("event_seq"),
+            event_rev = ?payload_data.data.get("event_rev"),
             "Sending notification"
         );
 
-        const MAX_RETRIES: u8 = 3;
-        let mut retry_count = 0;
-        let mut backoff_ms = 100;
-
-        loop {
-            match self.client.send(payload.clone()).await {
-                Ok(response) => {
-                    if response.code >= 200 && response.code < 300 {
-                        info!(
-                            notification_type = ?payload_data.notification_type,
-                            user_did = %payload_data.user_did,
-                            status = response.code,
-                            "Notification delivered successfully"
-                        );
-                    } else {
-                        // Non-2xx status is still an "Ok" response from the API but might indicate a problem
-                        warn!(
-                            notification_type = ?payload_data.notification_type,
-                            user_did = %payload_data.user_did,
-                            status = response.code,
-                            "Notification accepted but with non-success status"
-                        );
-                    }
-                    return Ok(());
-                }
-                Err(e) => {
-                    retry_count += 1;
+        let policy = RetryPolicy::new("apns_send")
+            .max_attempts(max_attempts.into())
+            .backoff(Duration::from_millis(100), Duration::from_secs(2))
+            .retry_if(|e| is_retryable(e) || is_apns_unavailable(e));
+
+        let was_sandbox = self.is_sandbox_token(&payload_data.device_token);
+        let mut attempts = 0;
+        let result = policy
+            .run(|attempt| {
+                attempts = attempt;
+                self.send_to_device(payload.clone())
+            })
+            .await;
+        let is_sandbox = self.is_sandbox_token(&payload_data.device_token);
+        let sandbox = (is_sandbox != was_sandbox).then_some(is_sandbox);
+
+        match result {
+            Ok(response) => {
+                if response.code >= 200 && response.code < 300 {
+                    info!(
+                        notification_type = ?payload_data.notification_type,
+                        user_did = %payload_data.user_did,
+                        status = response.code,
+                        "Notification delivered successfully"
+                    );
+                } else {
+                    // Non-2xx status is still an "Ok" response from the API but might indicate a problem
                     warn!(
                         notification_type = ?payload_data.notification_type,
                         user_did = %payload_data.user_did,
-                        error = %e,
-                        attempt = retry_count,
-                        "Failed to send notification, retrying"
+                        status = response.code,
+                        "Notification accepted but with non-success status"
                     );
-
-                    if retry_count >= MAX_RETRIES {
-                        error!(
-                            notification_type = ?payload_data.notification_type,
-                            user_did = %payload_data.user_did,
-                            error = %e,
-                            "Failed to send notification after maximum retries"
-                        );
-                        return Err(e.into());
-                    }
-
-                    tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
-                    backoff_ms *= 2;
                 }
+                let receipt = DeliveryReceipt {
+                    apns_id: response.apns_id,
+                    status: Some(response.code),
+                    reason: None,
+                    sandbox,
+                };
+                (DeliveryOutcome::Delivered, receipt)
             }
+            Err(e) => {
+                error!(
+                    notification_type = ?payload_data.notification_type,
+                    user_did = %payload_data.user_did,
+                    error = %e,
+                    attempts = attempts,
+                    "Failed to send notification"
+                );
+                let receipt = DeliveryReceipt {
+                    sandbox,
+                    ..DeliveryReceipt::from_error(&e)
+                };
+                (DeliveryOutcome::from_error(&e, attempts), receipt)
+            }
+        }
+    }
+}
+
+impl ApnsClient {
+    // Silent push carrying the registration nonce; the app echoes it back to
+    // /register/confirm to prove it actually owns the token
+    pub async fn send_verification(&self, device_token: &str, nonce: &str) -> Result<()> {
+        let mut payload = DefaultNotificationBuilder::new().set_content_available().build(
+            device_token,
+            NotificationOptions {
+                apns_topic: Some(self.topic(PushClass::Background)),
+                apns_priority: Some(Priority::Normal),
+                apns_push_type: Some(PushType::Background),
+                ..Default::default()
+            },
+        );
+        payload.add_custom_data("verification_nonce", &nonce)?;
+
+        let response = self.send_to_device(payload).await?;
+        debug!(status = response.code, "Verification push sent");
+        Ok(())
+    }
+
+    // Whether the token belongs to APNS_TOPIC, found out with a silent push. Errors
+    // other than a topic mismatch leave the question open.
+    pub async fn check_topic(&self, device_token: &str) -> Result<bool> {
+        let payload = DefaultNotificationBuilder::new().set_content_available().build(
+            device_token,
+            NotificationOptions {
+                apns_topic: Some(self.topic(PushClass::Background)),
+                apns_priority: Some(Priority::Normal),
+                apns_push_type: Some(PushType::Background),
+                ..Default::default()
+            },
+        );
+
+        match self.send_to_device(payload).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_topic_mismatch(&e) => Ok(false),
+            Err(e) => Err(e),
         }
     }
 }
 
+// Title, body and sound, plus the badge, media and interruption level the filter asked for
+fn alert_builder(payload_data: &NotificationPayload) -> DefaultNotificationBuilder<'_> {
+    let mut builder = DefaultNotificationBuilder::new()
+        .set_title(&payload_data.title)
+        .set_body(&payload_data.body)
+        .set_sound("default");
+
+    // The device's unread count, kept by the filter; pushes without one leave the
+    // badge as it is
+    if let Some(badge) = payload_data.data.get("unread_total").and_then(|total| total.parse().ok()) {
+        builder = builder.set_badge(badge);
+    }
+
+    // Lets the notification service extension download and attach the media
+    if payload_data.data.contains_key("image_url") || payload_data.data.contains_key("avatar_url") {
+        builder = builder.set_mutable_content();
+    }
+
+    // Set by the filter for users who prioritise mutuals, and for VIPs
+    match payload_data.data.get("interruption_level").map(String::as_str) {
+        _ if payload_data.is_vip() => {
+            builder = builder.set_interruption_level(InterruptionLevel::TimeSensitive)
+        }
+        Some("time-sensitive") => {
+            builder = builder.set_interruption_level(InterruptionLevel::TimeSensitive)
+        }
+        Some("passive") => builder = builder.set_interruption_level(InterruptionLevel::Passive),
+        _ => {}
+    }
+
+    builder
+}
+
+// Transport failures may succeed later, and are the only failures that say something
+// about the device: a token whose network path is black-holed never gets through
+pub fn is_retryable(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<a2::Error>() {
+        Some(a2::Error::ResponseError(_)) => false,
+        Some(_) => true,
+        // Transport failures on hosts we reach directly
+        None => error.downcast_ref::<reqwest::Error>().is_some(),
+    }
+}
+
+// Throttling and APNs server errors may also succeed later, but they're APNs' trouble
+// rather than the device's; other rejections are final for this payload and token
+fn is_apns_unavailable(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<a2::Error>(),
+        Some(a2::Error::ResponseError(response)) if response.code == 429 || response.code >= 500
+    )
+}
+
+async fn timed_send(route: &Route, payload: Payload<'_>) -> Result<a2::Response> {
+    let started = Instant::now();
+    let result = route.send(payload).await;
+    let status = match &result {
+        Ok(response) => response.code.to_string(),
+        Err(e) => match e.downcast_ref::<a2::Error>() {
+            Some(a2::Error::ResponseError(response)) => response.code.to_string(),
+            _ => "error".to_string(),
+        },
+    };
+    crate::metrics::APNS_SEND_LATENCY
+        .with_label_values(&[&route.host, &status])
+        .observe(started.elapsed().as_secs_f64());
+    result
+}
+
+// The token was issued to another app than the topic it was sent under
+pub fn is_topic_mismatch(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<a2::Error>(),
+        Some(a2::Error::ResponseError(a2::Response {
+            error: Some(a2::ErrorBody {
+                reason: a2::ErrorReason::DeviceTokenNotForTopic,
+                ..
+            }),
+            ..
+        }))
+    )
+}
+
+// The token isn't valid in the environment it was sent to
+fn is_bad_device_token(result: &Result<a2::Response>) -> bool {
+    match result {
+        Err(e) => matches!(
+            e.downcast_ref::<a2::Error>(),
+            Some(a2::Error::ResponseError(a2::Response {
+                error: Some(a2::ErrorBody {
+                    reason: a2::ErrorReason::BadDeviceToken,
+                    ..
+                }),
+                ..
+            }))
+        ),
+        Ok(_) => false,
+    }
+}
+
+fn is_connect_failure(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(error) => error.is_connect(),
+        None => matches!(error.downcast_ref::<a2::Error>(), Some(a2::Error::ConnectionError(_))),
+    }
+}
+
+// The grouping the app on the receiving device asked for
+fn grouping(notification: &NotificationPayload) -> Grouping {
+    notification
+        .data
+        .get("grouping")
+        .and_then(|grouping| Grouping::parse(grouping))
+        .unwrap_or_default()
+}
+
+// Notifications sharing a thread-id stack together in Notification Center
+fn thread_id(notification: &NotificationPayload) -> Option<String> {
+    match grouping(notification) {
+        Grouping::Thread => notification
+            .data
+            .get("thread_root")
+            .or_else(|| notification.data.get("uri"))
+            .cloned(),
+        Grouping::Author => notification.data.get("author_did").cloned(),
+        Grouping::App | Grouping::None => None,
+    }
+}
+
+// Notifications about the same subject replace each other on the device, so a burst
+// of likes on one post shows as a single alert, unless the app asked for no grouping.
+// The URI is hashed to stay under the 64 byte APNs limit. Anything without a URI
+// collapses by notification id, which replays reuse.
+fn collapse_id(notification: &NotificationPayload) -> String {
+    let subject = match grouping(notification) {
+        Grouping::None => None,
+        _ => notification.data.get("uri"),
+    };
+    match subject {
+        Some(uri) => {
+            let digest = format!("{:x}", Sha256::digest(uri.as_bytes()));
+            format!("{}:{}", notification.notification_type.as_str(), &digest[..40])
+        }
+        None => notification.data.get("notification_id").cloned().unwrap_or_default(),
+    }
+}
+
+// Count deliveries per copy experiment variant
+fn record_experiment_outcome(notification: &NotificationPayload, outcome: &str) {
+    if let (Some(experiment), Some(variant)) = (
+        notification.data.get("experiment"),
+        notification.data.get("variant"),
+    ) {
+        crate::metrics::EXPERIMENT_NOTIFICATIONS
+            .with_label_values(&[experiment, variant, outcome])
+            .inc();
+    }
+}
+
+// Attempts at an ordinary send before giving up
+const DEFAULT_SEND_ATTEMPTS: u8 = 3;
+
+// VIP notifications get more retries than the default before giving up
+const VIP_SEND_ATTEMPTS: u8 = 5;
+
 pub async fn run_notification_sender(
-    mut notification_receiver: mpsc::Receiver<NotificationPayload>,
-    apns_client: ApnsClient,
+    mut notification_receiver: PipelineReceiver<NotificationPayload>,
+    apns_client: Arc<ApnsClient>,
     db_pool: Pool<Postgres>,
+    device_health: Arc<DeviceHealth>,
+    token_cleanup: TokenCleanupQueue,
+    trace: crate::user_trace::UserTrace,
 ) -> Result<()> {
     info!("Starting notification sender");
 
@@ -145,9 +939,91 @@ pub async fn run_notification_sender(
     while let Some(notification) = notification_receiver.recv().await {
         notification_count += 1;
 
-        match apns_client.send_notification(&notification).await {
-            Ok(_) => {
+        // Quarantined devices only get an occasional single-attempt probe, except that a
+        // VIP notification always gets its one attempt
+        let (outcome, receipt) = match device_health.send_mode(&notification.device_token).await {
+            SendMode::Normal if notification.is_vip() => {
+                apns_client
+                    .send_notification_with_attempts(&notification, VIP_SEND_ATTEMPTS)
+                    .await
+            }
+            SendMode::Normal => {
+                apns_client
+                    .send_notification_with_attempts(&notification, DEFAULT_SEND_ATTEMPTS)
+                    .await
+            }
+            SendMode::Probe => {
+                debug!(user_did = %notification.user_did, "Probing quarantined device");
+                apns_client.send_notification_with_attempts(&notification, 1).await
+            }
+            SendMode::Skip if notification.is_vip() => {
+                debug!(user_did = %notification.user_did, "Sending VIP notification to quarantined device");
+                apns_client.send_notification_with_attempts(&notification, 1).await
+            }
+            SendMode::Skip => {
+                crate::metrics::NOTIFICATIONS_QUARANTINED.inc();
+                trace.record(&notification.user_did, "apns", || {
+                    format!("{} not sent: device quarantined", notification.notification_type.as_str())
+                });
+                continue;
+            }
+        };
+        trace.record(&notification.user_did, "apns", || {
+            format!("{} {}", notification.notification_type.as_str(), outcome.as_str())
+        });
+        match &outcome {
+            DeliveryOutcome::Delivered => device_health.record_success(&notification.device_token).await,
+            DeliveryOutcome::Retried { .. } => {
+                device_health.record_retryable_failure(&notification.device_token).await
+            }
+            // A mismatch on another class's topic says nothing about the device's token
+            DeliveryOutcome::TopicMismatch if PushClass::of(&notification).uses_device_token() => {
+                device_health.record_topic_mismatch(&notification.device_token).await
+            }
+            DeliveryOutcome::TopicMismatch => {}
+            DeliveryOutcome::Rejected { .. }
+            | DeliveryOutcome::TokenInvalid
+            | DeliveryOutcome::Unavailable { .. } => {}
+        }
+
+        let delivered = outcome == DeliveryOutcome::Delivered;
+        record_experiment_outcome(&notification, if delivered { "delivered" } else { "failed" });
+        crate::metrics::NOTIFICATION_DELIVERY_OUTCOMES
+            .with_label_values(&[outcome.as_str()])
+            .inc();
+        if let Err(e) =
+            crate::db::record_notification_history(&db_pool, &notification, outcome.as_str()).await
+        {
+            warn!("Failed to record notification history: {}", e);
+        }
+        if let Err(e) =
+            crate::db::record_delivery_log(&db_pool, &notification, outcome.as_str(), &receipt).await
+        {
+            warn!("Failed to record delivery log: {}", e);
+        }
+        if let Some(sandbox) = receipt.sandbox {
+            if let Err(e) = crate::db::set_device_sandbox(&db_pool, &notification.device_token, sandbox).await {
+                warn!("Failed to record device APNs environment: {}", e);
+            }
+        }
+
+        match outcome {
+            DeliveryOutcome::Delivered => {
                 success_count += 1;
+
+                let notification_type = notification.notification_type.as_str();
+                crate::metrics::NOTIFICATIONS_DELIVERED_BY_TYPE
+                    .with_label_values(&[notification_type])
+                    .inc();
+                if !notification.is_replay() {
+                    if let Err(e) = crate::db::record_delivery(&db_pool, notification_type).await {
+                        warn!("Failed to record delivery stats: {}", e);
+                    }
+                    if let Err(e) = crate::quota::record_usage(&db_pool, &notification.device_token).await {
+                        warn!("Failed to record tenant usage: {}", e);
+                    }
+                }
+
                 // Only log notification stats periodically to reduce log spam
                 if notification_count % 10 == 0 {
                     info!(
@@ -161,38 +1037,44 @@ pub async fn run_notification_sender(
                     notification.user_did
                 );
             }
-            Err(e) => {
+            DeliveryOutcome::TokenInvalid => {
+                error_count += 1;
+                info!("Queueing invalid token for removal for user {}", notification.user_did);
+                token_cleanup.enqueue(&notification.device_token);
+            }
+            DeliveryOutcome::TopicMismatch => {
+                error_count += 1;
+                error!(
+                    user_did = %notification.user_did,
+                    "Device token belongs to another app than APNS_TOPIC; device quarantined"
+                );
+            }
+            DeliveryOutcome::Rejected { reason } => {
                 error_count += 1;
                 error!(
                     notification_type = ?notification.notification_type,
                     user_did = %notification.user_did,
-                    "Failed to send notification: {}",
-                    e
+                    reason = %reason,
+                    "Notification rejected by APNs"
+                );
+            }
+            DeliveryOutcome::Retried { attempts } => {
+                error_count += 1;
+                error!(
+                    notification_type = ?notification.notification_type,
+                    user_did = %notification.user_did,
+                    attempts = attempts,
+                    "Notification still failing after retries"
+                );
+            }
+            DeliveryOutcome::Unavailable { attempts } => {
+                error_count += 1;
+                error!(
+                    notification_type = ?notification.notification_type,
+                    user_did = %notification.user_did,
+                    attempts = attempts,
+                    "APNs still unavailable after retries"
                 );
-
-                if let Some(a2_err) = e.downcast_ref::<a2::Error>() {
-                    if let a2::Error::ResponseError(resp) = a2_err {
-                        if resp.code == 410 {
-                            match sqlx::query!(
-                                "DELETE FROM user_devices WHERE device_token = $1",
-                                notification.device_token
-                            )
-                            .execute(&db_pool)
-                            .await
-                            {
-                                Ok(_) => {
-                                    info!(
-                                        "Removed invalid token for user {}",
-                                        notification.user_did
-                                    );
-                                }
-                                Err(e) => {
-                                    error!("Failed to remove invalid token: {}", e);
-                                }
-                            }
-                        }
-                    }
-                }
             }
         }
     }
@@ -200,3 +1082,168 @@ pub async fn run_notification_sender(
     info!("Notification sender stopped");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_error(code: u16) -> anyhow::Error {
+        a2::Error::ResponseError(a2::Response {
+            error: None,
+            apns_id: None,
+            code,
+        })
+        .into()
+    }
+
+    #[test]
+    fn classifies_send_errors() {
+        assert_eq!(DeliveryOutcome::from_error(&response_error(410), 1), DeliveryOutcome::TokenInvalid);
+        assert_eq!(
+            DeliveryOutcome::from_error(&response_error(503), 3),
+            DeliveryOutcome::Unavailable { attempts: 3 }
+        );
+        assert!(!is_retryable(&response_error(429)));
+        assert_eq!(
+            DeliveryOutcome::from_error(&response_error(413), 1),
+            DeliveryOutcome::Rejected {
+                reason: "HTTP 413".to_string()
+            }
+        );
+        assert_eq!(DeliveryReceipt::from_error(&response_error(410)).status, Some(410));
+        assert_eq!(DeliveryReceipt::from_error(&anyhow!("connection reset")).status, None);
+
+        let bad_token = a2::Error::ResponseError(a2::Response {
+            error: Some(a2::ErrorBody {
+                reason: a2::ErrorReason::BadDeviceToken,
+                timestamp: None,
+            }),
+            apns_id: None,
+            code: 400,
+        });
+        assert!(is_bad_device_token(&Err(bad_token.into())));
+        assert!(!is_bad_device_token(&Err(response_error(400))));
+
+        let other_app = a2::Error::ResponseError(a2::Response {
+            error: Some(a2::ErrorBody {
+                reason: a2::ErrorReason::DeviceTokenNotForTopic,
+                timestamp: None,
+            }),
+            apns_id: None,
+            code: 400,
+        });
+        assert_eq!(DeliveryOutcome::from_error(&other_app.into(), 1), DeliveryOutcome::TopicMismatch);
+    }
+
+    #[test]
+    fn provider_token_is_signed_and_reused() {
+        use p256::ecdsa::{signature::Verifier, VerifyingKey};
+
+        let key = SigningKey::from_slice(&[3u8; 32]).unwrap();
+        let verifying_key = VerifyingKey::from(&key);
+        let provider_token = ProviderToken {
+            key,
+            key_id: "KEY123".to_string(),
+            team_id: "TEAM456".to_string(),
+            current: Mutex::new(None),
+        };
+
+        let token = provider_token.get();
+        assert_eq!(provider_token.get(), token);
+
+        let (signing_input, signature) = token.rsplit_once('.').unwrap();
+        let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(signature).unwrap()).unwrap();
+        assert!(verifying_key.verify(signing_input.as_bytes(), &signature).is_ok());
+
+        let header: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(signing_input.split('.').next().unwrap()).unwrap())
+                .unwrap();
+        assert_eq!(header["kid"], "KEY123");
+    }
+
+    #[test]
+    fn reloads_the_signing_key_only_when_it_is_valid() {
+        use p256::pkcs8::{EncodePrivateKey, LineEnding};
+
+        let key_path = std::env::temp_dir().join(format!("apns-{}.p8", uuid::Uuid::new_v4()));
+        let pem = SigningKey::from_slice(&[5u8; 32]).unwrap().to_pkcs8_pem(LineEnding::LF).unwrap();
+        std::fs::write(&key_path, pem.as_bytes()).unwrap();
+        let key_path = key_path.to_str().unwrap();
+
+        let client = ApnsClient::new(
+            key_path,
+            "OLDKEY",
+            "TEAM",
+            true,
+            &["proxy.example".to_string()],
+            "app.example",
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .unwrap();
+        let key_id = |client: &ApnsClient| match &client.routes.read().unwrap()[0].transport {
+            Transport::Direct { token, .. } => token.key_id.clone(),
+            Transport::A2(_) => unreachable!(),
+        };
+
+        assert!(client.reload_key(Some("/nonexistent/key.p8"), "NEWKEY").is_err());
+        assert_eq!(key_id(&client), "OLDKEY");
+
+        client.reload_key(None, "NEWKEY").unwrap();
+        assert_eq!(key_id(&client), "NEWKEY");
+        std::fs::remove_file(key_path).unwrap();
+    }
+
+    #[test]
+    fn likes_on_one_post_share_a_collapse_id() {
+        let like = |uri: &str| NotificationPayload {
+            user_did: "did:plc:me".to_string(),
+            device_token: "token".to_string(),
+            notification_type: crate::models::NotificationType::Like,
+            title: String::new(),
+            body: String::new(),
+            data: [
+                ("uri".to_string(), uri.to_string()),
+                ("notification_id".to_string(), uuid::Uuid::new_v4().to_string()),
+            ]
+            .into(),
+        };
+        let post = "at://did:plc:abcdefghijklmnopqrstuvwx/app.bsky.feed.post/3kabcdefghij2";
+
+        assert_eq!(collapse_id(&like(post)), collapse_id(&like(post)));
+        assert_ne!(collapse_id(&like(post)), collapse_id(&like("at://did:plc:me/app.bsky.feed.post/other")));
+        assert!(collapse_id(&like(post)).len() <= 64);
+
+        let mut ungrouped = like(post);
+        ungrouped.data.insert("grouping".to_string(), "none".to_string());
+        assert_eq!(collapse_id(&ungrouped), ungrouped.data["notification_id"]);
+        assert_eq!(thread_id(&ungrouped), None);
+
+        let mut by_thread = like(post);
+        by_thread.data.insert("grouping".to_string(), "thread".to_string());
+        assert_eq!(thread_id(&by_thread).as_deref(), Some(post));
+    }
+
+    #[test]
+    fn parses_push_settings() {
+        let settings = parse_push_settings("like=background:5, follow=alert:5").unwrap();
+        assert!(settings["like"].background);
+        assert!(!settings["follow"].background && !settings["follow"].high_priority);
+
+        assert!(parse_push_settings("like=background:10").is_none());
+        assert!(parse_push_settings("like=loud").is_none());
+    }
+
+    #[test]
+    fn derives_topics_per_push_class() {
+        let overrides = parse_topic_overrides("complication=com.example.app.watchkitapp.complication").unwrap();
+        let topics = push_topics("com.example.app", &overrides);
+        assert_eq!(topics[&PushClass::Alert], "com.example.app");
+        assert_eq!(topics[&PushClass::Background], "com.example.app");
+        assert_eq!(topics[&PushClass::Voip], "com.example.app.voip");
+        assert_eq!(topics[&PushClass::Complication], "com.example.app.watchkitapp.complication");
+
+        assert!(parse_topic_overrides("liveactivity=com.example.app").is_none());
+        assert!(parse_topic_overrides("voip=").is_none());
+    }
+}
@@ -0,0 +1,234 @@
+// atom_feed.rs
+// Notification history as an Atom feed, for users who'd rather read notifications in a
+// feed reader than get pushes. A user opts in with PUT /preferences/atom-feed and gets
+// back a path of the form /feeds/{token}.atom; the token is the only credential, since
+// feed readers can't send device tokens, so it's long and random, stored only as a
+// hash, and can be rotated (POST /preferences/atom-feed/rotate) when the URL leaks.
+// The feed lists the latest FEED_ENTRIES notifications from the last FEED_WINDOW_DAYS,
+// optionally limited to some notification types. History is kept per device, so a
+// notification sent to several devices appears once. Retracted notifications are left
+// out, and turning the feed off deletes it, token and all.
+use anyhow::Result;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres, Transaction};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+const FEED_ENTRIES: i64 = 50;
+const FEED_WINDOW_DAYS: f64 = 30.0;
+
+#[derive(Debug, Serialize)]
+pub struct FeedSettings {
+    // Every type when absent
+    pub notification_types: Option<Vec<String>>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub rotated_at: OffsetDateTime,
+}
+
+struct FeedEntry {
+    notification_id: Uuid,
+    title: String,
+    body: String,
+    uri: Option<String>,
+    created_at: OffsetDateTime,
+}
+
+// 256 random bits, as hex
+fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+fn hash_token(token: &str) -> String {
+    crate::crypto::hex(&Sha256::digest(token.as_bytes()))
+}
+
+pub fn feed_path(token: &str) -> String {
+    format!("/feeds/{}.atom", token)
+}
+
+pub async fn settings(tx: &mut Transaction<'_, Postgres>, did: &str) -> Result<Option<FeedSettings>> {
+    let settings = sqlx::query_as!(
+        FeedSettings,
+        "SELECT notification_types, rotated_at FROM atom_feeds WHERE user_did = $1",
+        did
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+    Ok(settings)
+}
+
+// Turn the feed on, or change the types of one that's already on. Returns the new
+// feed's token; an existing feed keeps its token, which isn't stored to hand back.
+pub async fn enable(
+    tx: &mut Transaction<'_, Postgres>,
+    did: &str,
+    notification_types: Option<&[String]>,
+) -> Result<Option<String>> {
+    let token = generate_token();
+    let created = sqlx::query_scalar!(
+        r#"
+        INSERT INTO atom_feeds (user_did, token_hash, notification_types)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (tenant_id, user_did) DO UPDATE SET notification_types = EXCLUDED.notification_types
+        RETURNING (xmax = 0) AS "created!"
+        "#,
+        did,
+        hash_token(&token),
+        notification_types
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(created.then_some(token))
+}
+
+// Replace the feed's token, so the old URL stops working. None when the feed is off.
+pub async fn rotate(tx: &mut Transaction<'_, Postgres>, did: &str) -> Result<Option<String>> {
+    let token = generate_token();
+    let rotated = sqlx::query!(
+        "UPDATE atom_feeds SET token_hash = $2, rotated_at = NOW() WHERE user_did = $1",
+        did,
+        hash_token(&token)
+    )
+    .execute(&mut **tx)
+    .await?
+    .rows_affected();
+    Ok((rotated > 0).then_some(token))
+}
+
+pub async fn disable(tx: &mut Transaction<'_, Postgres>, did: &str) -> Result<bool> {
+    let deleted = sqlx::query!("DELETE FROM atom_feeds WHERE user_did = $1", did)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected();
+    Ok(deleted > 0)
+}
+
+// The feed behind a token, None for unknown tokens
+pub async fn render(pool: &Pool<Postgres>, token: &str) -> Result<Option<String>> {
+    let Some(feed) = sqlx::query!(
+        "SELECT tenant_id, user_did, notification_types FROM atom_feeds WHERE token_hash = $1",
+        hash_token(token)
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    // One row per notification and record, whichever of the app's devices it went to
+    let entries = sqlx::query_as!(
+        FeedEntry,
+        r#"
+        SELECT notification_id AS "notification_id!", title AS "title!", body AS "body!", uri,
+            created_at AS "created_at!"
+        FROM (
+            SELECT DISTINCT ON (COALESCE(data->>'record_uri', notification_id::text), notification_type)
+                notification_id, title, body, data->>'uri' AS uri, created_at
+            FROM notification_history
+            WHERE tenant_id = $5 AND user_did = $1 AND status <> 'retracted'
+              AND created_at > NOW() - INTERVAL '1 day' * $2
+              AND ($3::text[] IS NULL OR notification_type = ANY($3))
+            ORDER BY COALESCE(data->>'record_uri', notification_id::text), notification_type, created_at
+        ) entries
+        ORDER BY created_at DESC
+        LIMIT $4
+        "#,
+        feed.user_did,
+        FEED_WINDOW_DAYS,
+        feed.notification_types.as_deref(),
+        FEED_ENTRIES,
+        feed.tenant_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Some(render_feed(&hash_token(token), &entries)))
+}
+
+fn render_feed(feed_id: &str, entries: &[FeedEntry]) -> String {
+    let updated = entries
+        .first()
+        .map(|entry| entry.created_at)
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    // Derived from the token, so a rotated feed reads as a new one
+    xml.push_str(&format!("  <id>urn:sha256:{}</id>\n", feed_id));
+    xml.push_str("  <title>Bluesky notifications</title>\n");
+    xml.push_str(&format!("  <updated>{}</updated>\n", rfc3339(updated)));
+    xml.push_str("  <author><name>Bluesky</name></author>\n");
+    for entry in entries {
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!("    <id>urn:uuid:{}</id>\n", entry.notification_id));
+        xml.push_str(&format!("    <title>{}</title>\n", escape(&entry.title)));
+        xml.push_str(&format!("    <updated>{}</updated>\n", rfc3339(entry.created_at)));
+        if let Some(url) = entry.uri.as_deref().and_then(web_url) {
+            xml.push_str(&format!("    <link href=\"{}\"/>\n", escape(&url)));
+        }
+        xml.push_str(&format!("    <content type=\"text\">{}</content>\n", escape(&entry.body)));
+        xml.push_str("  </entry>\n");
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn rfc3339(at: OffsetDateTime) -> String {
+    at.format(&Rfc3339).unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters other than whitespace aren't allowed in XML
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Where the notification's subject opens on the web: the post, or the account
+fn web_url(uri: &str) -> Option<String> {
+    let mut parts = uri.strip_prefix("at://")?.split('/');
+    let did = parts.next().filter(|did| !did.is_empty())?;
+    match (parts.next(), parts.next()) {
+        (Some("app.bsky.feed.post"), Some(rkey)) => Some(format!("https://bsky.app/profile/{}/post/{}", did, rkey)),
+        _ => Some(format!("https://bsky.app/profile/{}", did)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_history_as_atom() {
+        let entries = [FeedEntry {
+            notification_id: Uuid::nil(),
+            title: "Alice <@alice> replied".to_string(),
+            body: "Tom & Jerry\u{7}".to_string(),
+            uri: Some("at://did:plc:alice/app.bsky.feed.post/3kabc".to_string()),
+            created_at: OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap(),
+        }];
+        let xml = render_feed("ab12", &entries);
+        assert!(xml.contains("<id>urn:sha256:ab12</id>"));
+        assert!(xml.contains("<updated>2023-11-14T22:13:20Z</updated>"));
+        assert!(xml.contains("<title>Alice &lt;@alice&gt; replied</title>"));
+        assert!(xml.contains("<link href=\"https://bsky.app/profile/did:plc:alice/post/3kabc\"/>"));
+        assert!(xml.contains("<content type=\"text\">Tom &amp; Jerry</content>"));
+
+        assert_eq!(web_url("at://did:plc:bob").as_deref(), Some("https://bsky.app/profile/did:plc:bob"));
+        assert_eq!(web_url("https://example.com"), None);
+        assert_eq!(generate_token().len(), 64);
+        assert_eq!(feed_path("abc"), "/feeds/abc.atom");
+    }
+}
@@ -0,0 +1,484 @@
+// backfill.rs
+// Reconstructing notifications a few users missed, from repo archives. When the service
+// was down longer than the relay's replay window, or a user's device was registered
+// late, the firehose can't give those events back. An admin starts a backfill with
+// POST /admin/backfill, naming the recipients, the window and the repos to read: DIDs,
+// whose repos are fetched from their PDS with com.atproto.sync.getRepo, and/or CAR
+// files on the server's disk, such as relay backups, gzip-compressed or not. Each
+// repo's records created inside the window go through the firehose handler as if they
+// had just been committed, marked with the "backfill" event source and with the
+// recipients attached, and the filter drops every other candidate, so nobody else is
+// notified twice. Everything after the filter (mutes, preferences, quiet hours) applies
+// as usual.
+//
+// A repo archive is a snapshot: records deleted since aren't in it and nothing is
+// retracted. Records are dated by their createdAt, which the author's client sets, so
+// the window should cover only the stretch that was missed; the short-lived dedup
+// cache won't catch repeats of notifications sent long ago. Jobs run one source at a
+// time in this process, and GET /admin/backfill reports their progress, keeping the
+// last MAX_JOBS_KEPT.
+use anyhow::{anyhow, bail, Context, Result};
+use atrium_repo::blockstore::{AsyncBlockStoreRead, CarStore};
+use ipld_core::cid::Cid;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::collections::VecDeque;
+use std::io::{Cursor, Read};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::channel::PipelineSender;
+use crate::decoder::DecodedOp;
+use crate::did_resolver::DidResolver;
+use crate::firehose::FirehoseHandler;
+use crate::models::{BlueskyEvent, EventOrigin, EventSource};
+use crate::relationship_manager::RelationshipManager;
+use crate::repo_state::RepoState;
+use crate::retraction::Retractions;
+
+const MAX_JOBS_KEPT: usize = 20;
+// Archives are read into memory whole
+const MAX_ARCHIVE_BYTES: usize = 512 * 1024 * 1024;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug, Deserialize)]
+pub struct BackfillRequest {
+    // Users whose missed notifications are reconstructed
+    pub recipients: Vec<String>,
+    // Repos fetched from their PDS
+    #[serde(default)]
+    pub repos: Vec<String>,
+    // Paths of CAR files readable by the server
+    #[serde(default)]
+    pub archives: Vec<String>,
+    // RFC 3339; records created from `since` up to `until` (now when absent) are read
+    pub since: String,
+    pub until: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackfillStatus {
+    Running,
+    Done,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackfillProgress {
+    pub id: Uuid,
+    pub status: BackfillStatus,
+    #[serde(with = "time::serde::rfc3339")]
+    pub started_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub finished_at: Option<OffsetDateTime>,
+    pub recipients: Vec<String>,
+    pub sources_total: usize,
+    pub sources_done: usize,
+    // The repo or archive being read
+    pub current: Option<String>,
+    // Records in the repos read so far, and those created inside the window
+    pub records_read: u64,
+    pub records_in_window: u64,
+    pub events_queued: u64,
+    // Sources that couldn't be read, with the reason
+    pub failures: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    Repo(String),
+    Archive(String),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Repo(did) => write!(f, "{}", did),
+            Source::Archive(path) => write!(f, "file:{}", path),
+        }
+    }
+}
+
+// Creation times a backfill reads records from
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    since: chrono::DateTime<chrono::FixedOffset>,
+    until: Option<chrono::DateTime<chrono::FixedOffset>>,
+}
+
+impl Window {
+    fn contains(&self, record: &serde_json::Value) -> bool {
+        let Some(created_at) = record
+            .get("createdAt")
+            .and_then(|v| v.as_str())
+            .and_then(|v| chrono::DateTime::parse_from_rfc3339(v).ok())
+        else {
+            return false;
+        };
+        created_at >= self.since && self.until.is_none_or(|until| created_at < until)
+    }
+}
+
+// A repo's records from inside the window, as create operations
+struct RepoRecords {
+    did: String,
+    rev: String,
+    records: u64,
+    ops: Vec<DecodedOp>,
+}
+
+#[derive(Clone)]
+pub struct Backfills {
+    handler: Arc<FirehoseHandler>,
+    did_resolver: Arc<DidResolver>,
+    http_client: reqwest::Client,
+    jobs: Arc<Mutex<VecDeque<BackfillProgress>>>,
+}
+
+impl Backfills {
+    pub fn new(
+        event_sender: PipelineSender<BlueskyEvent>,
+        db_pool: Pool<Postgres>,
+        relationship_manager: Arc<RelationshipManager>,
+        retractions: Retractions,
+        repo_state: RepoState,
+        did_resolver: Arc<DidResolver>,
+    ) -> Self {
+        Self {
+            handler: Arc::new(FirehoseHandler::new(
+                event_sender,
+                db_pool,
+                relationship_manager,
+                retractions,
+                repo_state,
+                false,
+            )),
+            did_resolver,
+            http_client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(300))
+                .build()
+                .expect("Failed to create HTTP client"),
+            jobs: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    // Check the request and start the job in the background
+    pub fn start(&self, req: BackfillRequest) -> Result<BackfillProgress> {
+        let since = chrono::DateTime::parse_from_rfc3339(&req.since).context("since must be an RFC 3339 timestamp")?;
+        let until = req
+            .until
+            .as_deref()
+            .map(chrono::DateTime::parse_from_rfc3339)
+            .transpose()
+            .context("until must be an RFC 3339 timestamp")?;
+        if until.is_some_and(|until| until <= since) {
+            bail!("until must be after since");
+        }
+        if req.recipients.is_empty() || req.recipients.iter().any(|did| !did.starts_with("did:")) {
+            bail!("recipients must be a non-empty list of DIDs");
+        }
+        if req.repos.iter().any(|did| !did.starts_with("did:")) {
+            bail!("repos must be DIDs");
+        }
+        let sources: Vec<Source> = req
+            .repos
+            .into_iter()
+            .map(Source::Repo)
+            .chain(req.archives.into_iter().map(Source::Archive))
+            .collect();
+        if sources.is_empty() {
+            bail!("Name at least one repo or archive");
+        }
+
+        let progress = BackfillProgress {
+            id: Uuid::new_v4(),
+            status: BackfillStatus::Running,
+            started_at: OffsetDateTime::now_utc(),
+            finished_at: None,
+            recipients: req.recipients,
+            sources_total: sources.len(),
+            sources_done: 0,
+            current: None,
+            records_read: 0,
+            records_in_window: 0,
+            events_queued: 0,
+            failures: Vec::new(),
+        };
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            jobs.push_front(progress.clone());
+            jobs.truncate(MAX_JOBS_KEPT);
+        }
+
+        info!(id = %progress.id, sources = sources.len(), recipients = progress.recipients.len(), "Starting backfill");
+        tokio::spawn(self.clone().run(
+            progress.id,
+            sources,
+            Window { since, until },
+            progress.recipients.clone(),
+        ));
+        Ok(progress)
+    }
+
+    // Most recent first
+    pub fn jobs(&self) -> Vec<BackfillProgress> {
+        self.jobs.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn job(&self, id: Uuid) -> Option<BackfillProgress> {
+        self.jobs.lock().unwrap().iter().find(|job| job.id == id).cloned()
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut BackfillProgress)) {
+        if let Some(job) = self.jobs.lock().unwrap().iter_mut().find(|job| job.id == id) {
+            f(job);
+        }
+    }
+
+    async fn run(self, id: Uuid, sources: Vec<Source>, window: Window, recipients: Vec<String>) {
+        for source in sources {
+            self.update(id, |job| job.current = Some(source.to_string()));
+            match self.read_source(&source, window).await {
+                Ok(repo) => {
+                    let origin = EventOrigin {
+                        source: EventSource::Backfill,
+                        seq: None,
+                        rev: Some(repo.rev.clone()),
+                        received_at: chrono::Utc::now().timestamp_millis(),
+                        recipients: Some(recipients.clone()),
+                    };
+                    let queued = self.handler.handle_ops(&repo.did, &repo.ops, &origin).await;
+                    debug!(id = %id, source = %source, records = repo.records, queued, "Backfilled repo");
+                    self.update(id, |job| {
+                        job.records_read += repo.records;
+                        job.records_in_window += repo.ops.len() as u64;
+                        job.events_queued += queued as u64;
+                    });
+                }
+                Err(e) => {
+                    warn!(id = %id, source = %source, "Failed to backfill: {:#}", e);
+                    self.update(id, |job| job.failures.push(format!("{}: {:#}", source, e)));
+                }
+            }
+            self.update(id, |job| job.sources_done += 1);
+        }
+
+        self.update(id, |job| {
+            job.status = BackfillStatus::Done;
+            job.current = None;
+            job.finished_at = Some(OffsetDateTime::now_utc());
+            info!(
+                id = %id,
+                events_queued = job.events_queued,
+                failures = job.failures.len(),
+                "Backfill finished"
+            );
+        });
+    }
+
+    async fn read_source(&self, source: &Source, window: Window) -> Result<RepoRecords> {
+        let bytes = match source {
+            Source::Repo(did) => {
+                let pds = self.did_resolver.get_pds_endpoint(did).await?;
+                let url = format!("{}/xrpc/com.atproto.sync.getRepo?did={}", pds.trim_end_matches('/'), did);
+                let response = self.http_client.get(&url).send().await?.error_for_status()?;
+                if response.content_length().is_some_and(|length| length as usize > MAX_ARCHIVE_BYTES) {
+                    bail!("Repo is larger than {} bytes", MAX_ARCHIVE_BYTES);
+                }
+                response.bytes().await?.to_vec()
+            }
+            Source::Archive(path) => tokio::fs::read(path)
+                .await
+                .with_context(|| format!("Failed to read {}", path))?,
+        };
+
+        // Decompressing and decoding a whole repo is CPU work, kept off the runtime
+        let repo = tokio::task::spawn_blocking(move || {
+            let car = decompress(bytes)?;
+            futures::executor::block_on(read_repo(car, window))
+        })
+        .await??;
+        if let Source::Repo(did) = source {
+            if &repo.did != did {
+                bail!("getRepo returned the repo of {}", repo.did);
+            }
+        }
+        Ok(repo)
+    }
+}
+
+// Gzip archives are recognised by their magic bytes
+fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    if !bytes.starts_with(&GZIP_MAGIC) {
+        return Ok(bytes);
+    }
+    let mut car = Vec::new();
+    flate2::read::GzDecoder::new(&bytes[..])
+        .take(MAX_ARCHIVE_BYTES as u64 + 1)
+        .read_to_end(&mut car)
+        .context("Failed to decompress archive")?;
+    if car.len() > MAX_ARCHIVE_BYTES {
+        bail!("Archive decompresses to more than {} bytes", MAX_ARCHIVE_BYTES);
+    }
+    Ok(car)
+}
+
+// The signed commit a repo CAR file is rooted at
+#[derive(Deserialize)]
+struct RepoCommit {
+    did: String,
+    rev: String,
+    // Root of the record tree
+    data: Cid,
+}
+
+// A node of the repo's Merkle search tree
+#[derive(Deserialize)]
+struct TreeNode {
+    // Subtree of keys before the first entry
+    l: Option<Cid>,
+    e: Vec<TreeEntry>,
+}
+
+#[derive(Deserialize)]
+struct TreeEntry {
+    // Length of the prefix shared with the previous entry's key
+    p: usize,
+    // The rest of the key
+    k: KeySuffix,
+    // The record
+    v: Cid,
+    // Subtree of keys between this entry and the next
+    t: Option<Cid>,
+}
+
+// dag-cbor byte string
+struct KeySuffix(Vec<u8>);
+
+impl<'de> Deserialize<'de> for KeySuffix {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = KeySuffix;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a byte string")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<KeySuffix, E> {
+                Ok(KeySuffix(bytes.to_vec()))
+            }
+        }
+        deserializer.deserialize_bytes(Visitor)
+    }
+}
+
+// Keys within a node are prefix-compressed against the previous entry's key
+fn expand_keys<'a>(entries: impl Iterator<Item = (usize, &'a [u8])>) -> Result<Vec<String>> {
+    let mut keys: Vec<String> = Vec::new();
+    for (prefix, suffix) in entries {
+        let previous = keys.last().map(String::as_bytes).unwrap_or_default();
+        if prefix > previous.len() {
+            bail!("Tree entry shares more than the previous key");
+        }
+        let mut key = previous[..prefix].to_vec();
+        key.extend_from_slice(suffix);
+        keys.push(String::from_utf8(key).context("Tree key is not UTF-8")?);
+    }
+    Ok(keys)
+}
+
+// Walk the repo's tree and decode the records of the collections the firehose handler
+// reads that were created inside the window
+async fn read_repo(car: Vec<u8>, window: Window) -> Result<RepoRecords> {
+    let mut store = CarStore::open(Cursor::new(car))
+        .await
+        .map_err(|e| anyhow!("Failed to open CAR file: {}", e))?;
+    let root = store.roots().next().ok_or_else(|| anyhow!("CAR file has no root"))?;
+    let commit: RepoCommit = serde_ipld_dagcbor::from_slice(&read_block(&mut store, root).await?)
+        .context("Failed to parse repo commit")?;
+
+    let mut repo = RepoRecords {
+        did: commit.did,
+        rev: commit.rev,
+        records: 0,
+        ops: Vec::new(),
+    };
+    let mut nodes = vec![commit.data];
+    while let Some(node) = nodes.pop() {
+        let node: TreeNode = serde_ipld_dagcbor::from_slice(&read_block(&mut store, node).await?)
+            .context("Failed to parse repo tree node")?;
+        nodes.extend(node.l);
+        let keys = expand_keys(node.e.iter().map(|entry| (entry.p, entry.k.0.as_slice())))?;
+
+        for (entry, path) in node.e.iter().zip(keys) {
+            nodes.extend(entry.t);
+            repo.records += 1;
+
+            let collection = path.split_once('/').map(|(collection, _)| collection).unwrap_or_default();
+            if !crate::decoder::is_decoded_collection(collection) {
+                continue;
+            }
+            // Partial archives may leave record blocks out
+            let Ok(block) = read_block(&mut store, entry.v).await else {
+                debug!(path = %path, "Record block missing from archive");
+                continue;
+            };
+            let record = match crate::decoder::deserialize_record(collection, &block) {
+                Ok(record) => record,
+                Err(e) => {
+                    debug!("Failed to deserialize {}: {}", collection, e);
+                    continue;
+                }
+            };
+            if window.contains(&record) {
+                repo.ops.push(DecodedOp {
+                    action: "create".to_string(),
+                    path,
+                    cid: Some(entry.v.to_string()),
+                    record: Some(record),
+                });
+            }
+        }
+    }
+    Ok(repo)
+}
+
+async fn read_block(store: &mut impl AsyncBlockStoreRead, cid: Cid) -> Result<Vec<u8>> {
+    store
+        .read_block(cid)
+        .await
+        .map_err(|e| anyhow!("Block {} not found: {}", cid, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_tree_keys_and_reads_records_in_the_window() {
+        let entries: [(usize, &[u8]); 3] = [
+            (0, b"app.bsky.feed.like/3kaaa"),
+            (23, b"b"),
+            (14, b"post/3kccc"),
+        ];
+        assert_eq!(
+            expand_keys(entries.into_iter()).unwrap(),
+            vec!["app.bsky.feed.like/3kaaa", "app.bsky.feed.like/3kaab", "app.bsky.feed.post/3kccc"]
+        );
+        assert!(expand_keys([(4, &b"x"[..])].into_iter()).is_err());
+
+        let window = Window {
+            since: chrono::DateTime::parse_from_rfc3339("2025-05-01T00:00:00Z").unwrap(),
+            until: Some(chrono::DateTime::parse_from_rfc3339("2025-05-02T00:00:00Z").unwrap()),
+        };
+        assert!(window.contains(&serde_json::json!({ "createdAt": "2025-05-01T12:00:00.000Z" })));
+        assert!(!window.contains(&serde_json::json!({ "createdAt": "2025-05-02T00:00:00Z" })));
+        assert!(!window.contains(&serde_json::json!({ "subject": "did:plc:a" })));
+
+        assert_eq!(decompress(b"car".to_vec()).unwrap(), b"car");
+    }
+}
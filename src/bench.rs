@@ -0,0 +1,523 @@
+// bench.rs
+// `bench-load`: drive the event filter with a synthetic firehose at a fixed rate and
+// report throughput and end-to-end latency (event injected -> notification queued).
+// Relevant events target real registered users, so the filter does its real
+// per-device DB work (short of bumping their unread counts), but nothing reaches
+// APNs: notifications are counted and dropped. Synthetic accounts and posts are
+// primed into the resolver caches so the run measures the pipeline rather than the
+// network.
+//
+// `bench-decode`: record commit frames off the relay and decode them inline on a
+// runtime worker, then through the decoder's blocking pool (see decoder.rs), reporting
+// frames per second and how late tasks sharing the runtime wake up in each case.
+use anyhow::{anyhow, Result};
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::channel::{self, OverflowPolicy};
+use crate::config::Config;
+use crate::models::{BlueskyEvent, EventOrigin};
+use crate::post_resolver::PostContent;
+use crate::subscription::Subscription;
+
+// Synthetic posts per registered user that likes and replies point at
+const POSTS_PER_USER: u64 = 4;
+
+#[derive(Debug, Clone)]
+struct LoadOptions {
+    rate: u32,
+    duration: Duration,
+    relevant_fraction: f64,
+    // Relative weights of posts, likes and follows
+    mix: [u32; 3],
+}
+
+impl LoadOptions {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut options = Self {
+            rate: 200,
+            duration: Duration::from_secs(60),
+            relevant_fraction: 0.05,
+            mix: [60, 30, 10],
+        };
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("{} needs a value", flag))?;
+            match flag.as_str() {
+                "--rate" => options.rate = value.parse()?,
+                "--duration" => options.duration = Duration::from_secs(value.parse()?),
+                "--relevant" => options.relevant_fraction = value.parse::<f64>()?.clamp(0.0, 1.0),
+                "--mix" => {
+                    let weights = value
+                        .split(':')
+                        .map(str::parse)
+                        .collect::<Result<Vec<u32>, _>>()?;
+                    options.mix = weights
+                        .try_into()
+                        .map_err(|_| anyhow!("--mix takes posts:likes:follows"))?;
+                }
+                _ => return Err(anyhow!("Unknown option {}", flag)),
+            }
+        }
+
+        if options.rate == 0 || options.mix.iter().sum::<u32>() == 0 {
+            return Err(anyhow!("--rate and --mix must be non-zero"));
+        }
+        Ok(options)
+    }
+}
+
+fn print_usage() {
+    println!("Usage:");
+    println!("  bench-load [--rate <events/s>] [--duration <secs>] [--relevant <fraction>] [--mix <posts:likes:follows>]");
+    println!("Defaults: --rate 200 --duration 60 --relevant 0.05 --mix 60:30:10");
+}
+
+// Small xorshift generator; the load only needs to look random, not be random
+struct Rng(u64);
+
+impl Rng {
+    fn seeded() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Self(nanos | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound.max(1)
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        (self.next() as f64 / u64::MAX as f64) < probability
+    }
+}
+
+struct EventFactory {
+    rng: Rng,
+    registered: Vec<String>,
+    options: LoadOptions,
+    seq: u64,
+}
+
+impl EventFactory {
+    // Returns the event and whether it was generated to be relevant
+    fn next(&mut self) -> (BlueskyEvent, bool) {
+        self.seq += 1;
+        let seq = self.seq;
+        let relevant = !self.registered.is_empty() && self.rng.chance(self.options.relevant_fraction);
+        let target = if relevant {
+            self.registered[self.rng.below(self.registered.len() as u64) as usize].clone()
+        } else {
+            format!("did:plc:benchother{:06}", self.rng.below(100_000))
+        };
+        let target_post = format!(
+            "at://{}/app.bsky.feed.post/benchpost{}",
+            target,
+            self.rng.below(POSTS_PER_USER)
+        );
+
+        let [posts, likes, _] = self.options.mix;
+        let roll = self.rng.below(self.options.mix.iter().sum::<u32>() as u64) as u32;
+        let (collection, record) = if roll < posts {
+            let mut record = json!({
+                "$type": "app.bsky.feed.post",
+                "text": format!("Synthetic load post {}", seq),
+                "createdAt": chrono::Utc::now().to_rfc3339(),
+            });
+            if relevant && seq.is_multiple_of(2) {
+                record["facets"] = json!([{
+                    "index": { "byteStart": 0, "byteEnd": 9 },
+                    "features": [{ "$type": "app.bsky.richtext.facet#mention", "did": target }],
+                }]);
+            } else if relevant {
+                let parent = json!({ "uri": target_post, "cid": "bafybenchparent" });
+                record["reply"] = json!({ "root": parent, "parent": parent });
+            }
+            ("app.bsky.feed.post", record)
+        } else if roll < posts + likes {
+            (
+                "app.bsky.feed.like",
+                json!({
+                    "$type": "app.bsky.feed.like",
+                    "subject": { "uri": target_post, "cid": "bafybenchsubject" },
+                    "createdAt": chrono::Utc::now().to_rfc3339(),
+                }),
+            )
+        } else {
+            (
+                "app.bsky.graph.follow",
+                json!({
+                    "$type": "app.bsky.graph.follow",
+                    "subject": target,
+                    "createdAt": chrono::Utc::now().to_rfc3339(),
+                }),
+            )
+        };
+
+        let event = BlueskyEvent {
+            op: "create".to_string(),
+            path: format!("{}/bench{}", collection, seq),
+            cid: format!("bafybench{}", seq),
+            author: author_did(seq),
+            record,
+            timestamp: chrono::Utc::now().timestamp(),
+            origin: EventOrigin {
+                seq: Some(seq as i64),
+                received_at: chrono::Utc::now().timestamp_millis(),
+                ..Default::default()
+            },
+        };
+        (event, relevant)
+    }
+}
+
+// A fresh author per event lets notifications be matched back to their event
+fn author_did(seq: u64) -> String {
+    format!("did:plc:bench{:012}", seq)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() as f64 - 1.0) * p).round() as usize;
+    sorted[index]
+}
+
+pub async fn run_command(config: &Config, db_pool: &Pool<Postgres>, args: &[String]) -> Result<()> {
+    let options = match LoadOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            print_usage();
+            return Ok(());
+        }
+    };
+
+    let registered = crate::db::get_registered_users(db_pool).await?;
+    if registered.is_empty() && options.relevant_fraction > 0.0 {
+        println!("No registered users; every event will be irrelevant");
+    }
+
+    // Synthetic accounts stay out of the shared cache tier
+    let shared_cache = crate::shared_cache::SharedCache::default();
+    let did_resolver = Arc::new(crate::did_resolver::DidResolver::new(db_pool.clone(), 1, shared_cache.clone()));
+    let post_resolver = Arc::new(crate::post_resolver::PostResolver::new(
+        db_pool.clone(),
+        60,
+        config.bsky_api_url.clone(),
+        shared_cache.clone(),
+    ));
+    for (i, did) in registered.iter().enumerate() {
+        did_resolver.prime(did, &format!("user{}.bench.test", i)).await;
+        for k in 0..POSTS_PER_USER {
+            post_resolver
+                .prime(
+                    &format!("at://{}/app.bsky.feed.post/benchpost{}", did, k),
+                    PostContent {
+                        text: "Synthetic post for load testing".to_string(),
+                        labels: Vec::new(),
+                        image_url: None,
+                    },
+                )
+                .await;
+        }
+    }
+
+    let relationship_manager = Arc::new(crate::relationship_manager::RelationshipManager::new(db_pool.clone(), shared_cache));
+    let profile_resolver = Arc::new(crate::profile_resolver::ProfileResolver::new(config.bsky_api_url.clone(), 360));
+    let feature_flags = Arc::new(crate::feature_flags::FeatureFlags::new(db_pool.clone()).await?);
+    let experiments = Arc::new(crate::experiments::Experiments::new(db_pool.clone(), feature_flags).await?);
+    let memory_guard = Arc::new(crate::memory_guard::MemoryGuard::new(
+        None,
+        None,
+        did_resolver.clone(),
+        post_resolver.clone(),
+        profile_resolver.clone(),
+        relationship_manager.clone(),
+    ));
+
+    let (event_sender, event_receiver) =
+        channel::channel("bench_events", config.event_channel_capacity, OverflowPolicy::Block, None);
+    let (notification_sender, mut notification_receiver) = channel::channel(
+        "bench_notifications",
+        config.notification_channel_capacity,
+        OverflowPolicy::Block,
+        None,
+    );
+
+    tokio::spawn(crate::filter::run_event_filter(
+        event_receiver,
+        notification_sender,
+        crate::filter::FilterContext {
+            db_pool: db_pool.clone(),
+            did_resolver: did_resolver.clone(),
+            post_resolver,
+            relationship_manager: relationship_manager.clone(),
+            profile_resolver: profile_resolver.clone(),
+            experiments,
+            fanout_limits: crate::filter::FanoutLimits::from_config(config),
+            content_fallbacks: crate::content_fallback::ContentFallbacks::from_config(config),
+            policy: crate::delivery_policy::DeliveryPolicy::new(
+                db_pool.clone(),
+                relationship_manager,
+                profile_resolver.clone(),
+                crate::cooldown::Cooldowns::from_config(config),
+                Arc::new(crate::quota::QuotaTracker::new(db_pool.clone())),
+            ),
+            aggregator: crate::aggregation::Aggregator::from_config(config),
+            dedup: crate::dedup::EventDedup::from_config(config),
+            retractions: crate::retraction::Retractions::from_config(config),
+            excerpts: crate::excerpt::ExcerptLimits::from_config(config),
+            memory_guard,
+            plugins: Arc::new(crate::plugins::PluginHost::new(Vec::new())),
+            copy_script: None,
+            cache_generation: Arc::new(crate::cache_sync::CacheGeneration::default()),
+            trace: crate::user_trace::UserTrace::default(),
+            records: crate::decoder::RecordCache::new(0),
+            ranking: crate::ranking::Ranking::default(),
+            count_unread: false,
+        },
+    ));
+
+    // Injection time of each relevant event, keyed by its unique author
+    let pending: Arc<Mutex<HashMap<String, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
+    let pending_collector = pending.clone();
+    let collector = tokio::spawn(async move {
+        let mut notifications = 0u64;
+        let mut latencies = Vec::new();
+        while let Some(payload) = notification_receiver.recv().await {
+            notifications += 1;
+            let injected = payload
+                .data
+                .get("author_did")
+                .and_then(|author| pending_collector.lock().unwrap().remove(author));
+            if let Some(injected) = injected {
+                latencies.push(injected.elapsed());
+            }
+        }
+        (notifications, latencies)
+    });
+
+    println!(
+        "Injecting {} events/s for {}s ({:.1}% relevant to {} registered users, mix {:?})",
+        options.rate,
+        options.duration.as_secs(),
+        options.relevant_fraction * 100.0,
+        registered.len(),
+        options.mix
+    );
+
+    let mut factory = EventFactory {
+        rng: Rng::seeded(),
+        registered,
+        options: options.clone(),
+        seq: 0,
+    };
+
+    // Inject in 10ms slices so high rates don't depend on timer resolution
+    let started = Instant::now();
+    let mut ticker = tokio::time::interval(Duration::from_millis(10));
+    let mut injected = 0u64;
+    let mut relevant = 0u64;
+    while started.elapsed() < options.duration {
+        ticker.tick().await;
+        let due = (started.elapsed().as_secs_f64() * options.rate as f64) as u64;
+        while injected < due {
+            let (event, is_relevant) = factory.next();
+            if is_relevant {
+                relevant += 1;
+                did_resolver.prime(&event.author, &format!("bench{}.bench.test", factory.seq)).await;
+                profile_resolver.prime(&event.author).await;
+                pending.lock().unwrap().insert(event.author.clone(), Instant::now());
+            }
+            if event_sender.send(event).await.is_err() {
+                return Err(anyhow!("Event filter stopped during the run"));
+            }
+            injected += 1;
+        }
+    }
+    let injection_time = started.elapsed();
+
+    // Let the filter drain what is queued before reporting
+    let drain_deadline = Instant::now() + Duration::from_secs(30);
+    while event_sender.capacity() < config.event_channel_capacity && Instant::now() < drain_deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    let total_time = started.elapsed();
+    drop(event_sender);
+    let (notifications, mut latencies) = collector.await?;
+    latencies.sort();
+
+    println!();
+    println!("Events injected:       {} in {:.1}s ({:.0}/s)", injected, injection_time.as_secs_f64(), injected as f64 / injection_time.as_secs_f64());
+    println!("Relevant events:       {}", relevant);
+    println!("Notifications queued:  {} ({:.1}/s)", notifications, notifications as f64 / total_time.as_secs_f64());
+    println!("Relevant, no push:     {} (preferences, mutes, quotas or no devices)", relevant.saturating_sub(latencies.len() as u64));
+    println!(
+        "Latency p50/p90/p99:   {:?} / {:?} / {:?} (max {:?})",
+        percentile(&latencies, 0.50),
+        percentile(&latencies, 0.90),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default()
+    );
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct DecodeOptions {
+    frames: usize,
+    concurrency: usize,
+}
+
+impl DecodeOptions {
+    fn parse(args: &[String], config: &Config) -> Result<Self> {
+        let mut options = Self {
+            frames: 5_000,
+            concurrency: config.firehose_decode_concurrency,
+        };
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("{} needs a value", flag))?;
+            match flag.as_str() {
+                "--frames" => options.frames = value.parse()?,
+                "--concurrency" => options.concurrency = value.parse()?,
+                _ => return Err(anyhow!("Unknown option {}", flag)),
+            }
+        }
+
+        if options.frames == 0 || options.concurrency == 0 {
+            return Err(anyhow!("--frames and --concurrency must be non-zero"));
+        }
+        Ok(options)
+    }
+}
+
+// Run `decoding` while one probe per runtime worker sleeps 1ms at a time, and return
+// how long decoding took and how late the probes woke up: the delay any other task on
+// the runtime, an API request say, would have seen
+async fn with_probes<F: std::future::Future<Output = ()>>(decoding: F) -> (Duration, Vec<Duration>) {
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let probes: Vec<_> = (0..tokio::runtime::Handle::current().metrics().num_workers())
+        .map(|_| {
+            let stop = stop.clone();
+            tokio::spawn(async move {
+                let mut lags = Vec::new();
+                while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    let slept = Instant::now();
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                    lags.push(slept.elapsed().saturating_sub(Duration::from_millis(1)));
+                }
+                lags
+            })
+        })
+        .collect();
+
+    let started = Instant::now();
+    decoding.await;
+    let elapsed = started.elapsed();
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+
+    let mut lags = Vec::new();
+    for probe in probes {
+        lags.extend(probe.await.unwrap_or_default());
+    }
+    lags.sort();
+    (elapsed, lags)
+}
+
+fn print_decode_run(label: &str, frames: usize, elapsed: Duration, lags: &[Duration]) {
+    println!(
+        "{:<22} {:.0} frames/s, runtime lag p50/p99/max {:?} / {:?} / {:?}",
+        label,
+        frames as f64 / elapsed.as_secs_f64(),
+        percentile(lags, 0.50),
+        percentile(lags, 0.99),
+        lags.last().copied().unwrap_or_default()
+    );
+}
+
+// `bench-decode`: record commit frames off the relay, then decode the same frames
+// twice, inline on a runtime worker as the consumer used to and through the Decoder's
+// blocking pool, reporting throughput and how late other tasks on the runtime woke up
+pub async fn run_decode_command(config: &Config, args: &[String]) -> Result<()> {
+    let options = match DecodeOptions::parse(args, config) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}", e);
+            println!("Usage:");
+            println!("  bench-decode [--frames <count>] [--concurrency <frames in flight>]");
+            println!("Defaults: --frames 5000 --concurrency FIREHOSE_DECODE_CONCURRENCY");
+            return Ok(());
+        }
+    };
+
+    println!("Recording {} commit frames from {}", options.frames, config.bsky_service_url);
+    let mut subscription = crate::firehose::RepoSubscription::new(&config.bsky_service_url, None).await?;
+    let deadline = Instant::now() + Duration::from_secs(300);
+    let mut frames = Vec::with_capacity(options.frames);
+    while frames.len() < options.frames {
+        if Instant::now() > deadline {
+            return Err(anyhow!("Recorded only {} frames in 5 minutes", frames.len()));
+        }
+        match subscription.next().await {
+            Some(Ok(crate::stream::frames::Frame::Message(Some(t), message))) if t == "#commit" => {
+                frames.push(message.body)
+            }
+            Some(Err(e)) => return Err(e),
+            _ => {}
+        }
+    }
+    drop(subscription);
+    let bytes: usize = frames.iter().map(Vec::len).sum();
+    println!("Recorded {} frames ({:.1} MB)", frames.len(), bytes as f64 / 1_000_000.0);
+    println!();
+
+    let records = crate::decoder::RecordCache::new(0);
+    let (elapsed, lags) = with_probes(async {
+        for body in &frames {
+            let _ = crate::decoder::decode("#commit", body, &records);
+            tokio::task::yield_now().await;
+        }
+    })
+    .await;
+    print_decode_run("Inline:", frames.len(), elapsed, &lags);
+
+    let (elapsed, lags) = with_probes(async {
+        let mut decoder = crate::decoder::Decoder::new(options.concurrency, records.clone());
+        for body in &frames {
+            while !decoder.has_room() {
+                decoder.next().await;
+            }
+            decoder.push("#commit".to_string(), body.clone());
+        }
+        while decoder.next().await.is_some() {}
+    })
+    .await;
+    print_decode_run(
+        &format!("Blocking pool ({}):", options.concurrency),
+        frames.len(),
+        elapsed,
+        &lags,
+    );
+    Ok(())
+}
@@ -0,0 +1,111 @@
+// build_info.rs
+// Which build is running and how, behind GET /admin/version, so operators can audit a
+// fleet replica by replica. The version comes from Cargo.toml; the commit and build
+// time come from GIT_COMMIT and BUILD_TIMESTAMP in the build environment (the release
+// pipeline sets them, e.g. GIT_COMMIT=$(git rev-parse HEAD) BUILD_TIMESTAMP=$(date -u
+// +%FT%TZ) cargo build --release) and are null in builds that didn't. Features are the
+// optional Cargo features compiled in; flags are the runtime feature flags as this
+// replica last loaded them. The cursor is the last firehose sequence number stored,
+// shared by every replica on the database. It's an admin route because the flags and
+// runtime details aren't for the public.
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::feature_flags::FeatureFlag;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: Option<&str> = option_env!("GIT_COMMIT");
+pub const BUILD_TIMESTAMP: Option<&str> = option_env!("BUILD_TIMESTAMP");
+
+// Optional Cargo features compiled into this binary
+pub fn compiled_features() -> Vec<&'static str> {
+    let features = [
+        ("wasm-plugins", cfg!(feature = "wasm-plugins")),
+        ("copy-scripts", cfg!(feature = "copy-scripts")),
+        ("redis-cache", cfg!(feature = "redis-cache")),
+    ];
+    features
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+pub struct RuntimeInfo {
+    pub worker_threads: usize,
+    pub alive_tasks: usize,
+    pub db_pool_max_connections: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FirehosePosition {
+    pub cursor: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    pub git_commit: Option<&'static str>,
+    pub built_at: Option<&'static str>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub started_at: OffsetDateTime,
+    pub uptime_secs: i64,
+    pub features: Vec<&'static str>,
+    pub feature_flags: Vec<FeatureFlag>,
+    pub runtime: RuntimeInfo,
+    pub firehose_cursor: Option<FirehosePosition>,
+}
+
+impl VersionInfo {
+    pub fn new(
+        started_at: OffsetDateTime,
+        mut feature_flags: Vec<FeatureFlag>,
+        runtime: RuntimeInfo,
+        firehose_cursor: Option<FirehosePosition>,
+    ) -> Self {
+        feature_flags.sort_by(|a, b| a.name.cmp(&b.name));
+        Self {
+            version: VERSION,
+            git_commit: GIT_COMMIT.filter(|commit| !commit.is_empty()),
+            built_at: BUILD_TIMESTAMP.filter(|built_at| !built_at.is_empty()),
+            started_at,
+            uptime_secs: (OffsetDateTime::now_utc() - started_at).whole_seconds(),
+            features: compiled_features(),
+            feature_flags,
+            runtime,
+            firehose_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_build_and_sorted_flags() {
+        let flag = |name: &str| FeatureFlag {
+            name: name.to_string(),
+            enabled: true,
+            rollout_percentage: 100,
+            description: None,
+        };
+        let started_at = OffsetDateTime::now_utc() - time::Duration::minutes(5);
+        let runtime = RuntimeInfo {
+            worker_threads: 4,
+            alive_tasks: 12,
+            db_pool_max_connections: 10,
+        };
+        let info = VersionInfo::new(started_at, vec![flag("b"), flag("a")], runtime, None);
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["version"], VERSION);
+        assert_eq!(json["feature_flags"][0]["name"], "a");
+        assert_eq!(json["runtime"]["worker_threads"], 4);
+        assert!(json["firehose_cursor"].is_null());
+        assert!((300..310).contains(&info.uptime_secs));
+    }
+}
@@ -0,0 +1,230 @@
+// cache_sync.rs
+// Propagates settings writes to the filter's in-memory caches. API handlers publish the
+// affected DID on a Postgres channel once their transaction commits (writes made from
+// the firehose and by erasure publish inside theirs, so the notification is only
+// delivered on commit) and every replica's listener invalidates that user's caches, so
+// a change applies to the very next event instead of after the periodic refresh. Only
+// registration changes make the filter reload its registered-user set.
+use anyhow::Result;
+use sqlx::postgres::PgListener;
+use sqlx::{PgExecutor, Pool, Postgres};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use crate::relationship_manager::RelationshipManager;
+
+pub const CHANNEL: &str = "notifier_cache_invalidation";
+
+// Bumped whenever a device or post subscription comes or goes
+#[derive(Default)]
+pub struct CacheGeneration(AtomicU64);
+
+impl CacheGeneration {
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn current(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+// Decides when the filter reloads its registered-user set: on any invalidation, or
+// after max_age as a fallback in case a notification was missed
+pub struct RefreshGate {
+    generation: u64,
+    refreshed_at: Instant,
+    max_age: Duration,
+}
+
+impl RefreshGate {
+    pub fn new(generation: u64, max_age: Duration) -> Self {
+        Self {
+            generation,
+            refreshed_at: Instant::now(),
+            max_age,
+        }
+    }
+
+    pub fn is_stale(&self, generation: u64) -> bool {
+        generation != self.generation || self.refreshed_at.elapsed() > self.max_age
+    }
+
+    pub fn mark_refreshed(&mut self, generation: u64) {
+        self.generation = generation;
+        self.refreshed_at = Instant::now();
+    }
+}
+
+// What changed for a DID, and so what replicas drop. Payloads are "<scope> <did>"; a
+// bare DID is a settings change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    // Preferences and relationship lists; only the user's cached entries are dropped
+    Settings,
+    // Devices and post subscriptions, which also reload the filter's user sets
+    Registration,
+    // One of the user's relationship caches, for blocks and follows applied from the
+    // firehose. Only that cache is dropped and the filter doesn't reload anything.
+    Blocks,
+    Follows,
+}
+
+impl Scope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Settings => "settings",
+            Scope::Registration => "registration",
+            Scope::Blocks => "blocks",
+            Scope::Follows => "follows",
+        }
+    }
+
+    fn parse_payload(payload: &str) -> (Self, &str) {
+        match payload.split_once(' ') {
+            Some(("registration", did)) => (Scope::Registration, did),
+            Some(("blocks", did)) => (Scope::Blocks, did),
+            Some(("follows", did)) => (Scope::Follows, did),
+            Some((_, did)) => (Scope::Settings, did),
+            None => (Scope::Settings, payload),
+        }
+    }
+}
+
+// Announce that a user's settings changed
+pub async fn publish<'e>(executor: impl PgExecutor<'e>, did: &str) -> Result<()> {
+    publish_scoped(executor, Scope::Settings, did).await
+}
+
+pub async fn publish_scoped<'e>(executor: impl PgExecutor<'e>, scope: Scope, did: &str) -> Result<()> {
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(CHANNEL)
+        .bind(format!("{} {}", scope.as_str(), did))
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// Listen for invalidations for the lifetime of the process, reconnecting on failure
+pub async fn run_listener(
+    db_pool: Pool<Postgres>,
+    generation: Arc<CacheGeneration>,
+    relationship_manager: Arc<RelationshipManager>,
+) {
+    loop {
+        if let Err(e) = listen(&db_pool, &generation, &relationship_manager).await {
+            warn!("Cache invalidation listener failed: {}", e);
+        }
+        // Anything published while disconnected was missed, so treat everything as stale
+        generation.bump();
+        relationship_manager.invalidate_all_caches();
+        if let Err(e) = relationship_manager.load_registered().await {
+            warn!("Failed to reload registered users: {}", e);
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+async fn listen(
+    db_pool: &Pool<Postgres>,
+    generation: &CacheGeneration,
+    relationship_manager: &RelationshipManager,
+) -> Result<()> {
+    let mut listener = PgListener::connect_with(db_pool).await?;
+    listener.listen(CHANNEL).await?;
+    info!("Listening for cache invalidations on {}", CHANNEL);
+
+    loop {
+        let notification = listener.recv().await?;
+        apply(notification.payload(), generation, relationship_manager).await;
+    }
+}
+
+// Drop what an invalidation payload names from this replica's caches
+async fn apply(payload: &str, generation: &CacheGeneration, relationship_manager: &RelationshipManager) {
+    let (scope, did) = Scope::parse_payload(payload);
+    debug!(did = %did, scope = scope.as_str(), "Invalidating cached settings");
+    match scope {
+        Scope::Settings => relationship_manager.invalidate_cache(did).await,
+        Scope::Registration => {
+            relationship_manager.invalidate_cache(did).await;
+            if let Err(e) = relationship_manager.refresh_registered(did).await {
+                warn!(did = %did, "Failed to recheck registration: {}", e);
+            }
+            generation.bump();
+        }
+        Scope::Blocks | Scope::Follows => relationship_manager.invalidate_local(scope.as_str(), did).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalidation_forces_refresh_on_next_event() {
+        let generation = CacheGeneration::default();
+        let mut gate = RefreshGate::new(generation.current(), Duration::from_secs(300));
+        assert!(!gate.is_stale(generation.current()));
+
+        generation.bump();
+        assert!(gate.is_stale(generation.current()));
+
+        gate.mark_refreshed(generation.current());
+        assert!(!gate.is_stale(generation.current()));
+
+        assert_eq!(Scope::parse_payload("blocks did:plc:a"), (Scope::Blocks, "did:plc:a"));
+        assert_eq!(Scope::parse_payload("registration did:plc:a"), (Scope::Registration, "did:plc:a"));
+        assert_eq!(Scope::parse_payload("did:plc:a"), (Scope::Settings, "did:plc:a"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database in DATABASE_URL"]
+    async fn settings_invalidation_reloads_vips_and_muted_words() {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect(&std::env::var("DATABASE_URL").unwrap())
+            .await
+            .unwrap();
+        let relationship_manager = RelationshipManager::new(pool.clone(), Default::default());
+        let generation = CacheGeneration::default();
+        let did = format!("did:plc:cache-sync-{}", uuid::Uuid::new_v4());
+        let post = serde_json::json!({ "text": "no spoilers please" });
+
+        sqlx::query("INSERT INTO user_vips (user_did, vip_did) VALUES ($1, 'did:plc:old')")
+            .bind(&did)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // Warm both caches, then change the rows underneath them
+        assert!(relationship_manager.get_vips(&did).await.unwrap().contains("did:plc:old"));
+        assert!(!relationship_manager.has_muted_word(&did, &post).await);
+
+        sqlx::query("UPDATE user_vips SET vip_did = 'did:plc:new' WHERE user_did = $1")
+            .bind(&did)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO user_muted_words (user_did, value, targets) VALUES ($1, 'spoilers', '{content}')")
+            .bind(&did)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(relationship_manager.get_vips(&did).await.unwrap().contains("did:plc:old"));
+        assert!(!relationship_manager.has_muted_word(&did, &post).await);
+
+        apply(&format!("settings {}", did), &generation, &relationship_manager).await;
+
+        let vips = relationship_manager.get_vips(&did).await.unwrap();
+        assert!(vips.contains("did:plc:new") && !vips.contains("did:plc:old"));
+        assert!(relationship_manager.has_muted_word(&did, &post).await);
+        // A settings change leaves the registered-user set alone
+        assert_eq!(generation.current(), 0);
+
+        sqlx::query("DELETE FROM user_vips WHERE user_did = $1").bind(&did).execute(&pool).await.unwrap();
+        sqlx::query("DELETE FROM user_muted_words WHERE user_did = $1").bind(&did).execute(&pool).await.unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+// channel.rs
+// Bounded pipeline channels with an explicit policy for what happens when full.
+use anyhow::{anyhow, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{Pool, Postgres};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{error, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // Wait for the consumer to make room (previous behavior)
+    Block,
+    // Evict the oldest queued item to make room
+    DropOldest,
+    // Write the item to the channel_outbox table and feed it back later
+    Spill,
+}
+
+impl OverflowPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "block" => Some(Self::Block),
+            "drop-oldest" | "drop_oldest" => Some(Self::DropOldest),
+            "spill" => Some(Self::Spill),
+            _ => None,
+        }
+    }
+}
+
+struct Shared<T> {
+    name: &'static str,
+    capacity: usize,
+    queue: Mutex<VecDeque<T>>,
+    item_ready: Notify,
+    space_ready: Notify,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+    // Set by close(): the receiver gets what's queued and then None
+    closed: AtomicBool,
+    // Spilled items may be waiting in the outbox; new items queue behind them there so
+    // the receiver still sees them in order
+    backlog: AtomicBool,
+}
+
+impl<T> Shared<T> {
+    fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    fn record_depth(&self, depth: usize) {
+        crate::metrics::CHANNEL_DEPTH
+            .with_label_values(&[self.name])
+            .set(depth as f64);
+    }
+
+    fn try_push(&self, item: T) -> Result<(), T> {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.len() >= self.capacity {
+                return Err(item);
+            }
+            queue.push_back(item);
+            self.record_depth(queue.len());
+        }
+        self.item_ready.notify_one();
+        Ok(())
+    }
+
+    async fn push_blocking(&self, mut item: T) -> Result<()> {
+        loop {
+            // Register interest before checking so a concurrent recv can't be missed
+            let space = self.space_ready.notified();
+            match self.try_push(item) {
+                Ok(()) => return Ok(()),
+                Err(back) => item = back,
+            }
+            if !self.receiver_alive.load(Ordering::Acquire) || self.closed.load(Ordering::Acquire) {
+                return Err(anyhow!("{} channel closed", self.name));
+            }
+            space.await;
+        }
+    }
+
+    // Returns true if an item had to be evicted
+    fn push_evicting(&self, item: T) -> bool {
+        let evicted = {
+            let mut queue = self.queue.lock().unwrap();
+            let evicted = queue.len() >= self.capacity && queue.pop_front().is_some();
+            queue.push_back(item);
+            self.record_depth(queue.len());
+            evicted
+        };
+        self.item_ready.notify_one();
+        evicted
+    }
+}
+
+pub struct PipelineSender<T> {
+    shared: Arc<Shared<T>>,
+    policy: OverflowPolicy,
+    db_pool: Option<Pool<Postgres>>,
+}
+
+pub struct PipelineReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+// Create a bounded channel. `db_pool` is required for the spill policy; without it
+// spilling falls back to blocking.
+pub fn channel<T>(
+    name: &'static str,
+    capacity: usize,
+    policy: OverflowPolicy,
+    db_pool: Option<Pool<Postgres>>,
+) -> (PipelineSender<T>, PipelineReceiver<T>) {
+    let shared = Arc::new(Shared {
+        name,
+        capacity: capacity.max(1),
+        queue: Mutex::new(VecDeque::with_capacity(capacity.max(1))),
+        item_ready: Notify::new(),
+        space_ready: Notify::new(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+        closed: AtomicBool::new(false),
+        // Items spilled before a restart are drained first
+        backlog: AtomicBool::new(policy == OverflowPolicy::Spill && db_pool.is_some()),
+    });
+
+    (
+        PipelineSender {
+            shared: shared.clone(),
+            policy,
+            db_pool,
+        },
+        PipelineReceiver { shared },
+    )
+}
+
+impl<T> PipelineSender<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    // Remaining capacity before the overflow policy kicks in
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity.saturating_sub(self.shared.len())
+    }
+
+    // Items waiting for the receiver
+    pub fn queued(&self) -> usize {
+        self.shared.len()
+    }
+
+    // Close the channel for every sender, e.g. at shutdown while long-lived tasks still
+    // hold clones. Later sends fail; the receiver drains the queue and then sees the end.
+    // Items already spilled stay in the outbox for the next start.
+    pub fn close(&self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.item_ready.notify_one();
+    }
+
+    pub async fn send(&self, item: T) -> Result<()> {
+        if !self.shared.receiver_alive.load(Ordering::Acquire) || self.shared.closed.load(Ordering::Acquire) {
+            return Err(anyhow!("{} channel closed", self.shared.name));
+        }
+
+        let item = if self.shared.backlog.load(Ordering::Acquire) {
+            item
+        } else {
+            match self.shared.try_push(item) {
+                Ok(()) => return Ok(()),
+                Err(item) => item,
+            }
+        };
+
+        match (self.policy, &self.db_pool) {
+            (OverflowPolicy::DropOldest, _) => {
+                if self.shared.push_evicting(item) {
+                    record_overflow(self.shared.name, "dropped_oldest");
+                }
+                Ok(())
+            }
+            (OverflowPolicy::Spill, Some(db_pool)) => {
+                self.shared.backlog.store(true, Ordering::Release);
+                let payload = serde_json::to_value(&item)?;
+                match crate::db::spill_to_outbox(db_pool, self.shared.name, payload).await {
+                    Ok(()) => {
+                        record_overflow(self.shared.name, "spilled");
+                        Ok(())
+                    }
+                    Err(e) => {
+                        warn!(channel = self.shared.name, "Failed to spill to outbox, blocking: {}", e);
+                        record_overflow(self.shared.name, "blocked");
+                        self.shared.push_blocking(item).await
+                    }
+                }
+            }
+            _ => {
+                record_overflow(self.shared.name, "blocked");
+                self.shared.push_blocking(item).await
+            }
+        }
+    }
+
+    // Feed spilled items back into the channel as room frees up. Does not count
+    // as a sender, so the channel still closes when all real senders are gone.
+    pub fn spawn_outbox_drain(&self, poll_interval: Duration) {
+        let (Some(db_pool), OverflowPolicy::Spill) = (self.db_pool.clone(), self.policy) else {
+            return;
+        };
+        let shared = self.shared.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(poll_interval);
+            while shared.receiver_alive.load(Ordering::Acquire) && !shared.closed.load(Ordering::Acquire) {
+                interval.tick().await;
+
+                let room = shared.capacity.saturating_sub(shared.len());
+                if room == 0 {
+                    continue;
+                }
+
+                // Cleared before reading, so an item spilled meanwhile sets it again
+                shared.backlog.store(false, Ordering::Release);
+                let drained = drain_outbox(&db_pool, &shared, room).await;
+                match drained {
+                    Ok((taken, closed)) => {
+                        if taken >= room {
+                            shared.backlog.store(true, Ordering::Release);
+                        }
+                        if closed {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        shared.backlog.store(true, Ordering::Release);
+                        error!(channel = shared.name, "Failed to drain outbox: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Queue up to `room` spilled items, deleting them from the outbox only once queued.
+// Returns how many were read and whether the channel closed partway; items left over
+// stay in the outbox for the next start.
+async fn drain_outbox<T: DeserializeOwned>(
+    db_pool: &Pool<Postgres>,
+    shared: &Shared<T>,
+    room: usize,
+) -> Result<(usize, bool)> {
+    let mut tx = db_pool.begin().await?;
+    let rows = crate::db::peek_outbox(&mut *tx, shared.name, room as i64).await?;
+    let read = rows.len();
+
+    let mut taken = Vec::with_capacity(read);
+    let mut closed = false;
+    for (id, payload) in rows {
+        match serde_json::from_value::<T>(payload) {
+            Ok(item) => {
+                if shared.push_blocking(item).await.is_err() {
+                    closed = true;
+                    break;
+                }
+            }
+            Err(e) => error!(channel = shared.name, "Dropping malformed outbox item: {}", e),
+        }
+        taken.push(id);
+    }
+
+    crate::db::delete_from_outbox(&mut *tx, &taken).await?;
+    tx.commit().await?;
+    Ok((read, closed))
+}
+
+impl<T> Clone for PipelineSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Self {
+            shared: self.shared.clone(),
+            policy: self.policy,
+            db_pool: self.db_pool.clone(),
+        }
+    }
+}
+
+impl<T> Drop for PipelineSender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Wake the receiver so it can observe the channel closing
+            self.shared.item_ready.notify_one();
+        }
+    }
+}
+
+impl<T> PipelineReceiver<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let ready = self.shared.item_ready.notified();
+            let item = {
+                let mut queue = self.shared.queue.lock().unwrap();
+                let item = queue.pop_front();
+                self.shared.record_depth(queue.len());
+                item
+            };
+            if let Some(item) = item {
+                self.shared.space_ready.notify_one();
+                return Some(item);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 || self.shared.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            ready.await;
+        }
+    }
+}
+
+impl<T> Drop for PipelineReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+        self.shared.space_ready.notify_waiters();
+    }
+}
+
+fn record_overflow(channel: &str, action: &str) {
+    crate::metrics::CHANNEL_OVERFLOW_ACTIONS
+        .with_label_values(&[channel, action])
+        .inc();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drop_oldest_keeps_newest() {
+        let (sender, mut receiver) = channel::<u32>("test", 2, OverflowPolicy::DropOldest, None);
+        for i in 0..4 {
+            sender.send(i).await.unwrap();
+        }
+        drop(sender);
+
+        assert_eq!(receiver.recv().await, Some(2));
+        assert_eq!(receiver.recv().await, Some(3));
+        assert_eq!(receiver.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn close_ends_the_channel_after_the_queue_drains() {
+        let (sender, mut receiver) = channel::<u32>("test", 4, OverflowPolicy::Block, None);
+        let other = sender.clone();
+        sender.send(1).await.unwrap();
+        sender.close();
+
+        assert!(other.send(2).await.is_err());
+        assert_eq!(sender.queued(), 1);
+        assert_eq!(receiver.recv().await, Some(1));
+        assert_eq!(receiver.recv().await, None);
+    }
+}
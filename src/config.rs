@@ -1,6 +1,14 @@
 use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::env;
 
+use crate::archive::S3Target;
+use crate::channel::OverflowPolicy;
+use crate::content_fallback::ContentFallback;
+use crate::maintenance::{MaintenanceSchedule, DEFAULT_MAINTENANCE_SCHEDULE};
+use crate::rate_limit::{RateLimits, DEFAULT_RATE_LIMITS};
+use crate::registration::RegistrationMode;
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub database_url: String,
@@ -11,6 +19,90 @@ pub struct Config {
     pub apns_team_id: String,
     pub apns_topic: String,
     pub apns_production: bool,
+    // APNs hosts in order of preference, falling back on connect failures; empty means
+    // Apple's host for the environment
+    pub apns_hosts: Vec<String>,
+    pub admin_api_token: Option<String>,
+    pub internal_api_token: Option<String>,
+    pub report_service_did: String,
+    // Account moderation reports are filed as, with its app password
+    pub report_account: Option<(String, String)>,
+    pub device_retention_days: i32,
+    pub device_verification_enabled: bool,
+    pub device_verification_window_secs: i64,
+    pub apns_topic_validation: bool,
+    pub fanout_max_recipients: usize,
+    pub fanout_spread_threshold: usize,
+    pub fanout_spread_batch_size: usize,
+    pub fanout_spread_interval_ms: u64,
+    pub fanout_max_spreading: usize,
+    pub memory_soft_limit_mb: Option<u64>,
+    pub memory_hard_limit_mb: Option<u64>,
+    pub event_channel_capacity: usize,
+    pub event_channel_overflow: OverflowPolicy,
+    pub notification_channel_capacity: usize,
+    pub notification_channel_overflow: OverflowPolicy,
+    pub self_test_device_token: Option<String>,
+    pub self_test_did: String,
+    pub self_test_post_uri: Option<String>,
+    pub watchdog_webhook_url: Option<String>,
+    pub watchdog_event_stall_minutes: u64,
+    pub watchdog_delivery_stall_minutes: u64,
+    pub multi_tenant: bool,
+    pub quota_digest_interval_minutes: u64,
+    pub activity_digest_interval_minutes: u64,
+    pub plugin_paths: Vec<String>,
+    pub plugin_fuel: u64,
+    pub plugin_memory_limit_mb: usize,
+    pub copy_script_path: Option<String>,
+    pub copy_script_timeout_ms: u64,
+    pub copy_script_max_operations: u64,
+    pub archive_target: Option<S3Target>,
+    pub archive_prefix: String,
+    pub archive_interval_minutes: u64,
+    pub archive_hot_window_days: i32,
+    pub feed_poll_interval_secs: u64,
+    pub feed_digest_interval_minutes: u64,
+    pub quarantine_failure_window_hours: u64,
+    pub quarantine_min_failures: u32,
+    pub quarantine_probe_interval_minutes: u64,
+    pub appview_service_did: String,
+    pub verification_interval_hours: u64,
+    pub firehose_replay_window_minutes: u64,
+    pub firehose_decode_concurrency: usize,
+    pub decoded_record_cache_size: u64,
+    pub plc_directory_url: String,
+    pub hosted_handle_suffixes: Vec<String>,
+    pub did_prefetch_interval_secs: u64,
+    pub did_prefetch_max: usize,
+    pub plc_requests_per_second: f64,
+    pub service_did: Option<String>,
+    pub rate_limits: RateLimits,
+    pub trusted_proxy_hops: usize,
+    pub post_fetch_fallbacks: HashMap<String, ContentFallback>,
+    pub post_fetch_retry_delay_secs: u64,
+    pub post_fetch_max_retries: u32,
+    pub maintenance_schedule: MaintenanceSchedule,
+    pub outbox_retention_hours: i64,
+    pub chat_service_did: String,
+    pub dm_poll_interval_secs: u64,
+    // Seconds by notification type
+    pub notification_cooldowns: HashMap<String, u64>,
+    // Display columns by notification type
+    pub excerpt_lengths: HashMap<String, usize>,
+    pub aggregation_window_minutes: u64,
+    pub event_dedup_window_seconds: u64,
+    pub retraction_window_minutes: u64,
+    // APNs push type and priority by notification type
+    pub apns_push_settings: HashMap<String, crate::apns::PushSettings>,
+    // apns-topic for push classes that don't use the derived one
+    pub apns_topic_overrides: HashMap<crate::apns::PushClass, String>,
+    pub delivery_log_retention_days: i32,
+    pub registration_mode: RegistrationMode,
+    // Preference column -> value for new devices
+    pub default_preferences: HashMap<String, bool>,
+    pub shared_cache_url: Option<String>,
+    pub shutdown_deadlines: crate::shutdown::ShutdownDeadlines,
 }
 
 impl Config {
@@ -28,6 +120,405 @@ impl Config {
             apns_production: env::var("APNS_PRODUCTION")
                 .map(|v| v == "true")
                 .unwrap_or(false),
+            apns_hosts: env::var("APNS_HOSTS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|host| host.trim().to_string())
+                        .filter(|host| !host.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            admin_api_token: env::var("ADMIN_API_TOKEN").ok().filter(|t| !t.is_empty()),
+            internal_api_token: env::var("INTERNAL_API_TOKEN").ok().filter(|t| !t.is_empty()),
+            // Bluesky's moderation service unless the deployment uses its own labeler
+            report_service_did: env::var("REPORT_SERVICE_DID")
+                .unwrap_or_else(|_| "did:plc:ar7c4by46qjdydhdevvrndac".to_string()),
+            // Reporting stays off until both are set
+            report_account: env::var("REPORT_ACCOUNT_DID")
+                .ok()
+                .filter(|d| !d.is_empty())
+                .zip(env::var("REPORT_ACCOUNT_PASSWORD").ok().filter(|p| !p.is_empty())),
+            device_retention_days: env::var("DEVICE_RETENTION_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            device_verification_enabled: env::var("DEVICE_VERIFICATION_ENABLED")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            device_verification_window_secs: env::var("DEVICE_VERIFICATION_WINDOW_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(300),
+            // Check new tokens against APNS_TOPIC with a silent push at registration
+            apns_topic_validation: env::var("APNS_TOPIC_VALIDATION")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            fanout_max_recipients: env::var("FANOUT_MAX_RECIPIENTS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(500),
+            fanout_spread_threshold: env::var("FANOUT_SPREAD_THRESHOLD")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(50),
+            fanout_spread_batch_size: env::var("FANOUT_SPREAD_BATCH_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(25)
+                .max(1),
+            fanout_spread_interval_ms: env::var("FANOUT_SPREAD_INTERVAL_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1000),
+            fanout_max_spreading: env::var("FANOUT_MAX_SPREADING")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(4)
+                .max(1),
+            memory_soft_limit_mb: env::var("MEMORY_SOFT_LIMIT_MB")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            memory_hard_limit_mb: env::var("MEMORY_HARD_LIMIT_MB")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            event_channel_capacity: env::var("EVENT_CHANNEL_CAPACITY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1000),
+            event_channel_overflow: overflow_policy_from_env("EVENT_CHANNEL_OVERFLOW")?,
+            notification_channel_capacity: env::var("NOTIFICATION_CHANNEL_CAPACITY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1000),
+            notification_channel_overflow: overflow_policy_from_env("NOTIFICATION_CHANNEL_OVERFLOW")?,
+            self_test_device_token: env::var("SELF_TEST_DEVICE_TOKEN").ok().filter(|t| !t.is_empty()),
+            // bsky.app's account
+            self_test_did: env::var("SELF_TEST_DID")
+                .unwrap_or_else(|_| "did:plc:z72i7hdynmk6r22z27h6tvur".to_string()),
+            self_test_post_uri: env::var("SELF_TEST_POST_URI").ok().filter(|u| !u.is_empty()),
+            watchdog_webhook_url: env::var("WATCHDOG_WEBHOOK_URL").ok().filter(|u| !u.is_empty()),
+            watchdog_event_stall_minutes: env::var("WATCHDOG_EVENT_STALL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+            watchdog_delivery_stall_minutes: env::var("WATCHDOG_DELIVERY_STALL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            multi_tenant: env::var("MULTI_TENANT")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            quota_digest_interval_minutes: env::var("QUOTA_DIGEST_INTERVAL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60)
+                .max(1),
+            activity_digest_interval_minutes: env::var("ACTIVITY_DIGEST_INTERVAL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60)
+                .max(1),
+            plugin_paths: env::var("PLUGIN_PATHS")
+                .map(|v| {
+                    v.split(',')
+                        .map(|path| path.trim().to_string())
+                        .filter(|path| !path.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            plugin_fuel: env::var("PLUGIN_FUEL")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10_000_000),
+            plugin_memory_limit_mb: env::var("PLUGIN_MEMORY_LIMIT_MB")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(16),
+            copy_script_path: env::var("COPY_SCRIPT_PATH").ok().filter(|v| !v.is_empty()),
+            copy_script_timeout_ms: env::var("COPY_SCRIPT_TIMEOUT_MS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(20),
+            copy_script_max_operations: env::var("COPY_SCRIPT_MAX_OPERATIONS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(100_000),
+            archive_target: archive_target_from_env(),
+            archive_prefix: env::var("ARCHIVE_PREFIX").unwrap_or_else(|_| "notifications/".to_string()),
+            archive_interval_minutes: env::var("ARCHIVE_INTERVAL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60)
+                .max(1),
+            archive_hot_window_days: env::var("ARCHIVE_HOT_WINDOW_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            feed_poll_interval_secs: env::var("FEED_POLL_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(120)
+                .max(30),
+            feed_digest_interval_minutes: env::var("FEED_DIGEST_INTERVAL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60)
+                .max(1),
+            quarantine_failure_window_hours: env::var("QUARANTINE_FAILURE_WINDOW_HOURS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3),
+            quarantine_min_failures: env::var("QUARANTINE_MIN_FAILURES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(20),
+            quarantine_probe_interval_minutes: env::var("QUARANTINE_PROBE_INTERVAL_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(15)
+                .max(1),
+            appview_service_did: env::var("APPVIEW_SERVICE_DID")
+                .unwrap_or_else(|_| "did:web:api.bsky.app".to_string()),
+            // 0 disables the periodic AppView comparison; firehose gaps are still
+            // reconciled
+            verification_interval_hours: env::var("VERIFICATION_INTERVAL_HOURS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(24),
+            // How far back a stored cursor may be replayed after a restart
+            firehose_replay_window_minutes: env::var("FIREHOSE_REPLAY_WINDOW_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+            // Firehose frames decoding on the blocking pool at once
+            firehose_decode_concurrency: env::var("FIREHOSE_DECODE_CONCURRENCY")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_else(num_cpus::get),
+            // Decoded firehose records kept by CID; 0 disables the cache
+            decoded_record_cache_size: env::var("DECODED_RECORD_CACHE_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(50_000),
+            // plc.directory or a mirror of it
+            plc_directory_url: env::var("PLC_DIRECTORY_URL")
+                .unwrap_or_else(|_| crate::did_resolver::DEFAULT_PLC_DIRECTORY.to_string()),
+            // Domains whose subdomains are handed out as default handles
+            hosted_handle_suffixes: env::var("HOSTED_HANDLE_SUFFIXES")
+                .unwrap_or_else(|_| crate::profile_resolver::DEFAULT_HOSTED_HANDLE_SUFFIXES.to_string())
+                .split(',')
+                .map(|suffix| suffix.trim().trim_start_matches('.').to_ascii_lowercase())
+                .filter(|suffix| !suffix.is_empty())
+                .collect(),
+            // 0 disables prefetching frequently seen DIDs
+            did_prefetch_interval_secs: env::var("DID_PREFETCH_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+            // Most-seen DIDs considered for prefetching per round
+            did_prefetch_max: env::var("DID_PREFETCH_MAX")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(500),
+            // Request budget for the prefetcher against the PLC directory
+            plc_requests_per_second: env::var("PLC_REQUESTS_PER_SECOND")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|rate: &f64| *rate > 0.0)
+                .unwrap_or(2.0),
+            // Our own DID; when set, /register requires service auth addressed to it
+            service_did: env::var("SERVICE_DID").ok().filter(|d| !d.is_empty()),
+            rate_limits: rate_limits_from_env()?,
+            // How many proxies in front of us append to X-Forwarded-For; 0 ignores the header
+            trusted_proxy_hops: env::var("TRUSTED_PROXY_HOPS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            post_fetch_fallbacks: post_fetch_fallbacks_from_env()?,
+            post_fetch_retry_delay_secs: env::var("POST_FETCH_RETRY_DELAY_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+            post_fetch_max_retries: env::var("POST_FETCH_MAX_RETRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3),
+            maintenance_schedule: maintenance_schedule_from_env()?,
+            outbox_retention_hours: env::var("OUTBOX_RETENTION_HOURS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(72),
+            chat_service_did: env::var("CHAT_SERVICE_DID")
+                .unwrap_or_else(|_| "did:web:api.bsky.chat".to_string()),
+            // 0 disables direct message pushes
+            dm_poll_interval_secs: env::var("DM_POLL_INTERVAL_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            notification_cooldowns: notification_cooldowns_from_env()?,
+            excerpt_lengths: excerpt_lengths_from_env()?,
+            // 0 sends every like and repost on its own
+            aggregation_window_minutes: env::var("AGGREGATION_WINDOW_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0),
+            // 0 processes every event the firehose delivers
+            event_dedup_window_seconds: env::var("EVENT_DEDUP_WINDOW_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(600),
+            // 0 leaves notifications in place when their record is deleted
+            retraction_window_minutes: env::var("RETRACTION_WINDOW_MINUTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10),
+            apns_push_settings: apns_push_settings_from_env()?,
+            apns_topic_overrides: apns_topic_overrides_from_env()?,
+            delivery_log_retention_days: env::var("DELIVERY_LOG_RETENTION_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(7),
+            registration_mode: registration_mode_from_env()?,
+            default_preferences: default_preferences_from_env()?,
+            // Unset keeps every cache private to this replica
+            shared_cache_url: env::var("SHARED_CACHE_URL").ok().filter(|v| !v.is_empty()),
+            shutdown_deadlines: shutdown_deadlines_from_env()?,
         })
     }
-}
\ No newline at end of file
+}
+// Unset means block, matching the original bounded channels, which waited when full
+fn overflow_policy_from_env(key: &str) -> Result<OverflowPolicy> {
+    match env::var(key) {
+        Ok(value) => OverflowPolicy::parse(&value).with_context(|| {
+            format!("{} must be one of block, drop-oldest, spill (got {})", key, value)
+        }),
+        Err(_) => Ok(OverflowPolicy::Block),
+    }
+}
+
+// An empty RATE_LIMITS disables rate limiting
+fn rate_limits_from_env() -> Result<RateLimits> {
+    let spec = env::var("RATE_LIMITS").unwrap_or_else(|_| DEFAULT_RATE_LIMITS.to_string());
+    RateLimits::parse(&spec).with_context(|| {
+        format!("RATE_LIMITS must be route=requests/seconds pairs separated by commas (got {})", spec)
+    })
+}
+
+// Unset means a generic body for every type
+fn post_fetch_fallbacks_from_env() -> Result<HashMap<String, ContentFallback>> {
+    match env::var("POST_FETCH_FALLBACKS") {
+        Ok(spec) => ContentFallback::parse_by_type(&spec).with_context(|| {
+            format!(
+                "POST_FETCH_FALLBACKS must be type=retry|generic|suppress pairs separated by commas (got {})",
+                spec
+            )
+        }),
+        Err(_) => Ok(HashMap::new()),
+    }
+}
+
+// Unset means no cool-downs
+fn notification_cooldowns_from_env() -> Result<HashMap<String, u64>> {
+    match env::var("NOTIFICATION_COOLDOWNS") {
+        Ok(spec) => crate::cooldown::parse_cooldowns(&spec).with_context(|| {
+            format!(
+                "NOTIFICATION_COOLDOWNS must be type=seconds pairs separated by commas (got {})",
+                spec
+            )
+        }),
+        Err(_) => Ok(HashMap::new()),
+    }
+}
+
+// Unset means DEFAULT_EXCERPT_LENGTH for every type
+fn excerpt_lengths_from_env() -> Result<HashMap<String, usize>> {
+    match env::var("EXCERPT_LENGTHS") {
+        Ok(spec) => crate::excerpt::parse_excerpt_lengths(&spec).with_context(|| {
+            format!(
+                "EXCERPT_LENGTHS must be type=columns pairs separated by commas (got {})",
+                spec
+            )
+        }),
+        Err(_) => Ok(HashMap::new()),
+    }
+}
+
+// Unset means anyone may register
+fn registration_mode_from_env() -> Result<RegistrationMode> {
+    match env::var("REGISTRATION_MODE") {
+        Ok(mode) => RegistrationMode::parse(&mode)
+            .with_context(|| format!("REGISTRATION_MODE must be open, invite or allowlist (got {})", mode)),
+        Err(_) => Ok(RegistrationMode::Open),
+    }
+}
+
+// Unset means new devices start from the schema defaults
+fn default_preferences_from_env() -> Result<HashMap<String, bool>> {
+    match env::var("DEFAULT_PREFERENCES") {
+        Ok(spec) => crate::default_preferences::parse_default_preferences(&spec).with_context(|| {
+            format!(
+                "DEFAULT_PREFERENCES must be preference=true|false pairs separated by commas (got {})",
+                spec
+            )
+        }),
+        Err(_) => Ok(HashMap::new()),
+    }
+}
+
+// Unset means every type is a high priority alert
+fn apns_push_settings_from_env() -> Result<HashMap<String, crate::apns::PushSettings>> {
+    match env::var("APNS_PUSH_SETTINGS") {
+        Ok(spec) => crate::apns::parse_push_settings(&spec).with_context(|| {
+            format!(
+                "APNS_PUSH_SETTINGS must be type=alert:10, type=alert:5 or type=background:5 pairs separated by commas (got {})",
+                spec
+            )
+        }),
+        Err(_) => Ok(HashMap::new()),
+    }
+}
+
+// Unset derives every topic from APNS_TOPIC
+fn apns_topic_overrides_from_env() -> Result<HashMap<crate::apns::PushClass, String>> {
+    match env::var("APNS_TOPIC_OVERRIDES") {
+        Ok(spec) => crate::apns::parse_topic_overrides(&spec).with_context(|| {
+            format!(
+                "APNS_TOPIC_OVERRIDES must be class=topic pairs separated by commas, with class alert, background, voip or complication (got {})",
+                spec
+            )
+        }),
+        Err(_) => Ok(HashMap::new()),
+    }
+}
+
+// Unset gives the API and firehose 10s and the filter and sender 20s
+fn shutdown_deadlines_from_env() -> Result<crate::shutdown::ShutdownDeadlines> {
+    match env::var("SHUTDOWN_DEADLINES") {
+        Ok(spec) => crate::shutdown::ShutdownDeadlines::parse(&spec).with_context(|| {
+            format!(
+                "SHUTDOWN_DEADLINES must be task=seconds pairs separated by commas, with task api, firehose, filter or sender (got {})",
+                spec
+            )
+        }),
+        Err(_) => Ok(Default::default()),
+    }
+}
+
+// Weekly by default; times are UTC
+fn maintenance_schedule_from_env() -> Result<MaintenanceSchedule> {
+    let spec = env::var("MAINTENANCE_SCHEDULE").unwrap_or_else(|_| DEFAULT_MAINTENANCE_SCHEDULE.to_string());
+    MaintenanceSchedule::parse(&spec).with_context(|| {
+        format!("MAINTENANCE_SCHEDULE must be \"<mon..sun|daily> HH:MM\" (got {})", spec)
+    })
+}
+
+// The archiver is enabled only when a bucket and credentials are all present
+fn archive_target_from_env() -> Option<S3Target> {
+    Some(S3Target {
+        endpoint: env::var("ARCHIVE_S3_ENDPOINT").ok()?,
+        bucket: env::var("ARCHIVE_S3_BUCKET").ok().filter(|v| !v.is_empty())?,
+        region: env::var("ARCHIVE_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+        access_key_id: env::var("ARCHIVE_S3_ACCESS_KEY_ID").ok()?,
+        secret_access_key: env::var("ARCHIVE_S3_SECRET_ACCESS_KEY").ok()?,
+    })
+}
@@ -0,0 +1,91 @@
+// content_fallback.rs
+// Likes and reposts show the text of the post they point at. When that post can't be
+// fetched, each type can retry later, send a generic body, or drop the push, as set in
+// POST_FETCH_FALLBACKS (e.g. "like=retry,repost=generic"). Retries are held in memory
+// by the filter, so a restart loses them.
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::models::NotificationType;
+
+// Body sent in place of the post text
+pub const GENERIC_BODY: &str = "Tap to view the post";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentFallback {
+    Retry,
+    Generic,
+    Suppress,
+}
+
+impl ContentFallback {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentFallback::Retry => "retry",
+            ContentFallback::Generic => "generic",
+            ContentFallback::Suppress => "suppress",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "retry" => Some(ContentFallback::Retry),
+            "generic" => Some(ContentFallback::Generic),
+            "suppress" => Some(ContentFallback::Suppress),
+            _ => None,
+        }
+    }
+
+    // "type=fallback" pairs separated by commas; unlisted types use Generic
+    pub fn parse_by_type(spec: &str) -> Option<HashMap<String, ContentFallback>> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (notification_type, fallback) = entry.split_once('=')?;
+                let notification_type = NotificationType::parse(notification_type.trim())?;
+                Some((notification_type.as_str().to_string(), ContentFallback::parse(fallback.trim())?))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ContentFallbacks {
+    by_type: HashMap<String, ContentFallback>,
+    pub retry_delay: Duration,
+    // After this many retries the generic body is sent instead
+    pub max_retries: u32,
+}
+
+impl ContentFallbacks {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            by_type: config.post_fetch_fallbacks.clone(),
+            retry_delay: Duration::from_secs(config.post_fetch_retry_delay_secs),
+            max_retries: config.post_fetch_max_retries,
+        }
+    }
+
+    pub fn for_type(&self, notification_type: &NotificationType) -> ContentFallback {
+        self.by_type
+            .get(notification_type.as_str())
+            .copied()
+            .unwrap_or(ContentFallback::Generic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fallbacks_per_type() {
+        let by_type = ContentFallback::parse_by_type("like=retry, repost=suppress").unwrap();
+        assert_eq!(by_type["like"], ContentFallback::Retry);
+        assert_eq!(by_type["repost"], ContentFallback::Suppress);
+
+        assert!(ContentFallback::parse_by_type("like=later").is_none());
+        assert!(ContentFallback::parse_by_type("likes=retry").is_none());
+    }
+}
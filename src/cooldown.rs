@@ -0,0 +1,88 @@
+// cooldown.rs
+// Per (recipient, author) cool-downs that blunt nuisance patterns such as like-unlike-
+// like spam: after one notification of a type from an author, the recipient hears
+// nothing more of that type from them until the cool-down passes. Set per type in
+// NOTIFICATION_COOLDOWNS (e.g. "like=600,repost=600", in seconds); unlisted types have
+// none. Last-notified times are kept in memory, so a restart resets them.
+use moka::future::Cache;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::models::NotificationType;
+
+// "type=seconds" pairs separated by commas
+pub fn parse_cooldowns(spec: &str) -> Option<HashMap<String, u64>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (notification_type, seconds) = entry.split_once('=')?;
+            let notification_type = NotificationType::parse(notification_type.trim())?;
+            Some((notification_type.as_str().to_string(), seconds.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+#[derive(Clone)]
+pub struct Cooldowns {
+    by_type: HashMap<String, Duration>,
+    // Keyed by "recipient|author|type"
+    last_notified: Cache<String, Instant>,
+}
+
+impl Cooldowns {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        let by_type: HashMap<String, Duration> = config
+            .notification_cooldowns
+            .iter()
+            .map(|(notification_type, seconds)| (notification_type.clone(), Duration::from_secs(*seconds)))
+            .collect();
+        let longest = by_type.values().max().copied().unwrap_or_default();
+
+        Self {
+            by_type,
+            last_notified: Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(longest.max(Duration::from_secs(1)))
+                .build(),
+        }
+    }
+
+    // Whether the recipient may be notified now, recording the notification if so
+    pub async fn allow(&self, recipient: &str, author: &str, notification_type: &NotificationType) -> bool {
+        let Some(cooldown) = self.by_type.get(notification_type.as_str()) else {
+            return true;
+        };
+
+        let key = format!("{}|{}|{}", recipient, author, notification_type.as_str());
+        if let Some(at) = self.last_notified.get(&key) {
+            if at.elapsed() < *cooldown {
+                return false;
+            }
+        }
+        self.last_notified.insert(key, Instant::now()).await;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn holds_back_repeats_from_the_same_author() {
+        let cooldowns = Cooldowns {
+            by_type: [("like".to_string(), Duration::from_secs(600))].into(),
+            last_notified: Cache::builder().time_to_live(Duration::from_secs(600)).build(),
+        };
+
+        assert!(cooldowns.allow("did:plc:me", "did:plc:spam", &NotificationType::Like).await);
+        assert!(!cooldowns.allow("did:plc:me", "did:plc:spam", &NotificationType::Like).await);
+        assert!(cooldowns.allow("did:plc:me", "did:plc:other", &NotificationType::Like).await);
+        assert!(cooldowns.allow("did:plc:me", "did:plc:spam", &NotificationType::Reply).await);
+        assert!(cooldowns.allow("did:plc:me", "did:plc:spam", &NotificationType::Reply).await);
+
+        assert_eq!(parse_cooldowns("like=600, repost=60").unwrap()["repost"], 60);
+        assert!(parse_cooldowns("like=soon").is_none());
+    }
+}
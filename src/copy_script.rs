@@ -0,0 +1,261 @@
+// copy_script.rs
+// Optional Rhai script that post-processes notification copy. The script runs with
+// `title`, `body` and `data` in scope and may rewrite any of them; `event` is a
+// read-only map with the event fields. For example, to strip emoji from titles:
+//
+//   title = title.replace("❤️", "");
+//
+// Scripts are bounded by an operation budget and a wall-clock deadline; on any error
+// the original copy is sent unchanged.
+use crate::models::{BlueskyEvent, NotificationType};
+
+// Event fields exposed to the script
+#[cfg_attr(not(feature = "copy-scripts"), allow(dead_code))]
+pub struct ScriptEvent<'a> {
+    pub notification_type: &'a NotificationType,
+    pub event: &'a BlueskyEvent,
+    pub author_handle: &'a str,
+}
+
+#[cfg(feature = "copy-scripts")]
+pub use rhai_script::CopyScript;
+
+#[cfg(not(feature = "copy-scripts"))]
+pub use disabled::CopyScript;
+
+#[cfg(not(feature = "copy-scripts"))]
+mod disabled {
+    use super::ScriptEvent;
+    use anyhow::Result;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    pub struct CopyScript;
+
+    impl CopyScript {
+        pub fn load(_path: &str, _timeout: Duration, _max_operations: u64) -> Result<Self> {
+            anyhow::bail!("COPY_SCRIPT_PATH is set but this build does not include the copy-scripts feature")
+        }
+
+        pub fn apply(
+            &self,
+            _event: &ScriptEvent<'_>,
+            title: String,
+            body: String,
+            data: HashMap<String, String>,
+        ) -> (String, String, HashMap<String, String>) {
+            (title, body, data)
+        }
+    }
+}
+
+#[cfg(feature = "copy-scripts")]
+mod rhai_script {
+    use super::ScriptEvent;
+    use anyhow::Result;
+    use rhai::{Dynamic, Engine, ImmutableString, Map, Scope, AST};
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use tracing::warn;
+
+    // Keys the rest of the pipeline depends on and scripts may not change
+    const PROTECTED_KEYS: &[&str] = &[
+        "notification_id",
+        "experiment",
+        "variant",
+        "unread_mentions",
+        "unread_total",
+        "vip",
+        "push_type",
+        "grouping",
+        "event_source",
+        "event_seq",
+        "event_rev",
+        "event_received_at",
+        "event_origin",
+        "record_uri",
+        "v",
+    ];
+
+    thread_local! {
+        // Scripts run synchronously, so the deadline of the current evaluation is per thread
+        static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    }
+
+    pub struct CopyScript {
+        engine: Engine,
+        ast: AST,
+        timeout: Duration,
+    }
+
+    impl CopyScript {
+        pub fn load(path: &str, timeout: Duration, max_operations: u64) -> Result<Self> {
+            let mut engine = Engine::new();
+            engine.set_max_operations(max_operations);
+            engine.set_max_call_levels(16);
+            engine.set_max_string_size(4096);
+            engine.set_max_array_size(256);
+            engine.set_max_map_size(64);
+            engine.on_progress(|_| {
+                let expired = DEADLINE.with(|deadline| {
+                    deadline.get().is_some_and(|deadline| Instant::now() >= deadline)
+                });
+                expired.then(|| Dynamic::from("timeout"))
+            });
+
+            let ast = engine
+                .compile_file(path.into())
+                .map_err(|e| anyhow::anyhow!("Failed to compile copy script {}: {}", path, e))?;
+
+            Ok(Self {
+                engine,
+                ast,
+                timeout,
+            })
+        }
+
+        pub fn apply(
+            &self,
+            event: &ScriptEvent<'_>,
+            title: String,
+            body: String,
+            data: HashMap<String, String>,
+        ) -> (String, String, HashMap<String, String>) {
+            match self.run(event, &title, &body, &data) {
+                Ok(result) => result,
+                Err(e) => {
+                    warn!("Copy script failed, sending original copy: {}", e);
+                    crate::metrics::COPY_SCRIPT_ERRORS.inc();
+                    (title, body, data)
+                }
+            }
+        }
+
+        fn run(
+            &self,
+            event: &ScriptEvent<'_>,
+            title: &str,
+            body: &str,
+            data: &HashMap<String, String>,
+        ) -> Result<(String, String, HashMap<String, String>)> {
+            let mut event_map = Map::new();
+            event_map.insert("type".into(), event.notification_type.as_str().into());
+            event_map.insert("op".into(), event.event.op.clone().into());
+            event_map.insert("path".into(), event.event.path.clone().into());
+            event_map.insert("author".into(), event.event.author.clone().into());
+            event_map.insert("author_handle".into(), event.author_handle.into());
+            event_map.insert(
+                "text".into(),
+                event
+                    .event
+                    .record
+                    .get("text")
+                    .and_then(|text| text.as_str())
+                    .unwrap_or_default()
+                    .into(),
+            );
+
+            let data_map: Map = data
+                .iter()
+                .map(|(key, value)| (key.into(), value.clone().into()))
+                .collect();
+
+            let mut scope = Scope::new();
+            scope.push("title", title.to_string());
+            scope.push("body", body.to_string());
+            scope.push("data", data_map);
+            scope.push_constant("event", event_map);
+
+            DEADLINE.with(|deadline| deadline.set(Some(Instant::now() + self.timeout)));
+            let result = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+            DEADLINE.with(|deadline| deadline.set(None));
+            result.map_err(|e| anyhow::anyhow!("{}", e))?;
+
+            let new_title = scope
+                .get_value::<ImmutableString>("title")
+                .ok_or_else(|| anyhow::anyhow!("title is no longer a string"))?;
+            let new_body = scope
+                .get_value::<ImmutableString>("body")
+                .ok_or_else(|| anyhow::anyhow!("body is no longer a string"))?;
+            let new_data = scope
+                .get_value::<Map>("data")
+                .ok_or_else(|| anyhow::anyhow!("data is no longer a map"))?;
+
+            let mut data_out: HashMap<String, String> = new_data
+                .into_iter()
+                .filter(|(key, _)| !PROTECTED_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            for key in PROTECTED_KEYS {
+                if let Some(value) = data.get(*key) {
+                    data_out.insert(key.to_string(), value.clone());
+                }
+            }
+
+            Ok((new_title.to_string(), new_body.to_string(), data_out))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::models::{BlueskyEvent, NotificationType};
+
+        fn script(source: &str) -> CopyScript {
+            let path = std::env::temp_dir().join(format!("copy-script-{}.rhai", uuid::Uuid::new_v4()));
+            std::fs::write(&path, source).unwrap();
+            let script = CopyScript::load(path.to_str().unwrap(), Duration::from_millis(50), 100_000).unwrap();
+            let _ = std::fs::remove_file(path);
+            script
+        }
+
+        fn apply(script: &CopyScript) -> (String, String, HashMap<String, String>) {
+            let event = BlueskyEvent {
+                op: "create".to_string(),
+                path: "app.bsky.feed.like/abc".to_string(),
+                cid: String::new(),
+                author: "did:plc:author".to_string(),
+                record: serde_json::json!({}),
+                timestamp: 0,
+                origin: Default::default(),
+            };
+            let mut data = HashMap::new();
+            data.insert("notification_id".to_string(), "id-1".to_string());
+            script.apply(
+                &ScriptEvent {
+                    notification_type: &NotificationType::Like,
+                    event: &event,
+                    author_handle: "alice.bsky.social",
+                },
+                "New Like ❤️".to_string(),
+                "@alice.bsky.social liked your post".to_string(),
+                data,
+            )
+        }
+
+        #[test]
+        fn script_rewrites_copy_but_not_protected_keys() {
+            let script = script(
+                r#"
+                title.replace(" ❤️", "");
+                body = event.author_handle + " liked your post";
+                data.notification_id = "forged";
+                data.tone = "plain";
+                "#,
+            );
+            let (title, body, data) = apply(&script);
+            assert_eq!(title, "New Like");
+            assert_eq!(body, "alice.bsky.social liked your post");
+            assert_eq!(data["notification_id"], "id-1");
+            assert_eq!(data["tone"], "plain");
+        }
+
+        #[test]
+        fn runaway_script_falls_back_to_original_copy() {
+            let script = script("title = \"changed\"; loop { }");
+            let (title, _, _) = apply(&script);
+            assert_eq!(title, "New Like ❤️");
+        }
+    }
+}
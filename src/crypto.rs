@@ -47,6 +47,41 @@ impl CryptoUtils {
     }
 }
 
+// Constant-time check of a hex HMAC-SHA256 signature
+pub fn verify_hmac_sha256(key: &[u8], data: &[u8], signature: &str) -> bool {
+    constant_time_eq::constant_time_eq(hmac_sha256(key, data).as_bytes(), signature.as_bytes())
+}
+
+// RFC 2104 HMAC over SHA-256, hex encoded
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> String {
+    hex(&hmac_sha256_bytes(key, data))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hmac_sha256_bytes(key: &[u8], data: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(data);
+    let inner_hash = inner.finalize();
+
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner_hash);
+    outer.finalize().into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,4 +112,13 @@ mod tests {
         assert_eq!(hashes.len(), 2);
         assert_eq!(hashes[0], hash1);
     }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
 }
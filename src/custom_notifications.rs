@@ -0,0 +1,185 @@
+// custom_notifications.rs
+// Notifications submitted by first-party services on POST /internal/notify rather than
+// derived from the firehose. Each one goes through the recipient's devices the way a
+// firehose notification would: the custom_notifications preference, mutes and blocks
+// of the account it is about, quiet hours and tenant quotas all apply, and it is sent
+// on the same pipeline. Callers can attach their own data but can't set the keys the
+// pipeline relies on.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::warn;
+
+use crate::api::ApiState;
+use crate::apns::PushClass;
+use crate::models::{NotificationPayload, NotificationType, QuietHoursMode, UserDevice};
+
+// Per request, to keep one call from holding the handler for long
+pub const MAX_BATCH: usize = 100;
+
+// Set by the pipeline; caller data under these keys is dropped
+const RESERVED_KEYS: &[&str] = &[
+    "notification_id",
+    "type",
+    "author_did",
+    "push_type",
+    "vip",
+    "grouping",
+    "experiment",
+    "variant",
+    "aggregated_count",
+    "record_uri",
+    "v",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct CustomNotification {
+    pub did: String,
+    pub title: String,
+    pub body: String,
+    #[serde(default)]
+    pub data: HashMap<String, String>,
+    // The account the notification is about, so the recipient's mutes and blocks apply
+    pub author_did: Option<String>,
+    // "background" for a silent push; alert by default. "voip" and "complication" are
+    // refused until devices register tokens for them.
+    pub push_class: Option<String>,
+}
+
+impl CustomNotification {
+    pub fn is_valid(&self) -> bool {
+        self.did.starts_with("did:")
+            && !self.title.trim().is_empty()
+            && self.author_did.as_deref().is_none_or(|author| author.starts_with("did:"))
+            && self
+                .push_class
+                .as_deref()
+                .is_none_or(|class| PushClass::parse(class).is_some_and(|class| class.uses_device_token()))
+    }
+
+    fn data(&self) -> HashMap<String, String> {
+        self.data
+            .iter()
+            .filter(|(key, _)| !RESERVED_KEYS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Delivery {
+    // Devices the notification was queued for
+    pub queued: usize,
+    // Devices it couldn't be queued for; nothing was sent to them
+    pub failed: usize,
+}
+
+// Queue the notification for each of the recipient's devices that accepts it. A
+// device that fails is counted and skipped rather than failing the rest, so the
+// caller knows exactly what went out.
+pub async fn deliver(state: &ApiState, notification: &CustomNotification) -> Result<Delivery> {
+    let mut delivery = Delivery::default();
+    if let Some(author) = &notification.author_did {
+        if state.relationship_manager.is_muted(&notification.did, author).await
+            || state.relationship_manager.is_blocked(&notification.did, author).await
+        {
+            return Ok(delivery);
+        }
+    }
+
+    for device in crate::db::get_user_devices(&state.db_pool, &notification.did).await? {
+        match deliver_to_device(state, notification, &device).await {
+            Ok(true) => delivery.queued += 1,
+            Ok(false) => {}
+            Err(e) => {
+                warn!(did = %notification.did, "Failed to queue custom notification: {}", e);
+                delivery.failed += 1;
+            }
+        }
+    }
+    Ok(delivery)
+}
+
+// Whether the notification was queued for the device rather than held back
+async fn deliver_to_device(
+    state: &ApiState,
+    notification: &CustomNotification,
+    device: &UserDevice,
+) -> Result<bool> {
+    let notification_type = NotificationType::Custom;
+    let prefs = crate::db::get_notification_preferences(&state.db_pool, device.id).await?;
+    if !prefs.custom_notifications {
+        return Ok(false);
+    }
+
+    if let Some(mode) = crate::quiet_hours::active_mode(&state.db_pool, device.id).await? {
+        crate::metrics::NOTIFICATIONS_QUIET_HOURS
+            .with_label_values(&[mode.as_str()])
+            .inc();
+        if mode == QuietHoursMode::Queue {
+            crate::quiet_hours::defer(&state.db_pool, device.id, &notification_type).await?;
+        }
+        return Ok(false);
+    }
+
+    if state.quota.is_over_quota(&device.tenant_id).await {
+        state
+            .quota
+            .defer_to_digest(&device.tenant_id, &notification.did, &device.device_token, &notification_type)
+            .await?;
+        return Ok(false);
+    }
+
+    let mut data = notification.data();
+    data.insert("type".to_string(), format!("{:?}", notification_type));
+    data.insert("notification_id".to_string(), uuid::Uuid::new_v4().to_string());
+    if let Some(author) = &notification.author_did {
+        data.insert("author_did".to_string(), author.clone());
+    }
+    if let Some(class) = &notification.push_class {
+        data.insert("push_type".to_string(), class.clone());
+    } else if prefs.delivers_in_background(&notification_type) {
+        data.insert("push_type".to_string(), "background".to_string());
+    }
+    if prefs.payload_version >= crate::payload_keys::COMPACT_VERSION {
+        data.insert(crate::payload_keys::VERSION_KEY.to_string(), prefs.payload_version.to_string());
+    }
+
+    let payload = NotificationPayload {
+        user_did: notification.did.clone(),
+        device_token: device.device_token.clone(),
+        notification_type,
+        title: notification.title.clone(),
+        body: notification.body.clone(),
+        data,
+    };
+    state.notification_sender.send(payload).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callers_cannot_set_reserved_keys() {
+        let notification: CustomNotification = serde_json::from_value(serde_json::json!({
+            "did": "did:plc:me",
+            "title": "Your export is ready",
+            "body": "Tap to download",
+            "data": { "uri": "https://example.com/export", "vip": "true", "type": "Reply" }
+        }))
+        .unwrap();
+
+        assert!(notification.is_valid());
+        let data = notification.data();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data["uri"], "https://example.com/export");
+
+        let untitled = CustomNotification {
+            title: " ".to_string(),
+            ..notification
+        };
+        assert!(!untitled.is_valid());
+    }
+}
@@ -1,9 +1,18 @@
 use anyhow::Result;
+use serde::Serialize;
+use sqlx::types::time::OffsetDateTime;
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres, Row};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use tracing::info;
 
-use crate::models::{FirehoseCursor, NotificationPreference, UserDevice};
+use crate::apns::DeliveryReceipt;
+use crate::feeds::FeedSubscription;
+use crate::retry::RetryPolicy;
+use crate::models::{
+    FirehoseCursor, Grouping, LabelVisibility, NotificationHistoryEntry, NotificationPayload,
+    NotificationPreference, NotificationThreshold, NotificationType, UserDevice,
+};
 
 pub async fn init_db_pool(database_url: &str) -> Result<Pool<Postgres>> {
     info!("Initializing database connection pool");
@@ -22,9 +31,24 @@ pub async fn init_db_pool(database_url: &str) -> Result<Pool<Postgres>> {
         max_connections
     );
 
-    let pool = PgPoolOptions::new()
-        .max_connections(max_connections)
-        .connect(database_url)
+    // The database may still be starting when the service comes up
+    let pool = RetryPolicy::new("db_connect")
+        .max_attempts(5)
+        .backoff(Duration::from_secs(1), Duration::from_secs(15))
+        .run(|_| async {
+            Ok(PgPoolOptions::new()
+                .max_connections(max_connections)
+                // The service works across tenants; API requests scope themselves with
+                // tenant::begin, and any other session sees nothing
+                .after_connect(|conn, _| {
+                    Box::pin(async move {
+                        sqlx::query("SET app.all_tenants = 'on'").execute(conn).await?;
+                        Ok(())
+                    })
+                })
+                .connect(database_url)
+                .await?)
+        })
         .await?;
 
     // Run migrations
@@ -33,17 +57,20 @@ pub async fn init_db_pool(database_url: &str) -> Result<Pool<Postgres>> {
     Ok(pool)
 }
 
-pub async fn get_user_devices(pool: &Pool<Postgres>, did: &str) -> Result<Vec<UserDevice>> {
+pub async fn get_user_devices<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+) -> Result<Vec<UserDevice>> {
     let devices = sqlx::query_as!(
         UserDevice,
         r#"
-        SELECT id, did, device_token, created_at, updated_at
+        SELECT id, did, device_token, created_at, updated_at, tenant_id
         FROM user_devices
-        WHERE did = $1
+        WHERE did = $1 AND deleted_at IS NULL AND verified_at IS NOT NULL
         "#,
         did
     )
-    .fetch_all(pool)
+    .fetch_all(executor)
     .await?;
 
     Ok(devices)
@@ -65,9 +92,9 @@ pub async fn get_user_devices_batch(
         let placeholders: Vec<String> = (1..=chunk.len()).map(|i| format!("${}", i)).collect();
 
         let query = format!(
-            "SELECT id, did, device_token, created_at, updated_at 
+            "SELECT id, did, device_token, created_at, updated_at, tenant_id 
              FROM user_devices 
-             WHERE did IN ({})",
+             WHERE did IN ({}) AND deleted_at IS NULL AND verified_at IS NOT NULL",
             placeholders.join(",")
         );
 
@@ -87,6 +114,7 @@ pub async fn get_user_devices_batch(
                 device_token: row.get("device_token"),
                 created_at: row.get("created_at"),
                 updated_at: row.get("updated_at"),
+                tenant_id: row.get("tenant_id"),
             };
 
             result
@@ -99,26 +127,107 @@ pub async fn get_user_devices_batch(
     Ok(result)
 }
 
-pub async fn get_notification_preferences(
-    pool: &Pool<Postgres>,
+pub async fn get_notification_preferences<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
     user_id: uuid::Uuid,
 ) -> Result<NotificationPreference> {
     let preferences = sqlx::query_as!(
         NotificationPreference,
         r#"
-        SELECT user_id, mentions, replies, likes, follows, reposts, quotes
+        SELECT user_id, mentions, replies, likes, follows, reposts, quotes, replies_to_replies,
+            priority_from_mutuals, feed_posts, digest_low_priority, dms, dm_redact_body, post_edits,
+            list_additions, background_types, comeback_posts, grouping, custom_notifications,
+            payload_version, payload_event_origin, reply_context, only_from_follows
         FROM notification_preferences
         WHERE user_id = $1
         "#,
         user_id
     )
-    .fetch_one(pool)
+    .fetch_one(executor)
     .await?;
 
     Ok(preferences)
 }
 
-pub async fn get_last_cursor(pool: &Pool<Postgres>) -> Result<Option<String>> {
+pub async fn get_notification_thresholds<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    user_id: uuid::Uuid,
+) -> Result<Vec<NotificationThreshold>> {
+    let thresholds = sqlx::query_as!(
+        NotificationThreshold,
+        r#"
+        SELECT user_id, notification_type, min_account_age_days, min_followers
+        FROM notification_thresholds
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(thresholds)
+}
+
+// Per-label visibility overrides for a device
+pub async fn get_label_preferences<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    user_id: uuid::Uuid,
+) -> Result<HashMap<String, LabelVisibility>> {
+    let rows = sqlx::query!(
+        "SELECT label, visibility FROM label_preferences WHERE user_id = $1",
+        user_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| LabelVisibility::parse(&row.visibility).map(|v| (row.label, v)))
+        .collect())
+}
+
+pub async fn get_feed_subscriptions<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    user_id: uuid::Uuid,
+) -> Result<Vec<FeedSubscription>> {
+    let rows = sqlx::query!(
+        "SELECT feed_uri, digest FROM feed_subscriptions WHERE user_id = $1 ORDER BY created_at",
+        user_id
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| FeedSubscription {
+            feed_uri: row.feed_uri,
+            digest: row.digest,
+        })
+        .collect())
+}
+
+pub async fn get_notification_threshold(
+    pool: &Pool<Postgres>,
+    user_id: uuid::Uuid,
+    notification_type: &str,
+) -> Result<Option<NotificationThreshold>> {
+    let threshold = sqlx::query_as!(
+        NotificationThreshold,
+        r#"
+        SELECT user_id, notification_type, min_account_age_days, min_followers
+        FROM notification_thresholds
+        WHERE user_id = $1 AND notification_type = $2
+        "#,
+        user_id,
+        notification_type
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(threshold)
+}
+
+pub async fn get_last_cursor(pool: &Pool<Postgres>) -> Result<Option<FirehoseCursor>> {
     let cursor = sqlx::query_as!(
         FirehoseCursor,
         r#"
@@ -131,7 +240,7 @@ pub async fn get_last_cursor(pool: &Pool<Postgres>) -> Result<Option<String>> {
     .fetch_optional(pool)
     .await?;
 
-    Ok(cursor.map(|c| c.cursor))
+    Ok(cursor)
 }
 
 pub async fn update_cursor(pool: &Pool<Postgres>, cursor: &str) -> Result<()> {
@@ -174,7 +283,7 @@ pub async fn update_cursor(pool: &Pool<Postgres>, cursor: &str) -> Result<()> {
 pub async fn get_registered_users(pool: &Pool<Postgres>) -> Result<Vec<String>> {
     let users = sqlx::query!(
         r#"
-        SELECT DISTINCT did FROM user_devices
+        SELECT DISTINCT did FROM user_devices WHERE deleted_at IS NULL AND verified_at IS NOT NULL
         "#
     )
     .fetch_all(pool)
@@ -200,3 +309,1112 @@ pub async fn cleanup_old_cursors(pool: &Pool<Postgres>, days_to_keep: i32) -> Re
 
     Ok(())
 }
+
+// Canonical form of an APNs device token: hex digits only, lowercased.
+// Older clients sent the `<abcd ef01 ...>` description format or uppercase hex.
+pub fn normalize_device_token(token: &str) -> String {
+    token
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '<' && *c != '>')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DuplicateMergeReport {
+    pub groups_found: usize,
+    pub devices_removed: usize,
+    pub tokens_normalized: usize,
+    pub dry_run: bool,
+}
+
+// A device row as the duplicate merge sees it
+#[derive(Debug, Clone)]
+struct MergeCandidate {
+    id: uuid::Uuid,
+    did: String,
+    tenant_id: String,
+    device_token: String,
+    updated_at: OffsetDateTime,
+    active: bool,
+}
+
+#[derive(Debug)]
+struct DuplicateGroup {
+    normalized: String,
+    survivor: MergeCandidate,
+    duplicates: Vec<MergeCandidate>,
+}
+
+// Group rows by tenant and normalized token, keeping the groups with something to merge
+// or normalize. The most recently updated active row survives, or the newest deleted
+// one when none is active.
+fn group_duplicates(mut devices: Vec<MergeCandidate>) -> Vec<DuplicateGroup> {
+    devices.sort_by(|a, b| b.active.cmp(&a.active).then(b.updated_at.cmp(&a.updated_at)));
+
+    let mut groups: HashMap<(String, String), Vec<MergeCandidate>> = HashMap::new();
+    for device in devices {
+        groups
+            .entry((device.tenant_id.clone(), normalize_device_token(&device.device_token)))
+            .or_default()
+            .push(device);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = groups
+        .into_iter()
+        .filter_map(|((_, normalized), mut group)| {
+            let survivor = group.remove(0);
+            (!group.is_empty() || survivor.device_token != normalized).then_some(DuplicateGroup {
+                normalized,
+                survivor,
+                duplicates: group,
+            })
+        })
+        .collect();
+    groups.sort_by(|a, b| (&a.survivor.tenant_id, &a.normalized).cmp(&(&b.survivor.tenant_id, &b.normalized)));
+    groups
+}
+
+// Find device rows in a tenant whose tokens only differ by case/whitespace and merge each
+// group into the most recently updated active row, carrying over every per-device
+// setting and pending count it doesn't have yet. Preferences, which include grouping,
+// payload version and quiet hours, come from whichever row was updated last, so a newer
+// deleted registration's settings win over an older active one's.
+pub async fn merge_duplicate_devices(
+    pool: &Pool<Postgres>,
+    dry_run: bool,
+) -> Result<DuplicateMergeReport> {
+    let devices = sqlx::query_as!(
+        MergeCandidate,
+        r#"
+        SELECT id, did, tenant_id, device_token, updated_at, deleted_at IS NULL AS "active!"
+        FROM user_devices
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut report = DuplicateMergeReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    for DuplicateGroup {
+        normalized,
+        survivor,
+        duplicates,
+    } in group_duplicates(devices)
+    {
+        let needs_normalizing = survivor.device_token != normalized;
+        if !duplicates.is_empty() {
+            report.groups_found += 1;
+            report.devices_removed += duplicates.len();
+        }
+        if needs_normalizing {
+            report.tokens_normalized += 1;
+        }
+
+        if dry_run {
+            continue;
+        }
+
+        let mut tx = pool.begin().await?;
+
+        // Adopt the newest registration's preferences before the duplicates cascade away
+        let newest = duplicates
+            .iter()
+            .filter(|duplicate| duplicate.updated_at > survivor.updated_at)
+            .max_by_key(|duplicate| duplicate.updated_at);
+        if let Some(newest) = newest {
+            sqlx::query!(
+                r#"
+                DELETE FROM notification_preferences
+                WHERE user_id = $1 AND EXISTS (SELECT 1 FROM notification_preferences WHERE user_id = $2)
+                "#,
+                survivor.id,
+                newest.id
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query!(
+                "UPDATE notification_preferences SET user_id = $1 WHERE user_id = $2",
+                survivor.id,
+                newest.id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        for duplicate in &duplicates {
+            merge_device_rows(&mut tx, survivor.id, duplicate.id).await?;
+
+            // Whatever is left cascades with the row
+            sqlx::query!("DELETE FROM user_devices WHERE id = $1", duplicate.id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        if needs_normalizing {
+            sqlx::query!(
+                r#"
+                UPDATE user_devices
+                SET device_token = $1, updated_at = NOW()
+                WHERE id = $2
+                "#,
+                normalized,
+                survivor.id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        info!(
+            survivor_id = %survivor.id,
+            did = %survivor.did,
+            removed = duplicates.len(),
+            "Merged duplicate device registrations"
+        );
+    }
+
+    Ok(report)
+}
+
+// Copy a duplicate's per-device rows onto the survivor. Settings the survivor already
+// has are kept; counts of notifications still waiting for a digest or the end of quiet
+// hours are added together, so none are dropped.
+async fn merge_device_rows(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    survivor: uuid::Uuid,
+    duplicate: uuid::Uuid,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO notification_thresholds (user_id, notification_type, min_account_age_days, min_followers)
+        SELECT $1, notification_type, min_account_age_days, min_followers
+        FROM notification_thresholds
+        WHERE user_id = $2
+        ON CONFLICT (user_id, notification_type) DO NOTHING
+        "#,
+        survivor,
+        duplicate
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO label_preferences (user_id, label, visibility)
+        SELECT $1, label, visibility FROM label_preferences WHERE user_id = $2
+        ON CONFLICT (user_id, label) DO NOTHING
+        "#,
+        survivor,
+        duplicate
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO feed_subscriptions (user_id, feed_uri, digest, created_at)
+        SELECT $1, feed_uri, digest, created_at FROM feed_subscriptions WHERE user_id = $2
+        ON CONFLICT (user_id, feed_uri) DO NOTHING
+        "#,
+        survivor,
+        duplicate
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    // Both rows count the same device's badge, so the survivor's stands
+    sqlx::query!(
+        r#"
+        INSERT INTO unread_counts (user_id, mentions, total, updated_at)
+        SELECT $1, mentions, total, updated_at FROM unread_counts WHERE user_id = $2
+        ON CONFLICT (user_id) DO NOTHING
+        "#,
+        survivor,
+        duplicate
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO quiet_hours_pending (user_id, notification_type, count, updated_at)
+        SELECT $1, notification_type, count, updated_at FROM quiet_hours_pending WHERE user_id = $2
+        ON CONFLICT (user_id, notification_type)
+        DO UPDATE SET count = quiet_hours_pending.count + EXCLUDED.count,
+            updated_at = GREATEST(quiet_hours_pending.updated_at, EXCLUDED.updated_at)
+        "#,
+        survivor,
+        duplicate
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO pending_feed_digests (user_id, feed_uri, count, updated_at)
+        SELECT $1, feed_uri, count, updated_at FROM pending_feed_digests WHERE user_id = $2
+        ON CONFLICT (user_id, feed_uri)
+        DO UPDATE SET count = pending_feed_digests.count + EXCLUDED.count,
+            updated_at = GREATEST(pending_feed_digests.updated_at, EXCLUDED.updated_at)
+        "#,
+        survivor,
+        duplicate
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO activity_digest_pending (user_id, notification_type, count, updated_at)
+        SELECT $1, notification_type, count, updated_at FROM activity_digest_pending WHERE user_id = $2
+        ON CONFLICT (user_id, notification_type)
+        DO UPDATE SET count = activity_digest_pending.count + EXCLUDED.count,
+            updated_at = GREATEST(activity_digest_pending.updated_at, EXCLUDED.updated_at)
+        "#,
+        survivor,
+        duplicate
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+// Mark a device as deleted without removing it, so it can be restored until purged.
+// Returns false when no active device matched.
+pub async fn soft_delete_device<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    device_token: &str,
+    reason: &str,
+) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_devices
+        SET deleted_at = NOW(), deleted_reason = $3, updated_at = NOW()
+        WHERE did = $1 AND device_token = $2 AND deleted_at IS NULL
+        "#,
+        did,
+        device_token,
+        reason
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Soft-delete devices by token alone, used when APNs reports tokens are no longer valid.
+// Returns how many active devices were removed.
+pub async fn soft_delete_devices_by_token(
+    pool: &Pool<Postgres>,
+    device_tokens: &[String],
+    reason: &str,
+) -> Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_devices
+        SET deleted_at = NOW(), deleted_reason = $2, updated_at = NOW()
+        WHERE device_token = ANY($1) AND deleted_at IS NULL
+        "#,
+        device_tokens,
+        reason
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Restore a soft-deleted device by token
+pub async fn restore_device(pool: &Pool<Postgres>, device_token: &str) -> Result<bool> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_devices
+        SET deleted_at = NULL, deleted_reason = NULL, updated_at = NOW()
+        WHERE device_token = $1 AND deleted_at IS NOT NULL
+        "#,
+        device_token
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Permanently remove devices that have been soft-deleted for longer than the retention window
+pub async fn purge_deleted_devices(pool: &Pool<Postgres>, retention_days: i32) -> Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM user_devices
+        WHERE deleted_at IS NOT NULL
+        AND deleted_at < NOW() - INTERVAL '1 day' * $1
+        "#,
+        retention_days as f64
+    )
+    .execute(pool)
+    .await?;
+
+    // Stored app passwords go with the user's last device in the app they were given to
+    sqlx::query!(
+        r#"
+        DELETE FROM verification_consents c
+        WHERE NOT EXISTS (SELECT 1 FROM user_devices d WHERE d.tenant_id = c.tenant_id AND d.did = c.user_did)
+        "#
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM dm_consents c
+        WHERE NOT EXISTS (SELECT 1 FROM user_devices d WHERE d.tenant_id = c.tenant_id AND d.did = c.user_did)
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Count a delivered notification towards today's open-rate denominator
+pub async fn record_delivery(pool: &Pool<Postgres>, notification_type: &str) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO notification_daily_stats (day, notification_type, delivered)
+        VALUES (CURRENT_DATE, $1, 1)
+        ON CONFLICT (day, notification_type)
+        DO UPDATE SET delivered = notification_daily_stats.delivered + 1
+        "#,
+        notification_type
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub enum OpenOutcome {
+    // Never delivered to this device
+    Unknown,
+    AlreadyOpened,
+    Opened {
+        notification_type: String,
+        experiment: Option<String>,
+        variant: Option<String>,
+    },
+}
+
+// Store an open event. The type and any experiment variant come from the delivery
+// record, so a device can only acknowledge notifications it was actually sent. Runs
+// in the request's tenant transaction, which the caller commits.
+pub async fn record_open(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    notification_id: uuid::Uuid,
+    user_did: &str,
+    device_token: &str,
+) -> Result<OpenOutcome> {
+    let Some(delivered) = sqlx::query!(
+        r#"
+        SELECT notification_type, data->>'experiment' AS experiment, data->>'variant' AS variant
+        FROM notification_history
+        WHERE notification_id = $1 AND user_did = $2 AND device_token = $3
+        "#,
+        notification_id,
+        user_did,
+        device_token
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    else {
+        return Ok(OpenOutcome::Unknown);
+    };
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO notification_opens (notification_id, user_did, notification_type, experiment, variant)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (notification_id) DO NOTHING
+        "#,
+        notification_id,
+        user_did,
+        delivered.notification_type,
+        delivered.experiment,
+        delivered.variant
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Ok(OpenOutcome::AlreadyOpened);
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO notification_daily_stats (day, notification_type, opened)
+        VALUES (CURRENT_DATE, $1, 1)
+        ON CONFLICT (day, notification_type)
+        DO UPDATE SET opened = notification_daily_stats.opened + 1
+        "#,
+        delivered.notification_type
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(OpenOutcome::Opened {
+        notification_type: delivered.notification_type,
+        experiment: delivered.experiment,
+        variant: delivered.variant,
+    })
+}
+
+// Count one more unread notification for a device; returns the new (mentions, total)
+pub async fn increment_unread_counts(
+    pool: &Pool<Postgres>,
+    user_id: uuid::Uuid,
+    mention: bool,
+) -> Result<(i32, i32)> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO unread_counts (user_id, mentions, total)
+        VALUES ($1, CASE WHEN $2 THEN 1 ELSE 0 END, 1)
+        ON CONFLICT (user_id) DO UPDATE
+        SET mentions = unread_counts.mentions + EXCLUDED.mentions,
+            total = unread_counts.total + 1,
+            updated_at = NOW()
+        RETURNING mentions, total
+        "#,
+        user_id,
+        mention
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((row.mentions, row.total))
+}
+
+// Take back a count for a notification that never made it into the queue
+pub async fn uncount_unread(pool: &Pool<Postgres>, user_id: uuid::Uuid, mention: bool) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE unread_counts
+        SET mentions = GREATEST(mentions - CASE WHEN $2 THEN 1 ELSE 0 END, 0),
+            total = GREATEST(total - 1, 0),
+            updated_at = NOW()
+        WHERE user_id = $1
+        "#,
+        user_id,
+        mention
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// An opened notification has been read on every device of the account
+pub async fn decrement_unread_counts<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    mention: bool,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE unread_counts
+        SET mentions = GREATEST(mentions - CASE WHEN $2 THEN 1 ELSE 0 END, 0),
+            total = GREATEST(total - 1, 0),
+            updated_at = NOW()
+        WHERE user_id IN (SELECT id FROM user_devices WHERE did = $1)
+        "#,
+        did,
+        mention
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// The user has seen their notifications in the app, either all of them or just the
+// Mentions tab
+pub async fn clear_unread_counts<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    mentions_only: bool,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE unread_counts
+        SET total = CASE WHEN $2 THEN GREATEST(total - mentions, 0) ELSE 0 END,
+            mentions = 0,
+            updated_at = NOW()
+        WHERE user_id IN (SELECT id FROM user_devices WHERE did = $1)
+        "#,
+        did,
+        mentions_only
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// The app was opened on this device, which clears its icon badge; other devices keep
+// their counts until they are opened too
+pub async fn clear_device_unread_counts<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    device_token: &str,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE unread_counts
+        SET mentions = 0, total = 0, updated_at = NOW()
+        WHERE user_id IN (SELECT id FROM user_devices WHERE did = $1 AND device_token = $2)
+        "#,
+        did,
+        device_token
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_device_payload_version<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    device_token: &str,
+    version: i16,
+    event_origin: bool,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE notification_preferences
+        SET payload_version = $3, payload_event_origin = $4
+        WHERE user_id IN (SELECT id FROM user_devices WHERE did = $1 AND device_token = $2)
+        "#,
+        did,
+        device_token,
+        version,
+        event_origin
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn set_device_grouping<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    device_token: &str,
+    grouping: Grouping,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE notification_preferences
+        SET grouping = $3
+        WHERE user_id IN (SELECT id FROM user_devices WHERE did = $1 AND device_token = $2)
+        "#,
+        did,
+        device_token,
+        grouping.as_str()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpenRate {
+    pub notification_type: String,
+    pub delivered: i64,
+    pub opened: i64,
+    pub open_rate: f64,
+}
+
+// Aggregate open rates per notification type over the last `days` days
+pub async fn get_open_rates(pool: &Pool<Postgres>, days: i32) -> Result<Vec<OpenRate>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT notification_type,
+               SUM(delivered)::BIGINT AS "delivered!",
+               SUM(opened)::BIGINT AS "opened!"
+        FROM notification_daily_stats
+        WHERE day > CURRENT_DATE - $1::INTEGER
+        GROUP BY notification_type
+        ORDER BY notification_type
+        "#,
+        days
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| OpenRate {
+            open_rate: if row.delivered > 0 {
+                row.opened as f64 / row.delivered as f64
+            } else {
+                0.0
+            },
+            notification_type: row.notification_type,
+            delivered: row.delivered,
+            opened: row.opened,
+        })
+        .collect())
+}
+
+// Mark a device as verified if the echoed nonce matches and hasn't expired. A token
+// claimed by another DID moves to it here, restored if it had been deleted. Returns the
+// DID the device belonged to before, or None when nothing matched.
+pub async fn confirm_device_verification<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    device_token: &str,
+    nonce: &str,
+) -> Result<Option<String>> {
+    let previous_did = sqlx::query_scalar!(
+        r#"
+        WITH claimed AS (
+            SELECT id, did FROM user_devices
+            WHERE device_token = $2 AND verification_nonce = $3
+            AND verification_expires_at > NOW()
+            AND COALESCE(pending_did, did) = $1
+            AND (deleted_at IS NULL OR pending_did IS NOT NULL)
+            FOR UPDATE
+        )
+        UPDATE user_devices
+        SET did = $1, pending_did = NULL, deleted_at = NULL, deleted_reason = NULL,
+            verified_at = NOW(), verification_nonce = NULL, verification_expires_at = NULL, updated_at = NOW()
+        FROM claimed
+        WHERE user_devices.id = claimed.id
+        RETURNING claimed.did
+        "#,
+        did,
+        device_token,
+        nonce
+    )
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(previous_did)
+}
+
+// Soft-delete registrations whose verification window lapsed a day ago without
+// confirmation, and drop claims on other DIDs' tokens that were never confirmed
+pub async fn purge_unverified_devices(pool: &Pool<Postgres>) -> Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_devices
+        SET deleted_at = NOW(), deleted_reason = 'unverified', updated_at = NOW()
+        WHERE verified_at IS NULL AND deleted_at IS NULL
+        AND verification_expires_at < NOW() - INTERVAL '1 day'
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        UPDATE user_devices
+        SET pending_did = NULL, verification_nonce = NULL, verification_expires_at = NULL
+        WHERE pending_did IS NOT NULL AND verification_expires_at < NOW() - INTERVAL '1 day'
+        "#
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Park an item from a full pipeline channel
+pub async fn spill_to_outbox(
+    pool: &Pool<Postgres>,
+    channel: &str,
+    payload: serde_json::Value,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO channel_outbox (channel, payload)
+        VALUES ($1, $2)
+        "#,
+        channel,
+        payload
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Lock up to `limit` of the oldest spilled items for a channel, oldest first. They stay
+// in the outbox until deleted in the same transaction, so a crash before they're queued
+// leaves them for the next drain.
+pub async fn peek_outbox<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    channel: &str,
+    limit: i64,
+) -> Result<Vec<(i64, serde_json::Value)>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, payload FROM channel_outbox
+        WHERE channel = $1
+        ORDER BY id
+        LIMIT $2
+        FOR UPDATE SKIP LOCKED
+        "#,
+        channel,
+        limit
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows.into_iter().map(|row| (row.id, row.payload)).collect())
+}
+
+pub async fn delete_from_outbox<'e>(executor: impl sqlx::PgExecutor<'e>, ids: &[i64]) -> Result<()> {
+    sqlx::query!("DELETE FROM channel_outbox WHERE id = ANY($1)", ids)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// Drop spilled items that have waited longer than the retention window; by then the
+// notifications they carry are too stale to be worth sending
+pub async fn expire_outbox(pool: &Pool<Postgres>, retention_hours: i64) -> Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM channel_outbox
+        WHERE created_at < NOW() - INTERVAL '1 hour' * $1
+        "#,
+        retention_hours as f64
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// Devices whose tokens come from development builds and go through the APNs sandbox
+pub async fn get_sandbox_device_tokens(pool: &Pool<Postgres>) -> Result<Vec<String>> {
+    let tokens = sqlx::query_scalar!(
+        "SELECT device_token FROM user_devices WHERE apns_sandbox AND deleted_at IS NULL"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tokens)
+}
+
+pub async fn set_device_sandbox(pool: &Pool<Postgres>, device_token: &str, sandbox: bool) -> Result<()> {
+    sqlx::query!(
+        "UPDATE user_devices SET apns_sandbox = $2, updated_at = NOW() WHERE device_token = $1",
+        device_token,
+        sandbox
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn expire_delivery_log(pool: &Pool<Postgres>, retention_days: i32) -> Result<u64> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM delivery_log
+        WHERE created_at < NOW() - INTERVAL '1 day' * $1
+        "#,
+        retention_days as f64
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+// One entry per send, unlike history, which replays update in place. The sender runs
+// across tenants, so the row takes the tenant of the device it went to.
+pub async fn record_delivery_log(
+    pool: &Pool<Postgres>,
+    notification: &NotificationPayload,
+    outcome: &str,
+    receipt: &DeliveryReceipt,
+) -> Result<()> {
+    let notification_id = notification
+        .data
+        .get("notification_id")
+        .and_then(|id| uuid::Uuid::parse_str(id).ok());
+
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery_log
+            (tenant_id, notification_id, user_did, device_token, notification_type, outcome, status_code, apns_id,
+            reason)
+        VALUES (device_tenant($2, $3), $1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        notification_id,
+        notification.user_did,
+        notification.device_token,
+        notification.notification_type.as_str(),
+        outcome,
+        receipt.status.map(i32::from),
+        receipt.apns_id,
+        receipt.reason
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryLogEntry {
+    pub notification_id: Option<uuid::Uuid>,
+    pub device_token: String,
+    pub notification_type: String,
+    pub outcome: String,
+    pub status_code: Option<i32>,
+    pub apns_id: Option<String>,
+    pub reason: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+// Newest first, for every device of the DID or only the one given
+pub async fn get_delivery_log<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    device_token: Option<&str>,
+    limit: i64,
+) -> Result<Vec<DeliveryLogEntry>> {
+    let entries = sqlx::query_as!(
+        DeliveryLogEntry,
+        r#"
+        SELECT notification_id, device_token, notification_type, outcome, status_code, apns_id, reason,
+            created_at
+        FROM delivery_log
+        WHERE user_did = $1 AND ($3::TEXT IS NULL OR device_token = $3)
+        ORDER BY created_at DESC, id DESC
+        LIMIT $2
+        "#,
+        did,
+        limit,
+        device_token
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(entries)
+}
+
+// Record a send attempt with its DeliveryOutcome status. Replays reuse the
+// original notification_id, so they don't add a second row, but a successful replay
+// of a failed send marks it delivered. Stored under the device's tenant, like the
+// delivery log.
+pub async fn record_notification_history(
+    pool: &Pool<Postgres>,
+    notification: &NotificationPayload,
+    status: &str,
+) -> Result<()> {
+    let Some(notification_id) = notification
+        .data
+        .get("notification_id")
+        .and_then(|id| uuid::Uuid::parse_str(id).ok())
+    else {
+        return Ok(());
+    };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO notification_history
+            (tenant_id, notification_id, user_did, device_token, notification_type, title, body, data, status)
+        VALUES (device_tenant($2, $3), $1, $2, $3, $4, $5, $6, $7, $8)
+        ON CONFLICT (notification_id) DO UPDATE SET status = EXCLUDED.status
+        WHERE notification_history.status <> 'delivered'
+        "#,
+        notification_id,
+        notification.user_did,
+        notification.device_token,
+        notification.notification_type.as_str(),
+        notification.title,
+        notification.body,
+        serde_json::to_value(&notification.data)?,
+        status
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Which of `dids` were already sent a notification about `uri`
+pub async fn get_notified_users(
+    pool: &Pool<Postgres>,
+    dids: &[String],
+    uri: &str,
+) -> Result<HashSet<String>> {
+    let rows = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT user_did
+        FROM notification_history
+        WHERE user_did = ANY($1) AND data->>'uri' = $2
+        "#,
+        dids,
+        uri
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().collect())
+}
+
+// One page of a device's notification history, newest first. Each device has its
+// own rows, so paging per device shows every notification exactly once.
+pub async fn get_notification_history<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    device_token: &str,
+    before_id: Option<i64>,
+    limit: i64,
+) -> Result<Vec<NotificationHistoryEntry>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id, notification_id, notification_type, title, body, data->>'uri' AS uri,
+            status, created_at
+        FROM notification_history
+        WHERE user_did = $1 AND device_token = $2 AND id < COALESCE($3, 9223372036854775807)
+        AND status <> 'retracted'
+        ORDER BY id DESC
+        LIMIT $4
+        "#,
+        did,
+        device_token,
+        before_id,
+        limit
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| NotificationHistoryEntry {
+            id: row.id,
+            notification_id: row.notification_id,
+            notification_type: row.notification_type,
+            title: row.title,
+            body: row.body,
+            uri: row.uri,
+            status: row.status,
+            created_at: row.created_at,
+        })
+        .collect())
+}
+
+// Rebuild payloads from history for devices the user still has registered
+pub async fn get_replayable_notifications(
+    pool: &Pool<Postgres>,
+    did: &str,
+    since_unix: f64,
+    limit: i64,
+) -> Result<Vec<NotificationPayload>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT h.user_did, h.device_token, h.notification_type, h.title, h.body, h.data
+        FROM notification_history h
+        JOIN user_devices d ON d.device_token = h.device_token AND d.did = h.user_did
+        WHERE h.user_did = $1
+        AND h.created_at >= to_timestamp($2)
+        AND h.status <> 'retracted'
+        AND d.deleted_at IS NULL AND d.verified_at IS NOT NULL
+        ORDER BY h.created_at
+        LIMIT $3
+        "#,
+        did,
+        since_unix,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|row| {
+            Some(NotificationPayload {
+                user_did: row.user_did,
+                device_token: row.device_token,
+                notification_type: NotificationType::parse(&row.notification_type)?,
+                title: row.title,
+                body: row.body,
+                data: serde_json::from_value(row.data).unwrap_or_default(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(tenant_id: &str, device_token: &str, updated_secs: i64, active: bool) -> MergeCandidate {
+        MergeCandidate {
+            id: uuid::Uuid::new_v4(),
+            did: "did:plc:alice".to_string(),
+            tenant_id: tenant_id.to_string(),
+            device_token: device_token.to_string(),
+            updated_at: OffsetDateTime::from_unix_timestamp(updated_secs).unwrap(),
+            active,
+        }
+    }
+
+    #[test]
+    fn groups_tokens_per_tenant_and_keeps_the_newest_active_row() {
+        let older_active = device("default", "ABCD", 100, true);
+        let newer_deleted = device("default", " abcd", 300, false);
+        let newest_active = device("default", "abcd ", 200, true);
+        let other_tenant = device("other", "abcd", 400, true);
+        let unique = device("default", "ffff", 100, true);
+        let uppercase = device("default", "EEEE", 100, true);
+
+        let groups = group_duplicates(vec![
+            older_active.clone(),
+            newer_deleted.clone(),
+            newest_active.clone(),
+            other_tenant,
+            unique,
+            uppercase.clone(),
+        ]);
+
+        // The other tenant's registration and the already-normalized token are left alone
+        assert_eq!(groups.len(), 2);
+
+        let merged = &groups[0];
+        assert_eq!(merged.normalized, "abcd");
+        assert_eq!(merged.survivor.id, newest_active.id);
+        let removed: Vec<_> = merged.duplicates.iter().map(|device| device.id).collect();
+        assert_eq!(removed, vec![older_active.id, newer_deleted.id]);
+
+        let normalized = &groups[1];
+        assert_eq!(normalized.normalized, "eeee");
+        assert_eq!(normalized.survivor.id, uppercase.id);
+        assert!(normalized.duplicates.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_newest_deleted_row_when_none_is_active() {
+        let older = device("default", "abcd", 100, false);
+        let newer = device("default", "ABCD", 200, false);
+
+        let groups = group_duplicates(vec![older.clone(), newer.clone()]);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].survivor.id, newer.id);
+        assert_eq!(groups[0].duplicates[0].id, older.id);
+    }
+}
@@ -0,0 +1,310 @@
+// decoder.rs
+// Firehose decoding off the async runtime. Parsing a commit's dag-cbor, opening the CAR
+// file it carries and decoding the records in it is CPU work that used to run inline
+// on a runtime worker, where a busy stretch of the firehose held up API requests and
+// the rest of the pipeline scheduled on the same thread. Frames now go to the blocking
+// pool as they arrive, with at most FIREHOSE_DECODE_CONCURRENCY in flight, and come back
+// in arrival order, so commits are still handled (and the cursor advanced) in sequence.
+// When decoding falls behind, the consumer stops reading the websocket until a slot
+// frees up rather than buffering frames without bound. firehose_decode_seconds shows
+// how much worker time decoding now takes off the runtime; `bench-decode` compares
+// both ways on frames recorded from the relay.
+//
+// Records that passed the filter's relevance pre-filter are kept by CID for a few
+// minutes (RecordCache), so when the same commit is read again, as it is when frames
+// dropped with a connection are replayed from the stored cursor, those blocks aren't
+// decoded twice. Everything else is never cached, which keeps the cache to the small
+// share of the firehose that concerns our users. A CID names its bytes, so a cached
+// record never goes stale; the TTL only bounds memory. Sized by
+// DECODED_RECORD_CACHE_SIZE (entries, 0 disables).
+use anyhow::{anyhow, Context, Result};
+use atrium_api::app::bsky::feed::like::Record as FeedLike;
+use atrium_api::app::bsky::feed::post::Record as FeedPost;
+use atrium_api::app::bsky::feed::repost::Record as FeedRepost;
+use atrium_api::app::bsky::graph::block::Record as GraphBlock;
+use atrium_api::app::bsky::graph::follow::Record as GraphFollow;
+use atrium_api::app::bsky::graph::listitem::Record as GraphListItem;
+use atrium_api::app::bsky::graph::starterpack::Record as GraphStarterPack;
+use atrium_api::app::bsky::notification::declaration::Record as NotificationDeclaration;
+use atrium_api::com::atproto::sync::subscribe_repos::{Commit, Info};
+use atrium_api::types::CidLink;
+use atrium_repo::blockstore::{AsyncBlockStoreRead, CarStore};
+use futures::stream::FuturesOrdered;
+use futures::StreamExt;
+use ipld_core::cid::Cid;
+use moka::future::Cache;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+pub enum Decoded {
+    Commit(DecodedCommit),
+    Info(Info),
+}
+
+// A commit with its records decoded, ready for the async side
+pub struct DecodedCommit {
+    pub repo: String,
+    pub seq: i64,
+    pub rev: String,
+    pub sent_at: Option<chrono::DateTime<chrono::FixedOffset>>,
+    pub ops: Vec<DecodedOp>,
+}
+
+pub struct DecodedOp {
+    pub action: String,
+    pub path: String,
+    pub cid: Option<String>,
+    // Set for created and updated records of the collections we read, when the block
+    // was present and decoded
+    pub record: Option<serde_json::Value>,
+}
+
+impl DecodedOp {
+    pub fn collection(&self) -> Option<&str> {
+        self.path.split_once('/').map(|(collection, _)| collection)
+    }
+}
+
+const RECORD_CACHE_TTL: Duration = Duration::from_secs(300);
+
+// Decoded records by CID, read by the decoder threads and filled by the filter
+#[derive(Clone)]
+pub struct RecordCache {
+    // None when disabled
+    records: Option<Cache<String, Arc<serde_json::Value>>>,
+}
+
+impl RecordCache {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self::new(config.decoded_record_cache_size)
+    }
+
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            records: (capacity > 0).then(|| {
+                Cache::builder()
+                    .max_capacity(capacity)
+                    .time_to_live(RECORD_CACHE_TTL)
+                    .build()
+            }),
+        }
+    }
+
+    fn get(&self, cid: &str) -> Option<serde_json::Value> {
+        let records = self.records.as_ref()?;
+        let record = records.get(cid);
+        let result = if record.is_some() { "hit" } else { "miss" };
+        crate::metrics::DECODED_RECORD_CACHE_LOOKUPS.with_label_values(&[result]).inc();
+        record.map(|record| record.as_ref().clone())
+    }
+
+    // Keep a record the filter found relevant
+    pub async fn insert(&self, cid: &str, record: &serde_json::Value) {
+        if let Some(records) = &self.records {
+            records.insert(cid.to_string(), Arc::new(record.clone())).await;
+        }
+    }
+}
+
+pub struct Decoder {
+    in_flight: FuturesOrdered<JoinHandle<Result<Decoded>>>,
+    max_in_flight: usize,
+    records: RecordCache,
+}
+
+impl Decoder {
+    pub fn new(max_in_flight: usize, records: RecordCache) -> Self {
+        Self {
+            in_flight: FuturesOrdered::new(),
+            max_in_flight: max_in_flight.max(1),
+            records,
+        }
+    }
+
+    pub fn has_room(&self) -> bool {
+        self.in_flight.len() < self.max_in_flight
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+
+    // Drop the results of frames still in flight
+    pub fn clear(&mut self) {
+        self.in_flight = FuturesOrdered::new();
+    }
+
+    // Start decoding a "#commit" or "#info" message body
+    pub fn push(&mut self, kind: String, body: Vec<u8>) {
+        let records = self.records.clone();
+        self.in_flight.push_back(tokio::task::spawn_blocking(move || {
+            let _timer = crate::metrics::FIREHOSE_DECODE_TIME.start_timer();
+            decode(&kind, &body, &records)
+        }));
+    }
+
+    // The oldest frame's result, once it's ready
+    pub async fn next(&mut self) -> Option<Result<Decoded>> {
+        let joined = self.in_flight.next().await?;
+        Some(joined.unwrap_or_else(|e| Err(anyhow!("Decoder task failed: {}", e))))
+    }
+}
+
+pub(crate) fn decode(kind: &str, body: &[u8], records: &RecordCache) -> Result<Decoded> {
+    match kind {
+        "#commit" => {
+            let commit: Commit = serde_ipld_dagcbor::from_reader(body).context("Failed to parse commit")?;
+            // The CAR file is already in memory, so its reads complete without waiting
+            futures::executor::block_on(decode_commit(commit, records)).map(Decoded::Commit)
+        }
+        "#info" => Ok(Decoded::Info(
+            serde_ipld_dagcbor::from_reader(body).context("Failed to parse info message")?,
+        )),
+        _ => Err(anyhow!("Unexpected message type {}", kind)),
+    }
+}
+
+async fn decode_commit(commit: Commit, records: &RecordCache) -> Result<DecodedCommit> {
+    let mut car_store = CarStore::open(Cursor::new(&commit.blocks[..]))
+        .await
+        .map_err(|e| anyhow!("Failed to create CarStore: {}", e))?;
+
+    let mut ops = Vec::with_capacity(commit.ops.len());
+    for op in &commit.ops {
+        let mut decoded = DecodedOp {
+            action: op.action.clone(),
+            path: op.path.clone(),
+            cid: op.cid.as_ref().map(|cid_link| format!("{:?}", cid_link.0)),
+            record: None,
+        };
+
+        let collection = decoded.collection().unwrap_or_default().to_string();
+        if let (Some(cid_link), Some(key), "create" | "update") = (&op.cid, &decoded.cid, op.action.as_str()) {
+            if is_decoded_collection(&collection) {
+                decoded.record = match records.get(key) {
+                    Some(record) => Some(record),
+                    None => read_record(&mut car_store, cid_link, &collection).await,
+                };
+            }
+        }
+        ops.push(decoded);
+    }
+
+    Ok(DecodedCommit {
+        repo: commit.repo.to_string(),
+        seq: commit.seq,
+        rev: commit.rev.as_str().to_string(),
+        sent_at: chrono::DateTime::parse_from_rfc3339(commit.time.as_str()).ok(),
+        ops,
+    })
+}
+
+// Read and decode a record's block
+async fn read_record(
+    car_store: &mut impl AsyncBlockStoreRead,
+    cid_link: &CidLink,
+    collection: &str,
+) -> Option<serde_json::Value> {
+    let cid = Cid::try_from(cid_link.0.to_bytes().as_slice())
+        .map_err(|e| error!("Invalid CID format: {}", e))
+        .ok()?;
+
+    let mut record_block = Vec::new();
+    if let Err(e) = car_store.read_block_into(cid, &mut record_block).await {
+        debug!("Record block not found for CID: {:?}, error: {}", cid_link, e);
+        return None;
+    }
+    deserialize_record(collection, &record_block)
+        .map_err(|e| debug!("Failed to deserialize {}: {}", collection, e))
+        .ok()
+}
+
+pub(crate) fn is_decoded_collection(collection: &str) -> bool {
+    matches!(
+        collection,
+        "app.bsky.feed.post"
+            | "app.bsky.feed.like"
+            | "app.bsky.graph.follow"
+            | "app.bsky.graph.block"
+            | "app.bsky.feed.repost"
+            | "app.bsky.graph.listitem"
+            | "app.bsky.graph.starterpack"
+            | "app.bsky.notification.declaration"
+    )
+}
+
+// Helper function with improved error handling for different record types
+pub(crate) fn deserialize_record(collection: &str, record_block: &[u8]) -> Result<serde_json::Value> {
+    let cursor = Cursor::new(record_block);
+    match collection {
+        "app.bsky.feed.post" => {
+            let post: FeedPost = serde_ipld_dagcbor::from_reader(cursor)?;
+            Ok(serde_json::to_value(post)?)
+        }
+        "app.bsky.feed.like" => {
+            let like: FeedLike = serde_ipld_dagcbor::from_reader(cursor)?;
+            Ok(serde_json::to_value(like)?)
+        }
+        "app.bsky.graph.follow" => {
+            let follow: GraphFollow = serde_ipld_dagcbor::from_reader(cursor)?;
+            Ok(serde_json::to_value(follow)?)
+        }
+        "app.bsky.graph.block" => {
+            let block: GraphBlock = serde_ipld_dagcbor::from_reader(cursor)?;
+            Ok(serde_json::to_value(block)?)
+        }
+        "app.bsky.feed.repost" => {
+            let repost: FeedRepost = serde_ipld_dagcbor::from_reader(cursor)?;
+            Ok(serde_json::to_value(repost)?)
+        }
+        "app.bsky.graph.listitem" => {
+            let list_item: GraphListItem = serde_ipld_dagcbor::from_reader(cursor)?;
+            Ok(serde_json::to_value(list_item)?)
+        }
+        "app.bsky.graph.starterpack" => {
+            let starter_pack: GraphStarterPack = serde_ipld_dagcbor::from_reader(cursor)?;
+            Ok(serde_json::to_value(starter_pack)?)
+        }
+        "app.bsky.notification.declaration" => {
+            let declaration: NotificationDeclaration = serde_ipld_dagcbor::from_reader(cursor)?;
+            Ok(serde_json::to_value(declaration)?)
+        }
+        _ => Err(anyhow!("Unsupported collection type: {}", collection)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_frames_in_arrival_order_within_the_bound() {
+        let mut decoder = Decoder::new(2, RecordCache::new(0));
+        decoder.push("#commit".to_string(), vec![0xff; 4096]);
+        assert!(decoder.has_room());
+        decoder.push("#labels".to_string(), Vec::new());
+        assert!(!decoder.has_room());
+
+        let first = decoder.next().await.unwrap().err().unwrap();
+        assert!(first.to_string().starts_with("Failed to parse commit"));
+        assert!(decoder.has_room());
+        let second = decoder.next().await.unwrap().err().unwrap();
+        assert_eq!(second.to_string(), "Unexpected message type #labels");
+        assert!(decoder.is_empty());
+    }
+    #[tokio::test]
+    async fn caches_records_by_cid() {
+        let records = RecordCache::new(100);
+        let like = serde_json::json!({ "subject": { "uri": "at://did:plc:a/app.bsky.feed.post/1" } });
+        assert!(records.get("bafyrei").is_none());
+        records.insert("bafyrei", &like).await;
+        assert_eq!(records.get("bafyrei"), Some(like.clone()));
+
+        let disabled = RecordCache::new(0);
+        disabled.insert("bafyrei", &like).await;
+        assert!(disabled.get("bafyrei").is_none());
+    }
+}
@@ -0,0 +1,78 @@
+// dedup.rs
+// Drops events the filter has already processed. A firehose reconnect resumes from the
+// last stored cursor, which is only written after a whole commit, and relays can
+// deliver the same commit twice, so one record can reach the filter more than once.
+// Each event is keyed by author, path and record CID and remembered for
+// EVENT_DEDUP_WINDOW_SECONDS. Keys are kept in memory, so a restart forgets them.
+use moka::future::Cache;
+use std::time::Duration;
+
+use crate::models::BlueskyEvent;
+
+pub fn idempotency_key(event: &BlueskyEvent) -> String {
+    format!("{}|{}|{}", event.author, event.path, event.cid)
+}
+
+#[derive(Clone)]
+pub struct EventDedup {
+    // None when disabled
+    seen: Option<Cache<String, ()>>,
+}
+
+impl EventDedup {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self::new(Duration::from_secs(config.event_dedup_window_seconds))
+    }
+
+    pub fn new(window: Duration) -> Self {
+        Self {
+            seen: (!window.is_zero()).then(|| {
+                Cache::builder()
+                    .max_capacity(1_000_000)
+                    .time_to_live(window)
+                    .build()
+            }),
+        }
+    }
+
+    // Whether this is the first time the event was offered inside the window
+    pub async fn first_seen(&self, event: &BlueskyEvent) -> bool {
+        let Some(seen) = &self.seen else {
+            return true;
+        };
+        seen.entry(idempotency_key(event))
+            .or_insert(())
+            .await
+            .is_fresh()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn like(cid: &str) -> BlueskyEvent {
+        BlueskyEvent {
+            op: "create".to_string(),
+            path: "app.bsky.feed.like/3kabc".to_string(),
+            cid: cid.to_string(),
+            author: "did:plc:author".to_string(),
+            record: serde_json::json!({}),
+            timestamp: 0,
+            origin: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn drops_repeats_of_the_same_record() {
+        let dedup = EventDedup::new(Duration::from_secs(60));
+
+        assert!(dedup.first_seen(&like("bafy1")).await);
+        assert!(!dedup.first_seen(&like("bafy1")).await);
+        assert!(dedup.first_seen(&like("bafy2")).await);
+
+        let disabled = EventDedup::new(Duration::ZERO);
+        assert!(disabled.first_seen(&like("bafy1")).await);
+        assert!(disabled.first_seen(&like("bafy1")).await);
+    }
+}
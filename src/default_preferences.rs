@@ -0,0 +1,125 @@
+// default_preferences.rs
+// Preference values a new device starts with. Every toggle defaults to the column
+// default in the schema unless the deployment overrides it in DEFAULT_PREFERENCES
+// (e.g. "likes=false,reposts=false,digest_low_priority=true"). Toggles the user already
+// set in the official app win over both. New toggles are added to PREFERENCE_COLUMNS so
+// deployments can set their defaults too.
+use anyhow::Result;
+use sqlx::Postgres;
+use std::collections::HashMap;
+
+use crate::server_preferences::ServerPreferences;
+
+// Boolean columns of notification_preferences a deployment may set a default for
+pub const PREFERENCE_COLUMNS: &[&str] = &[
+    "mentions",
+    "replies",
+    "likes",
+    "follows",
+    "reposts",
+    "quotes",
+    "replies_to_replies",
+    "priority_from_mutuals",
+    "feed_posts",
+    "digest_low_priority",
+    "dms",
+    "dm_redact_body",
+    "post_edits",
+    "list_additions",
+    "comeback_posts",
+    "custom_notifications",
+    "reply_context",
+    "only_from_follows",
+];
+
+// "column=true|false" pairs separated by commas
+pub fn parse_default_preferences(spec: &str) -> Option<HashMap<String, bool>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (column, value) = entry.split_once('=')?;
+            let column = column.trim();
+            if !PREFERENCE_COLUMNS.contains(&column) {
+                return None;
+            }
+            Some((column.to_string(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+// The deployment's defaults with the user's server-side toggles laid over them
+pub fn merge(defaults: &HashMap<String, bool>, server: &ServerPreferences) -> HashMap<String, bool> {
+    let mut preferences = defaults.clone();
+    let known = [
+        ("mentions", server.mentions),
+        ("replies", server.replies),
+        ("likes", server.likes),
+        ("follows", server.follows),
+        ("reposts", server.reposts),
+        ("quotes", server.quotes),
+    ];
+    for (column, value) in known {
+        if let Some(value) = value {
+            preferences.insert(column.to_string(), value);
+        }
+    }
+    preferences
+}
+
+// Set the given toggles on a device's preferences row
+pub async fn apply(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    device_id: uuid::Uuid,
+    preferences: &HashMap<String, bool>,
+) -> Result<()> {
+    // Column names come from PREFERENCE_COLUMNS, never from the map itself
+    let columns: Vec<&str> = PREFERENCE_COLUMNS
+        .iter()
+        .copied()
+        .filter(|column| preferences.contains_key(*column))
+        .collect();
+    if columns.is_empty() {
+        return Ok(());
+    }
+
+    let assignments: Vec<String> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| format!("{} = ${}", column, i + 2))
+        .collect();
+    let query = format!(
+        "UPDATE notification_preferences SET {} WHERE user_id = $1",
+        assignments.join(", ")
+    );
+
+    let mut q = sqlx::query(&query).bind(device_id);
+    for column in &columns {
+        q = q.bind(preferences[*column]);
+    }
+    q.execute(&mut **tx).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_toggles_win_over_deployment_defaults() {
+        let defaults = parse_default_preferences("likes=false, reposts=false, digest_low_priority=true").unwrap();
+        assert!(parse_default_preferences("likes=maybe").is_none());
+        assert!(parse_default_preferences("user_id=true").is_none());
+
+        let server = ServerPreferences {
+            likes: Some(true),
+            ..Default::default()
+        };
+        let preferences = merge(&defaults, &server);
+        assert!(preferences["likes"]);
+        assert!(!preferences["reposts"]);
+        assert!(preferences["digest_low_priority"]);
+        assert!(!preferences.contains_key("mentions"));
+    }
+}
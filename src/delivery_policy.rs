@@ -0,0 +1,397 @@
+// delivery_policy.rs
+// Whether a recipient's device gets a push for an event, in one place. The filter used
+// to run the mute, block, cool-down, preference, threshold, quiet-hour, digest and quota
+// checks inline, split between the per-recipient loop and the per-device futures;
+// DeliveryPolicy::evaluate runs them in the same order and returns a Decision, and the
+// filter only acts on it. A deferred notification isn't lost: it's counted toward the
+// quiet-hours summary, the activity rollup or the tenant's quota digest, whichever the
+// Decision names, by DeliveryPolicy::defer. Every suppression and deferral is counted
+// in notifications_skipped_total under its reason.
+//
+// Checks are ordered cheapest first and stop at the first that holds the push back.
+// VIP authors skip the cool-down and everything after the recipient's thresholds.
+use anyhow::Result;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use tracing::{error, warn};
+
+use crate::cooldown::Cooldowns;
+use crate::models::{BlueskyEvent, NotificationPreference, NotificationType, QuietHoursMode, UserDevice};
+use crate::profile_resolver::ProfileResolver;
+use crate::quota::QuotaTracker;
+use crate::relationship_manager::RelationshipManager;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuppressReason {
+    SelfNotification,
+    Muted,
+    Blocked,
+    MutedWord,
+    // "Only people I follow" is on and the recipient doesn't follow the author
+    NotFollowed,
+    Cooldown,
+    PreferenceOff,
+    Thresholds,
+    // Quiet hours in drop mode
+    QuietHours,
+}
+
+impl SuppressReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressReason::SelfNotification => "self",
+            SuppressReason::Muted => "muted",
+            SuppressReason::Blocked => "blocked",
+            SuppressReason::MutedWord => "muted_word",
+            SuppressReason::NotFollowed => "not_followed",
+            SuppressReason::Cooldown => "cooldown",
+            SuppressReason::PreferenceOff => "preference_off",
+            SuppressReason::Thresholds => "thresholds",
+            SuppressReason::QuietHours => "quiet_hours",
+        }
+    }
+}
+
+// The batched send a deferred notification goes out with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeferUntil {
+    // The summary sent when the device's quiet hours end
+    QuietHoursEnd,
+    // The recipient's next likes-and-reposts rollup
+    ActivityDigest,
+    // The over-quota tenant's next digest
+    QuotaDigest,
+}
+
+impl DeferUntil {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeferUntil::QuietHoursEnd => "quiet_hours",
+            DeferUntil::ActivityDigest => "digest",
+            DeferUntil::QuotaDigest => "quota",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Deliver,
+    Suppress(SuppressReason),
+    Defer(DeferUntil),
+}
+
+impl Decision {
+    // The label the decision is counted under, if it holds the push back
+    pub fn reason(&self) -> Option<&'static str> {
+        match self {
+            Decision::Deliver => None,
+            Decision::Suppress(reason) => Some(reason.as_str()),
+            Decision::Defer(until) => Some(until.as_str()),
+        }
+    }
+}
+
+// One device of a recipient, with its preferences
+pub struct Recipient<'a> {
+    pub did: &'a str,
+    pub device: &'a UserDevice,
+    pub prefs: &'a NotificationPreference,
+    // The author is one of the recipient's VIPs
+    pub vip: bool,
+    // The event is an edit of a post the recipient was notified about
+    pub edit: bool,
+}
+
+#[derive(Clone)]
+pub struct DeliveryPolicy {
+    db_pool: Pool<Postgres>,
+    relationship_manager: Arc<RelationshipManager>,
+    profile_resolver: Arc<ProfileResolver>,
+    cooldowns: Cooldowns,
+    quota: Arc<QuotaTracker>,
+}
+
+impl DeliveryPolicy {
+    pub fn new(
+        db_pool: Pool<Postgres>,
+        relationship_manager: Arc<RelationshipManager>,
+        profile_resolver: Arc<ProfileResolver>,
+        cooldowns: Cooldowns,
+        quota: Arc<QuotaTracker>,
+    ) -> Self {
+        Self {
+            db_pool,
+            relationship_manager,
+            profile_resolver,
+            cooldowns,
+            quota,
+        }
+    }
+
+    pub async fn evaluate(
+        &self,
+        recipient: &Recipient<'_>,
+        event: &BlueskyEvent,
+        notification_type: &NotificationType,
+    ) -> Decision {
+        if recipient.did == event.author {
+            return Decision::Suppress(SuppressReason::SelfNotification);
+        }
+        if self.relationship_manager.is_muted(recipient.did, &event.author).await {
+            return Decision::Suppress(SuppressReason::Muted);
+        }
+        if self.relationship_manager.is_blocked(recipient.did, &event.author).await {
+            return Decision::Suppress(SuppressReason::Blocked);
+        }
+        if event.path.starts_with("app.bsky.feed.post/")
+            && self.relationship_manager.has_muted_word(recipient.did, &event.record).await
+        {
+            return Decision::Suppress(SuppressReason::MutedWord);
+        }
+        // VIPs were picked by the recipient, so they count as followed
+        if recipient.prefs.only_from_follows
+            && !recipient.vip
+            && limited_to_follows(notification_type)
+            && !self.relationship_manager.follows(recipient.did, &event.author).await
+        {
+            return Decision::Suppress(SuppressReason::NotFollowed);
+        }
+
+        // Keyed by device so each of the recipient's devices gets the first one
+        if !recipient.vip
+            && !self
+                .cooldowns
+                .allow(&recipient.device.id.to_string(), &event.author, notification_type)
+                .await
+        {
+            crate::metrics::NOTIFICATIONS_COOLED_DOWN
+                .with_label_values(&[notification_type.as_str()])
+                .inc();
+            return Decision::Suppress(SuppressReason::Cooldown);
+        }
+
+        if !wants(recipient.prefs, notification_type, recipient.edit, &event.record) {
+            return Decision::Suppress(SuppressReason::PreferenceOff);
+        }
+        if !self.meets_author_thresholds(recipient.device.id, notification_type, &event.author).await {
+            return Decision::Suppress(SuppressReason::Thresholds);
+        }
+
+        if recipient.vip {
+            crate::metrics::NOTIFICATIONS_VIP.inc();
+            return Decision::Deliver;
+        }
+
+        let quiet_hours = match crate::quiet_hours::active_mode(&self.db_pool, recipient.device.id).await {
+            Ok(mode) => mode,
+            Err(e) => {
+                warn!("Failed to check quiet hours: {}", e);
+                None
+            }
+        };
+        if let Some(mode) = quiet_hours {
+            crate::metrics::NOTIFICATIONS_QUIET_HOURS
+                .with_label_values(&[mode.as_str()])
+                .inc();
+        }
+        let over_quota = self.quota.is_over_quota(&recipient.device.tenant_id).await;
+
+        hold_back(recipient.prefs, notification_type, quiet_hours, over_quota)
+    }
+
+    // Count a deferred notification toward the batched send it was deferred to
+    pub async fn defer(
+        &self,
+        recipient: &Recipient<'_>,
+        notification_type: &NotificationType,
+        until: DeferUntil,
+    ) -> Result<()> {
+        let device = recipient.device;
+        match until {
+            DeferUntil::QuietHoursEnd => crate::quiet_hours::defer(&self.db_pool, device.id, notification_type).await,
+            DeferUntil::ActivityDigest => {
+                crate::activity_digest::defer(&self.db_pool, device.id, notification_type).await
+            }
+            DeferUntil::QuotaDigest => {
+                self.quota
+                    .defer_to_digest(&device.tenant_id, recipient.did, &device.device_token, notification_type)
+                    .await
+            }
+        }
+    }
+
+    // Check the author's account age and follower count against the recipient's thresholds.
+    // Fails open when thresholds or the profile can't be loaded so outages don't drop notifications.
+    async fn meets_author_thresholds(
+        &self,
+        user_id: uuid::Uuid,
+        notification_type: &NotificationType,
+        author: &str,
+    ) -> bool {
+        let threshold =
+            match crate::db::get_notification_threshold(&self.db_pool, user_id, notification_type.as_str()).await {
+                Ok(Some(threshold)) => threshold,
+                Ok(None) => return true,
+                Err(e) => {
+                    error!("Failed to get notification thresholds: {}", e);
+                    return true;
+                }
+            };
+
+        if threshold.min_account_age_days.is_none() && threshold.min_followers.is_none() {
+            return true;
+        }
+
+        let profile = match self.profile_resolver.get_profile(author).await {
+            Ok(profile) => profile,
+            Err(e) => {
+                warn!(author = %author, error = %e, "Failed to resolve author profile for thresholds");
+                return true;
+            }
+        };
+
+        if let Some(min_age) = threshold.min_account_age_days {
+            // Profiles without a creation date can't prove their age
+            match profile.account_age_days() {
+                Some(age) if age >= min_age as i64 => {}
+                _ => return false,
+            }
+        }
+
+        if let Some(min_followers) = threshold.min_followers {
+            if profile.followers_count.unwrap_or(0) < min_followers as i64 {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+// The types "only people I follow" applies to
+fn limited_to_follows(notification_type: &NotificationType) -> bool {
+    matches!(
+        notification_type,
+        NotificationType::Like | NotificationType::Repost | NotificationType::Reply
+    )
+}
+
+// Whether the device's preferences turn this type of notification on
+fn wants(
+    prefs: &NotificationPreference,
+    notification_type: &NotificationType,
+    edit: bool,
+    record: &serde_json::Value,
+) -> bool {
+    match notification_type {
+        NotificationType::Mention if edit => prefs.mentions && prefs.post_edits,
+        NotificationType::Mention => prefs.mentions,
+        NotificationType::Reply if crate::filter::is_nested_reply(record) => prefs.replies_to_replies,
+        NotificationType::Reply => prefs.replies,
+        NotificationType::Like => prefs.likes,
+        NotificationType::Follow => prefs.follows,
+        NotificationType::Repost => prefs.reposts,
+        NotificationType::Quote => prefs.quotes,
+        NotificationType::FeedPost => prefs.feed_posts,
+        // Subscribing to the author was the opt-in
+        NotificationType::SubscribedPost => true,
+        NotificationType::DirectMessage => prefs.dms,
+        NotificationType::ListAddition => prefs.list_additions,
+        NotificationType::Custom => prefs.custom_notifications,
+    }
+}
+
+// For a non-VIP push the recipient wants: inside quiet hours it's dropped or held for the
+// summary, opted-in low-priority types wait for the rollup, and over-quota tenants get
+// a digest instead
+fn hold_back(
+    prefs: &NotificationPreference,
+    notification_type: &NotificationType,
+    quiet_hours: Option<QuietHoursMode>,
+    over_quota: bool,
+) -> Decision {
+    match quiet_hours {
+        Some(QuietHoursMode::Queue) => return Decision::Defer(DeferUntil::QuietHoursEnd),
+        Some(_) => return Decision::Suppress(SuppressReason::QuietHours),
+        None => {}
+    }
+    if prefs.digest_low_priority && crate::activity_digest::is_digestible(notification_type) {
+        return Decision::Defer(DeferUntil::ActivityDigest);
+    }
+    if over_quota {
+        return Decision::Defer(DeferUntil::QuotaDigest);
+    }
+    Decision::Deliver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefs() -> NotificationPreference {
+        NotificationPreference {
+            user_id: uuid::Uuid::nil(),
+            mentions: true,
+            replies: true,
+            likes: true,
+            follows: true,
+            reposts: false,
+            quotes: true,
+            replies_to_replies: false,
+            priority_from_mutuals: false,
+            feed_posts: true,
+            digest_low_priority: false,
+            dms: true,
+            dm_redact_body: false,
+            post_edits: false,
+            list_additions: true,
+            background_types: Vec::new(),
+            comeback_posts: true,
+            grouping: "app".to_string(),
+            custom_notifications: true,
+            payload_version: 1,
+            payload_event_origin: false,
+            reply_context: false,
+            only_from_follows: false,
+        }
+    }
+
+    #[test]
+    fn applies_preferences_then_holds_back_in_order() {
+        let mut prefs = prefs();
+        let top_level = serde_json::json!({});
+        let nested = serde_json::json!({
+            "reply": { "root": { "uri": "at://a/p/1" }, "parent": { "uri": "at://a/p/2" } }
+        });
+        let root_by_handle = serde_json::json!({
+            "reply": {
+                "root": { "uri": "at://alice.test/p/1", "cid": "bafy1" },
+                "parent": { "uri": "at://did:plc:alice/p/1", "cid": "bafy1" }
+            }
+        });
+        assert!(wants(&prefs, &NotificationType::Mention, false, &top_level));
+        assert!(!wants(&prefs, &NotificationType::Mention, true, &top_level));
+        assert!(wants(&prefs, &NotificationType::Reply, false, &top_level));
+        assert!(!wants(&prefs, &NotificationType::Reply, false, &nested));
+        assert!(wants(&prefs, &NotificationType::Reply, false, &root_by_handle));
+        assert!(!wants(&prefs, &NotificationType::Repost, false, &top_level));
+        assert!(limited_to_follows(&NotificationType::Reply));
+        assert!(!limited_to_follows(&NotificationType::Mention));
+
+        let like = NotificationType::Like;
+        assert_eq!(hold_back(&prefs, &like, None, false), Decision::Deliver);
+        assert_eq!(
+            hold_back(&prefs, &like, Some(QuietHoursMode::Queue), true),
+            Decision::Defer(DeferUntil::QuietHoursEnd)
+        );
+        assert_eq!(
+            hold_back(&prefs, &like, Some(QuietHoursMode::Suppress), false).reason(),
+            Some("quiet_hours")
+        );
+        assert_eq!(hold_back(&prefs, &like, None, true), Decision::Defer(DeferUntil::QuotaDigest));
+
+        prefs.digest_low_priority = true;
+        assert_eq!(hold_back(&prefs, &like, None, true), Decision::Defer(DeferUntil::ActivityDigest));
+        assert_eq!(hold_back(&prefs, &NotificationType::Mention, None, false), Decision::Deliver);
+    }
+}
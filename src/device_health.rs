@@ -0,0 +1,287 @@
+// device_health.rs
+// Quarantine for devices whose pushes keep failing in transport (typically tokens
+// whose network path is black-holed); APNs throttling and server errors don't count.
+// Once a device has failed continuously for the configured window it stops getting
+// regular sends, with their full retry budget, and instead gets a single-attempt probe
+// on an exponentially growing schedule. The first successful probe lifts the quarantine. Tokens APNs says belong
+// to another app are quarantined straight away, since no retry will ever reach them.
+use anyhow::Result;
+use moka::future::Cache;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+// Failing devices tracked at once; past this the least recently failing are forgotten
+const MAX_FAILURE_STREAKS: u64 = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct QuarantinePolicy {
+    // Failures must span at least this long before a device is quarantined
+    pub failure_window: Duration,
+    // ...and number at least this many, so one burst of sends can't trip it
+    pub min_failures: u32,
+    pub base_probe_interval: Duration,
+    pub max_probe_interval: Duration,
+}
+
+impl QuarantinePolicy {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            failure_window: Duration::from_secs(config.quarantine_failure_window_hours * 3600),
+            min_failures: config.quarantine_min_failures,
+            base_probe_interval: Duration::from_secs(config.quarantine_probe_interval_minutes * 60),
+            max_probe_interval: Duration::from_secs(24 * 3600),
+        }
+    }
+
+    // Delay before the probe following `probes` failed probes
+    fn probe_delay(&self, probes: u32) -> Duration {
+        self.base_probe_interval
+            .checked_mul(1 << probes.min(16))
+            .unwrap_or(self.max_probe_interval)
+            .min(self.max_probe_interval)
+    }
+}
+
+// What the sender should do with a notification for a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendMode {
+    Normal,
+    // Quarantined and a probe is due: one attempt, no retries
+    Probe,
+    // Quarantined and between probes: drop
+    Skip,
+}
+
+#[derive(Clone, Copy)]
+struct FailureStreak {
+    since: Instant,
+    failures: u32,
+}
+
+struct Quarantine {
+    probes: u32,
+    next_probe: Instant,
+}
+
+pub struct DeviceHealth {
+    db_pool: Pool<Postgres>,
+    policy: QuarantinePolicy,
+    // Streaks are only tracked in memory; quarantines are persisted so a restart
+    // doesn't hand a dead device a fresh retry budget
+    streaks: Cache<String, FailureStreak>,
+    quarantined: RwLock<HashMap<String, Quarantine>>,
+}
+
+impl DeviceHealth {
+    pub async fn new(db_pool: Pool<Postgres>, policy: QuarantinePolicy) -> Result<Self> {
+        let health = Self::in_memory(db_pool, policy);
+
+        let rows = sqlx::query!(
+            r#"
+            SELECT device_token, probes,
+                GREATEST(EXTRACT(EPOCH FROM next_probe_at - NOW()), 0)::FLOAT8 AS "probe_in_secs!"
+            FROM device_quarantine
+            "#
+        )
+        .fetch_all(&health.db_pool)
+        .await?;
+
+        {
+            let mut quarantined = health.quarantined.write().await;
+            for row in rows {
+                quarantined.insert(
+                    row.device_token,
+                    Quarantine {
+                        probes: row.probes as u32,
+                        next_probe: Instant::now() + Duration::from_secs_f64(row.probe_in_secs),
+                    },
+                );
+            }
+            crate::metrics::DEVICES_QUARANTINED.set(quarantined.len() as f64);
+        }
+
+        Ok(health)
+    }
+
+    fn in_memory(db_pool: Pool<Postgres>, policy: QuarantinePolicy) -> Self {
+        Self {
+            db_pool,
+            policy,
+            streaks: Cache::new(MAX_FAILURE_STREAKS),
+            quarantined: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn send_mode(&self, device_token: &str) -> SendMode {
+        match self.quarantined.read().await.get(device_token) {
+            None => SendMode::Normal,
+            Some(quarantine) if Instant::now() >= quarantine.next_probe => SendMode::Probe,
+            Some(_) => SendMode::Skip,
+        }
+    }
+
+    pub async fn record_success(&self, device_token: &str) {
+        // Nearly every send succeeds, so check before invalidating
+        if self.streaks.contains_key(device_token) {
+            self.streaks.invalidate(device_token).await;
+        }
+
+        if !self.quarantined.read().await.contains_key(device_token) {
+            return;
+        }
+
+        let released = {
+            let mut quarantined = self.quarantined.write().await;
+            let released = quarantined.remove(device_token).is_some();
+            crate::metrics::DEVICES_QUARANTINED.set(quarantined.len() as f64);
+            released
+        };
+
+        if released {
+            info!("Probe succeeded, releasing device from quarantine");
+            if let Err(e) = sqlx::query!("DELETE FROM device_quarantine WHERE device_token = $1", device_token)
+                .execute(&self.db_pool)
+                .await
+            {
+                warn!("Failed to release device quarantine: {}", e);
+            }
+        }
+    }
+
+    // Only transport failures count; rejections are handled by the sender
+    pub async fn record_retryable_failure(&self, device_token: &str) {
+        let probes = {
+            let mut quarantined = self.quarantined.write().await;
+            if let Some(quarantine) = quarantined.get_mut(device_token) {
+                // A failed probe pushes the next one further out
+                quarantine.probes += 1;
+                quarantine.next_probe = Instant::now() + self.policy.probe_delay(quarantine.probes);
+                Some(quarantine.probes)
+            } else {
+                None
+            }
+        };
+
+        if let Some(probes) = probes {
+            self.persist(device_token, probes).await;
+            return;
+        }
+
+        let mut streak = self.streaks.get(device_token).unwrap_or(FailureStreak {
+            since: Instant::now(),
+            failures: 0,
+        });
+        streak.failures += 1;
+
+        let quarantine = streak.failures >= self.policy.min_failures
+            && streak.since.elapsed() >= self.policy.failure_window;
+        if quarantine {
+            self.streaks.invalidate(device_token).await;
+        } else {
+            self.streaks.insert(device_token.to_string(), streak).await;
+        }
+
+        if quarantine {
+            warn!("Device has failed continuously, moving it to quarantine");
+            self.quarantine(device_token).await;
+        }
+    }
+
+    // The token was issued to another app, so no streak is needed: quarantine now, or
+    // count a failed probe if it already is
+    pub async fn record_topic_mismatch(&self, device_token: &str) {
+        crate::metrics::DEVICE_TOPIC_MISMATCHES.inc();
+        if self.quarantined.read().await.contains_key(device_token) {
+            self.record_retryable_failure(device_token).await;
+            return;
+        }
+
+        warn!("Device token is not for this app's APNs topic, moving it to quarantine");
+        self.streaks.invalidate(device_token).await;
+        self.quarantine(device_token).await;
+    }
+
+    async fn quarantine(&self, device_token: &str) {
+        {
+            let mut quarantined = self.quarantined.write().await;
+            quarantined.insert(
+                device_token.to_string(),
+                Quarantine {
+                    probes: 0,
+                    next_probe: Instant::now() + self.policy.probe_delay(0),
+                },
+            );
+            crate::metrics::DEVICES_QUARANTINED.set(quarantined.len() as f64);
+        }
+        self.persist(device_token, 0).await;
+    }
+
+    async fn persist(&self, device_token: &str, probes: u32) {
+        let delay = self.policy.probe_delay(probes).as_secs_f64();
+        if let Err(e) = sqlx::query!(
+            r#"
+            INSERT INTO device_quarantine (device_token, probes, next_probe_at)
+            VALUES ($1, $2, NOW() + make_interval(secs => $3))
+            ON CONFLICT (device_token)
+            DO UPDATE SET probes = EXCLUDED.probes, next_probe_at = EXCLUDED.next_probe_at
+            "#,
+            device_token,
+            probes as i32,
+            delay
+        )
+        .execute(&self.db_pool)
+        .await
+        {
+            warn!("Failed to persist device quarantine: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> QuarantinePolicy {
+        QuarantinePolicy {
+            failure_window: Duration::ZERO,
+            min_failures: 3,
+            base_probe_interval: Duration::from_secs(60),
+            max_probe_interval: Duration::from_secs(600),
+        }
+    }
+
+    #[test]
+    fn probe_delay_doubles_up_to_the_cap() {
+        let policy = policy();
+        assert_eq!(policy.probe_delay(0), Duration::from_secs(60));
+        assert_eq!(policy.probe_delay(2), Duration::from_secs(240));
+        assert_eq!(policy.probe_delay(10), Duration::from_secs(600));
+    }
+
+    #[tokio::test]
+    async fn streak_of_failures_quarantines_and_success_resets() {
+        // Lazy pool: persistence fails and is logged, in-memory state still applies
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(50))
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        let health = DeviceHealth::in_memory(pool, policy());
+
+        health.record_retryable_failure("a").await;
+        health.record_success("a").await;
+        health.record_retryable_failure("a").await;
+        health.record_retryable_failure("a").await;
+        assert_eq!(health.send_mode("a").await, SendMode::Normal);
+
+        health.record_retryable_failure("a").await;
+        assert_eq!(health.send_mode("a").await, SendMode::Skip);
+
+        assert_eq!(health.send_mode("b").await, SendMode::Normal);
+
+        health.record_topic_mismatch("b").await;
+        assert_eq!(health.send_mode("b").await, SendMode::Skip);
+    }
+}
@@ -0,0 +1,300 @@
+// did_prefetch.rs
+// Resolving DIDs before a notification needs them. When a post goes viral, thousands of
+// events name the same few accounts at once, and every cache entry that has lapsed for
+// them turns into a plc.directory request on the delivery path. The resolver counts
+// the DIDs it's asked about; every DID_PREFETCH_INTERVAL_SECS this job takes the most
+// looked-up ones (up to DID_PREFETCH_MAX) and resolves those that aren't cached or are
+// within PREFETCH_HORIZON of expiring, so they're warm when the next burst arrives.
+//
+// DIDs prefetched recently stay "hot", and the job also tails the directory's
+// /export stream of operations. An operation for a hot DID, such as a handle change or
+// PDS move, is written straight into did_cache from the operation itself, without
+// fetching the document. Both kinds of request share a budget of
+// PLC_REQUESTS_PER_SECOND; a 429 pauses the job for the Retry-After the directory
+// asks for. PLC_DIRECTORY_URL points this and the resolver at a mirror instead, e.g.
+// one loaded from a dump, which can take a higher rate. The export cursor starts at
+// the current time and is kept in memory only: operations from while the service was
+// down are picked up when the cached entries lapse.
+use anyhow::{Context, Result};
+use moka::future::Cache;
+use reqwest::{Client as HttpClient, StatusCode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::did_resolver::{DidDocument, DidResolver, Service, VerificationMethod};
+
+// Operations per export page, the directory's maximum
+const EXPORT_PAGE_SIZE: usize = 1_000;
+// Export pages read per round before leaving the rest for the next one
+const MAX_EXPORT_PAGES: usize = 20;
+// A DID looked up fewer times than this in a round isn't worth a request
+const MIN_OBSERVATIONS: u32 = 2;
+// Refresh entries this close to expiring
+const PREFETCH_HORIZON: Duration = Duration::from_secs(3600);
+// How long a prefetched DID's export operations keep being applied
+const HOT_TTL: Duration = Duration::from_secs(24 * 3600);
+const MAX_HOT_DIDS: u64 = 50_000;
+// Pause after a 429 without a usable Retry-After
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+// One entry of the /export stream
+#[derive(Debug, Deserialize)]
+struct ExportEntry {
+    did: String,
+    operation: serde_json::Value,
+    #[serde(default)]
+    nullified: bool,
+    #[serde(rename = "createdAt")]
+    created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlcOperation {
+    #[serde(rename = "type")]
+    op_type: String,
+    #[serde(rename = "alsoKnownAs", default)]
+    also_known_as: Vec<String>,
+    #[serde(default)]
+    services: HashMap<String, PlcService>,
+    #[serde(rename = "verificationMethods", default)]
+    verification_methods: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlcService {
+    #[serde(rename = "type")]
+    service_type: String,
+    endpoint: String,
+}
+
+// The DID document a "plc_operation" produces, as plc.directory would serve it. None
+// for tombstones and legacy operations, which are left to expire from the cache.
+fn document_from_operation(did: &str, operation: serde_json::Value) -> Option<DidDocument> {
+    let operation: PlcOperation = serde_json::from_value(operation).ok()?;
+    if operation.op_type != "plc_operation" {
+        return None;
+    }
+
+    Some(DidDocument {
+        id: did.to_string(),
+        also_known_as: Some(operation.also_known_as),
+        service: Some(
+            operation
+                .services
+                .into_iter()
+                .map(|(id, service)| Service {
+                    id: format!("#{}", id),
+                    service_type: service.service_type,
+                    service_endpoint: service.endpoint,
+                })
+                .collect(),
+        ),
+        verification_method: Some(
+            operation
+                .verification_methods
+                .into_iter()
+                .map(|(id, key)| VerificationMethod {
+                    id: format!("{}#{}", did, id),
+                    method_type: "Multikey".to_string(),
+                    public_key_multibase: key.strip_prefix("did:key:").map(str::to_string),
+                })
+                .collect(),
+        ),
+    })
+}
+
+// The `limit` most looked-up DIDs, most first
+fn hottest(observed: HashMap<String, u32>, limit: usize) -> Vec<String> {
+    let mut observed: Vec<(String, u32)> = observed
+        .into_iter()
+        .filter(|(_, count)| *count >= MIN_OBSERVATIONS)
+        .collect();
+    observed.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    observed.into_iter().take(limit).map(|(did, _)| did).collect()
+}
+
+pub struct DidPrefetcher {
+    did_resolver: Arc<DidResolver>,
+    http_client: HttpClient,
+    interval: Duration,
+    max_per_round: usize,
+    request_spacing: Duration,
+    // When the next directory request may go out
+    next_request: Instant,
+    hot: Cache<String, ()>,
+    // createdAt of the last export operation read
+    export_cursor: String,
+}
+
+impl DidPrefetcher {
+    // None when DID_PREFETCH_INTERVAL_SECS is 0
+    pub fn from_config(config: &crate::config::Config, did_resolver: Arc<DidResolver>) -> Option<Self> {
+        if config.did_prefetch_interval_secs == 0 {
+            return None;
+        }
+
+        Some(Self {
+            did_resolver,
+            http_client: HttpClient::builder()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .expect("Failed to create HTTP client"),
+            interval: Duration::from_secs(config.did_prefetch_interval_secs),
+            max_per_round: config.did_prefetch_max,
+            request_spacing: Duration::from_secs_f64(1.0 / config.plc_requests_per_second.max(0.01)),
+            next_request: Instant::now(),
+            hot: Cache::builder().max_capacity(MAX_HOT_DIDS).time_to_live(HOT_TTL).build(),
+            export_cursor: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        })
+    }
+
+    pub async fn run(mut self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+
+            match self.prefetch_hot().await {
+                Ok(0) => {}
+                Ok(count) => info!("Prefetched {} frequently seen DIDs", count),
+                Err(e) => warn!("Error prefetching DIDs: {}", e),
+            }
+
+            match self.apply_export().await {
+                Ok(0) => {}
+                Ok(count) => debug!("Applied {} PLC operations for hot DIDs", count),
+                Err(e) => warn!("Error reading the PLC export: {}", e),
+            }
+        }
+    }
+
+    // Resolve this round's most looked-up DIDs that aren't cached or are about to lapse
+    async fn prefetch_hot(&mut self) -> Result<usize> {
+        let hottest = hottest(self.did_resolver.take_observed(), self.max_per_round);
+        for did in &hottest {
+            self.hot.insert(did.clone(), ()).await;
+        }
+
+        let stale = self.did_resolver.needing_refresh(&hottest, PREFETCH_HORIZON).await?;
+        let mut prefetched = 0;
+        for did in stale.iter().filter(|did| did.starts_with("did:plc:")) {
+            let url = format!("{}/{}", self.did_resolver.plc_directory(), did);
+            let Some(response) = self.request(&url).await? else {
+                // Rate limited; the rest wait for the next round
+                break;
+            };
+            let document = match response.json::<DidDocument>().await {
+                Ok(document) => document,
+                Err(e) => {
+                    debug!(did = %did, "Failed to parse prefetched DID document: {}", e);
+                    continue;
+                }
+            };
+            self.did_resolver.store(did, document).await?;
+            crate::metrics::DIDS_PREFETCHED.with_label_values(&["resolve"]).inc();
+            prefetched += 1;
+        }
+        Ok(prefetched)
+    }
+
+    // Read the export from the cursor and cache the new documents of hot DIDs
+    async fn apply_export(&mut self) -> Result<usize> {
+        let mut applied = 0;
+        for _ in 0..MAX_EXPORT_PAGES {
+            let url = format!(
+                "{}/export?count={}&after={}",
+                self.did_resolver.plc_directory(),
+                EXPORT_PAGE_SIZE,
+                self.export_cursor
+            );
+            let Some(response) = self.request(&url).await? else {
+                break;
+            };
+            let body = response.text().await.context("Failed to read PLC export page")?;
+
+            let mut entries = 0;
+            for line in body.lines().filter(|line| !line.trim().is_empty()) {
+                let entry: ExportEntry = serde_json::from_str(line).context("Invalid PLC export entry")?;
+                entries += 1;
+                self.export_cursor = entry.created_at;
+                if entry.nullified || !self.hot.contains_key(&entry.did) {
+                    continue;
+                }
+                if let Some(document) = document_from_operation(&entry.did, entry.operation) {
+                    self.did_resolver.store(&entry.did, document).await?;
+                    crate::metrics::DIDS_PREFETCHED.with_label_values(&["export"]).inc();
+                    applied += 1;
+                }
+            }
+
+            if entries < EXPORT_PAGE_SIZE {
+                break;
+            }
+        }
+        Ok(applied)
+    }
+
+    // GET within the request budget. None when the directory rate limited us, after
+    // pushing the next request back by its Retry-After.
+    async fn request(&mut self, url: &str) -> Result<Option<reqwest::Response>> {
+        tokio::time::sleep_until(self.next_request).await;
+        self.next_request = Instant::now() + self.request_spacing;
+
+        let response = self
+            .http_client
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to fetch {}", url))?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            warn!(retry_after_secs = retry_after.as_secs(), "PLC directory rate limited the DID prefetcher");
+            crate::metrics::PLC_RATE_LIMITED.inc();
+            self.next_request = Instant::now() + retry_after;
+            return Ok(None);
+        }
+        Ok(Some(response.error_for_status()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_documents_from_export_operations() {
+        let entry: ExportEntry = serde_json::from_str(
+            r#"{"did":"did:plc:abc","operation":{"type":"plc_operation","alsoKnownAs":["at://alice.example.com"],
+            "services":{"atproto_pds":{"type":"AtprotoPersonalDataServer","endpoint":"https://pds.example.com"}},
+            "verificationMethods":{"atproto":"did:key:zQ3sh"},"rotationKeys":[],"prev":null,"sig":"x"},
+            "cid":"bafy","nullified":false,"createdAt":"2024-05-01T00:00:00.000Z"}"#,
+        )
+        .unwrap();
+
+        let document = document_from_operation(&entry.did, entry.operation).unwrap();
+        assert_eq!(document.also_known_as.unwrap(), vec!["at://alice.example.com"]);
+        let service = &document.service.unwrap()[0];
+        assert_eq!((service.id.as_str(), service.service_endpoint.as_str()), ("#atproto_pds", "https://pds.example.com"));
+        let key = &document.verification_method.unwrap()[0];
+        assert_eq!(key.id, "did:plc:abc#atproto");
+        assert_eq!(key.public_key_multibase.as_deref(), Some("zQ3sh"));
+        assert!(document_from_operation("did:plc:abc", serde_json::json!({ "type": "plc_tombstone" })).is_none());
+
+        let observed = HashMap::from([
+            ("did:plc:once".to_string(), 1),
+            ("did:plc:warm".to_string(), 3),
+            ("did:plc:viral".to_string(), 40),
+        ]);
+        assert_eq!(hottest(observed.clone(), 5), vec!["did:plc:viral", "did:plc:warm"]);
+        assert_eq!(hottest(observed, 1), vec!["did:plc:viral"]);
+    }
+}
@@ -5,10 +5,23 @@ use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Postgres, Row};
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tokio::sync::RwLock;
 use tracing::{debug, info, warn}; 
 
+use crate::expiry::Expiry;
+use crate::shared_cache::SharedCache;
+use crate::retry::{is_transient_http, RetryPolicy};
+
+pub const DEFAULT_PLC_DIRECTORY: &str = "https://plc.directory";
+
+// Distinct DIDs counted between prefetch rounds; further DIDs go uncounted until the
+// next round takes the counts
+const MAX_OBSERVED_DIDS: usize = 100_000;
+
+// Minimum time between forced re-resolves of one DID's signing key
+const KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
 // Simplified DID Document structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DidDocument {
@@ -16,9 +29,20 @@ pub struct DidDocument {
     #[serde(rename = "alsoKnownAs")]
     pub also_known_as: Option<Vec<String>>,
     pub service: Option<Vec<Service>>,
+    #[serde(rename = "verificationMethod")]
+    pub verification_method: Option<Vec<VerificationMethod>>,
     // Add other fields as needed
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationMethod {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub method_type: String,
+    #[serde(rename = "publicKeyMultibase")]
+    pub public_key_multibase: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Service {
     pub id: String,
@@ -28,12 +52,12 @@ pub struct Service {
     pub service_endpoint: String,
 }
 
-// Cache entry with expiration
-#[derive(Clone)]
+// Cache entry with expiration, also the shared tier's value
+#[derive(Clone, Serialize, Deserialize)]
 struct CachedDidInfo {
     document: DidDocument,
     handle: String,
-    expires_at: Instant,
+    expires_at: Expiry,
 }
 
 #[derive(Clone)]
@@ -42,10 +66,17 @@ pub struct DidResolver {
     memory_cache: Arc<RwLock<HashMap<String, CachedDidInfo>>>,
     db_pool: Pool<Postgres>,
     ttl: Duration,
+    shared: SharedCache,
+    plc_directory: String,
+    // Lookups per DID since the prefetcher last took the counts
+    observed: Arc<std::sync::Mutex<HashMap<String, u32>>>,
+    // DIDs whose signing key was re-resolved recently, so a stream of bad signatures
+    // can't make us fetch the document again on every request
+    key_refreshes: moka::future::Cache<String, ()>,
 }
 
 impl DidResolver {
-    pub fn new(db_pool: Pool<Postgres>, ttl_hours: u64) -> Self {
+    pub fn new(db_pool: Pool<Postgres>, ttl_hours: u64, shared: SharedCache) -> Self {
         Self {
             http_client: HttpClient::builder()
                 .timeout(Duration::from_secs(10))
@@ -54,9 +85,26 @@ impl DidResolver {
             memory_cache: Arc::new(RwLock::new(HashMap::new())),
             db_pool,
             ttl: Duration::from_secs(ttl_hours * 3600),
+            shared,
+            plc_directory: DEFAULT_PLC_DIRECTORY.to_string(),
+            observed: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            key_refreshes: moka::future::Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(KEY_REFRESH_INTERVAL)
+                .build(),
         }
     }
 
+    // Resolve did:plc against a mirror instead of plc.directory
+    pub fn with_plc_directory(mut self, plc_directory: &str) -> Self {
+        self.plc_directory = plc_directory.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn plc_directory(&self) -> &str {
+        &self.plc_directory
+    }
+
     // Main method to get a handle from a DID
     pub async fn get_handle(&self, did: &str) -> Result<String> {
         // 1. Check memory cache first
@@ -68,9 +116,9 @@ impl DidResolver {
 
         // 2. Check database cache
         let db_result = self.get_from_db_cache(did).await?;
-        if let Some((document, handle)) = db_result {
+        if let Some((document, handle, expires_at)) = db_result {
             // Update memory cache and return handle
-            self.update_memory_cache(did.to_string(), document, handle.clone()).await;
+            self.update_memory_cache(did.to_string(), document, handle.clone(), expires_at).await;
             debug!(did = %did, handle = %handle, "Handle found in database cache");
             return Ok(handle);
         }
@@ -85,26 +133,101 @@ impl DidResolver {
         Ok(handle)
     }
 
+    // The user's PDS, from the #atproto_pds service in their DID document
+    pub async fn get_pds_endpoint(&self, did: &str) -> Result<String> {
+        self.get_service_endpoint(did, "atproto_pds").await
+    }
+
+    // Endpoint of the service with the given fragment id (e.g. "bsky_fg") in the DID document
+    pub async fn get_service_endpoint(&self, did: &str, service_id: &str) -> Result<String> {
+        self.get_document(did)
+            .await?
+            .service
+            .unwrap_or_default()
+            .into_iter()
+            .find(|service| {
+                service.id.strip_prefix(did).unwrap_or(&service.id).strip_prefix('#') == Some(service_id)
+            })
+            .map(|service| service.service_endpoint.trim_end_matches('/').to_string())
+            .ok_or_else(|| anyhow::anyhow!("No #{} service in DID document for {}", service_id, did))
+    }
+
+    // The account's #atproto signing key as publicKeyMultibase. `refresh` bypasses the
+    // caches, for when a cached document may predate a key rotation, at most once per
+    // KEY_REFRESH_INTERVAL for each DID.
+    pub async fn get_signing_key(&self, did: &str, refresh: bool) -> Result<String> {
+        let refresh = refresh && !self.key_refreshes.contains_key(did);
+        let document = if refresh {
+            self.key_refreshes.insert(did.to_string(), ()).await;
+            let (document, handle) = self.resolve_did_network(did).await?;
+            self.update_caches(did.to_string(), document.clone(), handle).await?;
+            document
+        } else {
+            self.get_document(did).await?
+        };
+
+        document
+            .verification_method
+            .unwrap_or_default()
+            .into_iter()
+            .find(|method| method.id.strip_prefix(did).unwrap_or(&method.id) == "#atproto")
+            .and_then(|method| method.public_key_multibase)
+            .ok_or_else(|| anyhow::anyhow!("No #atproto signing key in DID document for {}", did))
+    }
+
+    async fn get_document(&self, did: &str) -> Result<DidDocument> {
+        let cached = {
+            let cache = self.memory_cache.read().await;
+            cache
+                .get(did)
+                .filter(|cached| cached.expires_at.is_live())
+                .map(|cached| cached.document.clone())
+        };
+
+        match cached {
+            Some(document) => Ok(document),
+            None => match self.get_from_db_cache(did).await? {
+                Some((document, handle, expires_at)) => {
+                    self.update_memory_cache(did.to_string(), document.clone(), handle, expires_at).await;
+                    Ok(document)
+                }
+                None => {
+                    let (document, handle) = self.resolve_did_network(did).await?;
+                    self.update_caches(did.to_string(), document.clone(), handle).await?;
+                    Ok(document)
+                }
+            },
+        }
+    }
+
     // Check memory cache for a DID
     async fn get_from_memory_cache(&self, did: &str) -> Option<String> {
         let cache = self.memory_cache.read().await;
         if let Some(cached) = cache.get(did) {
-            if cached.expires_at > Instant::now() {
+            if cached.expires_at.is_live() {
                 return Some(cached.handle.clone());
             }
         }
         None
     }
 
-    // Check database cache for a DID
-    async fn get_from_db_cache(&self, did: &str) -> Result<Option<(DidDocument, String)>> {
+    // Check the shared tier, then the database cache, for a DID
+    async fn get_from_db_cache(&self, did: &str) -> Result<Option<(DidDocument, String, Expiry)>> {
+        if let Some(shared) = self.shared.get::<CachedDidInfo>("did", did).await {
+            let expires_at = Expiry::stored(shared.expires_at.at(), self.ttl);
+            if expires_at.is_live() {
+                return Ok(Some((shared.document, shared.handle, expires_at)));
+            }
+        }
+
         let row = sqlx::query!(
             r#"
             SELECT document, handle, expires_at 
             FROM did_cache 
-            WHERE did = $1 AND expires_at > NOW()
+            WHERE did = $1 AND expires_at > $2
             "#,
-            did
+            did,
+            time::OffsetDateTime::now_utc()
         )
         .fetch_optional(&self.db_pool)
         .await?;
@@ -112,26 +235,50 @@ impl DidResolver {
         if let Some(row) = row {
             let document: DidDocument = serde_json::from_value(row.document)
                 .with_context(|| "Failed to deserialize DID document from database")?;
-            return Ok(Some((document, row.handle)));
+            let expires_at = Expiry::stored(row.expires_at, self.ttl);
+            self.share(did, &document, &row.handle, expires_at).await;
+            return Ok(Some((document, row.handle, expires_at)));
         }
         
         Ok(None)
     }
 
+    async fn share(&self, did: &str, document: &DidDocument, handle: &str, expires_at: Expiry) {
+        let entry = CachedDidInfo {
+            document: document.clone(),
+            handle: handle.to_string(),
+            expires_at,
+        };
+        self.shared.set("did", did, &entry, expires_at.remaining()).await;
+    }
+
     // Update memory cache with new DID info
-    async fn update_memory_cache(&self, did: String, document: DidDocument, handle: String) {
+    async fn update_memory_cache(&self, did: String, document: DidDocument, handle: String, expires_at: Expiry) {
         let mut cache = self.memory_cache.write().await;
         cache.insert(did, CachedDidInfo {
             document,
             handle,
-            expires_at: Instant::now() + self.ttl,
+            expires_at,
         });
     }
 
+    // Seed the in-memory layer without touching the network or the DB cache; used by
+    // bench-load so synthetic accounts resolve instantly
+    pub async fn prime(&self, did: &str, handle: &str) {
+        let document = DidDocument {
+            id: did.to_string(),
+            also_known_as: Some(vec![format!("at://{}", handle)]),
+            service: None,
+            verification_method: None,
+        };
+        self.update_memory_cache(did.to_string(), document, handle.to_string(), Expiry::after(self.ttl))
+            .await;
+    }
+
     // Update both caches with new DID info
     async fn update_caches(&self, did: String, document: DidDocument, handle: String) -> Result<()> {
-        // Update database cache
-        let expires_at = time::OffsetDateTime::now_utc() + time::Duration::hours(24);
+        // Update database cache; both layers share one expiry
+        let expires_at = Expiry::after(self.ttl);
         let json_doc = serde_json::to_value(document.clone())
             .with_context(|| "Failed to serialize DID document")?;
             
@@ -145,13 +292,14 @@ impl DidResolver {
             did.as_str(),
             json_doc,
             &handle,
-            expires_at
+            expires_at.at()
         )
         .execute(&self.db_pool)
         .await?;
+        self.share(&did, &document, &handle, expires_at).await;
         
         // Update memory cache
-        self.update_memory_cache(did, document, handle).await;
+        self.update_memory_cache(did, document, handle, expires_at).await;
         
         Ok(())
     }
@@ -169,22 +317,8 @@ impl DidResolver {
 
     // Resolve did:plc
     async fn resolve_plc_did(&self, did: &str) -> Result<(DidDocument, String)> {
-        let url = format!("https://plc.directory/{}", did);
-        let response = self.http_client.get(&url)
-            .send()
-            .await
-            .with_context(|| "Failed to fetch PLC DID document")?;
-            
-        if !response.status().is_success() {
-            return Err(anyhow::anyhow!(
-                "Failed to fetch PLC DID document, status: {}", 
-                response.status()
-            ));
-        }
-        
-        let document: DidDocument = response.json()
-            .await
-            .with_context(|| "Failed to parse PLC DID document")?;
+        let url = format!("{}/{}", self.plc_directory, did);
+        let document = self.fetch_document(&url, "PLC").await?;
             
         // Extract handle from alsoKnownAs
         let handle = self.extract_handle_from_document(&document)?;
@@ -192,6 +326,27 @@ impl DidResolver {
         Ok((document, handle))
     }
 
+    // Fetch a DID document, retrying transient network and server errors
+    async fn fetch_document(&self, url: &str, method: &str) -> Result<DidDocument> {
+        RetryPolicy::new("did_resolver")
+            .retry_if(is_transient_http)
+            .run(|_| async {
+                let response = self
+                    .http_client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .with_context(|| format!("Failed to fetch {} DID document", method))?;
+
+                response
+                    .json::<DidDocument>()
+                    .await
+                    .with_context(|| format!("Failed to parse {} DID document", method))
+            })
+            .await
+    }
+
     // Resolve did:web
     async fn resolve_web_did(&self, did: &str) -> Result<(DidDocument, String)> {
         // Convert did:web:example.com to https://example.com/.well-known/did.json
@@ -199,22 +354,7 @@ impl DidResolver {
             .ok_or_else(|| anyhow::anyhow!("Invalid did:web format"))?;
             
         let url = format!("https://{}/.well-known/did.json", domain);
-        
-        let response = self.http_client.get(&url)
-            .send()
-            .await
-            .with_context(|| "Failed to fetch Web DID document")?;
-            
-        if !response.status().is_success() {
-            return Err(anyhow::anyhow!(
-                "Failed to fetch Web DID document, status: {}", 
-                response.status()
-            ));
-        }
-        
-        let document: DidDocument = response.json()
-            .await
-            .with_context(|| "Failed to parse Web DID document")?;
+        let document = self.fetch_document(&url, "Web").await?;
             
         // Extract handle from alsoKnownAs
         let handle = self.extract_handle_from_document(&document)?;
@@ -247,13 +387,14 @@ impl DidResolver {
     // Get handles for multiple DIDs in bulk
     pub async fn get_handles_bulk(&self, dids: &[String]) -> HashMap<String, String> {
         let mut result = HashMap::new();
+        self.observe(dids);
         
         // 1. Try memory cache first for all DIDs
         {
             let cache = self.memory_cache.read().await;
             for did in dids {
                 if let Some(cached) = cache.get(did) {
-                    if cached.expires_at > Instant::now() {
+                    if cached.expires_at.is_live() {
                         result.insert(did.clone(), cached.handle.clone());
                         crate::metrics::DID_CACHE_HITS.inc();
                     }
@@ -273,10 +414,10 @@ impl DidResolver {
         
         // 3. Try database cache for missing DIDs
         if let Ok(db_results) = self.get_from_db_cache_bulk(&missing_dids).await {
-            for (did, doc, handle) in db_results {
+            for (did, doc, handle, expires_at) in db_results {
                 result.insert(did.clone(), handle.clone());
                 // Update memory cache
-                self.update_memory_cache(did, doc, handle).await;
+                self.update_memory_cache(did, doc, handle, expires_at).await;
                 crate::metrics::DID_CACHE_HITS.inc();
             }
         }
@@ -341,24 +482,71 @@ impl DidResolver {
         result
     }
     
+    fn observe(&self, dids: &[String]) {
+        let mut observed = self.observed.lock().unwrap();
+        for did in dids {
+            if let Some(count) = observed.get_mut(did) {
+                *count += 1;
+            } else if observed.len() < MAX_OBSERVED_DIDS {
+                observed.insert(did.clone(), 1);
+            }
+        }
+    }
+
+    // Lookups per DID since the last call, for the prefetcher
+    pub fn take_observed(&self) -> HashMap<String, u32> {
+        std::mem::take(&mut *self.observed.lock().unwrap())
+    }
+
+    // The DIDs with no cache entry, or one expiring within `within`
+    pub async fn needing_refresh(&self, dids: &[String], within: Duration) -> Result<Vec<String>> {
+        let fresh = |expires_at: &Expiry| expires_at.remaining() > within;
+        let uncached: Vec<String> = {
+            let cache = self.memory_cache.read().await;
+            dids.iter()
+                .filter(|did| !cache.get(*did).is_some_and(|cached| fresh(&cached.expires_at)))
+                .cloned()
+                .collect()
+        };
+        if uncached.is_empty() {
+            return Ok(uncached);
+        }
+
+        let cached: std::collections::HashSet<String> = self
+            .get_from_db_cache_bulk(&uncached)
+            .await?
+            .into_iter()
+            .filter(|(_, _, _, expires_at)| fresh(expires_at))
+            .map(|(did, ..)| did)
+            .collect();
+        Ok(uncached.into_iter().filter(|did| !cached.contains(did)).collect())
+    }
+
+    // Cache a document fetched elsewhere (the prefetcher), returning its handle
+    pub async fn store(&self, did: &str, document: DidDocument) -> Result<String> {
+        let handle = self.extract_handle_from_document(&document)?;
+        self.update_caches(did.to_string(), document, handle.clone()).await?;
+        Ok(handle)
+    }
+
     // Fetch multiple DIDs from DB cache at once
-    async fn get_from_db_cache_bulk(&self, dids: &[String]) -> Result<Vec<(String, DidDocument, String)>> {
+    async fn get_from_db_cache_bulk(&self, dids: &[String]) -> Result<Vec<(String, DidDocument, String, Expiry)>> {
         let mut results = Vec::new();
         
         // Using a simple loop instead of a more complex query
         // Could be optimized with an IN clause for larger sets
         for chunk in dids.chunks(50) {
-            let placeholders: Vec<String> = (1..=chunk.len())
+            let placeholders: Vec<String> = (2..=chunk.len() + 1)
                 .map(|i| format!("${}", i))
                 .collect();
                 
             let query = format!(
-                "SELECT did, document, handle FROM did_cache 
-                WHERE did IN ({}) AND expires_at > NOW()",
+                "SELECT did, document, handle, expires_at FROM did_cache 
+                WHERE did IN ({}) AND expires_at > $1",
                 placeholders.join(",")
             );
             
-            let mut q = sqlx::query(&query);
+            let mut q = sqlx::query(&query).bind(time::OffsetDateTime::now_utc());
             for did in chunk {
                 q = q.bind(did);
             }
@@ -369,9 +557,10 @@ impl DidResolver {
                 let did: String = row.get("did");
                 let doc_json: serde_json::Value = row.get("document");
                 let handle: String = row.get("handle");
+                let expires_at = Expiry::stored(row.get("expires_at"), self.ttl);
                 
                 if let Ok(doc) = serde_json::from_value(doc_json) {
-                    results.push((did, doc, handle));
+                    results.push((did, doc, handle, expires_at));
                 }
             }
         }
@@ -379,15 +568,24 @@ impl DidResolver {
         Ok(results)
     }
     
+    // Drop the in-memory layer under memory pressure; the DB cache still backs lookups
+    pub async fn clear_memory_cache(&self) -> usize {
+        let mut cache = self.memory_cache.write().await;
+        let cleared = cache.len();
+        cache.clear();
+        cache.shrink_to_fit();
+        cleared
+    }
+
     // Cleanup expired entries
     pub async fn cleanup_expired(&self) -> Result<usize> {
         // Clean memory cache
         let mut memory_cleaned = 0;
         {
             let mut cache = self.memory_cache.write().await;
-            let now = Instant::now();
+            let now = time::OffsetDateTime::now_utc();
             cache.retain(|_, v| {
-                let keep = v.expires_at > now;
+                let keep = v.expires_at.is_live_at(now);
                 if !keep {
                     memory_cleaned += 1;
                 }
@@ -397,7 +595,8 @@ impl DidResolver {
         
         // Clean database cache
         let db_result = sqlx::query!(
-            "DELETE FROM did_cache WHERE expires_at <= NOW() RETURNING did"
+            "DELETE FROM did_cache WHERE expires_at <= $1 RETURNING did",
+            time::OffsetDateTime::now_utc()
         )
         .fetch_all(&self.db_pool)
         .await?;
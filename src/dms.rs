@@ -0,0 +1,369 @@
+// dms.rs
+// Direct message pushes. Chat isn't on the firehose, so for each user who connected
+// their chat account (an app password with direct message access) the poller reads
+// chat.bsky.convo.getLog through their PDS and pushes messages from other members.
+// The first poll after connecting only records the log position, so connecting never
+// replays old conversations.
+use anyhow::{Context, Result};
+use reqwest::{Client as HttpClient, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, error, warn};
+
+use crate::channel::PipelineSender;
+use crate::did_resolver::DidResolver;
+use crate::excerpt::ExcerptLimits;
+use crate::models::{NotificationPayload, NotificationType, QuietHoursMode};
+use crate::relationship_manager::RelationshipManager;
+
+// Log pages read per user per poll
+const MAX_PAGES: usize = 5;
+// Shown instead of the text to users who redact message bodies
+const REDACTED_BODY: &str = "New message";
+
+#[derive(Clone)]
+struct ChatSession {
+    pds: String,
+    access_jwt: String,
+}
+
+#[derive(Deserialize)]
+struct Session {
+    #[serde(rename = "accessJwt")]
+    access_jwt: String,
+}
+
+#[derive(Deserialize)]
+struct GetLogResponse {
+    cursor: Option<String>,
+    logs: Vec<LogEntry>,
+}
+
+#[derive(Deserialize)]
+struct LogEntry {
+    #[serde(rename = "$type")]
+    log_type: String,
+    #[serde(rename = "convoId")]
+    convo_id: Option<String>,
+    message: Option<MessageView>,
+}
+
+#[derive(Deserialize)]
+struct MessageView {
+    id: String,
+    // Deleted message views carry no text
+    #[serde(default)]
+    text: String,
+    sender: MessageSender,
+}
+
+#[derive(Deserialize)]
+struct MessageSender {
+    did: String,
+}
+
+struct NewMessage {
+    convo_id: String,
+    id: String,
+    sender: String,
+    text: String,
+}
+
+// A message someone else sent to `did`, if the log entry is one
+fn new_message(entry: LogEntry, did: &str) -> Option<NewMessage> {
+    if entry.log_type != "chat.bsky.convo.defs#logCreateMessage" {
+        return None;
+    }
+    let message = entry.message?;
+    if message.sender.did == did || message.text.is_empty() {
+        return None;
+    }
+    Some(NewMessage {
+        convo_id: entry.convo_id?,
+        id: message.id,
+        sender: message.sender.did,
+        text: message.text,
+    })
+}
+
+pub struct DmPoller {
+    db_pool: Pool<Postgres>,
+    did_resolver: Arc<DidResolver>,
+    relationship_manager: Arc<RelationshipManager>,
+    notification_sender: PipelineSender<NotificationPayload>,
+    chat_service_did: String,
+    excerpts: ExcerptLimits,
+    http_client: HttpClient,
+    // Access tokens last a couple of hours, so sessions are reused until rejected
+    sessions: Mutex<HashMap<String, ChatSession>>,
+}
+
+impl DmPoller {
+    pub fn new(
+        db_pool: Pool<Postgres>,
+        did_resolver: Arc<DidResolver>,
+        relationship_manager: Arc<RelationshipManager>,
+        notification_sender: PipelineSender<NotificationPayload>,
+        chat_service_did: String,
+        excerpts: ExcerptLimits,
+    ) -> Self {
+        Self {
+            db_pool,
+            did_resolver,
+            relationship_manager,
+            notification_sender,
+            chat_service_did,
+            excerpts,
+            http_client: HttpClient::builder()
+                .timeout(Duration::from_secs(15))
+                .build()
+                .unwrap_or_default(),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn run(self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = self.poll_all().await {
+                error!("Error polling direct messages: {}", e);
+            }
+        }
+    }
+
+    async fn poll_all(&self) -> Result<()> {
+        let server_secret = crate::crypto::CryptoUtils::new()?.server_secret;
+        let consents = sqlx::query!(
+            r#"
+            SELECT c.tenant_id, c.user_did, c.log_cursor,
+                pgp_sym_decrypt(c.app_password_encrypted, $1) AS "app_password!"
+            FROM dm_consents c
+            WHERE EXISTS (
+                SELECT 1 FROM user_devices d
+                WHERE d.tenant_id = c.tenant_id AND d.did = c.user_did
+                AND d.deleted_at IS NULL AND d.verified_at IS NOT NULL
+            )
+            "#,
+            server_secret
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        for consent in consents {
+            if let Err(e) = self
+                .poll_user(&consent.tenant_id, &consent.user_did, &consent.app_password, consent.log_cursor)
+                .await
+            {
+                warn!(did = %consent.user_did, "Direct message poll failed: {:#}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // Each app a user granted access through keeps its own cursor and notifies its own
+    // devices
+    async fn poll_user(&self, tenant_id: &str, did: &str, app_password: &str, cursor: Option<String>) -> Result<()> {
+        let first_poll = cursor.is_none();
+        let mut cursor = cursor;
+
+        for _ in 0..MAX_PAGES {
+            let page = self.get_log(did, app_password, cursor.as_deref()).await?;
+            let advanced = page.cursor.is_some() && page.cursor != cursor;
+
+            // A message that can't be notified is skipped rather than read again on
+            // every poll, so the cursor still moves past it
+            if !first_poll {
+                for entry in page.logs {
+                    if let Some(message) = new_message(entry, did) {
+                        if let Err(e) = self.notify(tenant_id, did, message).await {
+                            warn!(did = %did, "Failed to notify direct message: {}", e);
+                        }
+                    }
+                }
+            }
+
+            if !advanced {
+                break;
+            }
+            cursor = page.cursor;
+            sqlx::query!(
+                "UPDATE dm_consents SET log_cursor = $2 WHERE tenant_id = $3 AND user_did = $1",
+                did,
+                cursor,
+                tenant_id
+            )
+            .execute(&self.db_pool)
+            .await?;
+
+            if first_poll {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_log(&self, did: &str, app_password: &str, cursor: Option<&str>) -> Result<GetLogResponse> {
+        let session = self.session(did, app_password).await?;
+        let mut response = self.send_get_log(&session, cursor).await?;
+
+        // An expired token comes back as 400 ExpiredToken; sign in again once
+        if matches!(response.status(), StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED) {
+            debug!(did = %did, "Chat session rejected, signing in again");
+            self.sessions.lock().await.remove(did);
+            let session = self.session(did, app_password).await?;
+            response = self.send_get_log(&session, cursor).await?;
+            if matches!(response.status(), StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED) {
+                // Don't keep a session the chat service won't take
+                self.sessions.lock().await.remove(did);
+            }
+        }
+
+        Ok(response
+            .error_for_status()
+            .context("Failed to read chat log")?
+            .json()
+            .await?)
+    }
+
+    async fn send_get_log(&self, session: &ChatSession, cursor: Option<&str>) -> Result<reqwest::Response> {
+        let mut request = self
+            .http_client
+            .get(format!("{}/xrpc/chat.bsky.convo.getLog", session.pds))
+            .bearer_auth(&session.access_jwt)
+            .header("atproto-proxy", format!("{}#bsky_chat", self.chat_service_did));
+        if let Some(cursor) = cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        request.send().await.context("Failed to read chat log")
+    }
+
+    async fn session(&self, did: &str, app_password: &str) -> Result<ChatSession> {
+        if let Some(session) = self.sessions.lock().await.get(did) {
+            return Ok(session.clone());
+        }
+
+        let pds = self.did_resolver.get_pds_endpoint(did).await?;
+        let session: Session = self
+            .http_client
+            .post(format!("{}/xrpc/com.atproto.server.createSession", pds))
+            .json(&json!({ "identifier": did, "password": app_password }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Failed to create session")?
+            .json()
+            .await?;
+
+        let session = ChatSession {
+            pds,
+            access_jwt: session.access_jwt,
+        };
+        self.sessions.lock().await.insert(did.to_string(), session.clone());
+        Ok(session)
+    }
+
+    async fn notify(&self, tenant_id: &str, did: &str, message: NewMessage) -> Result<()> {
+        if self.relationship_manager.is_muted(did, &message.sender).await
+            || self.relationship_manager.is_blocked(did, &message.sender).await
+        {
+            return Ok(());
+        }
+
+        let handle = self
+            .did_resolver
+            .get_handle(&message.sender)
+            .await
+            .unwrap_or_else(|_| message.sender.clone());
+
+        let devices = crate::db::get_user_devices(&self.db_pool, did).await?;
+        for device in devices.into_iter().filter(|device| device.tenant_id == tenant_id) {
+            let prefs = crate::db::get_notification_preferences(&self.db_pool, device.id).await?;
+            if !prefs.dms {
+                continue;
+            }
+
+            if let Some(mode) = crate::quiet_hours::active_mode(&self.db_pool, device.id).await? {
+                crate::metrics::NOTIFICATIONS_QUIET_HOURS
+                    .with_label_values(&[mode.as_str()])
+                    .inc();
+                if mode == QuietHoursMode::Queue {
+                    crate::quiet_hours::defer(&self.db_pool, device.id, &NotificationType::DirectMessage).await?;
+                }
+                continue;
+            }
+
+            let mut data = HashMap::new();
+            data.insert("type".to_string(), format!("{:?}", NotificationType::DirectMessage));
+            data.insert("notification_id".to_string(), uuid::Uuid::new_v4().to_string());
+            data.insert("author_did".to_string(), message.sender.clone());
+            data.insert("convo_id".to_string(), message.convo_id.clone());
+            data.insert("message_id".to_string(), message.id.clone());
+            if prefs.delivers_in_background(&NotificationType::DirectMessage) {
+                data.insert("push_type".to_string(), "background".to_string());
+            }
+
+            let payload = NotificationPayload {
+                user_did: did.to_string(),
+                device_token: device.device_token.clone(),
+                notification_type: NotificationType::DirectMessage,
+                title: format!("@{} sent you a message", handle),
+                body: if prefs.dm_redact_body {
+                    REDACTED_BODY.to_string()
+                } else {
+                    self.excerpts.excerpt(&NotificationType::DirectMessage, &message.text)
+                },
+                data,
+            };
+            if let Err(e) = self.notification_sender.send(payload).await {
+                warn!("Failed to queue direct message notification: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(value: serde_json::Value) -> LogEntry {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn only_messages_from_others_are_new() {
+        let log = |sender: &str| {
+            json!({
+                "$type": "chat.bsky.convo.defs#logCreateMessage",
+                "rev": "2",
+                "convoId": "convo1",
+                "message": {
+                    "$type": "chat.bsky.convo.defs#messageView",
+                    "id": "msg1",
+                    "rev": "2",
+                    "text": "hey",
+                    "sender": { "did": sender },
+                    "sentAt": "2025-05-01T10:00:00Z"
+                }
+            })
+        };
+
+        let message = new_message(entry(log("did:plc:friend")), "did:plc:me").unwrap();
+        assert_eq!(message.convo_id, "convo1");
+        assert_eq!(message.sender, "did:plc:friend");
+        assert_eq!(message.text, "hey");
+
+        assert!(new_message(entry(log("did:plc:me")), "did:plc:me").is_none());
+        assert!(new_message(
+            entry(json!({ "$type": "chat.bsky.convo.defs#logBeginConvo", "rev": "1", "convoId": "convo1" })),
+            "did:plc:me"
+        )
+        .is_none());
+    }
+}
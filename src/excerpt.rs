@@ -0,0 +1,166 @@
+// excerpt.rs
+// Post excerpts for notification bodies. The length budget is set per type in
+// EXCERPT_LENGTHS (e.g. "mention=280,reply=280,like=100"); unlisted types get
+// DEFAULT_EXCERPT_LENGTH. Budgets are in display columns rather than bytes or chars:
+// CJK and other wide characters take two columns, as they do on screen, so a Japanese
+// post gets about half as many characters as an English one. Text is only ever cut
+// between characters, combining marks stay with the character they modify, and text
+// in space-separated scripts is cut at a word boundary when one is close by.
+//
+// Recipients with reply_context on get replies opening with a one-line excerpt of
+// their post being answered, so the lock screen shows what the reply is about.
+use std::collections::HashMap;
+
+use crate::models::NotificationType;
+
+pub const DEFAULT_EXCERPT_LENGTH: usize = 140;
+
+// Columns for the line of the answered post a reply opens with
+pub const REPLY_CONTEXT_LENGTH: usize = 60;
+
+const ELLIPSIS: char = '…';
+
+// "type=columns" pairs separated by commas
+pub fn parse_excerpt_lengths(spec: &str) -> Option<HashMap<String, usize>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (notification_type, columns) = entry.split_once('=')?;
+            let notification_type = NotificationType::parse(notification_type.trim())?;
+            let columns: usize = columns.trim().parse().ok()?;
+            (columns > 0).then(|| (notification_type.as_str().to_string(), columns))
+        })
+        .collect()
+}
+
+#[derive(Clone, Default)]
+pub struct ExcerptLimits {
+    by_type: HashMap<String, usize>,
+}
+
+impl ExcerptLimits {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            by_type: config.excerpt_lengths.clone(),
+        }
+    }
+
+    pub fn budget(&self, notification_type: &NotificationType) -> usize {
+        self.by_type
+            .get(notification_type.as_str())
+            .copied()
+            .unwrap_or(DEFAULT_EXCERPT_LENGTH)
+    }
+
+    pub fn excerpt(&self, notification_type: &NotificationType, text: &str) -> String {
+        excerpt(text, self.budget(notification_type))
+    }
+}
+
+// The text cut to fit `budget` columns, ellipsis included
+pub fn excerpt(text: &str, budget: usize) -> String {
+    let text = text.trim();
+    if text.chars().map(width).sum::<usize>() <= budget {
+        return text.to_string();
+    }
+
+    // Leave a column for the ellipsis
+    let mut used = 0;
+    let mut cut = 0;
+    for (index, c) in text.char_indices() {
+        used += width(c);
+        if used > budget.saturating_sub(1) {
+            break;
+        }
+        cut = index + c.len_utf8();
+    }
+    let mut kept = &text[..cut];
+
+    // Prefer ending on a whole word if that doesn't lose more than a quarter of it
+    if let Some(space) = kept.rfind(char::is_whitespace) {
+        if space >= kept.len() - kept.len() / 4 {
+            kept = &kept[..space];
+        }
+    }
+
+    // A joiner with nothing after it would render as a broken sequence
+    let kept = kept.trim_end_matches(|c: char| c == '\u{200D}' || c.is_whitespace());
+    format!("{}{}", kept, ELLIPSIS)
+}
+
+// A reply's body opening with one line of the post it answers
+pub fn with_reply_context(body: &str, parent_text: &str) -> String {
+    let parent = parent_text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if parent.is_empty() {
+        return body.to_string();
+    }
+    format!("Replying to: \u{201C}{}\u{201D}\n{}", excerpt(&parent, REPLY_CONTEXT_LENGTH), body)
+}
+
+// Display columns taken by a character
+fn width(c: char) -> usize {
+    match c as u32 {
+        // Combining marks, joiners, variation selectors and skin tone modifiers attach to
+        // the character before them
+        0x0300..=0x036F | 0x200B..=0x200F | 0xFE00..=0xFE0F | 0x1F3FB..=0x1F3FF | 0xE0100..=0xE01EF => 0,
+        // Hangul Jamo, CJK, Hiragana, Katakana, Yi, Hangul syllables, compatibility
+        // ideographs, fullwidth forms, emoji and the supplementary ideograph planes
+        0x1100..=0x115F
+        | 0x2E80..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cuts_on_character_boundaries_within_the_budget() {
+        assert_eq!(excerpt("short post", 140), "short post");
+        assert_eq!(excerpt("one two three four five", 15), "one two three…");
+
+        // Two columns per ideograph: nine fit alongside the ellipsis
+        let japanese = "今日はとても良い天気ですね。散歩に行きましょう。";
+        let cut = excerpt(japanese, 20);
+        assert_eq!(cut, "今日はとても良い天…");
+
+        // Never splits a multibyte character or strips an accent from its letter
+        let accented = "e\u{301}".repeat(50);
+        let cut = excerpt(&accented, 10);
+        assert_eq!(cut, format!("{}…", "e\u{301}".repeat(9)));
+        for budget in 1..40 {
+            let cut = excerpt("👩‍👩‍👧 family 🇯🇵 日本語 text", budget);
+            assert!(cut.chars().map(width).sum::<usize>() <= budget.max(1));
+            assert!(!cut.contains("\u{200D}…"));
+        }
+
+        let limits = ExcerptLimits {
+            by_type: parse_excerpt_lengths("mention=280, like=100").unwrap(),
+        };
+        assert_eq!(limits.budget(&NotificationType::Mention), 280);
+        assert_eq!(limits.budget(&NotificationType::Reply), DEFAULT_EXCERPT_LENGTH);
+        assert!(parse_excerpt_lengths("mention=0").is_none());
+        assert!(parse_excerpt_lengths("bogus=10").is_none());
+    }
+
+    #[test]
+    fn reply_context_is_one_line() {
+        let body = with_reply_context("Agreed!", "Hot take:\n\npineapple belongs on pizza");
+        assert_eq!(body, "Replying to: \u{201C}Hot take: pineapple belongs on pizza\u{201D}\nAgreed!");
+
+        let long = "word ".repeat(40);
+        let first_line = with_reply_context("Agreed!", &long).lines().next().unwrap().to_string();
+        assert!(first_line.ends_with("…\u{201D}"));
+        assert_eq!(with_reply_context("Agreed!", "  \n "), "Agreed!");
+    }
+}
@@ -0,0 +1,194 @@
+// experiments.rs
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{debug, info};
+
+use crate::feature_flags::{rollout_bucket, FeatureFlags};
+use crate::models::NotificationType;
+
+// A copy variant. Templates may use {handle} and {body}; a missing template
+// keeps the default copy for that field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CopyVariant {
+    pub name: String,
+    pub weight: u32,
+    pub title_template: Option<String>,
+    pub body_template: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CopyExperiment {
+    pub name: String,
+    pub notification_type: String,
+    pub variants: Vec<CopyVariant>,
+}
+
+// The variant a recipient was assigned, carried in the payload data
+#[derive(Debug, Clone)]
+pub struct ExperimentAssignment {
+    pub experiment: String,
+    pub variant: CopyVariant,
+}
+
+impl ExperimentAssignment {
+    // Render the assigned variant over the default title/body
+    pub fn render(&self, handle: &str, title: &str, body: &str) -> (String, String) {
+        let fill = |template: &str| template.replace("{handle}", handle).replace("{body}", body);
+
+        (
+            self.variant
+                .title_template
+                .as_deref()
+                .map(fill)
+                .unwrap_or_else(|| title.to_string()),
+            self.variant
+                .body_template
+                .as_deref()
+                .map(fill)
+                .unwrap_or_else(|| body.to_string()),
+        )
+    }
+}
+
+pub struct Experiments {
+    db_pool: Pool<Postgres>,
+    feature_flags: Arc<FeatureFlags>,
+    experiments: RwLock<HashMap<String, CopyExperiment>>,
+}
+
+impl Experiments {
+    pub async fn new(db_pool: Pool<Postgres>, feature_flags: Arc<FeatureFlags>) -> Result<Self> {
+        let experiments = Self {
+            db_pool,
+            feature_flags,
+            experiments: RwLock::new(HashMap::new()),
+        };
+        experiments.refresh().await?;
+        Ok(experiments)
+    }
+
+    // Reload all experiments from the database
+    pub async fn refresh(&self) -> Result<()> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT name, notification_type, variants
+            FROM copy_experiments
+            "#
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let mut loaded = HashMap::new();
+        for row in rows {
+            let variants: Vec<CopyVariant> = serde_json::from_value(row.variants)
+                .with_context(|| format!("Invalid variants for experiment {}", row.name))?;
+            loaded.insert(
+                row.name.clone(),
+                CopyExperiment {
+                    name: row.name,
+                    notification_type: row.notification_type,
+                    variants,
+                },
+            );
+        }
+
+        let mut experiments = self.experiments.write().await;
+        *experiments = loaded;
+        debug!("Refreshed copy experiments, count: {}", experiments.len());
+        Ok(())
+    }
+
+    // Find the variant a recipient should see for this notification type, if any.
+    // The experiment's feature flag decides exposure; weights split exposed users.
+    pub async fn assign(
+        &self,
+        notification_type: &NotificationType,
+        did: &str,
+    ) -> Option<ExperimentAssignment> {
+        let candidates: Vec<CopyExperiment> = {
+            let experiments = self.experiments.read().await;
+            experiments
+                .values()
+                .filter(|e| e.notification_type == notification_type.as_str())
+                .cloned()
+                .collect()
+        };
+
+        for experiment in candidates {
+            if !self.feature_flags.is_enabled(&experiment.name, did).await {
+                continue;
+            }
+
+            if let Some(variant) = pick_variant(&experiment, did) {
+                return Some(ExperimentAssignment {
+                    experiment: experiment.name,
+                    variant,
+                });
+            }
+        }
+
+        None
+    }
+
+    pub async fn list(&self) -> Vec<CopyExperiment> {
+        let experiments = self.experiments.read().await;
+        let mut list: Vec<CopyExperiment> = experiments.values().cloned().collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    // Create or update an experiment and apply it immediately
+    pub async fn upsert(&self, experiment: CopyExperiment) -> Result<()> {
+        let variants = serde_json::to_value(&experiment.variants)?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO copy_experiments (name, notification_type, variants)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (name) DO UPDATE
+            SET notification_type = $2, variants = $3, updated_at = NOW()
+            "#,
+            experiment.name,
+            experiment.notification_type,
+            variants
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        info!(
+            experiment = %experiment.name,
+            variants = experiment.variants.len(),
+            "Updated copy experiment"
+        );
+
+        self.experiments
+            .write()
+            .await
+            .insert(experiment.name.clone(), experiment);
+        Ok(())
+    }
+}
+
+// Deterministically pick a variant by weight. Uses a different hash input than
+// the exposure flag so variant split is independent of exposure.
+fn pick_variant(experiment: &CopyExperiment, did: &str) -> Option<CopyVariant> {
+    let total: u32 = experiment.variants.iter().map(|v| v.weight).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let bucket = rollout_bucket(&format!("{}#variant", experiment.name), did) * total / 100;
+    let mut cumulative = 0;
+    for variant in &experiment.variants {
+        cumulative += variant.weight;
+        if bucket < cumulative {
+            return Some(variant.clone());
+        }
+    }
+
+    experiment.variants.last().cloned()
+}
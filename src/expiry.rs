@@ -0,0 +1,82 @@
+// expiry.rs
+// Cache expirations as UTC wall-clock timestamps. The in-memory cache layers used to
+// count down on the process's monotonic clock while the database layers compared
+// against the database's NOW(), so an entry's lifetime started over whenever it was
+// loaded back from the database and depended on whichever clock looked at it. An
+// expiry is now fixed when the entry is written, stored with it in every layer and
+// checked against this host's clock. A host whose clock runs ahead would write
+// expiries too far out, so expiries read back from storage are capped at the TTL plus
+// SKEW_TOLERANCE from now.
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+pub const SKEW_TOLERANCE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Expiry(#[serde(with = "time::serde::timestamp")] OffsetDateTime);
+
+impl Expiry {
+    pub fn after(ttl: Duration) -> Self {
+        Self(OffsetDateTime::now_utc() + ttl)
+    }
+
+    // An expiry read back from storage, which another host may have written
+    pub fn stored(expires_at: OffsetDateTime, ttl: Duration) -> Self {
+        Self::stored_at(expires_at, ttl, OffsetDateTime::now_utc())
+    }
+
+    fn stored_at(expires_at: OffsetDateTime, ttl: Duration, now: OffsetDateTime) -> Self {
+        Self(expires_at.min(now + ttl + SKEW_TOLERANCE))
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.is_live_at(OffsetDateTime::now_utc())
+    }
+
+    pub fn is_live_at(&self, now: OffsetDateTime) -> bool {
+        now < self.0
+    }
+
+    pub fn at(&self) -> OffsetDateTime {
+        self.0
+    }
+
+    // Time left, for stores that take a TTL rather than a timestamp
+    pub fn remaining(&self) -> Duration {
+        (self.0 - OffsetDateTime::now_utc()).try_into().unwrap_or(Duration::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn survives_a_restart_without_starting_over() {
+        let ttl = Duration::from_secs(3600);
+        let written = OffsetDateTime::now_utc();
+        let expiry = Expiry(written + ttl);
+
+        // Loaded back 50 minutes later, e.g. by a restarted process: 10 minutes left,
+        // not another hour
+        let restarted = written + Duration::from_secs(50 * 60);
+        let reloaded = Expiry::stored_at(expiry.at(), ttl, restarted);
+        assert_eq!(reloaded, expiry);
+        assert!(reloaded.is_live_at(restarted));
+        assert!(!reloaded.is_live_at(restarted + Duration::from_secs(11 * 60)));
+    }
+
+    #[test]
+    fn caps_expiries_written_by_a_fast_clock() {
+        let ttl = Duration::from_secs(3600);
+        let now = OffsetDateTime::now_utc();
+
+        // Within the tolerance is left alone
+        let slightly_ahead = now + ttl + Duration::from_secs(10);
+        assert_eq!(Expiry::stored_at(slightly_ahead, ttl, now).at(), slightly_ahead);
+
+        let far_ahead = now + ttl + Duration::from_secs(6 * 3600);
+        assert_eq!(Expiry::stored_at(far_ahead, ttl, now).at(), now + ttl + SKEW_TOLERANCE);
+    }
+}
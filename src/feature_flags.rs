@@ -0,0 +1,141 @@
+// feature_flags.rs
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use tracing::{debug, info};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureFlag {
+    pub name: String,
+    pub enabled: bool,
+    pub rollout_percentage: i32,
+    pub description: Option<String>,
+}
+
+// DB-backed feature flags with an in-memory copy refreshed in the background
+pub struct FeatureFlags {
+    db_pool: Pool<Postgres>,
+    flags: RwLock<HashMap<String, FeatureFlag>>,
+}
+
+impl FeatureFlags {
+    pub async fn new(db_pool: Pool<Postgres>) -> Result<Self> {
+        let flags = Self {
+            db_pool,
+            flags: RwLock::new(HashMap::new()),
+        };
+        flags.refresh().await?;
+        Ok(flags)
+    }
+
+    // Reload all flags from the database
+    pub async fn refresh(&self) -> Result<()> {
+        let rows = sqlx::query_as!(
+            FeatureFlag,
+            r#"
+            SELECT name, enabled, rollout_percentage, description
+            FROM feature_flags
+            "#
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let mut flags = self.flags.write().await;
+        *flags = rows.into_iter().map(|f| (f.name.clone(), f)).collect();
+        debug!("Refreshed feature flags, count: {}", flags.len());
+        Ok(())
+    }
+
+    // Whether a flag is on for a DID. Unknown or disabled flags are off; enabled flags
+    // are on for the DIDs whose stable bucket falls under the rollout percentage.
+    pub async fn is_enabled(&self, name: &str, did: &str) -> bool {
+        let enabled = {
+            let flags = self.flags.read().await;
+            match flags.get(name) {
+                Some(flag) if flag.enabled => {
+                    rollout_bucket(name, did) < flag.rollout_percentage.clamp(0, 100) as u32
+                }
+                _ => false,
+            }
+        };
+
+        crate::metrics::FEATURE_FLAG_EVALUATIONS
+            .with_label_values(&[name, if enabled { "on" } else { "off" }])
+            .inc();
+
+        enabled
+    }
+
+    pub async fn list(&self) -> Vec<FeatureFlag> {
+        let flags = self.flags.read().await;
+        let mut list: Vec<FeatureFlag> = flags.values().cloned().collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    // Create or update a flag and apply it immediately
+    pub async fn upsert(&self, flag: FeatureFlag) -> Result<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO feature_flags (name, enabled, rollout_percentage, description)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (name) DO UPDATE
+            SET enabled = $2, rollout_percentage = $3, description = $4, updated_at = NOW()
+            "#,
+            flag.name,
+            flag.enabled,
+            flag.rollout_percentage,
+            flag.description
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        info!(
+            flag = %flag.name,
+            enabled = flag.enabled,
+            rollout_percentage = flag.rollout_percentage,
+            "Updated feature flag"
+        );
+
+        self.flags.write().await.insert(flag.name.clone(), flag);
+        Ok(())
+    }
+}
+
+// Stable 0-99 bucket for a (flag, DID) pair. Hashing the flag name in means
+// each flag samples a different slice of users.
+pub fn rollout_bucket(flag: &str, did: &str) -> u32 {
+    let mut hasher = Sha256::new();
+    hasher.update(flag.as_bytes());
+    hasher.update(b":");
+    hasher.update(did.as_bytes());
+    let digest = hasher.finalize();
+
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollout_bucket() {
+        // Buckets are stable for the same flag and DID
+        assert_eq!(
+            rollout_bucket("aggregation", "did:plc:user1"),
+            rollout_bucket("aggregation", "did:plc:user1")
+        );
+
+        // Buckets stay in range and spread across users
+        let buckets: Vec<u32> = (0..1000)
+            .map(|i| rollout_bucket("aggregation", &format!("did:plc:user{}", i)))
+            .collect();
+        assert!(buckets.iter().all(|b| *b < 100));
+
+        let under_half = buckets.iter().filter(|b| **b < 50).count();
+        assert!(under_half > 400 && under_half < 600);
+    }
+}
@@ -0,0 +1,526 @@
+// feeds.rs
+// Custom feed subscriptions. Each subscribed feed is polled through its feed
+// generator's getFeedSkeleton; posts that were not in the previous skeleton are
+// pushed to subscribers as "new post in <feed>", or counted toward a periodic digest
+// for subscribers who asked for one. The first poll of a feed only records what is
+// already there, so subscribing never triggers a burst of old posts.
+use anyhow::Result;
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+use crate::channel::PipelineSender;
+use crate::did_resolver::DidResolver;
+use crate::excerpt::ExcerptLimits;
+use crate::models::{LabelVisibility, NotificationPayload, NotificationType};
+use crate::post_resolver::PostResolver;
+use crate::relationship_manager::RelationshipManager;
+
+// Posts requested from the generator per poll
+const SKELETON_LIMIT: usize = 30;
+// Post URIs remembered per feed; generators may reorder, so keep more than one page
+const SEEN_LIMIT: usize = 200;
+// More new posts than this in one poll are announced as a single summary push
+const MAX_POSTS_PER_POLL: usize = 3;
+pub const MAX_SUBSCRIPTIONS: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedSubscription {
+    pub feed_uri: String,
+    #[serde(default)]
+    pub digest: bool,
+}
+
+#[derive(Deserialize)]
+struct GetFeedGeneratorResponse {
+    view: GeneratorView,
+}
+
+#[derive(Deserialize)]
+struct GeneratorView {
+    did: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+}
+
+#[derive(Deserialize)]
+struct FeedSkeleton {
+    feed: Vec<SkeletonItem>,
+}
+
+#[derive(Deserialize)]
+struct SkeletonItem {
+    post: String,
+}
+
+// at://<did>/app.bsky.feed.generator/<rkey>
+pub fn is_feed_uri(uri: &str) -> bool {
+    uri.strip_prefix("at://")
+        .and_then(|rest| rest.split_once("/app.bsky.feed.generator/"))
+        .is_some_and(|(did, rkey)| did.starts_with("did:") && !rkey.is_empty() && !rkey.contains('/'))
+}
+
+// Skeleton entries not seen before, oldest first. Skeletons list newest first.
+fn unseen_posts(skeleton: &[String], seen: &[String]) -> Vec<String> {
+    let seen: HashSet<&String> = seen.iter().collect();
+    skeleton
+        .iter()
+        .rev()
+        .filter(|uri| !seen.contains(uri))
+        .cloned()
+        .collect()
+}
+
+// The current skeleton followed by older remembered URIs, capped at SEEN_LIMIT
+fn merge_seen(skeleton: &[String], seen: Vec<String>) -> Vec<String> {
+    let mut merged: Vec<String> = skeleton.to_vec();
+    let current: HashSet<String> = merged.iter().cloned().collect();
+    merged.extend(seen.into_iter().filter(|uri| !current.contains(uri)));
+    merged.truncate(SEEN_LIMIT);
+    merged
+}
+
+pub struct FeedPoller {
+    db_pool: Pool<Postgres>,
+    http_client: HttpClient,
+    api_url: String,
+    did_resolver: Arc<DidResolver>,
+    post_resolver: Arc<PostResolver>,
+    relationship_manager: Arc<RelationshipManager>,
+    notification_sender: PipelineSender<NotificationPayload>,
+    excerpts: ExcerptLimits,
+}
+
+impl FeedPoller {
+    pub fn new(
+        db_pool: Pool<Postgres>,
+        api_url: String,
+        did_resolver: Arc<DidResolver>,
+        post_resolver: Arc<PostResolver>,
+        relationship_manager: Arc<RelationshipManager>,
+        notification_sender: PipelineSender<NotificationPayload>,
+        excerpts: ExcerptLimits,
+    ) -> Self {
+        Self {
+            db_pool,
+            http_client: HttpClient::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to create HTTP client"),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            did_resolver,
+            post_resolver,
+            relationship_manager,
+            notification_sender,
+            excerpts,
+        }
+    }
+
+    pub async fn run(self, poll_interval: Duration, digest_interval: Duration) {
+        let mut poll_ticker = tokio::time::interval(poll_interval);
+        let mut digest_ticker = tokio::time::interval(digest_interval);
+        // The first tick fires immediately and there is nothing to flush yet
+        digest_ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = poll_ticker.tick() => {
+                    match self.poll_all().await {
+                        Ok(0) => {}
+                        Ok(count) => info!("Announced {} new feed posts", count),
+                        Err(e) => error!("Error polling subscribed feeds: {}", e),
+                    }
+                }
+                _ = digest_ticker.tick() => {
+                    if let Err(e) = self.flush_digests().await {
+                        error!("Error flushing feed digests: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn poll_all(&self) -> Result<usize> {
+        // Forget feeds nobody subscribes to any more
+        sqlx::query!(
+            r#"
+            DELETE FROM feed_poll_state
+            WHERE feed_uri NOT IN (SELECT feed_uri FROM feed_subscriptions)
+            "#
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        let feeds = sqlx::query!("SELECT DISTINCT feed_uri FROM feed_subscriptions")
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        let mut total = 0;
+        for feed in feeds {
+            // One broken generator must not hold up the other feeds
+            match self.poll_feed(&feed.feed_uri).await {
+                Ok(count) => total += count,
+                Err(e) => warn!(feed = %feed.feed_uri, "Failed to poll feed: {}", e),
+            }
+        }
+        Ok(total)
+    }
+
+    async fn poll_feed(&self, feed_uri: &str) -> Result<usize> {
+        let state = sqlx::query!(
+            "SELECT service_did, display_name, seen_uris FROM feed_poll_state WHERE feed_uri = $1",
+            feed_uri
+        )
+        .fetch_optional(&self.db_pool)
+        .await?;
+
+        let (service_did, display_name, seen) = match state {
+            Some(state) => (state.service_did, state.display_name, Some(state.seen_uris)),
+            None => {
+                let generator = self.describe_feed(feed_uri).await?;
+                (generator.did, generator.display_name, None)
+            }
+        };
+
+        let skeleton = self.fetch_skeleton(&service_did, feed_uri).await?;
+        let fresh = seen
+            .as_deref()
+            .map(|seen| unseen_posts(&skeleton, seen))
+            .unwrap_or_default();
+
+        sqlx::query!(
+            r#"
+            INSERT INTO feed_poll_state (feed_uri, service_did, display_name, seen_uris, polled_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            ON CONFLICT (feed_uri)
+            DO UPDATE SET seen_uris = EXCLUDED.seen_uris, polled_at = NOW()
+            "#,
+            feed_uri,
+            service_did,
+            display_name,
+            &merge_seen(&skeleton, seen.unwrap_or_default())
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        if fresh.is_empty() {
+            return Ok(0);
+        }
+
+        debug!(feed = %feed_uri, count = fresh.len(), "New posts in subscribed feed");
+        self.announce(feed_uri, &display_name, &fresh).await?;
+        Ok(fresh.len())
+    }
+
+    async fn describe_feed(&self, feed_uri: &str) -> Result<GeneratorView> {
+        let url = format!("{}/xrpc/app.bsky.feed.getFeedGenerator", self.api_url);
+        let response = self
+            .http_client
+            .get(&url)
+            .query(&[("feed", feed_uri)])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Failed to fetch feed generator, status: {}",
+                response.status()
+            ));
+        }
+
+        Ok(response.json::<GetFeedGeneratorResponse>().await?.view)
+    }
+
+    async fn fetch_skeleton(&self, service_did: &str, feed_uri: &str) -> Result<Vec<String>> {
+        let endpoint = self.did_resolver.get_service_endpoint(service_did, "bsky_fg").await?;
+        let url = format!("{}/xrpc/app.bsky.feed.getFeedSkeleton", endpoint);
+        let response = self
+            .http_client
+            .get(&url)
+            .query(&[("feed", feed_uri), ("limit", &SKELETON_LIMIT.to_string())])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Failed to fetch feed skeleton, status: {}",
+                response.status()
+            ));
+        }
+
+        Ok(response
+            .json::<FeedSkeleton>()
+            .await?
+            .feed
+            .into_iter()
+            .map(|item| item.post)
+            .collect())
+    }
+
+    async fn announce(&self, feed_uri: &str, display_name: &str, posts: &[String]) -> Result<()> {
+        let subscribers = sqlx::query!(
+            r#"
+            SELECT d.id, d.did, d.device_token, s.digest
+            FROM feed_subscriptions s
+            JOIN user_devices d ON d.id = s.user_id AND d.deleted_at IS NULL
+            JOIN notification_preferences p ON p.user_id = d.id
+            WHERE s.feed_uri = $1 AND p.feed_posts
+            "#,
+            feed_uri
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        // Post content is only needed for individual pushes
+        let mut contents = HashMap::new();
+        if posts.len() <= MAX_POSTS_PER_POLL && subscribers.iter().any(|s| !s.digest) {
+            for uri in posts {
+                match self.post_resolver.get_post(uri).await {
+                    Ok(post) => {
+                        contents.insert(uri.clone(), post);
+                    }
+                    Err(e) => warn!(uri = %uri, "Failed to resolve feed post: {}", e),
+                }
+            }
+        }
+
+        // One subscriber's failure doesn't cost the others their notification
+        for subscriber in subscribers {
+            let result: Result<()> = async {
+                if subscriber.digest {
+                    sqlx::query!(
+                        r#"
+                        INSERT INTO pending_feed_digests (user_id, feed_uri, count)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT (user_id, feed_uri)
+                        DO UPDATE SET count = pending_feed_digests.count + $3, updated_at = NOW()
+                        "#,
+                        subscriber.id,
+                        feed_uri,
+                        posts.len() as i32
+                    )
+                    .execute(&self.db_pool)
+                    .await?;
+                    crate::metrics::FEED_POSTS_NOTIFIED
+                        .with_label_values(&["digest"])
+                        .inc_by(posts.len() as f64);
+                    return Ok(());
+                }
+
+                if posts.len() > MAX_POSTS_PER_POLL {
+                    let payload = feed_payload(
+                        &subscriber.did,
+                        &subscriber.device_token,
+                        format!("New posts in {}", display_name),
+                        format!("{} new posts", posts.len()),
+                        feed_uri,
+                        feed_uri,
+                    );
+                    self.send(payload).await?;
+                    return Ok(());
+                }
+
+                let label_prefs = crate::db::get_label_preferences(&self.db_pool, subscriber.id)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!("Failed to load label preferences: {}", e);
+                        HashMap::new()
+                    });
+
+                for uri in posts {
+                    let Some(post) = contents.get(uri) else {
+                        continue;
+                    };
+
+                    let author = uri
+                        .strip_prefix("at://")
+                        .and_then(|rest| rest.split('/').next())
+                        .unwrap_or_default();
+                    if self.relationship_manager.is_muted(&subscriber.did, author).await
+                        || self.relationship_manager.is_blocked(&subscriber.did, author).await
+                    {
+                        continue;
+                    }
+
+                    let body = match LabelVisibility::resolve(&post.labels, &label_prefs) {
+                        LabelVisibility::Show => self.excerpts.excerpt(&NotificationType::FeedPost, &post.text),
+                        LabelVisibility::Mask => {
+                            crate::metrics::NOTIFICATIONS_LABEL_FILTERED
+                                .with_label_values(&["mask"])
+                                .inc();
+                            "This post contains sensitive content".to_string()
+                        }
+                        LabelVisibility::Hide => {
+                            crate::metrics::NOTIFICATIONS_LABEL_FILTERED
+                                .with_label_values(&["hide"])
+                                .inc();
+                            continue;
+                        }
+                    };
+
+                    let mut payload = feed_payload(
+                        &subscriber.did,
+                        &subscriber.device_token,
+                        format!("New post in {}", display_name),
+                        body,
+                        uri,
+                        feed_uri,
+                    );
+                    payload.data.insert("author_did".to_string(), author.to_string());
+                    self.send(payload).await?;
+                }
+                Ok(())
+            }
+            .await;
+            if let Err(e) = result {
+                warn!(did = %subscriber.did, feed = %feed_uri, "Failed to notify feed subscriber: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send(&self, payload: NotificationPayload) -> Result<()> {
+        self.notification_sender
+            .send(payload)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to queue feed notification: {}", e))?;
+        crate::metrics::FEED_POSTS_NOTIFIED
+            .with_label_values(&["push"])
+            .inc();
+        Ok(())
+    }
+
+    // One rollup push per device covering every digest feed with new posts
+    async fn flush_digests(&self) -> Result<usize> {
+        let devices = sqlx::query_scalar!(
+            r#"
+            SELECT DISTINCT f.user_id
+            FROM pending_feed_digests f
+            JOIN user_devices d ON d.id = f.user_id AND d.deleted_at IS NULL
+            "#
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        let mut sent = 0;
+        for user_id in devices {
+            // The counts are only deleted once the digest is queued
+            let mut tx = self.db_pool.begin().await?;
+            let rows = sqlx::query!(
+                r#"
+                WITH flushed AS (
+                    DELETE FROM pending_feed_digests
+                    WHERE user_id = $1
+                    RETURNING user_id, feed_uri, count
+                )
+                SELECT d.did, d.device_token, f.feed_uri, f.count, s.display_name AS "display_name?"
+                FROM flushed f
+                JOIN user_devices d ON d.id = f.user_id
+                LEFT JOIN feed_poll_state s ON s.feed_uri = f.feed_uri
+                "#,
+                user_id
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            let Some(first) = rows.first() else {
+                continue;
+            };
+            let (did, device_token) = (first.did.clone(), first.device_token.clone());
+
+            // (feed_uri, display name, new posts)
+            let mut feeds: Vec<(String, String, i32)> = rows
+                .into_iter()
+                .map(|row| {
+                    let name = row.display_name.unwrap_or_else(|| "a feed".to_string());
+                    (row.feed_uri, name, row.count)
+                })
+                .collect();
+            feeds.sort_by_key(|(_, _, count)| std::cmp::Reverse(*count));
+            let body = feeds
+                .iter()
+                .enumerate()
+                .map(|(i, (_, name, count))| match (i, count) {
+                    (0, 1) => format!("1 new post in {}", name),
+                    (0, _) => format!("{} new posts in {}", count, name),
+                    _ => format!("{} in {}", count, name),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            // A single feed deep links to it; several open the feeds list
+            let feed_uri = feeds[0].0.clone();
+            let mut payload = feed_payload(&did, &device_token, "Your feeds".to_string(), body, &feed_uri, &feed_uri);
+            payload.data.insert("digest".to_string(), "true".to_string());
+            if feeds.len() > 1 {
+                payload.data.remove("uri");
+            }
+
+            if let Err(e) = self.notification_sender.send(payload).await {
+                warn!("Failed to queue feed digest notification: {}", e);
+                tx.rollback().await?;
+                break;
+            }
+            tx.commit().await?;
+            sent += 1;
+        }
+
+        if sent > 0 {
+            info!("Sent {} feed digest notifications", sent);
+        }
+        Ok(sent)
+    }
+}
+
+fn feed_payload(
+    did: &str,
+    device_token: &str,
+    title: String,
+    body: String,
+    uri: &str,
+    feed_uri: &str,
+) -> NotificationPayload {
+    let mut data = HashMap::new();
+    data.insert("uri".to_string(), uri.to_string());
+    data.insert("type".to_string(), format!("{:?}", NotificationType::FeedPost));
+    data.insert("feed_uri".to_string(), feed_uri.to_string());
+    data.insert("notification_id".to_string(), uuid::Uuid::new_v4().to_string());
+
+    NotificationPayload {
+        user_did: did.to_string(),
+        device_token: device_token.to_string(),
+        notification_type: NotificationType::FeedPost,
+        title,
+        body,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uris(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| format!("at://did:plc:a/app.bsky.feed.post/{}", id)).collect()
+    }
+
+    #[test]
+    fn only_unseen_posts_are_announced_oldest_first() {
+        let seen = merge_seen(&uris(&["c", "b", "a"]), Vec::new());
+        let skeleton = uris(&["e", "d", "c", "b"]);
+
+        assert_eq!(unseen_posts(&skeleton, &seen), uris(&["d", "e"]));
+        assert_eq!(merge_seen(&skeleton, seen), uris(&["e", "d", "c", "b", "a"]));
+    }
+
+    #[test]
+    fn feed_uris_must_name_a_generator_record() {
+        assert!(is_feed_uri("at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.generator/whats-hot"));
+        assert!(!is_feed_uri("at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/abc"));
+        assert!(!is_feed_uri("https://bsky.app/profile/bsky.app/feed/whats-hot"));
+    }
+}
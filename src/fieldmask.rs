@@ -0,0 +1,84 @@
+// fieldmask.rs
+// Sparse responses for constrained clients: `?fields=mentions,replies` keeps only the
+// named fields of each object in a response. An endpoint declares the fields it can
+// return, plus any short aliases for them (e.g. "ts" for created_at), and rejects
+// unknown names so a typo isn't mistaken for an empty response. Without `fields`
+// responses are unchanged.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+// Extracted alongside an endpoint's own query parameters
+#[derive(Debug, Default, Deserialize)]
+pub struct FieldsQuery {
+    pub fields: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldMask {
+    // None keeps every field
+    fields: Option<HashSet<String>>,
+}
+
+impl FieldMask {
+    // Comma-separated names from `known` or `aliases`; Err carries the first unknown name
+    pub fn parse(spec: Option<&str>, known: &[&str], aliases: &[(&str, &str)]) -> Result<Self, String> {
+        let Some(spec) = spec.filter(|spec| !spec.trim().is_empty()) else {
+            return Ok(Self { fields: None });
+        };
+
+        let fields = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let field = aliases
+                    .iter()
+                    .find(|(alias, _)| *alias == name)
+                    .map_or(name, |(_, field)| *field);
+                if known.contains(&field) {
+                    Ok(field.to_string())
+                } else {
+                    Err(name.to_string())
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { fields: Some(fields) })
+    }
+
+    // The value serialized with only the masked fields; non-objects pass through
+    pub fn apply<T: Serialize>(&self, value: &T) -> serde_json::Result<Value> {
+        let mut value = serde_json::to_value(value)?;
+        if let (Some(fields), Value::Object(object)) = (&self.fields, &mut value) {
+            object.retain(|key, _| fields.contains(key));
+        }
+        Ok(value)
+    }
+
+    pub fn apply_all<T: Serialize>(&self, values: &[T]) -> serde_json::Result<Vec<Value>> {
+        values.iter().map(|value| self.apply(value)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn keeps_only_requested_fields() {
+        let known = ["notification_type", "uri", "created_at", "title"];
+        let aliases = [("type", "notification_type"), ("ts", "created_at")];
+        let entry = json!({ "notification_type": "like", "uri": "at://x", "created_at": "now", "title": "t" });
+
+        let mask = FieldMask::parse(Some("type, uri,ts"), &known, &aliases).unwrap();
+        assert_eq!(
+            mask.apply(&entry).unwrap(),
+            json!({ "notification_type": "like", "uri": "at://x", "created_at": "now" })
+        );
+
+        let all = FieldMask::parse(None, &known, &aliases).unwrap();
+        assert_eq!(all.apply_all(std::slice::from_ref(&entry)).unwrap(), vec![entry]);
+        assert_eq!(FieldMask::parse(Some("uri,bogus"), &known, &aliases), Err("bogus".to_string()));
+    }
+}
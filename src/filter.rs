@@ -1,62 +1,393 @@
 use anyhow::Result;
 use sqlx::{Pool, Postgres};
-use tokio::sync::mpsc;
 use tracing::{debug, error, info, warn};
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
     db,
-    models::{BlueskyEvent, NotificationPayload, NotificationType},
+    models::{BlueskyEvent, Grouping, LabelVisibility, NotificationPayload, NotificationType},
 };
 
+use crate::channel::{PipelineReceiver, PipelineSender};
+use crate::content_fallback::{ContentFallback, ContentFallbacks, GENERIC_BODY};
+use crate::aggregation::Aggregator;
+use crate::copy_script::{CopyScript, ScriptEvent};
+use crate::dedup::EventDedup;
+use crate::delivery_policy::{Decision, DeliveryPolicy, Recipient};
+use crate::excerpt::ExcerptLimits;
+use crate::experiments::Experiments;
+use crate::lists::{ListPurpose, ListTarget};
 use crate::post_resolver::PostResolver;
+use crate::profile_resolver::ProfileResolver;
+use crate::retraction::Retractions;
+use crate::user_trace::UserTrace;
+
+// Guards against a single event fanning out to a huge number of recipients
+#[derive(Debug, Clone)]
+pub struct FanoutLimits {
+    // Hard cap on recipients considered for one event
+    pub max_recipients: usize,
+    // Above this many deliveries the event is sent in spaced batches off the filter loop
+    pub spread_threshold: usize,
+    pub spread_batch_size: usize,
+    pub spread_interval: std::time::Duration,
+    // Spread fan-outs in flight at once; the filter loop waits for a slot beyond that
+    pub spreading: Arc<tokio::sync::Semaphore>,
+}
+
+impl FanoutLimits {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self {
+            max_recipients: config.fanout_max_recipients,
+            spread_threshold: config.fanout_spread_threshold,
+            spread_batch_size: config.fanout_spread_batch_size,
+            spread_interval: std::time::Duration::from_millis(config.fanout_spread_interval_ms),
+            spreading: Arc::new(tokio::sync::Semaphore::new(config.fanout_max_spreading)),
+        }
+    }
+}
+
+// The filter's shared handles and settings, built once in main
+pub struct FilterContext {
+    pub db_pool: Pool<Postgres>,
+    pub did_resolver: Arc<crate::did_resolver::DidResolver>,
+    pub post_resolver: Arc<crate::post_resolver::PostResolver>,
+    pub relationship_manager: Arc<crate::relationship_manager::RelationshipManager>,
+    pub profile_resolver: Arc<ProfileResolver>,
+    pub experiments: Arc<Experiments>,
+    pub fanout_limits: FanoutLimits,
+    pub content_fallbacks: ContentFallbacks,
+    pub policy: DeliveryPolicy,
+    pub aggregator: Aggregator,
+    pub dedup: EventDedup,
+    pub retractions: Retractions,
+    pub excerpts: ExcerptLimits,
+    pub memory_guard: Arc<crate::memory_guard::MemoryGuard>,
+    pub plugins: Arc<crate::plugins::PluginHost>,
+    pub copy_script: Option<Arc<CopyScript>>,
+    pub cache_generation: Arc<crate::cache_sync::CacheGeneration>,
+    pub trace: UserTrace,
+    pub records: crate::decoder::RecordCache,
+    pub ranking: crate::ranking::Ranking,
+    // Off for bench-load, whose notifications are dropped after the filter
+    pub count_unread: bool,
+}
 
 pub async fn run_event_filter(
-    mut event_receiver: mpsc::Receiver<BlueskyEvent>,
-    notification_sender: mpsc::Sender<NotificationPayload>,
-    db_pool: Pool<Postgres>,
-    did_resolver: Arc<crate::did_resolver::DidResolver>,
-    post_resolver: Arc<crate::post_resolver::PostResolver>,
-    relationship_manager: Arc<crate::relationship_manager::RelationshipManager>,
+    mut event_receiver: PipelineReceiver<BlueskyEvent>,
+    notification_sender: PipelineSender<NotificationPayload>,
+    context: FilterContext,
 ) -> Result<()> {
+    let FilterContext {
+        db_pool,
+        did_resolver,
+        post_resolver,
+        relationship_manager,
+        profile_resolver,
+        experiments,
+        fanout_limits,
+        content_fallbacks,
+        policy,
+        aggregator,
+        dedup,
+        retractions,
+        excerpts,
+        memory_guard,
+        plugins,
+        copy_script,
+        cache_generation,
+        trace,
+        records,
+        ranking,
+        count_unread,
+    } = context;
     info!("Starting event filter");
 
     // Cache of registered users to avoid frequent DB lookups
     let mut registered_users = db::get_registered_users(&db_pool).await?;
-    let mut last_cache_refresh = std::time::Instant::now();
+    let mut post_subscriptions = crate::post_subscriptions::load(&db_pool).await?;
+    let mut refresh_gate = crate::cache_sync::RefreshGate::new(
+        cache_generation.current(),
+        std::time::Duration::from_secs(300),
+    );
+
+    // Events waiting to retry a failed subject post fetch, with their attempt count.
+    // A slot is reserved before the delay starts, which bounds how many can be pending.
+    let (retry_sender, mut retry_receiver) = tokio::sync::mpsc::channel::<(BlueskyEvent, u32)>(10_000);
+
+    loop {
+        let (event, attempt) = tokio::select! {
+            event = event_receiver.recv() => match event {
+                Some(event) => (event, 0),
+                None => break,
+            },
+            Some(retry) = retry_receiver.recv() => retry,
+        };
 
-    while let Some(event) = event_receiver.recv().await {
         // Create timer to measure event processing time
         let timer = std::time::Instant::now();
         crate::metrics::EVENTS_PROCESSED.inc();
+
+        // Deletions only matter for records we recently notified about
+        if event.op == "delete" {
+            let record_uri = format!("at://{}/{}", event.author, event.path);
+            if retractions.was_notified(&record_uri) {
+                if let Err(e) = retractions.retract(&db_pool, &notification_sender, &record_uri).await {
+                    error!("Failed to retract notifications for deleted record: {}", e);
+                }
+            }
+            continue;
+        }
         
-        // Refresh user cache every 5 minutes
-        if last_cache_refresh.elapsed().as_secs() > 300 {
-            match db::get_registered_users(&db_pool).await {
-                Ok(users) => {
+        // Refresh user cache when settings change, or every 5 minutes as a fallback
+        let generation = cache_generation.current();
+        if refresh_gate.is_stale(generation) {
+            match (
+                db::get_registered_users(&db_pool).await,
+                crate::post_subscriptions::load(&db_pool).await,
+            ) {
+                (Ok(users), Ok(subscriptions)) => {
                     registered_users = users;
-                    last_cache_refresh = std::time::Instant::now();
+                    post_subscriptions = subscriptions;
+                    refresh_gate.mark_refreshed(generation);
                     debug!(
                         "Refreshed registered users cache, count: {}",
                         registered_users.len()
                     );
                 }
-                Err(e) => error!("Failed to refresh user cache: {}", e),
+                (Err(e), _) | (_, Err(e)) => error!("Failed to refresh user cache: {}", e),
             }
         }
 
-        // Skip event if author is not registered
-        if !registered_users.contains(&event.author) {
+        // Skip event if author is neither registered nor subscribed to
+        if !registered_users.contains(&event.author) && !post_subscriptions.contains_key(&event.author) {
             // Check if the event is relevant to any registered user
             if !is_event_relevant_to_users(&event, &registered_users) {
                 continue;
             }
         }
 
+        // Only posts change meaningfully on update; other records are rewritten in place
+        if event.op == "update" && !event.path.contains("app.bsky.feed.post") {
+            continue;
+        }
+
+        // Spares re-decoding the record if its commit is replayed
+        if attempt == 0 {
+            records.insert(&event.cid, &event.record).await;
+        }
+
+        // Reconnects and duplicate commits can deliver a record twice; retries are expected repeats
+        if attempt == 0 && !dedup.first_seen(&event).await {
+            debug!(
+                author = %event.author,
+                path = %event.path,
+                seq = ?event.origin.seq,
+                "Skipping event - already processed"
+            );
+            crate::metrics::EVENTS_DEDUPLICATED.inc();
+            trace.record(&event.author, "filter", || format!("{} {} skipped as a duplicate", event.op, event.path));
+            continue;
+        }
+        trace.record(&event.author, "filter", || {
+            format!("{} {} received (seq {:?}, attempt {})", event.op, event.path, event.origin.seq, attempt)
+        });
+
+        // How long a subscribed author had been silent before this post, if long enough
+        // to announce as a comeback
+        let comeback = if attempt == 0
+            && post_subscriptions.contains_key(&event.author)
+            && crate::post_subscriptions::is_activity(&event)
+        {
+            crate::post_subscriptions::record_post(&db_pool, &event.author)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to record subscribed author's post: {}", e);
+                    None
+                })
+        } else {
+            None
+        };
+
         // Determine notification type and extract relevant user DIDs
-        if let Some((notification_type, relevant_dids)) = classify_event(&event, &registered_users)
+        let mut classified: Vec<(NotificationType, Vec<String>)> =
+            classify_event(&event, &registered_users).into_iter().collect();
+
+        // A subscribed author's new post also goes to subscribers not already notified of it
+        if let Some(subscribers) = post_subscriptions
+            .get(&event.author)
+            .filter(|_| crate::post_subscriptions::is_new_post(&event))
         {
+            let notified: HashSet<&String> = classified.iter().flat_map(|(_, dids)| dids).collect();
+            let subscribers: Vec<String> = subscribers
+                .iter()
+                .filter(|did| !notified.contains(did))
+                .cloned()
+                .collect();
+            if !subscribers.is_empty() {
+                classified.push((NotificationType::SubscribedPost, subscribers));
+            }
+        }
+
+        // A backfill reconstructs the notifications its recipients missed, and nobody else's
+        if let Some(recipients) = &event.origin.recipients {
+            for (_, dids) in classified.iter_mut() {
+                dids.retain(|did| recipients.contains(did));
+            }
+            classified.retain(|(_, dids)| !dids.is_empty());
+        }
+
+        for (notification_type, mut relevant_dids) in classified {
+            for did in &relevant_dids {
+                trace.record(did, "filter", || {
+                    format!("{} candidate from {} ({} {})", notification_type.as_str(), event.author, event.op, event.path)
+                });
+            }
+
+            // The author's declaration decides which subscribers may hear about the post
+            if matches!(notification_type, NotificationType::SubscribedPost) {
+                relevant_dids = match crate::post_subscriptions::permitted_subscribers(
+                    &db_pool,
+                    &profile_resolver,
+                    &event.author,
+                    relevant_dids,
+                )
+                .await
+                {
+                    Ok(permitted) if !permitted.is_empty() => permitted,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        error!("Failed to check activity declaration: {}", e);
+                        continue;
+                    }
+                };
+            }
+
+            // An edit keeps the post's URI, so recipients already notified about it are
+            // skipped, except that opted-in users hear about edited mentions
+            let mut edit_recipients = HashSet::new();
+            if event.op == "update" {
+                let uri = format!("at://{}/{}", event.author, event.path);
+                match db::get_notified_users(&db_pool, &relevant_dids, &uri).await {
+                    Ok(notified) if matches!(notification_type, NotificationType::Mention) => {
+                        edit_recipients = notified;
+                    }
+                    Ok(notified) => relevant_dids.retain(|did| !notified.contains(did)),
+                    Err(e) => {
+                        error!("Failed to check earlier notifications for edited post: {}", e);
+                        continue;
+                    }
+                }
+                if relevant_dids.is_empty() {
+                    continue;
+                }
+            }
+
+            // Recipients who marked the author a VIP get this event whatever the load
+            let mut vip_recipients = HashSet::new();
+            for did in &relevant_dids {
+                if relationship_manager.is_vip(did, &event.author).await {
+                    vip_recipients.insert(did.clone());
+                }
+            }
+
+            // Under memory pressure only the important types get through
+            if memory_guard.is_shedding()
+                && !matches!(notification_type, NotificationType::Follow | NotificationType::Reply | NotificationType::Mention)
+            {
+                for did in relevant_dids.iter().filter(|did| !vip_recipients.contains(*did)) {
+                    trace.record(did, "filter", || format!("{} shed under memory pressure", notification_type.as_str()));
+                }
+                if vip_recipients.is_empty() {
+                    crate::metrics::EVENTS_SHED.inc();
+                    continue;
+                }
+                relevant_dids.retain(|did| vip_recipients.contains(did));
+            }
+
+            // Operator plugins run on the blocking pool since WASM execution is synchronous
+            let plugin_category = if plugins.is_empty() {
+                None
+            } else {
+                let plugins = plugins.clone();
+                let plugin_event = event.clone();
+                let plugin_type = notification_type.clone();
+                let recipients = relevant_dids.clone();
+                let decision = tokio::task::spawn_blocking(move || {
+                    plugins.evaluate(&plugin_event, &plugin_type, &recipients)
+                })
+                .await
+                .unwrap_or_default();
+
+                if decision.suppress {
+                    debug!(author = %event.author, path = %event.path, "Event suppressed by plugin");
+                    for did in &relevant_dids {
+                        trace.record(did, "filter", || format!("{} suppressed by plugin", notification_type.as_str()));
+                    }
+                    continue;
+                }
+                decision.category
+            };
+
+            // Likes and reposts show the subject post, so settle up front what happens if it's unavailable
+            if let Some(uri) = subject_post_uri(&notification_type, &event) {
+                if let Err(e) = post_resolver.get_post(uri).await {
+                    let mut fallback = content_fallbacks.for_type(&notification_type);
+                    if fallback == ContentFallback::Retry {
+                        match retry_sender.clone().try_reserve_owned() {
+                            Ok(permit) if attempt < content_fallbacks.max_retries => {
+                                debug!(uri = %uri, attempt = attempt + 1, "Subject post unavailable, retrying later: {}", e);
+                                let retry_event = event.clone();
+                                let delay = content_fallbacks.retry_delay;
+                                tokio::spawn(async move {
+                                    tokio::time::sleep(delay).await;
+                                    permit.send((retry_event, attempt + 1));
+                                });
+                            }
+                            // Out of retries or retry slots
+                            _ => fallback = ContentFallback::Generic,
+                        }
+                    }
+                    crate::metrics::POST_CONTENT_FALLBACKS
+                        .with_label_values(&[notification_type.as_str(), fallback.as_str()])
+                        .inc();
+                    if fallback != ContentFallback::Generic {
+                        continue;
+                    }
+                }
+            }
+
+            // List additions are announced by the list's purpose; moderation lists never are
+            let list_target = match (&notification_type, crate::lists::list_uri(&event.record)) {
+                (NotificationType::ListAddition, Some(list_uri)) => {
+                    match crate::lists::resolve(&db_pool, &profile_resolver, list_uri).await {
+                        Ok(target) if target.purpose == ListPurpose::Moderation => continue,
+                        Ok(target) => Some(target),
+                        Err(e) => {
+                            debug!(list = %list_uri, "Failed to resolve list: {}", e);
+                            continue;
+                        }
+                    }
+                }
+                _ => None,
+            };
+
+            if relevant_dids.len() > fanout_limits.max_recipients {
+                warn!(
+                    author = %event.author,
+                    path = %event.path,
+                    recipients = relevant_dids.len(),
+                    cap = fanout_limits.max_recipients,
+                    "Fan-out cap hit, dropping excess recipients"
+                );
+                crate::metrics::FANOUT_CAP_HITS.inc();
+                for did in &relevant_dids[fanout_limits.max_recipients..] {
+                    trace.record(did, "filter", || format!("{} dropped by the fan-out cap", notification_type.as_str()));
+                }
+                relevant_dids.truncate(fanout_limits.max_recipients);
+            }
+
             // Get all DIDs we need to resolve: author + all relevant recipients
             let mut dids_to_resolve = Vec::new();
             dids_to_resolve.push(event.author.clone());
@@ -77,34 +408,6 @@ pub async fn run_event_filter(
             // Process each relevant DID
             let mut notification_futures = Vec::new();
             for did in &relevant_dids {
-                // Add this check to skip self-notifications
-                if did == &event.author {
-                    debug!(
-                        recipient = %did,
-                        "Skipping self-notification"
-                    );
-                    continue;
-                }
-
-                // Check if the target has muted or blocked the author
-                if relationship_manager.is_muted(did, &event.author).await {
-                    debug!(
-                        recipient = %did,
-                        author = %event.author,
-                        "Skipping notification - author is muted by recipient"
-                    );
-                    continue;
-                }
-                
-                if relationship_manager.is_blocked(did, &event.author).await {
-                    debug!(
-                        recipient = %did,
-                        author = %event.author,
-                        "Skipping notification - author is blocked by recipient"
-                    );
-                    continue;
-                }
-                
                 if let Some(devices) = devices_map.get(did) {
                     // Process devices for this DID
                     for device in devices {
@@ -114,32 +417,146 @@ pub async fn run_event_filter(
                         let event = event.clone();
                         let handle_map = handle_map.clone();
                         let post_resolver = post_resolver.clone();
+                        let profile_resolver = profile_resolver.clone();
+                        let relationship_manager = relationship_manager.clone();
+                        let experiments = experiments.clone();
+                        let policy = policy.clone();
+                        let plugin_category = plugin_category.clone();
+                        let copy_script = copy_script.clone();
+                        let aggregator = aggregator.clone();
+                        let ranking = ranking.clone();
+                        let retractions = retractions.clone();
+                        let excerpts = excerpts.clone();
+                        let trace = trace.clone();
                         let notification_sender = notification_sender.clone();
                         let did = did.clone();
+                        let is_vip = vip_recipients.contains(&did);
+                        let is_edit = edit_recipients.contains(&did);
+                        let list_target = list_target.clone();
+                        let comeback = comeback.filter(|_| matches!(notification_type, NotificationType::SubscribedPost));
                         
                         notification_futures.push(async move {
                             // Get user preferences
                             match db::get_notification_preferences(&db_pool, device.id).await {
                                 Ok(prefs) => {
-                                    // Check if user wants this notification type
-                                    let should_notify = match &notification_type {
-                                        NotificationType::Mention => prefs.mentions,
-                                        NotificationType::Reply => prefs.replies,
-                                        NotificationType::Like => prefs.likes,
-                                        NotificationType::Follow => prefs.follows,
-                                        NotificationType::Repost => prefs.reposts,
-                                        NotificationType::Quote => prefs.quotes,
+                                    let recipient = Recipient {
+                                        did: &did,
+                                        device: &device,
+                                        prefs: &prefs,
+                                        vip: is_vip,
+                                        edit: is_edit,
                                     };
+                                    let decision = policy.evaluate(&recipient, &event, &notification_type).await;
+                                    if let Some(reason) = decision.reason() {
+                                        debug!(
+                                            recipient = %did,
+                                            author = %event.author,
+                                            reason,
+                                            source = event.origin.source.as_str(),
+                                            seq = ?event.origin.seq,
+                                            "Skipping notification"
+                                        );
+                                        skipped(&trace, &did, &notification_type, reason);
+                                    }
+                                    if let Decision::Defer(until) = decision {
+                                        if let Err(e) = policy.defer(&recipient, &notification_type, until).await {
+                                            error!("Failed to defer notification to {}: {}", until.as_str(), e);
+                                        }
+                                    }
 
-                                    if should_notify {
+                                    if decision == Decision::Deliver {
                                         // Create notification content with handle map and post resolver
                                         match create_notification_content(
                                             &handle_map,
                                             &notification_type, 
                                             &event,
-                                            &post_resolver
+                                            &post_resolver,
+                                            &excerpts,
+                                            list_target.as_ref(),
                                         ).await {
-                                            Ok((title, body, uri)) => {
+                                            Ok((title, body, uri, labels)) => {
+                                                let title = match comeback {
+                                                    _ if is_edit => format!(
+                                                        "@{} edited a post that mentions you",
+                                                        author_handle(&handle_map, &event.author)
+                                                    ),
+                                                    Some(silence) if prefs.comeback_posts => {
+                                                        crate::post_subscriptions::comeback_title(
+                                                            &author_handle(&handle_map, &event.author),
+                                                            silence,
+                                                        )
+                                                    }
+                                                    _ => title,
+                                                };
+
+                                                // Respect the recipient's handling of labeled content
+                                                let visibility = if labels.is_empty() {
+                                                    LabelVisibility::Show
+                                                } else {
+                                                    let label_prefs = db::get_label_preferences(&db_pool, device.id)
+                                                        .await
+                                                        .unwrap_or_else(|e| {
+                                                            warn!("Failed to load label preferences: {}", e);
+                                                            HashMap::new()
+                                                        });
+                                                    LabelVisibility::resolve(&labels, &label_prefs)
+                                                };
+                                                let show_media = matches!(visibility, LabelVisibility::Show);
+                                                let masked = matches!(visibility, LabelVisibility::Mask);
+                                                let body = match visibility {
+                                                    LabelVisibility::Show => body,
+                                                    LabelVisibility::Mask => {
+                                                        crate::metrics::NOTIFICATIONS_LABEL_FILTERED
+                                                            .with_label_values(&["mask"])
+                                                            .inc();
+                                                        "This post contains sensitive content".to_string()
+                                                    }
+                                                    LabelVisibility::Hide => {
+                                                        debug!(
+                                                            recipient = %did,
+                                                            labels = ?labels,
+                                                            "Skipping notification - labeled content hidden by recipient"
+                                                        );
+                                                        crate::metrics::NOTIFICATIONS_LABEL_FILTERED
+                                                            .with_label_values(&["hide"])
+                                                            .inc();
+                                                        skipped(&trace, &did, &notification_type, "label_hidden");
+                                                        return;
+                                                    }
+                                                };
+
+                                                // Replies to the recipient's own post can open with a line of it
+                                                let body = match reply_parent(&event.record) {
+                                                    Some(parent)
+                                                        if prefs.reply_context
+                                                            && show_media
+                                                            && matches!(notification_type, NotificationType::Reply)
+                                                            && parent.starts_with(&format!("at://{}/", did)) =>
+                                                    {
+                                                        match post_resolver.get_post_content(parent).await {
+                                                            Ok(text) => crate::excerpt::with_reply_context(&body, &text),
+                                                            Err(e) => {
+                                                                debug!("Failed to resolve replied-to post: {}", e);
+                                                                body
+                                                            }
+                                                        }
+                                                    }
+                                                    _ => body,
+                                                };
+
+                                                // Swap in experiment copy if the recipient is enrolled
+                                                let assignment = experiments.assign(&notification_type, &did).await;
+                                                let (title, body) = match &assignment {
+                                                    Some(assignment) => assignment.render(
+                                                        &author_handle(&handle_map, &event.author),
+                                                        &title,
+                                                        &body,
+                                                    ),
+                                                    None => (title, body),
+                                                };
+                                                // Masking covers the title too, whatever copy it was given
+                                                let title = if masked { "Sensitive content".to_string() } else { title };
+
                                                 // Prepare notification payload with additional data
                                                 let mut data = HashMap::new();
                                                 
@@ -149,6 +566,120 @@ pub async fn run_event_filter(
                                                     data.insert("type".to_string(), format!("{:?}", notification_type));
                                                 }
 
+                                                // Sent as the apns-id so the app can acknowledge opens
+                                                data.insert("notification_id".to_string(), uuid::Uuid::new_v4().to_string());
+
+                                                // Deleting the record soon after retracts the notification
+                                                let record_uri = format!("at://{}/{}", event.author, event.path);
+                                                retractions.remember(&record_uri).await;
+                                                data.insert("record_uri".to_string(), record_uri);
+
+                                                // Lets a report resolve its subject from history
+                                                data.insert("author_did".to_string(), event.author.clone());
+                                                if matches!(
+                                                    notification_type,
+                                                    NotificationType::Mention
+                                                        | NotificationType::Reply
+                                                        | NotificationType::Quote
+                                                        | NotificationType::SubscribedPost
+                                                ) {
+                                                    data.insert("cid".to_string(), event.cid.clone());
+                                                }
+
+                                                // The service extension has too little budget to fetch the avatar itself;
+                                                // the handle status lets the app hint how far to trust the name
+                                                match profile_resolver.get_profile(&event.author).await {
+                                                    Ok(profile) => {
+                                                        if let Some(status) = profile_resolver.handle_status(&profile) {
+                                                            data.insert("handle_status".to_string(), status.as_str().to_string());
+                                                        }
+                                                        if let Some(avatar_url) = profile.avatar_url {
+                                                            data.insert("avatar_url".to_string(), avatar_url);
+                                                        }
+                                                    }
+                                                    Err(e) => debug!("Failed to resolve author avatar: {}", e),
+                                                }
+
+                                                // Attached by the service extension; never for masked content
+                                                if show_media {
+                                                    if let Some(image_url) = notification_image(&notification_type, &event, &post_resolver).await {
+                                                        data.insert("image_url".to_string(), image_url);
+                                                    }
+                                                }
+
+                                                // Record the variant so delivery and opens can be attributed
+                                                if let Some(assignment) = &assignment {
+                                                    data.insert("experiment".to_string(), assignment.experiment.clone());
+                                                    data.insert("variant".to_string(), assignment.variant.name.clone());
+                                                }
+
+                                                event.origin.annotate(&mut data);
+                                                if prefs.payload_event_origin {
+                                                    data.insert(crate::payload_keys::ORIGIN_KEY.to_string(), "true".to_string());
+                                                }
+
+                                                if let Some(category) = &plugin_category {
+                                                    data.insert("category".to_string(), category.clone());
+                                                }
+
+                                                // Friends break through Focus, strangers arrive quietly
+                                                if prefs.priority_from_mutuals {
+                                                    let mutual = crate::follows::is_mutual(
+                                                        &relationship_manager,
+                                                        &profile_resolver,
+                                                        &did,
+                                                        &event.author,
+                                                    )
+                                                    .await;
+                                                    let level = match mutual {
+                                                        Ok(true) => Some("time-sensitive"),
+                                                        Ok(false) => Some("passive"),
+                                                        Err(e) => {
+                                                            debug!("Failed to check mutual follow: {}", e);
+                                                            None
+                                                        }
+                                                    };
+                                                    if let Some(level) = level {
+                                                        data.insert("interruption_level".to_string(), level.to_string());
+                                                    }
+                                                }
+
+                                                if is_vip {
+                                                    data.insert("vip".to_string(), "true".to_string());
+                                                    data.insert("interruption_level".to_string(), "time-sensitive".to_string());
+                                                } else if prefs.delivers_in_background(&notification_type) {
+                                                    data.insert("push_type".to_string(), "background".to_string());
+                                                }
+
+                                                // The payload builder picks thread-id and collapse-id from these
+                                                if prefs.grouping != Grouping::App.as_str() {
+                                                    data.insert("grouping".to_string(), prefs.grouping.clone());
+                                                }
+                                                if prefs.payload_version >= crate::payload_keys::COMPACT_VERSION {
+                                                    data.insert(
+                                                        crate::payload_keys::VERSION_KEY.to_string(),
+                                                        prefs.payload_version.to_string(),
+                                                    );
+                                                }
+                                                if let Some(root) = thread_root(&event.record) {
+                                                    data.insert("thread_root".to_string(), root.to_string());
+                                                }
+
+                                                // Deployment-specific copy tweaks; bounded tightly enough to run inline
+                                                let (title, body, data) = match &copy_script {
+                                                    Some(script) => script.apply(
+                                                        &ScriptEvent {
+                                                            notification_type: &notification_type,
+                                                            event: &event,
+                                                            author_handle: &author_handle(&handle_map, &event.author),
+                                                        },
+                                                        title,
+                                                        body,
+                                                        data,
+                                                    ),
+                                                    None => (title, body, data),
+                                                };
+
                                                 let payload = NotificationPayload {
                                                     user_did: did.clone(),
                                                     device_token: device.device_token.clone(),
@@ -158,6 +689,21 @@ pub async fn run_event_filter(
                                                     data, // Now contains URI and type for deep linking
                                                 };
 
+                                                // Later likes and reposts in a burst go out together when the window closes
+                                                let payload = if is_vip {
+                                                    payload
+                                                } else {
+                                                    match aggregator.offer(payload, &author_handle(&handle_map, &event.author)).await {
+                                                        Some(payload) => payload,
+                                                        None => {
+                                                            trace.record(&did, "filter", || {
+                                                                format!("{} held for aggregation (device {})", notification_type.as_str(), device.id)
+                                                            });
+                                                            return;
+                                                        }
+                                                    }
+                                                };
+
                                                 // Add backpressure detection
                                                 let remaining_capacity = notification_sender.capacity();
                                                 if remaining_capacity == 0 {
@@ -166,9 +712,14 @@ pub async fn run_event_filter(
                                                         format!("{:?}", notification_type).to_lowercase()
                                                     );
                                                     
-                                                    // Prioritize important notifications
-                                                    if !matches!(notification_type, NotificationType::Follow | NotificationType::Reply | NotificationType::Mention) {
+                                                    // Prioritize the types users open most, or important
+                                                    // ones until there's enough open data to rank them
+                                                    let low_priority = ranking.is_low_engagement(&notification_type).unwrap_or(
+                                                        !matches!(notification_type, NotificationType::Follow | NotificationType::Reply | NotificationType::Mention),
+                                                    );
+                                                    if !is_vip && low_priority {
                                                         warn!("Skipping low-priority notification due to system load");
+                                                        skipped(&trace, &did, &notification_type, "backpressure");
                                                         return;
                                                     }
                                                     
@@ -176,39 +727,97 @@ pub async fn run_event_filter(
                                                     tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
                                                 }
 
+                                                // Lets the app badge its tabs without asking us first. Counted
+                                                // only for notifications that actually go out, and taken back
+                                                // if the enqueue fails
+                                                let mut payload = payload;
+                                                let mut counted = false;
+                                                if count_unread {
+                                                    match db::increment_unread_counts(&db_pool, device.id, notification_type.is_mention()).await {
+                                                        Ok((mentions, total)) => {
+                                                            payload.data.insert("unread_mentions".to_string(), mentions.to_string());
+                                                            payload.data.insert("unread_total".to_string(), total.to_string());
+                                                            counted = true;
+                                                        }
+                                                        Err(e) => warn!("Failed to update unread counts: {}", e),
+                                                    }
+                                                }
+
                                                 // Send with timeout to avoid blocking indefinitely
-                                                match tokio::time::timeout(
+                                                let result = tokio::time::timeout(
                                                     tokio::time::Duration::from_secs(3),
                                                     notification_sender.send(payload)
-                                                ).await {
+                                                ).await;
+                                                if counted && !matches!(result, Ok(Ok(_))) {
+                                                    if let Err(e) = db::uncount_unread(&db_pool, device.id, notification_type.is_mention()).await {
+                                                        warn!("Failed to roll back unread counts: {}", e);
+                                                    }
+                                                }
+                                                match result {
                                                     Ok(Ok(_)) => {
                                                         crate::metrics::NOTIFICATIONS_SENT.inc();
+                                                        crate::metrics::NOTIFICATIONS_SENT_BY_TYPE
+                                                            .with_label_values(&[notification_type.as_str()])
+                                                            .inc();
+                                                        trace.record(&did, "filter", || {
+                                                            format!("{} queued for device {}", notification_type.as_str(), device.id)
+                                                        });
                                                     },
                                                     Ok(Err(e)) => {
                                                         error!("Failed to send notification to queue: {}", e);
+                                                        skipped(&trace, &did, &notification_type, "enqueue_failed");
                                                     },
                                                     Err(_) => {
                                                         error!("Timeout when sending notification to queue - system overloaded");
+                                                        skipped(&trace, &did, &notification_type, "enqueue_failed");
                                                     }
                                                 }
                                             },
                                             Err(e) => {
                                                 error!("Failed to create notification content: {}", e);
+                                                trace.record(&did, "filter", || format!("{} content failed to build: {}", notification_type.as_str(), e));
                                             }
                                         }
                                     }
                                 },
                                 Err(e) => {
                                     error!("Failed to get notification preferences: {}", e);
+                                    trace.record(&did, "filter", || format!("preferences for device {} failed to load: {}", device.id, e));
                                 }
                             }
                         });
                     }
+                } else {
+                    trace.record(did, "filter", || format!("{} has no registered devices", notification_type.as_str()));
                 }
             }
             
-            // Execute all notification processing in parallel
-            futures::future::join_all(notification_futures).await;
+            if notification_futures.len() > fanout_limits.spread_threshold {
+                // Spread very large fan-outs over time without stalling the filter loop
+                info!(
+                    author = %event.author,
+                    deliveries = notification_futures.len(),
+                    "Spreading large fan-out over time"
+                );
+                crate::metrics::FANOUT_SPREAD_EVENTS.inc();
+                let limits = fanout_limits.clone();
+                let permit = limits.spreading.clone().acquire_owned().await.unwrap();
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    let mut pending = notification_futures;
+                    while !pending.is_empty() {
+                        let rest = pending.split_off(pending.len().min(limits.spread_batch_size));
+                        futures::future::join_all(pending).await;
+                        pending = rest;
+                        if !pending.is_empty() {
+                            tokio::time::sleep(limits.spread_interval).await;
+                        }
+                    }
+                });
+            } else {
+                // Execute all notification processing in parallel
+                futures::future::join_all(notification_futures).await;
+            }
         }
         
         // Record event processing time
@@ -220,6 +829,12 @@ pub async fn run_event_filter(
     Ok(())
 }
 
+// Count a notification the filter didn't push, and note why in the recipient's trace
+fn skipped(trace: &UserTrace, did: &str, notification_type: &NotificationType, reason: &str) {
+    crate::metrics::record_skipped(notification_type, reason);
+    trace.record(did, "filter", || format!("{} skipped: {}", notification_type.as_str(), reason));
+}
+
 fn is_event_relevant_to_users(event: &BlueskyEvent, users: &[String]) -> bool {
     // Only debug log for specific types
     let event_type = if event.path.contains("app.bsky.feed.post") {
@@ -230,19 +845,22 @@ fn is_event_relevant_to_users(event: &BlueskyEvent, users: &[String]) -> bool {
         "repost"
     } else if event.path.contains("app.bsky.graph.follow") {
         "follow"
+    } else if event.path.contains("app.bsky.graph.listitem") {
+        "listitem"
     } else {
         "other"
     };
 
-    // Handle follows differently - subject is a direct DID string
-    if event.path.contains("app.bsky.graph.follow") {
+    // Handle follows and list items differently - subject is a direct DID string
+    if event.path.contains("app.bsky.graph.follow") || event.path.contains("app.bsky.graph.listitem") {
         if let Some(subject) = event.record.get("subject").and_then(|s| s.as_str()) {
             for user in users {
                 if subject == user {
                     info!(
                         type = %event_type,
                         user = %user,
-                        "Found relevant follow for user"
+                        "Found relevant {} for user",
+                        event_type
                     );
                     return true;
                 }
@@ -490,6 +1108,11 @@ fn classify_event(
             let relevant_dids = extract_target_dids(event, registered_users);
             (NotificationType::Repost, relevant_dids)
         }
+        path if path.contains("app.bsky.graph.listitem") => {
+            // Extract the account added to the list
+            let relevant_dids = extract_target_dids(event, registered_users);
+            (NotificationType::ListAddition, relevant_dids)
+        }
         _ => return None, // Not a notification-worthy event
     };
 
@@ -596,8 +1219,8 @@ fn extract_mention_dids(event: &BlueskyEvent, registered_users: &[String]) -> Ve
 
 fn extract_target_dids(event: &BlueskyEvent, registered_users: &[String]) -> Vec<String> {
     // Different extraction based on record type
-    if event.path.contains("app.bsky.graph.follow") {
-        // For follows, the subject is a direct DID string
+    if event.path.contains("app.bsky.graph.follow") || event.path.contains("app.bsky.graph.listitem") {
+        // For follows and list items, the subject is a direct DID string
         if let Some(subject) = event.record.get("subject").and_then(|s| s.as_str()) {
             return registered_users
                 .iter()
@@ -640,36 +1263,73 @@ fn extract_target_dids(event: &BlueskyEvent, registered_users: &[String]) -> Vec
     Vec::new()
 }
 
+// Use resolved handle if available, fallback to DID
+fn author_handle(handle_map: &HashMap<String, String>, author: &str) -> String {
+    handle_map
+        .get(author)
+        .cloned()
+        .unwrap_or_else(|| author.split(':').last().unwrap_or(author).to_string())
+}
+
+// The post a like or repost points at
+fn subject_post_uri<'a>(notification_type: &NotificationType, event: &'a BlueskyEvent) -> Option<&'a str> {
+    match notification_type {
+        NotificationType::Like | NotificationType::Repost => event.record.get("subject")?.get("uri")?.as_str(),
+        _ => None,
+    }
+}
+
+// Thumbnail of the post the notification is about, if it has images
+async fn notification_image(
+    notification_type: &NotificationType,
+    event: &BlueskyEvent,
+    post_resolver: &PostResolver,
+) -> Option<String> {
+    match notification_type {
+        // Already resolved for the body, so this is a cache hit
+        NotificationType::Like | NotificationType::Repost => {
+            post_resolver.get_post(subject_post_uri(notification_type, event)?).await.ok()?.image_url
+        }
+        NotificationType::Reply
+        | NotificationType::Mention
+        | NotificationType::Quote
+        | NotificationType::SubscribedPost => crate::post_resolver::record_thumbnail(&event.author, &event.record),
+        _ => None,
+    }
+}
+
 async fn create_notification_content(
     handle_map: &HashMap<String, String>,
     notification_type: &NotificationType,
     event: &BlueskyEvent,
     post_resolver: &PostResolver,
-) -> Result<(String, String, Option<String>)> {
-    // Use resolved handle if available, fallback to DID
-    let username = handle_map.get(&event.author)
-        .cloned()
-        .unwrap_or_else(|| event.author.split(':').last().unwrap_or(&event.author).to_string());
+    excerpts: &ExcerptLimits,
+    // Resolved before fan-out for list additions
+    list: Option<&ListTarget>,
+) -> Result<(String, String, Option<String>, Vec<String>)> {
+    let username = author_handle(handle_map, &event.author);
     
     // Extract URI and appropriate content based on notification type
-    let (title, body, uri) = match notification_type {
+    let (title, body, uri, labels) = match notification_type {
         NotificationType::Like => {
             // For likes, we need to fetch the content of the post that was liked
             if let Some(subject) = event.record.get("subject").and_then(|s| s.as_object()) {
                 if let Some(uri) = subject.get("uri").and_then(|u| u.as_str()) {
                     // Fetch the original post content that was liked
-                    match post_resolver.get_post_content(uri).await {
-                        Ok(content) => (
+                    match post_resolver.get_post(uri).await {
+                        Ok(post) => (
                             format!("@{} liked your post", username),
-                            content,
-                            Some(uri.to_string())
+                            post.text,
+                            Some(uri.to_string()),
+                            post.labels
                         ),
                         Err(e) => {
                             warn!(error = %e, "Failed to get original post content for like");
                             (
                                 format!("@{} liked your post", username),
-                                "".to_string(),
-                                Some(uri.to_string())
+                                GENERIC_BODY.to_string(),
+                                Some(uri.to_string()),
+                                Vec::new()
                             )
                         }
                     }
@@ -677,14 +1337,16 @@ async fn create_notification_content(
                     (
                         format!("@{} liked your post", username),
                         "".to_string(),
-                        None
+                        None,
+                        Vec::new()
                     )
                 }
             } else {
                 (
                     format!("@{} liked your post", username),
                     "".to_string(),
-                    None
+                    None,
+                    Vec::new()
                 )
             }
         },
@@ -693,18 +1355,20 @@ async fn create_notification_content(
             if let Some(subject) = event.record.get("subject").and_then(|s| s.as_object()) {
                 if let Some(uri) = subject.get("uri").and_then(|u| u.as_str()) {
                     // Fetch the original post content that was reposted
-                    match post_resolver.get_post_content(uri).await {
-                        Ok(content) => (
+                    match post_resolver.get_post(uri).await {
+                        Ok(post) => (
                             format!("@{} reposted your post", username),
-                            content,
-                            Some(uri.to_string())
+                            post.text,
+                            Some(uri.to_string()),
+                            post.labels
                         ),
                         Err(e) => {
                             warn!(error = %e, "Failed to get original post content for repost");
                             (
                                 format!("@{} reposted your post", username),
-                                "".to_string(),
-                                Some(uri.to_string())
+                                GENERIC_BODY.to_string(),
+                                Some(uri.to_string()),
+                                Vec::new()
                             )
                         }
                     }
@@ -712,14 +1376,16 @@ async fn create_notification_content(
                     (
                         format!("@{} reposted your post", username),
                         "".to_string(),
-                        None
+                        None,
+                        Vec::new()
                     )
                 }
             } else {
                 (
                     format!("@{} reposted your post", username),
                     "".to_string(),
-                    None
+                    None,
+                    Vec::new()
                 )
             }
         },
@@ -730,10 +1396,17 @@ async fn create_notification_content(
                 event.author, 
                 event.path.split('/').last().unwrap_or(""));
                 
+            let title = if is_nested_reply(&event.record) {
+                format!("@{} replied to your reply", username)
+            } else {
+                format!("@{} replied to your post", username)
+            };
+
             (
-                format!("@{} replied to you", username),
+                title,
                 post_text.to_string(),
-                Some(uri)
+                Some(uri),
+                self_labels(&event.record)
             )
         },
         NotificationType::Mention => {
@@ -746,7 +1419,8 @@ async fn create_notification_content(
             (
                 format!("@{} mentioned you", username),
                 post_text.to_string(),
-                Some(uri)
+                Some(uri),
+                self_labels(&event.record)
             )
         },
         NotificationType::Quote => {
@@ -759,7 +1433,8 @@ async fn create_notification_content(
             (
                 format!("@{} quoted your post", username),
                 post_text.to_string(),
-                Some(uri)
+                Some(uri),
+                self_labels(&event.record)
             )
         },
         NotificationType::Follow => {
@@ -769,10 +1444,54 @@ async fn create_notification_content(
             (
                 "New follower".to_string(),
                 format!("@{} followed you", username),
-                Some(profile_uri)  // Now includes URI for deep linking
+                Some(profile_uri),  // Now includes URI for deep linking
+                Vec::new()
+            )
+        }
+        NotificationType::SubscribedPost => {
+            // For subscribed posts, use the text of the new post
+            let post_text = event.record.get("text").and_then(|t| t.as_str()).unwrap_or("");
+            let uri = format!("at://{}/app.bsky.feed.post/{}", 
+                event.author, 
+                event.path.rsplit('/').next().unwrap_or(""));
+                
+            (
+                format!("@{} posted", username),
+                post_text.to_string(),
+                Some(uri),
+                self_labels(&event.record)
+            )
+        },
+        NotificationType::FeedPost => {
+            // Built by the feed poller, never from a firehose event
+            anyhow::bail!("Feed post notifications are not created from firehose events")
+        }
+        NotificationType::DirectMessage => {
+            // Built by the DM poller; chat isn't on the firehose
+            anyhow::bail!("Direct message notifications are not created from firehose events")
+        }
+        NotificationType::Custom => {
+            // Submitted by first-party services on the internal API
+            anyhow::bail!("Custom notifications are not created from firehose events")
+        }
+        NotificationType::ListAddition => {
+            // The list's name is the body and the list (or its starter pack) the deep link
+            let list = list.ok_or_else(|| anyhow::anyhow!("List addition without a resolved list"))?;
+            let title = if list.purpose == ListPurpose::StarterPack {
+                format!("@{} added you to a starter pack", username)
+            } else {
+                format!("@{} added you to a list", username)
+            };
+
+            (
+                title,
+                list.name.clone(),
+                Some(list.uri.clone()),
+                Vec::new()
             )
         }
     };
+    let body = excerpts.excerpt(notification_type, &body);
     
     tracing::debug!(
         notification_type = ?notification_type,
@@ -780,8 +1499,55 @@ async fn create_notification_content(
         title = %title,
         body = %body,
         uri = ?uri,
+        labels = ?labels,
         "Created notification content"
     );
 
-    Ok((title, body, uri))
+    Ok((title, body, uri, labels))
+}
+
+// The first post of the thread a reply belongs to
+fn thread_root(record: &serde_json::Value) -> Option<&str> {
+    record.get("reply")?.get("root")?.get("uri")?.as_str()
+}
+
+fn reply_parent(record: &serde_json::Value) -> Option<&str> {
+    record.get("reply")?.get("parent")?.get("uri")?.as_str()
+}
+
+// A reply whose parent isn't the thread root is answering another reply. Both are
+// strong refs, so they are matched by CID when they carry one; a handle in one URI
+// and a DID in the other still name the same post
+pub(crate) fn is_nested_reply(record: &serde_json::Value) -> bool {
+    let Some(reply) = record.get("reply") else {
+        return false;
+    };
+    match (reply.get("parent"), reply.get("root")) {
+        (Some(parent), Some(root)) => !same_post(parent, root),
+        _ => false,
+    }
+}
+
+fn same_post(a: &serde_json::Value, b: &serde_json::Value) -> bool {
+    let field = |r: &serde_json::Value, key: &str| r.get(key).and_then(|v| v.as_str()).map(str::to_string);
+    match (field(a, "cid"), field(b, "cid")) {
+        (Some(a), Some(b)) => a == b,
+        _ => field(a, "uri").is_some() && field(a, "uri") == field(b, "uri"),
+    }
+}
+
+// Labels the author applied to a new post themselves; labeler verdicts arrive later
+fn self_labels(record: &serde_json::Value) -> Vec<String> {
+    record
+        .get("labels")
+        .and_then(|labels| labels.get("values"))
+        .and_then(|values| values.as_array())
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|value| value.get("val").and_then(|v| v.as_str()))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
 }
\ No newline at end of file
@@ -1,33 +1,52 @@
 use anyhow::{anyhow, Result};
-use atrium_api::app::bsky::feed::like::Record as FeedLike;
-use atrium_api::app::bsky::feed::post::Record as FeedPost;
-use atrium_api::app::bsky::feed::repost::Record as FeedRepost;
-use atrium_api::app::bsky::graph::follow::Record as GraphFollow;
-use atrium_api::com::atproto::sync::subscribe_repos::{Commit, NSID};
-use atrium_repo::blockstore::{AsyncBlockStoreRead, CarStore};
+use atrium_api::com::atproto::sync::subscribe_repos::NSID;
 use futures::StreamExt;
-use ipld_core::cid::Cid; // Import Cid from ipld_core
 use sqlx::{Pool, Postgres};
-use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::TcpStream;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::oneshot;
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use tracing::{debug, error, info, warn};
 
+use crate::channel::PipelineSender;
+use crate::decoder::{Decoded, DecodedCommit, DecodedOp, Decoder};
+use crate::gaps::{GapReason, GapReporter};
+use crate::retry::RetryPolicy;
 use crate::stream::frames::Frame;
 use crate::subscription::{CommitHandler, Subscription};
-use crate::{db, models::BlueskyEvent};
-
-// WebSocket connection wrapper (no changes here)
-struct RepoSubscription {
+use crate::db;
+use crate::models::{BlueskyEvent, EventOrigin, EventSource};
+use crate::relationship_manager::RelationshipManager;
+use crate::repo_state::RepoState;
+use crate::retraction::Retractions;
+
+// Lag under which a consumer resuming from a stored cursor counts as caught up, and
+// its events as live rather than replayed
+const REPLAY_CAUGHT_UP_SECS: i64 = 30;
+
+// Records whose deletion is passed on to the filter
+const RETRACTABLE_COLLECTIONS: &[&str] = &[
+    "app.bsky.feed.post",
+    "app.bsky.feed.like",
+    "app.bsky.feed.repost",
+    "app.bsky.graph.follow",
+    "app.bsky.graph.listitem",
+];
+
+// WebSocket connection wrapper
+pub(crate) struct RepoSubscription {
     stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
 }
 
 impl RepoSubscription {
-    async fn new(bgs: &str, _cursor: Option<String>) -> Result<Self> {
-        let ws_url = format!("wss://{}/xrpc/{}", bgs, NSID);
+    pub(crate) async fn new(bgs: &str, cursor: Option<String>) -> Result<Self> {
+        let ws_url = match cursor {
+            Some(cursor) => format!("wss://{}/xrpc/{}?cursor={}", bgs, NSID, cursor),
+            None => format!("wss://{}/xrpc/{}", bgs, NSID),
+        };
         info!("Connecting to firehose at: {}", ws_url);
 
         let (stream, _) = connect_async(ws_url).await?;
@@ -48,41 +67,147 @@ impl Subscription for RepoSubscription {
     }
 }
 
-// Helper function with improved error handling for different record types
-fn deserialize_record(collection: &str, record_block: &[u8]) -> Result<serde_json::Value> {
-    let cursor = Cursor::new(record_block);
-    match collection {
-        "app.bsky.feed.post" => {
-            let post: FeedPost = serde_ipld_dagcbor::from_reader(cursor)?;
-            Ok(serde_json::to_value(post)?)
-        }
-        "app.bsky.feed.like" => {
-            let like: FeedLike = serde_ipld_dagcbor::from_reader(cursor)?;
-            Ok(serde_json::to_value(like)?)
-        }
-        "app.bsky.graph.follow" => {
-            let follow: GraphFollow = serde_ipld_dagcbor::from_reader(cursor)?;
-            // Add debug info to understand structure
-            let follow_value = serde_json::to_value(follow)?;
-            debug!("Follow record structure: {:?}", follow_value);
-            Ok(follow_value)
+// Handler for Commit events (the fix is here)
+pub(crate) struct FirehoseHandler {
+    event_sender: PipelineSender<BlueskyEvent>,
+    db_pool: Pool<Postgres>,
+    relationship_manager: Arc<RelationshipManager>,
+    retractions: Retractions,
+    repo_state: RepoState,
+    // Set while catching up from a stored cursor
+    replaying: AtomicBool,
+}
+
+impl FirehoseHandler {
+    pub(crate) fn new(
+        event_sender: PipelineSender<BlueskyEvent>,
+        db_pool: Pool<Postgres>,
+        relationship_manager: Arc<RelationshipManager>,
+        retractions: Retractions,
+        repo_state: RepoState,
+        replaying: bool,
+    ) -> Self {
+        Self {
+            event_sender,
+            db_pool,
+            relationship_manager,
+            retractions,
+            repo_state,
+            replaying: AtomicBool::new(replaying),
         }
-        "app.bsky.feed.repost" => {
-            let repost: FeedRepost = serde_ipld_dagcbor::from_reader(cursor)?;
-            Ok(serde_json::to_value(repost)?)
+    }
+
+    // Keep the account's latest declaration; deleting it restores the default
+    async fn handle_declaration(&self, did: &str, op: &DecodedOp) -> Result<()> {
+        let record = match (op.action.as_str(), &op.record) {
+            ("create" | "update", Some(record)) => record,
+            ("create" | "update", None) => return Err(anyhow!("Declaration record missing or malformed")),
+            ("delete", _) => {
+                self.repo_state.declare(did, None).await;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+
+        let allow_subscriptions = record
+            .get("allowSubscriptions")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Declaration without allowSubscriptions"))?;
+
+        self.repo_state.declare(did, Some(allow_subscriptions)).await;
+        debug!(did = %did, allow_subscriptions = %allow_subscriptions, "Recorded activity declaration");
+        Ok(())
+    }
+
+    // Starter packs are kept so additions to their lists can link to the pack
+    async fn handle_starter_pack(&self, did: &str, op: &DecodedOp) -> Result<()> {
+        let uri = format!("at://{}/{}", did, op.path);
+        let record = match (op.action.as_str(), &op.record) {
+            ("create" | "update", Some(record)) => record,
+            ("create" | "update", None) => return Err(anyhow!("Starter pack record missing or malformed")),
+            ("delete", _) => {
+                self.repo_state.starter_pack(&uri, None).await;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+
+        let list_uri = crate::lists::list_uri(record).ok_or_else(|| anyhow!("Starter pack without list"))?;
+        let name = record.get("name").and_then(|v| v.as_str()).unwrap_or("");
+
+        self.repo_state.starter_pack(&uri, Some((list_uri, name))).await;
+        Ok(())
+    }
+
+    // Registered users' blocks are applied as they're made, so notifications stop
+    // without waiting for the app to send its next /relationships update
+    async fn handle_block(&self, did: &str, op: &DecodedOp) -> Result<()> {
+        let rkey = op.path.rsplit('/').next().unwrap_or_default();
+        let applied = match (op.action.as_str(), &op.record) {
+            ("create", Some(record)) => {
+                let subject = record
+                    .get("subject")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow!("Block without subject"))?;
+                self.relationship_manager.record_block(did, rkey, subject).await?
+            }
+            ("create", None) => return Err(anyhow!("Block record missing or malformed")),
+            ("delete", _) => self.relationship_manager.remove_block(did, rkey).await?,
+            _ => false,
+        };
+        if applied {
+            crate::metrics::FIREHOSE_BLOCKS_APPLIED
+                .with_label_values(&[op.action.as_str()])
+                .inc();
         }
-        _ => Err(anyhow!("Unsupported collection type: {}", collection)),
+        Ok(())
     }
-}
 
-// Handler for Commit events (the fix is here)
-struct FirehoseHandler {
-    event_sender: mpsc::Sender<BlueskyEvent>,
-    db_pool: Pool<Postgres>,
+    // Registered users' follows, for the "only people I follow" preference
+    async fn handle_follow(&self, did: &str, op: &DecodedOp) -> Result<()> {
+        let rkey = op.path.rsplit('/').next().unwrap_or_default();
+        let applied = match (op.action.as_str(), &op.record) {
+            ("create", Some(record)) => {
+                let subject = record
+                    .get("subject")
+                    .and_then(|v| v.as_str())
+                    .ok_or_else(|| anyhow!("Follow without subject"))?;
+                self.relationship_manager.record_follow(did, rkey, subject).await?
+            }
+            ("create", None) => return Err(anyhow!("Follow record missing or malformed")),
+            ("delete", _) => self.relationship_manager.remove_follow(did, rkey).await?,
+            _ => false,
+        };
+        if applied {
+            crate::metrics::FIREHOSE_FOLLOWS_APPLIED
+                .with_label_values(&[op.action.as_str()])
+                .inc();
+        }
+        Ok(())
+    }
 }
 
 impl CommitHandler for FirehoseHandler {
-    async fn handle_commit(&self, commit: &Commit) -> Result<()> {
+    async fn handle_commit(&self, commit: &DecodedCommit) -> Result<()> {
+        if let Some(sent_at) = commit.sent_at {
+            let lag = chrono::Utc::now().signed_duration_since(sent_at);
+            crate::metrics::FIREHOSE_LAG_SECONDS.set(lag.num_milliseconds() as f64 / 1000.0);
+            if lag.num_seconds() < REPLAY_CAUGHT_UP_SECS && self.replaying.swap(false, Ordering::Relaxed) {
+                info!(seq = commit.seq, "Caught up with the live firehose");
+            }
+        }
+        let origin = EventOrigin {
+            source: if self.replaying.load(Ordering::Relaxed) {
+                EventSource::Replay
+            } else {
+                EventSource::Relay
+            },
+            seq: Some(commit.seq),
+            rev: Some(commit.rev.clone()),
+            received_at: chrono::Utc::now().timestamp_millis(),
+            recipients: None,
+        };
+
         // Only log every 1000 commits - this will show progress without flooding logs
         if commit.seq % 1000 == 0 {
             info!(
@@ -91,117 +216,174 @@ impl CommitHandler for FirehoseHandler {
             );
         }
 
-        // Create a CarStore from the blocks.
-        let mut car_store = CarStore::open(Cursor::new(&commit.blocks[..]))
-            .await
-            .map_err(|e| anyhow!("Failed to create CarStore: {}", e))?;
+        self.handle_ops(&commit.repo, &commit.ops, &origin).await;
 
-        for op in &commit.ops {
-            if op.action != "create" && op.action != "update" {
+        // Update cursor without logging every time
+        if let Err(e) = db::update_cursor(&self.db_pool, &commit.seq.to_string()).await {
+            error!("Failed to update cursor: {}", e);
+        }
+
+        Ok(())
+    }
+}
+
+impl FirehoseHandler {
+    // Apply a repo's operations and queue its events, whether they came from the relay
+    // or from a backfilled archive. Returns the number of events queued.
+    pub(crate) async fn handle_ops(&self, repo: &str, ops: &[DecodedOp], origin: &EventOrigin) -> usize {
+        let mut queued = 0;
+        for op in ops {
+            let Some(collection) = op.collection() else {
+                continue;
+            };
+
+            // Activity-subscription declarations are state to keep, not events to notify
+            // about. The subscriptions themselves (the bell in the official app) are
+            // private AppView state and never appear on the firehose.
+            if collection == "app.bsky.notification.declaration" {
+                if let Err(e) = self.handle_declaration(repo, op).await {
+                    debug!("Failed to record activity declaration: {}", e);
+                }
                 continue;
             }
 
-            let parts: Vec<&str> = op.path.split('/').collect();
-            if parts.len() < 2 {
+            if collection == "app.bsky.graph.block" {
+                if let Err(e) = self.handle_block(repo, op).await {
+                    debug!("Failed to apply block: {}", e);
+                }
                 continue;
             }
 
-            let collection = parts[0];
-            let _rkey_str = parts[1];
+            // Follows are also notified about below
+            if collection == "app.bsky.graph.follow" {
+                if let Err(e) = self.handle_follow(repo, op).await {
+                    debug!("Failed to apply follow: {}", e);
+                }
+            }
+
+            if collection == "app.bsky.graph.starterpack" {
+                if let Err(e) = self.handle_starter_pack(repo, op).await {
+                    debug!("Failed to record starter pack: {}", e);
+                }
+                continue;
+            }
+
+            // Deleting a record we notified about lets the filter retract it; every
+            // other deletion is dropped here rather than queued
+            if op.action == "delete" {
+                if RETRACTABLE_COLLECTIONS.contains(&collection)
+                    && self.retractions.was_notified(&format!("at://{}/{}", repo, op.path))
+                {
+                    let event = BlueskyEvent {
+                        op: op.action.clone(),
+                        path: op.path.clone(),
+                        cid: String::new(),
+                        author: repo.to_string(),
+                        record: serde_json::Value::Null,
+                        timestamp: chrono::Utc::now().timestamp(),
+                        origin: origin.clone(),
+                    };
+                    if let Err(e) = self.event_sender.send(event).await {
+                        error!("Failed to queue delete event: {}", e);
+                    }
+                }
+                continue;
+            }
+
+            if op.action != "create" && op.action != "update" {
+                continue;
+            }
 
             let notification_type = match collection {
                 "app.bsky.feed.post" => "post",
                 "app.bsky.feed.like" => "like",
                 "app.bsky.graph.follow" => "follow",
                 "app.bsky.feed.repost" => "repost",
+                "app.bsky.graph.listitem" => "listitem",
                 _ => {
                     continue; // Skip unhandled types silently
                 }
             };
 
-            // Get the record CID (if present).
-            if let Some(cid_link) = &op.cid {
-                // Correctly convert CidLink to ipld_core::cid::Cid
-                let cid_bytes = cid_link.0.to_bytes();
-                let cid = match Cid::try_from(cid_bytes.as_slice()) {
-                    Ok(cid) => cid,
-                    Err(e) => {
-                        error!("Invalid CID format: {}", e);
-                        continue;
-                    }
-                };
+            // The decoder already logged records it couldn't find or decode
+            let (Some(cid), Some(record)) = (&op.cid, &op.record) else {
+                continue;
+            };
 
-                let mut record_block = Vec::new();
-                match car_store.read_block_into(cid, &mut record_block).await {
-                    Ok(()) => {
-                        // Deserialize the record with better error handling
-                        let record_data = match deserialize_record(collection, &record_block) {
-                            Ok(data) => {
-                                // Log record structure only at debug level to understand format
-                                if collection == "app.bsky.graph.follow" {
-                                    debug!("Follow record structure: {:?}", data);
-                                }
-                                data
-                            }
-                            Err(e) => {
-                                // Only log deserialization errors at debug level
-                                debug!("Failed to deserialize {}: {}", notification_type, e);
-                                continue;
-                            }
-                        };
-
-                        // Create event.
-                        let event = BlueskyEvent {
-                            op: op.action.clone(),
-                            path: op.path.clone(),
-                            cid: format!("{:?}", cid_link.0),
-                            author: commit.repo.to_string(),
-                            record: record_data,
-                            timestamp: chrono::Utc::now().timestamp(),
-                        };
-
-                        // Send the event without logging success
-                        if let Err(e) = self.event_sender.send(event).await {
-                            error!("Failed to queue {} event: {}", notification_type, e);
-                        }
-                    }
-                    Err(e) => {
-                        debug!(
-                            "Record block not found for CID: {:?}, error: {}",
-                            cid_link, e
-                        );
-                    }
-                }
-            }
-        }
+            let event = BlueskyEvent {
+                op: op.action.clone(),
+                path: op.path.clone(),
+                cid: cid.clone(),
+                author: repo.to_string(),
+                record: record.clone(),
+                timestamp: chrono::Utc::now().timestamp(),
+                origin: origin.clone(),
+            };
 
-        // Update cursor without logging every time
-        if let Err(e) = db::update_cursor(&self.db_pool, &commit.seq.to_string()).await {
-            error!("Failed to update cursor: {}", e);
+            // Send the event without logging success
+            match self.event_sender.send(event).await {
+                Ok(()) => queued += 1,
+                Err(e) => error!("Failed to queue {} event: {}", notification_type, e),
+            }
         }
-
-        Ok(())
+        queued
     }
 }
 
+fn set_disconnected(relay: &str) {
+    crate::metrics::FIREHOSE_CONNECTED.set(0.0);
+    crate::metrics::FIREHOSE_RELAY.with_label_values(&[relay]).set(0.0);
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run_firehose_consumer(
     bsky_service_url: String,
-    event_sender: mpsc::Sender<BlueskyEvent>,
+    event_sender: PipelineSender<BlueskyEvent>,
     db_pool: Pool<Postgres>,
+    relationship_manager: Arc<RelationshipManager>,
+    retractions: Retractions,
+    repo_state: RepoState,
+    replay_window: Duration,
+    gaps: GapReporter,
+    mut decoder: Decoder,
     mut shutdown: oneshot::Receiver<()>,
 ) -> Result<()> {
     info!("Starting firehose consumer");
 
-    // Maximum reconnection attempts
-    const MAX_RECONNECTS: u32 = 10;
-    // Base delay between reconnection attempts (will be exponentially increased)
-    let mut reconnect_delay = 1;
-    let mut reconnect_attempts = 0;
+    // Give up after 10 consecutive failed connects, backing off from 1s up to 60s.
+    // There is only one connection, so there is no herd to spread out with jitter.
+    let reconnect_policy = RetryPolicy::new("firehose_connect")
+        .max_attempts(10)
+        .backoff(Duration::from_secs(1), Duration::from_secs(60))
+        .jitter(false);
+    let mut reconnects = reconnect_policy.tracker();
+    // Set once a cursor is given up on, so reconnecting before the first new commit
+    // doesn't resume from it again
+    let mut abandoned_cursor: Option<String> = None;
 
     'outer: loop {
-        // Get last cursor from database for resuming
+        // Resume from the last stored cursor so events during a restart or reconnect
+        // aren't skipped. A cursor older than the replay window would only produce
+        // stale notifications, so start from the live tip instead.
         let last_cursor = match db::get_last_cursor(&db_pool).await {
-            Ok(cursor) => cursor,
+            Ok(Some(stored)) if abandoned_cursor.as_deref() == Some(stored.cursor.as_str()) => None,
+            Ok(Some(stored)) => {
+                let age = time::OffsetDateTime::now_utc() - stored.updated_at;
+                if age.unsigned_abs() > replay_window {
+                    warn!(
+                        "Stored cursor {} is {}s old, beyond the {}s replay window; starting from live",
+                        stored.cursor,
+                        age.whole_seconds(),
+                        replay_window.as_secs()
+                    );
+                    gaps.record(GapReason::ReplayWindow, &stored.cursor, stored.updated_at).await;
+                    abandoned_cursor = Some(stored.cursor);
+                    None
+                } else {
+                    Some(stored)
+                }
+            }
+            Ok(None) => None,
             Err(e) => {
                 error!("Failed to get last cursor: {}", e);
                 None
@@ -210,33 +392,36 @@ pub async fn run_firehose_consumer(
 
         info!(
             "Connecting to firehose, starting from cursor: {:?}",
-            last_cursor
+            last_cursor.as_ref().map(|stored| &stored.cursor)
         );
 
         // Create subscription with retry logic
-        let subscription_result =
-            RepoSubscription::new(&bsky_service_url, last_cursor.clone()).await;
+        let subscription_result = RepoSubscription::new(
+            &bsky_service_url,
+            last_cursor.as_ref().map(|stored| stored.cursor.clone()),
+        )
+        .await;
 
         let mut subscription = match subscription_result {
-            Ok(sub) => sub,
+            Ok(sub) => {
+                crate::metrics::FIREHOSE_CONNECTED.set(1.0);
+                crate::metrics::FIREHOSE_RELAY
+                    .with_label_values(&[&bsky_service_url])
+                    .set(1.0);
+                sub
+            }
             Err(e) => {
                 error!("Failed to connect to firehose: {}", e);
 
-                // Check if we've reached max reconnect attempts
-                reconnect_attempts += 1;
-                if reconnect_attempts >= MAX_RECONNECTS {
+                let Some(delay) = reconnects.next_delay() else {
                     return Err(anyhow!("Max reconnection attempts reached"));
-                }
-
-                // Exponential backoff
-                let delay = Duration::from_secs(reconnect_delay);
-                reconnect_delay = std::cmp::min(reconnect_delay * 2, 60); // Cap at 60 seconds
+                };
 
                 info!(
                     "Retrying in {} seconds (attempt {}/{})",
                     delay.as_secs(),
-                    reconnect_attempts,
-                    MAX_RECONNECTS
+                    reconnects.failures(),
+                    reconnects.max_attempts()
                 );
 
                 // Wait before retrying, but also check for shutdown signal
@@ -251,39 +436,27 @@ pub async fn run_firehose_consumer(
         };
 
         // Create handler
-        let handler = FirehoseHandler {
-            event_sender: event_sender.clone(),
-            db_pool: db_pool.clone(),
-        };
+        let handler = FirehoseHandler::new(
+            event_sender.clone(),
+            db_pool.clone(),
+            relationship_manager.clone(),
+            retractions.clone(),
+            repo_state.clone(),
+            last_cursor.is_some(),
+        );
+
+        // Frames still decoding from the last connection are discarded; the cursor
+        // hasn't moved past them, so they're replayed from it
+        decoder.clear();
 
         // Process incoming frames
         'inner: loop {
             tokio::select! {
-                Some(frame_result) = subscription.next() => {
+                Some(frame_result) = subscription.next(), if decoder.has_room() => {
                     match frame_result {
                         Ok(Frame::Message(Some(t), message)) => {
-                            if t.as_str() == "#commit" {
-                                // Parse commit from message
-                                match serde_ipld_dagcbor::from_reader::<Commit, _>(&message.body[..]) {
-                                    Ok(commit) => {
-                                        // Only log occasional commits for processing stats
-                                        if commit.seq % 5000 == 0 {
-                                            info!("Processing commit at sequence: {}", commit.seq);
-                                        }
-
-                                        // Handle commit without flooding logs
-                                        if let Err(e) = handler.handle_commit(&commit).await {
-                                            error!("Error handling commit: {}", e);
-                                        }
-
-                                        // Reset reconnect counter on successful processing
-                                        reconnect_attempts = 0;
-                                        reconnect_delay = 1;
-                                    },
-                                    Err(e) => {
-                                        error!("Failed to parse commit: {}", e);
-                                    }
-                                }
+                            if t.as_str() == "#commit" || t.as_str() == "#info" {
+                                decoder.push(t, message.body);
                             } else {
                                 // Only log non-commit messages
                                 debug!("Received message of type: {}", t);
@@ -302,6 +475,40 @@ pub async fn run_firehose_consumer(
                         }
                     }
                 },
+                // Decoded frames come back in the order they arrived
+                Some(decoded) = decoder.next(), if !decoder.is_empty() => {
+                    match decoded {
+                        Ok(Decoded::Commit(commit)) => {
+                            // Only log occasional commits for processing stats
+                            if commit.seq % 5000 == 0 {
+                                info!("Processing commit at sequence: {}", commit.seq);
+                            }
+
+                            // Handle commit without flooding logs
+                            if let Err(e) = handler.handle_commit(&commit).await {
+                                error!("Error handling commit: {}", e);
+                            }
+
+                            // Reset reconnect counter on successful processing
+                            reconnects.reset();
+                        },
+                        // The relay would replay from its oldest event, which is
+                        // still a gap; go to the live tip instead
+                        Ok(Decoded::Info(info)) if info.name == "OutdatedCursor" => {
+                            if let Some(stored) = &last_cursor {
+                                gaps.record(GapReason::OutdatedCursor, &stored.cursor, stored.updated_at).await;
+                                abandoned_cursor = Some(stored.cursor.clone());
+                            }
+                            break 'inner;
+                        },
+                        Ok(Decoded::Info(info)) => warn!(
+                            "Firehose info {}: {}",
+                            info.name,
+                            info.message.as_deref().unwrap_or("")
+                        ),
+                        Err(e) => error!("{:#}", e),
+                    }
+                },
                 _ = &mut shutdown => {
                     info!("Received shutdown signal, stopping firehose consumer");
                     break 'outer; // Break outer loop to exit
@@ -309,10 +516,13 @@ pub async fn run_firehose_consumer(
             }
         }
 
+        set_disconnected(&bsky_service_url);
+
         // If we reach here, the inner loop has broken, attempt to reconnect
         warn!("Connection interrupted, attempting to reconnect");
     }
 
+    set_disconnected(&bsky_service_url);
     info!("Firehose consumer stopped");
     Ok(())
 }
@@ -0,0 +1,157 @@
+// follows.rs
+// The accounts a user follows, for the "only people I follow" preference, which limits
+// likes, reposts and replies to authors the user follows, and for "priority from
+// mutuals". The firehose keeps the list current from the user's own follow records;
+// this reads the follows they made before, with com.atproto.repo.listRecords on their
+// PDS, when they turn either preference on.
+// Follow records are public, so no session is needed. Until the import finishes the
+// user may miss notifications from accounts they followed earlier.
+use anyhow::Result;
+use reqwest::Client as HttpClient;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::did_resolver::DidResolver;
+use crate::profile_resolver::ProfileResolver;
+use crate::relationship_manager::RelationshipManager;
+
+// listRecords' maximum page size
+const PAGE_SIZE: usize = 100;
+// Well past what people follow; an account over it gets the first this many
+const MAX_FOLLOWS: usize = 50_000;
+
+// (rkey, followed_did) pairs from one listRecords page, and the cursor for the next
+fn parse_page(body: &serde_json::Value) -> (Vec<(String, String)>, Option<String>) {
+    let follows = body
+        .get("records")
+        .and_then(|records| records.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|record| {
+            let rkey = record.get("uri")?.as_str()?.rsplit('/').next()?;
+            let subject = record.get("value")?.get("subject")?.as_str()?;
+            subject
+                .starts_with("did:")
+                .then(|| (rkey.to_string(), subject.to_string()))
+        })
+        .collect();
+    let cursor = body
+        .get("cursor")
+        .and_then(|cursor| cursor.as_str())
+        .filter(|cursor| !cursor.is_empty())
+        .map(str::to_string);
+    (follows, cursor)
+}
+
+// Every follow record in the user's repo
+pub async fn fetch(did_resolver: &DidResolver, did: &str) -> Result<Vec<(String, String)>> {
+    let pds = did_resolver.get_pds_endpoint(did).await?;
+    let http = HttpClient::builder().timeout(Duration::from_secs(10)).build()?;
+
+    let mut follows = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut request = http
+            .get(format!("{}/xrpc/com.atproto.repo.listRecords", pds.trim_end_matches('/')))
+            .query(&[
+                ("repo", did),
+                ("collection", "app.bsky.graph.follow"),
+                ("limit", &PAGE_SIZE.to_string()),
+            ]);
+        if let Some(cursor) = &cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+        let body: serde_json::Value = request.send().await?.error_for_status()?.json().await?;
+
+        let (page, next) = parse_page(&body);
+        let last_page = page.len() < PAGE_SIZE;
+        follows.extend(page);
+        if follows.len() >= MAX_FOLLOWS {
+            warn!(did = %did, "Follow import stopped at {} follows", MAX_FOLLOWS);
+            follows.truncate(MAX_FOLLOWS);
+            break;
+        }
+        match next {
+            Some(next) if !last_page => cursor = Some(next),
+            _ => break,
+        }
+    }
+    Ok(follows)
+}
+
+// Whether the user and the author follow each other. The user's side comes from the
+// local follow store, so most authors, the ones the user doesn't follow, cost no
+// AppView request; the author's side goes through the resolver's relationship cache.
+pub async fn is_mutual(
+    relationship_manager: &RelationshipManager,
+    profile_resolver: &ProfileResolver,
+    user_did: &str,
+    author: &str,
+) -> Result<bool> {
+    let follows_author = relationship_manager.follows(user_did, author).await;
+    mutual(follows_author, async {
+        let (_, followed_back) = profile_resolver.relationship(user_did, author).await?;
+        Ok(followed_back)
+    })
+    .await
+}
+
+async fn mutual(follows_author: bool, followed_back: impl Future<Output = Result<bool>>) -> Result<bool> {
+    if !follows_author {
+        return Ok(false);
+    }
+    followed_back.await
+}
+
+// Import in the background, so turning the preference on doesn't wait for the PDS
+pub fn spawn_import(did_resolver: Arc<DidResolver>, relationship_manager: Arc<RelationshipManager>, did: String) {
+    tokio::spawn(async move {
+        let follows = match fetch(&did_resolver, &did).await {
+            Ok(follows) => follows,
+            Err(e) => {
+                error!(did = %did, "Failed to fetch follows: {}", e);
+                return;
+            }
+        };
+        match relationship_manager.replace_follows(&did, &follows).await {
+            Ok(()) => info!(did = %did, count = follows.len(), "Imported follows"),
+            Err(e) => error!(did = %did, "Failed to save imported follows: {}", e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_record_keys_and_subjects() {
+        let body = json!({
+            "records": [
+                {
+                    "uri": "at://did:plc:alice/app.bsky.graph.follow/3kabc",
+                    "value": { "$type": "app.bsky.graph.follow", "subject": "did:plc:bob" }
+                },
+                { "uri": "at://did:plc:alice/app.bsky.graph.follow/3kdef", "value": { "subject": "bob.test" } },
+                { "uri": "at://did:plc:alice/app.bsky.graph.follow/3kghi", "value": {} }
+            ],
+            "cursor": "3kghi"
+        });
+        let (follows, cursor) = parse_page(&body);
+        assert_eq!(follows, vec![("3kabc".to_string(), "did:plc:bob".to_string())]);
+        assert_eq!(cursor.as_deref(), Some("3kghi"));
+        assert_eq!(parse_page(&json!({ "records": [], "cursor": "" })), (Vec::new(), None));
+    }
+
+    #[tokio::test]
+    async fn mutual_asks_about_the_author_only_when_the_user_follows_them() {
+        let unreachable = async { panic!("AppView asked about an author the user doesn't follow") };
+        assert!(!mutual(false, unreachable).await.unwrap());
+        assert!(mutual(true, async { Ok(true) }).await.unwrap());
+        assert!(!mutual(true, async { Ok(false) }).await.unwrap());
+        assert!(mutual(true, async { Err(anyhow::anyhow!("AppView down")) }).await.is_err());
+    }
+}
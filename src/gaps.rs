@@ -0,0 +1,122 @@
+// gaps.rs
+// Firehose gaps. When the consumer can't resume from its stored cursor, because the
+// cursor is older than the replay window or the relay reports it outdated, it starts
+// again from the live tip and the events in between are never seen. Each gap is
+// recorded with its time window and operators are alerted through the watchdog
+// webhook. The AppView verifier is then woken to count what the gap cost the users
+// who opted in to verification.
+use anyhow::Result;
+use sqlx::types::time::OffsetDateTime;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tracing::error;
+
+use crate::watchdog::{Alert, AlertWebhook};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapReason {
+    // The cursor was stored longer ago than FIREHOSE_REPLAY_WINDOW_MINUTES
+    ReplayWindow,
+    // The relay no longer has events that far back
+    OutdatedCursor,
+}
+
+impl GapReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GapReason::ReplayWindow => "replay_window",
+            GapReason::OutdatedCursor => "outdated_cursor",
+        }
+    }
+}
+
+pub struct Gap {
+    pub id: i64,
+    pub started_at: OffsetDateTime,
+    pub ended_at: OffsetDateTime,
+}
+
+#[derive(Clone)]
+pub struct GapReporter {
+    db_pool: Pool<Postgres>,
+    webhook: AlertWebhook,
+    reconcile: Arc<Notify>,
+}
+
+impl GapReporter {
+    pub fn new(db_pool: Pool<Postgres>, webhook: AlertWebhook, reconcile: Arc<Notify>) -> Self {
+        Self {
+            db_pool,
+            webhook,
+            reconcile,
+        }
+    }
+
+    // `since` is when the abandoned cursor was last advanced, so the gap runs from
+    // the last event processed until now
+    pub async fn record(&self, reason: GapReason, cursor: &str, since: OffsetDateTime) {
+        let minutes = (OffsetDateTime::now_utc() - since).whole_minutes();
+        error!(
+            reason = reason.as_str(),
+            cursor = %cursor,
+            minutes,
+            "Firehose cursor abandoned, resuming from the live tip"
+        );
+        crate::metrics::FIREHOSE_GAPS
+            .with_label_values(&[reason.as_str()])
+            .inc();
+
+        if let Err(e) = sqlx::query!(
+            r#"
+            INSERT INTO firehose_gaps (reason, cursor, started_at, ended_at)
+            VALUES ($1, $2, $3, NOW())
+            "#,
+            reason.as_str(),
+            cursor,
+            since
+        )
+        .execute(&self.db_pool)
+        .await
+        {
+            error!("Failed to record firehose gap: {}", e);
+        }
+
+        self.webhook
+            .notify(
+                Alert::FirehoseGap,
+                "firing",
+                &format!(
+                    "Cursor {} abandoned ({}); about {} minutes of events were skipped and will be reconciled against the AppView",
+                    cursor,
+                    reason.as_str(),
+                    minutes
+                ),
+            )
+            .await;
+        self.reconcile.notify_one();
+    }
+}
+
+pub async fn pending(pool: &Pool<Postgres>) -> Result<Vec<Gap>> {
+    let gaps = sqlx::query_as!(
+        Gap,
+        "SELECT id, started_at, ended_at FROM firehose_gaps WHERE reconciled_at IS NULL ORDER BY id"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(gaps)
+}
+
+pub async fn mark_reconciled(pool: &Pool<Postgres>, id: i64, missed: i32) -> Result<()> {
+    sqlx::query!(
+        "UPDATE firehose_gaps SET reconciled_at = NOW(), missed = $2 WHERE id = $1",
+        id,
+        missed
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+// internal.rs
+// Endpoints for sibling services (dashboards, workers) that share this deployment's
+// caches and push pipeline. Authenticated with INTERNAL_API_TOKEN and disabled when it
+// is not set.
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::{debug, error, warn};
+
+use crate::api::ApiState;
+use crate::custom_notifications::{CustomNotification, Delivery, MAX_BATCH};
+use crate::post_resolver::PostContent;
+
+// Matches the AppView getPosts limit
+const MAX_POST_URIS: usize = 25;
+
+#[derive(Deserialize)]
+struct PostsQuery {
+    // Comma-separated at:// URIs
+    uris: String,
+}
+
+#[derive(Serialize)]
+struct PostsResponse {
+    // Keyed by URI; posts that could not be resolved are omitted
+    posts: HashMap<String, PostContent>,
+}
+
+#[derive(Deserialize)]
+struct NotifyRequest {
+    notifications: Vec<CustomNotification>,
+}
+
+#[derive(Serialize)]
+struct NotifyResponse {
+    // One per notification, in request order, so callers retry only what failed
+    results: Vec<NotifyResult>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum NotifyResult {
+    Delivered(Delivery),
+    // The recipient's devices couldn't be read; nothing was queued
+    Failed { error: &'static str },
+}
+
+pub fn create_internal_router(state: Arc<ApiState>) -> Router<Arc<ApiState>> {
+    Router::new()
+        .route("/posts", get(get_posts))
+        .route("/notify", post(notify))
+        .route_layer(middleware::from_fn_with_state(state, require_internal_token))
+}
+
+async fn require_internal_token(
+    State(state): State<Arc<ApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let expected = match &state.internal_api_token {
+        Some(token) => token,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match provided {
+        Some(token) if constant_time_eq::constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            next.run(request).await
+        }
+        _ => {
+            warn!(path = %request.uri().path(), "Unauthorized internal request");
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+    }
+}
+
+// Serve from the post cache, fetching (and warming the cache with) any misses
+async fn get_posts(
+    State(state): State<Arc<ApiState>>,
+    Query(query): Query<PostsQuery>,
+) -> Result<Json<PostsResponse>, StatusCode> {
+    let uris: Vec<&str> = query
+        .uris
+        .split(',')
+        .map(str::trim)
+        .filter(|uri| !uri.is_empty())
+        .collect();
+
+    if uris.is_empty() || uris.len() > MAX_POST_URIS || uris.iter().any(|uri| !uri.starts_with("at://")) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let results = futures::future::join_all(uris.iter().map(|uri| state.post_resolver.get_post(uri))).await;
+
+    let mut posts = HashMap::new();
+    for (uri, result) in uris.into_iter().zip(results) {
+        match result {
+            Ok(post) => {
+                posts.insert(uri.to_string(), post);
+            }
+            Err(e) => debug!(uri = %uri, "Failed to resolve post: {}", e),
+        }
+    }
+
+    Ok(Json(PostsResponse { posts }))
+}
+
+// Run custom notifications from first-party services through the regular pipeline
+async fn notify(
+    State(state): State<Arc<ApiState>>,
+    Json(req): Json<NotifyRequest>,
+) -> Result<Json<NotifyResponse>, StatusCode> {
+    if req.notifications.is_empty()
+        || req.notifications.len() > MAX_BATCH
+        || !req.notifications.iter().all(CustomNotification::is_valid)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut results = Vec::with_capacity(req.notifications.len());
+    for notification in &req.notifications {
+        match crate::custom_notifications::deliver(&state, notification).await {
+            Ok(delivery) => results.push(NotifyResult::Delivered(delivery)),
+            Err(e) => {
+                error!(did = %notification.did, "Error delivering custom notification: {}", e);
+                results.push(NotifyResult::Failed { error: "internal error" });
+            }
+        }
+    }
+
+    Ok(Json(NotifyResponse { results }))
+}
@@ -0,0 +1,83 @@
+// lists.rs
+// List and starter pack additions. A listitem record names the list and the account
+// added to it; the list's purpose, looked up on the AppView, decides how the addition
+// is announced, and additions to moderation lists are never announced. A starter pack
+// is a record pointing at a reference list, so starter pack records from the firehose
+// are kept (see repo_state.rs) to link additions to the pack. The first members of a
+// new pack are added before its record exists and get a link to the list instead.
+use anyhow::Result;
+use sqlx::{Pool, Postgres};
+
+use crate::profile_resolver::ProfileResolver;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListPurpose {
+    Curate,
+    Moderation,
+    // Reference lists back starter packs
+    StarterPack,
+}
+
+impl ListPurpose {
+    // Unknown purposes are announced like curated lists
+    pub fn parse(purpose: &str) -> Self {
+        match purpose.rsplit('#').next() {
+            Some("modlist") => ListPurpose::Moderation,
+            Some("referencelist") => ListPurpose::StarterPack,
+            _ => ListPurpose::Curate,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListTarget {
+    pub purpose: ListPurpose,
+    pub name: String,
+    // Deep link: the starter pack when we know it, otherwise the list
+    pub uri: String,
+}
+
+// The list a listitem record adds its subject to
+pub fn list_uri(record: &serde_json::Value) -> Option<&str> {
+    record.get("list").and_then(|list| list.as_str())
+}
+
+pub async fn resolve(
+    pool: &Pool<Postgres>,
+    profile_resolver: &ProfileResolver,
+    list_uri: &str,
+) -> Result<ListTarget> {
+    let starter_pack = sqlx::query!(
+        "SELECT uri, name FROM starter_packs WHERE list_uri = $1 LIMIT 1",
+        list_uri
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(pack) = starter_pack {
+        return Ok(ListTarget {
+            purpose: ListPurpose::StarterPack,
+            name: pack.name,
+            uri: pack.uri,
+        });
+    }
+
+    let list = profile_resolver.get_list(list_uri).await?;
+    Ok(ListTarget {
+        purpose: ListPurpose::parse(&list.purpose),
+        name: list.name,
+        uri: list_uri.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_list_purposes() {
+        assert_eq!(ListPurpose::parse("app.bsky.graph.defs#modlist"), ListPurpose::Moderation);
+        assert_eq!(ListPurpose::parse("app.bsky.graph.defs#referencelist"), ListPurpose::StarterPack);
+        assert_eq!(ListPurpose::parse("app.bsky.graph.defs#curatelist"), ListPurpose::Curate);
+        assert_eq!(ListPurpose::parse("app.bsky.graph.defs#somethingnew"), ListPurpose::Curate);
+    }
+}
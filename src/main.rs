@@ -1,27 +1,92 @@
+// metrics.rs declares more statics than lazy_static! expands at the default limit
+#![recursion_limit = "256"]
+
+mod activity_digest;
+mod aggregation;
+mod backfill;
+mod build_info;
+mod admin;
 mod api;
 mod apns;
+mod atom_feed;
+mod archive;
+mod bench;
+mod cache_sync;
+mod channel;
 mod config;
+mod content_fallback;
+mod cooldown;
+mod copy_script;
 mod crypto; // Add the new crypto module
+mod decoder;
+mod custom_notifications;
 mod db;
+mod dedup;
+mod delivery_policy;
+mod default_preferences;
+mod device_health;
+mod excerpt;
+mod experiments;
+mod expiry;
+mod fieldmask;
+mod feature_flags;
+mod feeds;
 mod filter;
+mod internal;
+mod lists;
 mod firehose;
+mod follows;
+mod gaps;
 mod logging;
+mod maintenance;
+mod memory_guard;
+mod plugins;
 mod models;
+mod muted_words;
 mod stream;
+mod watchdog;
 mod subscription;
+mod tenant;
+mod token_cleanup;
+mod user_trace;
+mod verification;
+mod did_prefetch;
 mod did_resolver;
+mod dms;
+mod offboarding;
+mod payload_keys;
+mod portability;
+mod presets;
+mod privacy;
+mod post_subscriptions;
 mod post_resolver;
+mod profile_resolver;
+mod quiet_hours;
+mod quota;
+mod ranking;
+mod rate_limit;
 mod metrics;
+mod registration;
+mod repo_state;
+mod rehash_backfill;
 mod relationship_manager;
+mod reporting;
+mod retraction;
+mod retry;
+mod self_test;
+mod server_preferences;
+mod service_auth;
+mod shared_cache;
+mod shutdown;
 
 use tracing::error;
 use anyhow::Result;
 use std::sync::Arc;
 use tokio::{
     signal,
-    sync::{mpsc, oneshot},
+    sync::oneshot,
 };
-use tracing::info;
+use tracing::{info, warn};
 use relationship_manager::RelationshipManager;
 
 fn main() -> Result<()> {
@@ -40,6 +105,9 @@ fn main() -> Result<()> {
         .unwrap();
     
     runtime.block_on(async {
+        // Reported as uptime on /admin/version
+        let started_at = time::OffsetDateTime::now_utc();
+
         // Initialize logging first thing
         logging::setup_logging();
 
@@ -51,11 +119,94 @@ fn main() -> Result<()> {
         // Load configuration
         let config = config::Config::from_env()?;
 
+        // Readiness check for deployment pipelines; exits instead of starting the service
+        if std::env::args().any(|arg| arg == "--self-test") {
+            let passed = self_test::run(&config).await;
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+
         // Initialize database connection pool
         let db_pool = db::init_db_pool(&config.database_url).await?;
 
+        // Tenant management subcommands run against the migrated DB and exit
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        if args.first().map(String::as_str) == Some("tenant") {
+            tenant::run_command(&db_pool, &args[1..]).await?;
+            return Ok(());
+        }
+        if args.first().map(String::as_str) == Some("rehash-backfill") {
+            rehash_backfill::run_command(&db_pool, &args[1..]).await?;
+            return Ok(());
+        }
+        if args.first().map(String::as_str) == Some("offboard") {
+            offboarding::run_command(&db_pool, &args[1..]).await?;
+            return Ok(());
+        }
+        if args.first().map(String::as_str) == Some("bench-load") {
+            bench::run_command(&config, &db_pool, &args[1..]).await?;
+            return Ok(());
+        }
+        if args.first().map(String::as_str) == Some("bench-decode") {
+            bench::run_decode_command(&config, &args[1..]).await?;
+            return Ok(());
+        }
+
+        // Cache tier shared with the other replicas, when configured
+        let shared_cache = shared_cache::SharedCache::connect(config.shared_cache_url.as_deref()).await?;
+
         // Initialize relationship manager with moka cache
-        let relationship_manager = Arc::new(RelationshipManager::new(db_pool.clone()));
+        let relationship_manager = Arc::new(RelationshipManager::new(db_pool.clone(), shared_cache.clone()));
+
+        // Settings writes from any replica invalidate the in-memory caches
+        let cache_generation = Arc::new(cache_sync::CacheGeneration::default());
+        tokio::spawn(cache_sync::run_listener(
+            db_pool.clone(),
+            cache_generation.clone(),
+            relationship_manager.clone(),
+        ));
+
+        // Which DIDs the firehose records blocks and follows for. Device changes update it
+        // through the invalidation listener; the reload catches anything it missed.
+        relationship_manager.load_registered().await?;
+        let relationship_manager_clone = relationship_manager.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(300));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = relationship_manager_clone.load_registered().await {
+                    tracing::error!("Error reloading registered users: {}", e);
+                }
+            }
+        });
+
+        // Load feature flags and keep them fresh
+        let feature_flags = Arc::new(feature_flags::FeatureFlags::new(db_pool.clone()).await?);
+        let feature_flags_clone = feature_flags.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                if let Err(e) = feature_flags_clone.refresh().await {
+                    tracing::error!("Error refreshing feature flags: {}", e);
+                }
+            }
+        });
+
+        // Copy experiments build on the feature flags for exposure
+        let experiments = Arc::new(
+            experiments::Experiments::new(db_pool.clone(), feature_flags.clone()).await?,
+        );
+        let experiments_clone = experiments.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                if let Err(e) = experiments_clone.refresh().await {
+                    tracing::error!("Error refreshing copy experiments: {}", e);
+                }
+            }
+        });
 
         // One-time cleanup to fix existing cursor issue
 info!("Running one-time cleanup of firehose cursor table");
@@ -75,88 +226,326 @@ if let Err(e) = db::cleanup_old_cursors(&db_pool, 1).await {
             }
         });
 
-                // Spawn cursor cleanup task
+        let did_resolver = Arc::new(
+            did_resolver::DidResolver::new(db_pool.clone(), 24, shared_cache.clone())
+                .with_plc_directory(&config.plc_directory_url),
+        );
+
+        // Keep the DIDs behind bursts of events resolved ahead of the deliveries
+        if let Some(prefetcher) = did_prefetch::DidPrefetcher::from_config(&config, did_resolver.clone()) {
+            tokio::spawn(prefetcher.run());
+        }
+
+        // After initializing did_resolver
+        let post_resolver = Arc::new(post_resolver::PostResolver::new(
+            db_pool.clone(),
+            60, // 60 minute TTL
+            std::env::var("BSKY_API_URL").unwrap_or_else(|_| "https://public.api.bsky.app".to_string()),
+            shared_cache.clone(),
+        ));
+
+        // Cache pruning, purges and ANALYZE, all at the scheduled low-traffic time
+        let maintenance = Arc::new(maintenance::Maintenance::new(
+            db_pool.clone(),
+            did_resolver.clone(),
+            post_resolver.clone(),
+            &config,
+        ));
+        tokio::spawn(maintenance.clone().run(config.maintenance_schedule));
+
+        // Profile metadata for author thresholds (6 hour TTL)
+        let profile_resolver = Arc::new(
+            profile_resolver::ProfileResolver::new(config.bsky_api_url.clone(), 360)
+                .with_hosted_handle_suffixes(config.hosted_handle_suffixes.clone()),
+        );
+
+        // Alert operators when the pipeline stalls
+        tokio::spawn(watchdog::Watchdog::from_config(&config).run());
+
+        // Firehose gaps wake the verifier to reconcile the skipped window
+        let gap_signal = Arc::new(tokio::sync::Notify::new());
+        let gap_reporter = gaps::GapReporter::new(
+            db_pool.clone(),
+            watchdog::AlertWebhook::from_config(&config),
+            gap_signal.clone(),
+        );
+
+        // Optional long-term archive of notification history to object storage
+        if let Some(archiver) = archive::Archiver::from_config(&config, db_pool.clone()) {
+            info!("Archiving notification history to object storage");
+            tokio::spawn(archiver.run(tokio::time::Duration::from_secs(
+                config.archive_interval_minutes * 60,
+            )));
+        }
+
+        // Watch RSS and shrink caches / shed load before the OOM killer steps in
+        let memory_guard = Arc::new(memory_guard::MemoryGuard::new(
+            config.memory_soft_limit_mb,
+            config.memory_hard_limit_mb,
+            did_resolver.clone(),
+            post_resolver.clone(),
+            profile_resolver.clone(),
+            relationship_manager.clone(),
+        ));
+        tokio::spawn(memory_guard.clone().run(tokio::time::Duration::from_secs(10)));
+
+        // Initialize APNs client
+        let apns_client = Arc::new(apns::ApnsClient::new(
+            &config.apns_key_path,
+            &config.apns_key_id,
+            &config.apns_team_id,
+            config.apns_production,
+            &config.apns_hosts,
+            &config.apns_topic,
+            config.apns_push_settings.clone(),
+            config.apns_topic_overrides.clone(),
+        )?);
+        if config.apns_production {
+            apns_client.remember_sandbox_tokens(db::get_sandbox_device_tokens(&db_pool).await?);
+        }
+
+        // Operator filtering plugins
+        let plugins = Arc::new(plugins::PluginHost::load(
+            &config.plugin_paths,
+            config.plugin_fuel,
+            config.plugin_memory_limit_mb,
+        )?);
+
+        let copy_script = match &config.copy_script_path {
+            Some(path) => Some(Arc::new(copy_script::CopyScript::load(
+                path,
+                std::time::Duration::from_millis(config.copy_script_timeout_ms),
+                config.copy_script_max_operations,
+            )?)),
+            None => None,
+        };
+
+        // Per-tenant quotas: refresh who is over quota every minute
+        let quota = Arc::new(quota::QuotaTracker::new(db_pool.clone()));
+        let quota_clone = quota.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                if let Err(e) = quota_clone.refresh().await {
+                    tracing::error!("Error refreshing tenant quotas: {}", e);
+                }
+            }
+        });
+
+        // Rank notification types by open rate for the filter's load shedding
+        let ranking = ranking::Ranking::default();
+        let ranking_clone = ranking.clone();
         let db_pool_clone = db_pool.clone();
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600)); // hourly
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(900));
             loop {
                 interval.tick().await;
-                // Keep only 1 day of history
-                if let Err(e) = db::cleanup_old_cursors(&db_pool_clone, 1).await {
-                    tracing::error!("Error cleaning up cursor history: {}", e);
+                if let Err(e) = ranking_clone.refresh(&db_pool_clone).await {
+                    tracing::error!("Error refreshing open-rate ranking: {}", e);
                 }
             }
         });
 
-        let did_resolver = Arc::new(did_resolver::DidResolver::new(db_pool.clone(), 24));
+        // Create channels for notification pipeline
+        let (event_sender, event_receiver) = channel::channel(
+            "events",
+            config.event_channel_capacity,
+            config.event_channel_overflow,
+            Some(db_pool.clone()),
+        );
+        let (notification_sender, notification_receiver) = channel::channel(
+            "notifications",
+            config.notification_channel_capacity,
+            config.notification_channel_overflow,
+            Some(db_pool.clone()),
+        );
+        event_sender.spawn_outbox_drain(tokio::time::Duration::from_secs(1));
+        notification_sender.spawn_outbox_drain(tokio::time::Duration::from_secs(1));
 
-        let did_resolver_clone = did_resolver.clone();
+        // Flush digests for over-quota tenants
+        let quota_clone = quota.clone();
+        let digest_sender = notification_sender.clone();
+        let digest_interval = config.quota_digest_interval_minutes;
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600)); // hourly
+            let mut interval =
+                tokio::time::interval(tokio::time::Duration::from_secs(digest_interval * 60));
             loop {
                 interval.tick().await;
-                if let Err(e) = did_resolver_clone.cleanup_expired().await {
-                    tracing::error!("Error cleaning up DID cache: {}", e);
+                if let Err(e) = quota_clone.flush_digests(&digest_sender).await {
+                    tracing::error!("Error flushing quota digests: {}", e);
                 }
             }
         });
 
-        // After initializing did_resolver
-        let post_resolver = Arc::new(post_resolver::PostResolver::new(
-            db_pool.clone(),
-            60, // 60 minute TTL
-            std::env::var("BSKY_API_URL").unwrap_or_else(|_| "https://public.api.bsky.app".to_string())
-        ));
+        // Roll up likes and reposts for users who opted in to activity digests
+        let db_pool_clone = db_pool.clone();
+        let activity_digest_sender = notification_sender.clone();
+        let activity_digest_interval = config.activity_digest_interval_minutes;
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
+                activity_digest_interval * 60,
+            ));
+            loop {
+                interval.tick().await;
+                if let Err(e) = activity_digest::flush(&db_pool_clone, &activity_digest_sender).await {
+                    tracing::error!("Error flushing activity digests: {}", e);
+                }
+            }
+        });
 
-        // Start post_resolver cleanup task
-        let post_resolver_clone = post_resolver.clone();
+        // Summarize notifications held back during quiet hours once each window closes
+        let db_pool_clone = db_pool.clone();
+        let summary_sender = notification_sender.clone();
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600)); // hourly
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
             loop {
                 interval.tick().await;
-                if let Err(e) = post_resolver_clone.cleanup_expired().await {
-                    tracing::error!("Error cleaning up post cache: {}", e);
+                if let Err(e) = quiet_hours::flush_summaries(&db_pool_clone, &summary_sender).await {
+                    tracing::error!("Error flushing quiet hours summaries: {}", e);
                 }
             }
         });
 
-        // Initialize APNs client
-        let apns_client = apns::ApnsClient::new(
-            &config.apns_key_path,
-            &config.apns_key_id,
-            &config.apns_team_id,
-            config.apns_production,
-        )?;
+        // Poll subscribed custom feeds for new posts
+        let feed_poller = feeds::FeedPoller::new(
+            db_pool.clone(),
+            config.bsky_api_url.clone(),
+            did_resolver.clone(),
+            post_resolver.clone(),
+            relationship_manager.clone(),
+            notification_sender.clone(),
+            excerpt::ExcerptLimits::from_config(&config),
+        );
+        tokio::spawn(feed_poller.run(
+            tokio::time::Duration::from_secs(config.feed_poll_interval_secs),
+            tokio::time::Duration::from_secs(config.feed_digest_interval_minutes * 60),
+        ));
 
-        // Create channels for notification pipeline
-        let (event_sender, event_receiver) = mpsc::channel(1000);
-        let (notification_sender, notification_receiver) = mpsc::channel(1000);
+        // Push direct messages for users who connected their chat account
+        if config.dm_poll_interval_secs > 0 {
+            let dm_poller = dms::DmPoller::new(
+                db_pool.clone(),
+                did_resolver.clone(),
+                relationship_manager.clone(),
+                notification_sender.clone(),
+                config.chat_service_did.clone(),
+                excerpt::ExcerptLimits::from_config(&config),
+            );
+            tokio::spawn(dm_poller.run(tokio::time::Duration::from_secs(
+                config.dm_poll_interval_secs,
+            )));
+        }
+
+        // Compare what we delivered with the AppView for users who opted in. Firehose
+        // gaps are reconciled even when the periodic comparison is off.
+        let verifier = verification::Verifier::new(
+            db_pool.clone(),
+            did_resolver.clone(),
+            config.appview_service_did.clone(),
+            watchdog::AlertWebhook::from_config(&config),
+            gap_signal.clone(),
+        );
+        tokio::spawn(verifier.run(
+            (config.verification_interval_hours > 0)
+                .then(|| tokio::time::Duration::from_secs(config.verification_interval_hours * 3600)),
+        ));
 
         // Create shutdown signal
         let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let (api_shutdown_tx, api_shutdown_rx) = oneshot::channel::<()>();
+        // Kept to close the pipeline's channels at shutdown, as timers hold senders too
+        let events = event_sender.clone();
+        let notifications = notification_sender.clone();
+
+        // Records recently notified about; the firehose only passes on their deletions
+        let retractions = retraction::Retractions::from_config(&config);
+        // Decoded records the filter found relevant, reused if their commit is replayed
+        let records = decoder::RecordCache::from_config(&config);
+        // Declarations and starter packs from the firehose, written in batches
+        let repo_state = repo_state::RepoState::new(db_pool.clone());
+        tokio::spawn(repo_state.clone().run());
+
+        // Admin backfills queue their events next to the firehose's
+        let backfills = backfill::Backfills::new(
+            event_sender.clone(),
+            db_pool.clone(),
+            relationship_manager.clone(),
+            retractions.clone(),
+            repo_state.clone(),
+            did_resolver.clone(),
+        );
 
         // Spawn firehose consumer task
         let firehose_handle = tokio::spawn(firehose::run_firehose_consumer(
             config.bsky_service_url.clone(),
             event_sender,
             db_pool.clone(),
+            relationship_manager.clone(),
+            retractions.clone(),
+            repo_state.clone(),
+            tokio::time::Duration::from_secs(config.firehose_replay_window_minutes * 60),
+            gap_reporter,
+            decoder::Decoder::new(config.firehose_decode_concurrency, records.clone()),
             shutdown_rx,
         ));
 
+        // Send the combined likes and reposts once each aggregation window closes
+        let aggregator = aggregation::Aggregator::from_config(&config);
+        tokio::spawn(aggregator.clone().run(notification_sender.clone()));
+
+        // Per-user pipeline traces, switched on through the admin API
+        let user_trace = user_trace::UserTrace::default();
+
         let filter_handle = tokio::spawn(filter::run_event_filter(
             event_receiver,
-            notification_sender,
-            db_pool.clone(),
-            did_resolver.clone(),
-            post_resolver.clone(),
-            relationship_manager.clone(), // Add relationship manager
+            notification_sender.clone(),
+            filter::FilterContext {
+                db_pool: db_pool.clone(),
+                did_resolver: did_resolver.clone(),
+                post_resolver: post_resolver.clone(),
+                relationship_manager: relationship_manager.clone(),
+                profile_resolver: profile_resolver.clone(),
+                experiments: experiments.clone(),
+                fanout_limits: filter::FanoutLimits::from_config(&config),
+                content_fallbacks: content_fallback::ContentFallbacks::from_config(&config),
+                policy: delivery_policy::DeliveryPolicy::new(
+                    db_pool.clone(),
+                    relationship_manager.clone(),
+                    profile_resolver.clone(),
+                    cooldown::Cooldowns::from_config(&config),
+                    quota.clone(),
+                ),
+                aggregator: aggregator.clone(),
+                dedup: dedup::EventDedup::from_config(&config),
+                retractions,
+                excerpts: excerpt::ExcerptLimits::from_config(&config),
+                memory_guard: memory_guard.clone(),
+                plugins,
+                copy_script,
+                cache_generation,
+                trace: user_trace.clone(),
+                records,
+                ranking,
+                count_unread: true,
+            },
         ));
 
         // Spawn notification sender task
+        let device_health = Arc::new(
+            device_health::DeviceHealth::new(
+                db_pool.clone(),
+                device_health::QuarantinePolicy::from_config(&config),
+            )
+            .await?,
+        );
         let apns_handle = tokio::spawn(apns::run_notification_sender(
             notification_receiver,
-            apns_client,
+            apns_client.clone(),
             db_pool.clone(),
+            device_health.clone(),
+            token_cleanup::TokenCleanupQueue::spawn(db_pool.clone()),
+            user_trace.clone(),
         ));
 
         // Spawn API server
@@ -164,7 +553,42 @@ if let Err(e) = db::cleanup_old_cursors(&db_pool, 1).await {
         let api_state = Arc::new(api::ApiState {
             db_pool: db_pool_clone,
             relationship_manager: relationship_manager.clone(), // Add relationship manager
+            admin_api_token: config.admin_api_token.clone(),
+            internal_api_token: config.internal_api_token.clone(),
+            post_resolver: post_resolver.clone(),
+            did_resolver: did_resolver.clone(),
+            reporter: Arc::new(reporting::Reporter::new(
+                did_resolver.clone(),
+                config.report_service_did.clone(),
+                config.report_account.clone(),
+            )),
+            appview_service_did: config.appview_service_did.clone(),
+            service_did: config.service_did.clone(),
+            feature_flags: feature_flags.clone(),
+            experiments: experiments.clone(),
+            apns_client: apns_client.clone(),
+            apns_topic_validation: config.apns_topic_validation,
+            device_health,
+            notification_sender,
+            multi_tenant: config.multi_tenant,
+            registration_mode: config.registration_mode,
+            default_preferences: config.default_preferences.clone(),
+            quota: quota.clone(),
+            device_verification_window_secs: config
+                .device_verification_enabled
+                .then_some(config.device_verification_window_secs),
+            maintenance,
+            rate_limiter: Arc::new(rate_limit::RateLimiter::new(
+                config.rate_limits.clone(),
+                config.trusted_proxy_hops,
+            )),
+            user_trace,
+            backfills,
+            started_at,
         });
+        if config.service_did.is_none() {
+            warn!("SERVICE_DID is not set; device registrations are accepted without service auth");
+        }
         let api_router = api::create_api_router(api_state);
 
         let api_handle = tokio::spawn(async move {
@@ -174,7 +598,16 @@ if let Err(e) = db::cleanup_old_cursors(&db_pool, 1).await {
             info!("Starting API server on {}", addr);
             
             let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-            axum::serve(listener, api_router).await.unwrap();
+            // Peer addresses feed the per-IP rate limits
+            axum::serve(
+                listener,
+                api_router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(async {
+                let _ = api_shutdown_rx.await;
+            })
+            .await
+            .unwrap();
         });
 
         // Handle graceful shutdown
@@ -184,12 +617,34 @@ if let Err(e) = db::cleanup_old_cursors(&db_pool, 1).await {
             }
         }
 
-        // Send shutdown signal to tasks
-        let _ = shutdown_tx.send(());
-
-        // Wait for ALL tasks to complete, including api_handle
-        let _ = tokio::join!(firehose_handle, filter_handle, apns_handle, api_handle);
+        // Stop taking new work first, then let each stage drain what's queued for it
+        let deadlines = config.shutdown_deadlines;
+        let dropped = shutdown::run(vec![
+            shutdown::Stage::new("api", deadlines.api, api_handle).on_stop(move || {
+                let _ = api_shutdown_tx.send(());
+            }),
+            shutdown::Stage::new("firehose", deadlines.firehose, firehose_handle).on_stop(move || {
+                let _ = shutdown_tx.send(());
+            }),
+            shutdown::Stage::new("filter", deadlines.filter, filter_handle)
+                .on_stop({
+                    let events = events.clone();
+                    move || events.close()
+                })
+                .with_queue(move || events.queued()),
+            shutdown::Stage::new("sender", deadlines.sender, apns_handle)
+                .on_stop({
+                    let notifications = notifications.clone();
+                    move || notifications.close()
+                })
+                .with_queue(move || notifications.queued()),
+        ])
+        .await;
+        repo_state.flush_logged().await;
 
+        if dropped {
+            return Err(anyhow::anyhow!("Shutdown aborted tasks with work still queued"));
+        }
         info!("Shutdown complete");
         Ok(())
     })
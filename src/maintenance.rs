@@ -0,0 +1,228 @@
+// maintenance.rs
+// Housekeeping that used to run as separate hourly tasks: expired DID and post cache
+// rows, old firehose cursors, stale outbox items, old delivery log entries,
+// soft-deleted and never-verified devices, then ANALYZE on the tables those deletes churn. It all runs together at a
+// low-traffic time set by MAINTENANCE_SCHEDULE ("sun 04:00" or "daily 04:00", UTC).
+// The last run's report is logged and served on the admin API.
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use time::{OffsetDateTime, Weekday};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::did_resolver::DidResolver;
+use crate::post_resolver::PostResolver;
+
+pub const DEFAULT_MAINTENANCE_SCHEDULE: &str = "sun 04:00";
+
+// Tables whose statistics go stale fastest after the deletes above
+const ANALYZE_TABLES: &[&str] = &[
+    "user_devices",
+    "notification_preferences",
+    "notification_history",
+    "channel_outbox",
+    "delivery_log",
+    "did_cache",
+    "post_cache",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceSchedule {
+    // None runs every day
+    weekday: Option<Weekday>,
+    at: time::Time,
+}
+
+impl MaintenanceSchedule {
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (day, at) = spec.trim().split_once(' ')?;
+        let weekday = match day.to_lowercase().as_str() {
+            "daily" => None,
+            "mon" => Some(Weekday::Monday),
+            "tue" => Some(Weekday::Tuesday),
+            "wed" => Some(Weekday::Wednesday),
+            "thu" => Some(Weekday::Thursday),
+            "fri" => Some(Weekday::Friday),
+            "sat" => Some(Weekday::Saturday),
+            "sun" => Some(Weekday::Sunday),
+            _ => return None,
+        };
+        let at = crate::quiet_hours::parse_time(at.trim())?;
+        Some(Self { weekday, at })
+    }
+
+    // The first scheduled time strictly after `now`
+    pub fn next_after(&self, now: OffsetDateTime) -> OffsetDateTime {
+        let mut next = now.replace_time(self.at);
+        if next <= now {
+            next += time::Duration::days(1);
+        }
+        if let Some(weekday) = self.weekday {
+            while next.weekday() != weekday {
+                next += time::Duration::days(1);
+            }
+        }
+        next
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceStep {
+    pub name: &'static str,
+    // Rows removed, where the step counts them
+    pub affected: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceReport {
+    #[serde(with = "time::serde::rfc3339")]
+    pub started_at: OffsetDateTime,
+    pub duration_ms: u64,
+    pub steps: Vec<MaintenanceStep>,
+}
+
+pub struct Maintenance {
+    db_pool: Pool<Postgres>,
+    did_resolver: Arc<DidResolver>,
+    post_resolver: Arc<PostResolver>,
+    device_retention_days: i32,
+    outbox_retention_hours: i64,
+    delivery_log_retention_days: i32,
+    last_report: RwLock<Option<MaintenanceReport>>,
+}
+
+impl Maintenance {
+    pub fn new(
+        db_pool: Pool<Postgres>,
+        did_resolver: Arc<DidResolver>,
+        post_resolver: Arc<PostResolver>,
+        config: &crate::config::Config,
+    ) -> Self {
+        Self {
+            db_pool,
+            did_resolver,
+            post_resolver,
+            device_retention_days: config.device_retention_days,
+            outbox_retention_hours: config.outbox_retention_hours,
+            delivery_log_retention_days: config.delivery_log_retention_days,
+            last_report: RwLock::new(None),
+        }
+    }
+
+    pub async fn run(self: Arc<Self>, schedule: MaintenanceSchedule) {
+        loop {
+            let now = OffsetDateTime::now_utc();
+            let next = schedule.next_after(now);
+            info!("Next maintenance run at {}", next);
+            tokio::time::sleep((next - now).try_into().unwrap_or_default()).await;
+            self.run_once().await;
+        }
+    }
+
+    // Every step runs even if an earlier one failed; failures are in the report
+    pub async fn run_once(&self) -> MaintenanceReport {
+        let started_at = OffsetDateTime::now_utc();
+        let timer = std::time::Instant::now();
+        info!("Starting scheduled maintenance");
+
+        let steps = vec![
+            step("did_cache", self.did_resolver.cleanup_expired().await.map(|n| Some(n as u64))),
+            step("post_cache", self.post_resolver.cleanup_expired().await.map(|n| Some(n as u64))),
+            step(
+                "firehose_cursors",
+                crate::db::cleanup_old_cursors(&self.db_pool, 1).await.map(|_| None),
+            ),
+            step(
+                "outbox",
+                crate::db::expire_outbox(&self.db_pool, self.outbox_retention_hours)
+                    .await
+                    .map(Some),
+            ),
+            step(
+                "delivery_log",
+                crate::db::expire_delivery_log(&self.db_pool, self.delivery_log_retention_days)
+                    .await
+                    .map(Some),
+            ),
+            step(
+                "deleted_devices",
+                crate::db::purge_deleted_devices(&self.db_pool, self.device_retention_days)
+                    .await
+                    .map(Some),
+            ),
+            step(
+                "unverified_devices",
+                crate::db::purge_unverified_devices(&self.db_pool).await.map(Some),
+            ),
+            step("analyze", self.analyze().await.map(|_| None)),
+        ];
+
+        let report = MaintenanceReport {
+            started_at,
+            duration_ms: timer.elapsed().as_millis() as u64,
+            steps,
+        };
+        for step in &report.steps {
+            match &step.error {
+                Some(error) => warn!(step = step.name, "Maintenance step failed: {}", error),
+                None => info!(step = step.name, affected = ?step.affected, "Maintenance step done"),
+            }
+        }
+        info!(duration_ms = report.duration_ms, "Maintenance finished");
+
+        *self.last_report.write().await = Some(report.clone());
+        report
+    }
+
+    pub async fn last_report(&self) -> Option<MaintenanceReport> {
+        self.last_report.read().await.clone()
+    }
+
+    async fn analyze(&self) -> Result<()> {
+        for table in ANALYZE_TABLES {
+            sqlx::query(&format!("ANALYZE {}", table))
+                .execute(&self.db_pool)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn step(name: &'static str, result: Result<Option<u64>>) -> MaintenanceStep {
+    match result {
+        Ok(affected) => MaintenanceStep { name, affected, error: None },
+        Err(e) => MaintenanceStep { name, affected: None, error: Some(e.to_string()) },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn may_2025(day: u8, hour: u8, minute: u8) -> OffsetDateTime {
+        time::Date::from_calendar_date(2025, time::Month::May, day)
+            .unwrap()
+            .with_hms(hour, minute, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    #[test]
+    fn schedules_next_run() {
+        // May 1st 2025 was a Thursday
+        let now = may_2025(1, 10, 0);
+
+        let weekly = MaintenanceSchedule::parse("sun 04:00").unwrap();
+        assert_eq!(weekly.next_after(now), may_2025(4, 4, 0));
+
+        let daily = MaintenanceSchedule::parse("daily 12:30").unwrap();
+        assert_eq!(daily.next_after(now), may_2025(1, 12, 30));
+        assert_eq!(daily.next_after(may_2025(1, 12, 30)), may_2025(2, 12, 30));
+
+        assert!(MaintenanceSchedule::parse("weekly 04:00").is_none());
+        assert!(MaintenanceSchedule::parse("sun").is_none());
+    }
+}
@@ -0,0 +1,129 @@
+// memory_guard.rs
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::did_resolver::DidResolver;
+use crate::post_resolver::PostResolver;
+use crate::profile_resolver::ProfileResolver;
+use crate::relationship_manager::RelationshipManager;
+
+// Watches process RSS and reacts before the OOM killer does: above the soft limit
+// caches are cleared, above the hard limit the filter sheds low-priority events.
+pub struct MemoryGuard {
+    soft_limit_bytes: Option<u64>,
+    hard_limit_bytes: Option<u64>,
+    shedding: AtomicBool,
+    shrunk: AtomicBool,
+    did_resolver: Arc<DidResolver>,
+    post_resolver: Arc<PostResolver>,
+    profile_resolver: Arc<ProfileResolver>,
+    relationship_manager: Arc<RelationshipManager>,
+}
+
+impl MemoryGuard {
+    pub fn new(
+        soft_limit_mb: Option<u64>,
+        hard_limit_mb: Option<u64>,
+        did_resolver: Arc<DidResolver>,
+        post_resolver: Arc<PostResolver>,
+        profile_resolver: Arc<ProfileResolver>,
+        relationship_manager: Arc<RelationshipManager>,
+    ) -> Self {
+        Self {
+            soft_limit_bytes: soft_limit_mb.map(|mb| mb * 1024 * 1024),
+            hard_limit_bytes: hard_limit_mb.map(|mb| mb * 1024 * 1024),
+            shedding: AtomicBool::new(false),
+            shrunk: AtomicBool::new(false),
+            did_resolver,
+            post_resolver,
+            profile_resolver,
+            relationship_manager,
+        }
+    }
+
+    // Whether the filter should drop low-priority events
+    pub fn is_shedding(&self) -> bool {
+        self.shedding.load(Ordering::Relaxed)
+    }
+
+    // Sample RSS on an interval and apply the configured thresholds
+    pub async fn run(self: Arc<Self>, sample_interval: Duration) {
+        let mut interval = tokio::time::interval(sample_interval);
+        loop {
+            interval.tick().await;
+            match read_rss_bytes() {
+                Ok(rss) => self.apply(rss).await,
+                Err(e) => {
+                    // Not on Linux or procfs unavailable; nothing to guard
+                    error!("Failed to read process RSS, stopping memory guard: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn apply(&self, rss: u64) {
+        crate::metrics::PROCESS_RSS_BYTES.set(rss as f64);
+
+        let above_soft = self.soft_limit_bytes.is_some_and(|limit| rss >= limit);
+        if above_soft {
+            // Shrink once per excursion above the soft limit
+            if !self.shrunk.swap(true, Ordering::Relaxed) {
+                self.shrink_caches(rss).await;
+            }
+        } else {
+            self.shrunk.store(false, Ordering::Relaxed);
+        }
+
+        let above_hard = self.hard_limit_bytes.is_some_and(|limit| rss >= limit);
+        let was_shedding = self.shedding.swap(above_hard, Ordering::Relaxed);
+        if above_hard && !was_shedding {
+            warn!(rss_bytes = rss, "Memory hard limit reached, shedding low-priority events");
+        } else if !above_hard && was_shedding {
+            info!(rss_bytes = rss, "Memory back under hard limit, resuming normal processing");
+        }
+    }
+
+    async fn shrink_caches(&self, rss: u64) {
+        let dids = self.did_resolver.clear_memory_cache().await;
+        let posts = self.post_resolver.clear_memory_cache().await;
+        self.profile_resolver.clear_cache();
+        self.relationship_manager.invalidate_all_caches();
+        crate::metrics::MEMORY_CACHE_SHRINKS.inc();
+
+        warn!(
+            rss_bytes = rss,
+            did_entries = dids,
+            post_entries = posts,
+            "Memory soft limit reached, cleared in-memory caches"
+        );
+    }
+}
+
+// Read the resident set size from procfs
+pub fn read_rss_bytes() -> Result<u64> {
+    let status = std::fs::read_to_string("/proc/self/status")
+        .context("Failed to read /proc/self/status")?;
+    parse_vm_rss(&status).context("VmRSS not found in /proc/self/status")
+}
+
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status = "Name:\tnotifier\nVmPeak:\t  204800 kB\nVmRSS:\t   51200 kB\nThreads:\t8\n";
+        assert_eq!(parse_vm_rss(status), Some(51200 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tnotifier\n"), None);
+    }
+}
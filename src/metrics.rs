@@ -1,6 +1,10 @@
 //metrics.rs
 use lazy_static::lazy_static;
-use prometheus::{register_counter, register_histogram, Counter, Histogram, HistogramOpts, Opts};
+use prometheus::{
+    register_counter, register_counter_vec, register_gauge, register_gauge_vec, register_histogram,
+    register_histogram_vec, Counter, CounterVec, Gauge, GaugeVec, Histogram, HistogramOpts,
+    HistogramVec, Opts,
+};
 
 // Define metrics
 lazy_static! {
@@ -16,6 +20,24 @@ lazy_static! {
         "Total number of notifications sent"
     ))
     .unwrap();
+
+    pub static ref NOTIFICATIONS_SENT_BY_TYPE: CounterVec = register_counter_vec!(
+        Opts::new(
+            "notifications_sent_by_type_total",
+            "Total number of notifications the filter queued for delivery, by type"
+        ),
+        &["type"]
+    )
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_SKIPPED: CounterVec = register_counter_vec!(
+        Opts::new(
+            "notifications_skipped_total",
+            "Total number of notifications the filter dropped or held back for a recipient, by type and reason"
+        ),
+        &["type", "reason"]
+    )
+    .unwrap();
     
     // Cache metrics
     pub static ref DID_CACHE_HITS: Counter = register_counter!(Opts::new(
@@ -61,6 +83,15 @@ lazy_static! {
     )
     .unwrap();
     
+    pub static ref CACHE_WARM_TIME: Histogram = register_histogram!(
+        HistogramOpts::new(
+            "cache_warm_time_seconds",
+            "Time taken to warm the caches for a newly registered DID"
+        )
+        .buckets(vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0])
+    )
+    .unwrap();
+
     pub static ref POST_FETCH_TIME: Histogram = register_histogram!(
         HistogramOpts::new(
             "post_fetch_time_seconds",
@@ -88,6 +119,452 @@ lazy_static! {
         .buckets(vec![0.01, 0.025, 0.05, 0.075, 0.1, 0.15, 0.2, 0.3, 0.5])
     )
     .unwrap();
+
+    // Feature flag metrics
+    pub static ref FEATURE_FLAG_EVALUATIONS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "feature_flag_evaluations_total",
+            "Total number of feature flag evaluations by flag and result"
+        ),
+        &["flag", "result"]
+    )
+    .unwrap();
+
+    pub static ref EXPERIMENT_NOTIFICATIONS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "experiment_notifications_total",
+            "Total number of experiment notifications by experiment, variant and outcome"
+        ),
+        &["experiment", "variant", "outcome"]
+    )
+    .unwrap();
+
+    pub static ref FANOUT_CAP_HITS: Counter = register_counter!(Opts::new(
+        "fanout_cap_hits_total",
+        "Total number of events whose recipients were truncated by the fan-out cap"
+    ))
+    .unwrap();
+
+    pub static ref FANOUT_SPREAD_EVENTS: Counter = register_counter!(Opts::new(
+        "fanout_spread_events_total",
+        "Total number of events delivered in spaced batches due to large fan-out"
+    ))
+    .unwrap();
+
+    pub static ref RETRY_ATTEMPTS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "retry_attempts_total",
+            "Total number of retries after a failed attempt, by call site"
+        ),
+        &["call_site"]
+    )
+    .unwrap();
+
+    pub static ref RETRIES_EXHAUSTED: CounterVec = register_counter_vec!(
+        Opts::new(
+            "retries_exhausted_total",
+            "Total number of operations that failed after their last allowed attempt, by call site"
+        ),
+        &["call_site"]
+    )
+    .unwrap();
+
+    pub static ref DEVICES_QUARANTINED: Gauge = register_gauge!(Opts::new(
+        "devices_quarantined",
+        "Number of devices quarantined after continuous retryable delivery failures"
+    ))
+    .unwrap();
+
+    pub static ref DEVICE_TOPIC_MISMATCHES: Counter = register_counter!(Opts::new(
+        "device_topic_mismatches_total",
+        "Total number of device tokens APNs reported as belonging to another app than APNS_TOPIC"
+    ))
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_QUARANTINED: Counter = register_counter!(Opts::new(
+        "notifications_quarantined_total",
+        "Total number of notifications dropped because the device is quarantined between probes"
+    ))
+    .unwrap();
+
+    pub static ref NOTIFICATION_DELIVERY_OUTCOMES: CounterVec = register_counter_vec!(
+        Opts::new(
+            "notification_delivery_outcomes_total",
+            "Total number of send attempts by outcome (delivered, rejected, retries_exhausted, apns_unavailable, token_invalid, topic_mismatch)"
+        ),
+        &["outcome"]
+    )
+    .unwrap();
+
+    pub static ref NOTIFICATION_VERIFICATION: CounterVec = register_counter_vec!(
+        Opts::new(
+            "notification_verification_total",
+            "Delivered notifications compared against the AppView, by outcome (matched, missed, extra, mismatched, failed)"
+        ),
+        &["outcome"]
+    )
+    .unwrap();
+
+    // Memory guard metrics
+    pub static ref PROCESS_RSS_BYTES: Gauge = register_gauge!(Opts::new(
+        "process_rss_bytes",
+        "Resident set size of the process in bytes"
+    ))
+    .unwrap();
+
+    pub static ref MEMORY_CACHE_SHRINKS: Counter = register_counter!(Opts::new(
+        "memory_cache_shrinks_total",
+        "Total number of times caches were cleared due to memory pressure"
+    ))
+    .unwrap();
+
+    pub static ref EVENTS_SHED: Counter = register_counter!(Opts::new(
+        "events_shed_total",
+        "Total number of low-priority events dropped while shedding load"
+    ))
+    .unwrap();
+
+    pub static ref CHANNEL_DEPTH: GaugeVec = register_gauge_vec!(
+        Opts::new(
+            "channel_depth",
+            "Number of items queued in each pipeline channel"
+        ),
+        &["channel"]
+    )
+    .unwrap();
+
+    pub static ref FIREHOSE_LAG_SECONDS: Gauge = register_gauge!(Opts::new(
+        "firehose_lag_seconds",
+        "Seconds between the relay's timestamp on the latest commit and when we handled it"
+    ))
+    .unwrap();
+
+    // Dependency health, for alerting without log parsing
+    pub static ref CIRCUIT_BREAKER_STATE: GaugeVec = register_gauge_vec!(
+        Opts::new(
+            "circuit_breaker_state",
+            "Circuit breaker state by breaker (0 closed, 1 half-open, 2 open)"
+        ),
+        &["breaker"]
+    )
+    .unwrap();
+
+    pub static ref FIREHOSE_DECODE_TIME: Histogram = register_histogram!(
+        HistogramOpts::new(
+            "firehose_decode_seconds",
+            "Time taken on the blocking pool to decode a firehose frame and its records"
+        )
+        .buckets(vec![0.0001, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1])
+    )
+    .unwrap();
+
+    pub static ref FIREHOSE_BLOCKS_APPLIED: CounterVec = register_counter_vec!(
+        Opts::new(
+            "firehose_blocks_applied_total",
+            "Registered users' blocks applied from the firehose, by action (create, delete)"
+        ),
+        &["action"]
+    )
+    .unwrap();
+
+    pub static ref FIREHOSE_FOLLOWS_APPLIED: CounterVec = register_counter_vec!(
+        Opts::new(
+            "firehose_follows_applied_total",
+            "Registered users' follows applied from the firehose, by action (create, delete)"
+        ),
+        &["action"]
+    )
+    .unwrap();
+
+    pub static ref DECODED_RECORD_CACHE_LOOKUPS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "decoded_record_cache_lookups_total",
+            "Decoded firehose record cache lookups by result (hit, miss)"
+        ),
+        &["result"]
+    )
+    .unwrap();
+
+    pub static ref DIDS_PREFETCHED: CounterVec = register_counter_vec!(
+        Opts::new(
+            "dids_prefetched_total",
+            "DID documents cached ahead of need, by source (resolve, export)"
+        ),
+        &["source"]
+    )
+    .unwrap();
+
+    pub static ref PLC_RATE_LIMITED: Counter = register_counter!(Opts::new(
+        "plc_rate_limited_total",
+        "PLC directory requests from the DID prefetcher answered with 429"
+    ))
+    .unwrap();
+
+    pub static ref FIREHOSE_CONNECTED: Gauge = register_gauge!(Opts::new(
+        "firehose_connected",
+        "1 while the firehose websocket is connected, 0 otherwise"
+    ))
+    .unwrap();
+
+    pub static ref FIREHOSE_RELAY: GaugeVec = register_gauge_vec!(
+        Opts::new(
+            "firehose_relay",
+            "1 for the relay the firehose is connected to"
+        ),
+        &["relay"]
+    )
+    .unwrap();
+
+    pub static ref DB_POOL_CONNECTIONS: GaugeVec = register_gauge_vec!(
+        Opts::new(
+            "db_pool_connections",
+            "Database pool connections by state (idle, in_use, max)"
+        ),
+        &["state"]
+    )
+    .unwrap();
+
+    pub static ref DB_POOL_UTILIZATION: Gauge = register_gauge!(Opts::new(
+        "db_pool_utilization",
+        "Fraction of the database pool's maximum connections in use"
+    ))
+    .unwrap();
+
+    pub static ref CHANNEL_OVERFLOW_ACTIONS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "channel_overflow_actions_total",
+            "Total number of overflow policy actions taken by pipeline channel"
+        ),
+        &["channel", "action"]
+    )
+    .unwrap();
+
+    pub static ref WATCHDOG_ALERTS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "watchdog_alerts_total",
+            "Total number of pipeline stall alerts fired by the watchdog"
+        ),
+        &["alert"]
+    )
+    .unwrap();
+
+    pub static ref TENANT_NOTIFICATIONS_DEFERRED: CounterVec = register_counter_vec!(
+        Opts::new(
+            "tenant_notifications_deferred_total",
+            "Total number of notifications deferred to digests because a tenant was over quota"
+        ),
+        &["tenant"]
+    )
+    .unwrap();
+
+    // Plugin metrics
+    pub static ref PLUGIN_DECISIONS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "plugin_decisions_total",
+            "Total number of filtering decisions returned by plugins"
+        ),
+        &["plugin", "decision"]
+    )
+    .unwrap();
+
+    pub static ref PLUGIN_ERRORS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "plugin_errors_total",
+            "Total number of plugin invocations that trapped, ran out of fuel or returned bad output"
+        ),
+        &["plugin"]
+    )
+    .unwrap();
+
+    pub static ref PLUGIN_EXECUTION_TIME: HistogramVec = register_histogram_vec!(
+        HistogramOpts::new(
+            "plugin_execution_time_seconds",
+            "Time taken by a plugin to decide on an event"
+        )
+        .buckets(vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1]),
+        &["plugin"]
+    )
+    .unwrap();
+
+    pub static ref COPY_SCRIPT_ERRORS: Counter = register_counter!(Opts::new(
+        "copy_script_errors_total",
+        "Total number of copy script runs that failed or timed out"
+    ))
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_LABEL_FILTERED: CounterVec = register_counter_vec!(
+        Opts::new(
+            "notifications_label_filtered_total",
+            "Total number of notifications masked or hidden because of moderation labels"
+        ),
+        &["action"]
+    )
+    .unwrap();
+
+    pub static ref FEED_POSTS_NOTIFIED: CounterVec = register_counter_vec!(
+        Opts::new(
+            "feed_posts_notified_total",
+            "Total number of new feed posts announced to subscribers, by delivery mode"
+        ),
+        &["mode"]
+    )
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_DELIVERED_BY_TYPE: CounterVec = register_counter_vec!(
+        Opts::new(
+            "notifications_delivered_by_type_total",
+            "Total number of notifications delivered to APNs by type"
+        ),
+        &["type"]
+    )
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_OPENED: CounterVec = register_counter_vec!(
+        Opts::new(
+            "notifications_opened_total",
+            "Total number of notifications opened by type, as reported by the app"
+        ),
+        &["type"]
+    )
+    .unwrap();
+
+    pub static ref INVALID_TOKENS_REMOVED: Counter = register_counter!(Opts::new(
+        "invalid_tokens_removed_total",
+        "Total number of devices soft-deleted because APNs reported the token invalid"
+    ))
+    .unwrap();
+
+    pub static ref POST_CONTENT_FALLBACKS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "post_content_fallbacks_total",
+            "Total number of like/repost events whose subject post couldn't be fetched, by type and fallback taken"
+        ),
+        &["type", "fallback"]
+    )
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_QUIET_HOURS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "notifications_quiet_hours_total",
+            "Total number of notifications held back by device quiet hours, by mode (suppress or queue)"
+        ),
+        &["mode"]
+    )
+    .unwrap();
+
+    pub static ref RATE_LIMITED_REQUESTS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "rate_limited_requests_total",
+            "Total number of API requests rejected by rate limits, by route and limited key (ip or did)"
+        ),
+        &["route", "key"]
+    )
+    .unwrap();
+
+    pub static ref TOKEN_CLEANUP_DROPPED: Counter = register_counter!(Opts::new(
+        "token_cleanup_dropped_total",
+        "Total number of invalid tokens dropped because the cleanup queue was full"
+    ))
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_COOLED_DOWN: CounterVec = register_counter_vec!(
+        Opts::new(
+            "notifications_cooled_down_total",
+            "Total number of notifications held back by a per-author cool-down, by type"
+        ),
+        &["type"]
+    )
+    .unwrap();
+
+    pub static ref FIREHOSE_GAPS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "firehose_gaps_total",
+            "Total number of times the stored firehose cursor was abandoned for the live tip, by reason"
+        ),
+        &["reason"]
+    )
+    .unwrap();
+
+    pub static ref APNS_SEND_LATENCY: HistogramVec = register_histogram_vec!(
+        HistogramOpts::new(
+            "apns_send_latency_seconds",
+            "Time taken by one APNs request, by host and HTTP status (\"error\" when no response)"
+        )
+        .buckets(vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+        &["host", "status"]
+    )
+    .unwrap();
+
+    pub static ref APNS_HOST_FAILOVERS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "apns_host_failovers_total",
+            "Total number of APNs requests moved to the next host after failing to connect, by the host that failed"
+        ),
+        &["host"]
+    )
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_VIP: Counter = register_counter!(Opts::new(
+        "notifications_vip_total",
+        "Total number of notifications from a recipient's VIPs, which skip quiet hours, digests and load shedding"
+    ))
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_AGGREGATED: Counter = register_counter!(Opts::new(
+        "notifications_aggregated_total",
+        "Total number of likes and reposts held back and folded into a combined notification"
+    ))
+    .unwrap();
+
+    pub static ref NOTIFICATIONS_RETRACTED: Counter = register_counter!(Opts::new(
+        "notifications_retracted_total",
+        "Total number of notifications retracted because their record was deleted"
+    ))
+    .unwrap();
+
+    pub static ref EVENTS_DEDUPLICATED: Counter = register_counter!(Opts::new(
+        "events_deduplicated_total",
+        "Total number of firehose events dropped because the same record was already processed"
+    ))
+    .unwrap();
+
+    pub static ref SHARED_CACHE_LOOKUPS: CounterVec = register_counter_vec!(
+        Opts::new(
+            "shared_cache_lookups_total",
+            "Total number of shared cache tier lookups by kind and result"
+        ),
+        &["kind", "result"]
+    )
+    .unwrap();
+}
+
+// Why the filter didn't push a notification: self, muted, blocked, cooldown,
+// preference_off, thresholds, quiet_hours, digest, quota, label_hidden, backpressure
+// or enqueue_failed
+pub fn record_skipped(notification_type: &crate::models::NotificationType, reason: &str) {
+    NOTIFICATIONS_SKIPPED
+        .with_label_values(&[notification_type.as_str(), reason])
+        .inc();
+}
+
+pub fn record_circuit_state(breaker: &str, state: &circuit_breaker::CircuitState) {
+    let value = match state {
+        circuit_breaker::CircuitState::Closed => 0.0,
+        circuit_breaker::CircuitState::Open => 2.0,
+        _ => 1.0,
+    };
+    CIRCUIT_BREAKER_STATE.with_label_values(&[breaker]).set(value);
+}
+
+// Sampled on each scrape
+pub fn record_db_pool(pool: &sqlx::Pool<sqlx::Postgres>) {
+    let size = pool.size() as f64;
+    let idle = pool.num_idle() as f64;
+    let max = pool.options().get_max_connections() as f64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS.with_label_values(&["in_use"]).set(size - idle);
+    DB_POOL_CONNECTIONS.with_label_values(&["max"]).set(max);
+    DB_POOL_UTILIZATION.set(if max > 0.0 { (size - idle) / max } else { 0.0 });
 }
 
 // Function to expose metrics endpoint
@@ -9,6 +9,7 @@ pub struct UserDevice {
     pub device_token: String,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
+    pub tenant_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +21,45 @@ pub struct NotificationPreference {
     pub follows: bool,
     pub reposts: bool,
     pub quotes: bool,
+    // Replies to one of the user's replies, as opposed to their top-level posts
+    pub replies_to_replies: bool,
+    // Mutuals break through Focus as time-sensitive, everyone else is delivered passively
+    pub priority_from_mutuals: bool,
+    // New posts in subscribed custom feeds
+    pub feed_posts: bool,
+    // Likes and reposts arrive as a periodic rollup
+    pub digest_low_priority: bool,
+    // Direct messages, for users who connected their chat account
+    pub dms: bool,
+    // Show "New message" instead of the message text
+    pub dm_redact_body: bool,
+    // "X edited a post that mentions you" when a post the user was notified of changes
+    pub post_edits: bool,
+    // Someone added the user to a list or starter pack
+    pub list_additions: bool,
+    // Types delivered as silent background pushes, so the app can refresh without
+    // alerting
+    pub background_types: Vec<String>,
+    // Subscribed authors posting after a long silence are announced as such
+    pub comeback_posts: bool,
+    // Set by the app on this device, see Grouping
+    pub grouping: String,
+    // Pushes submitted by first-party services
+    pub custom_notifications: bool,
+    // Declared by the app on this device, see payload_keys
+    pub payload_version: i16,
+    // Declared by the app on this device: whether pushes carry the event's origin
+    pub payload_event_origin: bool,
+    // Replies open with a line of the post they answer
+    pub reply_context: bool,
+    // Likes, reposts and replies only from accounts the user follows
+    pub only_from_follows: bool,
+}
+
+impl NotificationPreference {
+    pub fn delivers_in_background(&self, notification_type: &NotificationType) -> bool {
+        self.background_types.iter().any(|t| t == notification_type.as_str())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,6 +70,237 @@ pub enum NotificationType {
     Follow,
     Repost,
     Quote,
+    FeedPost,
+    // New post from an account the user subscribed to
+    SubscribedPost,
+    DirectMessage,
+    // Added to a list or starter pack
+    ListAddition,
+    // Submitted by a first-party service
+    Custom,
+}
+
+impl NotificationType {
+    // Stable lowercase name used for storage keys and API fields
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationType::Mention => "mention",
+            NotificationType::Reply => "reply",
+            NotificationType::Like => "like",
+            NotificationType::Follow => "follow",
+            NotificationType::Repost => "repost",
+            NotificationType::Quote => "quote",
+            NotificationType::FeedPost => "feed_post",
+            NotificationType::SubscribedPost => "subscribed_post",
+            NotificationType::DirectMessage => "dm",
+            NotificationType::ListAddition => "list_addition",
+            NotificationType::Custom => "custom",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "mention" => Some(NotificationType::Mention),
+            "reply" => Some(NotificationType::Reply),
+            "like" => Some(NotificationType::Like),
+            "follow" => Some(NotificationType::Follow),
+            "repost" => Some(NotificationType::Repost),
+            "quote" => Some(NotificationType::Quote),
+            "feed_post" => Some(NotificationType::FeedPost),
+            "subscribed_post" => Some(NotificationType::SubscribedPost),
+            "dm" => Some(NotificationType::DirectMessage),
+            "list_addition" => Some(NotificationType::ListAddition),
+            "custom" => Some(NotificationType::Custom),
+            _ => None,
+        }
+    }
+
+    // Shown under the app's Mentions tab as well as the full list
+    pub fn is_mention(&self) -> bool {
+        matches!(
+            self,
+            NotificationType::Mention | NotificationType::Reply | NotificationType::Quote
+        )
+    }
+}
+
+// What to do with a notification about content carrying a given moderation label.
+// Ordered from least to most restrictive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelVisibility {
+    Show,
+    Mask,
+    Hide,
+}
+
+impl LabelVisibility {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LabelVisibility::Show => "show",
+            LabelVisibility::Mask => "mask",
+            LabelVisibility::Hide => "hide",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "show" => Some(LabelVisibility::Show),
+            "mask" => Some(LabelVisibility::Mask),
+            "hide" => Some(LabelVisibility::Hide),
+            _ => None,
+        }
+    }
+
+    // Adult and graphic content is masked unless the user opts in
+    pub fn default_for(label: &str) -> Self {
+        match label {
+            "porn" | "sexual" | "nudity" | "graphic-media" | "gore" => LabelVisibility::Mask,
+            _ => LabelVisibility::Show,
+        }
+    }
+
+    // Strictest handling across all labels on the post
+    pub fn resolve(labels: &[String], preferences: &HashMap<String, LabelVisibility>) -> Self {
+        labels
+            .iter()
+            .map(|label| {
+                preferences
+                    .get(label)
+                    .copied()
+                    .unwrap_or_else(|| LabelVisibility::default_for(label))
+            })
+            .max()
+            .unwrap_or(LabelVisibility::Show)
+    }
+}
+
+// What happens to notifications generated during a device's quiet hours
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuietHoursMode {
+    // Dropped; the user catches up in the app
+    Suppress,
+    // Held back and summarized when the window closes
+    #[default]
+    Queue,
+}
+
+impl QuietHoursMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuietHoursMode::Suppress => "suppress",
+            QuietHoursMode::Queue => "queue",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "suppress" => Some(QuietHoursMode::Suppress),
+            "queue" => Some(QuietHoursMode::Queue),
+            _ => None,
+        }
+    }
+}
+
+// How a device's notifications are grouped in Notification Center (thread-id) and
+// which ones replace each other (collapse-id). Each client app picks its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Grouping {
+    // No thread-id, so iOS stacks everything together; activity on one post collapses
+    #[default]
+    App,
+    // One stack per conversation
+    Thread,
+    // One stack per person
+    Author,
+    // Nothing stacks or collapses
+    None,
+}
+
+impl Grouping {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Grouping::App => "app",
+            Grouping::Thread => "thread",
+            Grouping::Author => "author",
+            Grouping::None => "none",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "app" => Some(Grouping::App),
+            "thread" => Some(Grouping::Thread),
+            "author" => Some(Grouping::Author),
+            "none" => Some(Grouping::None),
+            _ => None,
+        }
+    }
+}
+
+// Minimum author requirements a user has set for a notification type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationThreshold {
+    pub user_id: Uuid,
+    pub notification_type: String,
+    pub min_account_age_days: Option<i32>,
+    pub min_followers: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventSource {
+    // Live from the relay firehose
+    #[default]
+    Relay,
+    Jetstream,
+    // Re-read from the relay after resuming from a stored cursor
+    Replay,
+    // Read from a repo archive by an admin backfill job
+    Backfill,
+}
+
+impl EventSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventSource::Relay => "relay",
+            EventSource::Jetstream => "jetstream",
+            EventSource::Replay => "replay",
+            EventSource::Backfill => "backfill",
+        }
+    }
+}
+
+// Where an event came from, so a push can be traced back to the exact stream position
+// that produced it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventOrigin {
+    pub source: EventSource,
+    // Relay sequence number of the commit
+    pub seq: Option<i64>,
+    // Repo revision the commit produced
+    pub rev: Option<String>,
+    // Unix milliseconds
+    pub received_at: i64,
+    // Set on backfilled events: only these users are notified
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipients: Option<Vec<String>>,
+}
+
+impl EventOrigin {
+    // Carried in notification data, so it lands in history along with the push; only
+    // devices that asked for it get it in the APNs payload, see payload_keys
+    pub fn annotate(&self, data: &mut HashMap<String, String>) {
+        data.insert("event_source".to_string(), self.source.as_str().to_string());
+        if let Some(seq) = self.seq {
+            data.insert("event_seq".to_string(), seq.to_string());
+        }
+        if let Some(rev) = &self.rev {
+            data.insert("event_rev".to_string(), rev.clone());
+        }
+        data.insert("event_received_at".to_string(), self.received_at.to_string());
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,6 +311,8 @@ pub struct BlueskyEvent {
     pub author: String,
     pub record: serde_json::Value,
     pub timestamp: i64,
+    #[serde(default)]
+    pub origin: EventOrigin,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,9 +325,78 @@ pub struct NotificationPayload {
     pub data: HashMap<String, String>, 
 }
 
+impl NotificationPayload {
+    // Set by the filter when the recipient marked the author a VIP
+    pub fn is_vip(&self) -> bool {
+        self.data.get("vip").is_some_and(|vip| vip == "true")
+    }
+
+    // Set by the filter for types the recipient wants delivered silently
+    pub fn is_background(&self) -> bool {
+        self.data.get("push_type").is_some_and(|push_type| push_type == "background")
+    }
+
+    // Re-sent from history or re-read after a resume; the first send was already counted
+    pub fn is_replay(&self) -> bool {
+        self.data
+            .get("event_source")
+            .is_some_and(|source| source == EventSource::Replay.as_str())
+    }
+}
+
+// A row of the in-app notification center
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationHistoryEntry {
+    #[serde(skip)]
+    pub id: i64,
+    pub notification_id: Uuid,
+    pub notification_type: String,
+    pub title: String,
+    pub body: String,
+    pub uri: Option<String>,
+    pub status: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FirehoseCursor {
     pub id: i32,
     pub cursor: String,
     pub updated_at: OffsetDateTime,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotates_notifications_with_the_event_origin() {
+        let origin = EventOrigin {
+            source: EventSource::Replay,
+            seq: Some(4242),
+            rev: Some("3kabc".to_string()),
+            received_at: 1_700_000_000_000,
+            recipients: None,
+        };
+        let mut data = HashMap::new();
+        origin.annotate(&mut data);
+        assert_eq!(data["event_source"], "replay");
+        assert_eq!(data["event_seq"], "4242");
+        assert_eq!(data["event_rev"], "3kabc");
+        let payload = NotificationPayload {
+            user_did: "did:plc:me".to_string(),
+            device_token: "token".to_string(),
+            notification_type: NotificationType::Like,
+            title: String::new(),
+            body: String::new(),
+            data: data.clone(),
+        };
+        assert!(payload.is_replay());
+
+        let mut data = HashMap::new();
+        EventOrigin::default().annotate(&mut data);
+        assert_eq!(data["event_source"], "relay");
+        assert!(!data.contains_key("event_seq"));
+    }
+}
@@ -0,0 +1,272 @@
+// muted_words.rs
+// Muted words and phrases, matched the way the Bluesky app matches them so a post the
+// user wouldn't see in their feed doesn't reach them as a push either. The app keeps
+// the list in the user's private preferences, which this service can't read, so it
+// sends a copy with PUT /relationships/muted-words whenever the list changes.
+//
+// A word muted for "tag" matches the post's hashtags; one muted for "content" also
+// matches its text, image alt text and link card, case-insensitively: single
+// characters and text in languages written without spaces match anywhere, phrases
+// with spaces or punctuation match as substrings, and other words match whole words,
+// ignoring punctuation around them. Expired words are ignored until the app replaces
+// the list.
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+// Large enough for the app's lists, small enough to match every post against
+pub const MAX_MUTED_WORDS: usize = 500;
+const MAX_WORD_LENGTH: usize = 200;
+
+// Languages where words aren't separated by spaces, so whole-word matching can't apply
+const LANGUAGE_EXCEPTIONS: &[&str] = &["ja", "zh", "ko", "th", "vi"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MutedWordTarget {
+    Content,
+    Tag,
+}
+
+impl MutedWordTarget {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MutedWordTarget::Content => "content",
+            MutedWordTarget::Tag => "tag",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "content" => Some(MutedWordTarget::Content),
+            "tag" => Some(MutedWordTarget::Tag),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MutedWord {
+    pub value: String,
+    pub targets: Vec<MutedWordTarget>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+// Normalise values the way the app stores them: trimmed, without a leading '#' and
+// without line breaks or invisible characters. Rejects lists the filter shouldn't run.
+pub fn sanitize(words: Vec<MutedWord>) -> Result<Vec<MutedWord>> {
+    if words.len() > MAX_MUTED_WORDS {
+        bail!("At most {} muted words", MAX_MUTED_WORDS);
+    }
+    let mut sanitized: Vec<MutedWord> = Vec::with_capacity(words.len());
+    for mut word in words {
+        let value = word.value.trim();
+        let value = match value.strip_prefix('#') {
+            Some(rest) if !rest.starts_with('\u{fe0f}') => rest,
+            _ => value,
+        };
+        word.value = value
+            .chars()
+            .filter(|c| !matches!(c, '\r' | '\n' | '\u{00ad}' | '\u{2060}' | '\u{200b}'..='\u{200d}'))
+            .collect();
+        if word.value.is_empty() || word.value.chars().count() > MAX_WORD_LENGTH || word.targets.is_empty() {
+            bail!("Muted words need a value of 1-{} characters and a target", MAX_WORD_LENGTH);
+        }
+        if !sanitized.iter().any(|existing| existing.value == word.value) {
+            sanitized.push(word);
+        }
+    }
+    Ok(sanitized)
+}
+
+// Unicode punctuation (\p{P}), which unlike ASCII symbols such as '$' or '+' separates
+// words in a muted phrase
+fn is_punctuation(c: char) -> bool {
+    matches!(
+        c,
+        '!' | '"'
+            | '#'
+            | '%'..='*'
+            | ','..='/'
+            | ':'
+            | ';'
+            | '?'
+            | '@'
+            | '['..=']'
+            | '_'
+            | '{'
+            | '}'
+            | '¡'
+            | '§'
+            | '«'
+            | '¶'
+            | '·'
+            | '»'
+            | '¿'
+            | '\u{2010}'..='\u{2027}'
+            | '\u{2030}'..='\u{205e}'
+            | '\u{3001}'..='\u{3003}'
+            | '\u{3008}'..='\u{3011}'
+            | '\u{ff01}'..='\u{ff0f}'
+    )
+}
+
+// Whether the post record matches any of the words that haven't expired
+pub fn matches(words: &[MutedWord], record: &serde_json::Value, now: OffsetDateTime) -> bool {
+    let words: Vec<&MutedWord> = words
+        .iter()
+        .filter(|word| word.expires_at.is_none_or(|expires_at| expires_at > now))
+        .collect();
+    if words.is_empty() {
+        return false;
+    }
+
+    let tags = tags(record);
+    let exception = record
+        .get("langs")
+        .and_then(|langs| langs.get(0))
+        .and_then(|lang| lang.as_str())
+        .and_then(|lang| lang.split('-').next())
+        .is_some_and(|lang| LANGUAGE_EXCEPTIONS.contains(&lang));
+
+    let texts: Vec<String> = texts(record).iter().map(|text| text.to_lowercase()).collect();
+    words.iter().any(|word| {
+        let muted = word.value.to_lowercase();
+        // Muting a word for content mutes it as a tag too
+        if tags.contains(&muted) {
+            return true;
+        }
+        word.targets.contains(&MutedWordTarget::Content)
+            && texts.iter().any(|text| matches_text(&muted, text, exception))
+    })
+}
+
+fn matches_text(muted: &str, text: &str, exception: bool) -> bool {
+    if (muted.chars().count() == 1 || exception) && text.contains(muted) {
+        return true;
+    }
+    if muted.len() > text.len() {
+        return false;
+    }
+    if muted == text {
+        return true;
+    }
+    // Phrases
+    if muted.chars().any(|c| c.is_whitespace() || is_punctuation(c)) && text.contains(muted) {
+        return true;
+    }
+
+    text.split_whitespace().any(|word| {
+        if word == muted {
+            return true;
+        }
+        // Punctuation inside the word counts, as in "s@ssy"
+        let trimmed = word.trim_matches(is_punctuation);
+        if trimmed == muted {
+            return true;
+        }
+        if muted.len() > trimmed.len() || !trimmed.chars().any(is_punctuation) {
+            return false;
+        }
+        // "muted-word" and "muted_word" match "muted word" and "mutedword"
+        let spaced = trimmed
+            .split(is_punctuation)
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        spaced == muted
+            || spaced.replace(' ', "") == muted
+            || trimmed.split(is_punctuation).any(|part| part == muted)
+    })
+}
+
+// Hashtags from the post's facets and its outline tags, lowercased
+fn tags(record: &serde_json::Value) -> Vec<String> {
+    let facet_tags = record
+        .get("facets")
+        .and_then(|facets| facets.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|facet| facet.get("features").and_then(|features| features.as_array()))
+        .flatten()
+        .filter(|feature| {
+            feature.get("$type").and_then(|t| t.as_str()) == Some("app.bsky.richtext.facet#tag")
+        })
+        .filter_map(|feature| feature.get("tag").and_then(|tag| tag.as_str()));
+    let outline_tags = record
+        .get("tags")
+        .and_then(|tags| tags.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|tag| tag.as_str());
+    facet_tags.chain(outline_tags).map(str::to_lowercase).collect()
+}
+
+// The post's text, its images' alt text and its link card's title and description,
+// each matched on its own
+fn texts(record: &serde_json::Value) -> Vec<&str> {
+    let mut texts: Vec<&str> = record.get("text").and_then(|text| text.as_str()).into_iter().collect();
+    let embed = record.get("embed");
+    // A quote with media carries the media one level down
+    for embed in [embed, embed.and_then(|embed| embed.get("media"))].into_iter().flatten() {
+        if let Some(images) = embed.get("images").and_then(|images| images.as_array()) {
+            texts.extend(images.iter().filter_map(|image| image.get("alt").and_then(|alt| alt.as_str())));
+        }
+        if let Some(external) = embed.get("external") {
+            for field in ["title", "description"] {
+                texts.extend(external.get(field).and_then(|value| value.as_str()));
+            }
+        }
+    }
+    texts.retain(|text| !text.is_empty());
+    texts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(value: &str, targets: &[MutedWordTarget]) -> MutedWord {
+        MutedWord {
+            value: value.to_string(),
+            targets: targets.to_vec(),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn matches_like_the_bluesky_app() {
+        use MutedWordTarget::{Content, Tag};
+        let now = OffsetDateTime::now_utc();
+        let post = |text: &str| serde_json::json!({ "text": text });
+
+        let words = [word("spoiler", &[Content, Tag])];
+        assert!(matches(&words, &post("No SPOILERS... just kidding: spoiler!"), now));
+        assert!(!matches(&words, &post("spoilers ahead"), now));
+        assert!(matches(&[word("muted word", &[Content])], &post("the muted-word here"), now));
+        assert!(matches(&[word("s@ssy", &[Content])], &post("feeling s@ssy."), now));
+        assert!(matches(&[word("!", &[Content])], &post("wow!"), now));
+
+        let tagged = serde_json::json!({
+            "text": "#Rust is great",
+            "facets": [{ "features": [{ "$type": "app.bsky.richtext.facet#tag", "tag": "Rust" }] }],
+        });
+        assert!(matches(&[word("rust", &[Tag])], &tagged, now));
+        assert!(!matches(&[word("great", &[Tag])], &tagged, now));
+
+        let japanese = serde_json::json!({ "text": "今日はいい天気", "langs": ["ja"] });
+        assert!(matches(&[word("天気", &[Content])], &japanese, now));
+
+        let alt = serde_json::json!({ "text": "look", "embed": { "images": [{ "alt": "A Spider on a wall" }] } });
+        assert!(matches(&[word("spider", &[Content])], &alt, now));
+
+        let mut expired = word("spoiler", &[Content]);
+        expired.expires_at = Some(now - time::Duration::hours(1));
+        assert!(!matches(&[expired], &post("spoiler"), now));
+
+        let sanitized = sanitize(vec![word("  #Rust\n", &[Tag]), word("Rust", &[Content])]).unwrap();
+        assert_eq!(sanitized, vec![word("Rust", &[Tag])]);
+        assert!(sanitize(vec![word("#", &[Content])]).is_err());
+    }
+}
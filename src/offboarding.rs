@@ -0,0 +1,129 @@
+// offboarding.rs
+// `offboard` subcommand: erase everything stored for a list of DIDs, for when an app
+// sunsets a region or a batch of legal requests comes in. Each DID goes through the
+// same erasure as DELETE /privacy/data (see privacy.rs), recorded in
+// relationship_audit_log as an "offboarding", but outside any tenant, so devices are
+// removed from every tenant. DIDs are erased --batch-size at a time, one transaction
+// per batch, with a progress line per batch; a failed batch stops the run with the
+// earlier batches committed, and rerunning the same list is harmless. --dry-run counts
+// the rows each table would lose and changes nothing.
+use anyhow::{bail, Context, Result};
+use sqlx::{Pool, Postgres};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::privacy::ErasedBy;
+
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+fn usage() {
+    println!("Usage:");
+    println!("  offboard <did-file|-> [--dry-run] [--batch-size <dids>]");
+    println!();
+    println!("The file lists one DID per line; blank lines and lines starting with # are skipped.");
+}
+
+pub async fn run_command(pool: &Pool<Postgres>, args: &[String]) -> Result<()> {
+    let mut path = None;
+    let mut dry_run = false;
+    let mut batch_size = DEFAULT_BATCH_SIZE;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--batch-size" => {
+                batch_size = args
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .filter(|size| *size > 0)
+                    .context("--batch-size must be a positive number")?;
+            }
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => {
+                usage();
+                return Ok(());
+            }
+        }
+    }
+    let Some(path) = path else {
+        usage();
+        return Ok(());
+    };
+
+    let input = if path == "-" {
+        std::io::read_to_string(std::io::stdin()).context("Failed to read DIDs from stdin")?
+    } else {
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?
+    };
+    let dids = parse_dids(&input)?;
+    println!(
+        "{} {} DIDs in batches of {}",
+        if dry_run { "Counting data for" } else { "Offboarding" },
+        dids.len(),
+        batch_size
+    );
+
+    let batches = dids.len().div_ceil(batch_size);
+    let mut totals: BTreeMap<&'static str, u64> = BTreeMap::new();
+    for (index, batch) in dids.chunks(batch_size).enumerate() {
+        let mut tx = pool.begin().await?;
+        let mut rows = 0;
+        for did in batch {
+            let counts = if dry_run {
+                crate::privacy::count(&mut tx, did).await
+            } else {
+                crate::privacy::erase(&mut tx, did, ErasedBy::Operator).await
+            }
+            .with_context(|| format!("Failed to offboard {}", did))?;
+            for (table, count) in counts {
+                *totals.entry(table).or_default() += count;
+                rows += count;
+            }
+        }
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        println!("Batch {}/{}: {} DIDs, {} rows", index + 1, batches, batch.len(), rows);
+    }
+
+    println!("{}:", if dry_run { "Rows that would be deleted" } else { "Rows deleted" });
+    for (table, count) in totals.iter().filter(|(_, count)| **count > 0) {
+        println!("  {:<28} {}", table, count);
+    }
+    println!("  {:<28} {}", "total", totals.values().sum::<u64>());
+    Ok(())
+}
+
+// One DID per line, in order, without repeats
+fn parse_dids(input: &str) -> Result<Vec<String>> {
+    let mut seen = HashSet::new();
+    let mut dids = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !line.starts_with("did:") || line.contains(char::is_whitespace) {
+            bail!("Line {} is not a DID: {}", number + 1, line);
+        }
+        if seen.insert(line) {
+            dids.push(line.to_string());
+        }
+    }
+    Ok(dids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_one_did_per_line() {
+        let dids = parse_dids("# region sunset\ndid:plc:a\n\n  did:web:b.example  \ndid:plc:a\n").unwrap();
+        assert_eq!(dids, vec!["did:plc:a", "did:web:b.example"]);
+
+        let err = parse_dids("did:plc:a\nalice.bsky.social\n").unwrap_err();
+        assert_eq!(err.to_string(), "Line 2 is not a DID: alice.bsky.social");
+    }
+}
@@ -0,0 +1,139 @@
+// payload_keys.rs
+// Compact custom data keys. APNs caps a payload at 4KB, and the long data keys
+// ("notification_id", "author_did", "thread_root", ...) take up room that post
+// excerpts could use. The app declares the highest payload version it understands on
+// PUT /device/payload-version; devices at COMPACT_VERSION get the short keys below and
+// a "v" entry naming the version, so the app knows how to read the payload. Devices
+// that never declared a version, i.e. older app versions, keep the long keys. Keys
+// without a short form are sent unchanged. The event origin keys stay in history but
+// are only sent to devices that asked for them, which the filter marks with ORIGIN_KEY.
+use std::collections::HashMap;
+
+// Marks the payload version in the data; absent means version 1, the long keys
+pub const VERSION_KEY: &str = "v";
+pub const COMPACT_VERSION: i16 = 2;
+pub const LATEST_VERSION: i16 = COMPACT_VERSION;
+
+// Set when the device asked for the event origin; never sent itself
+pub const ORIGIN_KEY: &str = "event_origin";
+const ORIGIN_KEYS: &[&str] = &["event_source", "event_seq", "event_rev", "event_received_at"];
+
+const SHORT_KEYS: &[(&str, &str)] = &[
+    ("notification_id", "n"),
+    ("type", "t"),
+    ("uri", "u"),
+    ("author_did", "a"),
+    ("handle_status", "hs"),
+    ("record_uri", "r"),
+    ("cid", "c"),
+    ("push_type", "p"),
+    ("grouping", "g"),
+    ("thread_root", "tr"),
+    ("image_url", "i"),
+    ("avatar_url", "av"),
+    ("interruption_level", "il"),
+    ("unread_total", "ut"),
+    ("unread_mentions", "um"),
+    ("vip", "vp"),
+    ("experiment", "x"),
+    ("variant", "xv"),
+    ("category", "k"),
+    ("digest", "d"),
+    ("feed_uri", "f"),
+    ("convo_id", "ci"),
+    ("message_id", "m"),
+    ("retract", "rt"),
+    ("aggregated_count", "ac"),
+    ("event_source", "es"),
+    ("event_seq", "eq"),
+    ("event_rev", "er"),
+    ("event_received_at", "et"),
+];
+
+// The version to use for a device that understands up to `declared`, or None if the
+// app declared something this server can't serve
+pub fn negotiate(declared: i16) -> Option<i16> {
+    (declared >= 1).then(|| declared.min(LATEST_VERSION))
+}
+
+// Custom data as it goes into the APNs payload
+pub fn encode(data: &HashMap<String, String>) -> Vec<(&str, &str)> {
+    let compact = data
+        .get(VERSION_KEY)
+        .and_then(|version| version.parse::<i16>().ok())
+        .is_some_and(|version| version >= COMPACT_VERSION);
+    let origin = data.get(ORIGIN_KEY).is_some_and(|origin| origin == "true");
+    let sent = data.iter().filter(move |(key, _)| {
+        key.as_str() != ORIGIN_KEY && (origin || !ORIGIN_KEYS.contains(&key.as_str()))
+    });
+    if !compact {
+        return sent.map(|(key, value)| (key.as_str(), value.as_str())).collect();
+    }
+
+    sent
+        .filter_map(|(key, value)| {
+            match SHORT_KEYS.iter().find(|(long, _)| long == key) {
+                Some((_, short)) => Some((*short, value.as_str())),
+                // A key that reads as another one's short form would be ambiguous
+                None if SHORT_KEYS.iter().any(|(_, short)| short == key) => None,
+                None => Some((key.as_str(), value.as_str())),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortens_keys_only_for_compact_devices() {
+        let mut shorts: Vec<&str> = SHORT_KEYS.iter().map(|(_, short)| *short).collect();
+        shorts.push(VERSION_KEY);
+        let count = shorts.len();
+        shorts.sort();
+        shorts.dedup();
+        assert_eq!(shorts.len(), count);
+        assert!(SHORT_KEYS.iter().all(|(long, _)| !shorts.contains(long)));
+
+        let mut data: HashMap<String, String> = [
+            ("uri", "at://did:plc:a/app.bsky.feed.post/1"),
+            ("type", "Reply"),
+            ("convo_link", "x"),
+            ("t", "caller data"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let mut long = encode(&data);
+        long.sort();
+        assert_eq!(long.len(), 4);
+        assert!(long.contains(&("uri", "at://did:plc:a/app.bsky.feed.post/1")));
+
+        // The origin only goes to devices that asked for it
+        data.insert("event_seq".to_string(), "4242".to_string());
+        assert_eq!(encode(&data).len(), 4);
+        data.insert(ORIGIN_KEY.to_string(), "true".to_string());
+        assert!(encode(&data).contains(&("event_seq", "4242")));
+        data.remove("event_seq");
+        data.remove(ORIGIN_KEY);
+
+        data.insert(VERSION_KEY.to_string(), COMPACT_VERSION.to_string());
+        let mut compact = encode(&data);
+        compact.sort();
+        assert_eq!(
+            compact,
+            vec![
+                ("convo_link", "x"),
+                ("t", "Reply"),
+                ("u", "at://did:plc:a/app.bsky.feed.post/1"),
+                ("v", "2"),
+            ]
+        );
+
+        assert_eq!(negotiate(1), Some(1));
+        assert_eq!(negotiate(7), Some(LATEST_VERSION));
+        assert_eq!(negotiate(0), None);
+    }
+}
@@ -0,0 +1,267 @@
+// plugins.rs
+// Operator-supplied filtering hooks. Each plugin sees the classified event as JSON
+// and returns a decision; any plugin can suppress the event or tag it with a
+// category that is forwarded to the app in the notification data.
+//
+// WASM plugins (built with the `wasm-plugins` feature) export:
+//   memory
+//   alloc(len: i32) -> i32                  buffer for the input JSON
+//   decide(ptr: i32, len: i32) -> i64       (out_ptr << 32) | out_len of the decision JSON
+// where the decision JSON is {"suppress": bool, "category": string?}.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::warn;
+
+use crate::models::{BlueskyEvent, NotificationType};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginDecision {
+    #[serde(default)]
+    pub suppress: bool,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PluginInput<'a> {
+    event: &'a BlueskyEvent,
+    notification_type: &'static str,
+    recipients: &'a [String],
+}
+
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+    fn decide(&self, input: &[u8]) -> Result<PluginDecision>;
+}
+
+pub struct PluginHost {
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl PluginHost {
+    pub fn new(plugins: Vec<Arc<dyn Plugin>>) -> Self {
+        Self { plugins }
+    }
+
+    // Load every configured module, failing startup rather than silently running
+    // without the operator's suppression rules
+    pub fn load(paths: &[String], fuel: u64, memory_limit_mb: usize) -> Result<Self> {
+        if paths.is_empty() {
+            return Ok(Self::new(Vec::new()));
+        }
+
+        #[cfg(feature = "wasm-plugins")]
+        {
+            let mut plugins: Vec<Arc<dyn Plugin>> = Vec::new();
+            for path in paths {
+                let plugin = wasm::WasmPlugin::load(path, fuel, memory_limit_mb * 1024 * 1024)?;
+                tracing::info!(plugin = %plugin.name(), "Loaded WASM plugin");
+                plugins.push(Arc::new(plugin));
+            }
+            Ok(Self::new(plugins))
+        }
+
+        #[cfg(not(feature = "wasm-plugins"))]
+        {
+            let _ = (fuel, memory_limit_mb);
+            anyhow::bail!("PLUGIN_PATHS is set but this build does not include the wasm-plugins feature")
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    // Run all plugins in order. A failing plugin is skipped (fail open) so a bad
+    // module cannot stop notifications entirely.
+    pub fn evaluate(
+        &self,
+        event: &BlueskyEvent,
+        notification_type: &NotificationType,
+        recipients: &[String],
+    ) -> PluginDecision {
+        let mut decision = PluginDecision::default();
+        if self.plugins.is_empty() {
+            return decision;
+        }
+
+        let input = match serde_json::to_vec(&PluginInput {
+            event,
+            notification_type: notification_type.as_str(),
+            recipients,
+        }) {
+            Ok(input) => input,
+            Err(e) => {
+                warn!("Failed to serialize plugin input: {}", e);
+                return decision;
+            }
+        };
+
+        for plugin in &self.plugins {
+            let timer = Instant::now();
+            let result = plugin.decide(&input);
+            crate::metrics::PLUGIN_EXECUTION_TIME
+                .with_label_values(&[plugin.name()])
+                .observe(timer.elapsed().as_secs_f64());
+
+            match result {
+                Ok(plugin_decision) => {
+                    let label = if plugin_decision.suppress { "suppress" } else { "allow" };
+                    crate::metrics::PLUGIN_DECISIONS
+                        .with_label_values(&[plugin.name(), label])
+                        .inc();
+
+                    if decision.category.is_none() {
+                        decision.category = plugin_decision.category;
+                    }
+                    if plugin_decision.suppress {
+                        decision.suppress = true;
+                        break;
+                    }
+                }
+                Err(e) => {
+                    warn!(plugin = %plugin.name(), "Plugin failed: {:#}", e);
+                    crate::metrics::PLUGIN_ERRORS
+                        .with_label_values(&[plugin.name()])
+                        .inc();
+                }
+            }
+        }
+
+        decision
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+mod wasm {
+    use super::{Plugin, PluginDecision};
+    use anyhow::{Context, Result};
+    use std::path::Path;
+    use wasmtime::{Config, Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+    // Decisions are tiny; anything larger is a plugin bug
+    const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+    pub struct WasmPlugin {
+        name: String,
+        engine: Engine,
+        instance_pre: InstancePre<StoreLimits>,
+        fuel: u64,
+        memory_limit_bytes: usize,
+    }
+
+    impl WasmPlugin {
+        pub fn load(path: &str, fuel: u64, memory_limit_bytes: usize) -> Result<Self> {
+            let mut config = Config::new();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config)?;
+
+            let module = Module::from_file(&engine, path)
+                .with_context(|| format!("Failed to compile plugin {}", path))?;
+            // No host imports: plugins are pure functions of their input
+            let linker: Linker<StoreLimits> = Linker::new(&engine);
+            let instance_pre = linker
+                .instantiate_pre(&module)
+                .with_context(|| format!("Plugin {} has unsupported imports", path))?;
+
+            let name = Path::new(path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.to_string());
+
+            Ok(Self {
+                name,
+                engine,
+                instance_pre,
+                fuel,
+                memory_limit_bytes,
+            })
+        }
+    }
+
+    impl Plugin for WasmPlugin {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        // Each call gets a fresh instance so plugins cannot keep state between events
+        fn decide(&self, input: &[u8]) -> Result<PluginDecision> {
+            let limits = StoreLimitsBuilder::new()
+                .memory_size(self.memory_limit_bytes)
+                .instances(1)
+                .build();
+            let mut store = Store::new(&self.engine, limits);
+            store.limiter(|limits| limits);
+            store.set_fuel(self.fuel)?;
+
+            let instance = self.instance_pre.instantiate(&mut store)?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .context("plugin does not export memory")?;
+            let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+            let decide = instance.get_typed_func::<(i32, i32), i64>(&mut store, "decide")?;
+
+            let input_len = i32::try_from(input.len()).context("plugin input too large")?;
+            let input_ptr = alloc.call(&mut store, input_len)?;
+            memory.write(&mut store, input_ptr as u32 as usize, input)?;
+
+            let packed = decide.call(&mut store, (input_ptr, input_len))?;
+            let output_ptr = (packed >> 32) as u32 as usize;
+            let output_len = packed as u32 as usize;
+            if output_len > MAX_OUTPUT_BYTES {
+                anyhow::bail!("plugin returned {} bytes", output_len);
+            }
+
+            let mut output = vec![0u8; output_len];
+            memory.read(&store, output_ptr, &mut output)?;
+            Ok(serde_json::from_slice(&output)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StaticPlugin(&'static str, Option<PluginDecision>);
+
+    impl Plugin for StaticPlugin {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn decide(&self, _input: &[u8]) -> Result<PluginDecision> {
+            self.1.clone().ok_or_else(|| anyhow::anyhow!("trap"))
+        }
+    }
+
+    #[test]
+    fn failing_plugins_are_skipped_and_suppress_wins() {
+        let event = BlueskyEvent {
+            op: "create".to_string(),
+            path: "app.bsky.feed.like/abc".to_string(),
+            cid: String::new(),
+            author: "did:plc:author".to_string(),
+            record: serde_json::json!({}),
+            timestamp: 0,
+            origin: Default::default(),
+        };
+        let host = PluginHost::new(vec![
+            Arc::new(StaticPlugin("broken", None)),
+            Arc::new(StaticPlugin(
+                "tagger",
+                Some(PluginDecision { suppress: false, category: Some("vip".to_string()) }),
+            )),
+            Arc::new(StaticPlugin(
+                "spam",
+                Some(PluginDecision { suppress: true, category: Some("spam".to_string()) }),
+            )),
+        ]);
+
+        let decision = host.evaluate(&event, &NotificationType::Like, &[]);
+        assert!(decision.suppress);
+        assert_eq!(decision.category.as_deref(), Some("vip"));
+    }
+}
@@ -0,0 +1,287 @@
+// portability.rs
+// Signed export/import of a user's settings so they can move between deployments.
+// Documents are HMAC-signed with EXPORT_SIGNING_KEY (falling back to the server secret);
+// deployments that should accept each other's exports configure the same key.
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Transaction};
+use std::collections::BTreeMap;
+
+use crate::crypto::{hmac_sha256, verify_hmac_sha256};
+use crate::models::{LabelVisibility, NotificationType};
+use crate::relationship_manager::RelationshipManager;
+use crate::tenant::Tenant;
+
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedPreferences {
+    pub mentions: bool,
+    pub replies: bool,
+    pub likes: bool,
+    pub follows: bool,
+    pub reposts: bool,
+    pub quotes: bool,
+    // Omitted when unset so documents signed before this field existed still verify
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub priority_from_mutuals: bool,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub replies_to_replies: bool,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub feed_posts: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub digest_low_priority: bool,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub dms: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dm_redact_body: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub post_edits: bool,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub list_additions: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub background_types: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub comeback_posts: bool,
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub custom_notifications: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reply_context: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub only_from_follows: bool,
+}
+
+pub(crate) fn default_true() -> bool {
+    true
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedThreshold {
+    pub notification_type: String,
+    pub min_account_age_days: Option<i32>,
+    pub min_followers: Option<i32>,
+}
+
+// Field order is the signing order; only append new fields (with serde defaults)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsDocument {
+    pub version: u32,
+    pub did: String,
+    pub exported_at: String,
+    pub preferences: ExportedPreferences,
+    #[serde(default)]
+    pub thresholds: Vec<ExportedThreshold>,
+    #[serde(default)]
+    pub mutes: Vec<String>,
+    #[serde(default)]
+    pub blocks: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub label_preferences: BTreeMap<String, LabelVisibility>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedSettings {
+    pub document: SettingsDocument,
+    pub signature: String,
+}
+
+impl SignedSettings {
+    pub fn sign(document: SettingsDocument) -> Result<Self> {
+        let signature = hmac_sha256(signing_key()?.as_bytes(), &serde_json::to_vec(&document)?);
+        Ok(Self { document, signature })
+    }
+
+    pub fn verify(&self) -> Result<bool> {
+        Ok(verify_hmac_sha256(
+            signing_key()?.as_bytes(),
+            &serde_json::to_vec(&self.document)?,
+            &self.signature,
+        ))
+    }
+}
+
+// Missing keys fail the request rather than the server
+fn signing_key() -> Result<String> {
+    std::env::var("EXPORT_SIGNING_KEY")
+        .or_else(|_| std::env::var("SERVER_ENCRYPTION_SECRET"))
+        .map_err(|_| anyhow!("EXPORT_SIGNING_KEY or SERVER_ENCRYPTION_SECRET must be set"))
+}
+
+// Collect everything the tenant of a transaction stores for a DID into an unsigned
+// document. The plaintext relationship tables hold every list, hashed storage or not.
+pub async fn export_settings(tx: &mut Transaction<'_, Postgres>, did: &str) -> Result<SettingsDocument> {
+    let device = crate::db::get_user_devices(&mut **tx, did)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No devices registered for DID"))?;
+
+    let prefs = crate::db::get_notification_preferences(&mut **tx, device.id).await?;
+    let thresholds = crate::db::get_notification_thresholds(&mut **tx, device.id).await?;
+    let label_preferences = crate::db::get_label_preferences(&mut **tx, device.id).await?;
+    let mutes = sqlx::query_scalar!(
+        "SELECT DISTINCT muted_did FROM user_mutes WHERE user_did = $1 ORDER BY muted_did",
+        did
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let blocks = sqlx::query_scalar!(
+        "SELECT DISTINCT blocked_did FROM user_blocks WHERE user_did = $1 ORDER BY blocked_did",
+        did
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(SettingsDocument {
+        version: EXPORT_VERSION,
+        did: did.to_string(),
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        preferences: ExportedPreferences {
+            mentions: prefs.mentions,
+            replies: prefs.replies,
+            likes: prefs.likes,
+            follows: prefs.follows,
+            reposts: prefs.reposts,
+            quotes: prefs.quotes,
+            priority_from_mutuals: prefs.priority_from_mutuals,
+            replies_to_replies: prefs.replies_to_replies,
+            feed_posts: prefs.feed_posts,
+            digest_low_priority: prefs.digest_low_priority,
+            dms: prefs.dms,
+            dm_redact_body: prefs.dm_redact_body,
+            post_edits: prefs.post_edits,
+            list_additions: prefs.list_additions,
+            background_types: prefs.background_types,
+            comeback_posts: prefs.comeback_posts,
+            custom_notifications: prefs.custom_notifications,
+            reply_context: prefs.reply_context,
+            only_from_follows: prefs.only_from_follows,
+        },
+        thresholds: thresholds
+            .into_iter()
+            .map(|t| ExportedThreshold {
+                notification_type: t.notification_type,
+                min_account_age_days: t.min_account_age_days,
+                min_followers: t.min_followers,
+            })
+            .collect(),
+        mutes,
+        blocks,
+        label_preferences: label_preferences.into_iter().collect(),
+    })
+}
+
+// Apply a verified document to every device of its DID in the tenant
+pub async fn import_settings(
+    pool: &Pool<Postgres>,
+    tenant: &Tenant,
+    relationship_manager: &RelationshipManager,
+    device_token: &str,
+    document: SettingsDocument,
+) -> Result<()> {
+    if document.version > EXPORT_VERSION {
+        return Err(anyhow!("Unsupported export version {}", document.version));
+    }
+    // Unknown types from a newer fork are dropped rather than rejected
+    let thresholds: Vec<_> = document
+        .thresholds
+        .into_iter()
+        .filter(|t| NotificationType::parse(&t.notification_type).is_some())
+        .collect();
+
+    let background_types: Vec<String> = document
+        .preferences
+        .background_types
+        .iter()
+        .filter(|t| NotificationType::parse(t).is_some())
+        .cloned()
+        .collect();
+
+    let mut tx = crate::tenant::begin(pool, tenant).await?;
+    let devices = crate::db::get_user_devices(&mut *tx, &document.did).await?;
+
+    for device in &devices {
+        let prefs = &document.preferences;
+        sqlx::query!(
+            r#"
+            UPDATE notification_preferences
+            SET mentions = $1, replies = $2, likes = $3, follows = $4, reposts = $5, quotes = $6,
+                priority_from_mutuals = $7, replies_to_replies = $8, feed_posts = $9,
+                digest_low_priority = $10, dms = $11, dm_redact_body = $12, post_edits = $13,
+                list_additions = $14, background_types = $15, comeback_posts = $16,
+                custom_notifications = $17, reply_context = $18, only_from_follows = $19,
+                preset = NULL, preset_version = NULL
+            WHERE user_id = $20
+            "#,
+            prefs.mentions,
+            prefs.replies,
+            prefs.likes,
+            prefs.follows,
+            prefs.reposts,
+            prefs.quotes,
+            prefs.priority_from_mutuals,
+            prefs.replies_to_replies,
+            prefs.feed_posts,
+            prefs.digest_low_priority,
+            prefs.dms,
+            prefs.dm_redact_body,
+            prefs.post_edits,
+            prefs.list_additions,
+            &background_types,
+            prefs.comeback_posts,
+            prefs.custom_notifications,
+            prefs.reply_context,
+            prefs.only_from_follows,
+            device.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM notification_thresholds WHERE user_id = $1",
+            device.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for threshold in &thresholds {
+            sqlx::query!(
+                r#"
+                INSERT INTO notification_thresholds (user_id, notification_type, min_account_age_days, min_followers)
+                VALUES ($1, $2, $3, $4)
+                "#,
+                device.id,
+                threshold.notification_type,
+                threshold.min_account_age_days,
+                threshold.min_followers
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        sqlx::query!("DELETE FROM label_preferences WHERE user_id = $1", device.id)
+            .execute(&mut *tx)
+            .await?;
+
+        for (label, visibility) in &document.label_preferences {
+            sqlx::query!(
+                "INSERT INTO label_preferences (user_id, label, visibility) VALUES ($1, $2, $3)",
+                device.id,
+                label,
+                visibility.as_str()
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    tx.commit().await?;
+
+    relationship_manager
+        .update_relationships_batch(tenant, &document.did, device_token, document.mutes, document.blocks)
+        .await
+}
@@ -9,7 +9,9 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::{Mutex, RwLock, oneshot};
 use tracing::{debug, info, warn};
-use ::time::Duration as TimeDuration;
+
+use crate::expiry::Expiry;
+use crate::shared_cache::SharedCache;
 
 // API response structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +25,87 @@ pub struct PostView {
     pub cid: String,
     pub author: Author,
     pub record: PostRecord,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    #[serde(default)]
+    pub embed: Option<serde_json::Value>,
+}
+
+// Moderation label applied to a post by a labeler (or self-applied by the author)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Label {
+    pub val: String,
+    #[serde(default)]
+    pub neg: bool,
+}
+
+// Post text for notification bodies, with its active moderation labels
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostContent {
+    pub text: String,
+    pub labels: Vec<String>,
+    // Thumbnail of the first attached image
+    #[serde(default)]
+    pub image_url: Option<String>,
+}
+
+impl PostContent {
+    fn unavailable() -> Self {
+        Self {
+            text: "Content temporarily unavailable".to_string(),
+            labels: Vec::new(),
+            image_url: None,
+        }
+    }
+}
+
+impl From<PostView> for PostContent {
+    fn from(post: PostView) -> Self {
+        // Kept whole; each notification type excerpts it to its own length
+        let text = post.record.text;
+        let labels = post
+            .labels
+            .into_iter()
+            .filter(|label| !label.neg)
+            .map(|label| label.val)
+            .collect();
+        let image_url = post.embed.as_ref().and_then(view_thumbnail);
+
+        Self { text, labels, image_url }
+    }
+}
+
+// Thumbnail URL from a hydrated embed view: images, or images alongside a quoted post
+fn view_thumbnail(embed: &serde_json::Value) -> Option<String> {
+    match embed.get("$type")?.as_str()? {
+        "app.bsky.embed.images#view" => embed.get("images")?.get(0)?.get("thumb")?.as_str().map(str::to_string),
+        "app.bsky.embed.recordWithMedia#view" => view_thumbnail(embed.get("media")?),
+        _ => None,
+    }
+}
+
+// Thumbnail URL for a post record straight off the firehose, which only carries the
+// image's blob CID, so the URL points at the AppView's image CDN
+pub fn record_thumbnail(author_did: &str, record: &serde_json::Value) -> Option<String> {
+    let mut embed = record.get("embed")?;
+    if embed.get("$type")?.as_str()? == "app.bsky.embed.recordWithMedia" {
+        embed = embed.get("media")?;
+    }
+    if embed.get("$type")?.as_str()? != "app.bsky.embed.images" {
+        return None;
+    }
+
+    let image = embed.get("images")?.get(0)?.get("image")?;
+    // Legacy blobs carry a bare "cid" instead of a ref link
+    let cid = image
+        .get("ref")
+        .and_then(|link| link.get("$link"))
+        .or_else(|| image.get("cid"))?
+        .as_str()?;
+    Some(format!(
+        "https://cdn.bsky.app/img/feed_thumbnail/plain/{}/{}@jpeg",
+        author_did, cid
+    ))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,12 +123,12 @@ pub struct PostRecord {
     pub created_at: String,
 }
 
-// Cache entry with expiration
-#[derive(Clone)]
+// Cache entry with expiration, also the shared tier's value
+#[derive(Clone, Serialize, Deserialize)]
 struct CachedPostInfo {
     uri: String,
-    text: String,
-    expires_at: Instant,
+    content: PostContent,
+    expires_at: Expiry,
 }
 
 #[derive(Clone)]
@@ -54,9 +137,10 @@ pub struct PostResolver {
     memory_cache: Arc<RwLock<HashMap<String, CachedPostInfo>>>,
     db_pool: Pool<Postgres>,
     ttl: Duration,
+    shared: SharedCache,
     bsky_service_url: String,
     api_circuit_breaker: Arc<RwLock<CircuitBreaker>>,
-    request_queue: Arc<Mutex<HashMap<String, oneshot::Sender<Result<String>>>>>,
+    request_queue: Arc<Mutex<HashMap<String, oneshot::Sender<Result<PostContent>>>>>,
     trigger_send: Arc<tokio::sync::Notify>,
 }
 
@@ -69,7 +153,7 @@ struct CircuitBreakerConfig {
 }
 
 impl PostResolver {
-    pub fn new(db_pool: Pool<Postgres>, ttl_minutes: u64, bsky_service_url: String) -> Self {
+    pub fn new(db_pool: Pool<Postgres>, ttl_minutes: u64, bsky_service_url: String, shared: SharedCache) -> Self {
         // Configure circuit breaker with appropriate settings
         let cb_config = CircuitBreakerConfig {
             failure_threshold: 5,         // Trip after 5 failures
@@ -94,6 +178,7 @@ impl PostResolver {
             memory_cache: Arc::new(RwLock::new(HashMap::new())),
             db_pool,
             ttl: Duration::from_secs(ttl_minutes * 60),
+            shared,
             bsky_service_url,
             api_circuit_breaker: Arc::new(RwLock::new(circuit_breaker)),
             request_queue,
@@ -111,6 +196,11 @@ impl PostResolver {
 
     // Main method to get post content from URI
     pub async fn get_post_content(&self, uri: &str) -> Result<String> {
+        Ok(self.get_post(uri).await?.text)
+    }
+
+    // Post text plus moderation labels
+    pub async fn get_post(&self, uri: &str) -> Result<PostContent> {
         // Create timer to measure fetching time
         let timer = std::time::Instant::now();
         
@@ -128,16 +218,16 @@ impl PostResolver {
 
         // 2. Check database cache
         let db_result = self.get_from_db_cache(uri).await?;
-        if let Some((uri_str, text)) = db_result {
+        if let Some((uri_str, content, expires_at)) = db_result {
             // Update memory cache and return content
-            self.update_memory_cache(uri_str, text.clone()).await;
+            self.update_memory_cache(uri_str, content.clone(), expires_at).await;
             // Record cache hit metric
             crate::metrics::POST_CACHE_HITS.inc();
             let elapsed = timer.elapsed().as_secs_f64();
             crate::metrics::POST_FETCH_TIME.observe(elapsed);
             
             debug!(uri = %uri, "Post content found in database cache");
-            return Ok(text);
+            return Ok(content);
         }
 
         // 3. Record cache miss metric
@@ -158,13 +248,13 @@ impl PostResolver {
         match tokio::time::timeout(Duration::from_millis(150), receiver).await {
             Ok(result) => {
                 match result {
-                    Ok(text) => {
+                    Ok(content) => {
                         // Record fetch time
                         let elapsed = timer.elapsed().as_secs_f64();
                         crate::metrics::POST_FETCH_TIME.observe(elapsed);
                         
                         debug!(uri = %uri, "Received post content from batch processor");
-                        text
+                        content
                     }
                     Err(_) => {
                         warn!(uri = %uri, "Batch processor disappeared, falling back to direct fetch");
@@ -181,15 +271,15 @@ impl PostResolver {
     }
     
     // Helper to fetch and cache an individual post (fallback)
-    async fn fetch_and_cache_individual(&self, uri: &str, timer: Instant) -> Result<String> {
+    async fn fetch_and_cache_individual(&self, uri: &str, timer: Instant) -> Result<PostContent> {
         match self.fetch_post_from_network_individual(uri).await {
-            Ok(text) => {
+            Ok(content) => {
                 // Update both caches asynchronously
                 let uri_clone = uri.to_string();
-                let text_clone = text.clone();
+                let content_clone = content.clone();
                 let self_clone = self.clone();
                 tokio::spawn(async move {
-                    if let Err(e) = self_clone.update_caches(uri_clone, text_clone).await {
+                    if let Err(e) = self_clone.update_caches(uri_clone, content_clone).await {
                         warn!("Failed to update caches: {}", e);
                     }
                 });
@@ -198,83 +288,128 @@ impl PostResolver {
                 let elapsed = timer.elapsed().as_secs_f64();
                 crate::metrics::POST_FETCH_TIME.observe(elapsed);
                 
-                Ok(text)
+                Ok(content)
             },
             Err(e) => Err(e)
         }
     }
 
     // Check memory cache for a post URI
-    async fn get_from_memory_cache(&self, uri: &str) -> Option<String> {
+    async fn get_from_memory_cache(&self, uri: &str) -> Option<PostContent> {
         let cache = self.memory_cache.read().await;
         if let Some(cached) = cache.get(uri) {
-            if cached.expires_at > Instant::now() {
-                return Some(cached.text.clone());
+            if cached.expires_at.is_live() {
+                return Some(cached.content.clone());
             }
         }
         None
     }
 
-    // Check database cache for a post URI
-    async fn get_from_db_cache(&self, uri: &str) -> Result<Option<(String, String)>> {
+    // Check the shared tier, then the database cache, for a post URI
+    async fn get_from_db_cache(&self, uri: &str) -> Result<Option<(String, PostContent, Expiry)>> {
+        if let Some(shared) = self.shared.get::<CachedPostInfo>("post", uri).await {
+            let expires_at = Expiry::stored(shared.expires_at.at(), self.ttl);
+            if expires_at.is_live() {
+                return Ok(Some((shared.uri, shared.content, expires_at)));
+            }
+        }
+
         let row = sqlx::query!(
             r#"
-            SELECT uri, text, expires_at 
+            SELECT uri, text, labels, image_url, expires_at 
             FROM post_cache 
-            WHERE uri = $1 AND expires_at > NOW()
+            WHERE uri = $1 AND expires_at > $2
             "#,
-            uri
+            uri,
+            time::OffsetDateTime::now_utc()
         )
         .fetch_optional(&self.db_pool)
         .await?;
         
         if let Some(row) = row {
-            return Ok(Some((row.uri, row.text)));
+            let content = PostContent {
+                text: row.text,
+                labels: row.labels,
+                image_url: row.image_url,
+            };
+            let expires_at = Expiry::stored(row.expires_at, self.ttl);
+            self.share(&row.uri, &content, expires_at).await;
+            return Ok(Some((row.uri, content, expires_at)));
         }
         
         Ok(None)
     }
 
+    // Seed the in-memory layer without fetching; used by bench-load
+    pub async fn prime(&self, uri: &str, content: PostContent) {
+        self.update_memory_cache(uri.to_string(), content, Expiry::after(self.ttl)).await;
+    }
+
+    async fn share(&self, uri: &str, content: &PostContent, expires_at: Expiry) {
+        let entry = CachedPostInfo {
+            uri: uri.to_string(),
+            content: content.clone(),
+            expires_at,
+        };
+        self.shared.set("post", uri, &entry, expires_at.remaining()).await;
+    }
+
     // Update memory cache with new post info
-    async fn update_memory_cache(&self, uri: String, text: String) {
+    async fn update_memory_cache(&self, uri: String, content: PostContent, expires_at: Expiry) {
         let mut cache = self.memory_cache.write().await;
         cache.insert(uri.clone(), CachedPostInfo {
             uri,
-            text,
-            expires_at: Instant::now() + self.ttl,
+            content,
+            expires_at,
         });
     }
 
     // Update both caches with new post info
-    async fn update_caches(&self, uri: String, text: String) -> Result<()> {
-        // Update database cache
-        let expires_at = time::OffsetDateTime::now_utc() + TimeDuration::minutes(60);
+    async fn update_caches(&self, uri: String, content: PostContent) -> Result<()> {
+        // Update database cache; both layers share one expiry
+        let expires_at = Expiry::after(self.ttl);
         sqlx::query!(
             r#"
-            INSERT INTO post_cache (uri, text, expires_at)
-            VALUES ($1, $2, $3)
+            INSERT INTO post_cache (uri, text, labels, image_url, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
             ON CONFLICT (uri) DO UPDATE
-            SET text = $2, expires_at = $3
+            SET text = $2, labels = $3, image_url = $4, expires_at = $5
             "#,
             uri.as_str(),
-            &text,
-            expires_at
+            &content.text,
+            &content.labels,
+            content.image_url,
+            expires_at.at()
         )
         .execute(&self.db_pool)
         .await?;
+        self.share(&uri, &content, expires_at).await;
         
         // Update memory cache
-        self.update_memory_cache(uri, text).await;
+        self.update_memory_cache(uri, content, expires_at).await;
         
         Ok(())
     }
 
+    // Feed an API call's outcome to the circuit breaker and publish its state
+    async fn record_api_result(&self, success: bool) {
+        let mut circuit_breaker = self.api_circuit_breaker.write().await;
+        if success {
+            circuit_breaker.handle_success();
+        } else {
+            circuit_breaker.handle_failure();
+        }
+        crate::metrics::record_circuit_state("post_resolver", &circuit_breaker.state());
+    }
+
     // New method to fetch multiple posts at once
-    async fn fetch_posts_batch(&self, uris: &[String]) -> Result<HashMap<String, String>> {
+    async fn fetch_posts_batch(&self, uris: &[String]) -> Result<HashMap<String, PostContent>> {
         // Check if circuit breaker is open using the correct API
         let circuit_breaker = self.api_circuit_breaker.read().await;
         // The crate uses state() which returns an enum, match on the enum type
-        let is_open = match circuit_breaker.state() {
+        let state = circuit_breaker.state();
+        crate::metrics::record_circuit_state("post_resolver", &state);
+        let is_open = match state {
             circuit_breaker::CircuitState::Open => true,
             _ => false,
         };
@@ -283,7 +418,7 @@ impl PostResolver {
             warn!("Circuit breaker open, returning fallback content for batch request");
             let mut results = HashMap::new();
             for uri in uris {
-                results.insert(uri.clone(), "Content temporarily unavailable".to_string());
+                results.insert(uri.clone(), PostContent::unavailable());
             }
             return Ok(results);
         }
@@ -308,7 +443,7 @@ impl PostResolver {
             Ok(response) => {
                 if response.status().is_success() {
                     // Record success with circuit breaker
-                    self.api_circuit_breaker.write().await.handle_success();
+                    self.record_api_result(true).await;
                     
                     match response.json::<GetPostsResponse>().await {
                         Ok(post_data) => {
@@ -316,15 +451,7 @@ impl PostResolver {
                             
                             // Process each post in the response
                             for post in post_data.posts {
-                                // Extract and truncate text
-                                let text = if post.record.text.len() > 140 {
-                                    format!("{}...", &post.record.text[..137])
-                                } else {
-                                    post.record.text
-                                };
-                                
-                                // Add to results
-                                results.insert(post.uri, text);
+                                results.insert(post.uri.clone(), PostContent::from(post));
                             }
                             
                             // Record batch metrics
@@ -338,13 +465,13 @@ impl PostResolver {
                         },
                         Err(e) => {
                             // Record failure with circuit breaker
-                            self.api_circuit_breaker.write().await.handle_failure();
+                            self.record_api_result(false).await;
                             Err(anyhow::anyhow!("Failed to parse batch post data: {}", e))
                         }
                     }
                 } else {
                     // Record failure with circuit breaker
-                    self.api_circuit_breaker.write().await.handle_failure();
+                    self.record_api_result(false).await;
                     Err(anyhow::anyhow!(
                         "Failed to fetch batch posts, status: {}", 
                         response.status()
@@ -353,24 +480,26 @@ impl PostResolver {
             },
             Err(e) => {
                 // Record failure with circuit breaker
-                self.api_circuit_breaker.write().await.handle_failure();
+                self.record_api_result(false).await;
                 Err(anyhow::anyhow!("Failed to fetch batch post content: {}", e))
             }
         }
     }
     
     // Individual post fetching as fallback (renamed from original fetch_post_from_network)
-    async fn fetch_post_from_network_individual(&self, uri: &str) -> Result<String> {
+    async fn fetch_post_from_network_individual(&self, uri: &str) -> Result<PostContent> {
         // Check if circuit breaker is open
         let circuit_breaker = self.api_circuit_breaker.read().await;
-        let is_open = match circuit_breaker.state() {
+        let state = circuit_breaker.state();
+        crate::metrics::record_circuit_state("post_resolver", &state);
+        let is_open = match state {
             circuit_breaker::CircuitState::Open => true,
             _ => false,
         };
         
         if is_open {
             warn!("Circuit breaker open, returning fallback content for {}", uri);
-            return Ok("Content temporarily unavailable".to_string());
+            return Ok(PostContent::unavailable());
         }
         drop(circuit_breaker); // Release read lock before we need to write
         
@@ -387,33 +516,23 @@ impl PostResolver {
             Ok(response) => {
                 if response.status().is_success() {
                     // Record success with circuit breaker
-                    self.api_circuit_breaker.write().await.handle_success();
+                    self.record_api_result(true).await;
                     
                     match response.json::<GetPostsResponse>().await {
                         Ok(post_data) => {
-                            // Get post text content
-                            let post_text = post_data.posts.get(0)
-                                .ok_or_else(|| anyhow::anyhow!("No posts returned for URI: {}", uri))?
-                                .record.text.clone();
-                                
-                            // Truncate if needed - don't want notification body to be too long
-                            let truncated_text = if post_text.len() > 140 {
-                                format!("{}...", &post_text[..137])
-                            } else {
-                                post_text
-                            };
-                            
-                            Ok(truncated_text)
+                            let post = post_data.posts.into_iter().next()
+                                .ok_or_else(|| anyhow::anyhow!("No posts returned for URI: {}", uri))?;
+                            Ok(PostContent::from(post))
                         },
                         Err(e) => {
                             // Record failure with circuit breaker
-                            self.api_circuit_breaker.write().await.handle_failure();
+                            self.record_api_result(false).await;
                             Err(anyhow::anyhow!("Failed to parse post data for URI {}: {}", uri, e))
                         }
                     }
                 } else {
                     // Record failure with circuit breaker
-                    self.api_circuit_breaker.write().await.handle_failure();
+                    self.record_api_result(false).await;
                     Err(anyhow::anyhow!(
                         "Failed to fetch post, status: {}", 
                         response.status()
@@ -422,26 +541,35 @@ impl PostResolver {
             },
             Err(e) => {
                 // Record failure with circuit breaker
-                self.api_circuit_breaker.write().await.handle_failure();
+                self.record_api_result(false).await;
                 Err(anyhow::anyhow!("Failed to fetch post content for URI {}: {}", uri, e))
             }
         }
     }
 
     // Fix the original fetch_post_from_network method to use fetch_post_from_network_individual
-    async fn fetch_post_from_network(&self, uri: &str) -> Result<String> {
+    async fn fetch_post_from_network(&self, uri: &str) -> Result<PostContent> {
         self.fetch_post_from_network_individual(uri).await
     }
 
+    // Drop the in-memory layer under memory pressure; the DB cache still backs lookups
+    pub async fn clear_memory_cache(&self) -> usize {
+        let mut cache = self.memory_cache.write().await;
+        let cleared = cache.len();
+        cache.clear();
+        cache.shrink_to_fit();
+        cleared
+    }
+
     // Cleanup expired entries
     pub async fn cleanup_expired(&self) -> Result<usize> {
         // Clean memory cache
         let mut memory_cleaned = 0;
         {
             let mut cache = self.memory_cache.write().await;
-            let now = Instant::now();
+            let now = time::OffsetDateTime::now_utc();
             cache.retain(|_, v| {
-                let keep = v.expires_at > now;
+                let keep = v.expires_at.is_live_at(now);
                 if !keep {
                     memory_cleaned += 1;
                 }
@@ -451,7 +579,8 @@ impl PostResolver {
         
         // Clean database cache
         let db_result = sqlx::query!(
-            "DELETE FROM post_cache WHERE expires_at <= NOW() RETURNING uri"
+            "DELETE FROM post_cache WHERE expires_at <= $1 RETURNING uri",
+            time::OffsetDateTime::now_utc()
         )
         .fetch_all(&self.db_pool)
         .await?;
@@ -543,8 +672,8 @@ impl PostResolver {
                     let self_clone = self.clone();
                     let results_clone = results.clone();
                     tokio::spawn(async move {
-                        for (uri, text) in &results_clone {
-                            if let Err(e) = self_clone.update_caches(uri.clone(), text.clone()).await {
+                        for (uri, content) in &results_clone {
+                            if let Err(e) = self_clone.update_caches(uri.clone(), content.clone()).await {
                                 warn!("Failed to update cache for {}: {}", uri, e);
                             }
                         }
@@ -552,18 +681,18 @@ impl PostResolver {
                     
                     // Respond to all requesters - move senders to avoid borrowing issues
                     for (uri, sender) in requests {
-                        if let Some(text) = results.get(&uri) {
-                            let _ = sender.send(Ok(text.clone()));
+                        if let Some(content) = results.get(&uri) {
+                            let _ = sender.send(Ok(content.clone()));
                         } else {
                             // URI wasn't found in results - do individual request as fallback
                             let self_clone = self.clone();
                             let uri_clone = uri.clone();
                             tokio::spawn(async move {
                                 match self_clone.fetch_post_from_network_individual(&uri_clone).await {
-                                    Ok(text) => {
+                                    Ok(content) => {
                                         // Also update caches
-                                        let _ = self_clone.update_caches(uri_clone.clone(), text.clone()).await;
-                                        let _ = sender.send(Ok(text));
+                                        let _ = self_clone.update_caches(uri_clone.clone(), content.clone()).await;
+                                        let _ = sender.send(Ok(content));
                                     },
                                     Err(e) => {
                                         let _ = sender.send(Err(e));
@@ -586,10 +715,10 @@ impl PostResolver {
                         let uri_clone = uri.clone();
                         tokio::spawn(async move {
                             match self_clone.fetch_post_from_network_individual(&uri_clone).await {
-                                Ok(text) => {
+                                Ok(content) => {
                                     // Also update caches
-                                    let _ = self_clone.update_caches(uri_clone.clone(), text.clone()).await;
-                                    let _ = sender.send(Ok(text));
+                                    let _ = self_clone.update_caches(uri_clone.clone(), content.clone()).await;
+                                    let _ = sender.send(Ok(content));
                                 },
                                 Err(e) => {
                                     let _ = sender.send(Err(e));
@@ -601,4 +730,35 @@ impl PostResolver {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn finds_the_first_image_thumbnail() {
+        let record = json!({
+            "text": "look",
+            "embed": {
+                "$type": "app.bsky.embed.recordWithMedia",
+                "record": { "record": { "uri": "at://did:plc:other/app.bsky.feed.post/1", "cid": "bafyquoted" } },
+                "media": {
+                    "$type": "app.bsky.embed.images",
+                    "images": [{ "alt": "", "image": { "$type": "blob", "ref": { "$link": "bafyimage" } } }]
+                }
+            }
+        });
+        assert_eq!(
+            record_thumbnail("did:plc:me", &record).as_deref(),
+            Some("https://cdn.bsky.app/img/feed_thumbnail/plain/did:plc:me/bafyimage@jpeg")
+        );
+        assert!(record_thumbnail("did:plc:me", &json!({ "text": "no images" })).is_none());
+
+        let view = json!({
+            "$type": "app.bsky.embed.images#view",
+            "images": [{ "thumb": "https://cdn.example/thumb.jpg", "fullsize": "https://cdn.example/full.jpg" }]
+        });
+        assert_eq!(view_thumbnail(&view).as_deref(), Some("https://cdn.example/thumb.jpg"));
+    }
+}
@@ -0,0 +1,235 @@
+// post_subscriptions.rs
+// Per-account "bell" subscriptions: a user subscribes to an author and gets a push for
+// each new top-level post. Authors decide who may subscribe to them with their
+// app.bsky.notification.declaration (kept in activity_declarations), which is checked
+// at delivery so a changed declaration applies from the author's next post.
+// Subscribed authors' last post times are kept so that users who opt in can be told
+// when one posts again after a long silence.
+use anyhow::Result;
+use time::{Duration, OffsetDateTime};
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use tracing::debug;
+
+use crate::models::BlueskyEvent;
+use crate::profile_resolver::ProfileResolver;
+
+pub const MAX_SUBSCRIPTIONS: usize = 1000;
+
+// Silence after which a post counts as a comeback
+const BREAK_DAYS: i64 = 60;
+
+// Who an author lets subscribe to their posts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllowSubscriptions {
+    All,
+    Followers,
+    Mutuals,
+    NoOne,
+}
+
+impl AllowSubscriptions {
+    // No declaration, or a value we don't know, means the lexicon default
+    pub fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("all") => AllowSubscriptions::All,
+            Some("mutuals") => AllowSubscriptions::Mutuals,
+            Some("none") => AllowSubscriptions::NoOne,
+            _ => AllowSubscriptions::Followers,
+        }
+    }
+}
+
+// Only newly created top-level posts are announced; replies and edits are not
+pub fn is_new_post(event: &BlueskyEvent) -> bool {
+    event.op == "create"
+        && event.path.contains("app.bsky.feed.post")
+        && event.record.get("reply").is_none()
+}
+
+// Any post, replies included, counts as activity
+pub fn is_activity(event: &BlueskyEvent) -> bool {
+    event.op == "create" && event.path.contains("app.bsky.feed.post")
+}
+
+// Records a post by a subscribed author and returns how long they had been silent,
+// if long enough to announce. Unknown until the first post seen after subscribing.
+pub async fn record_post(pool: &Pool<Postgres>, author: &str) -> Result<Option<Duration>> {
+    let previous = sqlx::query_scalar!(
+        r#"
+        UPDATE post_subscriptions s
+        SET last_post_at = NOW()
+        FROM (SELECT MAX(last_post_at) AS at FROM post_subscriptions WHERE subject_did = $1) previous
+        WHERE s.subject_did = $1
+        RETURNING previous.at
+        "#,
+        author
+    )
+    .fetch_optional(pool)
+    .await?
+    .flatten();
+
+    Ok(previous.and_then(|at| break_length(at, OffsetDateTime::now_utc())))
+}
+
+fn break_length(last_post_at: OffsetDateTime, now: OffsetDateTime) -> Option<Duration> {
+    let silence = now - last_post_at;
+    (silence >= Duration::days(BREAK_DAYS)).then_some(silence)
+}
+
+// "@alice posted for the first time in 3 months"
+pub fn comeback_title(handle: &str, silence: Duration) -> String {
+    let months = silence.whole_days() / 30;
+    if months >= 12 {
+        format!("@{} posted for the first time in over a year", handle)
+    } else {
+        format!("@{} posted for the first time in {} months", handle, months)
+    }
+}
+
+// Subject DID -> DIDs of registered users subscribed to it through any tenant
+pub async fn load(pool: &Pool<Postgres>) -> Result<HashMap<String, Vec<String>>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT s.subject_did, s.user_did
+        FROM post_subscriptions s
+        WHERE EXISTS (
+            SELECT 1 FROM user_devices d
+            WHERE d.tenant_id = s.tenant_id AND d.did = s.user_did
+            AND d.deleted_at IS NULL AND d.verified_at IS NOT NULL
+        )
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut subscriptions: HashMap<String, Vec<String>> = HashMap::new();
+    for row in rows {
+        subscriptions.entry(row.subject_did).or_default().push(row.user_did);
+    }
+    Ok(subscriptions)
+}
+
+// The subscribers the author's declaration lets hear about their posts, checked against
+// the author's relationships in batches. Fails closed: if they can't be checked, nobody
+// hears about this post.
+pub async fn permitted_subscribers(
+    pool: &Pool<Postgres>,
+    profile_resolver: &ProfileResolver,
+    author: &str,
+    subscribers: Vec<String>,
+) -> Result<Vec<String>> {
+    let declaration = sqlx::query_scalar!(
+        "SELECT allow_subscriptions FROM activity_declarations WHERE did = $1",
+        author
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let policy = AllowSubscriptions::parse(declaration.as_deref());
+    match policy {
+        AllowSubscriptions::All => return Ok(subscribers),
+        AllowSubscriptions::NoOne => return Ok(Vec::new()),
+        _ => {}
+    }
+
+    // (author follows subscriber, subscriber follows author)
+    let relationships = match profile_resolver.relationships(author, &subscribers).await {
+        Ok(relationships) => relationships,
+        Err(e) => {
+            debug!("Failed to check subscriber relationships: {}", e);
+            return Ok(Vec::new());
+        }
+    };
+    Ok(subscribers
+        .into_iter()
+        .filter(|subscriber| {
+            let (followed, following) = relationships.get(subscriber).copied().unwrap_or_default();
+            following && (followed || policy != AllowSubscriptions::Mutuals)
+        })
+        .collect())
+}
+
+pub async fn get<'e>(executor: impl sqlx::PgExecutor<'e>, did: &str) -> Result<Vec<String>> {
+    let subjects = sqlx::query_scalar!(
+        "SELECT subject_did FROM post_subscriptions WHERE user_did = $1 ORDER BY created_at",
+        did
+    )
+    .fetch_all(executor)
+    .await?;
+
+    Ok(subjects)
+}
+
+pub async fn set<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    did: &str,
+    subject: &str,
+    subscribed: bool,
+) -> Result<()> {
+    if subscribed {
+        sqlx::query!(
+            r#"
+            INSERT INTO post_subscriptions (user_did, subject_did)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            did,
+            subject
+        )
+        .execute(executor)
+        .await?;
+    } else {
+        sqlx::query!(
+            "DELETE FROM post_subscriptions WHERE user_did = $1 AND subject_did = $2",
+            did,
+            subject
+        )
+        .execute(executor)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_new_top_level_posts_are_announced() {
+        let mut event = BlueskyEvent {
+            op: "create".to_string(),
+            path: "app.bsky.feed.post/3k".to_string(),
+            cid: String::new(),
+            author: "did:plc:author".to_string(),
+            record: serde_json::json!({ "text": "hello" }),
+            timestamp: 0,
+            origin: Default::default(),
+        };
+        assert!(is_new_post(&event));
+
+        event.record = serde_json::json!({ "text": "hi", "reply": {} });
+        assert!(!is_new_post(&event));
+
+        event.record = serde_json::json!({ "text": "hello" });
+        event.op = "update".to_string();
+        assert!(!is_new_post(&event));
+
+        assert_eq!(AllowSubscriptions::parse(None), AllowSubscriptions::Followers);
+        assert_eq!(AllowSubscriptions::parse(Some("none")), AllowSubscriptions::NoOne);
+    }
+
+    #[test]
+    fn long_silences_are_comebacks() {
+        let now = OffsetDateTime::now_utc();
+        assert!(break_length(now - Duration::days(10), now).is_none());
+
+        let silence = break_length(now - Duration::days(100), now).unwrap();
+        assert_eq!(comeback_title("alice", silence), "@alice posted for the first time in 3 months");
+        assert_eq!(
+            comeback_title("alice", Duration::days(400)),
+            "@alice posted for the first time in over a year"
+        );
+    }
+}
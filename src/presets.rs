@@ -0,0 +1,202 @@
+// presets.rs
+// Named preference bundles ("Everything", "Essentials", "Mentions only") that onboarding
+// can offer as one tap. Presets live in the database and are edited on the admin API.
+// A preset lists the toggles it sets and a fallback for the notification types it
+// doesn't list, so a type added later is covered without editing every preset. The
+// behavior toggles in BEHAVIOR_COLUMNS change how notifications arrive, or who they come
+// from, rather than which types arrive, and are only set when a preset names them. Each edit bumps the preset's
+// version and is re-applied to devices still on it; changing a toggle by hand, importing
+// settings or syncing from the official app takes a device off its preset.
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres, Transaction};
+use std::collections::HashMap;
+
+use crate::default_preferences::PREFERENCE_COLUMNS;
+
+// Left alone unless a preset names them
+const BEHAVIOR_COLUMNS: &[&str] = &[
+    "priority_from_mutuals",
+    "digest_low_priority",
+    "dm_redact_body",
+    "reply_context",
+    "only_from_follows",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub label: String,
+    pub description: Option<String>,
+    // Value for notification types the preset doesn't list
+    pub fallback: bool,
+    pub toggles: HashMap<String, bool>,
+    pub version: i32,
+}
+
+impl Preset {
+    // The toggles applying the preset sets
+    pub fn resolve(&self) -> HashMap<String, bool> {
+        PREFERENCE_COLUMNS
+            .iter()
+            .filter_map(|column| match self.toggles.get(*column) {
+                Some(value) => Some((column.to_string(), *value)),
+                None if BEHAVIOR_COLUMNS.contains(column) => None,
+                None => Some((column.to_string(), self.fallback)),
+            })
+            .collect()
+    }
+}
+
+fn from_row(
+    name: String,
+    label: String,
+    description: Option<String>,
+    fallback: bool,
+    toggles: serde_json::Value,
+    version: i32,
+) -> Result<Preset> {
+    let toggles = serde_json::from_value(toggles).with_context(|| format!("Invalid toggles for preset {}", name))?;
+    Ok(Preset {
+        name,
+        label,
+        description,
+        fallback,
+        toggles,
+        version,
+    })
+}
+
+pub async fn list(pool: &Pool<Postgres>) -> Result<Vec<Preset>> {
+    sqlx::query!(
+        r#"
+        SELECT name, label, description, fallback, toggles, version
+        FROM preference_presets
+        ORDER BY name
+        "#
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| from_row(row.name, row.label, row.description, row.fallback, row.toggles, row.version))
+    .collect()
+}
+
+pub async fn get(tx: &mut Transaction<'_, Postgres>, name: &str) -> Result<Option<Preset>> {
+    sqlx::query!(
+        r#"
+        SELECT name, label, description, fallback, toggles, version
+        FROM preference_presets
+        WHERE name = $1
+        "#,
+        name
+    )
+    .fetch_optional(&mut **tx)
+    .await?
+    .map(|row| from_row(row.name, row.label, row.description, row.fallback, row.toggles, row.version))
+    .transpose()
+}
+
+// Put a device on the preset
+pub async fn apply(tx: &mut Transaction<'_, Postgres>, device_id: uuid::Uuid, preset: &Preset) -> Result<()> {
+    crate::default_preferences::apply(tx, device_id, &preset.resolve()).await?;
+    sqlx::query!(
+        "UPDATE notification_preferences SET preset = $2, preset_version = $3 WHERE user_id = $1",
+        device_id,
+        preset.name,
+        preset.version
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+// Create or edit a preset, bumping its version, and re-apply it to the devices on it.
+// Toggles must be PREFERENCE_COLUMNS. Returns the stored preset and how many devices
+// were updated.
+pub async fn upsert(
+    pool: &Pool<Postgres>,
+    name: &str,
+    label: &str,
+    description: Option<&str>,
+    fallback: bool,
+    toggles: &HashMap<String, bool>,
+) -> Result<(Preset, usize)> {
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO preference_presets (name, label, description, fallback, toggles)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (name) DO UPDATE
+        SET label = $2, description = $3, fallback = $4, toggles = $5,
+            version = preference_presets.version + 1, updated_at = NOW()
+        RETURNING version
+        "#,
+        name,
+        label,
+        description,
+        fallback,
+        serde_json::to_value(toggles)?
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let preset = Preset {
+        name: name.to_string(),
+        label: label.to_string(),
+        description: description.map(str::to_string),
+        fallback,
+        toggles: toggles.clone(),
+        version: row.version,
+    };
+
+    let followers = sqlx::query!(
+        r#"
+        SELECT p.user_id, d.did
+        FROM notification_preferences p
+        JOIN user_devices d ON d.id = p.user_id
+        WHERE p.preset = $1
+        "#,
+        name
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    for follower in &followers {
+        apply(&mut tx, follower.user_id, &preset).await?;
+    }
+
+    // Replicas drop the affected users' cached settings once the edit commits
+    let dids: std::collections::HashSet<&str> = followers.iter().map(|follower| follower.did.as_str()).collect();
+    for did in dids {
+        crate::cache_sync::publish(&mut *tx, did).await?;
+    }
+
+    tx.commit().await?;
+    Ok((preset, followers.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlisted_types_take_the_fallback() {
+        let preset = Preset {
+            name: "mentions_only".to_string(),
+            label: "Mentions only".to_string(),
+            description: None,
+            fallback: false,
+            toggles: HashMap::from([("mentions".to_string(), true), ("dm_redact_body".to_string(), true)]),
+            version: 1,
+        };
+
+        let resolved = preset.resolve();
+        assert!(resolved["mentions"]);
+        assert!(resolved["dm_redact_body"]);
+        // Every type toggle is set, including ones added after the preset was written
+        for column in PREFERENCE_COLUMNS.iter().filter(|column| !BEHAVIOR_COLUMNS.contains(column)) {
+            assert_eq!(resolved[*column], *column == "mentions");
+        }
+        assert!(!resolved.contains_key("digest_low_priority"));
+    }
+}
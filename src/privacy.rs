@@ -0,0 +1,268 @@
+// privacy.rs
+// Data export and erasure for one DID, behind GET /privacy/export and DELETE
+// /privacy/data. TABLES lists every table holding rows about a DID and how they're
+// found: by a DID column, by the DID's devices, by its device tokens, or by the
+// recipient of a notification spilled to the outbox. Export returns
+// each table's rows as JSON, without the columns in SECRET_COLUMNS; the hashed and
+// encrypted relationship copies are left out too, since they only repeat user_mutes
+// and user_blocks in a form nobody can read back. Erasure deletes the same rows in one
+// transaction and leaves a single relationship_audit_log entry with the row counts,
+// so there is a record that the erasure happened but not of what was erased.
+//
+// The API runs both in the request's tenant transaction, which only sees and erases
+// that tenant's rows: its devices and their settings, and the relationships, VIPs,
+// history and consents it stores for the DID. The rows in SHARED_TABLES belong to the
+// account rather than an app, so they're erased along with the last tenant the DID is
+// registered with. The offboarding command erases outside any tenant, so it removes
+// every tenant's rows. Caches shared by several users (did_cache, post_cache) aren't
+// per-DID data and are left to expire.
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{Postgres, Row, Transaction};
+use std::collections::BTreeMap;
+
+// How a table's rows are tied to the DID
+#[derive(Debug, Clone, Copy)]
+enum Key {
+    Did(&'static str),
+    // user_id referencing one of the DID's devices
+    Device,
+    DeviceToken,
+    // A field of a spilled channel payload
+    Payload(&'static str),
+}
+
+impl Key {
+    fn condition(&self) -> String {
+        match self {
+            Key::Did(column) => format!("{} = $1", column),
+            Key::Device => "user_id IN (SELECT id FROM user_devices WHERE did = $1)".to_string(),
+            Key::DeviceToken => "device_token IN (SELECT device_token FROM user_devices WHERE did = $1)".to_string(),
+            Key::Payload(field) => format!("payload->>'{}' = $1", field),
+        }
+    }
+}
+
+// Erased in reverse order, so rows found through the DID's devices go before the devices
+const TABLES: &[(&str, Key)] = &[
+    ("user_devices", Key::Did("did")),
+    ("notification_preferences", Key::Device),
+    ("notification_thresholds", Key::Device),
+    ("label_preferences", Key::Device),
+    ("feed_subscriptions", Key::Device),
+    ("pending_feed_digests", Key::Device),
+    ("unread_counts", Key::Device),
+    ("quiet_hours_pending", Key::Device),
+    ("activity_digest_pending", Key::Device),
+    ("device_quarantine", Key::DeviceToken),
+    ("pending_digests", Key::Did("user_did")),
+    ("user_mutes", Key::Did("user_did")),
+    ("user_blocks", Key::Did("user_did")),
+    ("firehose_blocks", Key::Did("user_did")),
+    ("user_follows", Key::Did("user_did")),
+    ("user_mutes_hashed", Key::Did("user_did")),
+    ("user_blocks_hashed", Key::Did("user_did")),
+    ("user_mutes_encrypted", Key::Did("user_did")),
+    ("user_blocks_encrypted", Key::Did("user_did")),
+    ("user_vips", Key::Did("user_did")),
+    ("user_muted_words", Key::Did("user_did")),
+    ("post_subscriptions", Key::Did("user_did")),
+    ("verification_consents", Key::Did("user_did")),
+    ("dm_consents", Key::Did("user_did")),
+    ("activity_declarations", Key::Did("did")),
+    ("atom_feeds", Key::Did("user_did")),
+    ("notification_history", Key::Did("user_did")),
+    ("notification_opens", Key::Did("user_did")),
+    ("notification_reports", Key::Did("user_did")),
+    ("delivery_log", Key::Did("user_did")),
+    ("channel_outbox", Key::Payload("user_did")),
+    ("relationship_audit_log", Key::Did("user_did")),
+];
+
+// Copies of user_mutes and user_blocks that can't be read back
+const UNEXPORTED_TABLES: &[&str] = &[
+    "user_mutes_hashed",
+    "user_blocks_hashed",
+    "user_mutes_encrypted",
+    "user_blocks_encrypted",
+];
+
+// Rows that aren't kept per tenant: mirrors of the account's own records, and state
+// the pipeline keeps for the DID as a whole
+const SHARED_TABLES: &[&str] = &[
+    "pending_digests",
+    "firehose_blocks",
+    "user_follows",
+    "activity_declarations",
+    "notification_reports",
+    "channel_outbox",
+];
+
+// Credentials and nonces, left out of exports
+const SECRET_COLUMNS: &[&str] = &["app_password_encrypted", "verification_nonce", "token_hash"];
+
+#[derive(Debug, Serialize)]
+pub struct PrivacyExport {
+    pub did: String,
+    pub exported_at: String,
+    // Rows by table
+    pub tables: BTreeMap<&'static str, serde_json::Value>,
+}
+
+fn select_sql(table: &str, key: Key) -> String {
+    format!(
+        "SELECT COALESCE(jsonb_agg(to_jsonb(t) - $2::text[]), '[]') AS rows FROM {} t WHERE {}",
+        table,
+        key.condition()
+    )
+}
+
+fn count_sql(table: &str, key: Key) -> String {
+    format!("SELECT COUNT(*) AS count FROM {} WHERE {}", table, key.condition())
+}
+
+fn delete_sql(table: &str, key: Key) -> String {
+    format!("DELETE FROM {} WHERE {}", table, key.condition())
+}
+
+pub async fn export(tx: &mut Transaction<'_, Postgres>, did: &str) -> Result<PrivacyExport> {
+    let secret_columns: Vec<String> = SECRET_COLUMNS.iter().map(|column| column.to_string()).collect();
+    let mut tables = BTreeMap::new();
+    for (table, key) in TABLES.iter().filter(|(table, _)| !UNEXPORTED_TABLES.contains(table)) {
+        let rows: serde_json::Value = sqlx::query(&select_sql(table, *key))
+            .bind(did)
+            .bind(&secret_columns)
+            .fetch_one(&mut **tx)
+            .await?
+            .try_get("rows")?;
+        tables.insert(*table, rows);
+    }
+
+    Ok(PrivacyExport {
+        did: did.to_string(),
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        tables,
+    })
+}
+
+// Who asked for an erasure, as recorded in the audit log
+#[derive(Debug, Clone, Copy)]
+pub enum ErasedBy<'a> {
+    // The user, from one of their devices
+    Device(&'a str),
+    // An operator offboarding the account
+    Operator,
+}
+
+impl ErasedBy<'_> {
+    fn action(&self) -> &'static str {
+        match self {
+            ErasedBy::Device(_) => "privacy_erasure",
+            ErasedBy::Operator => "offboarding",
+        }
+    }
+
+    fn device_token(&self) -> &str {
+        match self {
+            ErasedBy::Device(device_token) => device_token,
+            ErasedBy::Operator => "",
+        }
+    }
+}
+
+// The rows an erasure would delete, by table
+pub async fn count(tx: &mut Transaction<'_, Postgres>, did: &str) -> Result<BTreeMap<&'static str, u64>> {
+    let mut counts = BTreeMap::new();
+    for (table, key) in TABLES {
+        let count: i64 = sqlx::query(&count_sql(table, *key))
+            .bind(did)
+            .fetch_one(&mut **tx)
+            .await?
+            .try_get("count")?;
+        counts.insert(*table, count as u64);
+    }
+    Ok(counts)
+}
+
+// Delete everything the transaction's tenant stores for the DID, or every tenant's
+// outside one, and record the erasure. Returns the rows deleted by table.
+pub async fn erase(
+    tx: &mut Transaction<'_, Postgres>,
+    did: &str,
+    erased_by: ErasedBy<'_>,
+) -> Result<BTreeMap<&'static str, u64>> {
+    let registered_elsewhere = sqlx::query_scalar!(
+        r#"SELECT did_registered_elsewhere($1) AS "registered_elsewhere!""#,
+        did
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    let ((devices_table, devices_key), rest) = TABLES.split_first().expect("TABLES is not empty");
+    let mut deleted = BTreeMap::new();
+    for (table, key) in rest.iter().rev() {
+        if registered_elsewhere && SHARED_TABLES.contains(table) {
+            continue;
+        }
+        let result = sqlx::query(&delete_sql(table, *key)).bind(did).execute(&mut **tx).await?;
+        deleted.insert(*table, result.rows_affected());
+    }
+
+    // Counted before the devices go, for the audit entry
+    let devices = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM user_devices WHERE did = $1"#,
+        did
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    let mut details = deleted.clone();
+    details.insert(*devices_table, devices as u64);
+    sqlx::query!(
+        r#"
+        INSERT INTO relationship_audit_log (user_did, device_token, action, details)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        did,
+        erased_by.device_token(),
+        erased_by.action(),
+        serde_json::to_value(&details)?
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let result = sqlx::query(&delete_sql(devices_table, *devices_key))
+        .bind(did)
+        .execute(&mut **tx)
+        .await?;
+    deleted.insert(*devices_table, result.rows_affected());
+
+    crate::cache_sync::publish_scoped(&mut **tx, crate::cache_sync::Scope::Registration, did).await?;
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_rows_by_did_device_or_token() {
+        assert_eq!(
+            delete_sql("user_vips", Key::Did("user_did")),
+            "DELETE FROM user_vips WHERE user_did = $1"
+        );
+        assert_eq!(
+            delete_sql("unread_counts", Key::Device),
+            "DELETE FROM unread_counts WHERE user_id IN (SELECT id FROM user_devices WHERE did = $1)"
+        );
+        assert!(select_sql("device_quarantine", Key::DeviceToken)
+            .ends_with("FROM device_quarantine t WHERE device_token IN (SELECT device_token FROM user_devices WHERE did = $1)"));
+        assert_eq!(
+            delete_sql("channel_outbox", Key::Payload("user_did")),
+            "DELETE FROM channel_outbox WHERE payload->>'user_did' = $1"
+        );
+
+        // Devices go last, after the tables that find rows through them
+        assert_eq!(TABLES[0].0, "user_devices");
+        assert!(TABLES[1..].iter().all(|(table, _)| *table != "user_devices"));
+    }
+}
@@ -0,0 +1,349 @@
+// profile_resolver.rs
+// Profile metadata, follow relationships and list views from the AppView. A profile's
+// handle comes back as "handle.invalid" when the AppView couldn't verify that it
+// points back at the DID, which is what handle_status reads to tell a verified custom
+// domain from a handle on a hosting provider (HOSTED_HANDLE_SUFFIXES, *.bsky.social by
+// default) or one that doesn't verify.
+use anyhow::{Context, Result};
+use moka::future::Cache;
+use reqwest::Client as HttpClient;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::retry::{is_transient_http, RetryPolicy};
+
+// API response structures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetProfilesResponse {
+    pub profiles: Vec<ProfileView>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileView {
+    pub did: String,
+    pub handle: String,
+    #[serde(rename = "displayName")]
+    pub display_name: Option<String>,
+    // CDN URL of the avatar thumbnail
+    pub avatar: Option<String>,
+    #[serde(rename = "followersCount")]
+    pub followers_count: Option<i64>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetRelationshipsResponse {
+    pub relationships: Vec<RelationshipView>,
+}
+
+// Following/followedBy hold the follow record URIs when present
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelationshipView {
+    pub did: Option<String>,
+    pub following: Option<String>,
+    #[serde(rename = "followedBy")]
+    pub followed_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetListResponse {
+    pub list: ListView,
+}
+
+// Purpose is an app.bsky.graph.defs token such as "app.bsky.graph.defs#modlist"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListView {
+    pub name: String,
+    pub purpose: String,
+}
+
+// The subset of profile metadata used by notification filters and payloads
+#[derive(Debug, Clone)]
+pub struct ProfileInfo {
+    pub did: String,
+    pub followers_count: Option<i64>,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub avatar_url: Option<String>,
+    pub handle: Option<String>,
+}
+
+pub const DEFAULT_HOSTED_HANDLE_SUFFIXES: &str = "bsky.social";
+
+// getRelationships' limit on `others`
+const MAX_RELATIONSHIPS_PER_REQUEST: usize = 30;
+
+// The AppView's stand-in for a handle that failed verification
+const INVALID_HANDLE: &str = "handle.invalid";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleStatus {
+    // A domain the account verifiably controls
+    Domain,
+    // A subdomain handed out by a hosting provider
+    Hosted,
+    // Doesn't resolve back to the account
+    Invalid,
+}
+
+impl HandleStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandleStatus::Domain => "domain",
+            HandleStatus::Hosted => "hosted",
+            HandleStatus::Invalid => "invalid",
+        }
+    }
+}
+
+pub fn classify_handle(handle: &str, hosted_suffixes: &[String]) -> HandleStatus {
+    let handle = handle.to_ascii_lowercase();
+    if handle == INVALID_HANDLE {
+        return HandleStatus::Invalid;
+    }
+    let hosted = hosted_suffixes.iter().any(|suffix| {
+        handle
+            .strip_suffix(suffix.as_str())
+            .is_some_and(|prefix| prefix.ends_with('.'))
+    });
+    if hosted {
+        HandleStatus::Hosted
+    } else {
+        HandleStatus::Domain
+    }
+}
+
+impl ProfileInfo {
+    // Account age in whole days, if the AppView reported a creation date
+    pub fn account_age_days(&self) -> Option<i64> {
+        self.created_at
+            .map(|created| (chrono::Utc::now() - created).num_days())
+    }
+}
+
+impl From<ProfileView> for ProfileInfo {
+    fn from(view: ProfileView) -> Self {
+        let created_at = view
+            .created_at
+            .as_deref()
+            .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
+            .map(|dt| dt.with_timezone(&chrono::Utc));
+
+        Self {
+            did: view.did,
+            followers_count: view.followers_count,
+            created_at,
+            avatar_url: view.avatar,
+            handle: Some(view.handle),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ProfileResolver {
+    http_client: HttpClient,
+    cache: Cache<String, ProfileInfo>,
+    // Keyed by "actor|other"
+    relationships: Cache<String, (bool, bool)>,
+    lists: Cache<String, ListView>,
+    api_url: String,
+    hosted_handle_suffixes: Vec<String>,
+}
+
+impl ProfileResolver {
+    pub fn new(api_url: String, ttl_minutes: u64) -> Self {
+        // Follower counts drift slowly, so a TTL of a few hours is plenty
+        let cache: Cache<String, ProfileInfo> = Cache::builder()
+            .max_capacity(50_000)
+            .time_to_live(Duration::from_secs(ttl_minutes * 60))
+            .build();
+
+        Self {
+            http_client: HttpClient::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to create HTTP client"),
+            cache,
+            relationships: Cache::builder()
+                .max_capacity(100_000)
+                .time_to_live(Duration::from_secs(ttl_minutes * 60))
+                .build(),
+            lists: Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(Duration::from_secs(ttl_minutes * 60))
+                .build(),
+            api_url: api_url.trim_end_matches('/').to_string(),
+            hosted_handle_suffixes: vec![DEFAULT_HOSTED_HANDLE_SUFFIXES.to_string()],
+        }
+    }
+
+    pub fn with_hosted_handle_suffixes(mut self, suffixes: Vec<String>) -> Self {
+        self.hosted_handle_suffixes = suffixes;
+        self
+    }
+
+    // How far the profile's handle can be trusted, if the AppView returned one
+    pub fn handle_status(&self, profile: &ProfileInfo) -> Option<HandleStatus> {
+        let handle = profile.handle.as_deref()?;
+        Some(classify_handle(handle, &self.hosted_handle_suffixes))
+    }
+
+    // Get profile metadata for a single DID, using the cache when possible
+    pub async fn get_profile(&self, did: &str) -> Result<ProfileInfo> {
+        if let Some(profile) = self.cache.get(did) {
+            debug!(did = %did, "Profile found in cache");
+            return Ok(profile);
+        }
+
+        let mut profiles = self.fetch_profiles(&[did.to_string()]).await?;
+        profiles
+            .remove(did)
+            .ok_or_else(|| anyhow::anyhow!("No profile returned for DID: {}", did))
+    }
+
+    // (actor follows other, other follows actor)
+    pub async fn relationship(&self, actor: &str, other: &str) -> Result<(bool, bool)> {
+        let relationships = self.relationships(actor, &[other.to_string()]).await?;
+        Ok(relationships.get(other).copied().unwrap_or_default())
+    }
+
+    // (actor follows other, other follows actor) for each of others, fetching the ones
+    // not cached in as few requests as the AppView allows
+    pub async fn relationships(&self, actor: &str, others: &[String]) -> Result<HashMap<String, (bool, bool)>> {
+        let mut relationships = HashMap::with_capacity(others.len());
+        let mut missing = Vec::new();
+        for other in others {
+            match self.relationships.get(&format!("{}|{}", actor, other)) {
+                Some(relationship) => {
+                    relationships.insert(other.clone(), relationship);
+                }
+                None => missing.push(other.as_str()),
+            }
+        }
+
+        let url = format!("{}/xrpc/app.bsky.graph.getRelationships", self.api_url);
+        for chunk in missing.chunks(MAX_RELATIONSHIPS_PER_REQUEST) {
+            let mut query = vec![("actor", actor)];
+            query.extend(chunk.iter().map(|other| ("others", *other)));
+            let response = self.http_client.get(&url).query(&query).send().await?;
+
+            if !response.status().is_success() {
+                return Err(anyhow::anyhow!(
+                    "Failed to fetch relationships, status: {}",
+                    response.status()
+                ));
+            }
+
+            let data = response.json::<GetRelationshipsResponse>().await?;
+            for other in chunk {
+                let relationship = data
+                    .relationships
+                    .iter()
+                    .find(|rel| rel.did.as_deref() == Some(*other))
+                    .map(|rel| (rel.following.is_some(), rel.followed_by.is_some()))
+                    .unwrap_or_default();
+                self.relationships
+                    .insert(format!("{}|{}", actor, other), relationship)
+                    .await;
+                relationships.insert(other.to_string(), relationship);
+            }
+        }
+        Ok(relationships)
+    }
+
+    // A list's name and purpose, by list URI
+    pub async fn get_list(&self, uri: &str) -> Result<ListView> {
+        if let Some(list) = self.lists.get(uri) {
+            return Ok(list);
+        }
+
+        let url = format!("{}/xrpc/app.bsky.graph.getList", self.api_url);
+        let response = self
+            .http_client
+            .get(&url)
+            .query(&[("list", uri), ("limit", "1")])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Failed to fetch list, status: {}",
+                response.status()
+            ));
+        }
+
+        let list = response.json::<GetListResponse>().await?.list;
+        self.lists.insert(uri.to_string(), list.clone()).await;
+        Ok(list)
+    }
+
+    // Seed the cache with an empty profile so no fetch happens; used by bench-load
+    pub async fn prime(&self, did: &str) {
+        let profile = ProfileInfo {
+            did: did.to_string(),
+            followers_count: None,
+            created_at: None,
+            avatar_url: None,
+            handle: None,
+        };
+        self.cache.insert(did.to_string(), profile).await;
+    }
+
+    // Drop cached profiles under memory pressure
+    pub fn clear_cache(&self) {
+        self.cache.invalidate_all();
+        self.relationships.invalidate_all();
+        self.lists.invalidate_all();
+    }
+
+    // Fetch profiles from the AppView and populate the cache
+    async fn fetch_profiles(&self, dids: &[String]) -> Result<HashMap<String, ProfileInfo>> {
+        let url = format!("{}/xrpc/app.bsky.actor.getProfiles", self.api_url);
+        let query_params = dids
+            .iter()
+            .map(|did| ("actors", did.as_str()))
+            .collect::<Vec<_>>();
+
+        let data = RetryPolicy::new("profile_resolver")
+            .retry_if(is_transient_http)
+            .run(|_| async {
+                let response = self
+                    .http_client
+                    .get(&url)
+                    .query(&query_params)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .context("Failed to fetch profiles")?;
+                Ok(response.json::<GetProfilesResponse>().await?)
+            })
+            .await?;
+
+        let mut results = HashMap::new();
+        for view in data.profiles {
+            let profile = ProfileInfo::from(view);
+            self.cache.insert(profile.did.clone(), profile.clone()).await;
+            results.insert(profile.did.clone(), profile);
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_handles_by_how_they_verify() {
+        let hosted = vec!["bsky.social".to_string(), "pds.example".to_string()];
+        assert_eq!(classify_handle("alice.bsky.social", &hosted), HandleStatus::Hosted);
+        assert_eq!(classify_handle("Bob.PDS.example", &hosted), HandleStatus::Hosted);
+        assert_eq!(classify_handle("nytimes.com", &hosted), HandleStatus::Domain);
+        // Only subdomains are handed out; the suffix as a whole label is someone's domain
+        assert_eq!(classify_handle("notbsky.social", &hosted), HandleStatus::Domain);
+        assert_eq!(classify_handle("handle.invalid", &hosted), HandleStatus::Invalid);
+    }
+}
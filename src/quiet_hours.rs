@@ -0,0 +1,208 @@
+// quiet_hours.rs
+// Per-device do-not-disturb windows. The window is evaluated in Postgres against the
+// device's IANA timezone (in_quiet_hours()), so daylight saving needs no handling here.
+// Notifications generated inside the window are dropped or rolled up per type, and the
+// rollup is sent as a single summary once the window closes.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use tracing::{info, warn};
+
+use crate::channel::PipelineSender;
+use crate::models::{NotificationPayload, NotificationType, QuietHoursMode};
+
+// As exchanged with the app; times are "HH:MM" local to `timezone`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default)]
+    pub mode: QuietHoursMode,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+pub fn parse_time(value: &str) -> Option<time::Time> {
+    let (hour, minute) = value.split_once(':')?;
+    time::Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()
+}
+
+fn format_time(value: time::Time) -> String {
+    format!("{:02}:{:02}", value.hour(), value.minute())
+}
+
+// The device's mode if it is inside its quiet hours right now
+pub async fn active_mode(pool: &Pool<Postgres>, user_id: uuid::Uuid) -> Result<Option<QuietHoursMode>> {
+    let mode = sqlx::query_scalar!(
+        r#"
+        SELECT quiet_hours_mode
+        FROM notification_preferences
+        WHERE user_id = $1
+          AND in_quiet_hours(quiet_hours_start, quiet_hours_end, quiet_hours_timezone)
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(mode.as_deref().and_then(QuietHoursMode::parse))
+}
+
+// Hold a notification back for the summary sent when the window closes
+pub async fn defer(
+    pool: &Pool<Postgres>,
+    user_id: uuid::Uuid,
+    notification_type: &NotificationType,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO quiet_hours_pending (user_id, notification_type, count)
+        VALUES ($1, $2, 1)
+        ON CONFLICT (user_id, notification_type)
+        DO UPDATE SET count = quiet_hours_pending.count + 1, updated_at = NOW()
+        "#,
+        user_id,
+        notification_type.as_str()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Send one summary per device whose quiet hours have ended (or were turned off)
+pub async fn flush_summaries(
+    pool: &Pool<Postgres>,
+    notification_sender: &PipelineSender<NotificationPayload>,
+) -> Result<usize> {
+    let devices = sqlx::query_scalar!(
+        r#"
+        SELECT DISTINCT q.user_id
+        FROM quiet_hours_pending q
+        JOIN notification_preferences p ON p.user_id = q.user_id
+        JOIN user_devices d ON d.id = q.user_id AND d.deleted_at IS NULL
+        WHERE NOT in_quiet_hours(p.quiet_hours_start, p.quiet_hours_end, p.quiet_hours_timezone)
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut sent = 0;
+    for user_id in devices {
+        // The counts are only deleted once the summary is queued
+        let mut tx = pool.begin().await?;
+        let rows = sqlx::query!(
+            r#"
+            DELETE FROM quiet_hours_pending q
+            USING user_devices d
+            WHERE q.user_id = $1 AND d.id = q.user_id
+            RETURNING d.device_token, d.did, q.notification_type, q.count
+            "#,
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let Some(first) = rows.first() else {
+            continue;
+        };
+        let (device_token, user_did) = (first.device_token.clone(), first.did.clone());
+        let counts: Vec<_> = rows
+            .into_iter()
+            .filter_map(|row| Some((NotificationType::parse(&row.notification_type)?, row.count)))
+            .collect();
+        if counts.is_empty() {
+            tx.commit().await?;
+            continue;
+        }
+
+        let payload = crate::quota::digest_payload(user_did, device_token, counts, "While you were away");
+        if let Err(e) = notification_sender.send(payload).await {
+            warn!("Failed to queue quiet hours summary: {}", e);
+            tx.rollback().await?;
+            break;
+        }
+        tx.commit().await?;
+        sent += 1;
+    }
+
+    if sent > 0 {
+        info!("Sent {} quiet hours summaries", sent);
+    }
+    Ok(sent)
+}
+
+pub async fn get<'e>(executor: impl sqlx::PgExecutor<'e>, user_id: uuid::Uuid) -> Result<QuietHours> {
+    let row = sqlx::query!(
+        r#"
+        SELECT quiet_hours_start, quiet_hours_end, quiet_hours_timezone, quiet_hours_mode
+        FROM notification_preferences
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(QuietHours {
+        start: row.quiet_hours_start.map(format_time),
+        end: row.quiet_hours_end.map(format_time),
+        timezone: row.quiet_hours_timezone,
+        mode: QuietHoursMode::parse(&row.quiet_hours_mode).unwrap_or_default(),
+    })
+}
+
+pub async fn set<'e>(
+    executor: impl sqlx::PgExecutor<'e>,
+    user_id: uuid::Uuid,
+    start: Option<time::Time>,
+    end: Option<time::Time>,
+    timezone: &str,
+    mode: QuietHoursMode,
+) -> Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE notification_preferences
+        SET quiet_hours_start = $2, quiet_hours_end = $3, quiet_hours_timezone = $4, quiet_hours_mode = $5
+        WHERE user_id = $1
+        "#,
+        user_id,
+        start,
+        end,
+        timezone,
+        mode.as_str()
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// Postgres is the one evaluating the window, so it decides which zone names are valid
+pub async fn is_known_timezone<'e>(executor: impl sqlx::PgExecutor<'e>, timezone: &str) -> Result<bool> {
+    let known = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM pg_timezone_names WHERE name = $1) AS "known!""#,
+        timezone
+    )
+    .fetch_one(executor)
+    .await?;
+
+    Ok(known)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_local_times() {
+        let time = parse_time("22:30").unwrap();
+        assert_eq!(format_time(time), "22:30");
+        assert_eq!(format_time(parse_time("7:05").unwrap()), "07:05");
+        assert!(parse_time("24:00").is_none());
+        assert!(parse_time("22").is_none());
+    }
+}
@@ -0,0 +1,276 @@
+// quota.rs
+// Per-tenant daily notification quotas. Usage is metered by the sender; while a
+// tenant is over quota the filter defers its notifications into per-device digests
+// that are flushed on an interval instead of dropping them.
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+use std::collections::{HashMap, HashSet};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::channel::PipelineSender;
+use crate::models::{NotificationPayload, NotificationType};
+
+#[derive(Debug, Serialize)]
+pub struct TenantUsage {
+    pub tenant_id: String,
+    pub day: String,
+    pub sent: i64,
+    pub digested: i64,
+    pub daily_quota: Option<i64>,
+}
+
+pub struct QuotaTracker {
+    db_pool: Pool<Postgres>,
+    // Refreshed periodically, so a tenant can overshoot by up to one refresh interval
+    over_quota: RwLock<HashSet<String>>,
+}
+
+impl QuotaTracker {
+    pub fn new(db_pool: Pool<Postgres>) -> Self {
+        Self {
+            db_pool,
+            over_quota: RwLock::new(HashSet::new()),
+        }
+    }
+
+    pub async fn refresh(&self) -> Result<()> {
+        let tenants: HashSet<String> = sqlx::query!(
+            r#"
+            SELECT t.id
+            FROM tenants t
+            JOIN tenant_usage u ON u.tenant_id = t.id AND u.day = CURRENT_DATE
+            WHERE t.daily_quota IS NOT NULL AND u.sent >= t.daily_quota
+            "#
+        )
+        .fetch_all(&self.db_pool)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+        let mut over_quota = self.over_quota.write().await;
+        for tenant in tenants.difference(&over_quota) {
+            warn!(tenant = %tenant, "Tenant over daily quota, switching to digests");
+        }
+        *over_quota = tenants;
+        Ok(())
+    }
+
+    pub async fn is_over_quota(&self, tenant_id: &str) -> bool {
+        self.over_quota.read().await.contains(tenant_id)
+    }
+
+    // Hold a notification back for the next digest
+    pub async fn defer_to_digest(
+        &self,
+        tenant_id: &str,
+        user_did: &str,
+        device_token: &str,
+        notification_type: &NotificationType,
+    ) -> Result<()> {
+        let mut tx = self.db_pool.begin().await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO pending_digests (device_token, user_did, notification_type, count)
+            VALUES ($1, $2, $3, 1)
+            ON CONFLICT (device_token, notification_type)
+            DO UPDATE SET count = pending_digests.count + 1, updated_at = NOW()
+            "#,
+            device_token,
+            user_did,
+            notification_type.as_str()
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO tenant_usage (tenant_id, day, digested)
+            VALUES ($1, CURRENT_DATE, 1)
+            ON CONFLICT (tenant_id, day)
+            DO UPDATE SET digested = tenant_usage.digested + 1
+            "#,
+            tenant_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        crate::metrics::TENANT_NOTIFICATIONS_DEFERRED
+            .with_label_values(&[tenant_id])
+            .inc();
+        Ok(())
+    }
+
+    // Send one rollup push per device for everything deferred since the last flush
+    pub async fn flush_digests(&self, notification_sender: &PipelineSender<NotificationPayload>) -> Result<usize> {
+        let devices = sqlx::query_scalar!("SELECT DISTINCT device_token FROM pending_digests")
+            .fetch_all(&self.db_pool)
+            .await?;
+
+        let mut sent = 0;
+        for device_token in devices {
+            // The counts are only deleted once the digest is queued; another replica
+            // flushing the same device waits on the rows and then finds them gone
+            let mut tx = self.db_pool.begin().await?;
+            let rows = sqlx::query!(
+                r#"
+                DELETE FROM pending_digests
+                WHERE device_token = $1
+                RETURNING user_did, notification_type, count
+                "#,
+                device_token
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let mut by_user: HashMap<String, Vec<(NotificationType, i32)>> = HashMap::new();
+            for row in rows {
+                if let Some(notification_type) = NotificationType::parse(&row.notification_type) {
+                    by_user
+                        .entry(row.user_did)
+                        .or_default()
+                        .push((notification_type, row.count));
+                }
+            }
+
+            let mut queued = true;
+            for (user_did, counts) in by_user {
+                let payload = digest_payload(user_did, device_token.clone(), counts, "New activity");
+                if let Err(e) = notification_sender.send(payload).await {
+                    warn!("Failed to queue digest notification: {}", e);
+                    queued = false;
+                    break;
+                }
+                sent += 1;
+            }
+            if !queued {
+                tx.rollback().await?;
+                break;
+            }
+            tx.commit().await?;
+        }
+
+        if sent > 0 {
+            info!("Sent {} quota digest notifications", sent);
+        }
+        Ok(sent)
+    }
+
+    pub async fn usage(&self, days: i32) -> Result<Vec<TenantUsage>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT u.tenant_id, u.day::TEXT AS "day!", u.sent, u.digested, t.daily_quota
+            FROM tenant_usage u
+            JOIN tenants t ON t.id = u.tenant_id
+            WHERE u.day > CURRENT_DATE - $1::INTEGER
+            ORDER BY u.tenant_id, u.day
+            "#,
+            days
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| TenantUsage {
+                tenant_id: row.tenant_id,
+                day: row.day,
+                sent: row.sent,
+                digested: row.digested,
+                daily_quota: row.daily_quota,
+            })
+            .collect())
+    }
+
+    pub async fn set_quota(&self, tenant_id: &str, daily_quota: Option<i64>) -> Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE tenants SET daily_quota = $2 WHERE id = $1",
+            tenant_id,
+            daily_quota
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        self.refresh().await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+// One rollup push summarizing held-back notifications by type
+pub fn digest_payload(
+    user_did: String,
+    device_token: String,
+    mut counts: Vec<(NotificationType, i32)>,
+    title: &str,
+) -> NotificationPayload {
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    let summary = counts
+        .iter()
+        .map(|(notification_type, count)| format!("{} {}", count, digest_label(notification_type, *count)))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut data = HashMap::new();
+    data.insert("digest".to_string(), "true".to_string());
+    data.insert("notification_id".to_string(), uuid::Uuid::new_v4().to_string());
+
+    NotificationPayload {
+        user_did,
+        device_token,
+        // Most frequent type, so per-type metrics stay meaningful
+        notification_type: counts[0].0.clone(),
+        title: title.to_string(),
+        body: format!("You have {}", summary),
+        data,
+    }
+}
+
+fn digest_label(notification_type: &NotificationType, count: i32) -> &'static str {
+    let plural = count != 1;
+    match (notification_type, plural) {
+        (NotificationType::Mention, false) => "mention",
+        (NotificationType::Mention, true) => "mentions",
+        (NotificationType::Reply, false) => "reply",
+        (NotificationType::Reply, true) => "replies",
+        (NotificationType::Like, false) => "like",
+        (NotificationType::Like, true) => "likes",
+        (NotificationType::Follow, false) => "new follower",
+        (NotificationType::Follow, true) => "new followers",
+        (NotificationType::Repost, false) => "repost",
+        (NotificationType::Repost, true) => "reposts",
+        (NotificationType::Quote, false) => "quote",
+        (NotificationType::Quote, true) => "quotes",
+        (NotificationType::FeedPost, false) => "new feed post",
+        (NotificationType::FeedPost, true) => "new feed posts",
+        (NotificationType::SubscribedPost, false) => "new post",
+        (NotificationType::SubscribedPost, true) => "new posts",
+        (NotificationType::DirectMessage, false) => "message",
+        (NotificationType::DirectMessage, true) => "messages",
+        (NotificationType::ListAddition, false) => "list addition",
+        (NotificationType::ListAddition, true) => "list additions",
+        (NotificationType::Custom, false) => "update",
+        (NotificationType::Custom, true) => "updates",
+    }
+}
+
+// Meter a delivered notification against the device's tenant
+pub async fn record_usage(pool: &Pool<Postgres>, device_token: &str) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO tenant_usage (tenant_id, day, sent)
+        SELECT tenant_id, CURRENT_DATE, 1 FROM user_devices WHERE device_token = $1
+        ON CONFLICT (tenant_id, day)
+        DO UPDATE SET sent = tenant_usage.sent + 1
+        "#,
+        device_token
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// ranking.rs
+// Ranks notification types by how often they are opened, from the open
+// acknowledgments the app sends. When the notification channel is full the filter
+// sheds the types users open less often than average first, rather than a fixed
+// list. Types without OPEN_RATE_MIN_DELIVERIES deliveries in the window have no rank
+// yet, and the fixed list still applies to them.
+use anyhow::Result;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::db::OpenRate;
+use crate::models::NotificationType;
+
+const OPEN_RATE_WINDOW_DAYS: i32 = 7;
+const OPEN_RATE_MIN_DELIVERIES: i64 = 100;
+
+#[derive(Clone, Default)]
+pub struct Ranking {
+    // Whether each ranked type is opened less often than average
+    low_engagement: Arc<RwLock<HashMap<String, bool>>>,
+}
+
+impl Ranking {
+    pub async fn refresh(&self, pool: &Pool<Postgres>) -> Result<()> {
+        let rates = crate::db::get_open_rates(pool, OPEN_RATE_WINDOW_DAYS).await?;
+        *self.low_engagement.write().unwrap() = rank(&rates);
+        Ok(())
+    }
+
+    // Whether a type goes first under backpressure; None until it has enough data
+    pub fn is_low_engagement(&self, notification_type: &NotificationType) -> Option<bool> {
+        self.low_engagement
+            .read()
+            .unwrap()
+            .get(notification_type.as_str())
+            .copied()
+    }
+}
+
+fn rank(rates: &[OpenRate]) -> HashMap<String, bool> {
+    let ranked: Vec<&OpenRate> = rates
+        .iter()
+        .filter(|rate| rate.delivered >= OPEN_RATE_MIN_DELIVERIES)
+        .collect();
+    let delivered: i64 = ranked.iter().map(|rate| rate.delivered).sum();
+    let opened: i64 = ranked.iter().map(|rate| rate.opened).sum();
+    if delivered == 0 {
+        return HashMap::new();
+    }
+    let average = opened as f64 / delivered as f64;
+
+    ranked
+        .into_iter()
+        .map(|rate| (rate.notification_type.clone(), rate.open_rate < average))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(notification_type: &str, delivered: i64, opened: i64) -> OpenRate {
+        OpenRate {
+            notification_type: notification_type.to_string(),
+            delivered,
+            opened,
+            open_rate: opened as f64 / delivered as f64,
+        }
+    }
+
+    #[test]
+    fn ranks_types_against_the_average_open_rate() {
+        let ranked = rank(&[
+            rate("reply", 1000, 400),
+            rate("like", 3000, 150),
+            rate("quote", 10, 10),
+        ]);
+        assert_eq!(ranked.get("reply"), Some(&false));
+        assert_eq!(ranked.get("like"), Some(&true));
+        // Too few deliveries to rank
+        assert_eq!(ranked.get("quote"), None);
+        assert!(rank(&[]).is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+// rate_limit.rs
+// Fixed-window request limits per client IP and per DID on the DID-addressed API
+// routes, so one caller can't hammer the database through /register or /relationships.
+// Limits are set per route in RATE_LIMITS as "route=requests/seconds" pairs, with "*"
+// covering every other route. Rejected requests don't count, and a request only counts
+// against the DID it names once the handler has accepted it, so naming someone else's
+// DID can't use up their allowance.
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use moka::future::Cache;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::api::ApiState;
+
+pub const DEFAULT_RATE_LIMITS: &str = "/register=10/60,/relationships=30/60,/test-notification=5/60,*=120/60";
+
+// Same as axum's default Json limit, so buffering here rejects nothing the handler would take
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+// Distinct callers tracked per route before the oldest windows are evicted
+const MAX_TRACKED_KEYS: u64 = 100_000;
+const DEFAULT_ROUTE: &str = "*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub window: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    routes: HashMap<String, RateLimit>,
+}
+
+impl RateLimits {
+    // An empty spec disables rate limiting
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut routes = HashMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (route, limit) = entry.split_once('=')?;
+            let (requests, seconds) = limit.split_once('/')?;
+            let limit = RateLimit {
+                requests: requests.trim().parse().ok()?,
+                window: Duration::from_secs(seconds.trim().parse().ok().filter(|s| *s > 0)?),
+            };
+            routes.insert(route.trim().to_string(), limit);
+        }
+        Some(Self { routes })
+    }
+}
+
+pub struct RateLimiter {
+    limits: RateLimits,
+    // Behind load balancers the peer address is the nearest balancer's, and the client's
+    // is this many entries from the right of X-Forwarded-For
+    trusted_proxy_hops: usize,
+    // One cache per configured route so entries expire with their window
+    windows: HashMap<String, Cache<String, Arc<AtomicU32>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits, trusted_proxy_hops: usize) -> Self {
+        let windows = limits
+            .routes
+            .iter()
+            .map(|(route, limit)| {
+                let cache = Cache::builder()
+                    .max_capacity(MAX_TRACKED_KEYS)
+                    .time_to_live(limit.window)
+                    .build();
+                (route.clone(), cache)
+            })
+            .collect();
+
+        Self {
+            limits,
+            trusted_proxy_hops,
+            windows,
+        }
+    }
+
+    // The configured entry covering a route, if any
+    fn configured_route<'a>(&self, route: &'a str) -> Option<&'a str> {
+        if self.limits.routes.contains_key(route) {
+            Some(route)
+        } else if self.limits.routes.contains_key(DEFAULT_ROUTE) {
+            Some(DEFAULT_ROUTE)
+        } else {
+            None
+        }
+    }
+
+    async fn counter(&self, route: &str, kind: &str, key: &str) -> Option<(u32, Arc<AtomicU32>)> {
+        let configured = self.configured_route(route)?;
+        let (limit, windows) = (self.limits.routes.get(configured)?, self.windows.get(configured)?);
+        let counter = windows
+            .get_with(format!("{} {} {}", route, kind, key), async { Arc::new(AtomicU32::new(0)) })
+            .await;
+        Some((limit.requests, counter))
+    }
+
+    // The kind of the first key whose window is used up, without counting the request
+    async fn is_limited(&self, route: &str, keys: &[(&'static str, String)]) -> Option<&'static str> {
+        for (kind, key) in keys {
+            let (requests, counter) = self.counter(route, kind, key).await?;
+            if counter.load(Ordering::Relaxed) >= requests {
+                return Some(kind);
+            }
+        }
+        None
+    }
+
+    // Count an admitted request against a key
+    async fn charge(&self, route: &str, kind: &str, key: &str) {
+        if let Some((_, counter)) = self.counter(route, kind, key).await {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn retry_after(&self, route: &str) -> u64 {
+        self.configured_route(route)
+            .and_then(|configured| self.limits.routes.get(configured))
+            .map(|limit| limit.window.as_secs())
+            .unwrap_or(60)
+    }
+
+    fn client_ip(&self, request: &Request) -> Option<String> {
+        if self.trusted_proxy_hops > 0 {
+            // Entries left of what our own proxies appended are whatever the client sent
+            let forwarded = request
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| forwarded_client(v, self.trusted_proxy_hops));
+            if forwarded.is_some() {
+                return forwarded;
+            }
+        }
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+    }
+}
+
+// The address our outermost trusted proxy saw, `hops` entries from the right
+fn forwarded_client(header: &str, hops: usize) -> Option<String> {
+    header
+        .rsplit(',')
+        .nth(hops - 1)
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(str::to_string)
+}
+
+#[derive(Deserialize)]
+struct DidField {
+    did: Option<String>,
+}
+
+// The DID a request addresses, from the query string or the JSON body. The body is
+// buffered and put back for the handler.
+async fn extract_did(request: Request) -> Result<(Request, Option<String>), Response> {
+    if let Ok(Query(DidField { did: Some(did) })) = Query::<DidField>::try_from_uri(request.uri()) {
+        return Ok((request, Some(did)));
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, MAX_BODY_BYTES)
+        .await
+        .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE.into_response())?;
+    let did = serde_json::from_slice::<DidField>(&bytes)
+        .ok()
+        .and_then(|field| field.did);
+    Ok((Request::from_parts(parts, Body::from(bytes)), did))
+}
+
+pub async fn enforce_rate_limits(
+    State(state): State<Arc<ApiState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limiter = &state.rate_limiter;
+    let Some(route) = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+    else {
+        return next.run(request).await;
+    };
+    if limiter.configured_route(&route).is_none() {
+        return next.run(request).await;
+    }
+
+    let mut keys = Vec::with_capacity(2);
+    let ip = limiter.client_ip(&request);
+    if let Some(ip) = &ip {
+        keys.push(("ip", ip.clone()));
+    }
+    let (request, did) = match extract_did(request).await {
+        Ok(extracted) => extracted,
+        Err(response) => return response,
+    };
+    if let Some(did) = &did {
+        keys.push(("did", did.clone()));
+    }
+
+    if let Some(kind) = limiter.is_limited(&route, &keys).await {
+        warn!(route = %route, key = kind, "Rate limit exceeded");
+        crate::metrics::RATE_LIMITED_REQUESTS
+            .with_label_values(&[&route, kind])
+            .inc();
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, limiter.retry_after(&route).to_string())],
+            "Too many requests",
+        )
+            .into_response();
+    }
+
+    if let Some(ip) = &ip {
+        limiter.charge(&route, "ip", ip).await;
+    }
+    let response = next.run(request).await;
+    let rejected = matches!(response.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN);
+    if let (Some(did), false) = (&did, rejected) {
+        limiter.charge(&route, "did", did).await;
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_route_limits() {
+        let limits = RateLimits::parse(DEFAULT_RATE_LIMITS).unwrap();
+        assert_eq!(
+            limits.routes["/register"],
+            RateLimit { requests: 10, window: Duration::from_secs(60) }
+        );
+        assert_eq!(limits.routes["*"].requests, 120);
+
+        assert_eq!(RateLimits::parse("").unwrap(), RateLimits::default());
+        assert!(RateLimits::parse("/register=10").is_none());
+        assert!(RateLimits::parse("/register=10/0").is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_the_client_behind_trusted_proxies_and_counts_only_admitted_requests() {
+        let header = "6.6.6.6, 1.2.3.4, 10.0.0.2";
+        assert_eq!(forwarded_client(header, 1).as_deref(), Some("10.0.0.2"));
+        assert_eq!(forwarded_client(header, 2).as_deref(), Some("1.2.3.4"));
+        assert_eq!(forwarded_client(header, 4), None);
+
+        let limiter = RateLimiter::new(RateLimits::parse("*=1/60").unwrap(), 0);
+        let keys = [("ip", "1.2.3.4".to_string())];
+        assert_eq!(limiter.is_limited("/x", &keys).await, None);
+        assert_eq!(limiter.is_limited("/x", &keys).await, None);
+        limiter.charge("/x", "ip", "1.2.3.4").await;
+        assert_eq!(limiter.is_limited("/x", &keys).await, Some("ip"));
+    }
+}
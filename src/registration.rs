@@ -0,0 +1,223 @@
+// registration.rs
+// Who may register devices. REGISTRATION_MODE=open, the default, lets anyone in. In
+// invite mode an account's first device must redeem an invite code minted on the admin
+// API, each with an optional use limit and expiry, so semi-public deployments decide
+// who spends their APNs quota. Accounts that already have a device add more freely.
+// Allowlist mode is for closed betas and staged rollouts: only DIDs added on the admin
+// API may register, and every registration is checked.
+use anyhow::Result;
+use serde::Serialize;
+use sqlx::types::time::OffsetDateTime;
+use sqlx::{Pool, Postgres};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RegistrationMode {
+    #[default]
+    Open,
+    Invite,
+    Allowlist,
+}
+
+impl RegistrationMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "open" => Some(RegistrationMode::Open),
+            "invite" => Some(RegistrationMode::Invite),
+            "allowlist" => Some(RegistrationMode::Allowlist),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InviteCode {
+    pub code: String,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub expires_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub revoked_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+// Twelve characters in groups of four, easy to read out or type
+fn generate_code() -> String {
+    let hex = uuid::Uuid::new_v4().simple().to_string().to_uppercase();
+    format!("{}-{}-{}", &hex[0..4], &hex[4..8], &hex[8..12])
+}
+
+pub async fn mint(pool: &Pool<Postgres>, max_uses: Option<i32>, expires_in_days: Option<i32>) -> Result<InviteCode> {
+    let invite = sqlx::query_as!(
+        InviteCode,
+        r#"
+        INSERT INTO invite_codes (code, max_uses, expires_at)
+        VALUES ($1, $2, NOW() + INTERVAL '1 day' * $3)
+        RETURNING code, max_uses, uses, expires_at, revoked_at, created_at
+        "#,
+        generate_code(),
+        max_uses,
+        expires_in_days.map(f64::from)
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(invite)
+}
+
+pub async fn list(pool: &Pool<Postgres>) -> Result<Vec<InviteCode>> {
+    let invites = sqlx::query_as!(
+        InviteCode,
+        r#"
+        SELECT code, max_uses, uses, expires_at, revoked_at, created_at
+        FROM invite_codes
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(invites)
+}
+
+// Whether a live code was revoked
+pub async fn revoke(pool: &Pool<Postgres>, code: &str) -> Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE invite_codes SET revoked_at = NOW() WHERE code = $1 AND revoked_at IS NULL",
+        code
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AllowedDid {
+    pub did: String,
+    pub note: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+pub async fn list_allowed(pool: &Pool<Postgres>) -> Result<Vec<AllowedDid>> {
+    let allowed = sqlx::query_as!(
+        AllowedDid,
+        "SELECT did, note, created_at FROM registration_allowlist ORDER BY created_at DESC"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(allowed)
+}
+
+pub async fn allow(pool: &Pool<Postgres>, did: &str, note: Option<&str>) -> Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO registration_allowlist (did, note)
+        VALUES ($1, $2)
+        ON CONFLICT (did) DO UPDATE SET note = EXCLUDED.note
+        "#,
+        did,
+        note
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+// Devices already registered keep working; only new registrations are refused
+pub async fn disallow(pool: &Pool<Postgres>, did: &str) -> Result<bool> {
+    let result = sqlx::query!("DELETE FROM registration_allowlist WHERE did = $1", did)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+// Why a registration was refused, worded for the app to show
+pub const INVITE_REQUIRED: &str = "A valid invite code is required to register";
+pub const NOT_ALLOWLISTED: &str = "This account is not yet enabled for notifications on this server";
+
+// Whether the DID may register a device, redeeming an invite code if it needs one.
+// Runs in the registration transaction, so a failed registration gives the use back.
+// Returns the reason when it may not.
+pub async fn admit(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    mode: RegistrationMode,
+    did: &str,
+    invite_code: Option<&str>,
+) -> Result<Option<&'static str>> {
+    match mode {
+        RegistrationMode::Open => Ok(None),
+        RegistrationMode::Invite => {
+            let redeemed = admit_invite(tx, did, invite_code).await?;
+            Ok((!redeemed).then_some(INVITE_REQUIRED))
+        }
+        RegistrationMode::Allowlist => {
+            let allowed = sqlx::query_scalar!(
+                r#"SELECT EXISTS (SELECT 1 FROM registration_allowlist WHERE did = $1) AS "exists!""#,
+                did
+            )
+            .fetch_one(&mut **tx)
+            .await?;
+            Ok((!allowed).then_some(NOT_ALLOWLISTED))
+        }
+    }
+}
+
+async fn admit_invite(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    did: &str,
+    invite_code: Option<&str>,
+) -> Result<bool> {
+    // Accounts whose devices were all removed need a new code like anyone else
+    let known = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM user_devices WHERE did = $1 AND deleted_at IS NULL) AS "exists!""#,
+        did
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    if known {
+        return Ok(true);
+    }
+
+    let Some(code) = invite_code else {
+        return Ok(false);
+    };
+    let redeemed = sqlx::query!(
+        r#"
+        UPDATE invite_codes
+        SET uses = uses + 1
+        WHERE code = $1
+            AND revoked_at IS NULL
+            AND (max_uses IS NULL OR uses < max_uses)
+            AND (expires_at IS NULL OR expires_at > NOW())
+        "#,
+        code.trim().to_uppercase()
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(redeemed.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_readable_codes() {
+        let code = generate_code();
+        assert_eq!(code.len(), 14);
+        assert_eq!(code.matches('-').count(), 2);
+        assert_eq!(code, code.to_uppercase());
+        assert_ne!(code, generate_code());
+
+        assert_eq!(RegistrationMode::parse("invite"), Some(RegistrationMode::Invite));
+        assert_eq!(RegistrationMode::parse("allowlist"), Some(RegistrationMode::Allowlist));
+        assert_eq!(RegistrationMode::parse("closed"), None);
+    }
+}
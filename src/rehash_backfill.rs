@@ -0,0 +1,176 @@
+// rehash_backfill.rs
+// `rehash-backfill` subcommand. Relationships are written to the hashed tables
+// (user_mutes_encrypted, user_blocks_encrypted) only while USE_HASHED_RELATIONSHIPS is
+// on, so a deployment that turns it on after launch has users whose mutes and blocks
+// exist only in plaintext, and the hashed lookups miss them. This fills the hashed
+// tables from the plaintext ones, one user per transaction, in batches of users with a
+// progress line per batch. A user counts as done once they have hashed rows for a
+// table, which is also what a relationship update leaves behind, so the command can be
+// interrupted and rerun: it picks up the users still missing. Needs the
+// SERVER_ENCRYPTION_SECRET the service runs with.
+use anyhow::{Context, Result};
+use sqlx::{Pool, Postgres};
+
+use crate::crypto::CryptoUtils;
+
+const DEFAULT_BATCH_SIZE: i64 = 500;
+
+pub async fn run_command(pool: &Pool<Postgres>, args: &[String]) -> Result<()> {
+    let batch_size = match args {
+        [] => DEFAULT_BATCH_SIZE,
+        [flag, size] if flag == "--batch-size" => size
+            .parse()
+            .ok()
+            .filter(|size| *size > 0)
+            .context("--batch-size must be a positive number")?,
+        _ => {
+            println!("Usage:");
+            println!("  rehash-backfill [--batch-size <users>]");
+            return Ok(());
+        }
+    };
+
+    let crypto = CryptoUtils::new()?;
+    let pending = count_pending(pool).await?;
+    println!("{} users have relationships missing from the hashed tables", pending);
+
+    let mut cursor = String::new();
+    let (mut users, mut mutes, mut blocks) = (0, 0, 0);
+    loop {
+        let batch = pending_users(pool, &cursor, batch_size).await?;
+        let Some(last) = batch.last() else {
+            break;
+        };
+        cursor = last.clone();
+
+        for user_did in &batch {
+            let (user_mutes, user_blocks) = backfill_user(pool, &crypto, user_did)
+                .await
+                .with_context(|| format!("Failed to backfill {}", user_did))?;
+            mutes += user_mutes;
+            blocks += user_blocks;
+        }
+        users += batch.len();
+        println!(
+            "Backfilled {}/{} users ({} mutes, {} blocks), last {}",
+            users, pending, mutes, blocks, cursor
+        );
+    }
+
+    println!("Done: {} users, {} mutes, {} blocks", users, mutes, blocks);
+    Ok(())
+}
+
+async fn count_pending(pool: &Pool<Postgres>) -> Result<i64> {
+    let count = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM (
+            SELECT user_did FROM user_mutes m
+            WHERE NOT EXISTS (SELECT 1 FROM user_mutes_encrypted e WHERE e.tenant_id = m.tenant_id AND e.user_did = m.user_did)
+            UNION
+            SELECT user_did FROM user_blocks b
+            WHERE NOT EXISTS (SELECT 1 FROM user_blocks_encrypted e WHERE e.tenant_id = b.tenant_id AND e.user_did = b.user_did)
+        ) pending
+        "#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+// The next users after `cursor` with plaintext rows and no hashed rows for a table
+async fn pending_users(pool: &Pool<Postgres>, cursor: &str, limit: i64) -> Result<Vec<String>> {
+    let users = sqlx::query_scalar!(
+        r#"
+        SELECT user_did AS "user_did!" FROM (
+            SELECT user_did FROM user_mutes m
+            WHERE NOT EXISTS (SELECT 1 FROM user_mutes_encrypted e WHERE e.tenant_id = m.tenant_id AND e.user_did = m.user_did)
+            UNION
+            SELECT user_did FROM user_blocks b
+            WHERE NOT EXISTS (SELECT 1 FROM user_blocks_encrypted e WHERE e.tenant_id = b.tenant_id AND e.user_did = b.user_did)
+        ) pending
+        WHERE user_did > $1
+        ORDER BY user_did
+        LIMIT $2
+        "#,
+        cursor,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(users)
+}
+
+// Hash one user's plaintext mutes and blocks into the tables they're missing from,
+// for each tenant that has none yet. The plaintext rows are locked so a relationship
+// update for the user, which rewrites both copies, can't interleave with the backfill.
+async fn backfill_user(pool: &Pool<Postgres>, crypto: &CryptoUtils, user_did: &str) -> Result<(usize, usize)> {
+    let mut tx = pool.begin().await?;
+
+    let (tenants, muted): (Vec<String>, Vec<String>) = sqlx::query!(
+        r#"
+        SELECT tenant_id, muted_did FROM user_mutes m
+        WHERE user_did = $1
+          AND NOT EXISTS (SELECT 1 FROM user_mutes_encrypted e WHERE e.tenant_id = m.tenant_id AND e.user_did = m.user_did)
+        FOR UPDATE
+        "#,
+        user_did
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.tenant_id, crypto.hash_did(&row.muted_did, user_did)))
+    .unzip();
+
+    if !muted.is_empty() {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_mutes_encrypted (tenant_id, user_did, muted_did_encrypted)
+            SELECT tenant_id, $1, pgp_sym_encrypt(hash, $4)
+            FROM UNNEST($2::text[], $3::text[]) AS hashes(tenant_id, hash)
+            ON CONFLICT DO NOTHING
+            "#,
+            user_did,
+            &tenants,
+            &muted,
+            crypto.server_secret
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let (tenants, blocked): (Vec<String>, Vec<String>) = sqlx::query!(
+        r#"
+        SELECT tenant_id, blocked_did FROM user_blocks b
+        WHERE user_did = $1
+          AND NOT EXISTS (SELECT 1 FROM user_blocks_encrypted e WHERE e.tenant_id = b.tenant_id AND e.user_did = b.user_did)
+        FOR UPDATE
+        "#,
+        user_did
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| (row.tenant_id, crypto.hash_did(&row.blocked_did, user_did)))
+    .unzip();
+
+    if !blocked.is_empty() {
+        sqlx::query!(
+            r#"
+            INSERT INTO user_blocks_encrypted (tenant_id, user_did, blocked_did_encrypted)
+            SELECT tenant_id, $1, pgp_sym_encrypt(hash, $4)
+            FROM UNNEST($2::text[], $3::text[]) AS hashes(tenant_id, hash)
+            ON CONFLICT DO NOTHING
+            "#,
+            user_did,
+            &tenants,
+            &blocked,
+            crypto.server_secret
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok((muted.len(), blocked.len()))
+}
@@ -8,27 +8,53 @@ use tracing::{debug, error, info, warn};
 
 use crate::crypto::CryptoUtils;
 use crate::models::UserDevice;
+use crate::muted_words::{MutedWord, MutedWordTarget};
+use crate::shared_cache::SharedCache;
+
+const CACHE_TTL: Duration = Duration::from_secs(3600);
 
 pub struct RelationshipManager {
     // Moka caches
     mutes_cache: Cache<String, HashSet<String>>, // user_did -> set of muted_dids
     blocks_cache: Cache<String, HashSet<String>>, // user_did -> set of blocked_dids
+    vips_cache: Cache<String, HashSet<String>>, // user_did -> set of vip_dids
+    muted_words_cache: Cache<String, Vec<MutedWord>>,
+    follows_cache: Cache<String, HashSet<String>>, // user_did -> set of followed_dids
+    // DIDs with a verified, live device, so the firehose can skip everyone else's blocks
+    // and follows without a query
+    registered: std::sync::RwLock<HashSet<String>>,
+    shared: SharedCache,
     db_pool: Pool<Postgres>,
     crypto: CryptoUtils, // Add crypto utils
     use_hashed_storage: bool, // Flag to control which storage to use
 }
 
 impl RelationshipManager {
-    pub fn new(db_pool: Pool<Postgres>) -> Self {
+    pub fn new(db_pool: Pool<Postgres>, shared: SharedCache) -> Self {
         // Create caches with reasonable TTL and size limits
         let mutes_cache: Cache<String, HashSet<String>> = Cache::builder()
             .max_capacity(10_000)
-            .time_to_live(Duration::from_secs(3600)) // 1 hour TTL
+            .time_to_live(CACHE_TTL)
             .build();
 
         let blocks_cache: Cache<String, HashSet<String>> = Cache::builder()
             .max_capacity(10_000)
-            .time_to_live(Duration::from_secs(3600)) // 1 hour TTL
+            .time_to_live(CACHE_TTL)
+            .build();
+
+        let vips_cache: Cache<String, HashSet<String>> = Cache::builder()
+            .max_capacity(10_000)
+            .time_to_live(CACHE_TTL)
+            .build();
+
+        let muted_words_cache: Cache<String, Vec<MutedWord>> = Cache::builder()
+            .max_capacity(10_000)
+            .time_to_live(CACHE_TTL)
+            .build();
+
+        let follows_cache: Cache<String, HashSet<String>> = Cache::builder()
+            .max_capacity(10_000)
+            .time_to_live(CACHE_TTL)
             .build();
 
         // Create crypto utils
@@ -46,18 +72,59 @@ impl RelationshipManager {
         Self {
             mutes_cache,
             blocks_cache,
+            vips_cache,
+            muted_words_cache,
+            follows_cache,
+            registered: std::sync::RwLock::new(HashSet::new()),
+            shared,
             db_pool,
             crypto,
             use_hashed_storage,
         }
     }
 
+    pub fn is_registered(&self, did: &str) -> bool {
+        self.registered.read().unwrap().contains(did)
+    }
+
+    // Reload the whole registered-user set
+    pub async fn load_registered(&self) -> Result<()> {
+        let users = crate::db::get_registered_users(&self.db_pool).await?;
+        *self.registered.write().unwrap() = users.into_iter().collect();
+        Ok(())
+    }
+
+    // Recheck one DID after its devices may have changed
+    pub async fn refresh_registered(&self, did: &str) -> Result<()> {
+        let registered = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM user_devices WHERE did = $1 AND deleted_at IS NULL AND verified_at IS NOT NULL
+            ) AS "exists!"
+            "#,
+            did
+        )
+        .fetch_one(&self.db_pool)
+        .await?;
+        let mut users = self.registered.write().unwrap();
+        if registered {
+            users.insert(did.to_string());
+        } else {
+            users.remove(did);
+        }
+        Ok(())
+    }
+
     // Check if user_did has muted target_did
     pub async fn is_muted(&self, user_did: &str, target_did: &str) -> bool {
-        // Check memory cache first (which contains plaintext DIDs)
+        // Check memory cache first (which contains plaintext DIDs), then the shared tier's
+        // hashes
         if let Some(mutes) = self.mutes_cache.get(user_did) {
             return mutes.contains(target_did);
         }
+        if let Some(found) = self.shared_contains("mutes", user_did, target_did).await {
+            return found;
+        }
 
         // If using hashed storage and not in cache, check directly with hash comparison
         if self.use_hashed_storage {
@@ -97,10 +164,14 @@ impl RelationshipManager {
 
     // Check if user_did has blocked target_did
     pub async fn is_blocked(&self, user_did: &str, target_did: &str) -> bool {
-        // Check memory cache first (which contains plaintext DIDs)
+        // Check memory cache first (which contains plaintext DIDs), then the shared tier's
+        // hashes
         if let Some(blocks) = self.blocks_cache.get(user_did) {
             return blocks.contains(target_did);
         }
+        if let Some(found) = self.shared_contains("blocks", user_did, target_did).await {
+            return found;
+        }
 
         // If using hashed storage and not in cache, check directly with hash comparison
         if self.use_hashed_storage {
@@ -138,8 +209,316 @@ impl RelationshipManager {
         }
     }
 
+    // Check if user_did has marked target_did as a VIP. Fails closed, so an outage
+    // only costs VIP handling, never delivery.
+    pub async fn is_vip(&self, user_did: &str, target_did: &str) -> bool {
+        if let Some(set) = self.vips_cache.get(user_did) {
+            return set.contains(target_did);
+        }
+        if let Some(found) = self.shared_contains("vips", user_did, target_did).await {
+            return found;
+        }
+        match self.get_vips(user_did).await {
+            Ok(vips) => vips.contains(target_did),
+            Err(e) => {
+                error!("Failed to load VIPs for {}: {}", user_did, e);
+                false
+            }
+        }
+    }
+
+    // A user's VIP list, from cache when warm
+    pub async fn get_vips(&self, user_did: &str) -> Result<HashSet<String>> {
+        if let Some(vips) = self.vips_cache.get(user_did) {
+            return Ok(vips);
+        }
+        let generation = self.shared.generation("vips", user_did).await;
+
+        let vips = Self::load_vips(&self.db_pool, user_did).await?;
+
+        self.store(&self.vips_cache, "vips", user_did, vips.clone(), generation).await;
+
+        Ok(vips)
+    }
+
+    // A user's VIPs straight from the DB. On the pool that's every app's list, as the
+    // filter applies; in a tenant transaction it's the one the app manages.
+    pub async fn load_vips<'e>(executor: impl sqlx::PgExecutor<'e>, user_did: &str) -> Result<HashSet<String>> {
+        let vips = sqlx::query_scalar!(
+            "SELECT vip_did FROM user_vips WHERE user_did = $1",
+            user_did
+        )
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .collect();
+
+        Ok(vips)
+    }
+
+    // Replace a user's VIP list; the caller commits and publishes the invalidation
+    pub async fn set_vips(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+        user_did: &str,
+        vips: &[String],
+    ) -> Result<()> {
+        sqlx::query!("DELETE FROM user_vips WHERE user_did = $1", user_did)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to clear VIPs")?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO user_vips (user_did, vip_did)
+            SELECT $1, vip_did FROM UNNEST($2::text[]) AS vip_did
+            ON CONFLICT DO NOTHING
+            "#,
+            user_did,
+            vips
+        )
+        .execute(&mut **tx)
+        .await
+        .context("Failed to save VIPs")?;
+
+        Ok(())
+    }
+
+    // Check a post against the user's muted words. Fails open, like is_muted.
+    pub async fn has_muted_word(&self, user_did: &str, record: &serde_json::Value) -> bool {
+        match self.get_muted_words(user_did).await {
+            Ok(words) => crate::muted_words::matches(&words, record, time::OffsetDateTime::now_utc()),
+            Err(e) => {
+                error!("Failed to load muted words for {}: {}", user_did, e);
+                false
+            }
+        }
+    }
+
+    // A user's muted words, from cache when warm
+    pub async fn get_muted_words(&self, user_did: &str) -> Result<Vec<MutedWord>> {
+        if let Some(words) = self.muted_words_cache.get(user_did) {
+            return Ok(words);
+        }
+
+        let words = Self::load_muted_words(&self.db_pool, user_did).await?;
+
+        self.muted_words_cache.insert(user_did.to_string(), words.clone()).await;
+
+        Ok(words)
+    }
+
+    // A user's muted words straight from the DB, scoped like load_vips
+    pub async fn load_muted_words<'e>(executor: impl sqlx::PgExecutor<'e>, user_did: &str) -> Result<Vec<MutedWord>> {
+        let words = sqlx::query!(
+            "SELECT value, targets, expires_at FROM user_muted_words WHERE user_did = $1 ORDER BY value",
+            user_did
+        )
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .map(|row| MutedWord {
+            value: row.value,
+            targets: row.targets.iter().filter_map(|target| MutedWordTarget::parse(target)).collect(),
+            expires_at: row.expires_at,
+        })
+        .collect();
+
+        Ok(words)
+    }
+
+    // Replace a user's muted words; the caller commits and publishes the invalidation
+    pub async fn set_muted_words(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+        user_did: &str,
+        words: &[MutedWord],
+    ) -> Result<()> {
+        sqlx::query!("DELETE FROM user_muted_words WHERE user_did = $1", user_did)
+            .execute(&mut **tx)
+            .await
+            .context("Failed to clear muted words")?;
+
+        // Targets go in comma-joined, as arrays of arrays must all be the same length
+        let values: Vec<String> = words.iter().map(|word| word.value.clone()).collect();
+        let targets: Vec<String> = words
+            .iter()
+            .map(|word| {
+                word.targets
+                    .iter()
+                    .map(|target| target.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect();
+        let expires_at: Vec<Option<time::OffsetDateTime>> = words.iter().map(|word| word.expires_at).collect();
+        sqlx::query!(
+            r#"
+            INSERT INTO user_muted_words (user_did, value, targets, expires_at)
+            SELECT $1, value, string_to_array(targets, ','), expires_at
+            FROM UNNEST($2::text[], $3::text[], $4::timestamptz[]) AS w(value, targets, expires_at)
+            ON CONFLICT DO NOTHING
+            "#,
+            user_did,
+            &values,
+            &targets,
+            &expires_at as &[Option<time::OffsetDateTime>]
+        )
+        .execute(&mut **tx)
+        .await
+        .context("Failed to save muted words")?;
+
+        Ok(())
+    }
+
+    // Whether user_did follows target_did. Fails open, so an outage lets notifications
+    // through rather than holding back everyone's.
+    pub async fn follows(&self, user_did: &str, target_did: &str) -> bool {
+        if let Some(set) = self.follows_cache.get(user_did) {
+            return set.contains(target_did);
+        }
+        if let Some(found) = self.shared_contains("follows", user_did, target_did).await {
+            return found;
+        }
+        match self.get_follows(user_did).await {
+            Ok(follows) => follows.contains(target_did),
+            Err(e) => {
+                error!("Failed to load follows for {}: {}", user_did, e);
+                true
+            }
+        }
+    }
+
+    // The accounts a user follows, from cache when warm
+    pub async fn get_follows(&self, user_did: &str) -> Result<HashSet<String>> {
+        if let Some(follows) = self.follows_cache.get(user_did) {
+            return Ok(follows);
+        }
+        let generation = self.shared.generation("follows", user_did).await;
+
+        let follows: HashSet<String> = sqlx::query_scalar!(
+            "SELECT DISTINCT followed_did FROM user_follows WHERE user_did = $1",
+            user_did
+        )
+        .fetch_all(&self.db_pool)
+        .await?
+        .into_iter()
+        .collect();
+
+        self.store(&self.follows_cache, "follows", user_did, follows.clone(), generation).await;
+
+        Ok(follows)
+    }
+
+    // A follow a registered user made, seen on the firehose. Returns false, storing
+    // nothing, when the user isn't registered.
+    pub async fn record_follow(&self, user_did: &str, rkey: &str, followed_did: &str) -> Result<bool> {
+        if !self.is_registered(user_did) {
+            return Ok(false);
+        }
+        let mut tx = self.db_pool.begin().await?;
+
+        let recorded = sqlx::query!(
+            r#"
+            INSERT INTO user_follows (user_did, rkey, followed_did)
+            SELECT $1, $2, $3
+            WHERE EXISTS (SELECT 1 FROM user_devices WHERE did = $1 AND deleted_at IS NULL)
+            ON CONFLICT (user_did, rkey) DO UPDATE SET followed_did = EXCLUDED.followed_did
+            "#,
+            user_did,
+            rkey,
+            followed_did
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to record follow")?
+        .rows_affected()
+            > 0;
+        if !recorded {
+            return Ok(false);
+        }
+
+        crate::cache_sync::publish_scoped(&mut *tx, crate::cache_sync::Scope::Follows, user_did).await?;
+        tx.commit().await.context("Failed to commit follow")?;
+
+        self.drop_cached("follows", user_did).await;
+        Ok(true)
+    }
+
+    // Undo a follow recorded by record_follow or an import when its record is deleted.
+    // Returns false when the follow wasn't one we recorded.
+    pub async fn remove_follow(&self, user_did: &str, rkey: &str) -> Result<bool> {
+        if !self.is_registered(user_did) {
+            return Ok(false);
+        }
+        let mut tx = self.db_pool.begin().await?;
+
+        let Some(followed_did) = sqlx::query_scalar!(
+            "DELETE FROM user_follows WHERE user_did = $1 AND rkey = $2 RETURNING followed_did",
+            user_did,
+            rkey
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to remove follow")?
+        else {
+            return Ok(false);
+        };
+
+        // Another follow record may still name the same account
+        let still_followed = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM user_follows WHERE user_did = $1 AND followed_did = $2) AS "exists!""#,
+            user_did,
+            followed_did
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if !still_followed {
+            crate::cache_sync::publish_scoped(&mut *tx, crate::cache_sync::Scope::Follows, user_did).await?;
+        }
+        tx.commit().await.context("Failed to commit unfollow")?;
+
+        if !still_followed {
+            self.drop_cached("follows", user_did).await;
+        }
+        Ok(true)
+    }
+
+    // Replace a user's follows with the (rkey, followed_did) pairs read from their repo
+    pub async fn replace_follows(&self, user_did: &str, follows: &[(String, String)]) -> Result<()> {
+        let (rkeys, followed_dids): (Vec<String>, Vec<String>) = follows.iter().cloned().unzip();
+        let mut tx = self.db_pool.begin().await?;
+
+        sqlx::query!("DELETE FROM user_follows WHERE user_did = $1", user_did)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to clear follows")?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO user_follows (user_did, rkey, followed_did)
+            SELECT $1, rkey, followed_did FROM UNNEST($2::text[], $3::text[]) AS f(rkey, followed_did)
+            ON CONFLICT DO NOTHING
+            "#,
+            user_did,
+            &rkeys,
+            &followed_dids
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to save follows")?;
+
+        crate::cache_sync::publish_scoped(&mut *tx, crate::cache_sync::Scope::Follows, user_did).await?;
+        tx.commit().await.context("Failed to commit follows")?;
+
+        self.drop_cached("follows", user_did).await;
+        Ok(())
+    }
+
     // Load mutes for a user from DB and update cache
     async fn load_mutes_for_user(&self, user_did: &str) -> Result<HashSet<String>> {
+        let generation = self.shared.generation("mutes", user_did).await;
         let mutes = if self.use_hashed_storage {
             self.load_mutes_for_user_plaintext(user_did).await?
         } else {
@@ -147,15 +526,14 @@ impl RelationshipManager {
         };
 
         // Update cache
-        self.mutes_cache
-            .insert(user_did.to_string(), mutes.clone())
-            .await;
+        self.store(&self.mutes_cache, "mutes", user_did, mutes.clone(), generation).await;
 
         Ok(mutes)
     }
 
     // Load blocks for a user from DB and update cache
     async fn load_blocks_for_user(&self, user_did: &str) -> Result<HashSet<String>> {
+        let generation = self.shared.generation("blocks", user_did).await;
         let blocks = if self.use_hashed_storage {
             self.load_blocks_for_user_plaintext(user_did).await?
         } else {
@@ -163,9 +541,7 @@ impl RelationshipManager {
         };
 
         // Update cache
-        self.blocks_cache
-            .insert(user_did.to_string(), blocks.clone())
-            .await;
+        self.store(&self.blocks_cache, "blocks", user_did, blocks.clone(), generation).await;
 
         Ok(blocks)
     }
@@ -249,19 +625,26 @@ impl RelationshipManager {
         Ok(blocks)
     }
 
-    // Authenticate device token before updating relationships
-    async fn authenticate_device(&self, did: &str, device_token: &str) -> Result<UserDevice> {
+    // Authenticate a device token for a DID. Run it in the request's tenant transaction,
+    // so a token registered with one app can't act for the DID in another.
+    pub async fn authenticate_device<'e>(
+        &self,
+        executor: impl sqlx::PgExecutor<'e>,
+        did: &str,
+        device_token: &str,
+    ) -> Result<UserDevice> {
+        let device_token = crate::db::normalize_device_token(device_token);
         let device = sqlx::query_as!(
             UserDevice,
             r#"
-            SELECT id, did, device_token, created_at, updated_at
+            SELECT id, did, device_token, created_at, updated_at, tenant_id
             FROM user_devices
-            WHERE did = $1 AND device_token = $2
+            WHERE did = $1 AND device_token = $2 AND deleted_at IS NULL AND verified_at IS NOT NULL
             "#,
             did,
             device_token
         )
-        .fetch_optional(&self.db_pool)
+        .fetch_optional(executor)
         .await
         .context("Error querying device")?;
 
@@ -271,19 +654,22 @@ impl RelationshipManager {
         }
     }
 
-    // Update both mutes and blocks in a single batch operation - with authentication
+    // Update both mutes and blocks in a single batch operation - with authentication.
+    // Both run in a transaction scoped to the app the request came from, which only
+    // replaces that app's lists for the DID.
     pub async fn update_relationships_batch(
         &self,
+        tenant: &crate::tenant::Tenant,
         user_did: &str,
         device_token: &str,
         mutes: Vec<String>,
         blocks: Vec<String>,
     ) -> Result<()> {
-        // Authenticate first
-        let device = self.authenticate_device(user_did, device_token).await?;
-
         // Start a transaction for the entire batch
-        let mut tx = self.db_pool.begin().await?;
+        let mut tx = crate::tenant::begin(&self.db_pool, tenant).await?;
+
+        // Authenticate first
+        self.authenticate_device(&mut *tx, user_did, device_token).await?;
 
         if self.use_hashed_storage {
             // Update using privacy-preserving hashed storage
@@ -298,16 +684,10 @@ impl RelationshipManager {
             .await
             .context("Failed to commit relationship batch transaction")?;
 
-        // Update caches
-        let mute_set: HashSet<String> = mutes.into_iter().collect();
-        let block_set: HashSet<String> = blocks.into_iter().collect();
-
-        self.mutes_cache
-            .insert(user_did.to_string(), mute_set)
-            .await;
-        self.blocks_cache
-            .insert(user_did.to_string(), block_set)
-            .await;
+        // The filter applies the lists of every app the DID uses, so drop the caches
+        // and let them reload the union rather than storing this app's lists alone
+        self.drop_cached("mutes", user_did).await;
+        self.drop_cached("blocks", user_did).await;
 
         info!(user_did = %user_did, "Updated user relationships in batch");
         Ok(())
@@ -580,13 +960,228 @@ impl RelationshipManager {
         Ok(())
     }
 
+    // A block a registered user made on their account, seen on the firehose. Stored
+    // like the blocks the app sends on /relationships, and replaced along with them by
+    // the next update. Returns false, storing nothing, when the user isn't registered.
+    pub async fn record_block(&self, user_did: &str, rkey: &str, blocked_did: &str) -> Result<bool> {
+        if !self.is_registered(user_did) {
+            return Ok(false);
+        }
+        let mut tx = self.db_pool.begin().await?;
+
+        let recorded = sqlx::query!(
+            r#"
+            INSERT INTO firehose_blocks (user_did, rkey, blocked_did)
+            SELECT $1, $2, $3
+            WHERE EXISTS (SELECT 1 FROM user_devices WHERE did = $1 AND deleted_at IS NULL)
+            ON CONFLICT (user_did, rkey) DO UPDATE SET blocked_did = EXCLUDED.blocked_did
+            "#,
+            user_did,
+            rkey,
+            blocked_did
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to record firehose block")?
+        .rows_affected()
+            > 0;
+        if !recorded {
+            return Ok(false);
+        }
+
+        // The block is the account's own, so every app the user registered with gets it
+        sqlx::query!(
+            r#"
+            INSERT INTO user_blocks (tenant_id, user_did, blocked_did)
+            SELECT DISTINCT tenant_id, $1, $2 FROM user_devices WHERE did = $1 AND deleted_at IS NULL
+            ON CONFLICT DO NOTHING
+            "#,
+            user_did,
+            blocked_did
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to insert block")?;
+
+        if self.use_hashed_storage {
+            sqlx::query!(
+                r#"
+                INSERT INTO user_blocks_encrypted (tenant_id, user_did, blocked_did_encrypted)
+                SELECT DISTINCT d.tenant_id, $1, pgp_sym_encrypt($2, $3)
+                FROM user_devices d
+                WHERE d.did = $1 AND d.deleted_at IS NULL
+                AND NOT EXISTS (
+                    SELECT 1 FROM user_blocks_encrypted
+                    WHERE tenant_id = d.tenant_id AND user_did = $1
+                    AND pgp_sym_decrypt(blocked_did_encrypted, $3) = $2
+                )
+                "#,
+                user_did,
+                self.crypto.hash_did(blocked_did, user_did),
+                self.crypto.server_secret
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Failed to insert hashed block")?;
+        }
+
+        crate::cache_sync::publish_scoped(&mut *tx, crate::cache_sync::Scope::Blocks, user_did).await?;
+        tx.commit().await.context("Failed to commit firehose block")?;
+
+        self.drop_cached("blocks", user_did).await;
+        debug!(user_did = %user_did, "Recorded block from the firehose");
+        Ok(true)
+    }
+
+    // Undo a block recorded by record_block when its record is deleted. Returns false
+    // when the block wasn't one we recorded.
+    pub async fn remove_block(&self, user_did: &str, rkey: &str) -> Result<bool> {
+        if !self.is_registered(user_did) {
+            return Ok(false);
+        }
+        let mut tx = self.db_pool.begin().await?;
+
+        let Some(blocked_did) = sqlx::query_scalar!(
+            "DELETE FROM firehose_blocks WHERE user_did = $1 AND rkey = $2 RETURNING blocked_did",
+            user_did,
+            rkey
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to remove firehose block")?
+        else {
+            return Ok(false);
+        };
+
+        // Another block record may still name the same account
+        let still_blocked = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM firehose_blocks WHERE user_did = $1 AND blocked_did = $2) AS "exists!""#,
+            user_did,
+            blocked_did
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if !still_blocked {
+            sqlx::query!(
+                "DELETE FROM user_blocks WHERE user_did = $1 AND blocked_did = $2",
+                user_did,
+                blocked_did
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete block")?;
+
+            sqlx::query!(
+                r#"
+                DELETE FROM user_blocks_encrypted
+                WHERE user_did = $1 AND pgp_sym_decrypt(blocked_did_encrypted, $3) = $2
+                "#,
+                user_did,
+                self.crypto.hash_did(&blocked_did, user_did),
+                self.crypto.server_secret
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete hashed block")?;
+        }
+
+        if !still_blocked {
+            crate::cache_sync::publish_scoped(&mut *tx, crate::cache_sync::Scope::Blocks, user_did).await?;
+        }
+        tx.commit().await.context("Failed to commit firehose unblock")?;
+
+        if !still_blocked {
+            self.drop_cached("blocks", user_did).await;
+        }
+        debug!(user_did = %user_did, "Removed block from the firehose");
+        Ok(true)
+    }
+
+    // Drop one of a user's caches here and in the shared tier; other replicas drop theirs
+    // on the scoped invalidation
+    async fn drop_cached(&self, kind: &'static str, user_did: &str) {
+        self.invalidate_local(kind, user_did).await;
+        self.shared.bump(&[kind], user_did).await;
+    }
+
+    // Drop one of a user's caches in this replica only, for a change another replica
+    // already retired in the shared tier
+    pub async fn invalidate_local(&self, kind: &str, user_did: &str) {
+        match kind {
+            "mutes" => self.mutes_cache.invalidate(user_did).await,
+            "blocks" => self.blocks_cache.invalidate(user_did).await,
+            "follows" => self.follows_cache.invalidate(user_did).await,
+            _ => {}
+        }
+    }
+
+    // Current mutes and blocks for a user, from cache when warm
+    pub async fn get_relationships(&self, user_did: &str) -> Result<(HashSet<String>, HashSet<String>)> {
+        let mutes = match self.mutes_cache.get(user_did) {
+            Some(mutes) => mutes,
+            None => self.load_mutes_for_user(user_did).await?,
+        };
+        let blocks = match self.blocks_cache.get(user_did) {
+            Some(blocks) => blocks,
+            None => self.load_blocks_for_user(user_did).await?,
+        };
+        Ok((mutes, blocks))
+    }
+
+    // Load a user's mutes, blocks and VIPs ahead of their first notification
+    pub async fn warm(&self, user_did: &str) -> Result<()> {
+        self.load_mutes_for_user(user_did).await?;
+        self.load_blocks_for_user(user_did).await?;
+        self.get_vips(user_did).await?;
+        Ok(())
+    }
+
+    // Whether a user's set contains a DID, from the shared tier alone. It holds salted
+    // hashes rather than DIDs, so it can answer one lookup but not rebuild the set.
+    async fn shared_contains(&self, kind: &str, user_did: &str, target_did: &str) -> Option<bool> {
+        let generation = self.shared.generation(kind, user_did).await?;
+        let hashes: HashSet<String> = self.shared.get_at(kind, user_did, generation).await?;
+        Some(hashes.contains(&self.crypto.hash_did(target_did, user_did)))
+    }
+
+    // Cache a set loaded from the DB, sharing its hashes under the generation read before
+    // the load
+    async fn store(
+        &self,
+        cache: &Cache<String, HashSet<String>>,
+        kind: &str,
+        user_did: &str,
+        set: HashSet<String>,
+        generation: Option<u64>,
+    ) {
+        if let Some(generation) = generation {
+            let hashes: HashSet<String> = set.iter().map(|did| self.crypto.hash_did(did, user_did)).collect();
+            self.shared.set_at(kind, user_did, generation, &hashes, CACHE_TTL).await;
+        }
+        cache.insert(user_did.to_string(), set).await;
+    }
+
     // Invalidate cache entries for maintenance
     pub async fn invalidate_cache(&self, user_did: &str) {
         self.mutes_cache.invalidate(user_did).await;
         self.blocks_cache.invalidate(user_did).await;
+        self.vips_cache.invalidate(user_did).await;
+        self.muted_words_cache.invalidate(user_did).await;
+        self.follows_cache.invalidate(user_did).await;
+        self.shared.bump(&["mutes", "blocks", "vips", "follows"], user_did).await;
         debug!(user_did = %user_did, "Invalidated relationship caches");
     }
 
+    // Drop all cached relationships under memory pressure; they reload from the DB on
+    // demand
+    pub fn invalidate_all_caches(&self) {
+        self.mutes_cache.invalidate_all();
+        self.blocks_cache.invalidate_all();
+        self.vips_cache.invalidate_all();
+        self.muted_words_cache.invalidate_all();
+        self.follows_cache.invalidate_all();
+    }
+
     // Run periodic cache maintenance
     pub async fn run_cache_maintenance(&self) -> Result<()> {
         info!("Running relationship cache maintenance");
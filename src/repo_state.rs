@@ -0,0 +1,209 @@
+// repo_state.rs
+// Account state the firehose keeps rather than notifies about: activity-subscription
+// declarations and starter packs. Any account on the network can publish either, so
+// writing each one as it arrives would cost a database round trip per record.
+// Changes are buffered instead, the latest per account or pack winning, and written
+// in one batch every REPO_STATE_FLUSH_SECS, early once REPO_STATE_MAX_PENDING are
+// waiting, and at shutdown. A failed write drops its batch, so a crash or a database
+// outage loses at most one batch of changes.
+use anyhow::Result;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const REPO_STATE_FLUSH_SECS: u64 = 5;
+const REPO_STATE_MAX_PENDING: usize = 5_000;
+
+#[derive(Default)]
+struct Pending {
+    // DID -> allowSubscriptions, None when the declaration was deleted
+    declarations: HashMap<String, Option<String>>,
+    // Pack URI -> (list URI, name), None when the pack was deleted
+    starter_packs: HashMap<String, Option<(String, String)>>,
+}
+
+impl Pending {
+    fn len(&self) -> usize {
+        self.declarations.len() + self.starter_packs.len()
+    }
+}
+
+#[derive(Default, Debug, PartialEq)]
+struct Batch {
+    declared_dids: Vec<String>,
+    allow_subscriptions: Vec<String>,
+    undeclared_dids: Vec<String>,
+    pack_uris: Vec<String>,
+    pack_lists: Vec<String>,
+    pack_names: Vec<String>,
+    deleted_packs: Vec<String>,
+}
+
+impl From<Pending> for Batch {
+    fn from(pending: Pending) -> Self {
+        let mut batch = Batch::default();
+        for (did, declaration) in pending.declarations {
+            match declaration {
+                Some(allow_subscriptions) => {
+                    batch.declared_dids.push(did);
+                    batch.allow_subscriptions.push(allow_subscriptions);
+                }
+                None => batch.undeclared_dids.push(did),
+            }
+        }
+        for (uri, pack) in pending.starter_packs {
+            match pack {
+                Some((list_uri, name)) => {
+                    batch.pack_uris.push(uri);
+                    batch.pack_lists.push(list_uri);
+                    batch.pack_names.push(name);
+                }
+                None => batch.deleted_packs.push(uri),
+            }
+        }
+        batch
+    }
+}
+
+#[derive(Clone)]
+pub struct RepoState {
+    db_pool: Pool<Postgres>,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl RepoState {
+    pub fn new(db_pool: Pool<Postgres>) -> Self {
+        Self {
+            db_pool,
+            pending: Arc::new(Mutex::new(Pending::default())),
+        }
+    }
+
+    // Keep the account's latest declaration; None restores the default
+    pub async fn declare(&self, did: &str, allow_subscriptions: Option<&str>) {
+        let full = {
+            let mut pending = self.pending.lock().unwrap();
+            pending
+                .declarations
+                .insert(did.to_string(), allow_subscriptions.map(str::to_string));
+            pending.len() >= REPO_STATE_MAX_PENDING
+        };
+        if full {
+            self.flush_logged().await;
+        }
+    }
+
+    // Keep the pack's reference list and name; None forgets the pack
+    pub async fn starter_pack(&self, uri: &str, pack: Option<(&str, &str)>) {
+        let full = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.starter_packs.insert(
+                uri.to_string(),
+                pack.map(|(list_uri, name)| (list_uri.to_string(), name.to_string())),
+            );
+            pending.len() >= REPO_STATE_MAX_PENDING
+        };
+        if full {
+            self.flush_logged().await;
+        }
+    }
+
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(REPO_STATE_FLUSH_SECS));
+        loop {
+            interval.tick().await;
+            self.flush_logged().await;
+        }
+    }
+
+    pub async fn flush_logged(&self) {
+        match self.flush().await {
+            Ok(0) => {}
+            Ok(written) => debug!("Wrote {} declaration and starter pack changes", written),
+            Err(e) => warn!("Failed to write declarations and starter packs: {}", e),
+        }
+    }
+
+    // Write everything buffered so far, returning how many changes were written
+    pub async fn flush(&self) -> Result<usize> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        let written = pending.len();
+        if written == 0 {
+            return Ok(0);
+        }
+        let batch = Batch::from(pending);
+
+        let mut tx = self.db_pool.begin().await?;
+        if !batch.declared_dids.is_empty() {
+            sqlx::query!(
+                r#"
+                INSERT INTO activity_declarations (did, allow_subscriptions)
+                SELECT * FROM UNNEST($1::text[], $2::text[])
+                ON CONFLICT (did)
+                DO UPDATE SET allow_subscriptions = EXCLUDED.allow_subscriptions, updated_at = NOW()
+                "#,
+                &batch.declared_dids,
+                &batch.allow_subscriptions
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        if !batch.undeclared_dids.is_empty() {
+            sqlx::query!(
+                "DELETE FROM activity_declarations WHERE did = ANY($1)",
+                &batch.undeclared_dids
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        if !batch.pack_uris.is_empty() {
+            sqlx::query!(
+                r#"
+                INSERT INTO starter_packs (uri, list_uri, name)
+                SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[])
+                ON CONFLICT (uri)
+                DO UPDATE SET list_uri = EXCLUDED.list_uri, name = EXCLUDED.name, updated_at = NOW()
+                "#,
+                &batch.pack_uris,
+                &batch.pack_lists,
+                &batch.pack_names
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        if !batch.deleted_packs.is_empty() {
+            sqlx::query!("DELETE FROM starter_packs WHERE uri = ANY($1)", &batch.deleted_packs)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_the_latest_change_per_account_and_pack() {
+        let mut pending = Pending::default();
+        pending.declarations.insert("did:plc:a".into(), Some("all".into()));
+        pending.declarations.insert("did:plc:a".into(), Some("mutuals".into()));
+        pending.declarations.insert("did:plc:b".into(), None);
+        pending
+            .starter_packs
+            .insert("at://did:plc:a/app.bsky.graph.starterpack/1".into(), None);
+        assert_eq!(pending.len(), 3);
+
+        let batch = Batch::from(pending);
+        assert_eq!(batch.declared_dids, vec!["did:plc:a"]);
+        assert_eq!(batch.allow_subscriptions, vec!["mutuals"]);
+        assert_eq!(batch.undeclared_dids, vec!["did:plc:b"]);
+        assert!(batch.pack_uris.is_empty());
+        assert_eq!(batch.deleted_packs, vec!["at://did:plc:a/app.bsky.graph.starterpack/1"]);
+    }
+}
@@ -0,0 +1,255 @@
+// reporting.rs
+// Forwards "report this notification" actions to a moderation service. The subject is
+// resolved from notification history, and the report is filed by the service's own
+// account (REPORT_ACCOUNT_DID with an app password) through its PDS, proxied to the
+// labeler. The reason names the user who reported it, so moderators still see who
+// raised it without the service ever holding user credentials.
+use anyhow::{Context, Result};
+use reqwest::{Client as HttpClient, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::did_resolver::DidResolver;
+use crate::models::NotificationType;
+
+pub enum ReportOutcome {
+    Forwarded(serde_json::Value),
+    AlreadyReported,
+    NotFound,
+    NotConfigured,
+    UpstreamFailed,
+}
+
+pub struct ReportRequest<'a> {
+    // The app the report came through, which only sees its own notifications
+    pub tenant_id: &'a str,
+    pub user_did: &'a str,
+    pub notification_id: uuid::Uuid,
+    pub reason_type: &'a str,
+    pub reason: Option<&'a str>,
+}
+
+#[derive(Clone)]
+struct ReporterSession {
+    pds: String,
+    access_jwt: String,
+}
+
+#[derive(Deserialize)]
+struct Session {
+    #[serde(rename = "accessJwt")]
+    access_jwt: String,
+}
+
+pub struct Reporter {
+    did_resolver: Arc<DidResolver>,
+    service_did: String,
+    // The account reports are filed as, and its app password; None disables reporting
+    account: Option<(String, String)>,
+    http_client: HttpClient,
+    // Access tokens last a couple of hours, so the session is reused until rejected
+    session: Mutex<Option<ReporterSession>>,
+}
+
+pub fn is_valid_reason_type(reason_type: &str) -> bool {
+    reason_type.starts_with("com.atproto.moderation.defs#reason")
+}
+
+pub async fn forward_report(
+    pool: &Pool<Postgres>,
+    reporter: &Reporter,
+    request: ReportRequest<'_>,
+) -> Result<ReportOutcome> {
+    if reporter.account.is_none() {
+        return Ok(ReportOutcome::NotConfigured);
+    }
+
+    let row = sqlx::query!(
+        r#"
+        SELECT notification_type, data
+        FROM notification_history
+        WHERE notification_id = $1 AND user_did = $2 AND tenant_id = $3
+        "#,
+        request.notification_id,
+        request.user_did,
+        request.tenant_id
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(ReportOutcome::NotFound);
+    };
+    let Some(subject) = NotificationType::parse(&row.notification_type)
+        .and_then(|notification_type| report_subject(&notification_type, &row.data))
+    else {
+        return Ok(ReportOutcome::NotFound);
+    };
+
+    // Claim the idempotency key before calling out; released again if forwarding fails
+    let claimed = sqlx::query!(
+        r#"
+        INSERT INTO notification_reports (notification_id, user_did, reason_type)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (notification_id) DO NOTHING
+        "#,
+        request.notification_id,
+        request.user_did,
+        request.reason_type
+    )
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0;
+
+    if !claimed {
+        return Ok(ReportOutcome::AlreadyReported);
+    }
+
+    let outcome = reporter.send_report(&request, subject).await;
+    if !matches!(outcome, Ok(ReportOutcome::Forwarded(_))) {
+        sqlx::query!(
+            "DELETE FROM notification_reports WHERE notification_id = $1",
+            request.notification_id
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    outcome
+}
+
+// Posts the user was notified about are reported as records; likes, reposts and
+// follows point at the user's own content, and feed posts are announced without a
+// CID, so the acting account is reported instead
+fn report_subject(notification_type: &NotificationType, data: &serde_json::Value) -> Option<serde_json::Value> {
+    match notification_type {
+        NotificationType::Mention
+        | NotificationType::Reply
+        | NotificationType::Quote
+        | NotificationType::SubscribedPost => Some(json!({
+            "$type": "com.atproto.repo.strongRef",
+            "uri": data.get("uri")?.as_str()?,
+            "cid": data.get("cid")?.as_str()?,
+        })),
+        NotificationType::Like
+        | NotificationType::Repost
+        | NotificationType::Follow
+        | NotificationType::FeedPost
+        | NotificationType::DirectMessage
+        | NotificationType::ListAddition => Some(json!({
+            "$type": "com.atproto.admin.defs#repoRef",
+            "did": data.get("author_did")?.as_str()?,
+        })),
+        // Our own service sent it; there's nothing to take to a labeler
+        NotificationType::Custom => None,
+    }
+}
+
+impl Reporter {
+    pub fn new(did_resolver: Arc<DidResolver>, service_did: String, account: Option<(String, String)>) -> Self {
+        Self {
+            did_resolver,
+            service_did,
+            account,
+            http_client: HttpClient::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            session: Mutex::new(None),
+        }
+    }
+
+    async fn send_report(&self, request: &ReportRequest<'_>, subject: serde_json::Value) -> Result<ReportOutcome> {
+        let body = json!({
+            "reasonType": request.reason_type,
+            "reason": report_reason(request.user_did, request.reason),
+            "subject": subject,
+        });
+
+        let session = self.session().await?;
+        let mut response = self.send_create_report(&session, &body).await?;
+
+        // An expired token comes back as 400 ExpiredToken; sign in again once
+        if matches!(response.status(), StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED) {
+            debug!("Reporting session rejected, signing in again");
+            *self.session.lock().await = None;
+            let session = self.session().await?;
+            response = self.send_create_report(&session, &body).await?;
+        }
+
+        let status = response.status();
+        if !status.is_success() {
+            if status == StatusCode::UNAUTHORIZED {
+                *self.session.lock().await = None;
+            }
+            let text = response.text().await.unwrap_or_default();
+            warn!(did = %request.user_did, status = %status, "Moderation report rejected: {}", text);
+            return Ok(ReportOutcome::UpstreamFailed);
+        }
+
+        info!(
+            did = %request.user_did,
+            notification_id = %request.notification_id,
+            "Forwarded moderation report"
+        );
+        Ok(ReportOutcome::Forwarded(response.json().await.unwrap_or_default()))
+    }
+
+    async fn send_create_report(
+        &self,
+        session: &ReporterSession,
+        body: &serde_json::Value,
+    ) -> Result<reqwest::Response> {
+        self.http_client
+            .post(format!("{}/xrpc/com.atproto.moderation.createReport", session.pds))
+            .bearer_auth(&session.access_jwt)
+            .header("atproto-proxy", format!("{}#atproto_labeler", self.service_did))
+            .json(body)
+            .send()
+            .await
+            .context("Failed to file moderation report")
+    }
+
+    async fn session(&self) -> Result<ReporterSession> {
+        let mut current = self.session.lock().await;
+        if let Some(session) = current.as_ref() {
+            return Ok(session.clone());
+        }
+        let Some((did, app_password)) = &self.account else {
+            anyhow::bail!("No reporting account configured");
+        };
+
+        let pds = self.did_resolver.get_pds_endpoint(did).await?;
+        let session: Session = self
+            .http_client
+            .post(format!("{}/xrpc/com.atproto.server.createSession", pds))
+            .json(&json!({ "identifier": did, "password": app_password }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Failed to create reporting session")?
+            .json()
+            .await?;
+
+        let session = ReporterSession {
+            pds,
+            access_jwt: session.access_jwt,
+        };
+        *current = Some(session.clone());
+        Ok(session)
+    }
+}
+
+// The service files every report, so the reason carries the user who raised it
+fn report_reason(user_did: &str, reason: Option<&str>) -> String {
+    match reason {
+        Some(reason) => format!("Reported from a notification by {}: {}", user_did, reason),
+        None => format!("Reported from a notification by {}", user_did),
+    }
+}
@@ -0,0 +1,150 @@
+// retraction.rs
+// Takes back notifications about records deleted soon after they were made, such as
+// an unlike or a deleted reply. The filter remembers the record behind each
+// notification it sends for RETRACTION_WINDOW_MINUTES; when the firehose reports one
+// of those records deleted, its history entries are marked retracted, the devices'
+// unread counts drop by one and each device gets a silent push carrying the
+// notification id, so the app can remove it from Notification Center. The firehose
+// checks the same set, so deletions of anything else are never queued. 0 disables
+// retraction.
+use anyhow::Result;
+use moka::future::Cache;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::channel::PipelineSender;
+use crate::models::{NotificationPayload, NotificationType};
+
+#[derive(Clone)]
+pub struct Retractions {
+    window: Duration,
+    // Record URIs notified about inside the window; None when disabled
+    recent: Option<Cache<String, ()>>,
+}
+
+impl Retractions {
+    pub fn from_config(config: &crate::config::Config) -> Self {
+        Self::new(Duration::from_secs(config.retraction_window_minutes * 60))
+    }
+
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            recent: (!window.is_zero()).then(|| {
+                Cache::builder()
+                    .max_capacity(1_000_000)
+                    .time_to_live(window)
+                    .build()
+            }),
+        }
+    }
+
+    pub async fn remember(&self, record_uri: &str) {
+        if let Some(recent) = &self.recent {
+            recent.insert(record_uri.to_string(), ()).await;
+        }
+    }
+
+    pub fn was_notified(&self, record_uri: &str) -> bool {
+        self.recent
+            .as_ref()
+            .is_some_and(|recent| recent.contains_key(record_uri))
+    }
+
+    // Retract every notification sent about the record, returning how many
+    pub async fn retract(
+        &self,
+        pool: &Pool<Postgres>,
+        notification_sender: &PipelineSender<NotificationPayload>,
+        record_uri: &str,
+    ) -> Result<usize> {
+        if let Some(recent) = &self.recent {
+            recent.invalidate(record_uri).await;
+        }
+
+        let rows = sqlx::query!(
+            r#"
+            UPDATE notification_history
+            SET status = 'retracted'
+            WHERE data->>'record_uri' = $1
+            AND created_at > NOW() - make_interval(secs => $2)
+            AND status <> 'retracted'
+            RETURNING notification_id, user_did, device_token, notification_type,
+                (SELECT d.id FROM user_devices d
+                 WHERE d.device_token = notification_history.device_token AND d.deleted_at IS NULL) AS device_id
+            "#,
+            record_uri,
+            self.window.as_secs_f64()
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut retracted = 0;
+        for row in rows {
+            let Some(notification_type) = NotificationType::parse(&row.notification_type) else {
+                continue;
+            };
+            if let Some(device_id) = row.device_id {
+                if let Err(e) = crate::db::uncount_unread(pool, device_id, notification_type.is_mention()).await {
+                    warn!("Failed to uncount retracted notification: {}", e);
+                }
+            }
+            let payload = retraction_payload(row.user_did, row.device_token, notification_type, row.notification_id);
+            match notification_sender.send(payload).await {
+                Ok(()) => retracted += 1,
+                Err(e) => warn!("Failed to queue notification retraction: {}", e),
+            }
+        }
+        crate::metrics::NOTIFICATIONS_RETRACTED.inc_by(retracted as f64);
+        Ok(retracted)
+    }
+}
+
+// A silent push naming the notification to remove. It has no notification_id of its
+// own, so it isn't recorded in history.
+fn retraction_payload(
+    user_did: String,
+    device_token: String,
+    notification_type: NotificationType,
+    notification_id: uuid::Uuid,
+) -> NotificationPayload {
+    let mut data = HashMap::new();
+    data.insert("push_type".to_string(), "background".to_string());
+    data.insert("retract".to_string(), notification_id.to_string());
+    NotificationPayload {
+        user_did,
+        device_token,
+        notification_type,
+        title: String::new(),
+        body: String::new(),
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_remembers_records_inside_the_window() {
+        let retractions = Retractions::new(Duration::from_secs(600));
+        retractions.remember("at://did:plc:a/app.bsky.feed.like/1").await;
+        assert!(retractions.was_notified("at://did:plc:a/app.bsky.feed.like/1"));
+        assert!(!retractions.was_notified("at://did:plc:a/app.bsky.feed.like/2"));
+
+        let disabled = Retractions::new(Duration::ZERO);
+        disabled.remember("at://did:plc:a/app.bsky.feed.like/1").await;
+        assert!(!disabled.was_notified("at://did:plc:a/app.bsky.feed.like/1"));
+
+        let payload = retraction_payload(
+            "did:plc:me".to_string(),
+            "token".to_string(),
+            NotificationType::Like,
+            uuid::Uuid::nil(),
+        );
+        assert!(payload.is_background());
+        assert!(!payload.data.contains_key("notification_id"));
+    }
+}
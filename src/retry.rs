@@ -0,0 +1,212 @@
+// retry.rs
+// Shared retry policy: bounded attempts, capped exponential backoff with jitter, and
+// a classifier deciding which errors are worth another attempt. Each policy is named
+// after its call site, which labels the retry metrics.
+use anyhow::Result;
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    call_site: &'static str,
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    retryable: fn(&anyhow::Error) -> bool,
+}
+
+impl RetryPolicy {
+    // Three attempts, 100ms doubling to at most 10s, jittered, retrying every error
+    pub fn new(call_site: &'static str) -> Self {
+        Self {
+            call_site,
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            retryable: |_| true,
+        }
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn backoff(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn retry_if(mut self, retryable: fn(&anyhow::Error) -> bool) -> Self {
+        self.retryable = retryable;
+        self
+    }
+
+    // Delay after the given failed attempt (1-based). With jitter the delay is drawn
+    // from the upper half of the window so retries still back off.
+    pub fn delay_for(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .checked_mul(1 << attempt.saturating_sub(1).min(16))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        if !self.jitter {
+            return exponential;
+        }
+        let fraction = random_u64() as f64 / u64::MAX as f64;
+        exponential.mul_f64(0.5 + fraction / 2.0)
+    }
+
+    // Run `operation` until it succeeds, fails with a non-retryable error, or runs
+    // out of attempts. The closure receives the 1-based attempt number.
+    pub async fn run<T, F, Fut>(&self, mut operation: F) -> Result<T>
+    where
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation(attempt).await {
+                Ok(value) => return Ok(value),
+                Err(e) if !(self.retryable)(&e) => return Err(e),
+                Err(e) if attempt >= self.max_attempts => {
+                    crate::metrics::RETRIES_EXHAUSTED
+                        .with_label_values(&[self.call_site])
+                        .inc();
+                    return Err(e);
+                }
+                Err(e) => {
+                    let delay = self.delay_for(attempt);
+                    warn!(
+                        call_site = self.call_site,
+                        attempt = attempt,
+                        max_attempts = self.max_attempts,
+                        delay_ms = delay.as_millis() as u64,
+                        "Retrying after error: {:#}",
+                        e
+                    );
+                    crate::metrics::RETRY_ATTEMPTS
+                        .with_label_values(&[self.call_site])
+                        .inc();
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    // For loops that can't be expressed as a single operation, such as reconnects
+    // that must also watch for shutdown
+    pub fn tracker(&self) -> RetryTracker<'_> {
+        RetryTracker {
+            policy: self,
+            failures: 0,
+        }
+    }
+}
+
+pub struct RetryTracker<'a> {
+    policy: &'a RetryPolicy,
+    failures: u32,
+}
+
+impl RetryTracker<'_> {
+    // Record a failure; returns the delay before the next attempt, or None once
+    // attempts are exhausted
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        self.failures += 1;
+        if self.failures >= self.policy.max_attempts {
+            crate::metrics::RETRIES_EXHAUSTED
+                .with_label_values(&[self.policy.call_site])
+                .inc();
+            return None;
+        }
+        crate::metrics::RETRY_ATTEMPTS
+            .with_label_values(&[self.policy.call_site])
+            .inc();
+        Some(self.policy.delay_for(self.failures))
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.policy.max_attempts
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+// Timeouts, connection failures, throttling and server errors anywhere in the chain
+pub fn is_transient_http(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(|e| {
+            e.is_timeout()
+                || e.is_connect()
+                || e.status().is_some_and(|status| {
+                    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                })
+        })
+}
+
+// std's hasher keys are randomly seeded per instance, which is plenty for jitter
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn backoff_doubles_and_caps_without_jitter() {
+        let policy = RetryPolicy::new("test")
+            .backoff(Duration::from_millis(100), Duration::from_millis(350))
+            .jitter(false);
+        assert_eq!(policy.delay_for(1), Duration::from_millis(100));
+        assert_eq!(policy.delay_for(2), Duration::from_millis(200));
+        assert_eq!(policy.delay_for(3), Duration::from_millis(350));
+    }
+
+    #[tokio::test]
+    async fn stops_on_non_retryable_errors() {
+        let policy = RetryPolicy::new("test")
+            .max_attempts(5)
+            .backoff(Duration::ZERO, Duration::ZERO)
+            .retry_if(|e| e.to_string() == "transient");
+        let calls = AtomicU32::new(0);
+
+        let result: Result<()> = policy
+            .run(|attempt| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if attempt < 3 {
+                        anyhow::bail!("transient")
+                    }
+                    anyhow::bail!("fatal")
+                }
+            })
+            .await;
+
+        assert_eq!(result.unwrap_err().to_string(), "fatal");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,167 @@
+// self_test.rs
+// `--self-test`: exercise every external dependency once and print a readiness report.
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::apns::{ApnsClient, DeliveryOutcome};
+use crate::config::Config;
+use crate::did_resolver::DidResolver;
+use crate::firehose::RepoSubscription;
+use crate::models::{NotificationPayload, NotificationType};
+use crate::post_resolver::PostResolver;
+use crate::shared_cache::SharedCache;
+use crate::subscription::Subscription;
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+enum CheckResult {
+    Pass(String),
+    Fail(String),
+    Skip(String),
+}
+
+// Run all checks and return whether every non-skipped check passed
+pub async fn run(config: &Config) -> bool {
+    let mut results = Vec::new();
+
+    let db_pool = match timed(crate::db::init_db_pool(&config.database_url)).await {
+        Ok(pool) => {
+            // init_db_pool already ran migrations; confirm the bookkeeping matches
+            let expected = sqlx::migrate!("./migrations")
+                .iter()
+                .filter(|m| !m.migration_type.is_down_migration())
+                .count() as i64;
+            let applied: i64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations WHERE success")
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap_or(0);
+            results.push((
+                "database",
+                if applied >= expected {
+                    CheckResult::Pass(format!("connected, {} migrations applied", applied))
+                } else {
+                    CheckResult::Fail(format!("{} of {} migrations applied", applied, expected))
+                },
+            ));
+            Some(pool)
+        }
+        Err(e) => {
+            results.push(("database", CheckResult::Fail(e.to_string())));
+            None
+        }
+    };
+
+    results.push(("apns", check_apns(config).await));
+
+    match &db_pool {
+        Some(pool) => {
+            let did_resolver = DidResolver::new(pool.clone(), 1, SharedCache::default());
+            results.push((
+                "did resolution",
+                match timed(did_resolver.get_handle(&config.self_test_did)).await {
+                    Ok(handle) => CheckResult::Pass(format!("{} -> {}", config.self_test_did, handle)),
+                    Err(e) => CheckResult::Fail(e.to_string()),
+                },
+            ));
+
+            results.push((
+                "post fetch",
+                match &config.self_test_post_uri {
+                    Some(uri) => {
+                        let post_resolver =
+                            PostResolver::new(pool.clone(), 1, config.bsky_api_url.clone(), SharedCache::default());
+                        match timed(post_resolver.get_post_content(uri)).await {
+                            Ok(text) => CheckResult::Pass(format!("{} chars", text.chars().count())),
+                            Err(e) => CheckResult::Fail(e.to_string()),
+                        }
+                    }
+                    None => CheckResult::Skip("SELF_TEST_POST_URI not set".to_string()),
+                },
+            ));
+        }
+        None => {
+            results.push(("did resolution", CheckResult::Skip("database unavailable".to_string())));
+            results.push(("post fetch", CheckResult::Skip("database unavailable".to_string())));
+        }
+    }
+
+    results.push(("firehose", check_firehose(&config.bsky_service_url).await));
+
+    println!("Self-test report");
+    let mut passed = true;
+    for (name, result) in &results {
+        match result {
+            CheckResult::Pass(detail) => println!("  [PASS] {}: {}", name, detail),
+            CheckResult::Fail(detail) => {
+                passed = false;
+                println!("  [FAIL] {}: {}", name, detail);
+            }
+            CheckResult::Skip(detail) => println!("  [SKIP] {}: {}", name, detail),
+        }
+    }
+    println!("{}", if passed { "READY" } else { "NOT READY" });
+
+    passed
+}
+
+// Always uses the sandbox endpoint so a test token never hits production
+async fn check_apns(config: &Config) -> CheckResult {
+    let Some(device_token) = &config.self_test_device_token else {
+        return CheckResult::Skip("SELF_TEST_DEVICE_TOKEN not set".to_string());
+    };
+
+    let client = match ApnsClient::new(
+        &config.apns_key_path,
+        &config.apns_key_id,
+        &config.apns_team_id,
+        false,
+        &[],
+        &config.apns_topic,
+        std::collections::HashMap::new(),
+        config.apns_topic_overrides.clone(),
+    ) {
+        Ok(client) => client,
+        Err(e) => return CheckResult::Fail(e.to_string()),
+    };
+
+    let payload = NotificationPayload {
+        user_did: "self-test".to_string(),
+        device_token: device_token.clone(),
+        notification_type: NotificationType::Mention,
+        title: "Self-test".to_string(),
+        body: "Push delivery is working".to_string(),
+        data: HashMap::new(),
+    };
+
+    match timed(async { Ok(client.send_notification(&payload).await) }).await {
+        Ok(DeliveryOutcome::Delivered) => CheckResult::Pass("sandbox push accepted".to_string()),
+        Ok(outcome) => CheckResult::Fail(format!("{:?}", outcome)),
+        Err(e) => CheckResult::Fail(e.to_string()),
+    }
+}
+
+// Connect and wait for the first frame
+async fn check_firehose(bsky_service_url: &str) -> CheckResult {
+    let result = timed(async {
+        let mut subscription = RepoSubscription::new(bsky_service_url, None).await?;
+        match subscription.next().await {
+            Some(frame) => frame.map(|_| ()),
+            None => Err(anyhow!("connection closed before first frame")),
+        }
+    })
+    .await;
+
+    match result {
+        Ok(()) => CheckResult::Pass(format!("received a frame from {}", bsky_service_url)),
+        Err(e) => CheckResult::Fail(e.to_string()),
+    }
+}
+
+async fn timed<T>(future: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(CHECK_TIMEOUT, future)
+        .await
+        .map_err(|_| anyhow!("timed out after {}s", CHECK_TIMEOUT.as_secs()))?
+}
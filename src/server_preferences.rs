@@ -0,0 +1,92 @@
+// server_preferences.rs
+// Reads the notification preferences a user already set in the official app
+// (app.bsky.notification.getPreferences, proxied through their PDS to the AppView) so
+// they can seed our local preferences instead of being configured twice. Like
+// reporting, this uses the session token the client sends along for this one
+// request; it is never stored.
+use anyhow::Result;
+use reqwest::Client as HttpClient;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::did_resolver::DidResolver;
+use crate::models::NotificationType;
+
+// Push toggles from the server, one per notification type we also send. None where
+// the server didn't say, so our own default applies.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ServerPreferences {
+    pub mentions: Option<bool>,
+    pub replies: Option<bool>,
+    pub likes: Option<bool>,
+    pub follows: Option<bool>,
+    pub reposts: Option<bool>,
+    pub quotes: Option<bool>,
+}
+
+impl ServerPreferences {
+    fn from_response(body: &serde_json::Value) -> Self {
+        let push = |notification_type: NotificationType| {
+            body.get("preferences")?
+                .get(notification_type.as_str())?
+                .get("push")?
+                .as_bool()
+        };
+        Self {
+            mentions: push(NotificationType::Mention),
+            replies: push(NotificationType::Reply),
+            likes: push(NotificationType::Like),
+            follows: push(NotificationType::Follow),
+            reposts: push(NotificationType::Repost),
+            quotes: push(NotificationType::Quote),
+        }
+    }
+}
+
+pub async fn fetch(
+    did_resolver: &DidResolver,
+    appview_did: &str,
+    did: &str,
+    session_token: &str,
+) -> Result<ServerPreferences> {
+    let pds = did_resolver.get_pds_endpoint(did).await?;
+
+    // Registration waits on this, so keep it short
+    let body: serde_json::Value = HttpClient::builder()
+        .timeout(Duration::from_secs(5))
+        .build()?
+        .get(format!("{}/xrpc/app.bsky.notification.getPreferences", pds))
+        .bearer_auth(session_token)
+        .header("atproto-proxy", format!("{}#bsky_appview", appview_did))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let preferences = ServerPreferences::from_response(&body);
+    debug!(did = %did, preferences = ?preferences, "Fetched server notification preferences");
+    Ok(preferences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn maps_push_toggles_and_leaves_missing_types_unset() {
+        let body = json!({
+            "preferences": {
+                "like": { "include": "all", "list": true, "push": false },
+                "reply": { "include": "all", "list": true, "push": true },
+                "chat": { "include": "all", "push": true },
+            }
+        });
+
+        let preferences = ServerPreferences::from_response(&body);
+        assert_eq!(preferences.likes, Some(false));
+        assert_eq!(preferences.replies, Some(true));
+        assert_eq!(preferences.mentions, None);
+    }
+}
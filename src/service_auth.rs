@@ -0,0 +1,182 @@
+// service_auth.rs
+// Verifies AT Protocol inter-service auth: a short-lived JWT the user's PDS signs with
+// the account's #atproto key (com.atproto.server.getServiceAuth) to prove a request
+// comes from that account. The issuer's DID document provides the key.
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::did_resolver::DidResolver;
+
+// Method clients request the token for when registering a device
+pub const REGISTER_PUSH_LXM: &str = "app.bsky.notification.registerPush";
+
+// Multicodec prefixes of the two key types atproto signs with
+const SECP256K1_PUB: [u8; 2] = [0xe7, 0x01];
+const P256_PUB: [u8; 2] = [0x80, 0x24];
+
+// Allowance for clock drift between us and the PDS
+const CLOCK_SKEW_SECS: i64 = 30;
+
+#[derive(Debug, Deserialize)]
+struct Header {
+    alg: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    iss: String,
+    aud: String,
+    exp: i64,
+    lxm: Option<String>,
+}
+
+struct ServiceToken<'a> {
+    header: Header,
+    claims: Claims,
+    // The signed "header.payload" part
+    signing_input: &'a str,
+    signature: Vec<u8>,
+}
+
+impl<'a> ServiceToken<'a> {
+    fn parse(token: &'a str) -> Result<Self> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Malformed service auth token");
+        };
+
+        Ok(Self {
+            header: serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header)?)
+                .context("Invalid token header")?,
+            claims: serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)
+                .context("Invalid token claims")?,
+            signing_input: &token[..header.len() + 1 + payload.len()],
+            signature: URL_SAFE_NO_PAD.decode(signature)?,
+        })
+    }
+
+    fn check_claims(&self, did: &str, service_did: &str, lxm: &str, now: i64) -> Result<()> {
+        if self.claims.iss != did {
+            bail!("Token issued by {}, not {}", self.claims.iss, did);
+        }
+        if self.claims.aud != service_did {
+            bail!("Token is for {}, not this service", self.claims.aud);
+        }
+        if self.claims.exp + CLOCK_SKEW_SECS < now {
+            bail!("Token expired");
+        }
+        // Tokens without lxm are valid for any method
+        if let Some(token_lxm) = &self.claims.lxm {
+            if token_lxm != lxm {
+                bail!("Token is scoped to {}, not {}", token_lxm, lxm);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_signature(&self, public_key_multibase: &str) -> Result<()> {
+        use k256::ecdsa::signature::Verifier;
+
+        let (_, key) = multibase::decode(public_key_multibase)?;
+        let message = self.signing_input.as_bytes();
+
+        if let Some(key) = key.strip_prefix(&SECP256K1_PUB) {
+            if self.header.alg != "ES256K" {
+                bail!("Token algorithm {} doesn't match a secp256k1 key", self.header.alg);
+            }
+            // k256 rejects high-S signatures itself
+            let signature = k256::ecdsa::Signature::from_slice(&self.signature)?;
+            k256::ecdsa::VerifyingKey::from_sec1_bytes(key)?.verify(message, &signature)?;
+        } else if let Some(key) = key.strip_prefix(&P256_PUB) {
+            if self.header.alg != "ES256" {
+                bail!("Token algorithm {} doesn't match a P-256 key", self.header.alg);
+            }
+            let signature = p256::ecdsa::Signature::from_slice(&self.signature)?;
+            // atproto only accepts low-S signatures
+            if signature.normalize_s().is_some() {
+                bail!("High-S signature");
+            }
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(key)?.verify(message, &signature)?;
+        } else {
+            bail!("Unsupported signing key type");
+        }
+        Ok(())
+    }
+}
+
+// Checks that `token` was issued by `did` for `service_did` and the `lxm` method
+pub async fn verify(
+    did_resolver: &DidResolver,
+    token: &str,
+    did: &str,
+    service_did: &str,
+    lxm: &str,
+) -> Result<()> {
+    let token = ServiceToken::parse(token)?;
+    token.check_claims(did, service_did, lxm, chrono::Utc::now().timestamp())?;
+
+    // A cached DID document may predate a key rotation, so re-resolve once before
+    // rejecting the signature. The resolver throttles these per DID, so forged tokens
+    // can't turn into a fetch per request.
+    match did_resolver.get_signing_key(did, false).await {
+        Ok(key) if token.check_signature(&key).is_ok() => return Ok(()),
+        Ok(_) => debug!(did = %did, "Service auth signature failed against cached key, re-resolving"),
+        Err(e) => debug!(did = %did, "No cached signing key ({}), re-resolving", e),
+    }
+    let key = did_resolver.get_signing_key(did, true).await?;
+    token
+        .check_signature(&key)
+        .map_err(|e| anyhow!("Invalid service auth signature: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::{signature::Signer, Signature, SigningKey};
+    use serde_json::json;
+
+    fn sign(key: &SigningKey, claims: serde_json::Value) -> String {
+        let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "ES256K", "typ": "JWT" }).to_string());
+        let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+        let signing_input = format!("{}.{}", header, payload);
+        let signature: Signature = key.sign(signing_input.as_bytes());
+        format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+    }
+
+    #[test]
+    fn verifies_claims_and_secp256k1_signature() {
+        let key = SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let mut multikey = SECP256K1_PUB.to_vec();
+        multikey.extend_from_slice(&key.verifying_key().to_sec1_bytes());
+        let multikey = multibase::encode(multibase::Base::Base58Btc, multikey);
+
+        let raw = sign(
+            &key,
+            json!({ "iss": "did:plc:alice", "aud": "did:web:push.example", "exp": 1000, "lxm": REGISTER_PUSH_LXM }),
+        );
+        let token = ServiceToken::parse(&raw).unwrap();
+        token.check_signature(&multikey).unwrap();
+        token.check_claims("did:plc:alice", "did:web:push.example", REGISTER_PUSH_LXM, 900).unwrap();
+
+        assert!(token.check_claims("did:plc:mallory", "did:web:push.example", REGISTER_PUSH_LXM, 900).is_err());
+        assert!(token.check_claims("did:plc:alice", "did:web:other.example", REGISTER_PUSH_LXM, 900).is_err());
+        assert!(token.check_claims("did:plc:alice", "did:web:push.example", REGISTER_PUSH_LXM, 2000).is_err());
+
+        // Any change to the signed part invalidates the signature
+        let (_, signature) = raw.rsplit_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            sign(&key, json!({ "iss": "did:plc:mallory", "aud": "did:web:push.example", "exp": 1000 }))
+                .rsplit_once('.')
+                .unwrap()
+                .0,
+            signature
+        );
+        assert!(ServiceToken::parse(&forged).unwrap().check_signature(&multikey).is_err());
+    }
+}
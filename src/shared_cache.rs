@@ -0,0 +1,260 @@
+// shared_cache.rs
+// An optional cache tier shared by every replica, between each replica's in-memory
+// caches and Postgres. Without it every replica warms its own DID, post and
+// relationship caches from the database; with SHARED_CACHE_URL pointing at Redis (in
+// builds with the `redis-cache` feature) a value one replica loaded is there for the
+// rest. Values are stored as JSON under "notifier:<kind>:<key>" and expire with the
+// TTL of the layer they back. The tier is best effort: a failing backend is logged and
+// treated as a miss, so an outage falls back to Postgres rather than failing lookups.
+// Relationship sets are stored only as salted hashes of the DIDs, and muted words not
+// at all. Relationship entries are versioned: an invalidation bumps a per-user generation
+// instead of deleting, so a value loaded before the change lands under the old
+// generation and is never read.
+use anyhow::Result;
+use futures::future::BoxFuture;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+// Storage for the shared tier. Values are opaque strings; SharedCache handles encoding.
+pub trait CacheBackend: Send + Sync {
+    fn name(&self) -> &str;
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>>;
+    fn set<'a>(&'a self, key: &'a str, value: String, ttl: Duration) -> BoxFuture<'a, Result<()>>;
+    fn incr<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>>;
+}
+
+// Handle to the shared tier; does nothing when no backend is configured
+#[derive(Clone, Default)]
+pub struct SharedCache {
+    backend: Option<Arc<dyn CacheBackend>>,
+}
+
+impl SharedCache {
+    #[cfg_attr(not(feature = "redis-cache"), allow(dead_code))]
+    pub fn new(backend: Arc<dyn CacheBackend>) -> Self {
+        Self { backend: Some(backend) }
+    }
+
+    // Connect to the configured backend, failing startup rather than silently running
+    // every replica against Postgres
+    pub async fn connect(url: Option<&str>) -> Result<Self> {
+        let Some(url) = url else {
+            return Ok(Self::default());
+        };
+
+        #[cfg(feature = "redis-cache")]
+        {
+            let backend = redis_backend::RedisBackend::connect(url).await?;
+            tracing::info!("Using shared cache tier at {}", backend.name());
+            Ok(Self::new(Arc::new(backend)))
+        }
+
+        #[cfg(not(feature = "redis-cache"))]
+        {
+            let _ = url;
+            anyhow::bail!("SHARED_CACHE_URL is set but this build does not include the redis-cache feature")
+        }
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, kind: &str, key: &str) -> Option<T> {
+        let backend = self.backend.as_ref()?;
+        match backend.get(&cache_key(kind, key)).await {
+            Ok(Some(value)) => {
+                let parsed = serde_json::from_str(&value).ok();
+                let result = if parsed.is_some() { "hit" } else { "corrupt" };
+                crate::metrics::SHARED_CACHE_LOOKUPS.with_label_values(&[kind, result]).inc();
+                parsed
+            }
+            Ok(None) => {
+                crate::metrics::SHARED_CACHE_LOOKUPS.with_label_values(&[kind, "miss"]).inc();
+                None
+            }
+            Err(e) => {
+                crate::metrics::SHARED_CACHE_LOOKUPS.with_label_values(&[kind, "error"]).inc();
+                warn!(backend = %backend.name(), "Shared cache read failed: {}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn set<T: Serialize>(&self, kind: &str, key: &str, value: &T, ttl: Duration) {
+        let Some(backend) = &self.backend else {
+            return;
+        };
+        if ttl.is_zero() {
+            return;
+        }
+        let value = match serde_json::to_string(value) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to encode shared cache entry: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = backend.set(&cache_key(kind, key), value, ttl).await {
+            warn!(backend = %backend.name(), "Shared cache write failed: {}", e);
+        }
+    }
+
+    // Current generation of a key's versioned entries, 0 before its first bump. Read it
+    // before loading a value to store with set_at. None when the tier is off or failing.
+    pub async fn generation(&self, kind: &str, key: &str) -> Option<u64> {
+        let backend = self.backend.as_ref()?;
+        match backend.get(&generation_key(kind, key)).await {
+            Ok(value) => Some(value.and_then(|value| value.parse().ok()).unwrap_or(0)),
+            Err(e) => {
+                warn!(backend = %backend.name(), "Shared cache read failed: {}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn get_at<T: DeserializeOwned>(&self, kind: &str, key: &str, generation: u64) -> Option<T> {
+        self.get(kind, &versioned_key(key, generation)).await
+    }
+
+    pub async fn set_at<T: Serialize>(&self, kind: &str, key: &str, generation: u64, value: &T, ttl: Duration) {
+        self.set(kind, &versioned_key(key, generation), value, ttl).await
+    }
+
+    // Retire a key's versioned entries on every replica
+    pub async fn bump(&self, kinds: &[&str], key: &str) {
+        let Some(backend) = &self.backend else {
+            return;
+        };
+        for kind in kinds {
+            if let Err(e) = backend.incr(&generation_key(kind, key)).await {
+                warn!(backend = %backend.name(), "Shared cache invalidation failed: {}", e);
+            }
+        }
+    }
+}
+
+fn cache_key(kind: &str, key: &str) -> String {
+    format!("notifier:{}:{}", kind, key)
+}
+
+fn versioned_key(key: &str, generation: u64) -> String {
+    format!("{}@{}", key, generation)
+}
+
+// Kept without a TTL: one small counter per user and kind
+fn generation_key(kind: &str, key: &str) -> String {
+    format!("notifier:generation:{}:{}", kind, key)
+}
+
+#[cfg(feature = "redis-cache")]
+mod redis_backend {
+    use super::CacheBackend;
+    use anyhow::{Context, Result};
+    use futures::future::BoxFuture;
+    use redis::aio::ConnectionManager;
+    use redis::AsyncCommands;
+    use std::time::Duration;
+
+    pub struct RedisBackend {
+        name: String,
+        // Reconnects on its own after the server goes away
+        connection: ConnectionManager,
+    }
+
+    impl RedisBackend {
+        pub async fn connect(url: &str) -> Result<Self> {
+            let client = redis::Client::open(url).context("Invalid SHARED_CACHE_URL")?;
+            let name = client.get_connection_info().addr.to_string();
+            let connection = ConnectionManager::new(client)
+                .await
+                .with_context(|| format!("Failed to connect to shared cache at {}", name))?;
+            Ok(Self { name, connection })
+        }
+    }
+
+    impl CacheBackend for RedisBackend {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+            let mut connection = self.connection.clone();
+            Box::pin(async move { Ok(connection.get(key).await?) })
+        }
+
+        fn set<'a>(&'a self, key: &'a str, value: String, ttl: Duration) -> BoxFuture<'a, Result<()>> {
+            let mut connection = self.connection.clone();
+            // Whole seconds, rounded up so a short TTL isn't stored without one
+            let seconds = ttl.as_millis().div_ceil(1000) as u64;
+            Box::pin(async move { Ok(connection.set_ex(key, value, seconds).await?) })
+        }
+
+        fn incr<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+            let mut connection = self.connection.clone();
+            Box::pin(async move {
+                let _: i64 = connection.incr(key, 1).await?;
+                Ok(())
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::sync::Mutex;
+
+    #[derive(Default)]
+    struct MapBackend(Mutex<HashMap<String, String>>);
+
+    impl CacheBackend for MapBackend {
+        fn name(&self) -> &str {
+            "map"
+        }
+
+        fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+            Box::pin(async move { Ok(self.0.lock().await.get(key).cloned()) })
+        }
+
+        fn set<'a>(&'a self, key: &'a str, value: String, _ttl: Duration) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                self.0.lock().await.insert(key.to_string(), value);
+                Ok(())
+            })
+        }
+
+        fn incr<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                let mut map = self.0.lock().await;
+                let next = map.get(key).and_then(|value| value.parse::<u64>().ok()).unwrap_or(0) + 1;
+                map.insert(key.to_string(), next.to_string());
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn replicas_share_entries_through_the_backend() {
+        let backend: Arc<dyn CacheBackend> = Arc::new(MapBackend::default());
+        let (one, other) = (SharedCache::new(backend.clone()), SharedCache::new(backend));
+        let ttl = Duration::from_secs(60);
+
+        one.set("mutes", "did:plc:me", &vec!["did:plc:spam".to_string()], ttl).await;
+        one.set("blocks", "did:plc:me", &Vec::<String>::new(), ttl).await;
+        let mutes: Option<Vec<String>> = other.get("mutes", "did:plc:me").await;
+        assert_eq!(mutes, Some(vec!["did:plc:spam".to_string()]));
+
+        // A value loaded before an invalidation is written under the retired generation
+        let loaded_at = one.generation("blocks", "did:plc:me").await.unwrap();
+        other.bump(&["blocks"], "did:plc:me").await;
+        one.set_at("blocks", "did:plc:me", loaded_at, &vec!["stale".to_string()], ttl).await;
+        let current = other.generation("blocks", "did:plc:me").await.unwrap();
+        assert_eq!(current, loaded_at + 1);
+        assert!(other.get_at::<Vec<String>>("blocks", "did:plc:me", current).await.is_none());
+
+        let disabled = SharedCache::default();
+        disabled.set("mutes", "did:plc:me", &vec!["did:plc:spam".to_string()], ttl).await;
+        assert!(disabled.get::<Vec<String>>("mutes", "did:plc:me").await.is_none());
+        assert!(disabled.generation("mutes", "did:plc:me").await.is_none());
+    }
+}
@@ -0,0 +1,164 @@
+// shutdown.rs
+// Stopping the pipeline in order, each task within its own deadline. On SIGINT the API
+// stops taking requests, the firehose consumer disconnects, and then the filter and
+// the sender are each told to stop once their input channel is empty (the channels
+// are closed rather than waiting for every sender to drop, since timers and pollers
+// hold senders for the life of the process). A task that isn't done by its deadline
+// is aborted and logged with the items still queued for it. Those items are lost,
+// unless they were spilled to the outbox, so the shutdown is reported as unclean and
+// the process exits non-zero for orchestrators to alert on. Deadlines come from
+// SHUTDOWN_DEADLINES, e.g. "api=10,firehose=10,filter=20,sender=20" in seconds; the
+// firehose and API can be aborted without losing data, as the cursor and clients
+// retry what they were doing.
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::task::{AbortHandle, JoinHandle};
+use tracing::{error, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownDeadlines {
+    pub api: Duration,
+    pub firehose: Duration,
+    pub filter: Duration,
+    pub sender: Duration,
+}
+
+impl Default for ShutdownDeadlines {
+    fn default() -> Self {
+        Self {
+            api: Duration::from_secs(10),
+            firehose: Duration::from_secs(10),
+            filter: Duration::from_secs(20),
+            sender: Duration::from_secs(20),
+        }
+    }
+}
+
+impl ShutdownDeadlines {
+    // task=seconds pairs separated by commas; tasks left out keep their default
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut deadlines = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (task, secs) = entry.split_once('=')?;
+            let deadline = Duration::from_secs(secs.trim().parse().ok()?);
+            match task.trim() {
+                "api" => deadlines.api = deadline,
+                "firehose" => deadlines.firehose = deadline,
+                "filter" => deadlines.filter = deadline,
+                "sender" => deadlines.sender = deadline,
+                _ => return None,
+            }
+        }
+        Some(deadlines)
+    }
+}
+
+// A task to stop, with what tells it to stop and what it would drop if aborted
+pub struct Stage {
+    name: &'static str,
+    deadline: Duration,
+    abort: AbortHandle,
+    finished: Pin<Box<dyn Future<Output = ()> + Send>>,
+    stop: Option<Box<dyn FnOnce() + Send>>,
+    queued: Option<Box<dyn Fn() -> usize + Send>>,
+}
+
+impl Stage {
+    pub fn new<T: Send + 'static>(name: &'static str, deadline: Duration, handle: JoinHandle<T>) -> Self {
+        Self {
+            name,
+            deadline,
+            abort: handle.abort_handle(),
+            finished: Box::pin(async move {
+                let _ = handle.await;
+            }),
+            stop: None,
+            queued: None,
+        }
+    }
+
+    // Called when the stage starts stopping
+    pub fn on_stop(mut self, stop: impl FnOnce() + Send + 'static) -> Self {
+        self.stop = Some(Box::new(stop));
+        self
+    }
+
+    // The task's input queue; aborting with items in it drops them
+    pub fn with_queue(mut self, queued: impl Fn() -> usize + Send + 'static) -> Self {
+        self.queued = Some(Box::new(queued));
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Finished,
+    // Aborted at the deadline, with the items still queued for it
+    Aborted { queued: usize },
+}
+
+impl Outcome {
+    // Whether stopping this way lost work: an aborted consumer loses its queue and
+    // whatever it had in hand
+    fn dropped_data(&self, has_queue: bool) -> bool {
+        matches!(self, Outcome::Aborted { .. }) && has_queue
+    }
+}
+
+// Stop the stages in order. Returns whether any of them dropped data.
+pub async fn run(stages: Vec<Stage>) -> bool {
+    let mut dropped = false;
+    for stage in stages {
+        let has_queue = stage.queued.is_some();
+        dropped |= stop(stage).await.dropped_data(has_queue);
+    }
+    dropped
+}
+
+async fn stop(mut stage: Stage) -> Outcome {
+    if let Some(stop) = stage.stop.take() {
+        stop();
+    }
+    if tokio::time::timeout(stage.deadline, &mut stage.finished).await.is_ok() {
+        info!(task = stage.name, "Task stopped");
+        return Outcome::Finished;
+    }
+
+    let queued = stage.queued.as_ref().map(|queued| queued()).unwrap_or(0);
+    stage.abort.abort();
+    error!(
+        task = stage.name,
+        deadline_secs = stage.deadline.as_secs(),
+        queued,
+        "Task missed its shutdown deadline and was aborted"
+    );
+    Outcome::Aborted { queued }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_deadlines_per_task() {
+        let deadlines = ShutdownDeadlines::parse("filter=5, sender=0").unwrap();
+        assert_eq!(deadlines.filter, Duration::from_secs(5));
+        assert_eq!(deadlines.sender, Duration::ZERO);
+        assert_eq!(deadlines.api, ShutdownDeadlines::default().api);
+        assert_eq!(ShutdownDeadlines::parse(""), Some(ShutdownDeadlines::default()));
+        assert!(ShutdownDeadlines::parse("apns=5").is_none());
+        assert!(ShutdownDeadlines::parse("filter=soon").is_none());
+    }
+
+    #[tokio::test]
+    async fn aborts_tasks_past_their_deadline() {
+        let finished = Stage::new("quick", Duration::from_secs(1), tokio::spawn(async {}));
+        let stuck = Stage::new("stuck", Duration::from_millis(10), tokio::spawn(std::future::pending::<()>()))
+            .with_queue(|| 3);
+        assert_eq!(stop(finished).await, Outcome::Finished);
+        assert_eq!(stop(stuck).await, Outcome::Aborted { queued: 3 });
+        assert!(Outcome::Aborted { queued: 0 }.dropped_data(true));
+        assert!(!Outcome::Aborted { queued: 0 }.dropped_data(false));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::stream::frames::Frame;
 use anyhow::Result;
-use atrium_api::com::atproto::sync::subscribe_repos::Commit;
+use crate::decoder::DecodedCommit;
 use std::future::Future;
 
 #[trait_variant::make(HttpService: Send)]
@@ -9,5 +9,5 @@ pub trait Subscription {
 }
 
 pub trait CommitHandler {
-    fn handle_commit(&self, commit: &Commit) -> impl Future<Output = Result<()>>;
+    fn handle_commit(&self, commit: &DecodedCommit) -> impl Future<Output = Result<()>>;
 }
@@ -0,0 +1,154 @@
+// tenant.rs
+// Multi-tenant mode: requests carry an X-Api-Key identifying the app (tenant), and
+// DB work for DID-addressed endpoints runs in a transaction scoped to that tenant so
+// Postgres row-level security hides other tenants' users. The policies fail closed:
+// the service's pool opts in to every tenant when it connects, for the pipeline,
+// sender and admin work, and a tenant transaction opts back out.
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Postgres, Transaction};
+use std::sync::Arc;
+use tracing::{error, warn};
+
+use crate::api::ApiState;
+
+pub const DEFAULT_TENANT: &str = "default";
+const API_KEY_HEADER: &str = "x-api-key";
+
+#[derive(Debug, Clone)]
+pub struct Tenant {
+    pub id: String,
+}
+
+impl Default for Tenant {
+    fn default() -> Self {
+        Self {
+            id: DEFAULT_TENANT.to_string(),
+        }
+    }
+}
+
+// Attach the request's tenant as an extension. Single-tenant deployments always
+// get the default tenant and need no API key.
+pub async fn resolve_tenant(
+    State(state): State<Arc<ApiState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !state.multi_tenant {
+        request.extensions_mut().insert(Tenant::default());
+        return next.run(request).await;
+    }
+
+    let Some(api_key) = request
+        .headers()
+        .get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    match find_tenant_by_api_key(&state.db_pool, api_key).await {
+        Ok(Some(tenant)) => {
+            request.extensions_mut().insert(tenant);
+            next.run(request).await
+        }
+        Ok(None) => {
+            warn!(path = %request.uri().path(), "Request with unknown tenant API key");
+            StatusCode::UNAUTHORIZED.into_response()
+        }
+        Err(e) => {
+            error!("Error resolving tenant: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Begin a transaction that only sees (and inserts into) the given tenant
+pub async fn begin(pool: &Pool<Postgres>, tenant: &Tenant) -> Result<Transaction<'static, Postgres>> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "SELECT set_config('app.tenant_id', $1, true) AS tenant_id, set_config('app.all_tenants', 'off', true) AS all_tenants",
+        tenant.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    Ok(tx)
+}
+
+async fn find_tenant_by_api_key(pool: &Pool<Postgres>, api_key: &str) -> Result<Option<Tenant>> {
+    let tenant = sqlx::query!(
+        "SELECT id FROM tenants WHERE api_key_hash = $1",
+        hash_api_key(api_key)
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| Tenant { id: row.id });
+
+    Ok(tenant)
+}
+
+// Create a tenant and return its API key; only the hash is stored
+pub async fn create_tenant(pool: &Pool<Postgres>, id: &str, name: &str) -> Result<String> {
+    let api_key = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+
+    sqlx::query!(
+        "INSERT INTO tenants (id, name, api_key_hash) VALUES ($1, $2, $3)",
+        id,
+        name,
+        hash_api_key(&api_key)
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(api_key)
+}
+
+// Move devices registered before multi-tenant mode into a real tenant
+pub async fn adopt_default_devices(pool: &Pool<Postgres>, id: &str) -> Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE user_devices SET tenant_id = $1 WHERE tenant_id = $2",
+        id,
+        DEFAULT_TENANT
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+fn hash_api_key(api_key: &str) -> String {
+    format!("{:x}", Sha256::digest(api_key.as_bytes()))
+}
+
+// `tenant create <id> <name>` and `tenant adopt-default <id>` subcommands
+pub async fn run_command(pool: &Pool<Postgres>, args: &[String]) -> Result<()> {
+    match args {
+        [cmd, id, name @ ..] if cmd == "create" => {
+            let name = if name.is_empty() { id.clone() } else { name.join(" ") };
+            let api_key = create_tenant(pool, id, &name).await?;
+            println!("Created tenant {}", id);
+            println!("API key (shown once): {}", api_key);
+        }
+        [cmd, id] if cmd == "adopt-default" => {
+            let moved = adopt_default_devices(pool, id).await?;
+            println!("Moved {} devices from the default tenant to {}", moved, id);
+        }
+        _ => {
+            println!("Usage:");
+            println!("  tenant create <id> [name]");
+            println!("  tenant adopt-default <id>");
+        }
+    }
+    Ok(())
+}
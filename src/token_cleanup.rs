@@ -0,0 +1,66 @@
+// token_cleanup.rs
+// Soft-deletes device tokens APNs reported as invalid, off the send path. The sender
+// only enqueues; a dedicated task writes them in batches, so a slow database never
+// holds up delivery and a burst of 410s costs a single UPDATE.
+use sqlx::{Pool, Postgres};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+const QUEUE_CAPACITY: usize = 10_000;
+const MAX_BATCH_SIZE: usize = 500;
+// How long to let a burst accumulate after the first token arrives
+const BATCH_WINDOW: Duration = Duration::from_millis(500);
+
+#[derive(Clone)]
+pub struct TokenCleanupQueue {
+    sender: mpsc::Sender<String>,
+}
+
+impl TokenCleanupQueue {
+    pub fn spawn(db_pool: Pool<Postgres>) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(run_cleanup(db_pool, receiver));
+        Self { sender }
+    }
+
+    // Never waits. A token dropped because the queue is full comes back on its next 410.
+    pub fn enqueue(&self, device_token: &str) {
+        if self.sender.try_send(device_token.to_string()).is_err() {
+            crate::metrics::TOKEN_CLEANUP_DROPPED.inc();
+            warn!("Token cleanup queue full, dropping invalid token");
+        }
+    }
+}
+
+async fn run_cleanup(db_pool: Pool<Postgres>, mut receiver: mpsc::Receiver<String>) {
+    let mut batch = Vec::with_capacity(MAX_BATCH_SIZE);
+
+    while receiver.recv_many(&mut batch, MAX_BATCH_SIZE).await > 0 {
+        if batch.len() < MAX_BATCH_SIZE {
+            tokio::time::sleep(BATCH_WINDOW).await;
+            while batch.len() < MAX_BATCH_SIZE {
+                match receiver.try_recv() {
+                    Ok(device_token) => batch.push(device_token),
+                    Err(_) => break,
+                }
+            }
+        }
+
+        // The same token often fails several queued notifications in a row
+        batch.sort_unstable();
+        batch.dedup();
+
+        // Soft-delete so the device can be restored if the app re-registers it
+        match crate::db::soft_delete_devices_by_token(&db_pool, &batch, "apns_unregistered").await {
+            Ok(removed) => {
+                crate::metrics::INVALID_TOKENS_REMOVED.inc_by(removed as f64);
+                info!("Removed {} invalid device tokens", removed);
+            }
+            Err(e) => error!("Failed to remove {} invalid device tokens: {}", batch.len(), e),
+        }
+        batch.clear();
+    }
+
+    info!("Token cleanup stopped");
+}
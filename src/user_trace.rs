@@ -0,0 +1,150 @@
+// user_trace.rs
+// Following one user through the pipeline, for "I didn't get a notification" reports.
+// An admin turns tracing on for a DID with a TTL (PUT /admin/trace/:did); until it
+// lapses, every decision the filter and sender make about an event that DID authored
+// or would receive is logged at info level under the "user_trace" target and kept in
+// a ring buffer of the most recent TRACE_BUFFER_SIZE decisions, which
+// GET /admin/trace/:did returns. Traces live in this process only: each replica keeps
+// its own, so behind a load balancer the toggle has to reach every replica.
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::OffsetDateTime;
+use tracing::info;
+
+pub const TRACE_TARGET: &str = "user_trace";
+pub const TRACE_BUFFER_SIZE: usize = 2_000;
+
+// Upper bound on a trace's TTL, so a forgotten trace doesn't run forever
+pub const MAX_TRACE_TTL: Duration = Duration::from_secs(24 * 3600);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    pub did: String,
+    pub stage: &'static str,
+    pub decision: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveTrace {
+    pub did: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub until: OffsetDateTime,
+}
+
+#[derive(Default)]
+struct TraceState {
+    // Traced DID and when its trace lapses
+    traced: HashMap<String, OffsetDateTime>,
+    entries: VecDeque<TraceEntry>,
+}
+
+#[derive(Clone, Default)]
+pub struct UserTrace {
+    state: Arc<Mutex<TraceState>>,
+    // Whether any DID is traced, checked before taking the lock so the filter and
+    // sender don't contend on it while nothing is being traced
+    any_traced: Arc<AtomicBool>,
+}
+
+impl UserTrace {
+    // Trace the DID for the next `ttl`, replacing any trace already running for it
+    pub fn start(&self, did: &str, ttl: Duration) -> ActiveTrace {
+        let until = OffsetDateTime::now_utc() + ttl.min(MAX_TRACE_TTL);
+        self.state.lock().unwrap().traced.insert(did.to_string(), until);
+        self.any_traced.store(true, Ordering::Relaxed);
+        info!(target: TRACE_TARGET, did = %did, until = %until, "Tracing started");
+        ActiveTrace { did: did.to_string(), until }
+    }
+
+    // Stop tracing the DID; its recorded decisions stay in the buffer
+    pub fn stop(&self, did: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let stopped = state.traced.remove(did).is_some();
+        self.any_traced.store(!state.traced.is_empty(), Ordering::Relaxed);
+        drop(state);
+        if stopped {
+            info!(target: TRACE_TARGET, did = %did, "Tracing stopped");
+        }
+        stopped
+    }
+
+    pub fn active(&self) -> Vec<ActiveTrace> {
+        let now = OffsetDateTime::now_utc();
+        let mut state = self.state.lock().unwrap();
+        state.traced.retain(|_, until| *until > now);
+        self.any_traced.store(!state.traced.is_empty(), Ordering::Relaxed);
+        state
+            .traced
+            .iter()
+            .map(|(did, until)| ActiveTrace { did: did.clone(), until: *until })
+            .collect()
+    }
+
+    // Record a decision about the DID if it's being traced. The decision is only
+    // formatted for traced DIDs, and with no trace running nothing is locked at all.
+    pub fn record(&self, did: &str, stage: &'static str, decision: impl FnOnce() -> String) {
+        if !self.any_traced.load(Ordering::Relaxed) {
+            return;
+        }
+        let now = OffsetDateTime::now_utc();
+        let mut state = self.state.lock().unwrap();
+        match state.traced.get(did) {
+            None => return,
+            Some(until) if *until <= now => {
+                state.traced.remove(did);
+                self.any_traced.store(!state.traced.is_empty(), Ordering::Relaxed);
+                return;
+            }
+            Some(_) => {}
+        }
+
+        let decision = decision();
+        info!(target: TRACE_TARGET, did = %did, stage, "{}", decision);
+        if state.entries.len() == TRACE_BUFFER_SIZE {
+            state.entries.pop_front();
+        }
+        state.entries.push_back(TraceEntry {
+            at: now,
+            did: did.to_string(),
+            stage,
+            decision,
+        });
+    }
+
+    // The DID's recorded decisions, oldest first
+    pub fn entries(&self, did: &str) -> Vec<TraceEntry> {
+        let state = self.state.lock().unwrap();
+        state.entries.iter().filter(|entry| entry.did == did).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_only_traced_dids_until_the_ttl_lapses() {
+        let trace = UserTrace::default();
+        trace.start("did:plc:traced", Duration::from_secs(60));
+        trace.start("did:plc:lapsed", Duration::ZERO);
+
+        trace.record("did:plc:traced", "filter", || "skipped: muted".to_string());
+        trace.record("did:plc:other", "filter", || unreachable!());
+        trace.record("did:plc:lapsed", "filter", || unreachable!());
+
+        let entries = trace.entries("did:plc:traced");
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].stage, entries[0].decision.as_str()), ("filter", "skipped: muted"));
+        assert_eq!(trace.active().len(), 1);
+
+        assert!(trace.stop("did:plc:traced"));
+        assert!(!trace.any_traced.load(Ordering::Relaxed));
+        trace.record("did:plc:traced", "apns", || unreachable!());
+        assert_eq!(trace.entries("did:plc:traced").len(), 1);
+    }
+}
@@ -0,0 +1,499 @@
+// verification.rs
+// Correctness check against the official AppView. For users who opted in with an
+// app password, the notifications we delivered over the last day are compared with
+// what listNotifications reports for the same window, and the differences are
+// counted as missed (AppView has it, we don't), extra (we sent it, AppView doesn't
+// have it) or mismatched (same post, different type, e.g. a reply we called a
+// mention). The totals feed a metric so pipeline regressions show up as a trend.
+// Firehose gaps are checked the same way over the skipped window, as soon as they
+// are recorded.
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+use crate::did_resolver::DidResolver;
+use crate::models::NotificationType;
+use crate::retry::{is_transient_http, RetryPolicy};
+use crate::watchdog::{Alert, AlertWebhook};
+
+const WINDOW_SECS: f64 = 24.0 * 3600.0;
+// Items this close to either edge of the window may legitimately be on one side
+// only (delivery latency, indexing lag), so each side is matched against the other
+// side's window widened by this much
+const MARGIN_SECS: f64 = 600.0;
+const PAGE_LIMIT: usize = 100;
+const MAX_PAGES: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct NotificationKey {
+    author: String,
+    subject: String,
+    // Likes, reposts and follows are keyed by their own type. Mentions, replies and
+    // quotes all point at the acting post and share a class, which is what lets a
+    // disagreement about their type be reported as a mismatch.
+    class: &'static str,
+}
+
+#[derive(Debug, Clone)]
+struct Observed {
+    key: NotificationKey,
+    notification_type: NotificationType,
+    at: f64,
+}
+
+impl Observed {
+    // `subject` is the liked or reposted post for likes and reposts, and the acting
+    // post for mentions, replies and quotes
+    fn new(notification_type: NotificationType, author: &str, subject: &str, at: f64) -> Option<Self> {
+        let (class, subject) = match notification_type {
+            NotificationType::Like => ("like", subject),
+            NotificationType::Repost => ("repost", subject),
+            NotificationType::Follow => ("follow", ""),
+            NotificationType::Mention | NotificationType::Reply | NotificationType::Quote => ("post", subject),
+            // Feed posts, direct messages, list additions and custom pushes have no
+            // AppView counterpart, and the AppView only announces posts for its own
+            // subscriptions, not ours
+            NotificationType::FeedPost
+            | NotificationType::SubscribedPost
+            | NotificationType::DirectMessage
+            | NotificationType::ListAddition
+            | NotificationType::Custom => return None,
+        };
+        Some(Self {
+            key: NotificationKey {
+                author: author.to_string(),
+                subject: subject.to_string(),
+                class,
+            },
+            notification_type,
+            at,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DiffReport {
+    pub matched: usize,
+    pub missed: usize,
+    pub extra: usize,
+    pub mismatched: usize,
+}
+
+// Compare both sides over [start, end]. Each side was fetched with MARGIN_SECS of
+// slack around the window, so an item just inside the window still finds its
+// counterpart just outside it.
+fn diff(ours: &[Observed], theirs: &[Observed], start: f64, end: f64) -> DiffReport {
+    let in_window = |item: &&Observed| item.at >= start && item.at <= end;
+    // Several devices per user means several history rows per notification
+    let our_types: HashMap<_, _> = ours.iter().map(|o| (&o.key, o.notification_type.as_str())).collect();
+    let their_types: HashMap<_, _> = theirs.iter().map(|t| (&t.key, t.notification_type.as_str())).collect();
+
+    let mut report = DiffReport::default();
+    let mut compared = std::collections::HashSet::new();
+    for item in theirs.iter().filter(in_window) {
+        if !compared.insert(&item.key) {
+            continue;
+        }
+        match our_types.get(&item.key) {
+            None => report.missed += 1,
+            Some(ours) if *ours != item.notification_type.as_str() => report.mismatched += 1,
+            Some(_) => report.matched += 1,
+        }
+    }
+    for item in ours.iter().filter(in_window) {
+        if !their_types.contains_key(&item.key) && compared.insert(&item.key) {
+            report.extra += 1;
+        }
+    }
+    report
+}
+
+#[derive(Deserialize)]
+struct Session {
+    #[serde(rename = "accessJwt")]
+    access_jwt: String,
+}
+
+#[derive(Deserialize)]
+struct ListNotificationsResponse {
+    notifications: Vec<AppViewNotification>,
+    cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AppViewNotification {
+    uri: String,
+    author: AppViewAuthor,
+    reason: String,
+    #[serde(rename = "reasonSubject")]
+    reason_subject: Option<String>,
+    #[serde(rename = "indexedAt")]
+    indexed_at: String,
+}
+
+#[derive(Deserialize)]
+struct AppViewAuthor {
+    did: String,
+}
+
+pub struct Verifier {
+    db_pool: Pool<Postgres>,
+    did_resolver: Arc<DidResolver>,
+    appview_did: String,
+    http_client: HttpClient,
+    webhook: AlertWebhook,
+    // Signalled when a firehose gap is recorded
+    gap_signal: Arc<Notify>,
+}
+
+struct Consent {
+    tenant_id: String,
+    user_did: String,
+    app_password: String,
+}
+
+impl Verifier {
+    pub fn new(
+        db_pool: Pool<Postgres>,
+        did_resolver: Arc<DidResolver>,
+        appview_did: String,
+        webhook: AlertWebhook,
+        gap_signal: Arc<Notify>,
+    ) -> Self {
+        Self {
+            db_pool,
+            did_resolver,
+            appview_did,
+            http_client: HttpClient::builder()
+                .timeout(Duration::from_secs(15))
+                .build()
+                .unwrap_or_default(),
+            webhook,
+            gap_signal,
+        }
+    }
+
+    // Without an interval the daily comparison is off, but gaps are still reconciled as
+    // they are recorded
+    pub async fn run(self, interval: Option<Duration>) {
+        let mut ticker = interval.map(tokio::time::interval);
+        loop {
+            if let Err(e) = self.reconcile_gaps().await {
+                error!("Error reconciling firehose gaps: {}", e);
+            }
+            tokio::select! {
+                Some(_) = async { Some(ticker.as_mut()?.tick().await) } => {
+                    if let Err(e) = self.verify_all().await {
+                        error!("Error verifying notifications against the AppView: {}", e);
+                    }
+                }
+                _ = self.gap_signal.notified() => {}
+            }
+        }
+    }
+
+    // Only users who still have an active device in the app they consented through;
+    // consent outlives a reinstall. Each app's consent is verified on its own.
+    async fn consents(&self) -> Result<Vec<Consent>> {
+        let server_secret = crate::crypto::CryptoUtils::new()?.server_secret;
+        let consents = sqlx::query_as!(
+            Consent,
+            r#"
+            SELECT c.tenant_id, c.user_did, pgp_sym_decrypt(c.app_password_encrypted, $1) AS "app_password!"
+            FROM verification_consents c
+            WHERE EXISTS (
+                SELECT 1 FROM user_devices d
+                WHERE d.tenant_id = c.tenant_id AND d.did = c.user_did
+                AND d.deleted_at IS NULL AND d.verified_at IS NOT NULL
+            )
+            "#,
+            server_secret
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(consents)
+    }
+
+    async fn verify_all(&self) -> Result<()> {
+        let now = chrono::Utc::now().timestamp() as f64;
+        // Leave the newest items out of the window; they may still be in flight
+        let end = now - MARGIN_SECS;
+        let start = end - WINDOW_SECS;
+
+        let mut total = DiffReport::default();
+        for consent in self.consents().await? {
+            let report = match self.verify_user(&consent, start, end).await {
+                Ok(report) => report,
+                Err(e) => {
+                    warn!(did = %consent.user_did, "Notification verification failed: {:#}", e);
+                    crate::metrics::NOTIFICATION_VERIFICATION
+                        .with_label_values(&["failed"])
+                        .inc();
+                    continue;
+                }
+            };
+
+            for (outcome, count) in [
+                ("matched", report.matched),
+                ("missed", report.missed),
+                ("extra", report.extra),
+                ("mismatched", report.mismatched),
+            ] {
+                crate::metrics::NOTIFICATION_VERIFICATION
+                    .with_label_values(&[outcome])
+                    .inc_by(count as f64);
+            }
+
+            sqlx::query!(
+                r#"
+                UPDATE verification_consents
+                SET last_verified_at = NOW(), last_matched = $2, last_missed = $3,
+                    last_extra = $4, last_mismatched = $5
+                WHERE tenant_id = $6 AND user_did = $1
+                "#,
+                consent.user_did,
+                report.matched as i32,
+                report.missed as i32,
+                report.extra as i32,
+                report.mismatched as i32,
+                consent.tenant_id
+            )
+            .execute(&self.db_pool)
+            .await?;
+
+            total.matched += report.matched;
+            total.missed += report.missed;
+            total.extra += report.extra;
+            total.mismatched += report.mismatched;
+        }
+
+        info!(
+            matched = total.matched,
+            missed = total.missed,
+            extra = total.extra,
+            mismatched = total.mismatched,
+            "Verified delivered notifications against the AppView"
+        );
+        Ok(())
+    }
+
+    // Counts what each recorded firehose gap cost the opted-in users, then closes it
+    async fn reconcile_gaps(&self) -> Result<()> {
+        let gaps = crate::gaps::pending(&self.db_pool).await?;
+        if gaps.is_empty() {
+            return Ok(());
+        }
+
+        let consents = self.consents().await?;
+        for gap in gaps {
+            let start = gap.started_at.unix_timestamp() as f64;
+            let end = gap.ended_at.unix_timestamp() as f64;
+
+            let mut missed = 0;
+            for consent in &consents {
+                match self.verify_user(consent, start, end).await {
+                    Ok(report) => missed += report.missed,
+                    Err(e) => warn!(did = %consent.user_did, "Gap reconciliation failed: {:#}", e),
+                }
+            }
+
+            crate::gaps::mark_reconciled(&self.db_pool, gap.id, missed as i32).await?;
+            info!(gap = gap.id, missed, users = consents.len(), "Reconciled firehose gap against the AppView");
+            self.webhook
+                .notify(
+                    Alert::FirehoseGap,
+                    "resolved",
+                    &format!(
+                        "Gap {} reconciled: {} notifications missed across {} verification users",
+                        gap.id,
+                        missed,
+                        consents.len()
+                    ),
+                )
+                .await;
+        }
+        Ok(())
+    }
+
+    // What the app the user consented through delivered, against what the AppView shows
+    async fn verify_user(&self, consent: &Consent, start: f64, end: f64) -> Result<DiffReport> {
+        let did = &consent.user_did;
+        let enabled = self.enabled_types(&consent.tenant_id, did).await?;
+        let ours = self.delivered(&consent.tenant_id, did, start - MARGIN_SECS).await?;
+        let theirs: Vec<Observed> = self
+            .list_notifications(did, &consent.app_password, start - MARGIN_SECS)
+            .await?
+            .into_iter()
+            // A type the user turned off everywhere was never ours to send
+            .filter(|item| enabled.iter().any(|t| t.as_str() == item.notification_type.as_str()))
+            .collect();
+
+        Ok(diff(&ours, &theirs, start, end))
+    }
+
+    // Types at least one of the user's devices in the app has enabled
+    async fn enabled_types(&self, tenant_id: &str, did: &str) -> Result<Vec<NotificationType>> {
+        let mut enabled = Vec::new();
+        let devices = crate::db::get_user_devices(&self.db_pool, did).await?;
+        for device in devices.into_iter().filter(|device| device.tenant_id == tenant_id) {
+            let prefs = crate::db::get_notification_preferences(&self.db_pool, device.id).await?;
+            for (on, notification_type) in [
+                (prefs.mentions, NotificationType::Mention),
+                (prefs.replies || prefs.replies_to_replies, NotificationType::Reply),
+                (prefs.likes, NotificationType::Like),
+                (prefs.follows, NotificationType::Follow),
+                (prefs.reposts, NotificationType::Repost),
+                (prefs.quotes, NotificationType::Quote),
+            ] {
+                if on && !enabled.iter().any(|t: &NotificationType| t.as_str() == notification_type.as_str()) {
+                    enabled.push(notification_type);
+                }
+            }
+        }
+        Ok(enabled)
+    }
+
+    async fn delivered(&self, tenant_id: &str, did: &str, since_unix: f64) -> Result<Vec<Observed>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT notification_type, data, EXTRACT(EPOCH FROM created_at)::FLOAT8 AS "at!"
+            FROM notification_history
+            WHERE tenant_id = $3 AND user_did = $1 AND created_at >= to_timestamp($2) AND status = 'delivered'
+            "#,
+            did,
+            since_unix,
+            tenant_id
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let author = row.data.get("author_did")?.as_str()?;
+                let subject = row.data.get("uri").and_then(|u| u.as_str()).unwrap_or("");
+                Observed::new(NotificationType::parse(&row.notification_type)?, author, subject, row.at)
+            })
+            .collect())
+    }
+
+    async fn list_notifications(&self, did: &str, app_password: &str, since_unix: f64) -> Result<Vec<Observed>> {
+        let pds = self.did_resolver.get_pds_endpoint(did).await?;
+        let policy = RetryPolicy::new("verification").retry_if(is_transient_http);
+
+        let session: Session = policy
+            .run(|_| async {
+                let response = self
+                    .http_client
+                    .post(format!("{}/xrpc/com.atproto.server.createSession", pds))
+                    .json(&json!({ "identifier": did, "password": app_password }))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .context("Failed to create session")?;
+                Ok(response.json().await?)
+            })
+            .await?;
+
+        let mut observed = Vec::new();
+        let mut cursor: Option<String> = None;
+        for _ in 0..MAX_PAGES {
+            let page: ListNotificationsResponse = policy
+                .run(|_| async {
+                    let mut request = self
+                        .http_client
+                        .get(format!("{}/xrpc/app.bsky.notification.listNotifications", pds))
+                        .bearer_auth(&session.access_jwt)
+                        .header("atproto-proxy", format!("{}#bsky_appview", self.appview_did))
+                        .query(&[("limit", PAGE_LIMIT.to_string())]);
+                    if let Some(cursor) = &cursor {
+                        request = request.query(&[("cursor", cursor)]);
+                    }
+                    let response = request
+                        .send()
+                        .await
+                        .and_then(|response| response.error_for_status())
+                        .context("Failed to list notifications")?;
+                    Ok(response.json().await?)
+                })
+                .await?;
+
+            // Newest first, so the first item older than the window ends the scan
+            let mut reached_start = page.notifications.is_empty();
+            for item in page.notifications {
+                let at = chrono::DateTime::parse_from_rfc3339(&item.indexed_at)
+                    .map_err(|e| anyhow!("Invalid indexedAt {}: {}", item.indexed_at, e))?
+                    .timestamp() as f64;
+                if at < since_unix {
+                    reached_start = true;
+                    break;
+                }
+                // Starter pack joins, verifications and the like have no counterpart here
+                let Some(notification_type) = NotificationType::parse(&item.reason) else {
+                    continue;
+                };
+                let subject = match notification_type {
+                    NotificationType::Like | NotificationType::Repost => item.reason_subject.as_deref().unwrap_or(""),
+                    _ => item.uri.as_str(),
+                };
+                observed.extend(Observed::new(notification_type, &item.author.did, subject, at));
+            }
+
+            cursor = page.cursor;
+            if reached_start || cursor.is_none() {
+                break;
+            }
+        }
+
+        Ok(observed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observed(notification_type: NotificationType, author: &str, subject: &str, at: f64) -> Observed {
+        Observed::new(notification_type, author, subject, at).unwrap()
+    }
+
+    #[test]
+    fn diff_classifies_missed_extra_and_mismatched() {
+        let post = "at://did:plc:me/app.bsky.feed.post/1";
+        let reply = "at://did:plc:b/app.bsky.feed.post/2";
+        let ours = vec![
+            observed(NotificationType::Like, "did:plc:a", post, 100.0),
+            // Second device, same notification
+            observed(NotificationType::Like, "did:plc:a", post, 101.0),
+            observed(NotificationType::Mention, "did:plc:b", reply, 200.0),
+            observed(NotificationType::Follow, "did:plc:c", "at://did:plc:c", 300.0),
+            // Just before the window, matches an AppView item just inside it
+            observed(NotificationType::Repost, "did:plc:e", post, 95.0),
+        ];
+        let theirs = vec![
+            observed(NotificationType::Like, "did:plc:a", post, 100.0),
+            observed(NotificationType::Reply, "did:plc:b", reply, 200.0),
+            observed(NotificationType::Follow, "did:plc:d", "", 300.0),
+            observed(NotificationType::Repost, "did:plc:e", post, 105.0),
+        ];
+
+        let report = diff(&ours, &theirs, 100.0, 400.0);
+        assert_eq!(
+            report,
+            DiffReport {
+                matched: 2,
+                missed: 1,
+                extra: 1,
+                mismatched: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,168 @@
+// watchdog.rs
+// Detects pipeline stalls from the metric counters and notifies operators via webhook.
+// The webhook is shared with other operator alerts, such as firehose gaps.
+use prometheus::core::Collector;
+use reqwest::Client as HttpClient;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alert {
+    // The firehose/filter stopped processing events
+    EventsStalled,
+    // Notifications are being queued but none reach APNs
+    DeliveriesStalled,
+    // Events were skipped because the stored cursor could not be resumed from
+    FirehoseGap,
+}
+
+impl Alert {
+    fn name(&self) -> &'static str {
+        match self {
+            Alert::EventsStalled => "events_stalled",
+            Alert::DeliveriesStalled => "deliveries_stalled",
+            Alert::FirehoseGap => "firehose_gap",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AlertWebhook {
+    http_client: HttpClient,
+    url: Option<String>,
+}
+
+impl AlertWebhook {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            http_client: HttpClient::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("Failed to create HTTP client"),
+            url: config.watchdog_webhook_url.clone(),
+        }
+    }
+
+    pub async fn notify(&self, alert: Alert, status: &str, message: &str) {
+        let Some(url) = &self.url else {
+            return;
+        };
+
+        // `text` keeps the body compatible with Slack-style incoming webhooks
+        let body = json!({
+            "alert": alert.name(),
+            "status": status,
+            "text": format!("[bluesky-push-notifier] {}: {}", alert.name(), message),
+        });
+
+        match self.http_client.post(url).json(&body).send().await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => warn!(status = %response.status(), "Watchdog webhook rejected alert"),
+            Err(e) => warn!("Failed to send watchdog alert: {}", e),
+        }
+    }
+}
+
+pub struct Watchdog {
+    webhook: AlertWebhook,
+    event_stall: Duration,
+    delivery_stall: Duration,
+}
+
+impl Watchdog {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            webhook: AlertWebhook::from_config(config),
+            event_stall: Duration::from_secs(config.watchdog_event_stall_minutes * 60),
+            delivery_stall: Duration::from_secs(config.watchdog_delivery_stall_minutes * 60),
+        }
+    }
+
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+
+        let mut events = crate::metrics::EVENTS_PROCESSED.get();
+        let mut events_changed_at = Instant::now();
+        let mut delivered = delivered_total();
+        let mut delivered_changed_at = Instant::now();
+        let mut queued_at_last_delivery = crate::metrics::NOTIFICATIONS_SENT.get();
+
+        let mut events_firing = false;
+        let mut deliveries_firing = false;
+
+        loop {
+            interval.tick().await;
+
+            let events_now = crate::metrics::EVENTS_PROCESSED.get();
+            if events_now > events {
+                events = events_now;
+                events_changed_at = Instant::now();
+            }
+
+            let delivered_now = delivered_total();
+            let queued_now = crate::metrics::NOTIFICATIONS_SENT.get();
+            if delivered_now > delivered {
+                delivered = delivered_now;
+                delivered_changed_at = Instant::now();
+                queued_at_last_delivery = queued_now;
+            }
+
+            let events_stalled = events_changed_at.elapsed() >= self.event_stall;
+            self.transition(
+                Alert::EventsStalled,
+                &mut events_firing,
+                events_stalled,
+                format!(
+                    "No firehose events processed in the last {} minutes",
+                    self.event_stall.as_secs() / 60
+                ),
+            )
+            .await;
+
+            // Only a stall if work was queued; a quiet period with nothing to send is fine
+            let deliveries_stalled = delivered_changed_at.elapsed() >= self.delivery_stall
+                && queued_now > queued_at_last_delivery;
+            self.transition(
+                Alert::DeliveriesStalled,
+                &mut deliveries_firing,
+                deliveries_stalled,
+                format!(
+                    "{} notifications queued but none delivered in the last {} minutes",
+                    queued_now - queued_at_last_delivery,
+                    self.delivery_stall.as_secs() / 60
+                ),
+            )
+            .await;
+        }
+    }
+
+    // Fire once when a stall starts and once when it clears
+    async fn transition(&self, alert: Alert, firing: &mut bool, stalled: bool, message: String) {
+        if stalled && !*firing {
+            *firing = true;
+            error!(alert = alert.name(), "Watchdog: {}", message);
+            crate::metrics::WATCHDOG_ALERTS
+                .with_label_values(&[alert.name()])
+                .inc();
+            self.webhook.notify(alert, "firing", &message).await;
+        } else if !stalled && *firing {
+            *firing = false;
+            info!(alert = alert.name(), "Watchdog: stall resolved");
+            self.webhook.notify(alert, "resolved", "Pipeline has recovered").await;
+        }
+    }
+}
+
+fn delivered_total() -> f64 {
+    crate::metrics::NOTIFICATIONS_DELIVERED_BY_TYPE
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .map(|metric| metric.get_counter().get_value())
+        .sum()
+}